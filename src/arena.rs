@@ -0,0 +1,243 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Allocation-free processing of the stream in time slices, for real-time users.
+//!
+//! Everything one slice needs (its batch of events, the corners found, their
+//! descriptors, and scratch space for downstream work) lives in `Bump` arenas sized
+//! once, at creation. Starting a slice resets the arenas without freeing them, so
+//! after setup the allocator is never called: no allocator pressure, and a worst-case
+//! latency that depends only on the slice size. Arenas never grow, so a slice
+//! holding more events or corners than configured drops the excess, and counts it.
+//!
+//! Corners are found in quick mode (see `detector::detect_quick_observed`), which
+//! allocates nothing, and their descriptors are computed straight into the arena
+//! rather than boxed inside each corner.
+
+use crate::detector::{ring_descriptor, DetectionPolicy, ReplayDetector};
+use crate::sae_types::*;
+use crate::surface::RecordingHeader;
+
+
+/// A fixed-capacity bump arena: values are appended until it is full, and all of
+/// them are released at once by `reset`, keeping the storage for reuse
+pub struct Bump<T> {
+    items: Vec<T>,
+}
+
+impl<T> Bump<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Bump { items: Vec::with_capacity(capacity) }
+    }
+
+    /// Store a value, returning it back if the arena is full
+    pub fn alloc(&mut self, value: T) -> Result<&mut T, T> {
+        if self.items.len() == self.items.capacity() {
+            return Err(value);
+        }
+        self.items.push(value);
+        Ok(self.items.last_mut().unwrap())
+    }
+
+    /// Release every value, keeping the storage
+    pub fn reset(&mut self) {
+        self.items.clear();
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.items
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.items.capacity()
+    }
+}
+
+/// Arena sizes, fixing the most work one slice can hold
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArenaConfig {
+    pub max_events: usize,
+    pub max_corners: usize,
+    /// scratch values available to downstream work on each slice
+    pub scratch_len: usize,
+}
+
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        ArenaConfig {
+            max_events: 65_536,
+            max_corners: 4_096,
+            scratch_len: 0,
+        }
+    }
+}
+
+/// The outcome of one slice, borrowed from the arenas until the next slice starts
+pub struct SliceOutput<'a> {
+    /// the corners found, without boxed descriptors
+    pub corners: &'a [SaeEvent],
+    /// the descriptor of each corner, at the same index
+    pub descriptors: &'a [NormDescriptor],
+    /// events of the slice beyond the event arena, which were not processed
+    pub dropped_events: u64,
+    /// corners of the slice beyond the corner arena
+    pub dropped_corners: u64,
+}
+
+/// Detects corners a slice at a time, with every per-slice allocation taken from arenas
+pub struct SliceProcessor {
+    detector: ReplayDetector,
+    events: Bump<SaeEvent>,
+    corners: Bump<SaeEvent>,
+    descriptors: Bump<NormDescriptor>,
+    scratch: Bump<f32>,
+    dropped_events: u64,
+    dropped_corners: u64,
+}
+
+impl SliceProcessor {
+    pub fn new(header: &RecordingHeader, config: &ArenaConfig) -> Self {
+        SliceProcessor {
+            detector: ReplayDetector::new(header),
+            events: Bump::with_capacity(config.max_events),
+            corners: Bump::with_capacity(config.max_corners),
+            descriptors: Bump::with_capacity(config.max_corners),
+            scratch: Bump::with_capacity(config.scratch_len),
+            dropped_events: 0,
+            dropped_corners: 0,
+        }
+    }
+
+    /// Choose which surface events are checked on; set before processing,
+    /// as switching to the combined surface allocates it
+    pub fn set_detection_policy(&mut self, policy: DetectionPolicy) {
+        self.detector.set_detection_policy(policy);
+    }
+
+    /// Start a new slice, releasing everything held for the previous one
+    pub fn begin_slice(&mut self) {
+        self.events.reset();
+        self.corners.reset();
+        self.descriptors.reset();
+        self.scratch.reset();
+        self.dropped_events = 0;
+        self.dropped_corners = 0;
+    }
+
+    /// Add an event to the current slice, returning false if the event arena is full
+    /// and the event was dropped
+    pub fn push_event(&mut self, evt: &SaeEvent) -> bool {
+        // events are stored without descriptors, so copying them never allocates
+        let stored = SaeEvent { norm_descriptor: None, ..evt.clone() };
+        match self.events.alloc(stored) {
+            Ok(_) => true,
+            Err(_) => {
+                self.dropped_events += 1;
+                false
+            }
+        }
+    }
+
+    /// the events of the current slice
+    pub fn events(&self) -> &[SaeEvent] {
+        self.events.as_slice()
+    }
+
+    /// scratch space for the current slice
+    pub fn scratch(&mut self) -> &mut Bump<f32> {
+        &mut self.scratch
+    }
+
+    /// Detect corners in the events of the current slice, in the order pushed
+    pub fn process_slice(&mut self) -> SliceOutput<'_> {
+        for evt in self.events.as_slice() {
+            let (corner, surface) = match self.detector.process_quick_on(evt) {
+                Some(found) => found,
+                None => continue,
+            };
+            let descriptor = match ring_descriptor(surface.matrix(), corner.row as usize, corner.col as usize) {
+                Some(descriptor) => descriptor,
+                None => continue,
+            };
+            if self.corners.alloc(corner).is_err() {
+                self.dropped_corners += 1;
+                continue;
+            }
+            let _ = self.descriptors.alloc(descriptor);
+        }
+        SliceOutput {
+            corners: self.corners.as_slice(),
+            descriptors: self.descriptors.as_slice(),
+            dropped_events: self.dropped_events,
+            dropped_corners: self.dropped_corners,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surface::WarmupConfig;
+
+    fn corner_events(col_offset: u16, timestamp: SaeTime) -> Vec<SaeEvent> {
+        corner_block_events(10, 10 + col_offset, 0, timestamp)
+    }
+
+    #[test]
+    fn test_bump() {
+        let mut bump = Bump::with_capacity(2);
+        *bump.alloc(1).unwrap() += 10;
+        assert!(bump.alloc(2).is_ok());
+        assert_eq!(bump.alloc(3), Err(3));
+        assert_eq!(bump.as_slice(), &[11, 2]);
+        bump.reset();
+        assert!(bump.is_empty());
+        assert_eq!(bump.capacity(), 2);
+    }
+
+    #[test]
+    fn test_slices_match_quick_detection() {
+        let header = RecordingHeader::new(32, 48, WarmupConfig::disabled());
+        let config = ArenaConfig { max_events: 32, max_corners: 16, scratch_len: 4 };
+        let mut processor = SliceProcessor::new(&header, &config);
+        let mut reference = ReplayDetector::new(&header);
+        let arena_start = processor.events().as_ptr();
+
+        for (slice, offset) in [0u16, 20].iter().enumerate() {
+            let events = corner_events(*offset, 7 + slice as SaeTime * 100);
+            processor.begin_slice();
+            for evt in events.iter() {
+                assert!(processor.push_event(evt));
+            }
+            assert!(processor.scratch().alloc(1.0).is_ok());
+            let expected: Vec<SaeEvent> = events.iter().filter_map(|evt| reference.process_quick(evt)).collect();
+            let output = processor.process_slice();
+            assert!(!expected.is_empty());
+            assert_eq!(output.corners, &expected[..]);
+            assert_eq!(output.descriptors.len(), output.corners.len());
+            assert_eq!((output.dropped_events, output.dropped_corners), (0, 0));
+        }
+        // the arenas were reused, not reallocated
+        assert_eq!(processor.events().as_ptr(), arena_start);
+
+        processor.begin_slice();
+        let events: Vec<SaeEvent> = corner_events(0, 500).into_iter().chain(corner_events(20, 500)).collect();
+        let accepted = events.iter().filter(|evt| processor.push_event(evt)).count();
+        assert_eq!(accepted, config.max_events);
+        let output = processor.process_slice();
+        assert_eq!(output.dropped_events, (events.len() - config.max_events) as u64);
+    }
+}
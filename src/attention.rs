@@ -0,0 +1,181 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Corner-conditioned sampling of raw events.
+//!
+//! For each corner, the raw events within a square spatial window around it and a
+//! time window from `before` ahead of it to `after` behind it are collected, eg as the
+//! input of a learned local model, or to check a detection against the raw data.
+//! `extract_windows` works on a recording held in memory; `AttentionSampler` works on
+//! a live stream, holding back each window until the events after its corner arrive.
+
+use std::collections::VecDeque;
+
+use crate::sae_types::*;
+
+
+/// Extent of the window around each corner
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttentionConfig {
+    /// half the side length of the square window, in pixels
+    pub radius: u16,
+    /// how long before the corner the window starts
+    pub before: SaeTime,
+    /// how long after the corner the window ends
+    pub after: SaeTime,
+}
+
+impl Default for AttentionConfig {
+    fn default() -> Self {
+        AttentionConfig {
+            radius: 7,
+            before: 10_000,
+            after: 0,
+        }
+    }
+}
+
+impl AttentionConfig {
+    /// whether `evt` lies within the window around `corner`
+    pub fn contains(&self, corner: &SaeEvent, evt: &SaeEvent) -> bool {
+        evt.row.abs_diff(corner.row) <= self.radius &&
+            evt.col.abs_diff(corner.col) <= self.radius &&
+            evt.timestamp.saturating_add(self.before) >= corner.timestamp &&
+            evt.timestamp <= corner.timestamp.saturating_add(self.after)
+    }
+}
+
+/// The raw events around one corner, in time order
+#[derive(Clone, Debug)]
+pub struct EventWindow {
+    pub corner: SaeEvent,
+    pub events: Vec<SaeEvent>,
+}
+
+impl EventWindow {
+    /// each event as (row offset, col offset, time offset, polarity) relative to the corner
+    pub fn relative(&self) -> impl Iterator<Item = (i32, i32, i64, u8)> + '_ {
+        self.events.iter().map(move |evt| (
+            evt.row as i32 - self.corner.row as i32,
+            evt.col as i32 - self.corner.col as i32,
+            evt.timestamp as i64 - self.corner.timestamp as i64,
+            evt.polarity,
+        ))
+    }
+}
+
+/// The window around each corner, from time-ordered `events`
+pub fn extract_windows(events: &[SaeEvent], corners: &[SaeEvent], config: &AttentionConfig) -> Vec<EventWindow> {
+    corners.iter()
+        .map(|corner| {
+            let first = events.partition_point(|evt| evt.timestamp.saturating_add(config.before) < corner.timestamp);
+            let end = events.partition_point(|evt| evt.timestamp <= corner.timestamp.saturating_add(config.after));
+            let window = events[first..end.max(first)].iter()
+                .filter(|evt| config.contains(corner, evt))
+                .cloned()
+                .collect();
+            EventWindow { corner: corner.clone(), events: window }
+        })
+        .collect()
+}
+
+/// Collects windows around corners from a live, time-ordered stream
+pub struct AttentionSampler {
+    config: AttentionConfig,
+    /// recent events, back to `before` ahead of the newest
+    history: VecDeque<SaeEvent>,
+    /// windows still waiting for events after their corner, oldest corner first
+    pending: VecDeque<EventWindow>,
+    completed: Vec<EventWindow>,
+}
+
+impl AttentionSampler {
+    pub fn new(config: AttentionConfig) -> Self {
+        AttentionSampler { config, history: VecDeque::new(), pending: VecDeque::new(), completed: Vec::new() }
+    }
+
+    /// Add a raw event, completing the windows that end before it
+    pub fn add_event(&mut self, evt: &SaeEvent) {
+        while self.pending.front().is_some_and(|window| window.corner.timestamp.saturating_add(self.config.after) < evt.timestamp) {
+            let window = self.pending.pop_front().unwrap();
+            self.completed.push(window);
+        }
+        for window in self.pending.iter_mut() {
+            if self.config.contains(&window.corner, evt) {
+                window.events.push(evt.clone());
+            }
+        }
+        let horizon = evt.timestamp.saturating_sub(self.config.before);
+        while self.history.front().is_some_and(|old| old.timestamp < horizon) {
+            self.history.pop_front();
+        }
+        self.history.push_back(evt.clone());
+    }
+
+    /// Add a corner, detected from the events added so far:
+    /// its window takes the recent events now, and later events as they arrive
+    pub fn add_corner(&mut self, corner: &SaeEvent) {
+        let events = self.history.iter()
+            .filter(|evt| self.config.contains(corner, evt))
+            .cloned()
+            .collect();
+        self.pending.push_back(EventWindow { corner: corner.clone(), events });
+    }
+
+    /// Take the completed windows
+    pub fn take_windows(&mut self) -> Vec<EventWindow> {
+        std::mem::take(&mut self.completed)
+    }
+
+    /// Complete every pending window, eg at the end of the stream
+    pub fn flush(&mut self) -> Vec<EventWindow> {
+        self.completed.extend(self.pending.drain(..));
+        self.take_windows()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(row: u16, col: u16, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row, col, timestamp, polarity: 1, ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_batch_and_streaming_agree() {
+        let config = AttentionConfig { radius: 2, before: 300, after: 200 };
+        let events: Vec<SaeEvent> = (0..40u32)
+            .map(|i| event(10 + (i % 7) as u16, 10 + (i % 5) as u16, i * 50))
+            .collect();
+        let corners = vec![event(12, 12, 500), event(14, 10, 1_000), event(30, 30, 1_000)];
+
+        let windows = extract_windows(&events, &corners, &config);
+        assert_eq!(windows.len(), 3);
+        for window in windows.iter() {
+            assert!(window.relative().all(|(drow, dcol, dt, _)| drow.abs() <= 2 && dcol.abs() <= 2 && (-300..=200).contains(&dt)));
+        }
+        assert!(!windows[0].events.is_empty());
+        assert!(windows[2].events.is_empty());
+        let expected = events.iter().filter(|evt| config.contains(&corners[0], evt)).count();
+        assert_eq!(windows[0].events.len(), expected);
+
+        // live: each corner arrives right after the event at its timestamp
+        let mut sampler = AttentionSampler::new(config);
+        let mut streamed = Vec::new();
+        for evt in events.iter() {
+            sampler.add_event(evt);
+            for corner in corners.iter().filter(|corner| corner.timestamp == evt.timestamp) {
+                sampler.add_corner(corner);
+            }
+            streamed.extend(sampler.take_windows());
+        }
+        assert_eq!(streamed.len(), 3);
+        streamed.extend(sampler.flush());
+        for (live, batch) in streamed.iter().zip(windows.iter()) {
+            assert_eq!(live.corner, batch.corner);
+            assert_eq!(live.events, batch.events);
+        }
+    }
+}
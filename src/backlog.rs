@@ -0,0 +1,362 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! An input queue that prioritizes recent events once processing falls behind,
+//! so that corner output stays temporally relevant for control loops.
+//! Below the backlog threshold events are processed in arrival order.
+//! A `PrecisionController` can also trade detection precision for latency while the
+//! queue is deep, switching to the quick, inner-ring-only detector until it catches up.
+
+use std::collections::VecDeque;
+
+use crate::drops::{DropObserver, DropReason};
+use crate::sae_types::*;
+
+
+/// How to pick the next event once the queue exceeds its threshold
+#[derive(Clone, Debug, PartialEq)]
+pub enum BacklogPolicy {
+    /// always process in arrival order
+    Fifo,
+    /// process the most recently arrived events first. `pop` delivers them newest first;
+    /// a consumer maintaining surfaces should take them with `pop_batch`, so that the
+    /// surfaces see them in arrival order and only the corner checks are reordered
+    NewestFirst,
+    /// drop queued events older than `max_age` relative to the newest queued event,
+    /// then continue in arrival order
+    DropStale { max_age: SaeTime },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BacklogConfig {
+    /// queue length above which the policy takes effect
+    pub threshold: usize,
+    pub policy: BacklogPolicy,
+}
+
+impl Default for BacklogConfig {
+    fn default() -> Self {
+        BacklogConfig {
+            threshold: 10_000,
+            policy: BacklogPolicy::DropStale { max_age: 10_000 },
+        }
+    }
+}
+
+/// Counts of events passing through the queue
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BacklogStats {
+    pub received: u64,
+    pub delivered: u64,
+    /// events dropped as stale
+    pub dropped_stale: u64,
+    /// events delivered ahead of older queued events
+    pub delivered_out_of_order: u64,
+    /// longest queue length seen
+    pub max_depth: usize,
+}
+
+/// Events taken from a `BacklogQueue` by `pop_batch`
+#[derive(Clone, Debug, PartialEq)]
+pub enum BacklogBatch {
+    /// the next event to process
+    Event(SaeEvent),
+    /// the whole backlog under `BacklogPolicy::NewestFirst`, in arrival order:
+    /// the surfaces are to be updated with every event in this order,
+    /// and the events then checked for corners from the last to the first
+    NewestFirst(Vec<SaeEvent>),
+}
+
+/// Queue of events awaiting processing, applying a `BacklogPolicy` when it grows too long
+pub struct BacklogQueue {
+    config: BacklogConfig,
+    queue: VecDeque<SaeEvent>,
+    stats: BacklogStats,
+}
+
+impl BacklogQueue {
+    pub fn new(config: BacklogConfig) -> Self {
+        BacklogQueue {
+            config,
+            queue: VecDeque::new(),
+            stats: BacklogStats::default(),
+        }
+    }
+
+    pub fn push(&mut self, evt: SaeEvent) {
+        self.queue.push_back(evt);
+        self.stats.received += 1;
+        self.stats.max_depth = self.stats.max_depth.max(self.queue.len());
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// whether the queue is currently over its backlog threshold
+    pub fn is_backlogged(&self) -> bool {
+        self.queue.len() > self.config.threshold
+    }
+
+    pub fn stats(&self) -> &BacklogStats {
+        &self.stats
+    }
+
+    /// Take the next event to process, according to the policy
+    pub fn pop(&mut self) -> Option<SaeEvent> {
+        self.pop_observed(&mut |_: DropReason, _: Option<&SaeEvent>| {})
+    }
+
+    /// Like `pop`, reporting events dropped as stale to `observer`
+    pub fn pop_observed(&mut self, observer: &mut dyn DropObserver) -> Option<SaeEvent> {
+        let evt = if self.is_backlogged() {
+            match self.config.policy {
+                BacklogPolicy::Fifo => self.queue.pop_front(),
+                BacklogPolicy::NewestFirst => {
+                    let evt = self.queue.pop_back();
+                    if evt.is_some() && !self.queue.is_empty() {
+                        self.stats.delivered_out_of_order += 1;
+                    }
+                    evt
+                }
+                BacklogPolicy::DropStale { max_age } => {
+                    self.drop_stale(max_age, observer);
+                    self.queue.pop_front()
+                }
+            }
+        } else {
+            self.queue.pop_front()
+        };
+        if evt.is_some() {
+            self.stats.delivered += 1;
+        }
+        evt
+    }
+
+    /// Take the next events to process, according to the policy: a single event, or the
+    /// whole backlog at once under `NewestFirst`, so that reordering it for detection
+    /// doesn't feed the surfaces older events after newer ones
+    pub fn pop_batch(&mut self) -> Option<BacklogBatch> {
+        if self.config.policy != BacklogPolicy::NewestFirst || !self.is_backlogged() {
+            return self.pop().map(BacklogBatch::Event);
+        }
+        let events: Vec<SaeEvent> = self.queue.drain(..).collect();
+        self.stats.delivered += events.len() as u64;
+        self.stats.delivered_out_of_order += events.len() as u64 - 1;
+        Some(BacklogBatch::NewestFirst(events))
+    }
+
+    fn drop_stale(&mut self, max_age: SaeTime, observer: &mut dyn DropObserver) {
+        let newest = match self.queue.back() {
+            Some(evt) => evt.timestamp,
+            None => return,
+        };
+        while let Some(oldest) = self.queue.front() {
+            if newest.saturating_sub(oldest.timestamp) <= max_age {
+                break;
+            }
+            if let Some(stale) = self.queue.pop_front() {
+                observer.dropped(DropReason::Stale, Some(&stale));
+            }
+            self.stats.dropped_stale += 1;
+        }
+    }
+
+    /// An iterator popping events according to the policy until the queue is empty
+    pub fn drain(&mut self) -> BacklogDrain<'_> {
+        BacklogDrain { queue: self }
+    }
+}
+
+/// Iterator returned by `BacklogQueue::drain`
+pub struct BacklogDrain<'a> {
+    queue: &'a mut BacklogQueue,
+}
+
+impl<'a> Iterator for BacklogDrain<'a> {
+    type Item = SaeEvent;
+
+    fn next(&mut self) -> Option<SaeEvent> {
+        self.queue.pop()
+    }
+}
+
+/// Detector precision, chosen by `PrecisionController`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrecisionMode {
+    /// both rings checked, with descriptors
+    Full,
+    /// inner ring only, without descriptors: see `detector::detect_quick_observed`
+    Quick,
+}
+
+/// Queue depths at which the detector switches precision. The gap between them
+/// keeps a queue hovering around one depth from switching on every event.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrecisionConfig {
+    /// switch to quick mode once the queue is longer than this
+    pub enter_quick_depth: usize,
+    /// switch back to full precision once the queue is no longer than this
+    pub exit_quick_depth: usize,
+}
+
+impl Default for PrecisionConfig {
+    fn default() -> Self {
+        PrecisionConfig {
+            enter_quick_depth: 5_000,
+            exit_quick_depth: 500,
+        }
+    }
+}
+
+/// A change of precision mode
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModeSwitch {
+    /// timestamp of the first event processed in the new mode
+    pub timestamp: SaeTime,
+    /// queue depth that triggered the switch
+    pub depth: usize,
+    pub mode: PrecisionMode,
+}
+
+/// Counts of events processed in each mode
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PrecisionStats {
+    pub full_events: u64,
+    pub quick_events: u64,
+    pub switches: u64,
+}
+
+/// Trades precision for latency under backlog: picks quick mode while the input
+/// queue is deep and full precision once it has caught up, logging every switch
+pub struct PrecisionController {
+    config: PrecisionConfig,
+    mode: PrecisionMode,
+    switches: Vec<ModeSwitch>,
+    stats: PrecisionStats,
+}
+
+impl PrecisionController {
+    pub fn new(config: PrecisionConfig) -> Self {
+        PrecisionController {
+            config,
+            mode: PrecisionMode::Full,
+            switches: Vec::new(),
+            stats: PrecisionStats::default(),
+        }
+    }
+
+    /// The mode for the event at `timestamp`, with `depth` events still queued behind it
+    pub fn select(&mut self, depth: usize, timestamp: SaeTime) -> PrecisionMode {
+        let next = match self.mode {
+            PrecisionMode::Full if depth > self.config.enter_quick_depth => PrecisionMode::Quick,
+            PrecisionMode::Quick if depth <= self.config.exit_quick_depth => PrecisionMode::Full,
+            mode => mode,
+        };
+        if next != self.mode {
+            self.mode = next;
+            self.switches.push(ModeSwitch { timestamp, depth, mode: next });
+            self.stats.switches += 1;
+        }
+        match next {
+            PrecisionMode::Full => self.stats.full_events += 1,
+            PrecisionMode::Quick => self.stats.quick_events += 1,
+        }
+        next
+    }
+
+    pub fn mode(&self) -> PrecisionMode {
+        self.mode
+    }
+
+    /// every switch so far, oldest first
+    pub fn switches(&self) -> &[ModeSwitch] {
+        &self.switches
+    }
+
+    pub fn stats(&self) -> &PrecisionStats {
+        &self.stats
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drops::DropCounter;
+
+    fn queue_with(policy: BacklogPolicy, threshold: usize, count: u32) -> BacklogQueue {
+        let mut queue = BacklogQueue::new(BacklogConfig { threshold, policy });
+        for i in 0..count {
+            queue.push(SaeEvent { timestamp: i * 100, ..SaeEvent::default() });
+        }
+        queue
+    }
+
+    #[test]
+    fn test_fifo_below_threshold() {
+        let mut queue = queue_with(BacklogPolicy::NewestFirst, 10, 5);
+        let times: Vec<SaeTime> = queue.drain().map(|e| e.timestamp).collect();
+        assert_eq!(times, vec![0, 100, 200, 300, 400]);
+        assert_eq!(queue.stats().delivered_out_of_order, 0);
+    }
+
+    #[test]
+    fn test_newest_first_under_backlog() {
+        let mut queue = queue_with(BacklogPolicy::NewestFirst, 2, 5);
+        assert!(queue.is_backlogged());
+        let times: Vec<SaeTime> = queue.drain().map(|e| e.timestamp).collect();
+        assert_eq!(times, vec![400, 300, 200, 0, 100]);
+        assert_eq!(queue.stats().delivered_out_of_order, 3);
+        assert_eq!(queue.stats().max_depth, 5);
+
+        // as a batch, the backlog keeps its arrival order for the surfaces
+        let mut queue = queue_with(BacklogPolicy::NewestFirst, 2, 5);
+        let times: Vec<SaeTime> = match queue.pop_batch() {
+            Some(BacklogBatch::NewestFirst(events)) => events.iter().map(|e| e.timestamp).collect(),
+            other => panic!("expected a newest-first batch, got {:?}", other),
+        };
+        assert_eq!(times, vec![0, 100, 200, 300, 400]);
+        assert!(queue.pop_batch().is_none());
+        assert_eq!((queue.stats().delivered, queue.stats().delivered_out_of_order), (5, 4));
+
+        let mut queue = queue_with(BacklogPolicy::NewestFirst, 10, 2);
+        assert!(matches!(queue.pop_batch(), Some(BacklogBatch::Event(evt)) if evt.timestamp == 0));
+    }
+
+    #[test]
+    fn test_drop_stale_under_backlog() {
+        let mut queue = queue_with(BacklogPolicy::DropStale { max_age: 250 }, 3, 10);
+        let times: Vec<SaeTime> = queue.drain().map(|e| e.timestamp).collect();
+        assert_eq!(times, vec![700, 800, 900]);
+        let stats = queue.stats();
+        assert_eq!(stats.dropped_stale, 7);
+        assert_eq!(stats.received, stats.delivered + stats.dropped_stale);
+
+        let mut queue = queue_with(BacklogPolicy::DropStale { max_age: 250 }, 3, 10);
+        let mut counter = DropCounter::new();
+        while queue.pop_observed(&mut counter).is_some() {}
+        assert_eq!(counter.count(DropReason::Stale), 7);
+    }
+
+    #[test]
+    fn test_precision_hysteresis() {
+        let mut controller = PrecisionController::new(PrecisionConfig { enter_quick_depth: 10, exit_quick_depth: 2 });
+        let depths = [0, 5, 11, 8, 3, 2, 5, 12];
+        let modes: Vec<PrecisionMode> = depths.iter().enumerate()
+            .map(|(i, &depth)| controller.select(depth, i as SaeTime * 100))
+            .collect();
+        use PrecisionMode::*;
+        assert_eq!(modes, vec![Full, Full, Quick, Quick, Quick, Full, Full, Quick]);
+        assert_eq!(controller.switches(), &[
+            ModeSwitch { timestamp: 200, depth: 11, mode: Quick },
+            ModeSwitch { timestamp: 500, depth: 2, mode: Full },
+            ModeSwitch { timestamp: 700, depth: 12, mode: Quick },
+        ]);
+        assert_eq!(controller.stats(), &PrecisionStats { full_events: 4, quick_events: 4, switches: 3 });
+    }
+}
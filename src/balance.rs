@@ -0,0 +1,263 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Diagnostics of the balance between ON and OFF events.
+//!
+//! Over a scene with no net brightening or darkening, a well biased sensor produces
+//! about as many ON events as OFF events. A consistent excess of one polarity points
+//! to mismatched ON/OFF contrast thresholds, which costs Arc* recall: the surface of
+//! the weaker polarity is sparse and its corners go undetected. `PolarityBalance`
+//! counts both polarities per tile and per time window, flags windows and tiles out
+//! of balance, and suggests which threshold to adjust.
+
+use crate::sae_types::*;
+
+
+/// Parameters of the balance diagnostics
+#[derive(Clone, Debug, PartialEq)]
+pub struct BalanceConfig {
+    /// side length of the square tiles, in pixels
+    pub tile_size: usize,
+    /// length of each counting window
+    pub window: SaeTime,
+    /// largest acceptable distance of the ON fraction from one half
+    pub tolerance: f32,
+    /// windows and tiles with fewer events are not judged
+    pub min_events: u64,
+}
+
+impl Default for BalanceConfig {
+    fn default() -> Self {
+        BalanceConfig {
+            tile_size: 32,
+            window: 1_000_000,
+            tolerance: 0.15,
+            min_events: 100,
+        }
+    }
+}
+
+/// Counts of ON and OFF events
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PolarityCounts {
+    pub on: u64,
+    pub off: u64,
+}
+
+impl PolarityCounts {
+    pub fn add(&mut self, evt: &SaeEvent) {
+        if evt.polarity > 0 {
+            self.on += 1;
+        } else {
+            self.off += 1;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.on + self.off
+    }
+
+    /// fraction of ON events, None without events
+    pub fn on_fraction(&self) -> Option<f32> {
+        match self.total() {
+            0 => None,
+            total => Some(self.on as f32 / total as f32),
+        }
+    }
+
+    /// ON fraction minus one half: positive for an excess of ON events.
+    /// None with fewer than `min_events` events.
+    pub fn imbalance(&self, min_events: u64) -> Option<f32> {
+        if self.total() < min_events.max(1) {
+            return None;
+        }
+        self.on_fraction().map(|fraction| fraction - 0.5)
+    }
+}
+
+/// Which contrast threshold to adjust to restore the balance.
+/// Raising the ON threshold corresponds to eg the Prophesee `bias_diff_on` or
+/// the libcaer DVS `DiffOn` bias; likewise for OFF.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BiasSuggestion {
+    /// within tolerance, or too few events to judge
+    Balanced,
+    /// too many ON events: raise the ON threshold, or lower the OFF threshold.
+    /// `excess` is the ON fraction minus one half.
+    RaiseOnThreshold { excess: f32 },
+    /// too many OFF events: raise the OFF threshold, or lower the ON threshold.
+    /// `excess` is the OFF fraction minus one half.
+    RaiseOffThreshold { excess: f32 },
+}
+
+impl BiasSuggestion {
+    fn from_counts(counts: &PolarityCounts, config: &BalanceConfig) -> Self {
+        match counts.imbalance(config.min_events) {
+            Some(excess) if excess > config.tolerance => BiasSuggestion::RaiseOnThreshold { excess },
+            Some(excess) if -excess > config.tolerance => BiasSuggestion::RaiseOffThreshold { excess: -excess },
+            _ => BiasSuggestion::Balanced,
+        }
+    }
+}
+
+/// The counts of one time window
+#[derive(Clone, Debug, PartialEq)]
+pub struct BalanceWindow {
+    pub start: SaeTime,
+    pub overall: PolarityCounts,
+    /// counts per tile, row-major
+    pub tiles: Vec<PolarityCounts>,
+    pub tiles_per_row: usize,
+}
+
+impl BalanceWindow {
+    pub fn tile(&self, tile_row: usize, tile_col: usize) -> &PolarityCounts {
+        &self.tiles[tile_row * self.tiles_per_row + tile_col]
+    }
+
+    /// (tile row, tile col, imbalance) of every tile out of tolerance
+    pub fn unbalanced_tiles(&self, config: &BalanceConfig) -> Vec<(usize, usize, f32)> {
+        self.tiles.iter().enumerate()
+            .filter_map(|(idx, counts)| {
+                let imbalance = counts.imbalance(config.min_events)?;
+                if imbalance.abs() > config.tolerance {
+                    Some((idx / self.tiles_per_row, idx % self.tiles_per_row, imbalance))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn suggestion(&self, config: &BalanceConfig) -> BiasSuggestion {
+        BiasSuggestion::from_counts(&self.overall, config)
+    }
+}
+
+/// Accumulates polarity counts per tile and window
+pub struct PolarityBalance {
+    config: BalanceConfig,
+    tiles_per_row: usize,
+    tile_count: usize,
+    current: Option<BalanceWindow>,
+    completed: Vec<BalanceWindow>,
+    total: PolarityCounts,
+}
+
+impl PolarityBalance {
+    pub fn new(nrows: usize, ncols: usize, config: BalanceConfig) -> Self {
+        let tile_size = config.tile_size.max(1);
+        let tiles_per_row = ncols.div_ceil(tile_size);
+        let tile_count = tiles_per_row * nrows.div_ceil(tile_size);
+        PolarityBalance { config, tiles_per_row, tile_count, current: None, completed: Vec::new(), total: PolarityCounts::default() }
+    }
+
+    pub fn config(&self) -> &BalanceConfig {
+        &self.config
+    }
+
+    /// Count one event, returning the window it closed, if any.
+    /// Events outside the sensor count only towards the overall balance.
+    pub fn add(&mut self, evt: &SaeEvent) -> Option<&BalanceWindow> {
+        let window = self.config.window.max(1);
+        let closed = match self.current.as_ref() {
+            Some(current) if evt.timestamp >= current.start.saturating_add(window) => self.current.take(),
+            _ => None,
+        };
+        let (tile_count, tiles_per_row) = (self.tile_count, self.tiles_per_row);
+        let current = self.current.get_or_insert_with(|| BalanceWindow {
+            start: evt.timestamp - evt.timestamp % window,
+            overall: PolarityCounts::default(),
+            tiles: vec![PolarityCounts::default(); tile_count],
+            tiles_per_row,
+        });
+        current.overall.add(evt);
+        self.total.add(evt);
+        let tile_size = self.config.tile_size.max(1);
+        let (tile_row, tile_col) = (evt.row as usize / tile_size, evt.col as usize / tile_size);
+        if tile_col < self.tiles_per_row {
+            if let Some(tile) = current.tiles.get_mut(tile_row * self.tiles_per_row + tile_col) {
+                tile.add(evt);
+            }
+        }
+
+        let closed = closed?;
+        self.completed.push(closed);
+        self.completed.last()
+    }
+
+    /// Close the current window, eg at the end of a recording
+    pub fn finish(&mut self) -> Option<&BalanceWindow> {
+        let current = self.current.take()?;
+        self.completed.push(current);
+        self.completed.last()
+    }
+
+    /// completed windows, oldest first
+    pub fn windows(&self) -> &[BalanceWindow] {
+        &self.completed
+    }
+
+    /// the window being counted
+    pub fn current(&self) -> Option<&BalanceWindow> {
+        self.current.as_ref()
+    }
+
+    /// counts over all events so far
+    pub fn total(&self) -> &PolarityCounts {
+        &self.total
+    }
+
+    /// starts of the completed windows out of tolerance
+    pub fn unbalanced_windows(&self) -> Vec<SaeTime> {
+        self.completed.iter()
+            .filter(|window| window.suggestion(&self.config) != BiasSuggestion::Balanced)
+            .map(|window| window.start)
+            .collect()
+    }
+
+    /// A suggestion from the latest completed window, reflecting the current bias settings
+    pub fn suggestion(&self) -> BiasSuggestion {
+        self.completed.last().map_or(BiasSuggestion::Balanced, |window| window.suggestion(&self.config))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(row: u16, col: u16, polarity: u8, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row, col, polarity, timestamp, ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_balance_windows_and_tiles() {
+        let config = BalanceConfig { tile_size: 10, window: 1_000, tolerance: 0.1, min_events: 10 };
+        let mut balance = PolarityBalance::new(20, 20, config);
+        // first window balanced overall, but the left tiles all ON and the right all OFF
+        for i in 0..40 {
+            balance.add(&event((i % 20) as u16, 2, 1, i * 10));
+            assert!(balance.add(&event((i % 20) as u16, 15, 0, i * 10 + 5)).is_none());
+        }
+        // second window: three ON events for each OFF one
+        for i in 0..40u32 {
+            let closed = balance.add(&event(3, 3, (i % 4 != 0) as u8, 1_000 + i * 10)).cloned();
+            assert_eq!(closed.is_some(), i == 0);
+        }
+        balance.finish();
+
+        let windows = balance.windows();
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[0].overall, PolarityCounts { on: 40, off: 40 });
+        assert_eq!(windows[0].tile(0, 0), &PolarityCounts { on: 20, off: 0 });
+        let unbalanced = windows[0].unbalanced_tiles(balance.config());
+        assert_eq!(unbalanced, vec![(0, 0, 0.5), (0, 1, -0.5), (1, 0, 0.5), (1, 1, -0.5)]);
+        assert_eq!(windows[0].suggestion(balance.config()), BiasSuggestion::Balanced);
+
+        assert_eq!(windows[1].start, 1_000);
+        assert_eq!(balance.unbalanced_windows(), vec![1_000]);
+        assert_eq!(balance.suggestion(), BiasSuggestion::RaiseOnThreshold { excess: 0.25 });
+        assert_eq!(balance.total().total(), 120);
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Detection of blinking-LED beacons by their blink frequency.
+//! Each time an LED switches on, the pixels viewing it fire a burst of ON events.
+//! The image is divided into small cells; per cell, the intervals between
+//! successive burst onsets are compared against the configured beacon periods,
+//! and a cell showing several consecutive matching intervals is attributed to
+//! that beacon. Adjacent cells of the same beacon are merged into one observation.
+
+use crate::sae_types::*;
+
+
+/// Parameters of beacon detection
+#[derive(Clone, Debug, PartialEq)]
+pub struct BeaconConfig {
+    /// blink frequencies of the beacons to detect, in Hz
+    pub frequencies: Vec<f32>,
+    /// allowed deviation of an onset interval from a beacon period, as a fraction of the period
+    pub tolerance: f32,
+    /// consecutive matching intervals before a cell is attributed to a beacon
+    pub min_cycles: u8,
+    /// side length of the square cells the image is divided into, in pixels
+    pub cell_size: usize,
+}
+
+impl Default for BeaconConfig {
+    fn default() -> Self {
+        BeaconConfig {
+            frequencies: vec![1_000.0],
+            tolerance: 0.05,
+            min_cycles: 3,
+            cell_size: 2,
+        }
+    }
+}
+
+/// A beacon found in the image
+#[derive(Clone, Debug, PartialEq)]
+pub struct BeaconObservation {
+    /// index of the matched frequency in the configuration
+    pub beacon: usize,
+    pub frequency: f32,
+    /// mean position of the matching cells (x = column, y = row), in pixels
+    pub position: [f32; 2],
+    /// number of cells attributed to the beacon
+    pub cells: usize,
+    /// most recent burst onset of the beacon
+    pub timestamp: SaeTime,
+}
+
+#[derive(Clone, Copy, Default)]
+struct CellState {
+    /// time of the latest ON event
+    last_event: Option<SaeTime>,
+    /// start of the latest burst of ON events
+    last_onset: Option<SaeTime>,
+    /// beacon matched by the recent onset intervals, with consecutive match count
+    matched: Option<(usize, u8)>,
+}
+
+/// Identifies image regions blinking at the configured beacon frequencies
+pub struct BeaconDetector {
+    config: BeaconConfig,
+    /// beacon periods in SAE time units
+    periods: Vec<SaeTime>,
+    /// ON events closer together than this belong to the same burst
+    burst_gap: SaeTime,
+    cell_rows: usize,
+    cell_cols: usize,
+    cells: Vec<CellState>,
+}
+
+impl BeaconDetector {
+    pub fn new(nrows: usize, ncols: usize, config: BeaconConfig) -> Self {
+        let cell_size = config.cell_size.max(1);
+        let periods: Vec<SaeTime> = config.frequencies.iter()
+            .map(|&f| (1e6 / f.max(1e-3)).round() as SaeTime)
+            .collect();
+        // bursts are separated by at least a quarter of the shortest period
+        let burst_gap = periods.iter().cloned().min().unwrap_or(0) / 4;
+        let cell_rows = nrows.div_ceil(cell_size);
+        let cell_cols = ncols.div_ceil(cell_size);
+        BeaconDetector {
+            config: BeaconConfig { cell_size, ..config },
+            periods,
+            burst_gap,
+            cell_rows,
+            cell_cols,
+            cells: vec![CellState::default(); cell_rows * cell_cols],
+        }
+    }
+
+    /// the beacon whose period matches the interval, if any
+    fn match_period(&self, interval: SaeTime) -> Option<usize> {
+        self.periods.iter()
+            .map(|&period| {
+                let deviation = period.max(interval) - period.min(interval);
+                deviation as f32 / period as f32
+            })
+            .enumerate()
+            .filter(|&(_, deviation)| deviation <= self.config.tolerance)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(idx, _)| idx)
+    }
+
+    pub fn add_event(&mut self, evt: &SaeEvent) {
+        if evt.polarity == 0 {
+            return;
+        }
+        let (crow, ccol) = (evt.row as usize / self.config.cell_size, evt.col as usize / self.config.cell_size);
+        if crow >= self.cell_rows || ccol >= self.cell_cols {
+            return;
+        }
+        let idx = crow * self.cell_cols + ccol;
+        let cell = self.cells[idx];
+        let onset = match cell.last_event {
+            Some(last) => evt.timestamp.checked_elapsed_since(last).is_some_and(|gap| gap > self.burst_gap),
+            None => true,
+        };
+        let mut updated = cell;
+        updated.last_event = Some(evt.timestamp);
+        if onset {
+            if let Some(prev) = cell.last_onset {
+                let matched = self.match_period(evt.timestamp - prev);
+                updated.matched = match (matched, cell.matched) {
+                    (Some(beacon), Some((old, count))) if beacon == old => Some((beacon, count.saturating_add(1))),
+                    (Some(beacon), _) => Some((beacon, 1)),
+                    (None, _) => None,
+                };
+            }
+            updated.last_onset = Some(evt.timestamp);
+        }
+        self.cells[idx] = updated;
+    }
+
+    /// beacon attributed to a cell that is still blinking at time `now`
+    fn active_beacon(&self, cell: &CellState, now: SaeTime) -> Option<usize> {
+        let (beacon, count) = cell.matched?;
+        if count < self.config.min_cycles {
+            return None;
+        }
+        // a beacon that missed more than one cycle is no longer reported
+        let onset = cell.last_onset?;
+        if now.saturating_sub(onset) > 2 * self.periods[beacon] {
+            return None;
+        }
+        Some(beacon)
+    }
+
+    /// Beacons blinking at time `now`, one observation per connected group of cells
+    pub fn beacons(&self, now: SaeTime) -> Vec<BeaconObservation> {
+        let active: Vec<Option<usize>> = self.cells.iter().map(|cell| self.active_beacon(cell, now)).collect();
+        let mut visited = vec![false; active.len()];
+        let mut observations = Vec::new();
+        let half_cell = (self.config.cell_size as f32 - 1.0) / 2.0;
+        for start in 0..active.len() {
+            let beacon = match active[start] {
+                Some(beacon) if !visited[start] => beacon,
+                _ => continue,
+            };
+            visited[start] = true;
+            let mut stack = vec![start];
+            let (mut sum_x, mut sum_y, mut count, mut latest) = (0.0, 0.0, 0, 0);
+            while let Some(idx) = stack.pop() {
+                let (crow, ccol) = (idx / self.cell_cols, idx % self.cell_cols);
+                sum_x += (ccol * self.config.cell_size) as f32 + half_cell;
+                sum_y += (crow * self.config.cell_size) as f32 + half_cell;
+                count += 1;
+                latest = latest.max(self.cells[idx].last_onset.unwrap_or(0));
+                for dr in -1i64..=1 {
+                    for dc in -1i64..=1 {
+                        let (r, c) = (crow as i64 + dr, ccol as i64 + dc);
+                        if r < 0 || c < 0 || r >= self.cell_rows as i64 || c >= self.cell_cols as i64 {
+                            continue;
+                        }
+                        let neighbor = (r as usize) * self.cell_cols + c as usize;
+                        if !visited[neighbor] && active[neighbor] == Some(beacon) {
+                            visited[neighbor] = true;
+                            stack.push(neighbor);
+                        }
+                    }
+                }
+            }
+            observations.push(BeaconObservation {
+                beacon,
+                frequency: self.config.frequencies[beacon],
+                position: [sum_x / count as f32, sum_y / count as f32],
+                cells: count,
+                timestamp: latest,
+            });
+        }
+        observations
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn on_event(row: u16, col: u16, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row, col, polarity: 1, timestamp, ..SaeEvent::default() }
+    }
+
+    /// bursts of ON events over a 2x2 pixel block at the given period
+    fn blink(detector: &mut BeaconDetector, row: u16, col: u16, period: SaeTime, cycles: u32) {
+        for cycle in 0..cycles {
+            let onset = 100 + cycle * period;
+            for (k, &(dr, dc)) in [(0, 0), (0, 1), (1, 0), (1, 1)].iter().enumerate() {
+                detector.add_event(&on_event(row + dr, col + dc, onset + k as SaeTime * 5));
+            }
+        }
+    }
+
+    #[test]
+    fn test_two_beacons() {
+        let config = BeaconConfig { frequencies: vec![1_000.0, 2_500.0], ..BeaconConfig::default() };
+        let mut detector = BeaconDetector::new(32, 32, config);
+        blink(&mut detector, 4, 4, 1_000, 8);
+        blink(&mut detector, 20, 10, 400, 20);
+        // an irregularly active pixel
+        for &t in [0, 700, 1_900, 2_300, 4_100, 5_600].iter() {
+            detector.add_event(&on_event(12, 28, t));
+        }
+
+        let mut found = detector.beacons(8_000);
+        found.sort_by_key(|obs| obs.beacon);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].beacon, 0);
+        assert_eq!(found[0].position, [4.5, 4.5]);
+        assert_eq!(found[1].frequency, 2_500.0);
+        assert_eq!(found[1].position, [10.5, 20.5]);
+
+        // beacons that stop blinking are no longer reported
+        assert!(detector.beacons(20_000).is_empty());
+    }
+
+    #[test]
+    fn test_requires_consecutive_cycles() {
+        let mut detector = BeaconDetector::new(16, 16, BeaconConfig::default());
+        blink(&mut detector, 2, 2, 1_000, 3);
+        assert!(detector.beacons(2_200).is_empty());
+        // a different frequency does not match
+        blink(&mut detector, 8, 8, 1_300, 8);
+        assert!(detector.beacons(9_200).is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Per-region detection budgets, bounding worst-case detection cost on large sensors.
+//! The sensor is divided into square tiles, and time into fixed windows.
+//! Each window allows a global number of detections, shared between the tiles that
+//! were active in the previous window, plus a small guaranteed minimum per tile,
+//! so that a saturated region can't starve quiet regions of detections. Events over budget still update the SAE;
+//! only the corner check is skipped.
+
+use crate::sae_types::*;
+
+
+/// Parameters of the detection budget
+#[derive(Clone, Debug, PartialEq)]
+pub struct BudgetConfig {
+    /// side length of the square tiles, in pixels
+    pub tile_size: usize,
+    /// length of each budgeting window
+    pub window: SaeTime,
+    /// maximum number of detections across the whole sensor per window
+    pub global_budget: u32,
+    /// every active tile may run at least this many detections per window,
+    /// even once the global budget is exhausted
+    pub min_per_tile: u32,
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        BudgetConfig {
+            tile_size: 32,
+            window: 10_000,
+            global_budget: 20_000,
+            min_per_tile: 16,
+        }
+    }
+}
+
+/// Counts of detections run and skipped
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BudgetStats {
+    pub detections_allowed: u64,
+    pub detections_skipped: u64,
+    /// windows in which at least one detection was skipped
+    pub saturated_windows: u64,
+}
+
+/// Decides, per event, whether corner detection fits in its region's budget
+pub struct RegionBudget {
+    config: BudgetConfig,
+    tiles_per_row: usize,
+    /// detections run in each tile during the current window
+    counts: Vec<u32>,
+    window_start: Option<SaeTime>,
+    /// detections run across all tiles during the current window
+    window_total: u32,
+    window_saturated: bool,
+    /// per-tile cap for the current window
+    tile_cap: u32,
+    stats: BudgetStats,
+}
+
+impl RegionBudget {
+    pub fn new(nrows: usize, ncols: usize, config: BudgetConfig) -> Self {
+        let tile_size = config.tile_size.max(1);
+        let tiles_per_row = ncols.div_ceil(tile_size);
+        let tile_rows = nrows.div_ceil(tile_size);
+        RegionBudget {
+            tiles_per_row,
+            counts: vec![0; tiles_per_row * tile_rows],
+            window_start: None,
+            window_total: 0,
+            window_saturated: false,
+            tile_cap: config.global_budget.max(config.min_per_tile),
+            stats: BudgetStats::default(),
+            config: BudgetConfig { tile_size, ..config },
+        }
+    }
+
+    pub fn config(&self) -> &BudgetConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &BudgetStats {
+        &self.stats
+    }
+
+    /// the per-tile detection cap in effect for the current window
+    pub fn tile_cap(&self) -> u32 {
+        self.tile_cap
+    }
+
+    fn tile_index(&self, evt: &SaeEvent) -> Option<usize> {
+        let idx = (evt.row as usize / self.config.tile_size) * self.tiles_per_row +
+            (evt.col as usize / self.config.tile_size);
+        if (evt.col as usize) < self.tiles_per_row * self.config.tile_size && idx < self.counts.len() {
+            Some(idx)
+        } else {
+            None
+        }
+    }
+
+    /// Start a new window: share the global budget among the tiles active in the last one
+    fn roll_window(&mut self, start: SaeTime) {
+        let active = self.counts.iter().filter(|&&count| count > 0).count().max(1) as u32;
+        self.tile_cap = (self.config.global_budget / active).max(self.config.min_per_tile);
+        for count in self.counts.iter_mut() {
+            *count = 0;
+        }
+        if self.window_saturated {
+            self.stats.saturated_windows += 1;
+        }
+        self.window_saturated = false;
+        self.window_total = 0;
+        self.window_start = Some(start);
+    }
+
+    /// Whether detection should run for this event; charges the event's tile if so.
+    /// Events outside the sensor are always allowed (the detector rejects them anyway).
+    pub fn allow(&mut self, evt: &SaeEvent) -> bool {
+        match self.window_start {
+            Some(start) if evt.timestamp.saturating_sub(start) < self.config.window => {}
+            _ => self.roll_window(evt.timestamp),
+        }
+
+        let idx = match self.tile_index(evt) {
+            Some(idx) => idx,
+            None => return true,
+        };
+        let count = self.counts[idx];
+        let within_budget = count < self.tile_cap && self.window_total < self.config.global_budget;
+        if count >= self.config.min_per_tile && !within_budget {
+            self.stats.detections_skipped += 1;
+            self.window_saturated = true;
+            return false;
+        }
+        self.counts[idx] += 1;
+        self.window_total += 1;
+        self.stats.detections_allowed += 1;
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_at(row: u16, col: u16, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row, col, timestamp, ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_saturated_region_does_not_starve_quiet_region() {
+        let config = BudgetConfig { tile_size: 10, window: 1_000, global_budget: 20, min_per_tile: 2 };
+        let mut budget = RegionBudget::new(20, 20, config);
+
+        // first window: two active tiles, one of them saturated
+        for i in 0..30 {
+            budget.allow(&event_at(1, 1, i));
+        }
+        assert!(budget.allow(&event_at(15, 15, 40)));
+
+        // the next window shares the budget between the two active tiles
+        let mut busy_allowed = 0;
+        for i in 0..30 {
+            if budget.allow(&event_at(1, 1, 1_000 + i)) {
+                busy_allowed += 1;
+            }
+        }
+        assert_eq!(budget.tile_cap(), 10);
+        assert_eq!(busy_allowed, 10);
+        for i in 0..5 {
+            assert!(budget.allow(&event_at(15, 15, 1_100 + i)));
+        }
+        assert!(budget.stats().detections_skipped > 0);
+    }
+
+    #[test]
+    fn test_global_budget_bounds_window() {
+        let config = BudgetConfig { tile_size: 4, window: 1_000, global_budget: 8, min_per_tile: 0 };
+        let mut budget = RegionBudget::new(16, 16, config);
+        let mut allowed = 0;
+        for i in 0..16u16 {
+            // one event in each of 16 tiles
+            if budget.allow(&event_at((i / 4) * 4, (i % 4) * 4, 10)) {
+                allowed += 1;
+            }
+        }
+        assert_eq!(allowed, 8);
+        // out-of-sensor events aren't charged
+        assert!(budget.allow(&event_at(100, 100, 20)));
+        budget.allow(&event_at(0, 0, 5_000));
+        assert_eq!(budget.stats().saturated_windows, 1);
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Segmentation of event streams into motion bursts and quiescent periods.
+//!
+//! Corner statistics differ radically between a sensor in motion and one watching a
+//! still scene, where mostly noise fires, so evaluating or visualizing a recording as a
+//! whole mixes the two regimes. `segment_bursts` bins the event rate over time and
+//! finds the change points between regimes with a pair of rate thresholds, so that a
+//! rate hovering around one threshold does not split a regime, then absorbs segments
+//! too short to matter into their predecessors.
+
+use std::ops::Range;
+
+use crate::sae_types::*;
+
+
+/// Parameters of burst segmentation
+#[derive(Clone, Debug, PartialEq)]
+pub struct BurstConfig {
+    /// width of the time bins the event rate is measured over
+    pub bin_width: SaeTime,
+    /// event rate (events per second) above which a burst starts
+    pub enter_rate: f64,
+    /// event rate below which a burst ends
+    pub exit_rate: f64,
+    /// segments shorter than this are merged into the preceding segment
+    pub min_duration: SaeTime,
+}
+
+impl Default for BurstConfig {
+    fn default() -> Self {
+        BurstConfig {
+            bin_width: 10_000,
+            enter_rate: 200_000.0,
+            exit_rate: 50_000.0,
+            min_duration: 50_000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SegmentKind {
+    Burst,
+    Quiescent,
+}
+
+/// A run of events in one regime
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    /// start time, inclusive
+    pub start: SaeTime,
+    /// end time, exclusive
+    pub end: SaeTime,
+    /// indices of the segment's events in the segmented slice
+    pub events: Range<usize>,
+}
+
+impl Segment {
+    pub fn duration(&self) -> SaeTime {
+        self.end - self.start
+    }
+
+    /// mean event rate over the segment, events per second
+    pub fn event_rate(&self) -> f64 {
+        if self.duration() == 0 {
+            return 0.0;
+        }
+        self.events.len() as f64 * 1e6 / self.duration() as f64
+    }
+
+    pub fn contains(&self, timestamp: SaeTime) -> bool {
+        (self.start..self.end).contains(&timestamp)
+    }
+
+    /// the segment's events, from the slice that was segmented
+    pub fn slice<'a>(&self, events: &'a [SaeEvent]) -> &'a [SaeEvent] {
+        &events[self.events.clone()]
+    }
+
+    /// the items of `timed` (eg corners), in time order, falling within the segment
+    pub fn select<'a>(&self, timed: &'a [SaeEvent]) -> &'a [SaeEvent] {
+        let first = timed.partition_point(|evt| evt.timestamp < self.start);
+        let end = timed.partition_point(|evt| evt.timestamp < self.end);
+        &timed[first..end]
+    }
+}
+
+/// Split time-ordered `events` into bursts and quiescent periods, covering
+/// the recording from the bin of the first event to the end of the bin of the last
+pub fn segment_bursts(events: &[SaeEvent], config: &BurstConfig) -> Vec<Segment> {
+    let (first, last) = match (events.first(), events.last()) {
+        (Some(first), Some(last)) => (first.timestamp, last.timestamp),
+        _ => return Vec::new(),
+    };
+    let bin_width = config.bin_width.max(1);
+    let origin = first - first % bin_width;
+    let bins = ((last - origin) / bin_width) as usize + 1;
+    let mut counts = vec![0u64; bins];
+    for evt in events.iter() {
+        counts[(evt.timestamp.elapsed_since(origin) / bin_width) as usize] += 1;
+    }
+
+    // classify bins with hysteresis, as (kind, first bin, end bin) runs
+    let mut runs: Vec<(SegmentKind, usize, usize)> = Vec::new();
+    let mut kind = SegmentKind::Quiescent;
+    for (bin, &count) in counts.iter().enumerate() {
+        let rate = count as f64 * 1e6 / bin_width as f64;
+        kind = match kind {
+            SegmentKind::Quiescent if rate > config.enter_rate => SegmentKind::Burst,
+            SegmentKind::Burst if rate < config.exit_rate => SegmentKind::Quiescent,
+            kind => kind,
+        };
+        match runs.last_mut() {
+            Some(run) if run.0 == kind => run.2 = bin + 1,
+            _ => runs.push((kind, bin, bin + 1)),
+        }
+    }
+
+    // absorb short runs into their predecessors, then merge neighbors of one kind
+    let min_bins = config.min_duration.div_ceil(bin_width) as usize;
+    let mut merged: Vec<(SegmentKind, usize, usize)> = Vec::new();
+    for run in runs {
+        match merged.last_mut() {
+            Some(last) if run.2 - run.1 < min_bins || last.0 == run.0 => last.2 = run.2,
+            _ => merged.push(run),
+        }
+    }
+
+    merged.into_iter()
+        .map(|(kind, first_bin, end_bin)| {
+            let start = origin + first_bin as SaeTime * bin_width;
+            let end = origin + end_bin as SaeTime * bin_width;
+            let first_idx = events.partition_point(|evt| evt.timestamp < start);
+            let end_idx = events.partition_point(|evt| evt.timestamp < end);
+            Segment { kind, start, end, events: first_idx..end_idx }
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// events at `rate` per second from `start` to `end`
+    fn events_at(rate: u32, start: SaeTime, end: SaeTime) -> Vec<SaeEvent> {
+        let step = 1_000_000 / rate;
+        (start..end).step_by(step as usize)
+            .map(|timestamp| SaeEvent { timestamp, ..SaeEvent::default() })
+            .collect()
+    }
+
+    #[test]
+    fn test_segment_bursts() {
+        let config = BurstConfig { bin_width: 10_000, enter_rate: 200_000.0, exit_rate: 50_000.0, min_duration: 30_000 };
+        let mut events = events_at(10_000, 0, 100_000);
+        events.extend(events_at(500_000, 100_000, 200_000));
+        // a dip to a rate between the thresholds does not end the burst
+        events.extend(events_at(100_000, 200_000, 250_000));
+        events.extend(events_at(500_000, 250_000, 300_000));
+        events.extend(events_at(10_000, 300_000, 400_000));
+        // a spike too short to count as a burst
+        events.extend(events_at(500_000, 400_000, 410_000));
+        events.extend(events_at(10_000, 410_000, 500_000));
+
+        let segments = segment_bursts(&events, &config);
+        let spans: Vec<(SegmentKind, SaeTime, SaeTime)> = segments.iter().map(|s| (s.kind, s.start, s.end)).collect();
+        assert_eq!(spans, vec![
+            (SegmentKind::Quiescent, 0, 100_000),
+            (SegmentKind::Burst, 100_000, 300_000),
+            (SegmentKind::Quiescent, 300_000, 500_000),
+        ]);
+        assert_eq!(segments.iter().map(|s| s.events.len()).sum::<usize>(), events.len());
+        assert_eq!(segments[1].slice(&events).len(), 50_000 + 5_000 + 25_000);
+        assert!((segments[0].event_rate() - 10_000.0).abs() < 1.0);
+        assert!(segments[1].contains(299_999) && !segments[1].contains(300_000));
+
+        let corners = vec![SaeEvent { timestamp: 50_000, ..SaeEvent::default() }, SaeEvent { timestamp: 150_000, ..SaeEvent::default() }];
+        assert_eq!(segments[1].select(&corners).len(), 1);
+        assert!(segment_bursts(&[], &config).is_empty());
+    }
+}
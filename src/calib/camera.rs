@@ -0,0 +1,92 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Pinhole camera model with two-coefficient radial distortion.
+
+use nalgebra::{Isometry3, Matrix3, Point3, Vector3};
+
+
+/// Intrinsic parameters of a pinhole camera with radial distortion.
+/// Image coordinates are (x = column, y = row), in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraIntrinsics {
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+    /// radial distortion coefficients
+    pub k1: f64,
+    pub k2: f64,
+}
+
+impl CameraIntrinsics {
+    /// An undistorted pinhole camera
+    pub fn pinhole(fx: f64, fy: f64, cx: f64, cy: f64) -> Self {
+        CameraIntrinsics { fx, fy, cx, cy, k1: 0.0, k2: 0.0 }
+    }
+
+    /// the 3x3 camera matrix K
+    pub fn matrix(&self) -> Matrix3<f64> {
+        Matrix3::new(
+            self.fx, 0.0, self.cx,
+            0.0, self.fy, self.cy,
+            0.0, 0.0, 1.0)
+    }
+
+    /// Apply distortion to normalized image coordinates
+    pub fn distort(&self, normalized: [f64; 2]) -> [f64; 2] {
+        let r2 = normalized[0] * normalized[0] + normalized[1] * normalized[1];
+        let factor = 1.0 + self.k1 * r2 + self.k2 * r2 * r2;
+        [normalized[0] * factor, normalized[1] * factor]
+    }
+
+    /// Remove distortion from normalized image coordinates (fixed-point iteration)
+    pub fn undistort(&self, distorted: [f64; 2]) -> [f64; 2] {
+        let mut point = distorted;
+        for _ in 0..20 {
+            let r2 = point[0] * point[0] + point[1] * point[1];
+            let factor = 1.0 + self.k1 * r2 + self.k2 * r2 * r2;
+            point = [distorted[0] / factor, distorted[1] / factor];
+        }
+        point
+    }
+
+    /// Project a point in camera coordinates to the image; None if behind the camera
+    pub fn project(&self, point: &Point3<f64>) -> Option<[f64; 2]> {
+        if point.z <= 0.0 {
+            return None;
+        }
+        let d = self.distort([point.x / point.z, point.y / point.z]);
+        Some([self.fx * d[0] + self.cx, self.fy * d[1] + self.cy])
+    }
+
+    /// Project a point given in world coordinates, seen from a camera at `pose`
+    /// (the world-to-camera transform)
+    pub fn project_world(&self, pose: &Isometry3<f64>, point: &Point3<f64>) -> Option<[f64; 2]> {
+        self.project(&(pose * point))
+    }
+
+    /// The unit-depth ray through an image point, with distortion removed
+    pub fn unproject(&self, pixel: [f64; 2]) -> Vector3<f64> {
+        let distorted = [(pixel[0] - self.cx) / self.fx, (pixel[1] - self.cy) / self.fy];
+        let n = self.undistort(distorted);
+        Vector3::new(n[0], n[1], 1.0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_unproject() {
+        let camera = CameraIntrinsics { k1: -0.2, k2: 0.05, ..CameraIntrinsics::pinhole(300.0, 310.0, 160.0, 120.0) };
+        let point = Point3::new(0.3, -0.2, 2.0);
+        let pixel = camera.project(&point).unwrap();
+        let ray = camera.unproject(pixel);
+        assert!((ray.x - 0.15).abs() < 1e-9);
+        assert!((ray.y + 0.1).abs() < 1e-9);
+        assert!(camera.project(&Point3::new(0.0, 0.0, -1.0)).is_none());
+    }
+}
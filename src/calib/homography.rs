@@ -0,0 +1,156 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Planar homographies, estimated from point correspondences by the
+//! normalized direct linear transform.
+
+use nalgebra::{DMatrix, Matrix3, Vector3};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+
+/// A 3x3 projective mapping between two planes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Homography {
+    pub matrix: Matrix3<f64>,
+}
+
+/// Similarity transform moving the points' centroid to the origin,
+/// with mean distance sqrt(2) from it
+pub(crate) fn normalizing_transform(points: &[[f64; 2]]) -> Matrix3<f64> {
+    let n = points.len() as f64;
+    let cx = points.iter().map(|p| p[0]).sum::<f64>() / n;
+    let cy = points.iter().map(|p| p[1]).sum::<f64>() / n;
+    let mean_dist = points.iter()
+        .map(|p| ((p[0] - cx).powi(2) + (p[1] - cy).powi(2)).sqrt())
+        .sum::<f64>() / n;
+    let scale = if mean_dist > 0.0 { 2f64.sqrt() / mean_dist } else { 1.0 };
+    Matrix3::new(
+        scale, 0.0, -scale * cx,
+        0.0, scale, -scale * cy,
+        0.0, 0.0, 1.0)
+}
+
+impl Homography {
+    /// Estimate the homography mapping each `from` point to the matching `to` point.
+    /// Needs at least four correspondences, no three of them collinear.
+    pub fn estimate(from: &[[f64; 2]], to: &[[f64; 2]]) -> Option<Self> {
+        if from.len() < 4 || from.len() != to.len() {
+            return None;
+        }
+        let t_from = normalizing_transform(from);
+        let t_to = normalizing_transform(to);
+
+        let mut a = DMatrix::<f64>::zeros(2 * from.len(), 9);
+        for (i, (p, q)) in from.iter().zip(to.iter()).enumerate() {
+            let p = t_from * Vector3::new(p[0], p[1], 1.0);
+            let q = t_to * Vector3::new(q[0], q[1], 1.0);
+            let (x, y, u, v) = (p.x / p.z, p.y / p.z, q.x / q.z, q.y / q.z);
+            let rows = [
+                [-x, -y, -1.0, 0.0, 0.0, 0.0, u * x, u * y, u],
+                [0.0, 0.0, 0.0, -x, -y, -1.0, v * x, v * y, v],
+            ];
+            for (r, row) in rows.iter().enumerate() {
+                for (c, val) in row.iter().enumerate() {
+                    a[(2 * i + r, c)] = *val;
+                }
+            }
+        }
+
+        // the solution is the eigenvector of A^T A with the smallest eigenvalue
+        let ata = a.transpose() * &a;
+        let eigen = ata.symmetric_eigen();
+        let (min_idx, _) = eigen.eigenvalues.iter().enumerate()
+            .min_by(|x, y| x.1.partial_cmp(y.1).unwrap())?;
+        let h = eigen.eigenvectors.column(min_idx);
+        let normalized = Matrix3::new(h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7], h[8]);
+
+        let matrix = t_to.try_inverse()? * normalized * t_from;
+        if matrix[(2, 2)].abs() < 1e-12 {
+            return None;
+        }
+        Some(Homography { matrix: matrix / matrix[(2, 2)] })
+    }
+
+    /// Map a point through the homography
+    pub fn apply(&self, point: [f64; 2]) -> [f64; 2] {
+        let p = self.matrix * Vector3::new(point[0], point[1], 1.0);
+        [p.x / p.z, p.y / p.z]
+    }
+
+    pub fn inverse(&self) -> Option<Self> {
+        self.matrix.try_inverse().map(|matrix| Homography { matrix })
+    }
+
+    /// distance between `to` and the mapped `from` point
+    pub fn transfer_error(&self, from: [f64; 2], to: [f64; 2]) -> f64 {
+        let mapped = self.apply(from);
+        ((mapped[0] - to[0]).powi(2) + (mapped[1] - to[1]).powi(2)).sqrt()
+    }
+
+    /// Robustly estimate the homography when some correspondences are wrong:
+    /// the fit to random minimal samples with the most correspondences within
+    /// `threshold` is refit to all of them. Returns the homography and the inlier indices.
+    pub fn estimate_ransac(from: &[[f64; 2]], to: &[[f64; 2]], threshold: f64, iterations: usize, seed: u64)
+        -> Option<(Self, Vec<usize>)>
+    {
+        if from.len() < 4 || from.len() != to.len() {
+            return None;
+        }
+        let inliers_of = |h: &Homography| -> Vec<usize> {
+            (0..from.len()).filter(|&i| h.transfer_error(from[i], to[i]) <= threshold).collect()
+        };
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut best: Vec<usize> = Vec::new();
+        for _ in 0..iterations {
+            let sample = rand::seq::index::sample(&mut rng, from.len(), 4).into_vec();
+            let sample_from: Vec<[f64; 2]> = sample.iter().map(|&i| from[i]).collect();
+            let sample_to: Vec<[f64; 2]> = sample.iter().map(|&i| to[i]).collect();
+            if let Some(h) = Homography::estimate(&sample_from, &sample_to) {
+                let inliers = inliers_of(&h);
+                if inliers.len() > best.len() {
+                    best = inliers;
+                    if best.len() == from.len() {
+                        break;
+                    }
+                }
+            }
+        }
+        if best.len() < 4 {
+            return None;
+        }
+        let inlier_from: Vec<[f64; 2]> = best.iter().map(|&i| from[i]).collect();
+        let inlier_to: Vec<[f64; 2]> = best.iter().map(|&i| to[i]).collect();
+        let h = Homography::estimate(&inlier_from, &inlier_to)?;
+        let inliers = inliers_of(&h);
+        Some((h, inliers))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_homography() {
+        let truth = Homography {
+            matrix: Matrix3::new(
+                1.2, 0.1, 30.0,
+                -0.05, 0.9, 12.0,
+                0.0005, 0.0002, 1.0),
+        };
+        let from: Vec<[f64; 2]> = (0..3)
+            .flat_map(|r| (0..3).map(move |c| [c as f64 * 20.0, r as f64 * 20.0]))
+            .collect();
+        let to: Vec<[f64; 2]> = from.iter().map(|&p| truth.apply(p)).collect();
+        let estimated = Homography::estimate(&from, &to).unwrap();
+        for (p, q) in from.iter().zip(to.iter()) {
+            let mapped = estimated.apply(*p);
+            assert!((mapped[0] - q[0]).abs() < 1e-6 && (mapped[1] - q[1]).abs() < 1e-6);
+        }
+        let back = estimated.inverse().unwrap().apply(to[4]);
+        assert!((back[0] - from[4][0]).abs() < 1e-6);
+        assert!(Homography::estimate(&from[..3], &to[..3]).is_none());
+    }
+}
@@ -0,0 +1,279 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Intrinsic calibration from several views of a planar target.
+//!
+//! An initial pinhole estimate is computed in closed form from the per-view homographies
+//! (Zhang, "A Flexible New Technique for Camera Calibration", 2000), then all intrinsics,
+//! radial distortion and per-view poses are refined together by Levenberg-Marquardt
+//! minimization of the reprojection error.
+
+use nalgebra::{DMatrix, DVector, Isometry3, Matrix3, Point3, Rotation3, Translation3, UnitQuaternion, Vector3};
+
+use crate::calib::camera::CameraIntrinsics;
+use crate::calib::homography::Homography;
+use crate::calib::lm::levenberg_marquardt;
+use crate::calib::target::{TargetConfig, TargetDetector};
+use crate::calib::Correspondence;
+use crate::sae_types::*;
+
+
+/// Output of a calibration
+#[derive(Clone, Debug)]
+pub struct Calibration {
+    pub intrinsics: CameraIntrinsics,
+    /// target-to-camera transform for each view
+    pub poses: Vec<Isometry3<f64>>,
+    /// root-mean-square reprojection error, in pixels
+    pub rms_error: f64,
+}
+
+const INTRINSIC_PARAMS: usize = 6;
+const POSE_PARAMS: usize = 6;
+const MAX_ITERATIONS: usize = 100;
+
+fn object_point(corr: &Correspondence) -> Point3<f64> {
+    Point3::new(corr.object[0] as f64, corr.object[1] as f64, 0.0)
+}
+
+/// Row of the Zhang constraint matrix built from homography columns i and j
+fn zhang_row(h: &Matrix3<f64>, i: usize, j: usize) -> [f64; 6] {
+    [
+        h[(0, i)] * h[(0, j)],
+        h[(0, i)] * h[(1, j)] + h[(1, i)] * h[(0, j)],
+        h[(1, i)] * h[(1, j)],
+        h[(2, i)] * h[(0, j)] + h[(0, i)] * h[(2, j)],
+        h[(2, i)] * h[(1, j)] + h[(1, i)] * h[(2, j)],
+        h[(2, i)] * h[(2, j)],
+    ]
+}
+
+/// Closed-form zero-skew pinhole intrinsics from three or more homographies
+fn initial_intrinsics(homographies: &[Homography]) -> Option<CameraIntrinsics> {
+    let mut v = DMatrix::<f64>::zeros(2 * homographies.len(), 6);
+    for (n, h) in homographies.iter().enumerate() {
+        let v12 = zhang_row(&h.matrix, 0, 1);
+        let v11 = zhang_row(&h.matrix, 0, 0);
+        let v22 = zhang_row(&h.matrix, 1, 1);
+        for k in 0..6 {
+            v[(2 * n, k)] = v12[k];
+            v[(2 * n + 1, k)] = v11[k] - v22[k];
+        }
+    }
+    let eigen = (v.transpose() * &v).symmetric_eigen();
+    let (min_idx, _) = eigen.eigenvalues.iter().enumerate()
+        .min_by(|a, b| a.1.partial_cmp(b.1).unwrap())?;
+    let mut b: Vec<f64> = eigen.eigenvectors.column(min_idx).iter().cloned().collect();
+    if b[0] < 0.0 {
+        b.iter_mut().for_each(|val| *val = -*val);
+    }
+    let (b11, b12, b22, b13, b23, b33) = (b[0], b[1], b[2], b[3], b[4], b[5]);
+
+    let denom = b11 * b22 - b12 * b12;
+    if denom.abs() < 1e-300 {
+        return None;
+    }
+    let cy = (b12 * b13 - b11 * b23) / denom;
+    let lambda = b33 - (b13 * b13 + cy * (b12 * b13 - b11 * b23)) / b11;
+    let fx = (lambda / b11).sqrt();
+    let fy = (lambda * b11 / denom).sqrt();
+    let cx = -b13 * fx * fx / lambda;
+    if !(fx.is_finite() && fy.is_finite() && cx.is_finite() && cy.is_finite()) {
+        return None;
+    }
+    Some(CameraIntrinsics::pinhole(fx, fy, cx, cy))
+}
+
+/// Target pose from a homography and the camera matrix
+pub(crate) fn initial_pose(h: &Homography, k_inv: &Matrix3<f64>) -> Isometry3<f64> {
+    let h1 = k_inv * h.matrix.column(0);
+    let h2 = k_inv * h.matrix.column(1);
+    let h3 = k_inv * h.matrix.column(2);
+    let mut scale = 1.0 / h1.norm();
+    // the target lies in front of the camera
+    if h3.z * scale < 0.0 {
+        scale = -scale;
+    }
+    let r1 = h1 * scale;
+    let r2 = h2 * scale;
+    let r3 = r1.cross(&r2);
+    let approx = Matrix3::from_columns(&[r1, r2, r3]);
+    // nearest rotation matrix
+    let svd = approx.svd(true, true);
+    let rotation = svd.u.unwrap() * svd.v_t.unwrap();
+    let rotation = Rotation3::from_matrix_unchecked(rotation);
+    Isometry3::from_parts(Translation3::from(h3 * scale), UnitQuaternion::from_rotation_matrix(&rotation))
+}
+
+fn pack(intrinsics: &CameraIntrinsics, poses: &[Isometry3<f64>]) -> DVector<f64> {
+    let mut params = DVector::zeros(INTRINSIC_PARAMS + POSE_PARAMS * poses.len());
+    params[0] = intrinsics.fx;
+    params[1] = intrinsics.fy;
+    params[2] = intrinsics.cx;
+    params[3] = intrinsics.cy;
+    params[4] = intrinsics.k1;
+    params[5] = intrinsics.k2;
+    for (i, pose) in poses.iter().enumerate() {
+        let base = INTRINSIC_PARAMS + POSE_PARAMS * i;
+        let axis = pose.rotation.scaled_axis();
+        let t = pose.translation.vector;
+        for k in 0..3 {
+            params[base + k] = axis[k];
+            params[base + 3 + k] = t[k];
+        }
+    }
+    params
+}
+
+fn unpack(params: &DVector<f64>, views: usize) -> (CameraIntrinsics, Vec<Isometry3<f64>>) {
+    let intrinsics = CameraIntrinsics {
+        fx: params[0],
+        fy: params[1],
+        cx: params[2],
+        cy: params[3],
+        k1: params[4],
+        k2: params[5],
+    };
+    let poses = (0..views).map(|i| {
+        let base = INTRINSIC_PARAMS + POSE_PARAMS * i;
+        let axis = Vector3::new(params[base], params[base + 1], params[base + 2]);
+        let t = Vector3::new(params[base + 3], params[base + 4], params[base + 5]);
+        Isometry3::new(t, axis)
+    }).collect();
+    (intrinsics, poses)
+}
+
+/// Reprojection residuals (image minus projection) for all correspondences
+fn residuals(params: &DVector<f64>, views: &[Vec<Correspondence>]) -> DVector<f64> {
+    let (intrinsics, poses) = unpack(params, views.len());
+    let count: usize = views.iter().map(|v| v.len()).sum();
+    let mut res = DVector::zeros(2 * count);
+    let mut idx = 0;
+    for (view, pose) in views.iter().zip(poses.iter()) {
+        for corr in view {
+            // points behind the camera get a large, but finite, residual
+            let projected = intrinsics.project_world(pose, &object_point(corr)).unwrap_or([1e6, 1e6]);
+            res[idx] = corr.image[0] as f64 - projected[0];
+            res[idx + 1] = corr.image[1] as f64 - projected[1];
+            idx += 2;
+        }
+    }
+    res
+}
+
+/// Calibrate from three or more views of a planar target.
+/// Returns None if there are too few views, or a view has fewer than four correspondences.
+pub fn calibrate(views: &[Vec<Correspondence>]) -> Option<Calibration> {
+    if views.len() < 3 || views.iter().any(|v| v.len() < 4) {
+        return None;
+    }
+    let homographies = views.iter()
+        .map(|view| {
+            let object: Vec<[f64; 2]> = view.iter().map(|c| [c.object[0] as f64, c.object[1] as f64]).collect();
+            let image: Vec<[f64; 2]> = view.iter().map(|c| [c.image[0] as f64, c.image[1] as f64]).collect();
+            Homography::estimate(&object, &image)
+        })
+        .collect::<Option<Vec<Homography>>>()?;
+
+    let initial = initial_intrinsics(&homographies)?;
+    let k_inv = initial.matrix().try_inverse()?;
+    let poses: Vec<Isometry3<f64>> = homographies.iter().map(|h| initial_pose(h, &k_inv)).collect();
+
+    let params = levenberg_marquardt(pack(&initial, &poses), |p| residuals(p, views), MAX_ITERATIONS);
+    let res = residuals(&params, views);
+    let rms_error = (res.norm_squared() / (res.len() / 2) as f64).sqrt();
+    let (intrinsics, poses) = unpack(&params, views.len());
+    Some(Calibration { intrinsics, poses, rms_error })
+}
+
+/// Calibrate-from-recording workflow: feed corner events while the target is moved
+/// around, closing a view whenever the target has been held still long enough,
+/// then solve for the intrinsics from all complete views.
+pub struct CalibrationSession {
+    detector: TargetDetector,
+    views: Vec<Vec<Correspondence>>,
+}
+
+impl CalibrationSession {
+    pub fn new(target: TargetConfig) -> Self {
+        CalibrationSession {
+            detector: TargetDetector::new(target),
+            views: Vec::new(),
+        }
+    }
+
+    pub fn add_corner(&mut self, corner: &SaeEvent) {
+        self.detector.add_corner(corner);
+    }
+
+    /// Finish the current view: keep its correspondences if the full target was found.
+    /// Returns whether the view was kept.
+    pub fn end_view(&mut self) -> bool {
+        let found = self.detector.correspondences();
+        self.detector.reset();
+        match found {
+            Some(view) => {
+                self.views.push(view);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn views(&self) -> &[Vec<Correspondence>] {
+        &self.views
+    }
+
+    pub fn calibrate(&self) -> Option<Calibration> {
+        calibrate(&self.views)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn true_camera() -> CameraIntrinsics {
+        CameraIntrinsics { k1: -0.15, k2: 0.03, ..CameraIntrinsics::pinhole(420.0, 410.0, 170.0, 125.0) }
+    }
+
+    /// poses of a 7x5 target with 20 unit spacing, seen from a few angles
+    fn synthetic_views(camera: &CameraIntrinsics) -> Vec<Vec<Correspondence>> {
+        let poses = [
+            Isometry3::new(Vector3::new(-60.0, -40.0, 400.0), Vector3::new(0.1, -0.2, 0.05)),
+            Isometry3::new(Vector3::new(-50.0, -30.0, 350.0), Vector3::new(-0.3, 0.1, -0.1)),
+            Isometry3::new(Vector3::new(-70.0, -50.0, 450.0), Vector3::new(0.25, 0.3, 0.2)),
+            Isometry3::new(Vector3::new(-40.0, -45.0, 380.0), Vector3::new(-0.2, -0.35, 0.0)),
+        ];
+        poses.iter().map(|pose| {
+            let mut view = Vec::new();
+            for gr in 0..5 {
+                for gc in 0..7 {
+                    let object = [gc as f32 * 20.0, gr as f32 * 20.0];
+                    let point = Point3::new(object[0] as f64, object[1] as f64, 0.0);
+                    let image = camera.project_world(pose, &point).unwrap();
+                    view.push(Correspondence { image: [image[0] as f32, image[1] as f32], object });
+                }
+            }
+            view
+        }).collect()
+    }
+
+    #[test]
+    fn test_calibrate_synthetic() {
+        let truth = true_camera();
+        let views = synthetic_views(&truth);
+        let calibration = calibrate(&views).unwrap();
+        let found = calibration.intrinsics;
+        assert!(calibration.rms_error < 0.01, "rms {}", calibration.rms_error);
+        assert!((found.fx - truth.fx).abs() < 1.0, "fx {}", found.fx);
+        assert!((found.fy - truth.fy).abs() < 1.0, "fy {}", found.fy);
+        assert!((found.cx - truth.cx).abs() < 1.0, "cx {}", found.cx);
+        assert!((found.cy - truth.cy).abs() < 1.0, "cy {}", found.cy);
+        assert!((found.k1 - truth.k1).abs() < 0.01, "k1 {}", found.k1);
+        assert_eq!(calibration.poses.len(), 4);
+
+        assert!(calibrate(&views[..2]).is_none());
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Damped least-squares minimization shared by the calibration and pose solvers.
+
+use nalgebra::{DMatrix, DVector};
+
+
+/// Minimize the sum of squared residuals over the parameters, by Levenberg-Marquardt
+/// with a forward-difference Jacobian
+pub(crate) fn levenberg_marquardt<F>(mut params: DVector<f64>, residuals: F, max_iterations: usize) -> DVector<f64>
+    where F: Fn(&DVector<f64>) -> DVector<f64>
+{
+    let mut res = residuals(&params);
+    let mut cost = res.norm_squared();
+    let mut damping = 1e-3;
+    for _ in 0..max_iterations {
+        let mut jacobian = DMatrix::<f64>::zeros(res.len(), params.len());
+        for k in 0..params.len() {
+            let step = 1e-6 * params[k].abs().max(1e-3);
+            let mut shifted = params.clone();
+            shifted[k] += step;
+            let diff = (residuals(&shifted) - &res) / step;
+            jacobian.set_column(k, &diff);
+        }
+        let jtj = jacobian.transpose() * &jacobian;
+        let jtr = jacobian.transpose() * &res;
+
+        let mut improved = false;
+        while damping < 1e12 {
+            let mut augmented = jtj.clone();
+            for k in 0..params.len() {
+                augmented[(k, k)] += damping * jtj[(k, k)].max(1e-12);
+            }
+            let delta = match augmented.cholesky() {
+                Some(chol) => chol.solve(&(-&jtr)),
+                None => {
+                    damping *= 10.0;
+                    continue;
+                }
+            };
+            let candidate = &params + &delta;
+            let candidate_res = residuals(&candidate);
+            let candidate_cost = candidate_res.norm_squared();
+            if candidate_cost < cost {
+                let converged = (cost - candidate_cost) < 1e-12 * cost.max(1e-12);
+                params = candidate;
+                res = candidate_res;
+                cost = candidate_cost;
+                damping = (damping / 10.0).max(1e-12);
+                improved = !converged;
+                break;
+            }
+            damping *= 10.0;
+        }
+        if !improved {
+            break;
+        }
+    }
+    params
+}
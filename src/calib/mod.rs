@@ -0,0 +1,24 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Camera calibration from event data: calibration target detection,
+//! the geometry used to relate image points to target points, camera pose
+//! estimation from known landmarks, and reprojection residuals of tracked corners.
+
+pub mod camera;
+pub mod homography;
+pub mod intrinsics;
+mod lm;
+pub mod pnp;
+pub mod reprojection;
+pub mod target;
+
+
+/// A detected image point and the known position of the same point on a planar target
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Correspondence {
+    /// image position (x = column, y = row), in pixels
+    pub image: [f32; 2],
+    /// position on the target plane, in target units (eg millimeters)
+    pub object: [f32; 2],
+}
@@ -0,0 +1,340 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Camera pose from corners matched to known 3-D landmarks (Perspective-n-Point).
+//!
+//! An initial pose comes from a linear solve: a homography when the landmarks are
+//! coplanar (eg the corners of a marker), otherwise the direct linear transform.
+//! It is then refined by minimizing reprojection error. `solve_pnp_ransac` repeats the
+//! linear solve on random minimal samples to reject mismatched corners.
+
+use std::collections::HashMap;
+
+use nalgebra::{DMatrix, DVector, Isometry3, Matrix3, Point3, Rotation3, Translation3, UnitQuaternion, Vector3};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::calib::camera::CameraIntrinsics;
+use crate::calib::homography::Homography;
+use crate::calib::intrinsics::initial_pose;
+use crate::calib::lm::levenberg_marquardt;
+use crate::track::{TrackId, TrackStore};
+
+
+/// An image point matched to a known landmark position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LandmarkObservation {
+    /// image position (x = column, y = row), in pixels
+    pub image: [f64; 2],
+    /// landmark position in world coordinates
+    pub landmark: Point3<f64>,
+}
+
+/// Parameters for robust pose estimation
+#[derive(Clone, Debug, PartialEq)]
+pub struct PnpConfig {
+    /// number of random minimal samples to try
+    pub iterations: usize,
+    /// maximum reprojection error (pixels) for an observation to count as an inlier
+    pub inlier_threshold: f64,
+    /// minimum inliers for a pose to be accepted
+    pub min_inliers: usize,
+    /// seed for sample selection, so that results are repeatable
+    pub seed: u64,
+}
+
+impl Default for PnpConfig {
+    fn default() -> Self {
+        PnpConfig {
+            iterations: 200,
+            inlier_threshold: 2.0,
+            min_inliers: 6,
+            seed: 0,
+        }
+    }
+}
+
+/// A robustly estimated pose
+#[derive(Clone, Debug)]
+pub struct PnpSolution {
+    /// world-to-camera transform
+    pub pose: Isometry3<f64>,
+    /// indices of the observations consistent with the pose
+    pub inliers: Vec<usize>,
+    /// root-mean-square reprojection error over the inliers, in pixels
+    pub rms_error: f64,
+}
+
+const REFINE_ITERATIONS: usize = 50;
+
+/// Centroid and principal axes of a point set, axes ordered by decreasing spread
+fn principal_axes(points: &[Point3<f64>]) -> (Vector3<f64>, Matrix3<f64>, Vector3<f64>) {
+    let centroid = points.iter().fold(Vector3::zeros(), |acc, p| acc + p.coords) / points.len() as f64;
+    let mut cov = Matrix3::zeros();
+    for p in points {
+        let d = p.coords - centroid;
+        cov += d * d.transpose();
+    }
+    let eigen = cov.symmetric_eigen();
+    let mut order = [0, 1, 2];
+    order.sort_by(|&a, &b| eigen.eigenvalues[b].partial_cmp(&eigen.eigenvalues[a]).unwrap());
+    let axes = Matrix3::from_columns(&[
+        eigen.eigenvectors.column(order[0]).into_owned(),
+        eigen.eigenvectors.column(order[1]).into_owned(),
+        eigen.eigenvectors.column(order[2]).into_owned(),
+    ]);
+    let spread = Vector3::new(eigen.eigenvalues[order[0]], eigen.eigenvalues[order[1]], eigen.eigenvalues[order[2]]);
+    (centroid, axes, spread)
+}
+
+fn is_planar(landmarks: &[Point3<f64>]) -> bool {
+    let (_, _, spread) = principal_axes(landmarks);
+    spread[2] <= 1e-9 * spread[0]
+}
+
+/// Pose from coplanar landmarks, via the homography from the landmark plane
+fn planar_pose(rays: &[[f64; 2]], landmarks: &[Point3<f64>]) -> Option<Isometry3<f64>> {
+    let (centroid, mut axes, _) = principal_axes(landmarks);
+    let normal = axes.column(0).cross(&axes.column(1));
+    axes.set_column(2, &normal);
+    let local: Vec<[f64; 2]> = landmarks.iter()
+        .map(|p| {
+            let d = p.coords - centroid;
+            [d.dot(&axes.column(0)), d.dot(&axes.column(1))]
+        })
+        .collect();
+    let h = Homography::estimate(&local, rays)?;
+    let plane_to_camera = initial_pose(&h, &Matrix3::identity());
+    // world to plane frame: p_local = axes^T (p - centroid)
+    let rotation = Rotation3::from_matrix_unchecked(axes.transpose());
+    let world_to_plane = Isometry3::from_parts(
+        Translation3::from(-(axes.transpose() * centroid)),
+        UnitQuaternion::from_rotation_matrix(&rotation));
+    Some(plane_to_camera * world_to_plane)
+}
+
+/// Pose from six or more landmarks in general position, by the direct linear transform
+fn dlt_pose(rays: &[[f64; 2]], landmarks: &[Point3<f64>]) -> Option<Isometry3<f64>> {
+    // condition the landmarks: centered, unit mean distance
+    let centroid = landmarks.iter().fold(Vector3::zeros(), |acc, p| acc + p.coords) / landmarks.len() as f64;
+    let scale = landmarks.iter().map(|p| (p.coords - centroid).norm()).sum::<f64>() / landmarks.len() as f64;
+    if scale <= 0.0 {
+        return None;
+    }
+    let mut a = DMatrix::<f64>::zeros(2 * rays.len(), 12);
+    for (i, (ray, p)) in rays.iter().zip(landmarks.iter()).enumerate() {
+        let q = (p.coords - centroid) / scale;
+        let homog = [q.x, q.y, q.z, 1.0];
+        for k in 0..4 {
+            a[(2 * i, k)] = homog[k];
+            a[(2 * i, 8 + k)] = -ray[0] * homog[k];
+            a[(2 * i + 1, 4 + k)] = homog[k];
+            a[(2 * i + 1, 8 + k)] = -ray[1] * homog[k];
+        }
+    }
+    let eigen = (a.transpose() * &a).symmetric_eigen();
+    let (min_idx, _) = eigen.eigenvalues.iter().enumerate()
+        .min_by(|a, b| a.1.partial_cmp(b.1).unwrap())?;
+    let p = eigen.eigenvectors.column(min_idx);
+    let mut m = Matrix3::new(p[0], p[1], p[2], p[4], p[5], p[6], p[8], p[9], p[10]);
+    let mut t = Vector3::new(p[3], p[7], p[11]);
+    // the landmarks lie in front of the camera: their centroid has positive depth
+    if t.z < 0.0 {
+        m = -m;
+        t = -t;
+    }
+    let svd = m.svd(true, true);
+    let rotation = svd.u? * svd.v_t?;
+    if rotation.determinant() <= 0.0 {
+        return None;
+    }
+    let s = svd.singular_values.sum() / 3.0;
+    let t = t * (scale / s) - rotation * centroid;
+    let rotation = Rotation3::from_matrix_unchecked(rotation);
+    Some(Isometry3::from_parts(Translation3::from(t), UnitQuaternion::from_rotation_matrix(&rotation)))
+}
+
+fn linear_pose(camera: &CameraIntrinsics, observations: &[LandmarkObservation], planar: bool) -> Option<Isometry3<f64>> {
+    let rays: Vec<[f64; 2]> = observations.iter()
+        .map(|obs| {
+            let ray = camera.unproject(obs.image);
+            [ray.x, ray.y]
+        })
+        .collect();
+    let landmarks: Vec<Point3<f64>> = observations.iter().map(|obs| obs.landmark).collect();
+    if planar {
+        planar_pose(&rays, &landmarks)
+    } else {
+        dlt_pose(&rays, &landmarks)
+    }
+}
+
+fn reprojection_error(camera: &CameraIntrinsics, pose: &Isometry3<f64>, obs: &LandmarkObservation) -> f64 {
+    match camera.project_world(pose, &obs.landmark) {
+        Some(p) => ((p[0] - obs.image[0]).powi(2) + (p[1] - obs.image[1]).powi(2)).sqrt(),
+        None => f64::INFINITY,
+    }
+}
+
+/// Minimize reprojection error over the pose
+fn refine_pose(camera: &CameraIntrinsics, observations: &[LandmarkObservation], pose: &Isometry3<f64>) -> Isometry3<f64> {
+    let axis = pose.rotation.scaled_axis();
+    let t = pose.translation.vector;
+    let params = DVector::from_column_slice(&[axis.x, axis.y, axis.z, t.x, t.y, t.z]);
+    let residuals = |p: &DVector<f64>| {
+        let pose = Isometry3::new(Vector3::new(p[3], p[4], p[5]), Vector3::new(p[0], p[1], p[2]));
+        let mut res = DVector::zeros(2 * observations.len());
+        for (i, obs) in observations.iter().enumerate() {
+            let projected = camera.project_world(&pose, &obs.landmark).unwrap_or([1e6, 1e6]);
+            res[2 * i] = obs.image[0] - projected[0];
+            res[2 * i + 1] = obs.image[1] - projected[1];
+        }
+        res
+    };
+    let p = levenberg_marquardt(params, residuals, REFINE_ITERATIONS);
+    Isometry3::new(Vector3::new(p[3], p[4], p[5]), Vector3::new(p[0], p[1], p[2]))
+}
+
+fn min_sample_size(planar: bool) -> usize {
+    if planar { 4 } else { 6 }
+}
+
+/// Estimate the world-to-camera pose from all observations, assuming they are all correct.
+/// Needs at least four coplanar or six general landmarks.
+pub fn solve_pnp(camera: &CameraIntrinsics, observations: &[LandmarkObservation]) -> Option<Isometry3<f64>> {
+    let landmarks: Vec<Point3<f64>> = observations.iter().map(|obs| obs.landmark).collect();
+    if observations.len() < 4 {
+        return None;
+    }
+    let planar = is_planar(&landmarks);
+    if observations.len() < min_sample_size(planar) {
+        return None;
+    }
+    let initial = linear_pose(camera, observations, planar)?;
+    Some(refine_pose(camera, observations, &initial))
+}
+
+/// Estimate the world-to-camera pose robustly, ignoring mismatched observations
+pub fn solve_pnp_ransac(camera: &CameraIntrinsics, observations: &[LandmarkObservation], config: &PnpConfig)
+    -> Option<PnpSolution>
+{
+    if observations.len() < 4 {
+        return None;
+    }
+    let landmarks: Vec<Point3<f64>> = observations.iter().map(|obs| obs.landmark).collect();
+    let planar = is_planar(&landmarks);
+    let sample_size = min_sample_size(planar);
+    if observations.len() < sample_size.max(config.min_inliers) {
+        return None;
+    }
+    let inliers_of = |pose: &Isometry3<f64>| -> Vec<usize> {
+        (0..observations.len())
+            .filter(|&i| reprojection_error(camera, pose, &observations[i]) <= config.inlier_threshold)
+            .collect()
+    };
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut best: Vec<usize> = Vec::new();
+    for _ in 0..config.iterations {
+        let sample: Vec<LandmarkObservation> = rand::seq::index::sample(&mut rng, observations.len(), sample_size)
+            .into_iter()
+            .map(|i| observations[i])
+            .collect();
+        if let Some(pose) = linear_pose(camera, &sample, planar) {
+            let inliers = inliers_of(&pose);
+            if inliers.len() > best.len() {
+                best = inliers;
+                if best.len() == observations.len() {
+                    break;
+                }
+            }
+        }
+    }
+    if best.len() < config.min_inliers.max(sample_size) {
+        return None;
+    }
+
+    let inlier_obs: Vec<LandmarkObservation> = best.iter().map(|&i| observations[i]).collect();
+    let initial = linear_pose(camera, &inlier_obs, planar)?;
+    let pose = refine_pose(camera, &inlier_obs, &initial);
+    let inliers = inliers_of(&pose);
+    if inliers.len() < config.min_inliers.max(sample_size) {
+        return None;
+    }
+    let sum2: f64 = inliers.iter().map(|&i| reprojection_error(camera, &pose, &observations[i]).powi(2)).sum();
+    let rms_error = (sum2 / inliers.len() as f64).sqrt();
+    Some(PnpSolution { pose, inliers, rms_error })
+}
+
+/// Observations pairing the latest corner of each track with its known landmark
+pub fn observations_from_tracks(tracks: &TrackStore, landmarks: &HashMap<TrackId, Point3<f64>>) -> Vec<LandmarkObservation> {
+    tracks.iter()
+        .filter_map(|track| {
+            let landmark = landmarks.get(&track.id)?;
+            let (row, col) = track.last().subpixel_position();
+            Some(LandmarkObservation { image: [col as f64, row as f64], landmark: *landmark })
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> CameraIntrinsics {
+        CameraIntrinsics { k1: -0.05, ..CameraIntrinsics::pinhole(300.0, 300.0, 160.0, 120.0) }
+    }
+
+    fn true_pose() -> Isometry3<f64> {
+        Isometry3::new(Vector3::new(0.2, -0.1, 5.0), Vector3::new(0.1, -0.3, 0.2))
+    }
+
+    fn observe(landmarks: &[Point3<f64>]) -> Vec<LandmarkObservation> {
+        let camera = camera();
+        let pose = true_pose();
+        landmarks.iter()
+            .map(|p| LandmarkObservation { image: camera.project_world(&pose, p).unwrap(), landmark: *p })
+            .collect()
+    }
+
+    fn assert_pose_close(found: &Isometry3<f64>, expected: &Isometry3<f64>) {
+        let dt = (found.translation.vector - expected.translation.vector).norm();
+        let dr = found.rotation.angle_to(&expected.rotation);
+        assert!(dt < 1e-3, "translation error {}", dt);
+        assert!(dr < 1e-4, "rotation error {}", dr);
+    }
+
+    #[test]
+    fn test_planar_marker() {
+        let corners = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        ];
+        let pose = solve_pnp(&camera(), &observe(&corners)).unwrap();
+        assert_pose_close(&pose, &true_pose());
+        assert!(solve_pnp(&camera(), &observe(&corners[..3])).is_none());
+    }
+
+    #[test]
+    fn test_ransac_rejects_mismatches() {
+        let mut landmarks = Vec::new();
+        for i in 0..30 {
+            let f = i as f64;
+            landmarks.push(Point3::new((f * 0.37).sin() * 2.0, (f * 0.73).cos() * 1.5, (f * 1.31).sin()));
+        }
+        let mut observations = observe(&landmarks);
+        // mismatch a few corners
+        for i in [3, 11, 17, 25].iter() {
+            observations[*i].image[0] += 40.0;
+            observations[*i].image[1] -= 25.0;
+        }
+        let solution = solve_pnp_ransac(&camera(), &observations, &PnpConfig::default()).unwrap();
+        assert_eq!(solution.inliers.len(), 26);
+        assert!(!solution.inliers.contains(&11));
+        assert!(solution.rms_error < 1e-3);
+        assert_pose_close(&solution.pose, &true_pose());
+    }
+}
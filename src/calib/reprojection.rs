@@ -0,0 +1,299 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Reprojection residuals of tracked corners: the difference between where a
+//! track's corners were observed and where its landmark projects, given a camera
+//! model and the camera pose at each observation time from a `PoseSource`.
+//!
+//! `track_residuals` and `store_residuals` serve offline evaluation, summarized by
+//! `ResidualStats`; `ReprojectionGate` rejects tracks online as their latest
+//! observation drifts from the prediction.
+
+use std::collections::HashMap;
+
+use nalgebra::{Isometry3, Point3, Translation3};
+
+use crate::calib::camera::CameraIntrinsics;
+use crate::sae_types::*;
+use crate::track::{Track, TrackId, TrackStore};
+
+
+/// Provides the camera pose (world-to-camera transform) at a given time
+pub trait PoseSource {
+    /// the pose at `timestamp`, or None if it is not known
+    fn pose_at(&self, timestamp: SaeTime) -> Option<Isometry3<f64>>;
+}
+
+/// A static camera
+impl PoseSource for Isometry3<f64> {
+    fn pose_at(&self, _timestamp: SaeTime) -> Option<Isometry3<f64>> {
+        Some(*self)
+    }
+}
+
+impl<F: Fn(SaeTime) -> Option<Isometry3<f64>>> PoseSource for F {
+    fn pose_at(&self, timestamp: SaeTime) -> Option<Isometry3<f64>> {
+        self(timestamp)
+    }
+}
+
+/// Timestamped poses, eg from motion capture or a VO back end, interpolated between
+/// samples: linearly in translation and spherically in rotation
+#[derive(Clone, Debug, Default)]
+pub struct PoseTrajectory {
+    /// in time order
+    poses: Vec<(SaeTime, Isometry3<f64>)>,
+}
+
+impl PoseTrajectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a pose; poses out of time order are ignored
+    pub fn push(&mut self, timestamp: SaeTime, pose: Isometry3<f64>) {
+        if self.poses.last().is_none_or(|&(last, _)| timestamp > last) {
+            self.poses.push((timestamp, pose));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.poses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.poses.is_empty()
+    }
+}
+
+impl PoseSource for PoseTrajectory {
+    /// None outside the time spanned by the trajectory
+    fn pose_at(&self, timestamp: SaeTime) -> Option<Isometry3<f64>> {
+        let next = self.poses.partition_point(|&(t, _)| t < timestamp);
+        let &(t1, pose1) = self.poses.get(next)?;
+        if t1 == timestamp {
+            return Some(pose1);
+        }
+        let &(t0, pose0) = self.poses.get(next.checked_sub(1)?)?;
+        let frac = (timestamp - t0) as f64 / (t1 - t0) as f64;
+        let translation = pose0.translation.vector + (pose1.translation.vector - pose0.translation.vector) * frac;
+        let rotation = pose0.rotation.slerp(&pose1.rotation, frac);
+        Some(Isometry3::from_parts(Translation3::from(translation), rotation))
+    }
+}
+
+/// The reprojection residual of one corner observation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReprojectionResidual {
+    pub track: TrackId,
+    pub timestamp: SaeTime,
+    /// observed image position (x = column, y = row), in pixels
+    pub observed: [f64; 2],
+    /// projected landmark position, in pixels
+    pub predicted: [f64; 2],
+}
+
+impl ReprojectionResidual {
+    /// observed minus predicted position
+    pub fn residual(&self) -> [f64; 2] {
+        [self.observed[0] - self.predicted[0], self.observed[1] - self.predicted[1]]
+    }
+
+    /// length of the residual, in pixels
+    pub fn error(&self) -> f64 {
+        let [dx, dy] = self.residual();
+        dx.hypot(dy)
+    }
+}
+
+/// The residual of one corner of track `track` against `landmark`, if the pose at
+/// its time is known and the landmark lies in front of the camera
+pub fn corner_residual<P: PoseSource + ?Sized>(camera: &CameraIntrinsics, poses: &P, track: TrackId,
+                                               corner: &SaeEvent, landmark: &Point3<f64>) -> Option<ReprojectionResidual> {
+    let pose = poses.pose_at(corner.timestamp)?;
+    let predicted = camera.project_world(&pose, landmark)?;
+    let (row, col) = corner.subpixel_position();
+    Some(ReprojectionResidual { track, timestamp: corner.timestamp, observed: [col as f64, row as f64], predicted })
+}
+
+/// Residuals of every observation of `track` against its `landmark`
+pub fn track_residuals<P: PoseSource + ?Sized>(camera: &CameraIntrinsics, poses: &P, track: &Track,
+                                               landmark: &Point3<f64>) -> Vec<ReprojectionResidual> {
+    track.observations.iter()
+        .filter_map(|corner| corner_residual(camera, poses, track.id, corner, landmark))
+        .collect()
+}
+
+/// Residuals of every observation of every track with a known landmark, in track id order
+pub fn store_residuals<P: PoseSource + ?Sized>(camera: &CameraIntrinsics, poses: &P, store: &TrackStore,
+                                               landmarks: &HashMap<TrackId, Point3<f64>>) -> Vec<ReprojectionResidual> {
+    store.iter()
+        .filter_map(|track| landmarks.get(&track.id).map(|landmark| track_residuals(camera, poses, track, landmark)))
+        .flatten()
+        .collect()
+}
+
+/// Summary of a set of residuals, in pixels
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResidualStats {
+    pub count: usize,
+    pub mean: f64,
+    pub rms: f64,
+    pub median: f64,
+    pub max: f64,
+}
+
+impl ResidualStats {
+    pub fn from_residuals(residuals: &[ReprojectionResidual]) -> Self {
+        if residuals.is_empty() {
+            return Self::default();
+        }
+        let mut errors: Vec<f64> = residuals.iter().map(|r| r.error()).collect();
+        errors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let count = errors.len();
+        ResidualStats {
+            count,
+            mean: errors.iter().sum::<f64>() / count as f64,
+            rms: (errors.iter().map(|e| e * e).sum::<f64>() / count as f64).sqrt(),
+            median: errors[count / 2],
+            max: errors[count - 1],
+        }
+    }
+
+    /// fraction of residuals no larger than `threshold`
+    pub fn inlier_fraction(residuals: &[ReprojectionResidual], threshold: f64) -> f64 {
+        if residuals.is_empty() {
+            return 0.0;
+        }
+        residuals.iter().filter(|r| r.error() <= threshold).count() as f64 / residuals.len() as f64
+    }
+}
+
+/// Online outlier rejection: flags tracks whose latest observation reprojects
+/// more than `threshold` pixels from its landmark
+pub struct ReprojectionGate {
+    camera: CameraIntrinsics,
+    threshold: f64,
+    rejected: u64,
+}
+
+impl ReprojectionGate {
+    pub fn new(camera: CameraIntrinsics, threshold: f64) -> Self {
+        ReprojectionGate { camera, threshold, rejected: 0 }
+    }
+
+    /// total number of tracks flagged so far
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// Whether `corner`, a new observation of `track`, is consistent with `landmark`.
+    /// Observations whose residual can't be computed are accepted.
+    pub fn accept<P: PoseSource + ?Sized>(&mut self, poses: &P, track: TrackId, corner: &SaeEvent, landmark: &Point3<f64>) -> bool {
+        match corner_residual(&self.camera, poses, track, corner, landmark) {
+            Some(residual) if residual.error() > self.threshold => {
+                self.rejected += 1;
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Ids of the tracks with a known landmark whose latest observation is an outlier
+    pub fn check<P: PoseSource + ?Sized>(&mut self, poses: &P, store: &TrackStore,
+                                         landmarks: &HashMap<TrackId, Point3<f64>>) -> Vec<TrackId> {
+        let mut flagged = Vec::new();
+        for track in store.iter() {
+            if let Some(landmark) = landmarks.get(&track.id) {
+                if !self.accept(poses, track.id, track.last(), landmark) {
+                    flagged.push(track.id);
+                }
+            }
+        }
+        flagged
+    }
+
+    /// Like `check`, removing the flagged tracks from the store
+    pub fn prune<P: PoseSource + ?Sized>(&mut self, poses: &P, store: &mut TrackStore,
+                                         landmarks: &HashMap<TrackId, Point3<f64>>) -> Vec<TrackId> {
+        let flagged = self.check(poses, store, landmarks);
+        for id in flagged.iter() {
+            store.remove(*id);
+        }
+        flagged
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector3;
+
+    fn corner_at(p: [f64; 2], timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row_f: Some(p[1] as f32), col_f: Some(p[0] as f32), timestamp, ..SaeEvent::default() }
+    }
+
+    fn pose(step: u32) -> Isometry3<f64> {
+        let s = step as f64 * 0.1;
+        Isometry3::new(Vector3::new(-s, 0.0, 0.0), Vector3::new(0.0, 0.05 * s, 0.0))
+    }
+
+    #[test]
+    fn test_trajectory_interpolation() {
+        let mut trajectory = PoseTrajectory::new();
+        trajectory.push(0, pose(0));
+        trajectory.push(10_000, pose(2));
+        trajectory.push(5_000, pose(9));
+        assert_eq!(trajectory.len(), 2);
+        let mid = trajectory.pose_at(5_000).unwrap();
+        let expected = pose(1);
+        assert!((mid.translation.vector - expected.translation.vector).norm() < 1e-12);
+        assert!(mid.rotation.angle_to(&expected.rotation) < 1e-9);
+        assert!(trajectory.pose_at(10_001).is_none());
+    }
+
+    #[test]
+    fn test_residuals_and_gate() {
+        let camera = CameraIntrinsics::pinhole(200.0, 200.0, 120.0, 90.0);
+        let mut trajectory = PoseTrajectory::new();
+        for step in 0..=4 {
+            trajectory.push(step * 5_000, pose(step));
+        }
+        let mut store = TrackStore::new();
+        let mut landmarks = HashMap::new();
+        for k in 0..4 {
+            let landmark = Point3::new(k as f64 * 0.5 - 1.0, 0.3 * k as f64, 5.0);
+            let mut id = None;
+            for step in 0..=4u32 {
+                let mut pixel = camera.project_world(&pose(step), &landmark).unwrap();
+                // the last track drifts off its landmark at the end
+                if k == 3 && step == 4 {
+                    pixel[1] += 4.0;
+                }
+                let corner = corner_at(pixel, step * 5_000);
+                match id {
+                    None => id = Some(store.start_track(corner)),
+                    Some(id) => { store.extend_track(id, corner); }
+                }
+            }
+            landmarks.insert(id.unwrap(), landmark);
+        }
+
+        let residuals = store_residuals(&camera, &trajectory, &store, &landmarks);
+        assert_eq!(residuals.len(), 20);
+        let stats = ResidualStats::from_residuals(&residuals);
+        assert!((stats.max - 4.0).abs() < 1e-3);
+        assert!(stats.median < 1e-3);
+        assert!((ResidualStats::inlier_fraction(&residuals, 1.0) - 0.95).abs() < 1e-12);
+
+        let mut gate = ReprojectionGate::new(camera, 2.0);
+        let drifting = store.iter().last().unwrap().id;
+        assert_eq!(gate.prune(&trajectory, &mut store, &landmarks), vec![drifting]);
+        assert_eq!(store.len(), 3);
+        assert_eq!(gate.rejected(), 1);
+        // a static pose source
+        let static_residual = corner_residual(&camera, &pose(0), 0, &corner_at([0.0, 0.0], 7), &landmarks[&0]).unwrap();
+        assert_eq!(static_residual.timestamp, 7);
+    }
+}
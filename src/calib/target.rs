@@ -0,0 +1,288 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Detection of planar calibration targets from corner events: a grid of
+//! blinking LEDs, or the inner corners of a checkerboard held in view.
+//!
+//! Corners are clustered by position; clusters seen often enough (and, for LED
+//! targets, blinking at the expected period) are taken as target points.
+//! The four outermost clusters fix a homography to the ideal grid, through which
+//! every cluster is assigned its grid position.
+
+use crate::calib::homography::Homography;
+use crate::calib::Correspondence;
+use crate::sae_types::*;
+
+
+/// Description of the target and the clustering parameters
+#[derive(Clone, Debug, PartialEq)]
+pub struct TargetConfig {
+    /// number of grid points along the target's vertical axis
+    pub grid_rows: usize,
+    /// number of grid points along the target's horizontal axis
+    pub grid_cols: usize,
+    /// distance between neighboring grid points, in target units
+    pub spacing: f32,
+    /// corners within this distance (pixels) of a cluster center join the cluster
+    pub cluster_radius: f32,
+    /// minimum corners observed for a cluster to count as a target point
+    pub min_hits: u32,
+    /// for blinking-LED targets: the blink period that target points must show
+    pub blink_period: Option<SaeTime>,
+    /// allowed deviation from the blink period
+    pub period_tolerance: SaeTime,
+}
+
+impl Default for TargetConfig {
+    fn default() -> Self {
+        TargetConfig {
+            grid_rows: 4,
+            grid_cols: 5,
+            spacing: 30.0,
+            cluster_radius: 2.5,
+            min_hits: 5,
+            blink_period: None,
+            period_tolerance: 500,
+        }
+    }
+}
+
+/// Corners gathered around one image position
+#[derive(Clone, Debug)]
+pub struct CornerCluster {
+    /// mean position (x = column, y = row)
+    pub center: [f32; 2],
+    pub hits: u32,
+    last_hit: SaeTime,
+    /// start times of bursts of corner activity, most recent last
+    burst_starts: Vec<SaeTime>,
+}
+
+const MAX_BURSTS: usize = 16;
+
+impl CornerCluster {
+    fn new(pos: [f32; 2], timestamp: SaeTime) -> Self {
+        CornerCluster {
+            center: pos,
+            hits: 1,
+            last_hit: timestamp,
+            burst_starts: vec![timestamp],
+        }
+    }
+
+    fn add(&mut self, pos: [f32; 2], timestamp: SaeTime, burst_gap: SaeTime) {
+        self.hits += 1;
+        let weight = 1.0 / (self.hits as f32);
+        self.center[0] += (pos[0] - self.center[0]) * weight;
+        self.center[1] += (pos[1] - self.center[1]) * weight;
+        if timestamp.saturating_sub(self.last_hit) > burst_gap {
+            if self.burst_starts.len() == MAX_BURSTS {
+                self.burst_starts.remove(0);
+            }
+            self.burst_starts.push(timestamp);
+        }
+        self.last_hit = timestamp;
+    }
+
+    /// median interval between bursts, if at least two intervals were seen
+    pub fn blink_period(&self) -> Option<SaeTime> {
+        if self.burst_starts.len() < 3 {
+            return None;
+        }
+        let mut intervals: Vec<SaeTime> = self.burst_starts.windows(2).map(|w| w[1] - w[0]).collect();
+        intervals.sort_unstable();
+        Some(intervals[intervals.len() / 2])
+    }
+}
+
+/// Accumulates corner events and extracts target correspondences
+pub struct TargetDetector {
+    config: TargetConfig,
+    clusters: Vec<CornerCluster>,
+}
+
+impl TargetDetector {
+    pub fn new(config: TargetConfig) -> Self {
+        TargetDetector {
+            config,
+            clusters: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &TargetConfig {
+        &self.config
+    }
+
+    pub fn clusters(&self) -> &[CornerCluster] {
+        &self.clusters
+    }
+
+    /// forget all clusters, eg before the next view of the target
+    pub fn reset(&mut self) {
+        self.clusters.clear();
+    }
+
+    /// Add a detected corner
+    pub fn add_corner(&mut self, corner: &SaeEvent) {
+        let (row, col) = corner.subpixel_position();
+        let pos = [col, row];
+        // gaps of more than half a blink period separate bursts
+        let burst_gap = self.config.blink_period.map_or(0, |period| period / 2);
+        let radius2 = self.config.cluster_radius * self.config.cluster_radius;
+        let nearest = self.clusters.iter_mut()
+            .map(|cluster| {
+                let d2 = (cluster.center[0] - pos[0]).powi(2) + (cluster.center[1] - pos[1]).powi(2);
+                (d2, cluster)
+            })
+            .filter(|(d2, _)| *d2 <= radius2)
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        match nearest {
+            Some((_, cluster)) => cluster.add(pos, corner.timestamp, burst_gap),
+            None => self.clusters.push(CornerCluster::new(pos, corner.timestamp)),
+        }
+    }
+
+    fn is_target_point(&self, cluster: &CornerCluster) -> bool {
+        if cluster.hits < self.config.min_hits {
+            return false;
+        }
+        match self.config.blink_period {
+            Some(period) => cluster.blink_period().is_some_and(|measured| {
+                measured.max(period) - measured.min(period) <= self.config.period_tolerance
+            }),
+            None => true,
+        }
+    }
+
+    /// Order the target points into the grid and return their correspondences,
+    /// in row-major grid order. Returns None unless exactly one target point
+    /// is found for every grid position.
+    pub fn correspondences(&self) -> Option<Vec<Correspondence>> {
+        let rows = self.config.grid_rows;
+        let cols = self.config.grid_cols;
+        if rows < 2 || cols < 2 {
+            return None;
+        }
+        let mut points: Vec<&CornerCluster> = self.clusters.iter()
+            .filter(|cluster| self.is_target_point(cluster))
+            .collect();
+        if points.len() < rows * cols {
+            return None;
+        }
+        // keep the most frequently observed points
+        points.sort_by_key(|p| std::cmp::Reverse(p.hits));
+        points.truncate(rows * cols);
+
+        // the outermost points are the grid corners
+        let extreme = |score: &dyn Fn(&[f32; 2]) -> f32| {
+            points.iter()
+                .max_by(|a, b| score(&a.center).partial_cmp(&score(&b.center)).unwrap())
+                .map(|p| [p.center[0] as f64, p.center[1] as f64])
+                .unwrap()
+        };
+        let image_corners = [
+            extreme(&|c| -c[0] - c[1]),
+            extreme(&|c| c[0] - c[1]),
+            extreme(&|c| c[0] + c[1]),
+            extreme(&|c| -c[0] + c[1]),
+        ];
+        let (last_col, last_row) = ((cols - 1) as f64, (rows - 1) as f64);
+        let grid_corners = [[0.0, 0.0], [last_col, 0.0], [last_col, last_row], [0.0, last_row]];
+        let to_grid = Homography::estimate(&image_corners, &grid_corners)?;
+
+        let mut slots: Vec<Option<[f32; 2]>> = vec![None; rows * cols];
+        for point in points {
+            let grid = to_grid.apply([point.center[0] as f64, point.center[1] as f64]);
+            let (gc, gr) = (grid[0].round(), grid[1].round());
+            // each point must land close to a distinct grid position
+            if (grid[0] - gc).abs() > 0.3 || (grid[1] - gr).abs() > 0.3 ||
+                gc < 0.0 || gr < 0.0 || gc > last_col || gr > last_row {
+                return None;
+            }
+            let slot = &mut slots[(gr as usize) * cols + gc as usize];
+            if slot.is_some() {
+                return None;
+            }
+            *slot = Some(point.center);
+        }
+
+        let spacing = self.config.spacing;
+        slots.iter().enumerate()
+            .map(|(idx, slot)| slot.map(|image| Correspondence {
+                image,
+                object: [(idx % cols) as f32 * spacing, (idx / cols) as f32 * spacing],
+            }))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// image position of grid point (gr, gc) for a slightly rotated, offset target
+    fn grid_point(gr: usize, gc: usize) -> (f32, f32) {
+        let (x, y) = (gc as f32 * 12.0, gr as f32 * 12.0);
+        (40.0 + 0.1 * x + y, 30.0 + x - 0.1 * y)
+    }
+
+    fn corner(row: f32, col: f32, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row: row.round() as u16, col: col.round() as u16, timestamp, ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_checkerboard_grid() {
+        let config = TargetConfig { grid_rows: 3, grid_cols: 4, spacing: 25.0, ..TargetConfig::default() };
+        let mut detector = TargetDetector::new(config);
+        for t in 0..6 {
+            for gr in 0..3 {
+                for gc in 0..4 {
+                    let (row, col) = grid_point(gr, gc);
+                    detector.add_corner(&corner(row, col, t * 1000));
+                }
+            }
+        }
+        // a spurious corner seen only once
+        detector.add_corner(&corner(5.0, 5.0, 100));
+
+        let found = detector.correspondences().unwrap();
+        assert_eq!(found.len(), 12);
+        for (idx, corr) in found.iter().enumerate() {
+            let (row, col) = grid_point(idx / 4, idx % 4);
+            assert!((corr.image[0] - col.round()).abs() < 0.01);
+            assert!((corr.image[1] - row.round()).abs() < 0.01);
+            assert_eq!(corr.object, [(idx % 4) as f32 * 25.0, (idx / 4) as f32 * 25.0]);
+        }
+    }
+
+    #[test]
+    fn test_blinking_led_grid() {
+        let config = TargetConfig {
+            grid_rows: 2,
+            grid_cols: 2,
+            blink_period: Some(10_000),
+            ..TargetConfig::default()
+        };
+        let mut detector = TargetDetector::new(config);
+        for blink in 0..6 {
+            let start = blink * 10_000;
+            for burst in 0..3 {
+                for gr in 0..2 {
+                    for gc in 0..2 {
+                        let (row, col) = grid_point(gr, gc);
+                        detector.add_corner(&corner(row, col, start + burst * 100));
+                    }
+                }
+                // a steadily active corner, not blinking at the target period
+                detector.add_corner(&corner(100.0, 100.0, start + burst * 3_000));
+            }
+        }
+        assert_eq!(detector.clusters().len(), 5);
+        assert_eq!(detector.clusters()[0].blink_period(), Some(10_000));
+        assert_eq!(detector.correspondences().unwrap().len(), 4);
+
+        detector.reset();
+        assert!(detector.correspondences().is_none());
+    }
+}
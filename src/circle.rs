@@ -0,0 +1,197 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Circle geometry for the Arc* detector: ordered pixel offset tables and the
+//! arc length limits (Lmin, Lmax) that go with them.
+//! The standard detector uses the radius 3 and radius 4 circles; other radii,
+//! ellipses (for sensors with non-square pixels) or hand-made tables can be
+//! supplied through `DetectorConfig`.
+
+use std::f32::consts::PI;
+
+
+/// A closed ring of pixel offsets `[row, col]` around a point, ordered clockwise
+/// (in image coordinates, with rows increasing downward) starting from the east
+#[derive(Clone, Debug, PartialEq)]
+pub struct CircleSpec {
+    offsets: Vec<[i32; 2]>,
+    min_arc_len: usize,
+    max_arc_len: usize,
+}
+
+/// Sort offsets clockwise from the east, dropping duplicates
+fn order_ring(mut offsets: Vec<[i32; 2]>) -> Vec<[i32; 2]> {
+    let angle = |off: &[i32; 2]| {
+        let a = (off[0] as f32).atan2(off[1] as f32);
+        if a < 0.0 { a + 2.0 * PI } else { a }
+    };
+    offsets.sort_by(|a, b| angle(a).partial_cmp(&angle(b)).unwrap());
+    offsets.dedup();
+    offsets
+}
+
+impl CircleSpec {
+    /// A ring from user-supplied offsets and arc length limits.
+    /// Returns None unless `1 <= min_arc_len <= max_arc_len < offsets.len()`.
+    pub fn new(offsets: Vec<[i32; 2]>, min_arc_len: usize, max_arc_len: usize) -> Option<Self> {
+        if min_arc_len == 0 || min_arc_len > max_arc_len || max_arc_len >= offsets.len() {
+            return None;
+        }
+        Some(CircleSpec { offsets, min_arc_len, max_arc_len })
+    }
+
+    /// A ring from user-supplied offsets, with arc length limits derived from its size
+    /// in the same proportion as the standard circles (3..=6 of 16, 4..=8 of 20)
+    pub fn from_offsets(offsets: Vec<[i32; 2]>) -> Option<Self> {
+        let (min_arc_len, max_arc_len) = Self::derived_limits(offsets.len());
+        Self::new(offsets, min_arc_len, max_arc_len)
+    }
+
+    /// (Lmin, Lmax) for a ring of `len` pixels
+    pub fn derived_limits(len: usize) -> (usize, usize) {
+        ((len * 3 + 8) / 16, len * 2 / 5)
+    }
+
+    /// The digital circle of the given radius: one pixel per step along the ring,
+    /// matching the standard tables for radius 3 and 4
+    pub fn circle(radius: u32) -> Option<Self> {
+        let r = radius as i32;
+        let mut octant = Vec::new();
+        for minor in 0..=r {
+            let major = (((r * r - minor * minor) as f32).sqrt()).round() as i32;
+            if minor > major {
+                break;
+            }
+            // the diagonal pixel is only needed if the ring would otherwise have a gap there
+            if minor == major && octant.last().is_some_and(|&(a, b): &(i32, i32)| a - b <= 1) {
+                break;
+            }
+            octant.push((major, minor));
+        }
+        let mut offsets = Vec::new();
+        for &(major, minor) in octant.iter() {
+            for &(a, b) in [(major, minor), (minor, major)].iter() {
+                for &(sr, sc) in [(1, 1), (1, -1), (-1, 1), (-1, -1)].iter() {
+                    offsets.push([sr * b, sc * a]);
+                }
+            }
+        }
+        Self::from_offsets(order_ring(offsets))
+    }
+
+    /// An elliptical ring with the given radii along rows and columns,
+    /// eg for sensors whose pixels aren't square
+    pub fn ellipse(row_radius: f32, col_radius: f32) -> Option<Self> {
+        if row_radius == col_radius && row_radius.fract() == 0.0 && row_radius >= 0.0 {
+            return Self::circle(row_radius as u32);
+        }
+        let steps = (8.0 * (row_radius + col_radius)).ceil().max(8.0) as usize;
+        let offsets = (0..steps)
+            .map(|i| {
+                let theta = 2.0 * PI * (i as f32) / (steps as f32);
+                [(row_radius * theta.sin()).round() as i32, (col_radius * theta.cos()).round() as i32]
+            })
+            .collect();
+        Self::from_offsets(order_ring(offsets))
+    }
+
+    /// The same ring, with `reference`'s arc length limits scaled in proportion
+    /// to the ratio of the ring sizes, eg to keep the standard C3/C4 limits
+    /// meaningful on a stretched ring
+    pub fn with_limits_scaled_from(self, reference: &CircleSpec) -> Option<Self> {
+        let ratio = (self.len() as f32) / (reference.len() as f32);
+        let min_arc_len = ((reference.min_arc_len as f32) * ratio).round().max(1.0) as usize;
+        let max_arc_len = ((reference.max_arc_len as f32) * ratio).round() as usize;
+        Self::new(self.offsets, min_arc_len, max_arc_len)
+    }
+
+    /// the standard radius 3 circle
+    pub fn c3() -> Self {
+        Self::circle(3).unwrap()
+    }
+
+    /// the standard radius 4 circle
+    pub fn c4() -> Self {
+        Self::circle(4).unwrap()
+    }
+
+    pub fn offsets(&self) -> &[[i32; 2]] {
+        &self.offsets
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// rings always hold more than `max_arc_len` offsets
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    pub fn min_arc_len(&self) -> usize {
+        self.min_arc_len
+    }
+
+    pub fn max_arc_len(&self) -> usize {
+        self.max_arc_len
+    }
+
+    /// largest distance of any offset along a row or column:
+    /// points closer than this to the SAE border can't be evaluated
+    pub fn reach(&self) -> usize {
+        self.offsets.iter()
+            .map(|off| off[0].abs().max(off[1].abs()) as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Remove offsets for which `masked` returns true, eg offsets that always
+    /// land on a dead sensor column for a fixed detection region.
+    /// Returns None if too few offsets remain for the arc limits.
+    pub fn without(&self, masked: impl Fn(&[i32; 2]) -> bool) -> Option<Self> {
+        let offsets = self.offsets.iter().filter(|off| !masked(off)).cloned().collect();
+        Self::new(offsets, self.min_arc_len, self.max_arc_len)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_circles() {
+        let c3 = CircleSpec::c3();
+        assert_eq!(c3.len(), 16);
+        assert_eq!((c3.min_arc_len(), c3.max_arc_len()), (3, 6));
+        assert_eq!(&c3.offsets()[..4], &[[0, 3], [1, 3], [2, 2], [3, 1]]);
+        assert_eq!(c3.reach(), 3);
+
+        let c4 = CircleSpec::c4();
+        assert_eq!(c4.len(), 20);
+        assert_eq!((c4.min_arc_len(), c4.max_arc_len()), (4, 8));
+        assert_eq!(&c4.offsets()[..5], &[[0, 4], [1, 4], [2, 3], [3, 2], [4, 1]]);
+    }
+
+    #[test]
+    fn test_custom_rings() {
+        let c5 = CircleSpec::circle(5).unwrap();
+        assert_eq!(c5.reach(), 5);
+        assert!(c5.len() > 20);
+
+        let ellipse = CircleSpec::ellipse(3.0, 5.0).unwrap();
+        assert!(ellipse.offsets().iter().all(|off| off[0].abs() <= 3 && off[1].abs() <= 5));
+        assert_eq!(ellipse.offsets()[0], [0, 5]);
+
+        let stretched = CircleSpec::ellipse(4.0, 6.0).unwrap()
+            .with_limits_scaled_from(&CircleSpec::c4()).unwrap();
+        let ratio = stretched.len() as f32 / 20.0;
+        assert_eq!(stretched.min_arc_len(), (4.0 * ratio).round() as usize);
+        assert_eq!(stretched.max_arc_len(), (8.0 * ratio).round() as usize);
+        assert_eq!(CircleSpec::ellipse(3.0, 3.0), Some(CircleSpec::c3()));
+
+        assert!(CircleSpec::new(vec![[0, 1], [1, 0], [0, -1], [-1, 0]], 3, 4).is_none());
+        let masked = CircleSpec::c3().without(|off| off[1] == 3).unwrap();
+        assert_eq!(masked.len(), 13);
+    }
+}
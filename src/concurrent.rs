@@ -0,0 +1,194 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! A surface that several threads update and check for corners at once.
+//!
+//! With a `SaeMatrix`, each event's update and its corner check must run to
+//! completion before the next event's update, which caps throughput at one core.
+//! `ConcurrentSae` holds its timestamps in atomic cells, so `process_event` takes
+//! `&self`: workers fed disjoint regions of the sensor (eg by `split::Splitter` or
+//! by tile) update and check in parallel without locks. Cells are laid out tile by
+//! tile, so that a worker's updates and ring reads stay within a few cache lines and
+//! workers on different tiles rarely share one.
+//!
+//! A cell is updated with an atomic maximum, keeping the newest timestamp whichever
+//! worker writes last. A corner check reads the ring cells one by one, so an event
+//! checked near a region another worker is updating may see some of that worker's
+//! newer timestamps: the same surface a sequential run would show a moment later.
+//! Events of one pixel should go to one worker, in time order.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::detector::detect_and_compute_one;
+use crate::sae_types::*;
+use crate::view::SaeView;
+
+
+/// Default side length of the square tiles, in pixels
+pub const DEFAULT_TILE_SIZE: usize = 64;
+
+/// Per-polarity surfaces of atomic cells, shared between threads
+pub struct ConcurrentSae {
+    nrows: usize,
+    ncols: usize,
+    tile: usize,
+    tile_cols: usize,
+    /// OFF then ON surface cells, tile by tile
+    cells: [Box<[AtomicU32]>; 2],
+    events_processed: AtomicU64,
+    corners_detected: AtomicU64,
+}
+
+/// A read-only view of one polarity surface of a `ConcurrentSae`
+pub struct ConcurrentSurface<'a> {
+    sae: &'a ConcurrentSae,
+    cells: &'a [AtomicU32],
+}
+
+impl SaeView for ConcurrentSurface<'_> {
+    fn shape(&self) -> (usize, usize) {
+        (self.sae.nrows, self.sae.ncols)
+    }
+
+    fn timestamp(&self, row: usize, col: usize) -> SaeTime {
+        self.cells[self.sae.index(row, col)].load(Ordering::Relaxed)
+    }
+}
+
+impl ConcurrentSae {
+    pub fn new(nrows: usize, ncols: usize) -> Self {
+        Self::with_tile_size(nrows, ncols, DEFAULT_TILE_SIZE)
+    }
+
+    /// Surfaces laid out in square tiles of `tile` pixels
+    pub fn with_tile_size(nrows: usize, ncols: usize, tile: usize) -> Self {
+        let tile = tile.max(1);
+        let tile_cols = ncols.div_ceil(tile);
+        let len = nrows.div_ceil(tile) * tile_cols * tile * tile;
+        let cells = || (0..len).map(|_| AtomicU32::new(0)).collect::<Box<[AtomicU32]>>();
+        ConcurrentSae {
+            nrows,
+            ncols,
+            tile,
+            tile_cols,
+            cells: [cells(), cells()],
+            events_processed: AtomicU64::new(0),
+            corners_detected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn shape(&self) -> (usize, usize) {
+        (self.nrows, self.ncols)
+    }
+
+    pub fn tile_size(&self) -> usize {
+        self.tile
+    }
+
+    /// the tile (row, col) holding a pixel, for dividing the sensor between workers
+    pub fn tile_of(&self, row: usize, col: usize) -> (usize, usize) {
+        (row / self.tile, col / self.tile)
+    }
+
+    fn index(&self, row: usize, col: usize) -> usize {
+        let tile_idx = (row / self.tile) * self.tile_cols + col / self.tile;
+        tile_idx * self.tile * self.tile + (row % self.tile) * self.tile + col % self.tile
+    }
+
+    /// the surface of one polarity
+    pub fn surface(&self, polarity: u8) -> ConcurrentSurface<'_> {
+        ConcurrentSurface { sae: self, cells: &self.cells[(polarity > 0) as usize] }
+    }
+
+    /// Apply an event to its surface, returning false if it is outside the sensor
+    /// or older than the timestamp already at its pixel
+    pub fn update(&self, evt: &SaeEvent) -> bool {
+        let (row, col) = (evt.row as usize, evt.col as usize);
+        if row >= self.nrows || col >= self.ncols {
+            return false;
+        }
+        let cell = &self.cells[(evt.polarity > 0) as usize][self.index(row, col)];
+        cell.fetch_max(evt.timestamp, Ordering::Relaxed) <= evt.timestamp
+    }
+
+    /// Apply an event and check it for a corner on its own polarity surface
+    pub fn process_event(&self, evt: &SaeEvent) -> Option<SaeEvent> {
+        self.events_processed.fetch_add(1, Ordering::Relaxed);
+        if !self.update(evt) {
+            return None;
+        }
+        let corner = detect_and_compute_one(&self.surface(evt.polarity), evt);
+        if corner.is_some() {
+            self.corners_detected.fetch_add(1, Ordering::Relaxed);
+        }
+        corner
+    }
+
+    pub fn events_processed(&self) -> u64 {
+        self.events_processed.load(Ordering::Relaxed)
+    }
+
+    pub fn corners_detected(&self) -> u64 {
+        self.corners_detected.load(Ordering::Relaxed)
+    }
+
+    /// Copy one polarity surface into a matrix, eg for snapshots or rendering
+    pub fn to_matrix(&self, polarity: u8) -> SaeMatrix {
+        let surface = self.surface(polarity);
+        SaeMatrix::from_fn(self.nrows, self.ncols, |row, col| surface.timestamp(row, col))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(top: u16, left: u16, timestamp: SaeTime) -> Vec<SaeEvent> {
+        let mut events = Vec::new();
+        for row in top..top + 5 {
+            for col in left..left + 5 {
+                events.push(SaeEvent { row, col, polarity: 1, timestamp, ..SaeEvent::default() });
+            }
+        }
+        events.push(SaeEvent { row: top + 4, col: left + 4, polarity: 1, timestamp: timestamp + 2, ..SaeEvent::default() });
+        events
+    }
+
+    #[test]
+    fn test_parallel_workers_match_sequential() {
+        let regions: Vec<Vec<SaeEvent>> = [(8u16, 8u16), (8, 40), (40, 8), (40, 40)].iter()
+            .enumerate()
+            .map(|(idx, &(top, left))| block(top, left, 100 * (idx as SaeTime + 1)))
+            .collect();
+
+        let sequential = ConcurrentSae::with_tile_size(64, 64, 32);
+        let mut expected: Vec<SaeEventKey> = regions.iter().flatten()
+            .filter_map(|evt| sequential.process_event(evt))
+            .map(|corner| corner.key())
+            .collect();
+        assert!(!expected.is_empty());
+
+        let shared = ConcurrentSae::with_tile_size(64, 64, 32);
+        let mut found: Vec<SaeEventKey> = std::thread::scope(|scope| {
+            let workers: Vec<_> = regions.iter()
+                .map(|events| {
+                    let sae = &shared;
+                    scope.spawn(move || events.iter().filter_map(|evt| sae.process_event(evt)).map(|c| c.key()).collect::<Vec<_>>())
+                })
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+        });
+        let by_time = |a: &SaeEventKey, b: &SaeEventKey| (a.timestamp, a.row, a.col).cmp(&(b.timestamp, b.row, b.col));
+        expected.sort_by(by_time);
+        found.sort_by(by_time);
+        assert_eq!(found, expected);
+        assert_eq!(shared.events_processed(), sequential.events_processed());
+        assert_eq!(shared.to_matrix(1), sequential.to_matrix(1));
+        assert_eq!(shared.tile_of(40, 8), (1, 0));
+
+        // older events don't overwrite newer ones
+        assert!(!shared.update(&SaeEvent { row: 8, col: 8, polarity: 1, timestamp: 1, ..SaeEvent::default() }));
+        assert_eq!(shared.surface(1).timestamp(8, 8), 100);
+    }
+}
@@ -0,0 +1,387 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Hooks for steering sensor hardware from detector feedback.
+//!
+//! Sensors such as Prophesee's can restrict their output to hardware regions of
+//! interest, and cap their output with an event-rate controller (ERC). Programmed from
+//! the detector's own state, these concentrate the link bandwidth on the tracked
+//! features and shed load before the host falls behind. `SensorControl` is the
+//! interface a live backend implements over its vendor SDK; this crate has no
+//! capture backends of its own, so `EmulatedControl` applies the same settings in
+//! software, as an `EventFilter`, for replays and for sensors without the hardware.
+//! `SensorFeedback` decides the settings: ROIs around the predicted corners of the
+//! active tracks, and a rate limit that backs off while the processing backlog grows.
+
+use std::io;
+
+use crate::filter::EventFilter;
+use crate::objects::BoundingBox;
+use crate::predict::CornerPrediction;
+use crate::sae_types::*;
+
+
+/// A rectangular window of pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoiWindow {
+    pub row: u16,
+    pub col: u16,
+    pub height: u16,
+    pub width: u16,
+}
+
+impl RoiWindow {
+    /// The pixels covered by `region`, clipped to the sensor; None if none are
+    pub fn from_region(region: &BoundingBox, nrows: usize, ncols: usize) -> Option<Self> {
+        let clip = |value: f32, limit: usize| value.floor().max(0.0).min(limit as f32) as u16;
+        let (col, end_col) = (clip(region.min[0], ncols), clip(region.max[0] + 1.0, ncols));
+        let (row, end_row) = (clip(region.min[1], nrows), clip(region.max[1] + 1.0, nrows));
+        if end_col <= col || end_row <= row {
+            return None;
+        }
+        Some(RoiWindow { row, col, height: end_row - row, width: end_col - col })
+    }
+
+    pub fn contains(&self, row: u16, col: u16) -> bool {
+        row >= self.row && row - self.row < self.height && col >= self.col && col - self.col < self.width
+    }
+
+    pub fn area(&self) -> u32 {
+        self.height as u32 * self.width as u32
+    }
+
+    /// the smallest window covering both
+    pub fn union(&self, other: &RoiWindow) -> RoiWindow {
+        let row = self.row.min(other.row);
+        let col = self.col.min(other.col);
+        let end_row = (self.row + self.height).max(other.row + other.height);
+        let end_col = (self.col + self.width).max(other.col + other.width);
+        RoiWindow { row, col, height: end_row - row, width: end_col - col }
+    }
+
+    pub fn overlaps(&self, other: &RoiWindow) -> bool {
+        self.row < other.row + other.height && other.row < self.row + self.height &&
+            self.col < other.col + other.width && other.col < self.col + self.width
+    }
+}
+
+fn unsupported(feature: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("{} not supported by this sensor", feature))
+}
+
+/// Control over a sensor's hardware ROIs and event-rate controller.
+/// The default methods report the feature as unsupported.
+pub trait SensorControl {
+    /// maximum number of ROI windows the sensor can hold at once; zero without ROI support
+    fn max_rois(&self) -> usize {
+        0
+    }
+
+    /// Restrict the sensor output to the union of `windows`; no windows restores the full sensor
+    fn set_rois(&mut self, _windows: &[RoiWindow]) -> io::Result<()> {
+        Err(unsupported("ROI"))
+    }
+
+    /// Cap the sensor output at `limit` events per second, or lift the cap
+    fn set_event_rate_limit(&mut self, _limit: Option<u64>) -> io::Result<()> {
+        Err(unsupported("event rate control"))
+    }
+}
+
+impl<C: SensorControl + ?Sized> SensorControl for &mut C {
+    fn max_rois(&self) -> usize {
+        (**self).max_rois()
+    }
+
+    fn set_rois(&mut self, windows: &[RoiWindow]) -> io::Result<()> {
+        (**self).set_rois(windows)
+    }
+
+    fn set_event_rate_limit(&mut self, limit: Option<u64>) -> io::Result<()> {
+        (**self).set_event_rate_limit(limit)
+    }
+}
+
+/// Applies sensor control settings in software: events outside the ROIs are dropped,
+/// and so are events beyond the rate limit within each millisecond of event time
+pub struct EmulatedControl {
+    max_rois: usize,
+    rois: Vec<RoiWindow>,
+    rate_limit: Option<u64>,
+    bin_start: SaeTime,
+    bin_count: u64,
+    dropped: u64,
+}
+
+impl EmulatedControl {
+    const BIN_WIDTH: SaeTime = 1_000;
+
+    pub fn new(max_rois: usize) -> Self {
+        EmulatedControl { max_rois, rois: Vec::new(), rate_limit: None, bin_start: 0, bin_count: 0, dropped: 0 }
+    }
+
+    pub fn rois(&self) -> &[RoiWindow] {
+        &self.rois
+    }
+
+    pub fn rate_limit(&self) -> Option<u64> {
+        self.rate_limit
+    }
+
+    /// number of events dropped so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl SensorControl for EmulatedControl {
+    fn max_rois(&self) -> usize {
+        self.max_rois
+    }
+
+    fn set_rois(&mut self, windows: &[RoiWindow]) -> io::Result<()> {
+        if windows.len() > self.max_rois {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("{} ROI windows requested, at most {} supported", windows.len(), self.max_rois)));
+        }
+        self.rois = windows.to_vec();
+        Ok(())
+    }
+
+    fn set_event_rate_limit(&mut self, limit: Option<u64>) -> io::Result<()> {
+        self.rate_limit = limit;
+        Ok(())
+    }
+}
+
+impl EventFilter for EmulatedControl {
+    fn accept(&mut self, evt: &SaeEvent) -> bool {
+        if !self.rois.is_empty() && !self.rois.iter().any(|roi| roi.contains(evt.row, evt.col)) {
+            self.dropped += 1;
+            return false;
+        }
+        if let Some(limit) = self.rate_limit {
+            let bin_start = evt.timestamp - evt.timestamp % Self::BIN_WIDTH;
+            if bin_start != self.bin_start {
+                self.bin_start = bin_start;
+                self.bin_count = 0;
+            }
+            if self.bin_count >= limit * Self::BIN_WIDTH as u64 / 1_000_000 {
+                self.dropped += 1;
+                return false;
+            }
+            self.bin_count += 1;
+        }
+        true
+    }
+}
+
+/// Windows covering `regions`, clipped to the sensor, with overlapping windows merged and
+/// then, while there are more than `max_rois`, the pair whose union adds the least area
+pub fn plan_rois(regions: &[BoundingBox], nrows: usize, ncols: usize, max_rois: usize) -> Vec<RoiWindow> {
+    if max_rois == 0 {
+        return Vec::new();
+    }
+    let mut windows: Vec<RoiWindow> = Vec::new();
+    for region in regions.iter() {
+        let mut window = match RoiWindow::from_region(region, nrows, ncols) {
+            Some(window) => window,
+            None => continue,
+        };
+        // absorb every window the new one overlaps, including through the growing union
+        while let Some(idx) = windows.iter().position(|other| other.overlaps(&window)) {
+            window = window.union(&windows.swap_remove(idx));
+        }
+        windows.push(window);
+    }
+    while windows.len() > max_rois {
+        let mut best = (0, 1, u32::MAX);
+        for a in 0..windows.len() {
+            for b in (a + 1)..windows.len() {
+                let growth = windows[a].union(&windows[b]).area() - windows[a].area().max(windows[b].area());
+                if growth < best.2 {
+                    best = (a, b, growth);
+                }
+            }
+        }
+        let removed = windows.swap_remove(best.1);
+        windows[best.0] = windows[best.0].union(&removed);
+    }
+    windows.sort_by_key(|window| (window.row, window.col));
+    windows
+}
+
+/// Parameters of `SensorFeedback`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeedbackConfig {
+    /// margin (pixels) added around each predicted gate
+    pub roi_margin: f32,
+    /// with fewer predictions than this the ROIs are lifted, so that new features can be found
+    pub min_predictions: usize,
+    /// backlog depth above which the rate limit is halved
+    pub target_depth: usize,
+    /// the rate limit is doubled while the backlog is below this, and lifted above `max_rate`
+    pub relax_depth: usize,
+    /// the lowest rate limit applied, events per second
+    pub min_rate: u64,
+    /// the rate limit first applied, and above which it is lifted
+    pub max_rate: u64,
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        FeedbackConfig {
+            roi_margin: 4.0,
+            min_predictions: 4,
+            target_depth: 5_000,
+            relax_depth: 500,
+            min_rate: 1_000_000,
+            max_rate: 20_000_000,
+        }
+    }
+}
+
+/// Programs a sensor from the detector's state. Settings are only sent when they change,
+/// and features the sensor does not support are left alone.
+pub struct SensorFeedback {
+    config: FeedbackConfig,
+    nrows: usize,
+    ncols: usize,
+    rois: Vec<RoiWindow>,
+    rate_limit: Option<u64>,
+}
+
+impl SensorFeedback {
+    pub fn new(nrows: usize, ncols: usize, config: FeedbackConfig) -> Self {
+        SensorFeedback { config, nrows, ncols, rois: Vec::new(), rate_limit: None }
+    }
+
+    /// the ROI windows last sent
+    pub fn rois(&self) -> &[RoiWindow] {
+        &self.rois
+    }
+
+    /// the rate limit last sent
+    pub fn rate_limit(&self) -> Option<u64> {
+        self.rate_limit
+    }
+
+    fn next_rate_limit(&self, queue_depth: usize) -> Option<u64> {
+        let config = &self.config;
+        if queue_depth > config.target_depth {
+            let limit = self.rate_limit.map_or(config.max_rate, |limit| limit / 2);
+            Some(limit.max(config.min_rate))
+        } else if queue_depth < config.relax_depth {
+            self.rate_limit.map(|limit| limit.saturating_mul(2)).filter(|&limit| limit <= config.max_rate)
+        } else {
+            self.rate_limit
+        }
+    }
+
+    /// Update the sensor from the predicted corners of the active tracks and the
+    /// depth of the processing backlog
+    pub fn update<C: SensorControl + ?Sized>(&mut self, control: &mut C, predictions: &[CornerPrediction],
+                                             queue_depth: usize) -> io::Result<()> {
+        let max_rois = control.max_rois();
+        if max_rois > 0 {
+            let rois = if predictions.len() < self.config.min_predictions {
+                Vec::new()
+            } else {
+                let regions: Vec<BoundingBox> = predictions.iter()
+                    .map(|prediction| prediction.region().inflated(self.config.roi_margin))
+                    .collect();
+                plan_rois(&regions, self.nrows, self.ncols, max_rois)
+            };
+            if rois != self.rois {
+                control.set_rois(&rois)?;
+                self.rois = rois;
+            }
+        }
+
+        let rate_limit = self.next_rate_limit(queue_depth);
+        if rate_limit != self.rate_limit {
+            match control.set_event_rate_limit(rate_limit) {
+                Ok(()) => self.rate_limit = rate_limit,
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prediction(col: f32, row: f32) -> CornerPrediction {
+        CornerPrediction { track: 0, timestamp: 0, position: [col, row], radius: 2.0, earliest: 0, latest: 0 }
+    }
+
+    fn event(row: u16, col: u16, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row, col, timestamp, ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_plan_rois() {
+        let boxes = [
+            BoundingBox { min: [0.0, 0.0], max: [4.0, 4.0] },
+            BoundingBox { min: [3.0, 3.0], max: [6.0, 6.0] },
+            BoundingBox { min: [20.0, 0.0], max: [22.0, 2.0] },
+            BoundingBox { min: [20.0, 30.0], max: [40.0, 40.0] },
+            BoundingBox { min: [100.0, 100.0], max: [110.0, 110.0] },
+        ];
+        let windows = plan_rois(&boxes, 32, 32, 8);
+        assert_eq!(windows, vec![
+            RoiWindow { row: 0, col: 0, height: 7, width: 7 },
+            RoiWindow { row: 0, col: 20, height: 3, width: 3 },
+            RoiWindow { row: 30, col: 20, height: 2, width: 12 },
+        ]);
+        let windows = plan_rois(&boxes, 32, 32, 2);
+        assert_eq!(windows[0], RoiWindow { row: 0, col: 0, height: 7, width: 23 });
+        assert!(plan_rois(&boxes, 32, 32, 0).is_empty());
+    }
+
+    #[test]
+    fn test_feedback_programs_emulated_sensor() {
+        let config = FeedbackConfig { min_predictions: 2, target_depth: 100, relax_depth: 10, min_rate: 1_000_000, max_rate: 4_000_000, ..FeedbackConfig::default() };
+        let mut feedback = SensorFeedback::new(64, 64, config);
+        let mut sensor = EmulatedControl::new(4);
+        let predictions = vec![prediction(10.0, 10.0), prediction(50.0, 40.0)];
+
+        feedback.update(&mut sensor, &predictions[..1], 0).unwrap();
+        assert!(sensor.rois().is_empty());
+        feedback.update(&mut sensor, &predictions, 200).unwrap();
+        assert_eq!(sensor.rois().len(), 2);
+        assert_eq!(sensor.rate_limit(), Some(4_000_000));
+        feedback.update(&mut sensor, &predictions, 200).unwrap();
+        feedback.update(&mut sensor, &predictions, 200).unwrap();
+        feedback.update(&mut sensor, &predictions, 200).unwrap();
+        assert_eq!(sensor.rate_limit(), Some(1_000_000));
+        feedback.update(&mut sensor, &predictions, 50).unwrap();
+        assert_eq!(sensor.rate_limit(), Some(1_000_000));
+
+        // in software: events outside the ROIs are dropped, as are events over the rate limit
+        assert!(sensor.accept(&event(10, 10, 0)));
+        assert!(!sensor.accept(&event(30, 30, 1)));
+        sensor.set_event_rate_limit(Some(2_000)).unwrap();
+        assert!(sensor.accept(&event(11, 11, 1_000)));
+        assert!(sensor.accept(&event(11, 11, 1_200)));
+        assert!(!sensor.accept(&event(11, 11, 1_400)));
+        assert!(sensor.accept(&event(11, 11, 2_000)));
+        assert_eq!(sensor.dropped(), 2);
+
+        feedback.update(&mut sensor, &predictions, 0).unwrap();
+        feedback.update(&mut sensor, &predictions, 0).unwrap();
+        feedback.update(&mut sensor, &predictions, 0).unwrap();
+        assert_eq!(sensor.rate_limit(), None);
+
+        // a sensor without control support is left alone
+        struct Fixed;
+        impl SensorControl for Fixed {}
+        let mut fixed = Fixed;
+        assert!(feedback.update(&mut fixed, &predictions, 1_000).is_ok());
+        assert_eq!(feedback.rate_limit(), None);
+    }
+}
@@ -0,0 +1,171 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Covisibility queries over tracked corners: which features were observed within
+//! a time window, and optionally a region, eg to gather the features and
+//! observations of a local bundle adjustment problem over a sliding window.
+//!
+//! `CovisibilityIndex` buckets observations into square grid cells, each holding
+//! its observations in time order, so that a query touches only the cells
+//! overlapping its region and, within them, only the observations in its window.
+//! Feed it each corner with the track it was assigned to, as returned by
+//! `track::CornerTracker::add_corner`, and prune it as the window slides.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::control::RoiWindow;
+use crate::sae_types::*;
+use crate::track::{TrackId, TrackStore};
+
+
+/// Parameters of a covisibility index
+#[derive(Clone, Debug, PartialEq)]
+pub struct CovisConfig {
+    /// side of the square grid cells, in pixels
+    pub cell_size: u16,
+}
+
+impl Default for CovisConfig {
+    fn default() -> Self {
+        CovisConfig { cell_size: 16 }
+    }
+}
+
+/// One observation of a tracked feature
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CovisObservation {
+    pub track: TrackId,
+    pub timestamp: SaeTime,
+    pub row: u16,
+    pub col: u16,
+}
+
+/// Observations indexed by grid cell and time
+#[derive(Clone, Debug)]
+pub struct CovisibilityIndex {
+    config: CovisConfig,
+    /// observations of each cell, in time order
+    cells: HashMap<(u16, u16), Vec<CovisObservation>>,
+    len: usize,
+}
+
+impl CovisibilityIndex {
+    pub fn new(config: CovisConfig) -> Self {
+        CovisibilityIndex { config, cells: HashMap::new(), len: 0 }
+    }
+
+    /// An index of every observation of every track in `store`
+    pub fn from_store(store: &TrackStore, config: CovisConfig) -> Self {
+        let mut index = Self::new(config);
+        for track in store.iter() {
+            for obs in track.observations.iter() {
+                index.insert(track.id, obs);
+            }
+        }
+        index
+    }
+
+    fn cell_of(&self, row: u16, col: u16) -> (u16, u16) {
+        let size = self.config.cell_size.max(1);
+        (row / size, col / size)
+    }
+
+    /// Add an observation of `track`; cheapest in time order
+    pub fn insert(&mut self, track: TrackId, corner: &SaeEvent) {
+        let obs = CovisObservation { track, timestamp: corner.timestamp, row: corner.row, col: corner.col };
+        let cell = self.cells.entry(self.cell_of(corner.row, corner.col)).or_default();
+        let pos = cell.partition_point(|other| other.timestamp <= obs.timestamp);
+        cell.insert(pos, obs);
+        self.len += 1;
+    }
+
+    /// Forget the observations before `timestamp`, as the window slides past them
+    pub fn prune_before(&mut self, timestamp: SaeTime) {
+        let mut removed = 0;
+        self.cells.retain(|_, cell| {
+            let stale = cell.partition_point(|obs| obs.timestamp < timestamp);
+            cell.drain(..stale);
+            removed += stale;
+            !cell.is_empty()
+        });
+        self.len -= removed;
+    }
+
+    /// number of observations held
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The observations from `start` through `end`, within `region` if given,
+    /// in no particular order
+    pub fn observations(&self, start: SaeTime, end: SaeTime, region: Option<&RoiWindow>) -> Vec<CovisObservation> {
+        let mut found = Vec::new();
+        let mut collect = |cell: &Vec<CovisObservation>| {
+            let first = cell.partition_point(|obs| obs.timestamp < start);
+            found.extend(cell[first..].iter()
+                .take_while(|obs| obs.timestamp <= end)
+                .filter(|obs| region.is_none_or(|region| region.contains(obs.row, obs.col))));
+        };
+        match region {
+            Some(region) if region.height > 0 && region.width > 0 => {
+                let (top, left) = self.cell_of(region.row, region.col);
+                let (bottom, right) = self.cell_of(region.row.saturating_add(region.height - 1),
+                                                   region.col.saturating_add(region.width - 1));
+                for cell_row in top..=bottom {
+                    for cell_col in left..=right {
+                        if let Some(cell) = self.cells.get(&(cell_row, cell_col)) {
+                            collect(cell);
+                        }
+                    }
+                }
+            }
+            Some(_) => {}
+            None => self.cells.values().for_each(collect),
+        }
+        found
+    }
+
+    /// The features observed from `start` through `end`, within `region` if given
+    pub fn covisible(&self, start: SaeTime, end: SaeTime, region: Option<&RoiWindow>) -> BTreeSet<TrackId> {
+        self.observations(start, end, region).iter().map(|obs| obs.track).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_covisible_in_window_and_region() {
+        let mut index = CovisibilityIndex::new(CovisConfig { cell_size: 8 });
+        let corner = |row, col, timestamp| SaeEvent { row, col, timestamp, ..SaeEvent::default() };
+        // track 0 crosses cells, track 1 stays put, track 2 comes later elsewhere
+        for (t, col) in [(100, 5u16), (200, 9), (300, 13)].iter() {
+            index.insert(0, &corner(4, *col, *t));
+        }
+        index.insert(1, &corner(20, 20, 150));
+        index.insert(1, &corner(20, 21, 250));
+        index.insert(2, &corner(40, 40, 500));
+        // out of time order
+        index.insert(2, &corner(40, 41, 120));
+        assert_eq!(index.len(), 7);
+
+        assert_eq!(index.covisible(0, 1_000, None), [0, 1, 2].iter().copied().collect());
+        assert_eq!(index.covisible(130, 260, None), [0, 1].iter().copied().collect());
+        let left = RoiWindow { row: 0, col: 0, height: 32, width: 10 };
+        assert_eq!(index.covisible(0, 1_000, Some(&left)), [0].iter().copied().collect());
+        assert_eq!(index.observations(0, 1_000, Some(&left)).len(), 2);
+        let empty = RoiWindow { width: 0, ..left };
+        assert!(index.observations(0, 1_000, Some(&empty)).is_empty());
+
+        index.prune_before(200);
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.covisible(0, 1_000, None), [0, 1, 2].iter().copied().collect());
+        assert_eq!(index.covisible(0, 499, None), [0, 1].iter().copied().collect());
+    }
+}
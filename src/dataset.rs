@@ -0,0 +1,222 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Extraction of labeled SAE patches for training learned corner verifiers and descriptors.
+//!
+//! Every event far enough from the sensor border is a corner candidate. The candidates
+//! the Arc* detector accepts are labeled positive, and a fraction of those it rejects
+//! are kept as negatives. Each sample holds the SAE timestamps of a square patch
+//! centered on the candidate, taken from the surface of its polarity just after the
+//! candidate's update, so a learned model sees what the detector saw. The default
+//! patch radius covers both detector rings.
+
+#[cfg(feature = "io")]
+use std::io::{self, Write};
+
+use crate::detector::{ring_descriptor, DetectorConfig};
+#[cfg(feature = "io")]
+use crate::io::npy::NpzWriter;
+use crate::sae_types::*;
+use crate::surface::{SaeSurface, WarmupConfig};
+
+
+/// Configuration for `PatchDataset`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatchDatasetConfig {
+    /// patches are (2 * radius + 1) pixels square
+    pub radius: usize,
+    /// keep every Nth rejected candidate; rejections far outnumber detections
+    pub rejected_stride: u32,
+    /// candidates are only sampled once the surfaces are warmed up, as for detection
+    pub warmup: WarmupConfig,
+}
+
+impl Default for PatchDatasetConfig {
+    fn default() -> Self {
+        PatchDatasetConfig {
+            radius: DetectorConfig::default().border_inset(),
+            rejected_stride: 10,
+            warmup: WarmupConfig::default(),
+        }
+    }
+}
+
+/// One labeled candidate
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatchSample {
+    /// the candidate event, with its ring descriptor unless its rings are blank
+    pub candidate: SaeEvent,
+    /// whether the detector accepted the candidate as a corner
+    pub accepted: bool,
+    /// SAE timestamps of the patch, row by row; unobserved pixels are 0
+    pub patch: Vec<SaeTime>,
+}
+
+/// Collects labeled patches from an event stream
+pub struct PatchDataset {
+    config: PatchDatasetConfig,
+    surfaces: [SaeSurface; 2],
+    samples: Vec<PatchSample>,
+    rejected_seen: u64,
+}
+
+impl PatchDataset {
+    pub fn new(nrows: usize, ncols: usize, config: PatchDatasetConfig) -> Self {
+        let surface = || SaeSurface::with_warmup(nrows, ncols, config.warmup.clone());
+        PatchDataset {
+            surfaces: [surface(), surface()],
+            config,
+            samples: Vec::new(),
+            rejected_seen: 0,
+        }
+    }
+
+    /// side length of the patches
+    pub fn patch_size(&self) -> usize {
+        2 * self.config.radius + 1
+    }
+
+    /// Update the surfaces with the event and sample it if it is a candidate.
+    /// Returns the label of the sample taken, if any.
+    pub fn process(&mut self, evt: &SaeEvent) -> Option<bool> {
+        let surface = &mut self.surfaces[evt.polarity.min(1) as usize];
+        let corner = surface.update_and_detect(evt);
+        let (nrows, ncols) = surface.shape();
+        let (row, col, radius) = (evt.row as usize, evt.col as usize, self.config.radius);
+        if !surface.is_warmed_up() || row < radius || col < radius || row + radius >= nrows || col + radius >= ncols {
+            return None;
+        }
+        let accepted = corner.is_some();
+        if !accepted {
+            self.rejected_seen += 1;
+            if !(self.rejected_seen - 1).is_multiple_of(self.config.rejected_stride.max(1) as u64) {
+                return None;
+            }
+        }
+
+        let sae = surface.matrix();
+        let mut patch = Vec::with_capacity((2 * radius + 1) * (2 * radius + 1));
+        for r in (row - radius)..=(row + radius) {
+            for c in (col - radius)..=(col + radius) {
+                patch.push(sae[(r, c)]);
+            }
+        }
+        let candidate = match corner {
+            Some(corner) => corner,
+            None => SaeEvent {
+                norm_descriptor: ring_descriptor(sae, row, col).map(Box::new),
+                ..evt.clone()
+            },
+        };
+        self.samples.push(PatchSample { candidate, accepted, patch });
+        Some(accepted)
+    }
+
+    /// Process all events from the iterator
+    pub fn run<I: IntoIterator<Item = SaeEvent>>(&mut self, events: I) {
+        for evt in events {
+            self.process(&evt);
+        }
+    }
+
+    pub fn samples(&self) -> &[PatchSample] {
+        &self.samples
+    }
+
+    pub fn into_samples(self) -> Vec<PatchSample> {
+        self.samples
+    }
+
+    /// (accepted, rejected) sample counts
+    pub fn label_counts(&self) -> (usize, usize) {
+        let accepted = self.samples.iter().filter(|sample| sample.accepted).count();
+        (accepted, self.samples.len() - accepted)
+    }
+
+    /// Write the samples as a numpy `.npz` archive holding `patches` (N x size x size,
+    /// uint32 timestamps), `labels` (N, uint8: 1 for accepted) and `candidates`
+    /// (the corner structured array of `io::npy`, holding positions, timestamps and descriptors)
+    #[cfg(feature = "io")]
+    pub fn write_npz<W: Write>(&self, writer: W) -> io::Result<W> {
+        let size = self.patch_size();
+        let mut patches = Vec::with_capacity(self.samples.len() * size * size * 4);
+        for sample in self.samples.iter() {
+            sample.patch.iter().for_each(|t| patches.extend_from_slice(&t.to_le_bytes()));
+        }
+        let labels: Vec<u8> = self.samples.iter().map(|sample| sample.accepted as u8).collect();
+        let candidates: Vec<SaeEvent> = self.samples.iter().map(|sample| sample.candidate.clone()).collect();
+
+        let mut npz = NpzWriter::new(writer);
+        npz.add_array("patches", "'<u4'", &[self.samples.len(), size, size], &patches)?;
+        npz.add_array("labels", "'|u1'", &[self.samples.len()], &labels)?;
+        npz.add_corners("candidates", &candidates)?;
+        npz.finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a bright square moving diagonally: its corners are detected, its edges rejected
+    fn moving_square() -> Vec<SaeEvent> {
+        let mut events = Vec::new();
+        for step in 0..20u16 {
+            let t = 1_000 + step as SaeTime * 1_000;
+            for i in 0..8u16 {
+                for &(row, col) in [(10 + step, 10 + step + i), (10 + step + i, 10 + step)].iter() {
+                    events.push(SaeEvent { row, col, polarity: 1, timestamp: t + i as SaeTime, ..SaeEvent::default() });
+                }
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn test_sampling() {
+        let config = PatchDatasetConfig { warmup: WarmupConfig::disabled(), rejected_stride: 1, ..PatchDatasetConfig::default() };
+        let mut all = PatchDataset::new(48, 48, config.clone());
+        all.run(moving_square());
+        let (accepted, rejected) = all.label_counts();
+        assert!(accepted > 0 && rejected > 0);
+        let size = all.patch_size();
+        assert_eq!(size, 9);
+        for sample in all.samples() {
+            assert_eq!(sample.patch.len(), size * size);
+            // the candidate's own pixel is the patch center
+            assert_eq!(sample.patch[size * size / 2], sample.candidate.timestamp);
+            // rejected candidates on a blank ring have no descriptor
+            assert!(!sample.accepted || sample.candidate.norm_descriptor.is_some());
+            assert!(sample.candidate.norm_descriptor.iter().flat_map(|desc| desc.iter()).all(|val| val.is_finite()));
+        }
+
+        let mut strided = PatchDataset::new(48, 48, PatchDatasetConfig { rejected_stride: 4, ..config });
+        strided.run(moving_square());
+        assert_eq!(strided.label_counts(), (accepted, rejected.div_ceil(4)));
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn test_write_npz() {
+        use crate::io::npy::NpzArchive;
+
+        let config = PatchDatasetConfig { warmup: WarmupConfig::disabled(), ..PatchDatasetConfig::default() };
+        let mut dataset = PatchDataset::new(48, 48, config);
+        dataset.run(moving_square());
+        let count = dataset.samples().len();
+        let bytes = dataset.write_npz(Vec::new()).unwrap();
+
+        let archive = NpzArchive::read_from(bytes.as_slice()).unwrap();
+        let candidates = archive.corners("candidates").unwrap();
+        assert_eq!(candidates.len(), count);
+        assert_eq!(candidates[0], dataset.samples()[0].candidate);
+        let patches = archive.npy_bytes("patches").unwrap();
+        let header = String::from_utf8_lossy(&patches[10..128]);
+        assert!(header.contains(&format!("'shape': ({}, 9, 9)", count)));
+        assert_eq!(patches.len() % 64, (count * 81 * 4) % 64);
+        let labels = archive.npy_bytes("labels").unwrap();
+        let accepted = labels[labels.len() - count..].iter().filter(|&&label| label == 1).count();
+        assert_eq!(accepted, dataset.label_counts().0);
+    }
+}
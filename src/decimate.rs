@@ -0,0 +1,153 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Decimation of track observations by the information they add, to cut the load
+//! on a downstream optimizer.
+//!
+//! A tracked corner is observed far more often than it moves appreciably: most
+//! observations of a slow track repeat the last position to within a fraction of a
+//! pixel, and add constraints without adding information. `Decimator` keeps an
+//! observation only once its track has moved at least `min_displacement` since the
+//! last kept observation, or once `max_interval` has passed, so that slow and still
+//! tracks keep a steady trickle of observations. The first observation of every
+//! track is kept.
+
+use std::collections::HashMap;
+
+use crate::sae_types::*;
+use crate::track::{Track, TrackId, TrackObserver};
+
+
+/// Parameters of decimation
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecimationConfig {
+    /// least displacement (pixels) since the last kept observation of a track
+    pub min_displacement: f32,
+    /// an observation this long after the last kept one is kept regardless;
+    /// None never keeps an observation for its age alone
+    pub max_interval: Option<SaeTime>,
+}
+
+impl Default for DecimationConfig {
+    fn default() -> Self {
+        DecimationConfig {
+            min_displacement: 1.0,
+            max_interval: Some(100_000),
+        }
+    }
+}
+
+/// Counts of observations offered to and kept by a decimator
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DecimationStats {
+    pub offered: u64,
+    pub kept: u64,
+}
+
+impl DecimationStats {
+    /// ratio of kept to offered observations (0..1): smaller is more reduction
+    pub fn output_ratio(&self) -> f32 {
+        if self.offered == 0 {
+            return 1.0;
+        }
+        self.kept as f32 / self.offered as f32
+    }
+}
+
+/// Decides which observations of each track to keep
+pub struct Decimator {
+    config: DecimationConfig,
+    /// position (row, col) and time of the last kept observation of each track
+    last_kept: HashMap<TrackId, ((f32, f32), SaeTime)>,
+    stats: DecimationStats,
+}
+
+impl Decimator {
+    pub fn new(config: DecimationConfig) -> Self {
+        Decimator { config, last_kept: HashMap::new(), stats: DecimationStats::default() }
+    }
+
+    pub fn config(&self) -> &DecimationConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &DecimationStats {
+        &self.stats
+    }
+
+    /// Decide whether to keep an observation of `track`, in time order per track
+    pub fn keep(&mut self, track: TrackId, corner: &SaeEvent) -> bool {
+        self.stats.offered += 1;
+        let position = corner.subpixel_position();
+        let keep = match self.last_kept.get(&track) {
+            None => true,
+            Some(&((row, col), timestamp)) => {
+                let moved = ((position.0 - row).powi(2) + (position.1 - col).powi(2)).sqrt() >= self.config.min_displacement;
+                moved || self.config.max_interval.is_some_and(|interval| corner.timestamp.elapsed_since(timestamp) >= interval)
+            }
+        };
+        if keep {
+            self.last_kept.insert(track, (position, corner.timestamp));
+            self.stats.kept += 1;
+        }
+        keep
+    }
+
+    /// Forget a track that has ended
+    pub fn forget(&mut self, track: TrackId) {
+        self.last_kept.remove(&track);
+    }
+
+    /// number of tracks with a kept observation, not yet forgotten
+    pub fn tracks(&self) -> usize {
+        self.last_kept.len()
+    }
+}
+
+impl TrackObserver for Decimator {
+    fn track_ended(&mut self, track: &Track) {
+        self.forget(track.id);
+    }
+}
+
+/// A track with only the observations a fresh decimator would keep
+pub fn decimate_track(track: &Track, config: &DecimationConfig) -> Track {
+    let mut decimator = Decimator::new(config.clone());
+    let observations = track.observations.iter()
+        .filter(|obs| decimator.keep(track.id, obs))
+        .cloned()
+        .collect();
+    Track { id: track.id, observations }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decimate_slow_track() {
+        // a corner creeping along at 0.1 pixel per observation, every millisecond
+        let mut track = Track::new(3, SaeEvent { row: 10, col: 10, row_f: Some(10.0), col_f: Some(10.0), ..SaeEvent::default() });
+        for i in 1..100u32 {
+            let col = 10.0 + 0.1 * i as f32;
+            track.observations.push(SaeEvent { row: 10, col: col as u16, row_f: Some(10.0), col_f: Some(col),
+                                               timestamp: 1_000 * i, ..SaeEvent::default() });
+        }
+        let config = DecimationConfig { min_displacement: 1.0, max_interval: None };
+        let decimated = decimate_track(&track, &config);
+        // the first, then one per pixel moved
+        assert_eq!(decimated.len(), 10);
+        assert_eq!(decimated.first().timestamp, 0);
+        // fewer, but spanning the same motion
+        assert!(decimated.last().timestamp >= 90_000);
+
+        let mut decimator = Decimator::new(DecimationConfig { min_displacement: 100.0, max_interval: Some(20_000) });
+        let kept = track.observations.iter().filter(|obs| decimator.keep(track.id, obs)).count();
+        assert_eq!(kept, 5);
+        assert_eq!(decimator.stats().offered, 100);
+        assert!((decimator.stats().output_ratio() - 0.05).abs() < 1e-6);
+        decimator.track_ended(&track);
+        assert_eq!(decimator.tracks(), 0);
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Descriptor extraction: fingerprints of the SAE around an event, used for matching.
+//!
+//! `RingExtractor` produces the normalized C3/C4 ring descriptor computed by the detector.
+//! `HatsExtractor` is a HATS-style alternative after Sironi, Brambilla, Bourdis, Lagorce
+//! & Benosman, "HATS: Histograms of Averaged Time Surfaces for Robust Event-based
+//! Object Classification" (CVPR 2018): the neighborhood is split into a grid of cells,
+//! and each cell holds the average of the local time surfaces of the pixels in it.
+//! Averaging over cells makes it more robust than the single-ring normalization when
+//! matching across larger time gaps.
+
+use crate::detector::ring_descriptor;
+use crate::sae_types::*;
+
+
+/// Computes a descriptor for an event from the SAE of its polarity
+pub trait DescriptorExtractor {
+    /// number of elements in each descriptor
+    fn descriptor_len(&self) -> usize;
+
+    /// Compute the descriptor at the event; None where it can't be computed,
+    /// eg too close to the SAE border
+    fn extract(&self, sae_pol: &SaeMatrix, evt: &SaeEvent) -> Option<Vec<f32>>;
+
+    /// Like `extract`, telling unobserved pixels from events at timestamp 0 by `occupancy`.
+    /// By default the occupancy is ignored.
+    fn extract_observed(&self, sae_pol: &SaeMatrix, _occupancy: &SaeOccupancy, evt: &SaeEvent) -> Option<Vec<f32>> {
+        self.extract(sae_pol, evt)
+    }
+}
+
+/// The normalized C3/C4 ring descriptor used by the Arc* detector
+#[derive(Clone, Debug, Default)]
+pub struct RingExtractor;
+
+impl DescriptorExtractor for RingExtractor {
+    fn descriptor_len(&self) -> usize {
+        NORM_DESCRIPTOR_LEN
+    }
+
+    fn extract(&self, sae_pol: &SaeMatrix, evt: &SaeEvent) -> Option<Vec<f32>> {
+        ring_descriptor(sae_pol, evt.row as usize, evt.col as usize).map(|desc| desc.to_vec())
+    }
+}
+
+/// Parameters of the HATS-style extractor
+#[derive(Clone, Debug, PartialEq)]
+pub struct HatsConfig {
+    /// side length of each square cell, in pixels
+    pub cell_size: usize,
+    /// number of cells along each side of the grid centered on the event
+    pub grid_cells: usize,
+    /// radius of each pixel's local time surface
+    pub surface_radius: usize,
+    /// decay constant of the time surfaces
+    pub tau: f32,
+    /// pixels older than this (relative to the event) don't contribute
+    pub window: SaeTime,
+}
+
+impl Default for HatsConfig {
+    fn default() -> Self {
+        // 2x2 cells of 3x3 time surfaces: 36 elements, the same length as the ring descriptor
+        HatsConfig {
+            cell_size: 4,
+            grid_cells: 2,
+            surface_radius: 1,
+            tau: 20_000.0,
+            window: 100_000,
+        }
+    }
+}
+
+/// Histograms of averaged time surfaces over a grid of cells around the event
+#[derive(Clone, Debug, Default)]
+pub struct HatsExtractor {
+    config: HatsConfig,
+}
+
+impl HatsExtractor {
+    pub fn new(config: HatsConfig) -> Self {
+        HatsExtractor { config }
+    }
+
+    fn surface_side(&self) -> usize {
+        2 * self.config.surface_radius + 1
+    }
+
+    /// whether the pixel holds an event recent enough, relative to `now`, to contribute.
+    /// Without `occupancy`, pixels at timestamp 0 count as unobserved.
+    fn is_recent(&self, sae_pol: &SaeMatrix, occupancy: Option<&SaeOccupancy>, pos: (usize, usize), now: SaeTime) -> bool {
+        let ts = sae_pol[pos];
+        let observed = occupancy.map_or(ts > 0, |occupancy| occupancy[pos]);
+        observed && ts <= now && now - ts <= self.config.window
+    }
+
+    /// Add the local time surface of the pixel at (row, col) into `out`
+    fn accumulate_surface(&self, sae_pol: &SaeMatrix, occupancy: Option<&SaeOccupancy>, row: usize, col: usize, out: &mut [f32]) {
+        let center = sae_pol[(row, col)];
+        let radius = self.config.surface_radius as i64;
+        let side = self.surface_side();
+        let (nrows, ncols) = sae_pol.shape();
+        for dr in -radius..=radius {
+            for dc in -radius..=radius {
+                let r = row as i64 + dr;
+                let c = col as i64 + dc;
+                if r < 0 || c < 0 || r >= nrows as i64 || c >= ncols as i64 {
+                    continue;
+                }
+                let pos = (r as usize, c as usize);
+                if !self.is_recent(sae_pol, occupancy, pos, center) {
+                    continue;
+                }
+                let ts = sae_pol[pos];
+                let idx = ((dr + radius) as usize) * side + (dc + radius) as usize;
+                out[idx] += (-((center - ts) as f32) / self.config.tau).exp();
+            }
+        }
+    }
+}
+
+impl DescriptorExtractor for HatsExtractor {
+    fn descriptor_len(&self) -> usize {
+        let side = self.surface_side();
+        self.config.grid_cells * self.config.grid_cells * side * side
+    }
+
+    fn extract(&self, sae_pol: &SaeMatrix, evt: &SaeEvent) -> Option<Vec<f32>> {
+        self.extract_with(sae_pol, None, evt)
+    }
+
+    fn extract_observed(&self, sae_pol: &SaeMatrix, occupancy: &SaeOccupancy, evt: &SaeEvent) -> Option<Vec<f32>> {
+        self.extract_with(sae_pol, Some(occupancy), evt)
+    }
+}
+
+impl HatsExtractor {
+    fn extract_with(&self, sae_pol: &SaeMatrix, occupancy: Option<&SaeOccupancy>, evt: &SaeEvent) -> Option<Vec<f32>> {
+        let (nrows, ncols) = sae_pol.shape();
+        let span = self.config.cell_size * self.config.grid_cells;
+        let half = span / 2;
+        let row = evt.row as usize;
+        let col = evt.col as usize;
+        if row < half || col < half || row + span - half > nrows || col + span - half > ncols {
+            return None;
+        }
+        let (top, left) = (row - half, col - half);
+
+        let cell_len = self.surface_side() * self.surface_side();
+        let mut descriptor = vec![0.0; self.descriptor_len()];
+        for (cell_idx, cell) in descriptor.chunks_mut(cell_len).enumerate() {
+            let cell_top = top + (cell_idx / self.config.grid_cells) * self.config.cell_size;
+            let cell_left = left + (cell_idx % self.config.grid_cells) * self.config.cell_size;
+            let mut contributors = 0;
+            for r in cell_top..cell_top + self.config.cell_size {
+                for c in cell_left..cell_left + self.config.cell_size {
+                    if self.is_recent(sae_pol, occupancy, (r, c), evt.timestamp) {
+                        self.accumulate_surface(sae_pol, occupancy, r, c, cell);
+                        contributors += 1;
+                    }
+                }
+            }
+            if contributors > 0 {
+                for val in cell.iter_mut() {
+                    *val /= contributors as f32;
+                }
+            }
+        }
+        Some(descriptor)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// an edge that swept left to right, one column per 1000 time units
+    fn swept_sae() -> SaeMatrix {
+        SaeMatrix::from_fn(16, 16, |_row, col| 1_000 + 1_000 * col as SaeTime)
+    }
+
+    #[test]
+    fn test_ring_extractor_matches_detector() {
+        let sae = swept_sae();
+        let evt = SaeEvent { row: 8, col: 8, timestamp: 9_000, ..SaeEvent::default() };
+        let desc = RingExtractor.extract(&sae, &evt).unwrap();
+        assert_eq!(desc.len(), RingExtractor.descriptor_len());
+        assert!(desc.iter().all(|&v| v > 0.0 && v <= 1.0));
+        assert!(RingExtractor.extract(&sae, &SaeEvent { row: 1, ..evt.clone() }).is_none());
+        // a blank ring has no descriptor, rather than one of NaN
+        assert!(RingExtractor.extract(&SaeMatrix::zeros(16, 16), &evt).is_none());
+    }
+
+    #[test]
+    fn test_hats_extractor() {
+        let extractor = HatsExtractor::default();
+        assert_eq!(extractor.descriptor_len(), NORM_DESCRIPTOR_LEN);
+        let sae = swept_sae();
+        let evt = SaeEvent { row: 8, col: 8, timestamp: 9_000, ..SaeEvent::default() };
+        let desc = extractor.extract(&sae, &evt).unwrap();
+        assert_eq!(desc.len(), NORM_DESCRIPTOR_LEN);
+
+        // within each cell's averaged surface, the newest column (right) is absent:
+        // neighbors to the right are newer than the center, so they never contribute
+        for cell in desc.chunks(9) {
+            assert!(cell[4] > 0.99);
+            assert_eq!(cell[5], 0.0);
+            assert!(cell[3] > 0.0 && cell[3] < cell[4]);
+        }
+
+        // the same edge at a later time gives the same descriptor: only relative times matter
+        let later = sae.map(|t| t + 50_000);
+        let later_desc = extractor.extract(&later, &SaeEvent { timestamp: 59_000, ..evt.clone() }).unwrap();
+        for (a, b) in desc.iter().zip(later_desc.iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+
+        assert!(extractor.extract(&sae, &SaeEvent { row: 2, ..evt.clone() }).is_none());
+
+        // events at timestamp 0 contribute where the occupancy marks them observed
+        let at_zero = SaeMatrix::zeros(16, 16);
+        let evt = SaeEvent { row: 8, col: 8, timestamp: 0, ..SaeEvent::default() };
+        assert!(extractor.extract(&at_zero, &evt).unwrap().iter().all(|&v| v == 0.0));
+        let observed = SaeOccupancy::from_element(16, 16, true);
+        let desc = extractor.extract_observed(&at_zero, &observed, &evt).unwrap();
+        assert!(desc.iter().all(|&v| v == 1.0));
+    }
+}
@@ -1,5 +1,6 @@
 // Copyright 2019, Todd Stellanova
 // License: see LICENSE file
+#![allow(clippy::empty_line_after_doc_comments)]
 
 
 /// Rust implementation of the Arc* algorithm 1 described in:
@@ -35,8 +36,20 @@
 /// timestamps (one per pixel), indicating when a change event (rising or falling above or
 /// below the detection threshold) most recently triggered at a particular pixel.
 
-use arrayvec::ArrayVec;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+#[cfg(feature = "std")]
+use crate::circle::CircleSpec;
+#[cfg(feature = "std")]
+use crate::profile::WorkProfile;
 use crate::sae_types::*;
+#[cfg(feature = "std")]
+use crate::subpixel::SubpixelConfig;
+#[cfg(feature = "std")]
+use crate::surface::{RecordingHeader, SaeSurface, UpdatePolicy, UpdateStats, WarmupConfig};
+use crate::view::SaeView;
 
 
 const CIRCLE3_DIM: usize = 16;
@@ -49,7 +62,7 @@ const CIRCLE3_GEN: [[i32; 2] ; CIRCLE3_DIM] = [
 ];
 const CIRCLE3_MIN_ARC_LEN:usize = 3;
 const CIRCLE3_MAX_ARC_LEN:usize = 6;
-type Circle3Vals = ArrayVec<[SaeTime;CIRCLE3_DIM]>;
+type Circle3Vals = [SaeTime; CIRCLE3_DIM];
 
 const CIRCLE4_DIM: usize = 20;
 /// pixel offsets of radius 4 circle surrounding point of interest
@@ -62,53 +75,45 @@ const CIRCLE4_GEN: [[i32; 2] ; CIRCLE4_DIM]  = [
 ];
 const CIRCLE4_MIN_ARC_LEN:usize = 4;
 const CIRCLE4_MAX_ARC_LEN:usize = 8;
-type Circle4Vals = ArrayVec<[SaeTime;CIRCLE4_DIM]>;
+type Circle4Vals = [SaeTime; CIRCLE4_DIM];
 
 
 /// Number of pixels inset from all borders where we can start evaluating corners
 const BORDER_INSET: usize = 4;
 
-/// Get array of SAE values from the C3 circle surrounding the given point
-fn c3_vals_for_point(sae_pol: &SaeMatrix, row: usize, col: usize) -> Circle3Vals {
-    let mut res = Circle3Vals::new();
-
+/// Get array of SAE values from the circle of `N` offsets surrounding the given point
+fn circle_vals_for_point<V: SaeView + ?Sized, const N: usize>(sae_pol: &V, circle: &[[i32; 2]; N], row: usize, col: usize) -> [SaeTime; N] {
     let irow = row as i32;
     let icol = col as i32;
 
-    for item in CIRCLE3_GEN.iter() {
+    let mut res = [0; N];
+    for (val, item) in res.iter_mut().zip(circle.iter()) {
         let a = (item[0] + irow) as usize;
         let b = (item[1] + icol) as usize;
-        res.push(sae_pol[(a, b)] );
+        *val = sae_pol.timestamp(a, b);
     }
 
     res
 }
 
-/// Get array of SAE values from the C4circle surrounding the given point
-fn c4_vals_for_point(sae_pol: &SaeMatrix, row: usize, col: usize) -> Circle4Vals {
-    let mut res = Circle4Vals::new();
-
-    let irow = row as i32;
-    let icol = col as i32;
-
-    for item in CIRCLE4_GEN.iter() {
-        let a = (item[0] + irow) as usize;
-        let b = (item[1] + icol) as usize;
-        res.push(sae_pol[(a, b)] );
-    }
+/// Get array of SAE values from the C3 circle surrounding the given point
+fn c3_vals_for_point<V: SaeView + ?Sized>(sae_pol: &V, row: usize, col: usize) -> Circle3Vals {
+    circle_vals_for_point(sae_pol, &CIRCLE3_GEN, row, col)
+}
 
-    res
+/// Get array of SAE values from the C4circle surrounding the given point
+fn c4_vals_for_point<V: SaeView + ?Sized>(sae_pol: &V, row: usize, col: usize) -> Circle4Vals {
+    circle_vals_for_point(sae_pol, &CIRCLE4_GEN, row, col)
 }
 
 
 
 /// Find the freshest timestamp in the given circle
-fn find_freshest_in_circle(circle_vals: &[SaeTime]) -> (usize, SaeTime) {
+pub(crate) fn find_freshest_in_circle(circle_vals: &[SaeTime]) -> (usize, SaeTime) {
     let mut newest_idx = 0;
     let mut newest_val: SaeTime = 0;
     //find the newest val in the circle
-    for i in 0..circle_vals.len() {
-        let val = circle_vals[i];
+    for (i, &val) in circle_vals.iter().enumerate() {
         if val > newest_val {
             newest_val = val;
             newest_idx = i;
@@ -118,8 +123,41 @@ fn find_freshest_in_circle(circle_vals: &[SaeTime]) -> (usize, SaeTime) {
     (newest_idx, newest_val)
 }
 
-/// returns the size of the arc segment containing the freshest SAE timestamps
-fn arcstar_expand(circle_vals: &[SaeTime], circle_dim: usize, min_arc_size: usize,  newest_idx: usize)  -> usize {
+/// The side of the newest element an arc grows to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArcDirection {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// One expansion decision of Arc*: the fresher of the next elements on either side is taken
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExpansionStep {
+    pub direction: ArcDirection,
+    /// ring index of the element taken
+    pub index: usize,
+    pub value: SaeTime,
+    /// the older candidate, on the other side, that lost
+    pub other_value: SaeTime,
+    /// whether the step was growing the arc to its minimum length, which is unconditional
+    pub minimal: bool,
+    /// whether the element joined the freshest segment, being no older than its oldest element
+    pub in_segment: bool,
+    /// length of the freshest segment after the step
+    pub segment_size: usize,
+    /// oldest timestamp in the freshest segment after the step
+    pub segment_oldest: SaeTime,
+}
+
+/// returns the size of the arc segment containing the freshest SAE timestamps,
+/// and how many of its elements lie clockwise and counter-clockwise of `newest_idx`
+fn arcstar_expand(circle_vals: &[SaeTime], circle_dim: usize, min_arc_size: usize,  newest_idx: usize)  -> (usize, usize, usize) {
+    arcstar_expand_observed(circle_vals, circle_dim, min_arc_size, newest_idx, |_| {})
+}
+
+/// Like `arcstar_expand`, reporting each expansion decision to `observe`, eg for `trace`
+pub(crate) fn arcstar_expand_observed<F: FnMut(ExpansionStep)>(circle_vals: &[SaeTime], circle_dim: usize,
+                                                                min_arc_size: usize, newest_idx: usize, mut observe: F) -> (usize, usize, usize) {
 
     let mut cw_idx:usize = (newest_idx + 1) % circle_dim;
     let mut ccw_idx:usize = (newest_idx + (circle_dim-1)) % circle_dim;
@@ -128,17 +166,22 @@ fn arcstar_expand(circle_vals: &[SaeTime], circle_dim: usize, min_arc_size: usiz
     let mut arc_ccw_val = circle_vals[ccw_idx];
     let mut arc_cw_oldest = arc_cw_val;
     let mut arc_ccw_oldest = arc_ccw_val;
-    let mut segment_oldest =  SaeTime::max_value();
+    let mut segment_oldest =  SaeTime::MAX;
+    // elements taken into the cw and ccw arcs so far
+    let (mut cw_taken, mut ccw_taken) = (0, 0);
 
     //Expand beginning with pixels immediately neighboring newest_idx
-    for _iteration in 1..min_arc_size {
+    for iteration in 1..min_arc_size {
         // Pick CW/CCW expansion based on which next circle item has freshest timestamp
         if arc_cw_val > arc_ccw_val {
             // CW arc has freshest value: include arc in new segment
             if arc_cw_oldest < segment_oldest {
                 segment_oldest = arc_cw_oldest;
             }
+            observe(ExpansionStep { direction: ArcDirection::Clockwise, index: cw_idx, value: arc_cw_val, other_value: arc_ccw_val,
+                minimal: true, in_segment: true, segment_size: iteration + 1, segment_oldest });
             // Expand arc cw
+            cw_taken += 1;
             cw_idx = ( cw_idx + 1 ) % circle_dim;
             arc_cw_val = circle_vals[cw_idx];
             if arc_cw_val < arc_cw_oldest {
@@ -151,7 +194,10 @@ fn arcstar_expand(circle_vals: &[SaeTime], circle_dim: usize, min_arc_size: usiz
             if arc_ccw_oldest < segment_oldest {
                 segment_oldest = arc_ccw_oldest;
             }
+            observe(ExpansionStep { direction: ArcDirection::CounterClockwise, index: ccw_idx, value: arc_ccw_val, other_value: arc_cw_val,
+                minimal: true, in_segment: true, segment_size: iteration + 1, segment_oldest });
             // Expand arc ccw
+            ccw_taken += 1;
             ccw_idx = (ccw_idx + (circle_dim - 1)) % circle_dim;
             arc_ccw_val = circle_vals[ccw_idx];
             if arc_ccw_val < arc_ccw_oldest {
@@ -164,18 +210,26 @@ fn arcstar_expand(circle_vals: &[SaeTime], circle_dim: usize, min_arc_size: usiz
     // this is the arc length of the arc containing the freshest elements in the circle
     //TODO check this assumption
     let mut freshest_arc_size: usize = min_arc_size;
+    // elements of the freshest segment on either side of newest_idx
+    let (mut segment_cw, mut segment_ccw) = (cw_taken, ccw_taken);
 
     // Continue expansion, looking at freshest values
     for iteration in min_arc_size..circle_dim {
         // Pick CW/CCW expansion based on which next circle item has freshest timestamp
         if arc_cw_val > arc_ccw_val {
             // CW arc has the freshest value: include arc in freshest segment
-            if arc_cw_val >=  segment_oldest {
+            cw_taken += 1;
+            let in_segment = arc_cw_val >= segment_oldest;
+            if in_segment {
                 freshest_arc_size = iteration + 1;
+                segment_cw = cw_taken;
+                segment_ccw = ccw_taken;
                 if arc_cw_oldest < segment_oldest {
                     segment_oldest = arc_cw_oldest;
                 }
             }
+            observe(ExpansionStep { direction: ArcDirection::Clockwise, index: cw_idx, value: arc_cw_val, other_value: arc_ccw_val,
+                minimal: false, in_segment, segment_size: freshest_arc_size, segment_oldest });
             // Expand arc clockwise
             cw_idx = ( cw_idx + 1) % circle_dim;
             arc_cw_val = circle_vals[cw_idx];
@@ -186,12 +240,18 @@ fn arcstar_expand(circle_vals: &[SaeTime], circle_dim: usize, min_arc_size: usiz
         }
         else {
             // CCW arc has the freshest value: include arc in freshest segment
-            if arc_ccw_val >=  segment_oldest {
+            ccw_taken += 1;
+            let in_segment = arc_ccw_val >= segment_oldest;
+            if in_segment {
                 freshest_arc_size = iteration + 1;
+                segment_cw = cw_taken;
+                segment_ccw = ccw_taken;
                 if arc_ccw_oldest < segment_oldest {
                     segment_oldest = arc_ccw_oldest;
                 }
             }
+            observe(ExpansionStep { direction: ArcDirection::CounterClockwise, index: ccw_idx, value: arc_ccw_val, other_value: arc_cw_val,
+                minimal: false, in_segment, segment_size: freshest_arc_size, segment_oldest });
             // Expand arc counter-clockwise
             ccw_idx = (ccw_idx + (circle_dim - 1) ) % circle_dim;
             arc_ccw_val = circle_vals[ccw_idx];
@@ -202,95 +262,922 @@ fn arcstar_expand(circle_vals: &[SaeTime], circle_dim: usize, min_arc_size: usiz
         }
     }
 
-    freshest_arc_size
+    (freshest_arc_size, segment_cw, segment_ccw)
 }
 
-/// returns whether the given point in updated SAE is a corner
-fn arcstar_check_for_point(sae_pol: &SaeMatrix, evt: &mut SaeEvent) -> bool {
-    let row = evt.row as usize;
-    let col = evt.col as usize;
+/// Direction (radians) of the bisector of the arc of `offsets` holding `cw` elements
+/// clockwise and `ccw` counter-clockwise of `newest_idx`, as a unit vector
+fn arc_bisector(offsets: &[[i32; 2]], newest_idx: usize, cw: usize, ccw: usize) -> (f32, f32) {
+    let dim = offsets.len();
+    let (mut row, mut col) = (0.0f32, 0.0f32);
+    for i in 0..=(cw + ccw).min(dim - 1) {
+        let off = offsets[(newest_idx + dim - ccw + i) % dim];
+        let len = libm::sqrtf((off[0] * off[0] + off[1] * off[1]) as f32);
+        row += off[0] as f32 / len;
+        col += off[1] as f32 / len;
+    }
+    let norm = libm::sqrtf(row * row + col * col);
+    if norm > 0.0 { (row / norm, col / norm) } else { (0.0, 0.0) }
+}
 
-    let c3_vals:Circle3Vals = c3_vals_for_point(sae_pol, row, col);
-    let c3_vals_slice = c3_vals.as_slice();
-    let (freshest_c3_idx, freshest_c3_val) = find_freshest_in_circle(c3_vals_slice);
-    let freshest_c3_segment_size = arcstar_expand(c3_vals_slice, CIRCLE3_DIM, CIRCLE3_MIN_ARC_LEN, freshest_c3_idx);
-
-    let mut arc_valid =
-        (freshest_c3_segment_size <= CIRCLE3_MAX_ARC_LEN) ||
-            ((freshest_c3_segment_size >= (CIRCLE3_DIM - CIRCLE3_MAX_ARC_LEN)) &&
-                (freshest_c3_segment_size <= (CIRCLE3_DIM - CIRCLE3_MIN_ARC_LEN) ));
-
-    if arc_valid {
-        let c4_vals:Circle4Vals = c4_vals_for_point(sae_pol, row, col);
-        let c4_vals_slice = c4_vals.as_slice();
-
-        let (freshest_c4_idx, freshest_c4_val) = find_freshest_in_circle(c4_vals_slice);
-        let freshest_c4_segment_size = arcstar_expand(c4_vals_slice, CIRCLE4_DIM, CIRCLE4_MIN_ARC_LEN, freshest_c4_idx);
-        arc_valid =
-            (freshest_c4_segment_size <= CIRCLE4_MAX_ARC_LEN) ||
-                ((freshest_c4_segment_size >= (CIRCLE4_DIM - CIRCLE4_MAX_ARC_LEN)) &&
-                    (freshest_c4_segment_size <= (CIRCLE4_DIM - CIRCLE4_MIN_ARC_LEN) ));
-
-        if arc_valid {
-            //this is where we calculate the descriptor "fingerprint" for an event,
-            //based on the shape of the surrounding SAE
-            let freshest_seg_val:f32 = (freshest_c3_val.max(freshest_c4_val)) as f32;
-            let mut desc_idx = 0;
-            let mut norm_descriptor:NormDescriptor = [0.0; NORM_DESCRIPTOR_LEN];
-            //iterate around C3 starting from maximum index
-            for c3_idx in 0..c3_vals_slice.len() {
-                let true_idx = (c3_idx + freshest_c3_idx) % c3_vals_slice.len();
-                let val = c3_vals_slice[true_idx];
-                let norm: f32 = 1.0f32 - (freshest_seg_val - (val as f32))/freshest_seg_val;
-                norm_descriptor[desc_idx] = norm;
-                desc_idx +=1;
-            }
-            //iterate around C4 starting from maximum index
-            for c4_idx in 0..c4_vals_slice.len() {
-                let true_idx = (c4_idx + freshest_c4_idx) % c4_vals_slice.len();
-                let val = c4_vals_slice[true_idx];
-                let norm: f32 = 1.0f32 - (freshest_seg_val - (val as f32))/freshest_seg_val;
-                norm_descriptor[desc_idx] = norm;
-                desc_idx +=1;
+/// Inside or outside, from the freshest segment sizes of both rings of a corner
+fn corner_kind(inner_segment: usize, inner_max_arc_len: usize, outer_segment: usize, outer_max_arc_len: usize) -> Option<CornerKind> {
+    match (inner_segment <= inner_max_arc_len, outer_segment <= outer_max_arc_len) {
+        (true, true) => Some(CornerKind::Outside),
+        (false, false) => Some(CornerKind::Inside),
+        _ => None,
+    }
+}
+
+/// Corner orientation from the bisectors of the freshest arcs of both rings
+fn corner_orientation(inner: (f32, f32), outer: (f32, f32)) -> Option<f32> {
+    let (row, col) = (inner.0 + outer.0, inner.1 + outer.1);
+    if row == 0.0 && col == 0.0 {
+        return None;
+    }
+    Some(libm::atan2f(row, col))
+}
+
+/// Counts of the elementary operations of corner detection, for predicting the cost
+/// of a workload on targets where timing it isn't practical, eg MCUs and DSPs
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DetectorWork {
+    /// SAE timestamps read from the rings
+    pub circle_samples: u64,
+    /// occupancy and dead pixel flags read from the rings
+    pub occupancy_samples: u64,
+    /// steps of the arc expansion loop
+    pub expansion_steps: u64,
+    /// descriptors computed, one per corner
+    pub descriptors: u64,
+}
+
+impl DetectorWork {
+    /// add the counts of `other`
+    pub fn add(&mut self, other: &DetectorWork) {
+        self.circle_samples += other.circle_samples;
+        self.occupancy_samples += other.occupancy_samples;
+        self.expansion_steps += other.expansion_steps;
+        self.descriptors += other.descriptors;
+    }
+
+    /// the larger of each count of `self` and `other`
+    pub fn max(&self, other: &DetectorWork) -> DetectorWork {
+        DetectorWork {
+            circle_samples: self.circle_samples.max(other.circle_samples),
+            occupancy_samples: self.occupancy_samples.max(other.occupancy_samples),
+            expansion_steps: self.expansion_steps.max(other.expansion_steps),
+            descriptors: self.descriptors.max(other.descriptors),
+        }
+    }
+
+}
+
+/// Calculate the descriptor "fingerprint" for an event, based on the shape of the surrounding SAE:
+/// each circle's timestamps, starting from its freshest element, normalized by the freshest timestamp.
+fn normalized_ring_descriptor(c3_vals: &[SaeTime], freshest_c3_idx: usize, c4_vals: &[SaeTime], freshest_c4_idx: usize) -> NormDescriptor {
+    let freshest_seg_val: f32 = (c3_vals[freshest_c3_idx].max(c4_vals[freshest_c4_idx])) as f32;
+    let mut norm_descriptor: NormDescriptor = [0.0; NORM_DESCRIPTOR_LEN];
+    //iterate around C3 starting from maximum index, then around C4 starting from maximum index
+    let c3_ring = (0..c3_vals.len()).map(|idx| c3_vals[(idx + freshest_c3_idx) % c3_vals.len()]);
+    let c4_ring = (0..c4_vals.len()).map(|idx| c4_vals[(idx + freshest_c4_idx) % c4_vals.len()]);
+    for (desc, val) in norm_descriptor.iter_mut().zip(c3_ring.chain(c4_ring)) {
+        *desc = 1.0f32 - (freshest_seg_val - (val as f32))/freshest_seg_val;
+    }
+    norm_descriptor
+}
+
+/// Pairs of descriptor elements (the C3 then the C4 ring, each from its freshest element)
+/// compared for the bits of a binary descriptor, drawn once by a fixed generator
+#[cfg(feature = "std")]
+const BINARY_PAIRS: [(u8, u8); BINARY_DESCRIPTOR_BITS] = binary_pairs();
+
+#[cfg(feature = "std")]
+const fn binary_pairs() -> [(u8, u8); BINARY_DESCRIPTOR_BITS] {
+    let mut pairs = [(0u8, 0u8); BINARY_DESCRIPTOR_BITS];
+    // xorshift32
+    let mut state: u32 = 0x2545_F491;
+    let mut count = 0;
+    while count < BINARY_DESCRIPTOR_BITS {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let first = (state % NORM_DESCRIPTOR_LEN as u32) as u8;
+        let second = ((state >> 16) % NORM_DESCRIPTOR_LEN as u32) as u8;
+        if first != second {
+            pairs[count] = (first, second);
+            count += 1;
+        }
+    }
+    pairs
+}
+
+/// Calculate the binary descriptor for an event from rings of the standard lengths,
+/// each read from its freshest element as in `normalized_ring_descriptor`
+#[cfg(feature = "std")]
+fn binary_ring_descriptor(c3_vals: &[SaeTime], freshest_c3_idx: usize, c4_vals: &[SaeTime], freshest_c4_idx: usize) -> BinaryDescriptor {
+    let element = |idx: usize| if idx < DESCRIPTOR_C3_LEN {
+        c3_vals[(idx + freshest_c3_idx) % c3_vals.len()]
+    } else {
+        c4_vals[(idx - DESCRIPTOR_C3_LEN + freshest_c4_idx) % c4_vals.len()]
+    };
+    let bits = BINARY_PAIRS.iter()
+        .enumerate()
+        .filter(|(_, &(first, second))| element(first as usize) > element(second as usize))
+        .fold(0u128, |bits, (bit, _)| bits | (1 << bit));
+    BinaryDescriptor(bits)
+}
+
+/// Heuristic confidence (0..1) in the arc of one ring, averaging three cues:
+/// the arc margin (how far the arc length lies inside the accepted range),
+/// the time contrast (how much fresher the `segment_size` freshest timestamps are than
+/// the rest, relative to the ring's time span), and the local `support` (the fraction of
+/// ring pixels observed). Only the ranking is meaningful: see `eval::confidence`
+/// for calibration to precision.
+fn ring_confidence(vals: &[SaeTime], segment_size: usize, min_arc_len: usize, max_arc_len: usize, support: f32) -> f32 {
+    let dim = vals.len();
+    if dim == 0 || segment_size == 0 || segment_size >= dim {
+        return 0.0;
+    }
+    let arc_len = if segment_size <= max_arc_len { segment_size } else { dim - segment_size };
+    let half_range = (max_arc_len - min_arc_len) as f32 / 2.0 + 1.0;
+    let margin = ((arc_len + 1).saturating_sub(min_arc_len).min((max_arc_len + 1).saturating_sub(arc_len)) as f32 / half_range).min(1.0);
+
+    let mut sorted: Vec<SaeTime> = vals.to_vec();
+    sorted.sort_unstable_by(|a, b| b.cmp(a));
+    let span = (sorted[0] - sorted[dim - 1]) as f32;
+    let contrast = if span > 0.0 {
+        let fresh = sorted[..segment_size].iter().map(|&t| t as f32).sum::<f32>() / segment_size as f32;
+        let rest = sorted[segment_size..].iter().map(|&t| t as f32).sum::<f32>() / (dim - segment_size) as f32;
+        (fresh - rest) / span
+    } else {
+        0.0
+    };
+
+    (margin + contrast + support.clamp(0.0, 1.0)) / 3.0
+}
+
+/// Compute the normalized ring descriptor at any point far enough from the SAE border,
+/// whether or not it is a corner. None for a blank ring, all of whose timestamps are
+/// zero, which has nothing to normalize by.
+pub fn ring_descriptor<V: SaeView + ?Sized>(sae_pol: &V, row: usize, col: usize) -> Option<NormDescriptor> {
+    if !is_inside_border(sae_pol, row, col) {
+        return None;
+    }
+    let c3_vals: Circle3Vals = c3_vals_for_point(sae_pol, row, col);
+    let c4_vals: Circle4Vals = c4_vals_for_point(sae_pol, row, col);
+    let (freshest_c3_idx, freshest_c3) = find_freshest_in_circle(c3_vals.as_slice());
+    let (freshest_c4_idx, freshest_c4) = find_freshest_in_circle(c4_vals.as_slice());
+    if freshest_c3 == 0 && freshest_c4 == 0 {
+        return None;
+    }
+    Some(normalized_ring_descriptor(c3_vals.as_slice(), freshest_c3_idx, c4_vals.as_slice(), freshest_c4_idx))
+}
+
+/// whether the point is far enough from the SAE border to evaluate both circles
+fn is_inside_border<V: SaeView + ?Sized>(sae_pol: &V, row: usize, col: usize) -> bool {
+    let (nrows, ncols) = sae_pol.shape();
+    !((col < BORDER_INSET) || (col >= (ncols - BORDER_INSET)) ||
+        (row < BORDER_INSET) || (row >= (nrows - BORDER_INSET)))
+}
+
+/// Check the ring timestamps `vals` for a valid arc, returning the index of the freshest
+/// element and the extent of the freshest segment, as from `arcstar_expand`, if valid:
+/// the segment, or the rest of the ring, must be `min_arc_len..=max_arc_len` long
+fn check_ring_arc(vals: &[SaeTime], min_arc_len: usize, max_arc_len: usize) -> Option<(usize, (usize, usize, usize))> {
+    let dim = vals.len();
+    if dim <= max_arc_len {
+        return None;
+    }
+    let (freshest_idx, _) = find_freshest_in_circle(vals);
+    let segment = arcstar_expand(vals, dim, min_arc_len, freshest_idx);
+    let segment_size = segment.0;
+    let valid = (segment_size <= max_arc_len) || ((dim - max_arc_len)..=(dim - min_arc_len)).contains(&segment_size);
+    if valid { Some((freshest_idx, segment)) } else { None }
+}
+
+/// The configuration of the standard Arc* detector, shared by the free functions
+#[cfg(feature = "std")]
+fn standard_config() -> &'static DetectorConfig {
+    static STANDARD: std::sync::OnceLock<DetectorConfig> = std::sync::OnceLock::new();
+    STANDARD.get_or_init(DetectorConfig::default)
+}
+
+/// Detect whether the input event is a corner, and compute descriptor if so:
+/// returns a modified event with computed descriptor, if it's a corner.
+/// This is `detect_and_compute_configured` with the default `DetectorConfig`.
+#[cfg(feature = "std")]
+pub fn detect_and_compute_one<V: SaeView + ?Sized>(sae_pol: &V, evt: &SaeEvent) -> Option<SaeEvent> {
+    detect_and_compute_configured(standard_config(), sae_pol, None, evt)
+}
+
+/// events per chunk evaluated by one task of `detect_and_compute_batch_par`
+#[cfg(all(feature = "rayon", feature = "std"))]
+const PAR_BATCH_CHUNK: usize = 1024;
+
+/// Detect corners among a batch of events, all checked against the same surface:
+/// the corners `detect_and_compute_one` finds for each event in turn, in the order
+/// of `events`. Only corners are copied from the input.
+#[cfg(feature = "std")]
+pub fn detect_and_compute_batch<V: SaeView + ?Sized>(sae_pol: &V, events: &[SaeEvent]) -> Vec<SaeEvent> {
+    events.iter()
+        .filter_map(|evt| detect_and_compute_one(sae_pol, evt))
+        .collect()
+}
+
+/// Like `detect_and_compute_batch`, evaluating chunks of the batch in parallel.
+/// The output is identical, in the same order.
+#[cfg(all(feature = "rayon", feature = "std"))]
+pub fn detect_and_compute_batch_par<V: SaeView + Sync + ?Sized>(sae_pol: &V, events: &[SaeEvent]) -> Vec<SaeEvent> {
+    use rayon::prelude::*;
+    events.par_chunks(PAR_BATCH_CHUNK)
+        .flat_map_iter(|chunk| detect_and_compute_batch(sae_pol, chunk))
+        .collect()
+}
+
+/// Like `detect_and_compute_one`, but skips events whose surrounding circles
+/// contain too few observed pixels to form a minimal arc:
+/// unobserved pixels hold no real timestamp, and can't take part in a corner.
+#[cfg(feature = "std")]
+pub fn detect_and_compute_one_observed<V: SaeView + ?Sized>(sae_pol: &V, occupancy: &SaeOccupancy, evt: &SaeEvent) -> Option<SaeEvent> {
+    detect_and_compute_configured(standard_config(), sae_pol, Some(occupancy), evt)
+}
+
+/// Like `detect_and_compute_one_observed`, adding the work done to `work`
+#[cfg(feature = "std")]
+pub fn detect_and_compute_one_observed_counted<V: SaeView + ?Sized>(sae_pol: &V, occupancy: &SaeOccupancy, evt: &SaeEvent, work: &mut DetectorWork) -> Option<SaeEvent> {
+    detect_and_compute_configured_counted(standard_config(), sae_pol, Some(occupancy), evt, work)
+}
+
+
+/// Quick mode detection, trading precision for latency: only the C3 ring is checked,
+/// with no C4 confirmation, and corners carry no descriptor, confidence, orientation or kind.
+/// Events are skipped for unobserved rings as in `detect_and_compute_one_observed`.
+#[cfg(feature = "std")]
+pub fn detect_quick_observed<V: SaeView + ?Sized>(sae_pol: &V, occupancy: &SaeOccupancy, evt: &SaeEvent) -> Option<SaeEvent> {
+    detect_quick_configured(standard_config(), sae_pol, Some(occupancy), evt)
+}
+
+/// Like `detect_quick_observed`, checking only the inner ring of `config`
+#[cfg(feature = "std")]
+pub fn detect_quick_configured<V: SaeView + ?Sized>(config: &DetectorConfig, sae_pol: &V, occupancy: Option<&SaeOccupancy>, evt: &SaeEvent) -> Option<SaeEvent> {
+    let mut work = DetectorWork::default();
+    if !configured_point(config, sae_pol, evt, &mut work) {
+        return None;
+    }
+    configured_arc(config, &config.inner, sae_pol, occupancy, evt, &mut work)?;
+    Some(SaeEvent { norm_descriptor: None, ..evt.clone() })
+}
+
+/// A ring of fixed geometry and arc length limits, known at compile time
+pub trait StaticRingGeometry {
+    /// pixel offsets of the ring, in order around it
+    const OFFSETS: &'static [[i32; 2]];
+    const MIN_ARC_LEN: usize;
+    const MAX_ARC_LEN: usize;
+}
+
+/// The standard circle of radius `RADIUS` (3 or 4), with arc length limits `MIN_ARC..=MAX_ARC`
+pub struct StaticRing<const RADIUS: usize, const MIN_ARC: usize, const MAX_ARC: usize>;
+
+impl<const MIN_ARC: usize, const MAX_ARC: usize> StaticRingGeometry for StaticRing<3, MIN_ARC, MAX_ARC> {
+    const OFFSETS: &'static [[i32; 2]] = &CIRCLE3_GEN;
+    const MIN_ARC_LEN: usize = MIN_ARC;
+    const MAX_ARC_LEN: usize = MAX_ARC;
+}
+
+impl<const MIN_ARC: usize, const MAX_ARC: usize> StaticRingGeometry for StaticRing<4, MIN_ARC, MAX_ARC> {
+    const OFFSETS: &'static [[i32; 2]] = &CIRCLE4_GEN;
+    const MIN_ARC_LEN: usize = MIN_ARC;
+    const MAX_ARC_LEN: usize = MAX_ARC;
+}
+
+/// largest ring a `StaticDetector` can sample
+const STATIC_RING_CAPACITY: usize = CIRCLE4_DIM;
+
+/// A detector whose rings, arc length limits, and whether it computes descriptors
+/// are fixed at compile time, so that the hot path can be fully unrolled and inlined
+/// for that one configuration. Without descriptors, corners are reported with only
+/// the fields of the input event. `StandardStaticDetector` matches `detect_and_compute_one`.
+pub struct StaticDetector<Inner, Outer, const DESCRIPTOR: bool> {
+    rings: PhantomData<(Inner, Outer)>,
+}
+
+/// The standard Arc* configuration, with descriptors
+pub type StandardStaticDetector = StaticDetector<StaticRing<3, CIRCLE3_MIN_ARC_LEN, CIRCLE3_MAX_ARC_LEN>,
+                                                 StaticRing<4, CIRCLE4_MIN_ARC_LEN, CIRCLE4_MAX_ARC_LEN>, true>;
+
+impl<Inner: StaticRingGeometry, Outer: StaticRingGeometry, const DESCRIPTOR: bool> Default for StaticDetector<Inner, Outer, DESCRIPTOR> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Inner: StaticRingGeometry, Outer: StaticRingGeometry, const DESCRIPTOR: bool> StaticDetector<Inner, Outer, DESCRIPTOR> {
+    /// rejects, at compile time, arc length limits that can't hold an arc
+    const VALID_LIMITS: () = assert!(
+        0 < Inner::MIN_ARC_LEN && Inner::MIN_ARC_LEN <= Inner::MAX_ARC_LEN && Inner::MAX_ARC_LEN < Inner::OFFSETS.len() &&
+        0 < Outer::MIN_ARC_LEN && Outer::MIN_ARC_LEN <= Outer::MAX_ARC_LEN && Outer::MAX_ARC_LEN < Outer::OFFSETS.len());
+
+    pub fn new() -> Self {
+        let () = Self::VALID_LIMITS;
+        StaticDetector { rings: PhantomData }
+    }
+
+    /// Sample a ring into the front of `vals`, returning the index of its freshest
+    /// element and its freshest segment, as from `arcstar_expand`, if it holds a valid arc
+    #[inline(always)]
+    fn check_ring<R: StaticRingGeometry, S: SaeView + ?Sized>(sae_pol: &S, row: usize, col: usize,
+                                                                vals: &mut [SaeTime; STATIC_RING_CAPACITY])
+        -> Option<(usize, (usize, usize, usize))> {
+        let dim = R::OFFSETS.len();
+        for (val, item) in vals.iter_mut().zip(R::OFFSETS.iter()) {
+            *val = sae_pol.timestamp((item[0] + row as i32) as usize, (item[1] + col as i32) as usize);
+        }
+        check_ring_arc(&vals[..dim], R::MIN_ARC_LEN, R::MAX_ARC_LEN)
+    }
+
+    /// Detect whether the event is a corner of the SAE, as `detect_and_compute_one`,
+    /// on any view of it, eg a fixed-size `storage::FixedSae`
+    #[inline]
+    pub fn detect<S: SaeView + ?Sized>(&self, sae_pol: &S, evt: &SaeEvent) -> Option<SaeEvent> {
+        let row = evt.row as usize;
+        let col = evt.col as usize;
+        let (nrows, ncols) = sae_pol.shape();
+        if row < BORDER_INSET || col < BORDER_INSET || row + BORDER_INSET >= nrows || col + BORDER_INSET >= ncols {
+            return None;
+        }
+
+        let mut inner_vals = [0; STATIC_RING_CAPACITY];
+        let (inner_freshest, inner_segment) = Self::check_ring::<Inner, S>(sae_pol, row, col, &mut inner_vals)?;
+        let mut outer_vals = [0; STATIC_RING_CAPACITY];
+        let (outer_freshest, outer_segment) = Self::check_ring::<Outer, S>(sae_pol, row, col, &mut outer_vals)?;
+        if !DESCRIPTOR {
+            return Some(evt.clone());
+        }
+
+        let inner_vals = &inner_vals[..Inner::OFFSETS.len()];
+        let outer_vals = &outer_vals[..Outer::OFFSETS.len()];
+        let norm_descriptor = normalized_ring_descriptor(
+            &resample_ring(inner_vals, inner_freshest, DESCRIPTOR_C3_LEN), 0,
+            &resample_ring(outer_vals, outer_freshest, DESCRIPTOR_C4_LEN), 0);
+        let confidence = (
+            ring_confidence(inner_vals, inner_segment.0, Inner::MIN_ARC_LEN, Inner::MAX_ARC_LEN, 1.0) +
+            ring_confidence(outer_vals, outer_segment.0, Outer::MIN_ARC_LEN, Outer::MAX_ARC_LEN, 1.0)
+        ) / 2.0;
+        let orientation = corner_orientation(
+            arc_bisector(Inner::OFFSETS, inner_freshest, inner_segment.1, inner_segment.2),
+            arc_bisector(Outer::OFFSETS, outer_freshest, outer_segment.1, outer_segment.2));
+        let corner_kind = corner_kind(inner_segment.0, Inner::MAX_ARC_LEN, outer_segment.0, Outer::MAX_ARC_LEN);
+        Some(SaeEvent { norm_descriptor: Some(Box::new(norm_descriptor)), confidence: Some(confidence), orientation, corner_kind, ..evt.clone() })
+    }
+}
+
+
+/// Anything that consumes events and reports corners, eg a `ReplayDetector`
+/// or an alternative detector to compare or fuse with Arc*
+pub trait CornerDetector {
+    /// Process one event, returning the corner it forms, if any
+    fn process(&mut self, evt: &SaeEvent) -> Option<SaeEvent>;
+}
+
+impl<D: CornerDetector + ?Sized> CornerDetector for &mut D {
+    fn process(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        (**self).process(evt)
+    }
+}
+
+impl<D: CornerDetector + ?Sized> CornerDetector for Box<D> {
+    fn process(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        (**self).process(evt)
+    }
+}
+
+/// A stateless corner check of an event against a surface already updated with it,
+/// eg Arc* (`DetectorConfig`, `StaticDetector`) or eFAST (`efast::EfastDetector`)
+#[cfg(feature = "std")]
+pub trait SurfaceDetector {
+    /// The corner `evt` forms on `sae`, if any
+    fn detect(&self, sae: &SaeMatrix, evt: &SaeEvent) -> Option<SaeEvent>;
+}
+
+#[cfg(feature = "std")]
+impl<D: SurfaceDetector + ?Sized> SurfaceDetector for &D {
+    fn detect(&self, sae: &SaeMatrix, evt: &SaeEvent) -> Option<SaeEvent> {
+        (**self).detect(sae, evt)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D: SurfaceDetector + ?Sized> SurfaceDetector for Box<D> {
+    fn detect(&self, sae: &SaeMatrix, evt: &SaeEvent) -> Option<SaeEvent> {
+        (**self).detect(sae, evt)
+    }
+}
+
+#[cfg(feature = "std")]
+impl SurfaceDetector for DetectorConfig {
+    fn detect(&self, sae: &SaeMatrix, evt: &SaeEvent) -> Option<SaeEvent> {
+        detect_and_compute_configured(self, sae, None, evt)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<Inner: StaticRingGeometry, Outer: StaticRingGeometry, const DESCRIPTOR: bool> SurfaceDetector for StaticDetector<Inner, Outer, DESCRIPTOR> {
+    fn detect(&self, sae: &SaeMatrix, evt: &SaeEvent) -> Option<SaeEvent> {
+        StaticDetector::detect(self, sae, evt)
+    }
+}
+
+/// Runs a `SurfaceDetector` on a stream, maintaining one surface per polarity:
+/// each event updates the surface of its polarity and is checked on it,
+/// once that surface is warmed up
+#[cfg(feature = "std")]
+pub struct OnlineDetector<D> {
+    detector: D,
+    /// OFF and ON surfaces
+    surfaces: [SaeSurface; 2],
+}
+
+#[cfg(feature = "std")]
+impl<D: SurfaceDetector> OnlineDetector<D> {
+    pub fn new(detector: D, nrows: usize, ncols: usize, warmup: WarmupConfig) -> Self {
+        let surface = || SaeSurface::with_warmup(nrows, ncols, warmup.clone());
+        OnlineDetector { detector, surfaces: [surface(), surface()] }
+    }
+
+    pub fn detector(&self) -> &D {
+        &self.detector
+    }
+
+    pub fn detector_mut(&mut self) -> &mut D {
+        &mut self.detector
+    }
+
+    /// the surface of events of `polarity`
+    pub fn surface(&self, polarity: u8) -> &SaeSurface {
+        &self.surfaces[(polarity > 0) as usize]
+    }
+
+    /// Clear both surfaces, restarting the warm-up period
+    pub fn reset(&mut self) {
+        self.surfaces.iter_mut().for_each(SaeSurface::reset);
+    }
+
+    /// Shift both surfaces back by `offset`, as `SaeSurface::rebase` does
+    pub fn rebase(&mut self, offset: SaeTime) {
+        self.surfaces.iter_mut().for_each(|surface| surface.rebase(offset));
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D: SurfaceDetector> CornerDetector for OnlineDetector<D> {
+    fn process(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        let surface = &mut self.surfaces[(evt.polarity > 0) as usize];
+        if !surface.update(evt) || !surface.is_warmed_up() {
+            return None;
+        }
+        self.detector.detect(surface.matrix(), evt)
+    }
+}
+
+/// Which surface Arc* consults for each event. Conventions differ between papers and
+/// datasets, and so do the results, so comparisons should use the same policy.
+/// Every event updates the surface of its own polarity, whatever the policy.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DetectionPolicy {
+    /// only ON events are checked, on the ON surface
+    OnSurfaceOnly,
+    /// only OFF events are checked, on the OFF surface
+    OffSurfaceOnly,
+    /// each event is checked on the surface of its own polarity
+    #[default]
+    MatchEventPolarity,
+    /// every event is checked on a single surface holding, at each pixel,
+    /// the latest timestamp of either polarity
+    CombinedMaxSurface,
+}
+
+/// Detects corners using one surface per polarity, configured from a recording header:
+/// its geometry, detector config, detection and update policies, and sub-pixel refinement.
+/// Use the same detector for live processing and replay to get identical output.
+#[cfg(feature = "std")]
+pub struct ReplayDetector {
+    surfaces: [SaeSurface; 2],
+    policy: DetectionPolicy,
+    /// both polarities together, for `DetectionPolicy::CombinedMaxSurface`
+    combined: Option<SaeSurface>,
+}
+
+#[cfg(feature = "std")]
+impl ReplayDetector {
+    pub fn new(header: &RecordingHeader) -> Self {
+        let mut detector = ReplayDetector {
+            surfaces: [header.surface(), header.surface()],
+            policy: DetectionPolicy::default(),
+            combined: None,
+        };
+        detector.set_detection_policy(header.detection_policy);
+        detector
+    }
+
+    /// Choose which surface events are checked on. Switching to the combined surface
+    /// builds it from the current polarity surfaces.
+    pub fn set_detection_policy(&mut self, policy: DetectionPolicy) {
+        self.policy = policy;
+        self.combined = match policy {
+            DetectionPolicy::CombinedMaxSurface => Some(SaeSurface::combined(&self.surfaces[0], &self.surfaces[1])),
+            _ => None,
+        };
+    }
+
+    pub fn detection_policy(&self) -> DetectionPolicy {
+        self.policy
+    }
+
+    fn all_surfaces_mut(&mut self) -> impl Iterator<Item = &mut SaeSurface> {
+        self.surfaces.iter_mut().chain(self.combined.as_mut())
+    }
+
+    /// the surface of events of `polarity`
+    pub fn surface(&self, polarity: u8) -> &SaeSurface {
+        &self.surfaces[if polarity > 0 { 1 } else { 0 }]
+    }
+
+    /// Clear every surface, restarting their warm-up periods
+    pub fn reset(&mut self) {
+        for surface in self.all_surfaces_mut() {
+            surface.reset();
+        }
+    }
+
+    /// Shift every surface back by `offset`, as `SaeSurface::rebase` does
+    pub fn rebase(&mut self, offset: SaeTime) {
+        for surface in self.all_surfaces_mut() {
+            surface.rebase(offset);
+        }
+    }
+
+    /// Change the dimensions of every surface, clearing them
+    pub fn resize(&mut self, nrows: usize, ncols: usize) {
+        for surface in self.all_surfaces_mut() {
+            surface.resize(nrows, ncols);
+        }
+    }
+
+    /// Use custom circle geometry on both surfaces
+    pub fn set_detector_config(&mut self, config: DetectorConfig) {
+        for surface in self.all_surfaces_mut() {
+            surface.set_detector_config(config.clone());
+        }
+    }
+
+    /// the custom circle geometry, if set
+    pub fn detector_config(&self) -> Option<&DetectorConfig> {
+        self.surfaces[0].detector_config()
+    }
+
+    /// Guard both surfaces against out-of-order or duplicate events
+    pub fn set_update_policy(&mut self, policy: UpdatePolicy) {
+        for surface in self.all_surfaces_mut() {
+            surface.set_update_policy(policy);
+        }
+    }
+
+    /// Refine corners on both surfaces to sub-pixel positions, or stop refining with None
+    pub fn set_subpixel_config(&mut self, config: Option<SubpixelConfig>) {
+        for surface in self.all_surfaces_mut() {
+            surface.set_subpixel_config(config.clone());
+        }
+    }
+
+    /// counts of events ignored by the update policy, over both surfaces
+    pub fn update_stats(&self) -> UpdateStats {
+        let (on, off) = (self.surfaces[1].update_stats(), self.surfaces[0].update_stats());
+        UpdateStats {
+            ignored_older: on.ignored_older + off.ignored_older,
+            ignored_duplicate: on.ignored_duplicate + off.ignored_duplicate,
+        }
+    }
+
+    /// Count the detector work of every event on both surfaces, or stop counting
+    pub fn set_work_profiling(&mut self, enabled: bool) {
+        for surface in self.all_surfaces_mut() {
+            surface.set_work_profiling(enabled);
+        }
+    }
+
+    /// the detector work counted so far over both polarities, if profiling
+    pub fn work_profile(&self) -> Option<WorkProfile> {
+        let mut profile = WorkProfile::new();
+        for surface in self.surfaces.iter().chain(self.combined.as_ref()) {
+            profile.merge(surface.work_profile()?);
+        }
+        Some(profile)
+    }
+
+    /// Update the surfaces with the event, returning the surface to check it on, if any
+    fn route(&mut self, evt: &SaeEvent) -> Option<&mut SaeSurface> {
+        let idx = if evt.polarity > 0 { 1 } else { 0 };
+        let checked = match self.policy {
+            DetectionPolicy::OnSurfaceOnly => idx == 1,
+            DetectionPolicy::OffSurfaceOnly => idx == 0,
+            DetectionPolicy::MatchEventPolarity => true,
+            DetectionPolicy::CombinedMaxSurface => {
+                self.surfaces[idx].update(evt);
+                return self.combined.as_mut();
             }
+        };
+        if checked {
+            Some(&mut self.surfaces[idx])
+        } else {
+            self.surfaces[idx].update(evt);
+            None
+        }
+    }
+
+    /// update the surfaces with the event, and check for a corner as the detection policy says
+    pub fn process(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        self.route(evt)?.update_and_detect(evt)
+    }
+
+    /// like `process`, checking for a corner in quick mode
+    pub fn process_quick(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        self.route(evt)?.update_and_detect_quick(evt)
+    }
+
+    /// like `process_quick`, also returning the surface the corner was found on,
+    /// eg to compute its descriptor
+    pub fn process_quick_on(&mut self, evt: &SaeEvent) -> Option<(SaeEvent, &SaeSurface)> {
+        let surface = self.route(evt)?;
+        let corner = surface.update_and_detect_quick(evt)?;
+        Some((corner, surface))
+    }
+
+    /// Update the surfaces with the event without checking for a corner, returning
+    /// whether the surface it is checked on took it, ie whether `detect` may report it
+    pub fn update(&mut self, evt: &SaeEvent) -> bool {
+        self.route(evt).is_some_and(|surface| surface.update(evt))
+    }
+
+    /// the surface events of `polarity` are checked on, as the detection policy says
+    fn checked_surface(&mut self, polarity: u8) -> Option<&mut SaeSurface> {
+        let idx = if polarity > 0 { 1 } else { 0 };
+        match self.policy {
+            DetectionPolicy::OnSurfaceOnly if idx == 0 => None,
+            DetectionPolicy::OffSurfaceOnly if idx == 1 => None,
+            DetectionPolicy::CombinedMaxSurface => self.combined.as_mut(),
+            _ => Some(&mut self.surfaces[idx]),
+        }
+    }
+
+    /// Check an event already applied with `update` for a corner, on the surfaces as they
+    /// now stand: eg to check a batch newest first, after updating with it in arrival order
+    pub fn detect(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        self.checked_surface(evt.polarity)?.detect(evt)
+    }
+
+    /// like `detect`, checking for a corner in quick mode
+    pub fn detect_quick(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        self.checked_surface(evt.polarity)?.detect_quick(evt)
+    }
+}
+
+#[cfg(feature = "std")]
+impl CornerDetector for ReplayDetector {
+    fn process(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        ReplayDetector::process(self, evt)
+    }
+}
+
+/// Circle geometry and parameters of the configurable detector.
+/// The default matches the standard Arc* detector: the radius 3 circle inside the radius 4 circle,
+/// each with its standard arc length limits, computing descriptors.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DetectorConfig {
+    /// ring checked first, and the source of the first part of the descriptor
+    pub inner: CircleSpec,
+    /// ring checked only if the inner ring holds a valid arc
+    pub outer: CircleSpec,
+    /// pixels flagged `true` (eg dead sensor columns) are left out of the rings
+    pub dead_pixels: Option<SaeOccupancy>,
+    /// points closer than this to the SAE border are skipped, even where the rings would fit
+    pub min_border_inset: usize,
+    /// whether corners carry a descriptor; without, they still carry confidence,
+    /// orientation and kind
+    pub descriptor: bool,
+    /// whether corners carry a `BinaryDescriptor`, alongside or instead of the descriptor
+    pub binary_descriptor: bool,
+    /// ring pixels last updated longer than this before the event count as unobserved,
+    /// and as older than any observed pixel
+    pub max_age: Option<SaeTime>,
+}
+
+#[cfg(feature = "std")]
+impl Default for DetectorConfig {
+    fn default() -> Self {
+        DetectorConfig::new(CircleSpec::c3(), CircleSpec::c4())
+    }
+}
+
+#[cfg(feature = "std")]
+impl DetectorConfig {
+    pub fn new(inner: CircleSpec, outer: CircleSpec) -> Self {
+        DetectorConfig { inner, outer, dead_pixels: None, min_border_inset: 0, descriptor: true, binary_descriptor: false, max_age: None }
+    }
+
+    /// The standard circles with other arc length limits: `(min, max)` for the
+    /// inner and outer ring. Returns None unless `1 <= min <= max < ring length`.
+    pub fn with_arc_limits(inner: (usize, usize), outer: (usize, usize)) -> Option<Self> {
+        let inner = CircleSpec::new(CircleSpec::c3().offsets().to_vec(), inner.0, inner.1)?;
+        let outer = CircleSpec::new(CircleSpec::c4().offsets().to_vec(), outer.0, outer.1)?;
+        Some(DetectorConfig::new(inner, outer))
+    }
+
+    /// Elliptical rings for non-square pixels or anamorphic rectification:
+    /// the standard radii are multiplied by `row_scale` vertically and `col_scale`
+    /// horizontally, and the standard arc length limits scaled with the ring sizes.
+    /// Returns None if the scaled rings are too small to hold an arc.
+    pub fn anisotropic(row_scale: f32, col_scale: f32) -> Option<Self> {
+        let ring = |radius: f32, reference: &CircleSpec| {
+            CircleSpec::ellipse(radius * row_scale, radius * col_scale)
+                .and_then(|ring| ring.with_limits_scaled_from(reference))
+        };
+        let inner = ring(3.0, &CircleSpec::c3())?;
+        let outer = ring(4.0, &CircleSpec::c4())?;
+        Some(DetectorConfig::new(inner, outer))
+    }
+
+    /// Anisotropic rings for pixels `pixel_aspect` (width / height) times wider than tall,
+    /// so that the rings are circular in the scene, keeping the mean radius standard
+    pub fn for_pixel_aspect(pixel_aspect: f32) -> Option<Self> {
+        let root = pixel_aspect.sqrt();
+        Self::anisotropic(root, 1.0 / root)
+    }
 
-            evt.norm_descriptor = Some(Box::new(norm_descriptor));
+    pub fn with_dead_pixels(mut self, dead_pixels: SaeOccupancy) -> Self {
+        self.dead_pixels = Some(dead_pixels);
+        self
+    }
+
+    /// points closer than this to the SAE border aren't evaluated
+    pub fn border_inset(&self) -> usize {
+        self.inner.reach().max(self.outer.reach()).max(self.min_border_inset)
+    }
+
+    /// SAE values of the ring around the point, skipping dead pixels and reading
+    /// unobserved and stale ones as 0, the offsets they were taken from, and how many of them have been observed
+    pub(crate) fn ring_vals<V: SaeView + ?Sized>(&self, ring: &CircleSpec, sae_pol: &V, occupancy: Option<&SaeOccupancy>, evt: &SaeEvent,
+                 work: &mut DetectorWork) -> (Vec<SaeTime>, Vec<[i32; 2]>, usize) {
+        let (row, col) = (evt.row as usize, evt.col as usize);
+        let mut vals = Vec::with_capacity(ring.len());
+        let mut offsets = Vec::with_capacity(ring.len());
+        let mut observed = 0;
+        for item in ring.offsets() {
+            let pos = ((item[0] + row as i32) as usize, (item[1] + col as i32) as usize);
+            if self.dead_pixels.as_ref().is_some_and(|dead| dead[pos]) {
+                continue;
+            }
+            let value = sae_pol.timestamp(pos.0, pos.1);
+            if self.max_age.is_some_and(|max_age| !value.is_within(evt.timestamp, max_age)) {
+                vals.push(0);
+                offsets.push(*item);
+                continue;
+            }
+            // unobserved pixels count as older than any observed one, as stale ones do
+            if occupancy.is_none_or(|occ| occ[pos]) {
+                observed += 1;
+                vals.push(value);
+            } else {
+                vals.push(0);
+            }
+            offsets.push(*item);
         }
+        let flag_maps = self.dead_pixels.is_some() as u64 + occupancy.is_some() as u64;
+        work.occupancy_samples += flag_maps * ring.len() as u64;
+        work.circle_samples += vals.len() as u64;
+        (vals, offsets, observed)
     }
+}
 
-    arc_valid
+/// Resample ring values to `len` elements, starting from the freshest,
+/// so that descriptors from custom rings keep the standard layout
+fn resample_ring(vals: &[SaeTime], freshest_idx: usize, len: usize) -> Vec<SaeTime> {
+    (0..len)
+        .map(|i| vals[(freshest_idx + i * vals.len() / len) % vals.len()])
+        .collect()
 }
 
-fn arcstar_is_event_corner(sae_pol: &SaeMatrix, evt: &mut SaeEvent) -> bool {
+/// Check one ring for a valid arc, returning the index of its freshest element
+/// and the extent of the freshest segment, as from `arcstar_expand`, if valid
+#[cfg(feature = "std")]
+fn configured_ring_check(vals: &[SaeTime], ring: &CircleSpec, work: &mut DetectorWork) -> Option<(usize, (usize, usize, usize))> {
+    if vals.len() > ring.max_arc_len() {
+        work.expansion_steps += (vals.len() - 1) as u64;
+    }
+    check_ring_arc(vals, ring.min_arc_len(), ring.max_arc_len())
+}
+
+/// A ring of a `DetectorConfig` holding a valid arc around an event
+#[cfg(feature = "std")]
+struct ConfiguredArc {
+    /// ring values, as from `DetectorConfig::ring_vals`
+    vals: Vec<SaeTime>,
+    /// the offsets the values were taken from
+    offsets: Vec<[i32; 2]>,
+    /// how many of the values have been observed
+    observed: usize,
+    /// index of the freshest value
+    freshest: usize,
+    /// the freshest segment, as from `arcstar_expand`
+    segment: (usize, usize, usize),
+}
+
+/// Sample `ring` around the event and check it for a valid arc, skipping rings with
+/// too few observed pixels to form a minimal one
+#[cfg(feature = "std")]
+fn configured_arc<V: SaeView + ?Sized>(config: &DetectorConfig, ring: &CircleSpec, sae_pol: &V, occupancy: Option<&SaeOccupancy>,
+                                       evt: &SaeEvent, work: &mut DetectorWork) -> Option<ConfiguredArc> {
+    let (vals, offsets, observed) = config.ring_vals(ring, sae_pol, occupancy, evt, work);
+    if observed < ring.min_arc_len() {
+        return None;
+    }
+    let (freshest, segment) = configured_ring_check(&vals, ring, work)?;
+    Some(ConfiguredArc { vals, offsets, observed, freshest, segment })
+}
+
+/// Whether `config` evaluates the event at all: it must be far enough from the
+/// SAE border, and not on a dead pixel
+#[cfg(feature = "std")]
+fn configured_point<V: SaeView + ?Sized>(config: &DetectorConfig, sae_pol: &V, evt: &SaeEvent, work: &mut DetectorWork) -> bool {
     let row = evt.row as usize;
     let col = evt.col as usize;
-
-    //filter out events too close to SAE border
+    let inset = config.border_inset();
     let (nrows, ncols) = sae_pol.shape();
-    if (col < BORDER_INSET) || (col >= (ncols - BORDER_INSET)) ||
-        (row < BORDER_INSET) || (row >= (nrows - BORDER_INSET))  {
-        //println!("shape: ({}, {}) border: (row {} , col {})" , nrows, ncols, row, col);
+    if row < inset || col < inset || row + inset >= nrows || col + inset >= ncols {
         return false;
     }
-
-    arcstar_check_for_point(sae_pol, evt)
+    !config.dead_pixels.as_ref().is_some_and(|dead| {
+        work.occupancy_samples += 1;
+        dead[(row, col)]
+    })
 }
 
+/// Whether the ring timestamps `vals`, in the order of the offsets of `ring`,
+/// hold a valid arc: the Arc* decision for a single ring
+#[cfg(feature = "std")]
+pub fn is_arc_valid(vals: &[SaeTime], ring: &CircleSpec) -> bool {
+    configured_ring_check(vals, ring, &mut DetectorWork::default()).is_some()
+}
 
-/// Detect whether the input event is a corner, and compute descriptor if so:
-/// returns a modified event with computed descriptor, if it's a corner.
-pub fn detect_and_compute_one(sae_pol: &SaeMatrix, evt: &SaeEvent) -> Option<SaeEvent> {
-    let mut out_evt: SaeEvent = evt.clone();
+/// Like `detect_and_compute_one`, using the circles of `config`.
+/// If `occupancy` is given, events whose rings hold too few observed pixels to
+/// form a minimal arc are skipped, as in `detect_and_compute_one_observed`.
+/// Descriptors keep the standard 16 + 20 element layout: custom rings are resampled.
+#[cfg(feature = "std")]
+pub fn detect_and_compute_configured<V: SaeView + ?Sized>(config: &DetectorConfig, sae_pol: &V, occupancy: Option<&SaeOccupancy>, evt: &SaeEvent) -> Option<SaeEvent> {
+    detect_and_compute_configured_counted(config, sae_pol, occupancy, evt, &mut DetectorWork::default())
+}
 
-    match arcstar_is_event_corner(sae_pol, &mut out_evt) {
-        true => Some(out_evt),
-        false => None
+/// Like `detect_and_compute_configured`, adding the work done to `work`
+#[cfg(feature = "std")]
+pub fn detect_and_compute_configured_counted<V: SaeView + ?Sized>(config: &DetectorConfig, sae_pol: &V, occupancy: Option<&SaeOccupancy>,
+                                                                  evt: &SaeEvent, work: &mut DetectorWork) -> Option<SaeEvent> {
+    if !configured_point(config, sae_pol, evt, work) {
+        return None;
     }
+    let inner = configured_arc(config, &config.inner, sae_pol, occupancy, evt, work)?;
+    let outer = configured_arc(config, &config.outer, sae_pol, occupancy, evt, work)?;
+
+    let (norm_descriptor, binary_descriptor) = if config.descriptor || config.binary_descriptor {
+        let c3_vals = resample_ring(&inner.vals, inner.freshest, DESCRIPTOR_C3_LEN);
+        let c4_vals = resample_ring(&outer.vals, outer.freshest, DESCRIPTOR_C4_LEN);
+        work.descriptors += 1;
+        (config.descriptor.then(|| Box::new(normalized_ring_descriptor(&c3_vals, 0, &c4_vals, 0))),
+         config.binary_descriptor.then(|| binary_ring_descriptor(&c3_vals, 0, &c4_vals, 0)))
+    } else {
+        (None, None)
+    };
+    // dead pixels count against support, as unobserved ones do
+    let confidence = (
+        ring_confidence(&inner.vals, inner.segment.0, config.inner.min_arc_len(), config.inner.max_arc_len(),
+                        inner.observed as f32 / config.inner.len() as f32) +
+        ring_confidence(&outer.vals, outer.segment.0, config.outer.min_arc_len(), config.outer.max_arc_len(),
+                        outer.observed as f32 / config.outer.len() as f32)
+    ) / 2.0;
+    let orientation = corner_orientation(
+        arc_bisector(&inner.offsets, inner.freshest, inner.segment.1, inner.segment.2),
+        arc_bisector(&outer.offsets, outer.freshest, outer.segment.1, outer.segment.2));
+    let corner_kind = corner_kind(inner.segment.0, config.inner.max_arc_len(), outer.segment.0, config.outer.max_arc_len());
+    Some(SaeEvent { norm_descriptor, binary_descriptor, confidence: Some(confidence), orientation, corner_kind, ..evt.clone() })
 }
 
 
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
 
     use super::*;
@@ -647,6 +1534,39 @@ mod tests {
         [0, 0, 0, 0, 0, 0, 0, 0, 0 ]
     ];
 
+    /// every test SAE
+    const ALL_ARRAYS: [&StaticSaeArray; 29] = [
+        &SAE_ALL_RAYS,
+        &SAE_BLANK,
+        &SAE_OUTSIDE_CORNER_NE,
+        &SAE_OUTSIDE_CORNER_SE,
+        &SAE_OUTSIDE_CORNER_SW,
+        &SAE_OUTSIDE_CORNER_NW,
+        &SAE_OUTSIDE_CORNER_SSE,
+        &SAE_INSIDE_CORNER_NE,
+        &SAE_INSIDE_CORNER_NW,
+        &SAE_INSIDE_CORNER_SE,
+        &SAE_INSIDE_CORNER_SW,
+        &SAE_INSIDE_CORNER_N,
+        &SAE_INSIDE_CORNER_S,
+        &SAE_INSIDE_CORNER_E,
+        &SAE_INSIDE_CORNER_W,
+        &SAE_OUTSIDE_CORNER_N,
+        &SAE_OUTSIDE_CORNER_S,
+        &SAE_OUTSIDE_CORNER_E,
+        &SAE_OUTSIDE_CORNER_W,
+        &SAE_BAR_VERT_THICK,
+        &SAE_BAR_VERT_THIN,
+        &SAE_CENTER_BAR_VERT_THICK,
+        &SAE_CENTER_BAR_VERT_THIN,
+        &SAE_DIAG_BAR_VERT_THIN,
+        &SAE_DIAG_BAR_NE_THIN,
+        &SAE_BAR_HORIZ_THIN,
+        &SAE_BAR_HORIZ_THICK,
+        &SAE_CENTER_BAR_HORIZ_THIN,
+        &SAE_CENTER_BAR_HORIZ_THICK,
+    ];
+
     fn generate_test_event() -> SaeEvent {
         SaeEvent {
             row: 4,
@@ -654,6 +1574,7 @@ mod tests {
             polarity: 0,
             timestamp: 0,
             norm_descriptor: Some(Box::new([666.0f32; NORM_DESCRIPTOR_LEN])),
+            ..SaeEvent::default()
         }
     }
 
@@ -669,6 +1590,14 @@ mod tests {
         sae_pol
     }
 
+    /// whether the event is a corner of the standard detector, taking its corner fields if so
+    fn arcstar_is_event_corner(sae_pol: &SaeMatrix, evt: &mut SaeEvent) -> bool {
+        match detect_and_compute_one(sae_pol, evt) {
+            Some(corner) => { *evt = corner; true }
+            None => false,
+        }
+    }
+
 
     #[test]
     fn test_is_event_corner_blank() {
@@ -839,6 +1768,207 @@ mod tests {
         assert_eq!(false, arcstar_is_event_corner(&sae_pol, &mut evt));
     }
 
+    #[test]
+    fn test_corner_orientation() {
+        use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+        let config = DetectorConfig::default();
+        let evt = generate_test_event();
+        let cases: [(&StaticSaeArray, f32); 3] = [
+            (&SAE_OUTSIDE_CORNER_S, FRAC_PI_2),
+            (&SAE_OUTSIDE_CORNER_W, PI),
+            // the freshest arc of an inside corner is the long one, facing away from the corner
+            (&SAE_INSIDE_CORNER_SW, -FRAC_PI_4),
+        ];
+        for (input, expected) in cases.iter() {
+            let sae_pol = init_matrix_from_static_sae_array(input);
+            let corner = detect_and_compute_one(&sae_pol, &evt).unwrap();
+            let orientation = corner.orientation.unwrap();
+            let diff = (orientation - expected).sin().atan2((orientation - expected).cos());
+            assert!(diff.abs() < 1e-3, "orientation {} expected {}", orientation, expected);
+            let configured = detect_and_compute_configured(&config, &sae_pol, None, &evt).unwrap();
+            assert_eq!(configured.orientation, corner.orientation);
+        }
+
+        // the freshest timestamps of this corner lie toward the east edge of the NE quadrant
+        let sae_pol = init_matrix_from_static_sae_array(&SAE_OUTSIDE_CORNER_NE);
+        let orientation = detect_and_compute_one(&sae_pol, &evt).unwrap().orientation.unwrap();
+        assert!(orientation > -FRAC_PI_2 && orientation < 0.0);
+    }
+
+    #[test]
+    fn test_corner_kind() {
+        let config = DetectorConfig::default();
+        let evt = generate_test_event();
+        let outside = [
+            &SAE_OUTSIDE_CORNER_NE, &SAE_OUTSIDE_CORNER_SE, &SAE_OUTSIDE_CORNER_SW, &SAE_OUTSIDE_CORNER_NW,
+            &SAE_OUTSIDE_CORNER_N, &SAE_OUTSIDE_CORNER_S, &SAE_OUTSIDE_CORNER_E, &SAE_OUTSIDE_CORNER_W,
+        ];
+        let inside = [
+            &SAE_INSIDE_CORNER_NE, &SAE_INSIDE_CORNER_NW, &SAE_INSIDE_CORNER_SE, &SAE_INSIDE_CORNER_SW,
+            &SAE_INSIDE_CORNER_N, &SAE_INSIDE_CORNER_S, &SAE_INSIDE_CORNER_E, &SAE_INSIDE_CORNER_W,
+        ];
+        for (inputs, kind) in [(&outside, CornerKind::Outside), (&inside, CornerKind::Inside)].iter() {
+            for input in inputs.iter() {
+                let sae_pol = init_matrix_from_static_sae_array(input);
+                let corner = detect_and_compute_one(&sae_pol, &evt).unwrap();
+                assert_eq!(corner.corner_kind, Some(*kind));
+                let configured = detect_and_compute_configured(&config, &sae_pol, None, &evt).unwrap();
+                assert_eq!(configured.corner_kind, corner.corner_kind);
+            }
+        }
+    }
+
+    #[test]
+    fn test_detect_observed() {
+        let sae_pol = init_matrix_from_static_sae_array(&SAE_OUTSIDE_CORNER_NE);
+        let evt = generate_test_event();
+        let mut occupancy = sae_pol.map(|val| val > 0);
+        let corner = detect_and_compute_one_observed(&sae_pol, &occupancy, &evt).unwrap();
+        // unobserved ring pixels lower the confidence, through the local support
+        let unmasked = detect_and_compute_one(&sae_pol, &evt).unwrap();
+        assert!(corner.confidence > Some(0.0) && corner.confidence < unmasked.confidence);
+
+        // the same timestamps, but with too few pixels actually observed
+        occupancy.fill(false);
+        occupancy[(4, 4)] = true;
+        occupancy[(1, 4)] = true;
+        assert!(detect_and_compute_one_observed(&sae_pol, &occupancy, &evt).is_none());
+    }
+
+    #[test]
+    fn test_detect_observed_ignores_unobserved_values() {
+        let sae_pol = init_matrix_from_static_sae_array(&SAE_OUTSIDE_CORNER_NE);
+        let evt = generate_test_event();
+        let occupancy = sae_pol.map(|val| val > 0);
+        let expected = detect_and_compute_one_observed(&sae_pol, &occupancy, &evt).unwrap();
+        // leftovers fresher than the corner in the unobserved pixels, eg from before a reset
+        let stale = sae_pol.map(|val| if val > 0 { val } else { 200 });
+        assert_ne!(detect_and_compute_one(&stale, &evt).map(|corner| corner.norm_descriptor), Some(expected.norm_descriptor.clone()));
+
+        // unobserved pixels are expanded over and described as the oldest, whatever they hold
+        let corner = detect_and_compute_one_observed(&stale, &occupancy, &evt).unwrap();
+        assert!(corner.descriptor_approx_eq(&expected, 0.0));
+        assert_eq!((corner.confidence, corner.orientation, corner.corner_kind),
+                   (expected.confidence, expected.orientation, expected.corner_kind));
+        let quick = detect_quick_observed(&stale, &occupancy, &evt);
+        assert_eq!(quick.is_some(), detect_quick_observed(&sae_pol, &occupancy, &evt).is_some());
+
+        let config = DetectorConfig::default();
+        let configured = detect_and_compute_configured(&config, &stale, Some(&occupancy), &evt).unwrap();
+        let reference = detect_and_compute_configured(&config, &sae_pol, Some(&occupancy), &evt).unwrap();
+        assert!(configured.descriptor_approx_eq(&reference, 0.0));
+    }
+
+    #[test]
+    fn test_default_config_matches_standard_detector() {
+        let config = DetectorConfig::default();
+        let evt = generate_test_event();
+        for input in ALL_ARRAYS.iter() {
+            let sae_pol = init_matrix_from_static_sae_array(input);
+            let standard = detect_and_compute_one(&sae_pol, &evt);
+            let configured = detect_and_compute_configured(&config, &sae_pol, None, &evt);
+            assert_eq!(standard.is_some(), configured.is_some());
+            if let (Some(standard), Some(configured)) = (standard, configured) {
+                assert_eq!(standard.norm_descriptor, configured.norm_descriptor);
+                assert_eq!(standard.confidence, configured.confidence);
+                assert!(standard.confidence.is_some_and(|confidence| confidence > 0.0 && confidence <= 1.0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_static_detector_matches_standard_detector() {
+        let detector = StandardStaticDetector::new();
+        let decision_only = StaticDetector::<StaticRing<3, 3, 6>, StaticRing<4, 4, 8>, false>::new();
+        let evt = generate_test_event();
+        for input in ALL_ARRAYS.iter() {
+            let sae_pol = init_matrix_from_static_sae_array(input);
+            let standard = detect_and_compute_one(&sae_pol, &evt);
+            let fixed = detector.detect(&sae_pol, &evt);
+            assert_eq!(standard.is_some(), fixed.is_some());
+            assert_eq!(standard.is_some(), decision_only.detect(&sae_pol, &evt).is_some());
+            if let (Some(standard), Some(fixed)) = (standard, fixed) {
+                assert_eq!(standard.norm_descriptor, fixed.norm_descriptor);
+                assert_eq!((standard.confidence, standard.orientation, standard.corner_kind),
+                           (fixed.confidence, fixed.orientation, fixed.corner_kind));
+            }
+        }
+        // the outer ring alone decides with both rings radius 4
+        let sae_pol = init_matrix_from_static_sae_array(&SAE_OUTSIDE_CORNER_NE);
+        let outer_only = StaticDetector::<StaticRing<4, 4, 8>, StaticRing<4, 4, 8>, false>::new();
+        assert!(outer_only.detect(&sae_pol, &evt).is_some());
+        assert_eq!(outer_only.detect(&sae_pol, &evt).unwrap().norm_descriptor, evt.norm_descriptor);
+    }
+
+    #[test]
+    fn test_configured_dead_pixels() {
+        let sae_pol = init_matrix_from_static_sae_array(&SAE_OUTSIDE_CORNER_NE);
+        let evt = generate_test_event();
+        // masking off every pixel of the rings leaves nothing to form an arc
+        let dead = SaeOccupancy::from_element(9, 9, true);
+        let config = DetectorConfig::default().with_dead_pixels(dead);
+        assert!(detect_and_compute_configured(&config, &sae_pol, None, &evt).is_none());
+        // nor is an event at a dead pixel a corner
+        let mut dead = SaeOccupancy::from_element(9, 9, false);
+        dead[(evt.row as usize, evt.col as usize)] = true;
+        assert!(detect_and_compute_configured(&DetectorConfig::default(), &sae_pol, None, &evt).is_some());
+        assert!(detect_and_compute_configured(&DetectorConfig::default().with_dead_pixels(dead), &sae_pol, None, &evt).is_none());
+
+        // unit scales give the standard rings; stretched rings reach further along one axis
+        assert_eq!(DetectorConfig::anisotropic(1.0, 1.0), Some(DetectorConfig::default()));
+        let wide = DetectorConfig::for_pixel_aspect(0.5).unwrap();
+        assert!(wide.outer.offsets().iter().all(|off| off[0].abs() <= 3));
+        assert_eq!(wide.border_inset(), 6);
+
+        // a larger circle needs a wider border
+        let config = DetectorConfig::new(CircleSpec::c4(), CircleSpec::circle(5).unwrap());
+        assert_eq!(config.border_inset(), 5);
+        assert!(detect_and_compute_configured(&config, &sae_pol, None, &evt).is_none());
+    }
+
+    #[test]
+    fn test_configured_parameters() {
+        let sae_pol = init_matrix_from_static_sae_array(&SAE_OUTSIDE_CORNER_NE);
+        let evt = SaeEvent { timestamp: 100, ..generate_test_event() };
+        assert_eq!(DetectorConfig::with_arc_limits((3, 6), (4, 8)), Some(DetectorConfig::default()));
+        assert_eq!(DetectorConfig::with_arc_limits((3, 16), (4, 8)), None);
+
+        let config = DetectorConfig { descriptor: false, ..DetectorConfig::default() };
+        let corner = detect_and_compute_configured(&config, &sae_pol, None, &evt).unwrap();
+        assert!(corner.norm_descriptor.is_none());
+        assert!(corner.confidence > Some(0.0));
+
+        // only the event itself is recent enough to count
+        let config = DetectorConfig { max_age: Some(1), ..DetectorConfig::default() };
+        assert!(detect_and_compute_configured(&config, &sae_pol, None, &evt).is_none());
+        let config = DetectorConfig { max_age: Some(50), ..DetectorConfig::default() };
+        assert!(detect_and_compute_configured(&config, &sae_pol, None, &evt).is_some());
+
+        let config = DetectorConfig { min_border_inset: 5, ..DetectorConfig::default() };
+        assert_eq!(config.border_inset(), 5);
+        assert!(detect_and_compute_configured(&config, &sae_pol, None, &evt).is_none());
+    }
+
+    #[test]
+    fn test_binary_descriptor() {
+        let sae_pol = init_matrix_from_static_sae_array(&SAE_OUTSIDE_CORNER_NE);
+        let evt = SaeEvent { timestamp: 100, ..generate_test_event() };
+        assert!(BINARY_PAIRS.iter().all(|&(first, second)| first != second && (second as usize) < NORM_DESCRIPTOR_LEN));
+
+        let config = DetectorConfig { descriptor: false, binary_descriptor: true, ..DetectorConfig::default() };
+        let corner = detect_and_compute_configured(&config, &sae_pol, None, &evt).unwrap();
+        assert!(corner.norm_descriptor.is_none());
+        let binary = corner.binary_descriptor.unwrap();
+        assert_ne!(binary.0, 0);
+        assert_eq!(binary.hamming_distance(&binary), 0);
+        assert_eq!(binary.likeness(&BinaryDescriptor(!binary.0)), 0.0);
+        // the same shape later in time has the same bits
+        let later = sae_pol.map(|t| if t > 0 { t + 1_000 } else { 0 });
+        let shifted = detect_and_compute_configured(&config, &later, None, &SaeEvent { timestamp: 1_100, ..evt.clone() }).unwrap();
+        assert_eq!(shifted.binary_descriptor, Some(binary));
+        assert!(detect_and_compute_configured(&DetectorConfig::default(), &sae_pol, None, &evt).unwrap().binary_descriptor.is_none());
+    }
+
     #[test]
     fn test_is_event_corner_all_rays() {
         let sae_pol = init_matrix_from_static_sae_array(&SAE_ALL_RAYS);
@@ -846,5 +1976,31 @@ mod tests {
         assert_eq!(false, arcstar_is_event_corner(&sae_pol, &mut evt));
     }
 
+    #[test]
+    fn test_batch_matches_single() {
+        let mut sae_pol = SaeMatrix::zeros(40, 40);
+        for (offset, array) in [(0, &SAE_ALL_RAYS), (10, &SAE_OUTSIDE_CORNER_NE), (20, &SAE_BLANK)].iter() {
+            for row in 0..9 {
+                for col in 0..9 {
+                    sae_pol[(row + offset, col + offset)] = array[row][col];
+                }
+            }
+        }
+        let events: Vec<SaeEvent> = (0..3000u32)
+            .map(|idx| SaeEvent { row: (idx % 40) as u16, col: (idx / 40 % 40) as u16, polarity: (idx % 2) as u8,
+                timestamp: sae_pol[((idx % 40) as usize, (idx / 40 % 40) as usize)], ..SaeEvent::default() })
+            .collect();
+        let expected: Vec<SaeEvent> = events.iter().filter_map(|evt| detect_and_compute_one(&sae_pol, evt)).collect();
+        assert!(!expected.is_empty());
+        let corners = detect_and_compute_batch(&sae_pol, &events);
+        assert_eq!(corners, expected);
+        for (corner, single) in corners.iter().zip(expected.iter()) {
+            assert_eq!(corner.norm_descriptor, single.norm_descriptor);
+            assert_eq!((corner.confidence, corner.orientation, corner.corner_kind), (single.confidence, single.orientation, single.corner_kind));
+        }
+        #[cfg(feature = "rayon")]
+        assert_eq!(detect_and_compute_batch_par(&sae_pol, &events), expected);
+    }
+
 
 }
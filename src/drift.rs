@@ -0,0 +1,274 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Per-track descriptor aggregation and drift detection.
+//!
+//! A track matched by proximity alone can slide from one feature onto a neighboring
+//! one, after which its later observations describe a different point: poison for a
+//! map built from long tracks. `DriftDetector` keeps an aggregate of each track's
+//! descriptors, a running mean plus a few exemplars of the distinct appearances seen
+//! while the track was healthy, and flags a track once its new observations stop
+//! resembling any of them. Outlying observations are kept out of the aggregate, so a
+//! contaminated track can't drag its own reference along.
+
+use std::collections::HashMap;
+
+use crate::sae_types::*;
+use crate::track::{Track, TrackId, TrackObserver, TrackStore};
+
+
+/// Parameters of descriptor aggregation and drift detection
+#[derive(Clone, Debug, PartialEq)]
+pub struct DriftConfig {
+    /// weight of each new descriptor in the running mean
+    pub smoothing: f32,
+    /// maximum number of exemplar descriptors kept per track
+    pub max_exemplars: usize,
+    /// a descriptor less alike than this to every exemplar becomes a new exemplar
+    pub exemplar_likeness: f32,
+    /// an observation less alike than this to the mean and every exemplar is an outlier
+    pub drift_likeness: f32,
+    /// observations folded into the aggregate before outliers are looked for
+    pub warmup: u32,
+    /// consecutive outliers after which a track is flagged as drifted
+    pub patience: u32,
+}
+
+impl Default for DriftConfig {
+    fn default() -> Self {
+        DriftConfig {
+            smoothing: 0.1,
+            max_exemplars: 4,
+            exemplar_likeness: 0.9,
+            drift_likeness: 0.6,
+            warmup: 3,
+            patience: 2,
+        }
+    }
+}
+
+/// The aggregated descriptor of one track
+#[derive(Clone, Debug)]
+pub struct TrackDescriptor {
+    mean: NormDescriptor,
+    exemplars: Vec<NormDescriptor>,
+    observations: u32,
+    outliers_in_a_row: u32,
+    drifted_at: Option<SaeTime>,
+}
+
+impl TrackDescriptor {
+    pub fn new(first: &NormDescriptor) -> Self {
+        TrackDescriptor { mean: *first, exemplars: vec![*first], observations: 1, outliers_in_a_row: 0, drifted_at: None }
+    }
+
+    /// running mean of the descriptors folded in
+    pub fn mean(&self) -> &NormDescriptor {
+        &self.mean
+    }
+
+    /// the first distinct appearances of the track
+    pub fn exemplars(&self) -> &[NormDescriptor] {
+        &self.exemplars
+    }
+
+    /// number of descriptors folded into the aggregate
+    pub fn observations(&self) -> u32 {
+        self.observations
+    }
+
+    /// time of the observation at which the track was flagged as drifted
+    pub fn drifted_at(&self) -> Option<SaeTime> {
+        self.drifted_at
+    }
+
+    /// the largest likeness of `desc` to the mean or any exemplar
+    pub fn likeness(&self, desc: &NormDescriptor) -> f32 {
+        let weights = DescriptorWeights::uniform();
+        self.exemplars.iter()
+            .map(|exemplar| descriptor_likeness(exemplar, desc, &weights))
+            .fold(descriptor_likeness(&self.mean, desc, &weights), f32::max)
+    }
+
+    /// Judge a new observation, folding it in unless it is an outlier.
+    /// Returns its likeness, and whether this observation flagged the track as drifted.
+    fn observe(&mut self, desc: &NormDescriptor, timestamp: SaeTime, config: &DriftConfig) -> (f32, bool) {
+        let likeness = self.likeness(desc);
+        if self.observations >= config.warmup && likeness < config.drift_likeness {
+            self.outliers_in_a_row += 1;
+            let flagged = self.drifted_at.is_none() && self.outliers_in_a_row >= config.patience.max(1);
+            if flagged {
+                self.drifted_at = Some(timestamp);
+            }
+            return (likeness, flagged);
+        }
+
+        self.outliers_in_a_row = 0;
+        self.observations += 1;
+        for (mean, value) in self.mean.iter_mut().zip(desc.iter()) {
+            *mean += (value - *mean) * config.smoothing;
+        }
+        let weights = DescriptorWeights::uniform();
+        if self.exemplars.len() < config.max_exemplars &&
+            self.exemplars.iter().all(|exemplar| descriptor_likeness(exemplar, desc, &weights) < config.exemplar_likeness) {
+            self.exemplars.push(*desc);
+        }
+        (likeness, false)
+    }
+}
+
+/// A track flagged as drifted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DriftEvent {
+    pub track: TrackId,
+    /// time of the observation that flagged the track
+    pub timestamp: SaeTime,
+    /// likeness of that observation to the track's aggregate
+    pub likeness: f32,
+}
+
+/// Aggregates descriptors per track and flags tracks whose descriptors drift.
+/// Observations without descriptors are ignored.
+///
+/// As a `TrackObserver` it follows tracks as they are extended, and forgets them as they end.
+pub struct DriftDetector {
+    config: DriftConfig,
+    tracks: HashMap<TrackId, TrackDescriptor>,
+    events: Vec<DriftEvent>,
+}
+
+impl DriftDetector {
+    pub fn new(config: DriftConfig) -> Self {
+        DriftDetector { config, tracks: HashMap::new(), events: Vec::new() }
+    }
+
+    pub fn config(&self) -> &DriftConfig {
+        &self.config
+    }
+
+    /// Add an observation of `track`, returning a drift event if it flagged the track
+    pub fn observe(&mut self, track: TrackId, corner: &SaeEvent) -> Option<DriftEvent> {
+        let desc = corner.norm_descriptor.as_ref()?;
+        let state = match self.tracks.get_mut(&track) {
+            Some(state) => state,
+            None => {
+                self.tracks.insert(track, TrackDescriptor::new(desc));
+                return None;
+            }
+        };
+        let (likeness, flagged) = state.observe(desc, corner.timestamp, &self.config);
+        if !flagged {
+            return None;
+        }
+        let event = DriftEvent { track, timestamp: corner.timestamp, likeness };
+        self.events.push(event);
+        Some(event)
+    }
+
+    /// the aggregated descriptor of a track, if it has had a descriptor
+    pub fn track(&self, id: TrackId) -> Option<&TrackDescriptor> {
+        self.tracks.get(&id)
+    }
+
+    pub fn is_drifted(&self, id: TrackId) -> bool {
+        self.tracks.get(&id).is_some_and(|state| state.drifted_at.is_some())
+    }
+
+    /// Take the drift events since the last call, in the order they occurred
+    pub fn take_events(&mut self) -> Vec<DriftEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Forget a track, eg once it has been removed
+    pub fn remove(&mut self, id: TrackId) -> Option<TrackDescriptor> {
+        self.tracks.remove(&id)
+    }
+
+    /// Ids of the drifted tracks in `store`
+    pub fn check(&self, store: &TrackStore) -> Vec<TrackId> {
+        store.iter()
+            .filter(|track| self.is_drifted(track.id))
+            .map(|track| track.id)
+            .collect()
+    }
+
+    /// Like `check`, removing the drifted tracks from the store, and forgetting them
+    pub fn prune(&mut self, store: &mut TrackStore) -> Vec<TrackId> {
+        let flagged = self.check(store);
+        for id in flagged.iter() {
+            store.remove(*id);
+            self.tracks.remove(id);
+        }
+        flagged
+    }
+}
+
+impl TrackObserver for DriftDetector {
+    fn track_extended(&mut self, track: &Track) {
+        if self.tracks.contains_key(&track.id) {
+            self.observe(track.id, track.last());
+        } else {
+            for obs in track.observations.iter() {
+                self.observe(track.id, obs);
+            }
+        }
+    }
+
+    fn track_ended(&mut self, track: &Track) {
+        self.tracks.remove(&track.id);
+    }
+}
+
+/// The first drift of a complete track, replayed through a fresh aggregate
+pub fn track_drift(track: &Track, config: &DriftConfig) -> Option<DriftEvent> {
+    let mut detector = DriftDetector::new(config.clone());
+    track.observations.iter().find_map(|obs| detector.observe(track.id, obs))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a descriptor with the first `split` elements high and the rest low
+    fn descriptor(split: usize) -> NormDescriptor {
+        let mut desc = [0.1; NORM_DESCRIPTOR_LEN];
+        for value in desc[..split].iter_mut() {
+            *value = 1.0;
+        }
+        desc
+    }
+
+    fn corner(desc: NormDescriptor, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { norm_descriptor: Some(Box::new(desc)), timestamp, ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_drift_flags_contaminated_track() {
+        let config = DriftConfig::default();
+        let mut store = TrackStore::new();
+        let id = store.start_track(corner(descriptor(18), 0));
+        let mut detector = DriftDetector::new(config.clone());
+        // the feature's appearance varies, with a glitch, then the track slides onto another feature
+        let descriptors = [16, 12, 20, 0, 17, 0, 0, 18];
+        for (step, &split) in descriptors.iter().enumerate() {
+            store.extend_track_observed(id, corner(descriptor(split), (step as SaeTime + 1) * 100), &mut detector);
+        }
+        let events = detector.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].track, id);
+        assert_eq!(events[0].timestamp, 700);
+        assert!(events[0].likeness < config.drift_likeness);
+        let state = detector.track(id).unwrap();
+        // outliers are kept out of the aggregate
+        assert_eq!(state.observations(), 6);
+        assert_eq!(state.exemplars().len(), 2);
+        assert_eq!(track_drift(store.get(id).unwrap(), &config), Some(events[0]));
+
+        let other = store.start_track(corner(descriptor(18), 0));
+        assert!(!detector.is_drifted(other));
+        assert_eq!(detector.prune(&mut store), vec![id]);
+        assert_eq!(store.len(), 1);
+        assert!(detector.track(id).is_none());
+    }
+}
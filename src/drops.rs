@@ -0,0 +1,110 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Accounting for discarded events, so that data-quality problems are visible
+//! rather than silent. Stages that drop events report each one, with the reason,
+//! to a `DropObserver`: a `DropCounter`, or any closure taking the reason and event.
+
+use crate::sae_types::*;
+
+
+/// Why an event was discarded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DropReason {
+    /// the event lies outside the sensor
+    OutOfBounds,
+    /// a filter stage rejected the event
+    Filtered,
+    /// the event was shed as stale while processing was behind
+    Stale,
+    /// input bytes could not be decoded as events and were skipped
+    Corrupted,
+}
+
+impl DropReason {
+    pub const ALL: [DropReason; 4] = [
+        DropReason::OutOfBounds,
+        DropReason::Filtered,
+        DropReason::Stale,
+        DropReason::Corrupted,
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            DropReason::OutOfBounds => 0,
+            DropReason::Filtered => 1,
+            DropReason::Stale => 2,
+            DropReason::Corrupted => 3,
+        }
+    }
+}
+
+/// Notified of every discarded event
+pub trait DropObserver {
+    /// `evt` is None where no event could be decoded, eg for corrupted input
+    fn dropped(&mut self, reason: DropReason, evt: Option<&SaeEvent>);
+}
+
+impl<F: FnMut(DropReason, Option<&SaeEvent>)> DropObserver for F {
+    fn dropped(&mut self, reason: DropReason, evt: Option<&SaeEvent>) {
+        self(reason, evt)
+    }
+}
+
+/// Counts of discarded events by reason
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DropCounter {
+    counts: [u64; 4],
+}
+
+impl DropCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self, reason: DropReason) -> u64 {
+        self.counts[reason.index()]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// (reason, count) for every reason with at least one drop
+    pub fn nonzero(&self) -> impl Iterator<Item = (DropReason, u64)> + '_ {
+        DropReason::ALL.iter()
+            .map(move |&reason| (reason, self.count(reason)))
+            .filter(|&(_, count)| count > 0)
+    }
+}
+
+impl DropObserver for DropCounter {
+    fn dropped(&mut self, reason: DropReason, _evt: Option<&SaeEvent>) {
+        self.counts[reason.index()] += 1;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_and_callback() {
+        let mut counter = DropCounter::new();
+        counter.dropped(DropReason::Filtered, Some(&SaeEvent::new()));
+        counter.dropped(DropReason::Filtered, None);
+        counter.dropped(DropReason::Stale, None);
+        assert_eq!(counter.count(DropReason::Filtered), 2);
+        assert_eq!(counter.total(), 3);
+        let nonzero: Vec<(DropReason, u64)> = counter.nonzero().collect();
+        assert_eq!(nonzero, vec![(DropReason::Filtered, 2), (DropReason::Stale, 1)]);
+
+        let mut seen = Vec::new();
+        {
+            let mut log = |reason: DropReason, evt: Option<&SaeEvent>| seen.push((reason, evt.map(|e| e.timestamp)));
+            log.dropped(DropReason::OutOfBounds, Some(&SaeEvent { timestamp: 5, ..SaeEvent::default() }));
+        }
+        assert_eq!(seen, vec![(DropReason::OutOfBounds, Some(5))]);
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Separate ON and OFF surfaces, as the Arc* paper keeps them, with per-polarity
+//! statistics.
+//!
+//! `DualSae` runs a `detector::OnlineDetector`, which routes each event to the surface
+//! of its polarity and checks it for a corner there, and in addition counts the
+//! events and corners of each polarity and the time they span, giving event and
+//! corner rates per polarity. A strong imbalance between the two usually points to a
+//! biasing problem of the sensor, or to a scene lit by a flickering source. Both
+//! surfaces can be read for visualization, and saved as an `.npz` archive.
+
+#[cfg(feature = "io")]
+use std::io::{self, Write};
+
+use crate::detector::{CornerDetector, DetectorConfig, OnlineDetector};
+#[cfg(feature = "io")]
+use crate::io::npy::NpzWriter;
+use crate::sae_types::*;
+use crate::surface::{SaeSurface, WarmupConfig};
+
+
+/// Counts of the events and corners of one polarity
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PolarityStats {
+    pub events: u64,
+    pub corners: u64,
+    /// times of the first and the latest event
+    pub first: Option<SaeTime>,
+    pub last: Option<SaeTime>,
+}
+
+impl PolarityStats {
+    fn record(&mut self, evt: &SaeEvent, corner: bool) {
+        self.events += 1;
+        self.corners += corner as u64;
+        self.first.get_or_insert(evt.timestamp);
+        self.last = Some(self.last.map_or(evt.timestamp, |last| last.max(evt.timestamp)));
+    }
+
+    /// time spanned by the events, in microseconds
+    pub fn duration(&self) -> SaeTime {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => last.elapsed_since(first),
+            _ => 0,
+        }
+    }
+
+    /// events per second over the time spanned
+    pub fn event_rate(&self) -> f64 {
+        per_second(self.events, self.duration())
+    }
+
+    /// corners per second over the time spanned
+    pub fn corner_rate(&self) -> f64 {
+        per_second(self.corners, self.duration())
+    }
+
+    /// fraction of the events detected as corners
+    pub fn corner_fraction(&self) -> f64 {
+        if self.events == 0 { 0.0 } else { self.corners as f64 / self.events as f64 }
+    }
+}
+
+fn per_second(count: u64, duration: SaeTime) -> f64 {
+    if duration == 0 { 0.0 } else { count as f64 * 1e6 / duration as f64 }
+}
+
+/// OFF and ON surfaces, each checked for corners by the events of its polarity
+pub struct DualSae {
+    detector: OnlineDetector<DetectorConfig>,
+    /// OFF then ON
+    stats: [PolarityStats; 2],
+}
+
+impl DualSae {
+    pub fn new(nrows: usize, ncols: usize, warmup: WarmupConfig) -> Self {
+        DualSae {
+            detector: OnlineDetector::new(DetectorConfig::default(), nrows, ncols, warmup),
+            stats: Default::default(),
+        }
+    }
+
+    /// Detect corners on both surfaces with custom circle geometry
+    pub fn set_detector_config(&mut self, config: DetectorConfig) {
+        *self.detector.detector_mut() = config;
+    }
+
+    fn index(polarity: u8) -> usize {
+        (polarity > 0) as usize
+    }
+
+    /// Update the surface of the event's polarity, and check the event for a corner on it
+    pub fn process(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        let corner = self.detector.process(evt);
+        self.stats[Self::index(evt.polarity)].record(evt, corner.is_some());
+        corner
+    }
+
+    /// the surface of events of `polarity`
+    pub fn surface(&self, polarity: u8) -> &SaeSurface {
+        self.detector.surface(polarity)
+    }
+
+    /// the statistics of events of `polarity`
+    pub fn stats(&self, polarity: u8) -> &PolarityStats {
+        &self.stats[Self::index(polarity)]
+    }
+
+    /// events and corners of both polarities together
+    pub fn combined_stats(&self) -> PolarityStats {
+        let [off, on] = &self.stats;
+        PolarityStats {
+            events: off.events + on.events,
+            corners: off.corners + on.corners,
+            first: off.first.into_iter().chain(on.first).min(),
+            last: off.last.into_iter().chain(on.last).max(),
+        }
+    }
+
+    /// fraction of the events that were ON, 0.5 for a balanced stream
+    pub fn on_fraction(&self) -> f64 {
+        let total = self.combined_stats().events;
+        if total == 0 { 0.5 } else { self.stats[1].events as f64 / total as f64 }
+    }
+
+    /// Clear both surfaces and the statistics
+    pub fn reset(&mut self) {
+        self.detector.reset();
+        self.stats = Default::default();
+    }
+
+    /// Save both surfaces as an `.npz` archive of `off` and `on` arrays of `uint32`
+    /// timestamps, (rows, cols) in C order, and `off_observed` and `on_observed` flags
+    #[cfg(feature = "io")]
+    pub fn write_npz<W: Write>(&self, writer: W) -> io::Result<W> {
+        let mut npz = NpzWriter::new(writer);
+        for (polarity, name) in ["off", "on"].iter().enumerate() {
+            let surface = self.surface(polarity as u8);
+            let (nrows, ncols) = surface.shape();
+            let mut timestamps = Vec::with_capacity(nrows * ncols * 4);
+            let mut observed = Vec::with_capacity(nrows * ncols);
+            for row in 0..nrows {
+                for col in 0..ncols {
+                    timestamps.extend_from_slice(&surface.matrix()[(row, col)].to_le_bytes());
+                    observed.push(surface.is_observed(row, col) as u8);
+                }
+            }
+            npz.add_array(name, "'<u4'", &[nrows, ncols], &timestamps)?;
+            npz.add_array(&format!("{}_observed", name), "'|b1'", &[nrows, ncols], &observed)?;
+        }
+        npz.finish()
+    }
+}
+
+impl CornerDetector for DualSae {
+    fn process(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        DualSae::process(self, evt)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dual_surfaces_and_stats() {
+        let mut dual = DualSae::new(32, 32, WarmupConfig::disabled());
+        let corners: Vec<SaeEvent> = corner_block_events(10, 10, 1, 1_000).iter().filter_map(|evt| dual.process(evt)).collect();
+        assert_eq!(corners.last().map(|corner| corner.timestamp), Some(1_002));
+        // OFF events only touch the OFF surface
+        dual.process(&SaeEvent { row: 20, col: 20, polarity: 0, timestamp: 1_000, ..SaeEvent::default() });
+        dual.process(&SaeEvent { row: 20, col: 21, polarity: 0, timestamp: 1_001_000, ..SaeEvent::default() });
+        assert!(!dual.surface(1).is_observed(20, 20));
+        assert_eq!(dual.surface(0).matrix()[(20, 21)], 1_001_000);
+
+        let on = dual.stats(1);
+        assert_eq!((on.events, on.duration()), (26, 2));
+        assert!(on.corners >= 1);
+        assert_eq!(dual.stats(0).event_rate(), 2.0);
+        assert_eq!(dual.stats(0).corners, 0);
+        let combined = dual.combined_stats();
+        assert_eq!((combined.events, combined.first, combined.last), (28, Some(1_000), Some(1_001_000)));
+        assert!((dual.on_fraction() - 26.0 / 28.0).abs() < 1e-9);
+
+        dual.reset();
+        assert_eq!(dual.combined_stats(), PolarityStats::default());
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn test_write_npz() {
+        use crate::io::npy::NpzArchive;
+
+        let mut dual = DualSae::new(32, 32, WarmupConfig::disabled());
+        dual.process(&SaeEvent { row: 20, col: 20, polarity: 0, timestamp: 1_000, ..SaeEvent::default() });
+        let archive = NpzArchive::read_from(&dual.write_npz(Vec::new()).unwrap()[..]).unwrap();
+        let mut names: Vec<&str> = archive.names().collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["off", "off_observed", "on", "on_observed"]);
+    }
+}
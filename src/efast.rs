@@ -0,0 +1,130 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Rust implementation of the eFAST event corner detector described in:
+//! "Fast Event-based Corner Detection", E. Mueggler, C. Bartolozzi & D. Scaramuzza,
+//! BMVC 2017.
+//!
+//! eFAST samples the same two circles as Arc*, of radius 3 (16 pixels) and 4 (20 pixels).
+//! An event is a corner if, on both circles, some contiguous arc of pixels is newer
+//! than every other pixel of the circle: an arc of 3 to 6 pixels on the inner circle
+//! and of 4 to 8 on the outer one. Unlike Arc*, arcs longer than half the circle are
+//! not accepted, and the test is exhaustive over every arc rather than grown from the
+//! newest pixel. Both detectors implement `detector::SurfaceDetector`, so either
+//! can be run by a `detector::OnlineDetector`.
+
+use crate::circle::CircleSpec;
+use crate::detector::SurfaceDetector;
+use crate::sae_types::*;
+
+
+/// Arc length limits of eFAST, on each circle
+#[derive(Clone, Debug, PartialEq)]
+pub struct EfastConfig {
+    /// (shortest, longest) arc on the radius 3 circle
+    pub inner_arc: (usize, usize),
+    /// (shortest, longest) arc on the radius 4 circle
+    pub outer_arc: (usize, usize),
+}
+
+impl Default for EfastConfig {
+    fn default() -> Self {
+        EfastConfig { inner_arc: (3, 6), outer_arc: (4, 8) }
+    }
+}
+
+/// The eFAST detector
+#[derive(Clone, Debug)]
+pub struct EfastDetector {
+    config: EfastConfig,
+    inner: CircleSpec,
+    outer: CircleSpec,
+}
+
+impl Default for EfastDetector {
+    fn default() -> Self {
+        Self::new(EfastConfig::default())
+    }
+}
+
+/// Whether some contiguous arc of `vals`, of a length within `arc`, holds only
+/// values newer than every value outside it
+fn has_newest_arc(vals: &[SaeTime], arc: (usize, usize)) -> bool {
+    let n = vals.len();
+    let (shortest, longest) = (arc.0.max(1), arc.1.min(n.saturating_sub(1)));
+    for start in 0..n {
+        let mut arc_oldest = SaeTime::MAX;
+        for len in 1..=longest {
+            arc_oldest = arc_oldest.min(vals[(start + len - 1) % n]);
+            if len < shortest {
+                continue;
+            }
+            let rest_newest = (len..n).map(|idx| vals[(start + idx) % n]).max().unwrap_or(0);
+            if arc_oldest > rest_newest {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+impl EfastDetector {
+    pub fn new(config: EfastConfig) -> Self {
+        EfastDetector { config, inner: CircleSpec::c3(), outer: CircleSpec::c4() }
+    }
+
+    pub fn config(&self) -> &EfastConfig {
+        &self.config
+    }
+
+    fn ring_vals(ring: &CircleSpec, sae: &SaeMatrix, row: usize, col: usize) -> Vec<SaeTime> {
+        ring.offsets().iter()
+            .map(|item| sae[((item[0] + row as i32) as usize, (item[1] + col as i32) as usize)])
+            .collect()
+    }
+}
+
+impl SurfaceDetector for EfastDetector {
+    fn detect(&self, sae: &SaeMatrix, evt: &SaeEvent) -> Option<SaeEvent> {
+        let (row, col) = (evt.row as usize, evt.col as usize);
+        let inset = self.outer.reach();
+        let (nrows, ncols) = sae.shape();
+        if row < inset || col < inset || row + inset >= nrows || col + inset >= ncols {
+            return None;
+        }
+        if !has_newest_arc(&Self::ring_vals(&self.inner, sae, row, col), self.config.inner_arc) ||
+            !has_newest_arc(&Self::ring_vals(&self.outer, sae, row, col), self.config.outer_arc) {
+            return None;
+        }
+        Some(SaeEvent { norm_descriptor: None, ..evt.clone() })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detector::{CornerDetector, DetectorConfig, OnlineDetector};
+    use crate::surface::WarmupConfig;
+
+    #[test]
+    fn test_efast_and_arcstar_behind_one_trait() {
+        assert!(has_newest_arc(&[1, 9, 9, 9, 1, 1, 1, 1], (3, 4)));
+        assert!(!has_newest_arc(&[1, 9, 9, 9, 9, 9, 1, 1], (3, 4)));
+        assert!(!has_newest_arc(&[1, 9, 9, 1, 9, 1, 1, 1], (3, 4)));
+
+        // the tip of a block, newer than its surroundings: a corner for both detectors
+        let detectors: Vec<Box<dyn SurfaceDetector>> = vec![Box::new(EfastDetector::default()), Box::new(DetectorConfig::default())];
+        for detector in detectors {
+            let mut online = OnlineDetector::new(detector, 32, 32, WarmupConfig::disabled());
+            for row in 5..20 {
+                for col in 10..15 {
+                    online.process(&SaeEvent { row, col, timestamp: 7, ..SaeEvent::default() });
+                }
+            }
+            assert!(online.process(&SaeEvent { row: 19, col: 14, timestamp: 9, ..SaeEvent::default() }).is_some());
+            // the middle of its edge is not
+            assert!(online.process(&SaeEvent { row: 12, col: 14, timestamp: 9, ..SaeEvent::default() }).is_none());
+        }
+    }
+}
@@ -0,0 +1,382 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Two-view epipolar geometry over corner tracks.
+//! The fundamental matrix relating track positions at two times is estimated
+//! robustly by the normalized eight-point algorithm, and tracks whose positions
+//! violate the epipolar constraint are flagged as outliers: they are mismatched,
+//! drifting, or on independently moving objects. `EpipolarFilter` repeats this
+//! periodically over the active tracks as the stream advances.
+
+use nalgebra::{DMatrix, Matrix2, Matrix3, Rotation3, Unit, UnitQuaternion, Vector2, Vector3};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::calib::camera::CameraIntrinsics;
+use crate::calib::homography::normalizing_transform;
+use crate::sae_types::*;
+use crate::track::{TrackId, TrackStore};
+
+
+/// Relates corresponding image points x, x' in two views by x'^T F x = 0
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FundamentalMatrix {
+    pub matrix: Matrix3<f64>,
+}
+
+const EIGHT_POINT_SAMPLE: usize = 8;
+
+impl FundamentalMatrix {
+    /// Estimate from eight or more correspondences by the normalized eight-point algorithm
+    pub fn estimate(from: &[[f64; 2]], to: &[[f64; 2]]) -> Option<Self> {
+        if from.len() < EIGHT_POINT_SAMPLE || from.len() != to.len() {
+            return None;
+        }
+        let t_from = normalizing_transform(from);
+        let t_to = normalizing_transform(to);
+        let mut a = DMatrix::<f64>::zeros(from.len(), 9);
+        for (i, (p, q)) in from.iter().zip(to.iter()).enumerate() {
+            let p = t_from * Vector3::new(p[0], p[1], 1.0);
+            let q = t_to * Vector3::new(q[0], q[1], 1.0);
+            let row = [q.x * p.x, q.x * p.y, q.x, q.y * p.x, q.y * p.y, q.y, p.x, p.y, 1.0];
+            for (c, val) in row.iter().enumerate() {
+                a[(i, c)] = *val;
+            }
+        }
+        let eigen = (a.transpose() * &a).symmetric_eigen();
+        let (min_idx, _) = eigen.eigenvalues.iter().enumerate()
+            .min_by(|x, y| x.1.partial_cmp(y.1).unwrap())?;
+        let f = eigen.eigenvectors.column(min_idx);
+        let normalized = Matrix3::new(f[0], f[1], f[2], f[3], f[4], f[5], f[6], f[7], f[8]);
+
+        // enforce rank two
+        let svd = normalized.svd(true, true);
+        let mut singular = svd.singular_values;
+        let (min_sv, _) = singular.iter().enumerate()
+            .min_by(|x, y| x.1.partial_cmp(y.1).unwrap())?;
+        singular[min_sv] = 0.0;
+        let rank2 = svd.u? * Matrix3::from_diagonal(&singular) * svd.v_t?;
+
+        let matrix = t_to.transpose() * rank2 * t_from;
+        let norm = matrix.norm();
+        if norm < 1e-300 {
+            return None;
+        }
+        Some(FundamentalMatrix { matrix: matrix / norm })
+    }
+
+    /// First-order geometric distance (pixels) of a correspondence from the epipolar constraint
+    pub fn sampson_error(&self, from: [f64; 2], to: [f64; 2]) -> f64 {
+        let x = Vector3::new(from[0], from[1], 1.0);
+        let xp = Vector3::new(to[0], to[1], 1.0);
+        let fx = self.matrix * x;
+        let ftxp = self.matrix.transpose() * xp;
+        let num = xp.dot(&fx);
+        let denom = fx.x * fx.x + fx.y * fx.y + ftxp.x * ftxp.x + ftxp.y * ftxp.y;
+        if denom <= 0.0 {
+            return f64::INFINITY;
+        }
+        (num * num / denom).sqrt()
+    }
+
+    /// Robustly estimate from correspondences, some of which may be wrong.
+    /// Returns the fundamental matrix and the inlier indices.
+    pub fn estimate_ransac(from: &[[f64; 2]], to: &[[f64; 2]], threshold: f64, iterations: usize, seed: u64)
+        -> Option<(Self, Vec<usize>)>
+    {
+        if from.len() < EIGHT_POINT_SAMPLE || from.len() != to.len() {
+            return None;
+        }
+        let inliers_of = |f: &FundamentalMatrix| -> Vec<usize> {
+            (0..from.len()).filter(|&i| f.sampson_error(from[i], to[i]) <= threshold).collect()
+        };
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut best: Vec<usize> = Vec::new();
+        for _ in 0..iterations {
+            let sample = rand::seq::index::sample(&mut rng, from.len(), EIGHT_POINT_SAMPLE).into_vec();
+            let sample_from: Vec<[f64; 2]> = sample.iter().map(|&i| from[i]).collect();
+            let sample_to: Vec<[f64; 2]> = sample.iter().map(|&i| to[i]).collect();
+            if let Some(f) = FundamentalMatrix::estimate(&sample_from, &sample_to) {
+                let inliers = inliers_of(&f);
+                if inliers.len() > best.len() {
+                    best = inliers;
+                    if best.len() == from.len() {
+                        break;
+                    }
+                }
+            }
+        }
+        if best.len() < EIGHT_POINT_SAMPLE {
+            return None;
+        }
+        let inlier_from: Vec<[f64; 2]> = best.iter().map(|&i| from[i]).collect();
+        let inlier_to: Vec<[f64; 2]> = best.iter().map(|&i| to[i]).collect();
+        let f = FundamentalMatrix::estimate(&inlier_from, &inlier_to)?;
+        let inliers = inliers_of(&f);
+        Some((f, inliers))
+    }
+}
+
+/// Motion between two views of a calibrated camera: a point X1 in the first camera's
+/// frame is at X2 = rotation * X1 + translation in the second. Two views fix only the
+/// direction of the translation, so it has unit length.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RelativePose {
+    pub rotation: UnitQuaternion<f64>,
+    pub translation: Unit<Vector3<f64>>,
+}
+
+/// Depths of a correspondence of normalized points in both views, by least squares
+fn triangulate_depths(rotation: &Matrix3<f64>, translation: &Vector3<f64>, p1: &Vector3<f64>, p2: &Vector3<f64>) -> Option<(f64, f64)> {
+    // d1 * R p1 + t = d2 * p2
+    let a = rotation * p1;
+    let ata = Matrix2::new(a.dot(&a), -a.dot(p2), -a.dot(p2), p2.dot(p2));
+    let atb = Vector2::new(-a.dot(translation), p2.dot(translation));
+    let d = ata.try_inverse()? * atb;
+    Some((d.x, d.y))
+}
+
+/// Estimate the relative pose of a calibrated camera from pixel correspondences between
+/// two views, some of which may be wrong. `threshold` is the maximum epipolar error in pixels.
+/// Returns the pose and the inlier indices.
+pub fn relative_pose(camera: &CameraIntrinsics, from: &[[f64; 2]], to: &[[f64; 2]], threshold: f64, iterations: usize, seed: u64)
+    -> Option<(RelativePose, Vec<usize>)>
+{
+    let normalize = |p: &[f64; 2]| {
+        let ray = camera.unproject(*p);
+        [ray.x, ray.y]
+    };
+    let rays_from: Vec<[f64; 2]> = from.iter().map(normalize).collect();
+    let rays_to: Vec<[f64; 2]> = to.iter().map(normalize).collect();
+    let focal = (camera.fx + camera.fy) / 2.0;
+    let (f, inliers) = FundamentalMatrix::estimate_ransac(&rays_from, &rays_to, threshold / focal, iterations, seed)?;
+
+    // in normalized coordinates F is the essential matrix, up to projection onto
+    // matrices with two equal singular values
+    let svd = f.matrix.svd(true, true);
+    let mut u = svd.u?;
+    let mut v_t = svd.v_t?;
+    if u.determinant() < 0.0 {
+        u = -u;
+    }
+    if v_t.determinant() < 0.0 {
+        v_t = -v_t;
+    }
+    // the null vector of E, ie the translation direction, is the column of U
+    // with the smallest singular value; order the columns so that it is last
+    let (min_sv, _) = svd.singular_values.iter().enumerate()
+        .min_by(|x, y| x.1.partial_cmp(y.1).unwrap())?;
+    let order = match min_sv {
+        0 => [1, 2, 0],
+        1 => [2, 0, 1],
+        _ => [0, 1, 2],
+    };
+    let u = Matrix3::from_columns(&[u.column(order[0]), u.column(order[1]), u.column(order[2])]);
+    let v_t = Matrix3::from_rows(&[v_t.row(order[0]), v_t.row(order[1]), v_t.row(order[2])]);
+
+    let w = Matrix3::new(0.0, -1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0);
+    let t = u.column(2).into_owned();
+    let candidates = [
+        (u * w * v_t, t),
+        (u * w * v_t, -t),
+        (u * w.transpose() * v_t, t),
+        (u * w.transpose() * v_t, -t),
+    ];
+    // the right decomposition puts the points in front of both cameras
+    let (rotation, translation) = candidates.iter()
+        .max_by_key(|(rotation, translation)| {
+            inliers.iter()
+                .filter(|&&i| {
+                    let p1 = Vector3::new(rays_from[i][0], rays_from[i][1], 1.0);
+                    let p2 = Vector3::new(rays_to[i][0], rays_to[i][1], 1.0);
+                    triangulate_depths(rotation, translation, &p1, &p2).is_some_and(|(d1, d2)| d1 > 0.0 && d2 > 0.0)
+                })
+                .count()
+        })?;
+    let rotation = UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(*rotation));
+    Some((RelativePose { rotation, translation: Unit::new_normalize(*translation) }, inliers))
+}
+
+/// Parameters of the rolling epipolar check
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpipolarConfig {
+    /// time between checks
+    pub interval: SaeTime,
+    /// time between the two views compared in each check; larger baselines
+    /// make the epipolar constraint more discriminating
+    pub baseline: SaeTime,
+    /// maximum Sampson error (pixels) for a track to be consistent
+    pub threshold: f64,
+    /// minimum number of tracks spanning the baseline for a check to run
+    pub min_tracks: usize,
+    /// random samples tried per estimate
+    pub iterations: usize,
+    pub seed: u64,
+}
+
+impl Default for EpipolarConfig {
+    fn default() -> Self {
+        EpipolarConfig {
+            interval: 10_000,
+            baseline: 20_000,
+            threshold: 1.0,
+            min_tracks: 12,
+            iterations: 200,
+            seed: 0,
+        }
+    }
+}
+
+/// Periodically checks active tracks for epipolar consistency
+pub struct EpipolarFilter {
+    config: EpipolarConfig,
+    last_check: Option<SaeTime>,
+    latest: Option<FundamentalMatrix>,
+    rejected: u64,
+}
+
+impl EpipolarFilter {
+    pub fn new(config: EpipolarConfig) -> Self {
+        EpipolarFilter {
+            config,
+            last_check: None,
+            latest: None,
+            rejected: 0,
+        }
+    }
+
+    /// the fundamental matrix from the most recent successful check
+    pub fn latest(&self) -> Option<&FundamentalMatrix> {
+        self.latest.as_ref()
+    }
+
+    /// total number of tracks flagged so far
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// If a check is due at time `now`, return the ids of tracks spanning the baseline
+    /// that violate the epipolar constraint between `now - baseline` and `now`.
+    /// Returns None if no check was due, or too few tracks span the baseline.
+    pub fn check(&mut self, store: &TrackStore, now: SaeTime) -> Option<Vec<TrackId>> {
+        if self.last_check.is_some_and(|last| now < last.after(self.config.interval)) {
+            return None;
+        }
+        let start = now.checked_sub(self.config.baseline)?;
+        self.last_check = Some(now);
+
+        let mut ids = Vec::new();
+        let mut from = Vec::new();
+        let mut to = Vec::new();
+        for track in store.iter() {
+            if let (Some(p0), Some(p1)) = (track.position_at(start), track.position_at(now)) {
+                ids.push(track.id);
+                from.push([p0[0] as f64, p0[1] as f64]);
+                to.push([p1[0] as f64, p1[1] as f64]);
+            }
+        }
+        if ids.len() < self.config.min_tracks.max(EIGHT_POINT_SAMPLE) {
+            return None;
+        }
+        let (f, inliers) = FundamentalMatrix::estimate_ransac(
+            &from, &to, self.config.threshold, self.config.iterations, self.config.seed)?;
+        self.latest = Some(f);
+        let flagged: Vec<TrackId> = ids.iter().enumerate()
+            .filter(|(idx, _)| !inliers.contains(idx))
+            .map(|(_, &id)| id)
+            .collect();
+        self.rejected += flagged.len() as u64;
+        Some(flagged)
+    }
+
+    /// Like `check`, removing the flagged tracks from the store
+    pub fn prune(&mut self, store: &mut TrackStore, now: SaeTime) -> Vec<TrackId> {
+        let flagged = self.check(store, now).unwrap_or_default();
+        for id in flagged.iter() {
+            store.remove(*id);
+        }
+        flagged
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calib::camera::CameraIntrinsics;
+    use nalgebra::{Isometry3, Point3};
+
+    fn corner_at(p: [f64; 2], timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row_f: Some(p[1] as f32), col_f: Some(p[0] as f32), timestamp, ..SaeEvent::default() }
+    }
+
+    /// tracks of a static scene seen by a camera moving right and turning slightly,
+    /// plus two tracks moving vertically on their own
+    fn scene_tracks() -> (TrackStore, Vec<TrackId>) {
+        let camera = CameraIntrinsics::pinhole(200.0, 200.0, 120.0, 90.0);
+        let mut store = TrackStore::new();
+        for k in 0..24 {
+            let f = k as f64;
+            let point = Point3::new((f * 0.9).sin() * 2.0, (f * 1.7).cos() * 1.5, 4.0 + (f * 0.5).sin() * 1.5);
+            let mut id = None;
+            for step in 0..=4u32 {
+                let s = step as f64 * 0.1;
+                let pose = Isometry3::new(Vector3::new(-s, 0.02 * s, 0.0), Vector3::new(0.0, 0.05 * s, 0.02 * s));
+                let pixel = camera.project_world(&pose, &point).unwrap();
+                let corner = corner_at(pixel, step * 5_000);
+                match id {
+                    None => id = Some(store.start_track(corner)),
+                    Some(id) => { store.extend_track(id, corner); }
+                }
+            }
+        }
+        let mut movers = Vec::new();
+        for &(x, y) in [(60.0, 40.0), (150.0, 120.0)].iter() {
+            let id = store.start_track(corner_at([x, y], 0));
+            for step in 1..=4u32 {
+                store.extend_track(id, corner_at([x, y + 3.0 * step as f64], step * 5_000));
+            }
+            movers.push(id);
+        }
+        (store, movers)
+    }
+
+    #[test]
+    fn test_relative_pose() {
+        let camera = CameraIntrinsics { k1: -0.05, ..CameraIntrinsics::pinhole(250.0, 250.0, 120.0, 90.0) };
+        let motion = Isometry3::new(Vector3::new(-0.3, 0.05, 0.1), Vector3::new(0.02, 0.1, -0.03));
+        let mut from = Vec::new();
+        let mut to = Vec::new();
+        for k in 0..30 {
+            let f = k as f64;
+            let point = Point3::new((f * 0.9).sin() * 2.0, (f * 1.7).cos() * 1.5, 5.0 + (f * 0.5).sin() * 2.0);
+            from.push(camera.project(&point).unwrap());
+            to.push(camera.project_world(&motion, &point).unwrap());
+        }
+        // a mismatch
+        to[7] = [to[7][0] + 10.0, to[7][1] - 20.0];
+
+        let (pose, inliers) = relative_pose(&camera, &from, &to, 0.5, 200, 1).unwrap();
+        assert_eq!(inliers.len(), 29);
+        assert!(!inliers.contains(&7));
+        assert!(pose.rotation.angle_to(&motion.rotation) < 1e-6);
+        let expected = motion.translation.vector.normalize();
+        assert!((pose.translation.into_inner() - expected).norm() < 1e-6);
+    }
+
+    #[test]
+    fn test_flags_inconsistent_tracks() {
+        let (mut store, movers) = scene_tracks();
+        let mut filter = EpipolarFilter::new(EpipolarConfig::default());
+        // not enough history yet
+        assert!(filter.check(&store, 10_000).is_none());
+
+        let flagged = filter.prune(&mut store, 20_000);
+        assert_eq!(flagged, movers);
+        assert_eq!(store.len(), 24);
+        assert_eq!(filter.rejected(), 2);
+        assert!(filter.latest().is_some());
+        // the next check is not due yet
+        assert!(filter.check(&store, 25_000).is_none());
+    }
+}
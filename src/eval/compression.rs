@@ -0,0 +1,88 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Accuracy of compressed descriptors relative to the full descriptors.
+
+use nalgebra::DVector;
+
+use crate::projection::DescriptorProjection;
+use crate::sae_types::*;
+
+
+/// How well a descriptor projection preserves matching
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompressionReport {
+    pub dims: usize,
+    pub explained_variance: f32,
+    /// mean L2 distance between descriptors and their reconstructions
+    pub mean_reconstruction_error: f32,
+    /// fraction of queries whose nearest candidate is the same in both spaces
+    pub match_agreement: f32,
+}
+
+fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+fn nearest<T, F: Fn(&T) -> f32>(candidates: &[T], dist: F) -> Option<usize> {
+    candidates.iter()
+        .map(dist)
+        .enumerate()
+        .filter(|(_, dist)| !dist.is_nan())
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(idx, _)| idx)
+}
+
+/// Compare nearest-neighbor matching of `queries` against `candidates`
+/// in the full descriptor space and in the projected space
+pub fn evaluate_projection(projection: &DescriptorProjection, queries: &[NormDescriptor], candidates: &[NormDescriptor]) -> CompressionReport {
+    let projected: Vec<DVector<f32>> = candidates.iter().map(|c| projection.project(c)).collect();
+
+    let mut agree = 0;
+    let mut reconstruction_error = 0.0;
+    for query in queries {
+        let reduced = projection.project(query);
+        let rebuilt = projection.reconstruct(&reduced);
+        reconstruction_error += squared_distance(query, &rebuilt).sqrt();
+
+        let full_match = nearest(candidates, |c| squared_distance(query, c));
+        let reduced_match = nearest(&projected, |c| squared_distance(reduced.as_slice(), c.as_slice()));
+        if full_match.is_some() && full_match == reduced_match {
+            agree += 1;
+        }
+    }
+
+    let nqueries = queries.len().max(1) as f32;
+    CompressionReport {
+        dims: projection.dims(),
+        explained_variance: projection.explained_variance(),
+        mean_reconstruction_error: reconstruction_error / nqueries,
+        match_agreement: (agree as f32) / nqueries,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate_projection() {
+        // descriptors on a line through descriptor space: one dimension suffices
+        let descriptors: Vec<NormDescriptor> = (0..20)
+            .map(|i| [i as f32 / 20.0; NORM_DESCRIPTOR_LEN])
+            .collect();
+        let projection = DescriptorProjection::train(&descriptors, 1).unwrap();
+        let queries: Vec<NormDescriptor> = descriptors.iter().step_by(3).cloned().collect();
+        let report = evaluate_projection(&projection, &queries, &descriptors);
+        assert_eq!(report.dims, 1);
+        assert!(report.mean_reconstruction_error < 1e-3);
+        assert_eq!(report.match_agreement, 1.0);
+
+        // a NaN candidate never matches, rather than panicking
+        let mut candidates = descriptors.clone();
+        candidates.insert(0, [f32::NAN; NORM_DESCRIPTOR_LEN]);
+        let report = evaluate_projection(&projection, &queries, &candidates);
+        assert_eq!(report.match_agreement, 1.0);
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Calibration of corner confidence to empirical precision.
+//!
+//! The detector's `confidence` ranks corners, but its values are not probabilities.
+//! Given corners labeled true or false, eg by `label_corners` against reference
+//! corners, `ConfidenceCalibration::fit` bins the confidences and fits a
+//! non-decreasing precision to the bins (isotonic regression by pool adjacent
+//! violators), so that a calibrated confidence reads as the fraction of
+//! corners at that confidence that were true.
+
+use crate::sae_types::*;
+
+
+/// Label each corner true if a reference corner lies within `radius` pixels
+/// and `max_dt` microseconds of it
+pub fn label_corners(corners: &[SaeEvent], reference: &[SaeEvent], radius: f32, max_dt: SaeTime) -> Vec<bool> {
+    let radius2 = radius * radius;
+    corners.iter()
+        .map(|corner| {
+            let (row, col) = corner.subpixel_position();
+            reference.iter().any(|truth| {
+                let (trow, tcol) = truth.subpixel_position();
+                corner.timestamp.max(truth.timestamp) - corner.timestamp.min(truth.timestamp) <= max_dt &&
+                    (trow - row).powi(2) + (tcol - col).powi(2) <= radius2
+            })
+        })
+        .collect()
+}
+
+/// A monotone mapping from confidence to precision
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfidenceCalibration {
+    /// upper confidence bound of each bin; the last is 1
+    bounds: Vec<f32>,
+    /// fitted precision of each bin, non-decreasing
+    precision: Vec<f32>,
+    /// number of labeled corners in each bin
+    counts: Vec<usize>,
+}
+
+impl ConfidenceCalibration {
+    /// Fit to `(confidence, is_true)` samples, in `bins` equal-width confidence bins.
+    /// Bins without samples take the precision of their neighbors.
+    /// Returns None if there are no samples.
+    pub fn fit(samples: &[(f32, bool)], bins: usize) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let bins = bins.max(1);
+        let mut trues = vec![0usize; bins];
+        let mut counts = vec![0usize; bins];
+        for &(confidence, is_true) in samples.iter() {
+            let bin = Self::bin_of(confidence, bins);
+            counts[bin] += 1;
+            trues[bin] += is_true as usize;
+        }
+
+        // pool adjacent violators over the non-empty bins: blocks of (trues, count, bins)
+        let mut blocks: Vec<(usize, usize, Vec<usize>)> = Vec::new();
+        for bin in (0..bins).filter(|&bin| counts[bin] > 0) {
+            blocks.push((trues[bin], counts[bin], vec![bin]));
+            while blocks.len() > 1 {
+                let (t1, n1, _) = blocks[blocks.len() - 1];
+                let (t0, n0, _) = blocks[blocks.len() - 2];
+                if (t0 as f64) / (n0 as f64) <= (t1 as f64) / (n1 as f64) {
+                    break;
+                }
+                let (t1, n1, members) = blocks.pop().unwrap();
+                let last = blocks.last_mut().unwrap();
+                last.0 += t1;
+                last.1 += n1;
+                last.2.extend(members);
+            }
+        }
+        let mut precision = vec![f32::NAN; bins];
+        for (t, n, members) in blocks.iter() {
+            for &bin in members.iter() {
+                precision[bin] = *t as f32 / *n as f32;
+            }
+        }
+        // empty bins: carry the precision of the bin below, or above for leading ones
+        let first = precision.iter().copied().find(|p| !p.is_nan()).unwrap_or(0.0);
+        let mut previous = first;
+        for p in precision.iter_mut() {
+            if p.is_nan() {
+                *p = previous;
+            }
+            previous = *p;
+        }
+
+        let bounds = (1..=bins).map(|i| i as f32 / bins as f32).collect();
+        Some(ConfidenceCalibration { bounds, precision, counts })
+    }
+
+    /// Fit to corners labeled by `label_corners`
+    pub fn fit_corners(corners: &[SaeEvent], labels: &[bool], bins: usize) -> Option<Self> {
+        let samples: Vec<(f32, bool)> = corners.iter().zip(labels.iter())
+            .map(|(corner, &label)| (corner.confidence.unwrap_or(0.0), label))
+            .collect();
+        Self::fit(&samples, bins)
+    }
+
+    fn bin_of(confidence: f32, bins: usize) -> usize {
+        ((confidence.clamp(0.0, 1.0) * bins as f32) as usize).min(bins - 1)
+    }
+
+    /// the expected precision of corners with this raw confidence
+    pub fn precision(&self, confidence: f32) -> f32 {
+        self.precision[Self::bin_of(confidence, self.bounds.len())]
+    }
+
+    /// Replace the corner's raw confidence with its calibrated precision
+    pub fn calibrate(&self, corner: &mut SaeEvent) {
+        corner.confidence = corner.confidence.map(|confidence| self.precision(confidence));
+    }
+
+    /// (upper confidence bound, fitted precision, sample count) of each bin
+    pub fn bins(&self) -> impl Iterator<Item = (f32, f32, usize)> + '_ {
+        self.bounds.iter().zip(self.precision.iter()).zip(self.counts.iter())
+            .map(|((&bound, &precision), &count)| (bound, precision, count))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_corners() {
+        let corner = |row, col, timestamp| SaeEvent { row, col, timestamp, ..SaeEvent::default() };
+        let reference = vec![corner(10, 10, 1_000)];
+        let detected = vec![corner(11, 10, 1_400), corner(10, 10, 3_000), corner(20, 20, 1_000)];
+        assert_eq!(label_corners(&detected, &reference, 1.5, 500), vec![true, false, false]);
+    }
+
+    #[test]
+    fn test_isotonic_fit() {
+        // precision 0.25 at low confidence, a violating dip at 0.5, then 0.75
+        let mut samples = Vec::new();
+        for i in 0..4 {
+            samples.push((0.1, i == 0));
+            samples.push((0.45, i < 2));
+            samples.push((0.55, i < 1));
+            samples.push((0.9, i < 3));
+        }
+        let calibration = ConfidenceCalibration::fit(&samples, 10).unwrap();
+        assert_eq!(calibration.precision(0.1), 0.25);
+        // the dip is pooled with its lower neighbor
+        assert_eq!(calibration.precision(0.45), 0.375);
+        assert_eq!(calibration.precision(0.55), 0.375);
+        // empty bins take the precision below them
+        assert_eq!(calibration.precision(0.7), 0.375);
+        assert_eq!(calibration.precision(0.95), 0.75);
+        assert_eq!(calibration.precision(0.0), 0.25);
+        let precisions: Vec<f32> = calibration.bins().map(|(_, precision, _)| precision).collect();
+        assert!(precisions.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(calibration.bins().map(|(_, _, count)| count).sum::<usize>(), 16);
+
+        let mut corner = SaeEvent { confidence: Some(0.92), ..SaeEvent::default() };
+        calibration.calibrate(&mut corner);
+        assert_eq!(corner.confidence, Some(0.75));
+        assert!(ConfidenceCalibration::fit(&[], 4).is_none());
+    }
+}
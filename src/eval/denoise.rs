@@ -0,0 +1,306 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Quantitative comparison of noise filters on recordings whose events are labeled
+//! as signal or noise, such as the DVS noise datasets (DND21).
+//!
+//! `LabeledRecording::read` loads the delimited text form those datasets are shipped
+//! in, one event per line: by default `timestamp, x, y, polarity, label` separated by
+//! commas or whitespace, with label 1 for signal. Column order, timestamp units and
+//! label values are set by `LabeledFormat`. `score_filter` runs a filter over a
+//! recording and scores it by the standard measures: the fractions of signal and
+//! noise passed, overall event retention, and the improvement of the signal to
+//! noise ratio. `NoiseBenchmark` scores a filter over a suite of recordings, as JSON.
+
+use std::io::{self, BufRead, Write};
+
+use crate::eval::json_string;
+use crate::filter::EventFilter;
+use crate::sae_types::*;
+
+
+/// An event with its ground-truth label
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabeledEvent {
+    pub event: SaeEvent,
+    /// true for a signal event, false for noise
+    pub signal: bool,
+}
+
+/// Layout of a labeled text recording: zero-based column index of each field
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabeledFormat {
+    pub timestamp_col: usize,
+    pub x_col: usize,
+    pub y_col: usize,
+    pub polarity_col: usize,
+    pub label_col: usize,
+    /// microseconds per timestamp unit, eg 1e6 for timestamps in seconds
+    pub timestamp_scale: f64,
+    /// the label value marking signal; any other value is noise
+    pub signal_label: i64,
+    /// sensor (rows, cols); None takes the extent of the events
+    pub sensor: Option<(u16, u16)>,
+}
+
+impl Default for LabeledFormat {
+    fn default() -> Self {
+        LabeledFormat {
+            timestamp_col: 0,
+            x_col: 1,
+            y_col: 2,
+            polarity_col: 3,
+            label_col: 4,
+            timestamp_scale: 1.0,
+            signal_label: 1,
+            sensor: None,
+        }
+    }
+}
+
+fn invalid(line: usize, what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("labeled events: invalid {} on line {}", what, line))
+}
+
+/// A labeled recording
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabeledRecording {
+    pub name: String,
+    pub nrows: u16,
+    pub ncols: u16,
+    pub events: Vec<LabeledEvent>,
+}
+
+impl LabeledRecording {
+    /// Read a recording in `format`. Blank lines, `#` comments and a non-numeric
+    /// header line are skipped; events are sorted by time, as filters expect.
+    pub fn read<R: BufRead>(name: &str, reader: R, format: &LabeledFormat) -> io::Result<Self> {
+        let mut events = Vec::new();
+        let (mut nrows, mut ncols) = (0u16, 0u16);
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|field| !field.is_empty())
+                .collect();
+            if events.is_empty() && fields.first().is_some_and(|field| field.parse::<f64>().is_err()) {
+                // column names
+                continue;
+            }
+            let field = |col: usize, what: &str| -> io::Result<f64> {
+                fields.get(col).and_then(|field| field.parse::<f64>().ok()).ok_or_else(|| invalid(idx + 1, what))
+            };
+            let timestamp = field(format.timestamp_col, "timestamp")? * format.timestamp_scale;
+            let (x, y) = (field(format.x_col, "x")?, field(format.y_col, "y")?);
+            if !(0.0..=SaeTime::MAX as f64).contains(&timestamp) ||
+                !(0.0..u16::MAX as f64).contains(&x) || !(0.0..u16::MAX as f64).contains(&y) {
+                return Err(invalid(idx + 1, "event"));
+            }
+            let event = SaeEvent {
+                row: y as u16,
+                col: x as u16,
+                polarity: (field(format.polarity_col, "polarity")? > 0.0) as u8,
+                timestamp: timestamp.round() as SaeTime,
+                ..SaeEvent::default()
+            };
+            nrows = nrows.max(event.row + 1);
+            ncols = ncols.max(event.col + 1);
+            let signal = field(format.label_col, "label")? as i64 == format.signal_label;
+            events.push(LabeledEvent { event, signal });
+        }
+        events.sort_by_key(|labeled| labeled.event.timestamp);
+        let (nrows, ncols) = format.sensor.unwrap_or((nrows, ncols));
+        Ok(LabeledRecording { name: name.to_string(), nrows, ncols, events })
+    }
+
+    /// (signal, noise) event counts
+    pub fn label_counts(&self) -> (u64, u64) {
+        let signal = self.events.iter().filter(|labeled| labeled.signal).count() as u64;
+        (signal, self.events.len() as u64 - signal)
+    }
+}
+
+/// How a filter treated the signal and noise of a recording
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DenoiseScore {
+    pub signal_in: u64,
+    pub noise_in: u64,
+    pub signal_kept: u64,
+    pub noise_kept: u64,
+}
+
+fn ratio(num: u64, den: u64) -> f64 {
+    if den > 0 { num as f64 / den as f64 } else { 0.0 }
+}
+
+/// a signal to noise ratio in decibels, infinite without noise
+fn snr_db(signal: u64, noise: u64) -> f64 {
+    10.0 * (signal as f64 / noise as f64).log10()
+}
+
+impl DenoiseScore {
+    /// fraction of all events kept
+    pub fn retention(&self) -> f64 {
+        ratio(self.signal_kept + self.noise_kept, self.signal_in + self.noise_in)
+    }
+
+    /// fraction of signal events kept: the true positive rate
+    pub fn signal_retention(&self) -> f64 {
+        ratio(self.signal_kept, self.signal_in)
+    }
+
+    /// fraction of noise events kept: the false positive rate
+    pub fn noise_pass_rate(&self) -> f64 {
+        ratio(self.noise_kept, self.noise_in)
+    }
+
+    /// signal to noise ratio of the input, in decibels
+    pub fn snr_in_db(&self) -> f64 {
+        snr_db(self.signal_in, self.noise_in)
+    }
+
+    /// signal to noise ratio of the filtered events, in decibels
+    pub fn snr_out_db(&self) -> f64 {
+        snr_db(self.signal_kept, self.noise_kept)
+    }
+
+    /// how much the filter raised the signal to noise ratio, in decibels
+    pub fn snr_improvement_db(&self) -> f64 {
+        self.snr_out_db() - self.snr_in_db()
+    }
+
+    /// add the counts of `other`
+    pub fn add(&mut self, other: &DenoiseScore) {
+        self.signal_in += other.signal_in;
+        self.noise_in += other.noise_in;
+        self.signal_kept += other.signal_kept;
+        self.noise_kept += other.noise_kept;
+    }
+
+    pub fn to_json(&self) -> String {
+        // JSON has no infinity: a ratio without noise is written as null
+        let db = |value: f64| if value.is_finite() { value.to_string() } else { "null".to_string() };
+        format!("{{\"signal_in\":{},\"noise_in\":{},\"signal_kept\":{},\"noise_kept\":{},\"retention\":{},\
+                 \"signal_retention\":{},\"noise_pass_rate\":{},\"snr_in_db\":{},\"snr_out_db\":{},\"snr_improvement_db\":{}}}",
+                self.signal_in, self.noise_in, self.signal_kept, self.noise_kept, self.retention(),
+                self.signal_retention(), self.noise_pass_rate(), db(self.snr_in_db()), db(self.snr_out_db()),
+                db(self.snr_improvement_db()))
+    }
+}
+
+/// Run `filter` over the events of a recording, in order, and score it
+pub fn score_filter<F: EventFilter + ?Sized>(filter: &mut F, events: &[LabeledEvent]) -> DenoiseScore {
+    let mut score = DenoiseScore::default();
+    for labeled in events {
+        let kept = filter.accept(&labeled.event);
+        if labeled.signal {
+            score.signal_in += 1;
+            score.signal_kept += kept as u64;
+        } else {
+            score.noise_in += 1;
+            score.noise_kept += kept as u64;
+        }
+    }
+    score
+}
+
+/// A suite of labeled recordings to compare filters on
+#[derive(Default)]
+pub struct NoiseBenchmark {
+    recordings: Vec<LabeledRecording>,
+}
+
+/// The scores of one filter over a suite
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchmarkResult {
+    pub filter: String,
+    /// (recording name, score) in suite order
+    pub recordings: Vec<(String, DenoiseScore)>,
+    /// counts pooled over every recording
+    pub total: DenoiseScore,
+}
+
+impl NoiseBenchmark {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_recording(&mut self, recording: LabeledRecording) {
+        self.recordings.push(recording);
+    }
+
+    pub fn recordings(&self) -> &[LabeledRecording] {
+        &self.recordings
+    }
+
+    /// Score a filter on every recording, each with a fresh filter made for it
+    pub fn run<F, M>(&self, filter: &str, mut make_filter: M) -> BenchmarkResult
+        where F: EventFilter, M: FnMut(&LabeledRecording) -> F
+    {
+        let mut total = DenoiseScore::default();
+        let recordings = self.recordings.iter()
+            .map(|recording| {
+                let score = score_filter(&mut make_filter(recording), &recording.events);
+                total.add(&score);
+                (recording.name.clone(), score)
+            })
+            .collect();
+        BenchmarkResult { filter: filter.to_string(), recordings, total }
+    }
+}
+
+impl BenchmarkResult {
+    pub fn to_json(&self) -> String {
+        let recordings: Vec<String> = self.recordings.iter()
+            .map(|(name, score)| format!("{{\"name\":{},\"score\":{}}}", json_string(name), score.to_json()))
+            .collect();
+        format!("{{\"filter\":{},\"recordings\":[{}],\"total\":{}}}",
+                json_string(&self.filter), recordings.join(","), self.total.to_json())
+    }
+
+    pub fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.to_json().as_bytes())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::noise::RowColumnDenoiser;
+
+    #[test]
+    fn test_benchmark_scores_denoiser() {
+        // a dense moving edge as signal, and isolated noise events far from it
+        let mut text = String::from("t,x,y,p,label\n# edge\n");
+        for step in 0..20u32 {
+            for y in 10..20 {
+                text.push_str(&format!("{} {} {} 1 1\n", 1_000 + step * 100, 5 + step, y));
+            }
+        }
+        for idx in 0..20u32 {
+            text.push_str(&format!("{},{},{},0,0\n", 1_050 + idx * 100, 35 + (idx * 7) % 10, (idx * 13) % 30));
+        }
+        let format = LabeledFormat { sensor: Some((32, 48)), ..LabeledFormat::default() };
+        let recording = LabeledRecording::read("edge", text.as_bytes(), &format).unwrap();
+        assert_eq!(recording.label_counts(), (200, 20));
+        assert_eq!((recording.nrows, recording.ncols), (32, 48));
+        assert!(recording.events.windows(2).all(|pair| pair[0].event.timestamp <= pair[1].event.timestamp));
+
+        let mut benchmark = NoiseBenchmark::new();
+        benchmark.add_recording(recording);
+        let result = benchmark.run("row_column",
+            |recording| RowColumnDenoiser::new(recording.nrows as usize, recording.ncols as usize, 500));
+        let score = result.total;
+        assert!(score.signal_retention() > 0.9);
+        assert!(score.noise_pass_rate() < 0.5);
+        assert!(score.snr_improvement_db() > 3.0);
+        assert!((score.snr_in_db() - 10.0).abs() < 1e-9);
+        assert!(result.to_json().starts_with("{\"filter\":\"row_column\",\"recordings\":[{\"name\":\"edge\",\"score\":{\"signal_in\":200,"));
+
+        assert!(LabeledRecording::read("bad", "1,2,3,1,1\n1,2\n".as_bytes(), &format).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Differences between two corner streams, eg the output before and after a code change.
+//!
+//! Bit-exact comparison of detector output fails on any harmless change, such as a
+//! refinement moving sub-pixel positions by a fraction of a pixel. `diff_corners`
+//! instead matches corners within a spatiotemporal tolerance, and reports which
+//! corners are unchanged, which moved or shifted in time, and which were added
+//! or removed, so regression tests can bound the change rather than forbid it.
+
+use std::io::{self, Write};
+
+use crate::sae_types::*;
+
+
+/// Tolerances for matching corners between the streams
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiffConfig {
+    /// maximum distance (pixels) between matched corners
+    pub radius: f32,
+    /// maximum time between matched corners
+    pub max_dt: SaeTime,
+    /// matched corners no further apart than this, at the same time, are unchanged
+    pub position_tolerance: f32,
+    /// whether matched corners must have the same polarity
+    pub match_polarity: bool,
+}
+
+impl Default for DiffConfig {
+    fn default() -> Self {
+        DiffConfig {
+            radius: 2.0,
+            max_dt: 1_000,
+            position_tolerance: 1e-3,
+            match_polarity: true,
+        }
+    }
+}
+
+/// A corner that differs between the streams
+#[derive(Clone, Debug, PartialEq)]
+pub enum CornerChange {
+    /// matched, but at a different position or time
+    Moved { baseline: SaeEvent, candidate: SaeEvent },
+    /// only in the baseline stream
+    Removed(SaeEvent),
+    /// only in the candidate stream
+    Added(SaeEvent),
+}
+
+impl CornerChange {
+    /// time of the change: that of the baseline corner, where there is one
+    pub fn timestamp(&self) -> SaeTime {
+        match self {
+            CornerChange::Moved { baseline, .. } => baseline.timestamp,
+            CornerChange::Removed(corner) | CornerChange::Added(corner) => corner.timestamp,
+        }
+    }
+}
+
+/// Numbers of corners in each category of a diff
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiffCounts {
+    pub unchanged: usize,
+    pub moved: usize,
+    pub removed: usize,
+    pub added: usize,
+}
+
+impl DiffCounts {
+    /// fraction of the baseline corners moved or removed, plus the added corners
+    /// relative to the baseline; zero for two empty streams
+    pub fn changed_fraction(&self) -> f32 {
+        let baseline = self.unchanged + self.moved + self.removed;
+        let changed = self.moved + self.removed + self.added;
+        if changed == 0 {
+            0.0
+        } else {
+            changed as f32 / baseline.max(1) as f32
+        }
+    }
+}
+
+/// The differences between a baseline and a candidate corner stream
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamDiff {
+    pub unchanged: usize,
+    /// changes in time order
+    pub changes: Vec<CornerChange>,
+}
+
+impl StreamDiff {
+    /// whether every corner matched an unchanged corner
+    pub fn is_identical(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn counts(&self) -> DiffCounts {
+        let mut counts = DiffCounts { unchanged: self.unchanged, ..DiffCounts::default() };
+        for change in self.changes.iter() {
+            match change {
+                CornerChange::Moved { .. } => counts.moved += 1,
+                CornerChange::Removed(_) => counts.removed += 1,
+                CornerChange::Added(_) => counts.added += 1,
+            }
+        }
+        counts
+    }
+
+    /// mean distance (pixels) between the moved corners and their matches
+    pub fn mean_displacement(&self) -> f32 {
+        let distances: Vec<f32> = self.changes.iter()
+            .filter_map(|change| match change {
+                CornerChange::Moved { baseline, candidate } => Some(distance(baseline, candidate)),
+                _ => None,
+            })
+            .collect();
+        if distances.is_empty() {
+            return 0.0;
+        }
+        distances.iter().sum::<f32>() / distances.len() as f32
+    }
+
+    /// Write one line per change, in the style of a unified diff:
+    /// `-` for removed, `+` for added and `~` for moved corners, then a summary line
+    pub fn write_report<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let position = |corner: &SaeEvent| {
+            let (row, col) = corner.subpixel_position();
+            format!("t={} row={:.2} col={:.2} pol={}", corner.timestamp, row, col, corner.polarity)
+        };
+        for change in self.changes.iter() {
+            match change {
+                CornerChange::Removed(corner) => writeln!(writer, "- {}", position(corner))?,
+                CornerChange::Added(corner) => writeln!(writer, "+ {}", position(corner))?,
+                CornerChange::Moved { baseline, candidate } => {
+                    writeln!(writer, "~ {} -> {}", position(baseline), position(candidate))?
+                }
+            }
+        }
+        let counts = self.counts();
+        writeln!(writer, "{} unchanged, {} moved, {} removed, {} added",
+                 counts.unchanged, counts.moved, counts.removed, counts.added)
+    }
+}
+
+fn distance(a: &SaeEvent, b: &SaeEvent) -> f32 {
+    let (arow, acol) = a.subpixel_position();
+    let (brow, bcol) = b.subpixel_position();
+    (arow - brow).hypot(acol - bcol)
+}
+
+/// Compare two time-ordered corner streams. Each baseline corner, in time order, is
+/// matched to the nearest unmatched candidate corner within the tolerances;
+/// ties in distance go to the candidate closest in time.
+pub fn diff_corners(baseline: &[SaeEvent], candidate: &[SaeEvent], config: &DiffConfig) -> StreamDiff {
+    let mut matched = vec![false; candidate.len()];
+    let mut diff = StreamDiff::default();
+    // first candidate that can still match a baseline corner, by time
+    let mut first = 0;
+    for corner in baseline.iter() {
+        while first < candidate.len() && candidate[first].timestamp.saturating_add(config.max_dt) < corner.timestamp {
+            if !matched[first] {
+                diff.changes.push(CornerChange::Added(candidate[first].clone()));
+                matched[first] = true;
+            }
+            first += 1;
+        }
+        let best = candidate[first..].iter()
+            .enumerate()
+            .take_while(|(_, other)| other.timestamp <= corner.timestamp.saturating_add(config.max_dt))
+            .filter(|&(idx, other)| !matched[first + idx] && (!config.match_polarity || other.polarity == corner.polarity))
+            .map(|(idx, other)| (first + idx, distance(corner, other), corner.timestamp.abs_diff(other.timestamp)))
+            .filter(|&(_, dist, _)| dist <= config.radius)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.2.cmp(&b.2)));
+        match best {
+            Some((idx, dist, dt)) => {
+                matched[idx] = true;
+                if dist <= config.position_tolerance && dt == 0 {
+                    diff.unchanged += 1;
+                } else {
+                    diff.changes.push(CornerChange::Moved { baseline: corner.clone(), candidate: candidate[idx].clone() });
+                }
+            }
+            None => diff.changes.push(CornerChange::Removed(corner.clone())),
+        }
+    }
+    for (idx, other) in candidate.iter().enumerate().skip(first) {
+        if !matched[idx] {
+            diff.changes.push(CornerChange::Added(other.clone()));
+        }
+    }
+    diff.changes.sort_by_key(|change| change.timestamp());
+    diff
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corner(row: u16, col: u16, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row, col, timestamp, polarity: 1, ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_diff_corners() {
+        let baseline = vec![corner(5, 5, 100), corner(10, 10, 200), corner(20, 20, 300), corner(30, 30, 5_000)];
+        let mut moved = corner(10, 11, 250);
+        moved.col_f = Some(11.5);
+        let candidate = vec![corner(5, 5, 100), moved.clone(), corner(40, 40, 400), corner(30, 30, 5_000)];
+
+        let diff = diff_corners(&baseline, &candidate, &DiffConfig::default());
+        assert_eq!(diff.counts(), DiffCounts { unchanged: 2, moved: 1, removed: 1, added: 1 });
+        assert_eq!(diff.changes, vec![
+            CornerChange::Moved { baseline: corner(10, 10, 200), candidate: moved },
+            CornerChange::Removed(corner(20, 20, 300)),
+            CornerChange::Added(corner(40, 40, 400)),
+        ]);
+        assert!((diff.mean_displacement() - 1.5).abs() < 1e-6);
+        assert!((diff.counts().changed_fraction() - 0.75).abs() < 1e-6);
+
+        let mut report = Vec::new();
+        diff.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert_eq!(report.lines().count(), 4);
+        assert!(report.lines().nth(1).unwrap().starts_with("- t=300 row=20.00"));
+        assert_eq!(report.lines().last().unwrap(), "2 unchanged, 1 moved, 1 removed, 1 added");
+
+        assert!(diff_corners(&baseline, &baseline, &DiffConfig::default()).is_identical());
+        // a flipped polarity breaks the match unless polarity is ignored
+        let flipped: Vec<SaeEvent> = baseline.iter().map(|c| SaeEvent { polarity: 0, ..c.clone() }).collect();
+        assert_eq!(diff_corners(&baseline, &flipped, &DiffConfig::default()).counts().removed, 4);
+        let lenient = DiffConfig { match_polarity: false, ..DiffConfig::default() };
+        assert!(diff_corners(&baseline, &flipped, &lenient).is_identical());
+    }
+}
@@ -0,0 +1,264 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Detector accuracy against ground-truth corner annotations, as in the published
+//! Arc* evaluations on the Event Camera Dataset.
+//!
+//! `read_ground_truth` loads annotations as delimited text, one corner per line:
+//! by default `timestamp x y`, with the timestamp in seconds as the dataset gives it.
+//! `evaluate_detector` runs a detector over an event source, timing each event, and
+//! scores its corners against the annotations: a corner is a true positive if an
+//! annotation lies within `GroundTruthConfig::radius` pixels and `max_dt` of it, and
+//! an annotation is recalled if some corner does the same. Repeatability is the
+//! fraction of corners that re-detect a feature already detected nearby within
+//! `repeat_window`, rather than firing once and vanishing.
+
+use std::io::{self, BufRead, Write};
+use std::time::Instant;
+
+use crate::detector::CornerDetector;
+use crate::sae_types::*;
+use crate::source::EventSource;
+
+
+/// Columns and units of a ground-truth annotation file
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroundTruthFormat {
+    pub timestamp_col: usize,
+    pub x_col: usize,
+    pub y_col: usize,
+    /// multiplier converting file timestamps to microseconds
+    pub timestamp_scale: f64,
+}
+
+impl Default for GroundTruthFormat {
+    fn default() -> Self {
+        GroundTruthFormat { timestamp_col: 0, x_col: 1, y_col: 2, timestamp_scale: 1e6 }
+    }
+}
+
+fn invalid(line: usize, what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("ground truth: invalid {} on line {}", what, line))
+}
+
+/// Read corner annotations in `format`, sorted by time. Sub-pixel positions are kept.
+/// Blank lines, `#` comments and a non-numeric header line are skipped.
+pub fn read_ground_truth<R: BufRead>(reader: R, format: &GroundTruthFormat) -> io::Result<Vec<SaeEvent>> {
+    let mut corners = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|field| !field.is_empty())
+            .collect();
+        if corners.is_empty() && fields.first().is_some_and(|field| field.parse::<f64>().is_err()) {
+            // column names
+            continue;
+        }
+        let field = |col: usize, what: &str| -> io::Result<f64> {
+            fields.get(col).and_then(|field| field.parse::<f64>().ok()).ok_or_else(|| invalid(idx + 1, what))
+        };
+        let timestamp = field(format.timestamp_col, "timestamp")? * format.timestamp_scale;
+        let (x, y) = (field(format.x_col, "x")?, field(format.y_col, "y")?);
+        if !(0.0..=SaeTime::MAX as f64).contains(&timestamp) ||
+            !(0.0..u16::MAX as f64).contains(&x) || !(0.0..u16::MAX as f64).contains(&y) {
+            return Err(invalid(idx + 1, "corner"));
+        }
+        corners.push(SaeEvent {
+            row: y.round() as u16,
+            col: x.round() as u16,
+            row_f: Some(y as f32),
+            col_f: Some(x as f32),
+            timestamp: timestamp.round() as SaeTime,
+            ..SaeEvent::default()
+        });
+    }
+    corners.sort_by_key(|corner| corner.timestamp);
+    Ok(corners)
+}
+
+/// Matching tolerances
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroundTruthConfig {
+    /// pixels between a corner and the annotation it matches
+    pub radius: f32,
+    /// time between a corner and the annotation it matches
+    pub max_dt: SaeTime,
+    /// how far back a corner may re-detect an earlier one, for repeatability
+    pub repeat_window: SaeTime,
+}
+
+impl Default for GroundTruthConfig {
+    fn default() -> Self {
+        GroundTruthConfig { radius: 3.5, max_dt: 5_000, repeat_window: 50_000 }
+    }
+}
+
+/// Whether any of `sorted` (by time) lies within `radius` and `max_dt` of `corner`,
+/// looking only at those before it if `earlier_only`
+fn has_neighbor(corner: &SaeEvent, sorted: &[SaeEvent], radius: f32, max_dt: SaeTime, earlier_only: bool) -> bool {
+    let (row, col) = corner.subpixel_position();
+    let start = sorted.partition_point(|other| other.timestamp < corner.timestamp.saturating_sub(max_dt));
+    let end = if earlier_only {
+        sorted.partition_point(|other| other.timestamp < corner.timestamp)
+    } else {
+        sorted.partition_point(|other| other.timestamp <= corner.timestamp.saturating_add(max_dt))
+    };
+    sorted[start..end].iter().any(|other| {
+        let (orow, ocol) = other.subpixel_position();
+        (orow - row).powi(2) + (ocol - col).powi(2) <= radius * radius
+    })
+}
+
+/// Per-event processing time, in nanoseconds
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LatencyStats {
+    pub mean: f64,
+    pub median: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl LatencyStats {
+    pub fn from_samples(samples: &mut [u64]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort_unstable();
+        let at = |quantile: f64| samples[((samples.len() - 1) as f64 * quantile).round() as usize];
+        LatencyStats {
+            mean: samples.iter().sum::<u64>() as f64 / samples.len() as f64,
+            median: at(0.5),
+            p99: at(0.99),
+            max: samples[samples.len() - 1],
+        }
+    }
+}
+
+/// The accuracy and speed of a detector on one recording
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DetectionEvaluation {
+    pub events: u64,
+    pub corners: u64,
+    pub annotations: u64,
+    /// corners matching an annotation
+    pub true_positives: u64,
+    /// annotations matched by a corner
+    pub recalled: u64,
+    /// corners re-detecting an earlier corner
+    pub repeated: u64,
+    pub latency: LatencyStats,
+}
+
+impl DetectionEvaluation {
+    pub fn precision(&self) -> f64 {
+        if self.corners == 0 { 0.0 } else { self.true_positives as f64 / self.corners as f64 }
+    }
+
+    pub fn recall(&self) -> f64 {
+        if self.annotations == 0 { 0.0 } else { self.recalled as f64 / self.annotations as f64 }
+    }
+
+    pub fn f1(&self) -> f64 {
+        let (precision, recall) = (self.precision(), self.recall());
+        if precision + recall == 0.0 { 0.0 } else { 2.0 * precision * recall / (precision + recall) }
+    }
+
+    pub fn repeatability(&self) -> f64 {
+        if self.corners == 0 { 0.0 } else { self.repeated as f64 / self.corners as f64 }
+    }
+
+    /// Write as CSV, a header line then one row: `events,corners,annotations,precision,recall,f1,
+    /// repeatability,latency_mean_ns,latency_median_ns,latency_p99_ns,latency_max_ns`
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "events,corners,annotations,precision,recall,f1,repeatability,\
+                          latency_mean_ns,latency_median_ns,latency_p99_ns,latency_max_ns")?;
+        writeln!(writer, "{},{},{},{:.4},{:.4},{:.4},{:.4},{:.1},{},{},{}",
+                 self.events, self.corners, self.annotations, self.precision(), self.recall(), self.f1(),
+                 self.repeatability(), self.latency.mean, self.latency.median, self.latency.p99, self.latency.max)
+    }
+}
+
+/// Score `corners` against `ground_truth`, both sorted by time
+pub fn score_corners(corners: &[SaeEvent], ground_truth: &[SaeEvent], config: &GroundTruthConfig) -> DetectionEvaluation {
+    DetectionEvaluation {
+        corners: corners.len() as u64,
+        annotations: ground_truth.len() as u64,
+        true_positives: corners.iter()
+            .filter(|corner| has_neighbor(corner, ground_truth, config.radius, config.max_dt, false))
+            .count() as u64,
+        recalled: ground_truth.iter()
+            .filter(|truth| has_neighbor(truth, corners, config.radius, config.max_dt, false))
+            .count() as u64,
+        repeated: corners.iter()
+            .filter(|corner| has_neighbor(corner, corners, config.radius, config.repeat_window, true))
+            .count() as u64,
+        ..DetectionEvaluation::default()
+    }
+}
+
+/// Run every event of `source` through `detector`, timing each, and score its corners
+pub fn evaluate_detector<D, E>(detector: &mut D, source: &mut E, ground_truth: &[SaeEvent],
+                               config: &GroundTruthConfig) -> io::Result<DetectionEvaluation>
+    where D: CornerDetector + ?Sized, E: EventSource + ?Sized
+{
+    let mut corners = Vec::new();
+    let mut latencies = Vec::new();
+    while let Some(evt) = source.next_event()? {
+        let start = Instant::now();
+        let corner = detector.process(&evt);
+        latencies.push(start.elapsed().as_nanos() as u64);
+        corners.extend(corner);
+    }
+    corners.sort_by_key(|corner| corner.timestamp);
+    Ok(DetectionEvaluation {
+        events: latencies.len() as u64,
+        latency: LatencyStats::from_samples(&mut latencies),
+        ..score_corners(&corners, ground_truth, config)
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detector::{DetectorConfig, OnlineDetector};
+    use crate::source::IterSource;
+    use crate::surface::WarmupConfig;
+
+    #[test]
+    fn test_evaluate_against_annotations() {
+        let mut events = Vec::new();
+        for &(top, left, t) in [(10u16, 10u16, 1_000u32), (10, 40, 2_000)].iter() {
+            for row in top..top + 5 {
+                for col in left..left + 5 {
+                    events.push(SaeEvent { row, col, polarity: 1, timestamp: t, ..SaeEvent::default() });
+                }
+            }
+            events.push(SaeEvent { row: top + 4, col: left + 4, polarity: 1, timestamp: t + 10, ..SaeEvent::default() });
+        }
+        // a corner at the first block, and one nowhere near a detection
+        let text = "# t x y\n0.00101 14 14\n0.5 30 30\n";
+        let ground_truth = read_ground_truth(text.as_bytes(), &GroundTruthFormat::default()).unwrap();
+        assert_eq!((ground_truth[0].timestamp, ground_truth[0].col_f), (1_010, Some(14.0)));
+        assert!(read_ground_truth("0.1 2\n".as_bytes(), &GroundTruthFormat::default()).is_err());
+
+        let mut detector = OnlineDetector::new(DetectorConfig::default(), 32, 64, WarmupConfig::disabled());
+        let eval = evaluate_detector(&mut detector, &mut IterSource::new(events.clone()), &ground_truth,
+                                     &GroundTruthConfig::default()).unwrap();
+        assert_eq!(eval.events, events.len() as u64);
+        assert!(eval.corners > 0);
+        assert_eq!(eval.recall(), 0.5);
+        assert!(eval.precision() > 0.0 && eval.precision() < 1.0);
+        assert!(eval.latency.max >= eval.latency.median);
+
+        let mut csv = Vec::new();
+        eval.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.lines().nth(1).unwrap().starts_with(&format!("{},{},2,", events.len(), eval.corners)));
+    }
+}
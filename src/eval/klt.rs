@@ -0,0 +1,286 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Feature-track evaluation against frame-based KLT reference tracks, reproducing
+//! the tracking-accuracy experiments of the Arc* paper.
+//!
+//! Given intensity frames (eg DAVIS APS frames) spanning a recording, each event
+//! track is seeded into a Lucas-Kanade tracker at the first frame within its lifetime,
+//! and followed frame to frame. The distance between the event track and the
+//! KLT reference at each frame gives the tracking error.
+
+use nalgebra::{DMatrix, Matrix2, Vector2};
+
+use crate::sae_types::*;
+use crate::track::{Track, TrackId, TrackStore};
+
+
+/// A grayscale intensity frame
+#[derive(Clone, Debug)]
+pub struct GrayFrame {
+    pub timestamp: SaeTime,
+    /// intensities indexed by (row, col)
+    pub pixels: DMatrix<f32>,
+}
+
+/// Parameters of the Lucas-Kanade tracker
+#[derive(Clone, Debug, PartialEq)]
+pub struct KltParams {
+    /// half-size of the square integration window
+    pub half_window: usize,
+    pub max_iterations: usize,
+    /// iteration stops once the update step is smaller than this (pixels)
+    pub epsilon: f32,
+    /// points whose window has less texture than this (minimum eigenvalue of
+    /// the structure tensor, per window pixel) are considered lost
+    pub min_eigenvalue: f32,
+}
+
+impl Default for KltParams {
+    fn default() -> Self {
+        KltParams {
+            half_window: 7,
+            max_iterations: 20,
+            epsilon: 0.01,
+            min_eigenvalue: 1e-4,
+        }
+    }
+}
+
+/// Bilinear intensity lookup at (x = col, y = row), or None outside the frame
+fn sample(pixels: &DMatrix<f32>, x: f32, y: f32) -> Option<f32> {
+    let (nrows, ncols) = pixels.shape();
+    if x < 0.0 || y < 0.0 {
+        return None;
+    }
+    let x0 = x.floor() as usize;
+    let y0 = y.floor() as usize;
+    if x0 + 1 >= ncols || y0 + 1 >= nrows {
+        return None;
+    }
+    let fx = x - x0 as f32;
+    let fy = y - y0 as f32;
+    let top = pixels[(y0, x0)] * (1.0 - fx) + pixels[(y0, x0 + 1)] * fx;
+    let bottom = pixels[(y0 + 1, x0)] * (1.0 - fx) + pixels[(y0 + 1, x0 + 1)] * fx;
+    Some(top * (1.0 - fy) + bottom * fy)
+}
+
+/// Track a point (x = col, y = row) from `prev` to `next`.
+/// Returns the new position, or None if the point is lost.
+pub fn track_point(prev: &DMatrix<f32>, next: &DMatrix<f32>, pos: (f32, f32), params: &KltParams) -> Option<(f32, f32)> {
+    let hw = params.half_window as i32;
+    let (px, py) = pos;
+
+    // template intensities and gradients from the previous frame
+    let mut template = Vec::new();
+    let mut structure = Matrix2::<f32>::zeros();
+    for dy in -hw..=hw {
+        for dx in -hw..=hw {
+            let x = px + dx as f32;
+            let y = py + dy as f32;
+            let val = sample(prev, x, y)?;
+            let gx = (sample(prev, x + 1.0, y)? - sample(prev, x - 1.0, y)?) * 0.5;
+            let gy = (sample(prev, x, y + 1.0)? - sample(prev, x, y - 1.0)?) * 0.5;
+            structure += Matrix2::new(gx * gx, gx * gy, gx * gy, gy * gy);
+            template.push((dx as f32, dy as f32, val, gx, gy));
+        }
+    }
+
+    let npix = template.len() as f32;
+    let min_eig = structure.symmetric_eigenvalues().min();
+    if min_eig / npix < params.min_eigenvalue {
+        return None;
+    }
+    let inverse = structure.try_inverse()?;
+
+    let mut disp = Vector2::<f32>::zeros();
+    for _ in 0..params.max_iterations {
+        let mut mismatch = Vector2::<f32>::zeros();
+        for &(dx, dy, val, gx, gy) in template.iter() {
+            let moved = sample(next, px + dx + disp.x, py + dy + disp.y)?;
+            let diff = val - moved;
+            mismatch += Vector2::new(diff * gx, diff * gy);
+        }
+        let step = inverse * mismatch;
+        disp += step;
+        if step.norm() < params.epsilon {
+            break;
+        }
+    }
+
+    Some((px + disp.x, py + disp.y))
+}
+
+/// Position of the event track at time `t`: its latest observation at or before `t`
+fn track_position_at(track: &Track, t: SaeTime) -> Option<(f32, f32)> {
+    track.observations.iter()
+        .take_while(|obs| obs.timestamp <= t)
+        .last()
+        .map(|obs| (obs.col as f32, obs.row as f32))
+}
+
+/// Errors of one event track against its KLT reference
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackComparison {
+    pub track_id: TrackId,
+    /// number of frames at which both tracks were available
+    pub frames_compared: usize,
+    /// mean distance (pixels) between event track and reference over those frames
+    pub mean_error: f32,
+    /// distance at the last compared frame
+    pub endpoint_error: f32,
+    /// event track lifetime (SAE time units)
+    pub track_lifetime: SaeTime,
+    /// time over which the KLT reference could follow the feature
+    pub reference_lifetime: SaeTime,
+}
+
+/// Aggregate tracking accuracy over all evaluated tracks
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackingEvaluation {
+    pub comparisons: Vec<TrackComparison>,
+}
+
+impl TrackingEvaluation {
+    fn mean_of<F: Fn(&TrackComparison) -> f32>(&self, f: F) -> f32 {
+        if self.comparisons.is_empty() {
+            return 0.0;
+        }
+        self.comparisons.iter().map(f).sum::<f32>() / (self.comparisons.len() as f32)
+    }
+
+    pub fn tracks_evaluated(&self) -> usize {
+        self.comparisons.len()
+    }
+
+    pub fn mean_error(&self) -> f32 {
+        self.mean_of(|c| c.mean_error)
+    }
+
+    pub fn mean_endpoint_error(&self) -> f32 {
+        self.mean_of(|c| c.endpoint_error)
+    }
+
+    pub fn mean_track_lifetime(&self) -> f32 {
+        self.mean_of(|c| c.track_lifetime as f32)
+    }
+
+    pub fn mean_reference_lifetime(&self) -> f32 {
+        self.mean_of(|c| c.reference_lifetime as f32)
+    }
+}
+
+/// Compare a single event track against a KLT reference seeded from it
+pub fn compare_track(track: &Track, frames: &[GrayFrame], params: &KltParams) -> Option<TrackComparison> {
+    let start = track.first().timestamp;
+    let end = track.last().timestamp;
+    let first_idx = frames.iter().position(|f| f.timestamp >= start && f.timestamp <= end)?;
+
+    let seed_frame = &frames[first_idx];
+    let mut reference = track_position_at(track, seed_frame.timestamp)?;
+    let mut total_error = 0.0;
+    let mut endpoint_error = 0.0;
+    let mut frames_compared = 1;
+    let mut reference_end = seed_frame.timestamp;
+
+    for pair in frames[first_idx..].windows(2) {
+        let (prev, next) = (&pair[0], &pair[1]);
+        if next.timestamp > end {
+            break;
+        }
+        reference = match track_point(&prev.pixels, &next.pixels, reference, params) {
+            Some(pos) => pos,
+            None => break,
+        };
+        reference_end = next.timestamp;
+        if let Some((x, y)) = track_position_at(track, next.timestamp) {
+            let error = ((x - reference.0).powi(2) + (y - reference.1).powi(2)).sqrt();
+            total_error += error;
+            endpoint_error = error;
+            frames_compared += 1;
+        }
+    }
+
+    Some(TrackComparison {
+        track_id: track.id,
+        frames_compared,
+        // the seed frame contributes zero error by construction
+        mean_error: total_error / (frames_compared as f32),
+        endpoint_error,
+        track_lifetime: track.lifetime(),
+        reference_lifetime: reference_end - seed_frame.timestamp,
+    })
+}
+
+/// Evaluate all tracks that overlap at least one frame
+pub fn evaluate_tracks(store: &TrackStore, frames: &[GrayFrame], params: &KltParams) -> TrackingEvaluation {
+    TrackingEvaluation {
+        comparisons: store.iter()
+            .filter_map(|track| compare_track(track, frames, params))
+            .collect(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a smooth bright blob centered at (cx = col, cy = row)
+    fn blob_frame(timestamp: SaeTime, cx: f32, cy: f32) -> GrayFrame {
+        let pixels = DMatrix::from_fn(48, 48, |row, col| {
+            let dx = col as f32 - cx;
+            let dy = row as f32 - cy;
+            (-(dx * dx + dy * dy) / 30.0).exp()
+        });
+        GrayFrame { timestamp, pixels }
+    }
+
+    #[test]
+    fn test_track_point_recovers_shift() {
+        let prev = blob_frame(0, 20.0, 20.0);
+        let next = blob_frame(1, 21.5, 20.5);
+        let (x, y) = track_point(&prev.pixels, &next.pixels, (20.0, 20.0), &KltParams::default()).unwrap();
+        assert!((x - 21.5).abs() < 0.1, "x {}", x);
+        assert!((y - 20.5).abs() < 0.1, "y {}", y);
+    }
+
+    #[test]
+    fn test_textureless_point_is_lost() {
+        let flat = GrayFrame { timestamp: 0, pixels: DMatrix::from_element(48, 48, 0.5) };
+        assert!(track_point(&flat.pixels, &flat.pixels, (20.0, 20.0), &KltParams::default()).is_none());
+    }
+
+    #[test]
+    fn test_evaluate_tracks() {
+        // the blob moves one column per frame; frames every 1000 time units
+        let frames: Vec<GrayFrame> = (0..5)
+            .map(|i| blob_frame(i * 1000, 18.0 + i as f32, 20.0))
+            .collect();
+
+        let mut store = TrackStore::new();
+        // an accurate event track
+        let good = store.start_track(SaeEvent { row: 20, col: 18, timestamp: 0, ..SaeEvent::default() });
+        for i in 1..5u16 {
+            store.extend_track(good, SaeEvent { row: 20, col: 18 + i, timestamp: 1000 * i as SaeTime, ..SaeEvent::default() });
+        }
+        // a track that stays put while the feature moves
+        let bad = store.start_track(SaeEvent { row: 20, col: 18, timestamp: 0, ..SaeEvent::default() });
+        store.extend_track(bad, SaeEvent { row: 20, col: 18, timestamp: 4000, ..SaeEvent::default() });
+        // a track outside the frame times isn't evaluated
+        store.start_track(SaeEvent { row: 20, col: 18, timestamp: 9000, ..SaeEvent::default() });
+
+        let evaluation = evaluate_tracks(&store, &frames, &KltParams::default());
+        assert_eq!(evaluation.tracks_evaluated(), 2);
+
+        let good_cmp = &evaluation.comparisons[0];
+        assert_eq!(good_cmp.frames_compared, 5);
+        assert!(good_cmp.endpoint_error < 0.2);
+        assert_eq!(good_cmp.reference_lifetime, 4000);
+
+        let bad_cmp = &evaluation.comparisons[1];
+        assert!((bad_cmp.endpoint_error - 4.0).abs() < 0.2);
+        assert!(evaluation.mean_endpoint_error() > good_cmp.endpoint_error);
+        assert_eq!(evaluation.mean_track_lifetime(), 4000.0);
+    }
+}
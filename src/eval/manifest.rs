@@ -0,0 +1,494 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Experiment manifests, so published evaluation results can be reproduced exactly.
+//!
+//! A manifest records, as JSON, everything an `eval::sweep` run depends on: the crate
+//! version, the recording geometry and a fingerprint of its events (count and CRC-32
+//! of their compact encoding), the full detector, denoiser and tracker configuration,
+//! and the resulting metrics. `Manifest::rerun` repeats the run on the same events,
+//! refusing other data, and `Manifest::reproduces` checks a new result against the
+//! recorded metrics. Numbers are written in shortest round-trip form, so configuration
+//! values are restored bit for bit.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::circle::CircleSpec;
+use crate::detector::DetectorConfig;
+use crate::eval::sweep::{evaluate_point, SweepPoint, SweepResult};
+use crate::eval::{json_string, ring_json};
+use crate::io::compact::{encode_event, RecordingHeader};
+use crate::io::npy::crc32;
+use crate::predict::PredictionConfig;
+use crate::sae_types::*;
+use crate::surface::WarmupConfig;
+use crate::track::{GeometricConfig, MatchMode, TrackerConfig};
+
+
+/// Identifies the events a run was made on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DatasetFingerprint {
+    pub events: u64,
+    /// CRC-32 of the events in the compact record format
+    pub crc32: u32,
+}
+
+impl DatasetFingerprint {
+    pub fn of(events: &[SaeEvent]) -> Self {
+        let mut bytes = Vec::with_capacity(events.len() * 9);
+        for evt in events.iter() {
+            bytes.extend_from_slice(&encode_event(evt));
+        }
+        DatasetFingerprint { events: events.len() as u64, crc32: crc32(&bytes) }
+    }
+}
+
+/// A complete record of one evaluation run
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifest {
+    /// version of this crate that made the run
+    pub version: String,
+    pub header: RecordingHeader,
+    pub dataset: DatasetFingerprint,
+    pub result: SweepResult,
+}
+
+impl Manifest {
+    pub fn new(header: &RecordingHeader, events: &[SaeEvent], result: SweepResult) -> Self {
+        Manifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            header: header.clone(),
+            dataset: DatasetFingerprint::of(events),
+            result,
+        }
+    }
+
+    /// Evaluate `point` on `events` and record the run
+    pub fn record(header: &RecordingHeader, events: &[SaeEvent], point: &SweepPoint) -> Self {
+        Self::new(header, events, evaluate_point(header, events, point))
+    }
+
+    /// Repeat the run on `events`, which must match the recorded fingerprint
+    pub fn rerun(&self, events: &[SaeEvent]) -> io::Result<SweepResult> {
+        if DatasetFingerprint::of(events) != self.dataset {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "events do not match the manifest dataset"));
+        }
+        Ok(evaluate_point(&self.header, events, &self.result.point))
+    }
+
+    /// Whether `result` reproduces the recorded metrics; wall-clock time is not compared
+    pub fn reproduces(&self, result: &SweepResult) -> bool {
+        SweepResult { elapsed: self.result.elapsed, ..result.clone() } == self.result
+    }
+
+    pub fn to_json(&self) -> String {
+        let r = &self.result;
+        let p = &r.point;
+        let detector = &p.detector;
+        let dead_pixels = match detector.dead_pixels.as_ref() {
+            Some(dead) => {
+                let mut positions = Vec::new();
+                for col in 0..dead.ncols() {
+                    for row in 0..dead.nrows() {
+                        if dead[(row, col)] {
+                            positions.push(format!("[{},{}]", row, col));
+                        }
+                    }
+                }
+                format!("{{\"nrows\":{},\"ncols\":{},\"positions\":[{}]}}", dead.nrows(), dead.ncols(), positions.join(","))
+            }
+            None => "null".to_string(),
+        };
+        let denoise_window = p.denoise_window.map_or("null".to_string(), |window| window.to_string());
+        let max_age = detector.max_age.map_or("null".to_string(), |max_age| max_age.to_string());
+        let geometric = match &p.tracker.mode {
+            MatchMode::Proximity => "null".to_string(),
+            MatchMode::Geometric(geometric) => {
+                let prediction = &geometric.prediction;
+                format!("{{\"history\":{},\"base_radius\":{},\"radius_growth\":{},\"time_tolerance\":{},\"flow_radius\":{}}}",
+                        prediction.history, prediction.base_radius, prediction.radius_growth, prediction.time_tolerance,
+                        geometric.flow_radius)
+            }
+        };
+        format!(concat!(
+            "{{\"crate\":\"arcstar\",\"version\":{},\n",
+            "\"dataset\":{{\"nrows\":{},\"ncols\":{},\"warmup\":{{\"min_populated_fraction\":{},\"min_elapsed\":{}}},",
+            "\"events\":{},\"crc32\":{}}},\n",
+            "\"config\":{{\"detector_label\":{},\"inner\":{},\"outer\":{},\"dead_pixels\":{},",
+            "\"min_border_inset\":{},\"descriptor\":{},\"binary_descriptor\":{},\"max_age\":{},\"denoise_window\":{},",
+            "\"tracker\":{{\"match_radius\":{},\"max_gap\":{},\"min_likeness\":{},\"geometric\":{}}}}},\n",
+            "\"metrics\":{{\"events_processed\":{},\"events_filtered\":{},\"corners\":{},\"corner_rate\":{},\"tracks\":{},",
+            "\"mean_lifetime\":{},\"singleton_fraction\":{},\"mean_redetection_rate\":{},\"elapsed\":{}}}}}\n"),
+            json_string(&self.version),
+            self.header.nrows, self.header.ncols, self.header.warmup.min_populated_fraction, self.header.warmup.min_elapsed,
+            self.dataset.events, self.dataset.crc32,
+            json_string(&p.detector_label), ring_json(&detector.inner), ring_json(&detector.outer), dead_pixels,
+            detector.min_border_inset, detector.descriptor, detector.binary_descriptor, max_age, denoise_window,
+            p.tracker.match_radius, p.tracker.max_gap, p.tracker.min_likeness, geometric,
+            r.events_processed, r.events_filtered, r.corners, r.corner_rate, r.tracks,
+            r.mean_lifetime, r.singleton_fraction, r.mean_redetection_rate, r.elapsed)
+    }
+
+    pub fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.to_json().as_bytes())
+    }
+
+    pub fn from_json(text: &str) -> io::Result<Self> {
+        let root = Parser { bytes: text.as_bytes(), pos: 0 }.document()?;
+        let dataset = root.get("dataset")?;
+        let config = root.get("config")?;
+        let tracker = config.get("tracker")?;
+        let metrics = root.get("metrics")?;
+        let warmup = dataset.get("warmup")?;
+
+        let header = RecordingHeader::new(
+            dataset.get("nrows")?.number()?,
+            dataset.get("ncols")?.number()?,
+            WarmupConfig {
+                min_populated_fraction: warmup.get("min_populated_fraction")?.number()?,
+                min_elapsed: warmup.get("min_elapsed")?.number()?,
+            },
+        );
+        let mut detector = DetectorConfig::new(parse_ring(config.get("inner")?)?, parse_ring(config.get("outer")?)?);
+        let dead = config.get("dead_pixels")?;
+        if !dead.is_null() {
+            let mut mask = SaeOccupancy::from_element(dead.get("nrows")?.number()?, dead.get("ncols")?.number()?, false);
+            for pos in dead.get("positions")?.array()? {
+                let pos = pos.array()?;
+                let (row, col): (usize, usize) = (pos.first().ok_or_else(|| invalid("dead pixel"))?.number()?,
+                                                  pos.get(1).ok_or_else(|| invalid("dead pixel"))?.number()?);
+                if row >= mask.nrows() || col >= mask.ncols() {
+                    return Err(invalid("dead pixel"));
+                }
+                mask[(row, col)] = true;
+            }
+            detector = detector.with_dead_pixels(mask);
+        }
+        // manifests from before these parameters were configurable used the defaults
+        if let Ok(inset) = config.get("min_border_inset") {
+            detector.min_border_inset = inset.number()?;
+        }
+        if let Ok(descriptor) = config.get("descriptor") {
+            detector.descriptor = descriptor.boolean()?;
+        }
+        if let Ok(binary) = config.get("binary_descriptor") {
+            detector.binary_descriptor = binary.boolean()?;
+        }
+        match config.get("max_age") {
+            Ok(max_age) if !max_age.is_null() => detector.max_age = Some(max_age.number()?),
+            _ => {}
+        }
+        let denoise_window = config.get("denoise_window")?;
+        // manifests from before geometric matching have no mode
+        let mode = match tracker.get("geometric") {
+            Ok(geometric) if !geometric.is_null() => MatchMode::Geometric(GeometricConfig {
+                prediction: PredictionConfig {
+                    history: geometric.get("history")?.number()?,
+                    base_radius: geometric.get("base_radius")?.number()?,
+                    radius_growth: geometric.get("radius_growth")?.number()?,
+                    time_tolerance: geometric.get("time_tolerance")?.number()?,
+                },
+                flow_radius: geometric.get("flow_radius")?.number()?,
+            }),
+            _ => MatchMode::Proximity,
+        };
+        let point = SweepPoint {
+            detector_label: config.get("detector_label")?.string()?.to_string(),
+            detector,
+            denoise_window: if denoise_window.is_null() { None } else { Some(denoise_window.number()?) },
+            tracker: TrackerConfig {
+                match_radius: tracker.get("match_radius")?.number()?,
+                max_gap: tracker.get("max_gap")?.number()?,
+                min_likeness: tracker.get("min_likeness")?.number()?,
+                mode,
+            },
+        };
+        Ok(Manifest {
+            version: root.get("version")?.string()?.to_string(),
+            header,
+            dataset: DatasetFingerprint { events: dataset.get("events")?.number()?, crc32: dataset.get("crc32")?.number()? },
+            result: SweepResult {
+                point,
+                events_processed: metrics.get("events_processed")?.number()?,
+                events_filtered: metrics.get("events_filtered")?.number()?,
+                corners: metrics.get("corners")?.number()?,
+                corner_rate: metrics.get("corner_rate")?.number()?,
+                tracks: metrics.get("tracks")?.number()?,
+                mean_lifetime: metrics.get("mean_lifetime")?.number()?,
+                singleton_fraction: metrics.get("singleton_fraction")?.number()?,
+                mean_redetection_rate: metrics.get("mean_redetection_rate")?.number()?,
+                elapsed: metrics.get("elapsed")?.number()?,
+            },
+        })
+    }
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("manifest: invalid {}", what))
+}
+
+fn parse_ring(value: &Value) -> io::Result<CircleSpec> {
+    let mut offsets = Vec::new();
+    for off in value.get("offsets")?.array()? {
+        let off = off.array()?;
+        if off.len() != 2 {
+            return Err(invalid("ring offset"));
+        }
+        offsets.push([off[0].number()?, off[1].number()?]);
+    }
+    CircleSpec::new(offsets, value.get("min_arc_len")?.number()?, value.get("max_arc_len")?.number()?)
+        .ok_or_else(|| invalid("ring"))
+}
+
+/// A parsed JSON value; numbers keep their text, to be parsed into the type wanted
+enum Value {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+impl Value {
+    fn get(&self, key: &str) -> io::Result<&Value> {
+        match self {
+            Value::Object(fields) => fields.get(key).ok_or_else(|| invalid(key)),
+            _ => Err(invalid(key)),
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    fn number<T: std::str::FromStr>(&self) -> io::Result<T> {
+        match self {
+            Value::Number(text) => text.parse().map_err(|_| invalid("number")),
+            _ => Err(invalid("number")),
+        }
+    }
+
+    fn boolean(&self) -> io::Result<bool> {
+        match self {
+            Value::Bool(value) => Ok(*value),
+            _ => Err(invalid("boolean")),
+        }
+    }
+
+    fn string(&self) -> io::Result<&str> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(invalid("string")),
+        }
+    }
+
+    fn array(&self) -> io::Result<&[Value]> {
+        match self {
+            Value::Array(items) => Ok(items),
+            _ => Err(invalid("array")),
+        }
+    }
+}
+
+/// A recursive descent JSON parser, enough for manifests
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn document(mut self) -> io::Result<Value> {
+        let value = self.value()?;
+        self.skip_whitespace();
+        if self.pos != self.bytes.len() {
+            return Err(invalid("trailing data"));
+        }
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.bytes.get(self.pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> io::Result<()> {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) != Some(&byte) {
+            return Err(invalid("syntax"));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, text: &str, value: Value) -> io::Result<Value> {
+        if !self.bytes[self.pos..].starts_with(text.as_bytes()) {
+            return Err(invalid("syntax"));
+        }
+        self.pos += text.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> io::Result<Value> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.bytes.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.bytes.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Value::Array(items));
+                        }
+                        _ => return Err(invalid("syntax")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = BTreeMap::new();
+                self.skip_whitespace();
+                if self.bytes.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.insert(key, self.value()?);
+                    self.skip_whitespace();
+                    match self.bytes.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Value::Object(fields));
+                        }
+                        _ => return Err(invalid("syntax")),
+                    }
+                }
+            }
+            Some(b) if *b == b'-' || b.is_ascii_digit() => {
+                let start = self.pos;
+                while self.bytes.get(self.pos).is_some_and(|b| b"+-.eE".contains(b) || b.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+                Ok(Value::Number(String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned()))
+            }
+            _ => Err(invalid("syntax")),
+        }
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        if self.bytes.get(self.pos) != Some(&b'"') {
+            return Err(invalid("string"));
+        }
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            match self.bytes.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return String::from_utf8(out).map_err(|_| invalid("string"));
+                }
+                Some(b'\\') => {
+                    let escaped = *self.bytes.get(self.pos + 1).ok_or_else(|| invalid("string"))?;
+                    self.pos += 2;
+                    match escaped {
+                        b'n' => out.push(b'\n'),
+                        b't' => out.push(b'\t'),
+                        b'r' => out.push(b'\r'),
+                        b'u' => {
+                            let hex = self.bytes.get(self.pos..self.pos + 4).ok_or_else(|| invalid("string"))?;
+                            let code = u32::from_str_radix(&String::from_utf8_lossy(hex), 16).map_err(|_| invalid("string"))?;
+                            let c = char::from_u32(code).ok_or_else(|| invalid("string"))?;
+                            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                            self.pos += 4;
+                        }
+                        other => out.push(other),
+                    }
+                }
+                Some(&b) => {
+                    out.push(b);
+                    self.pos += 1;
+                }
+                None => return Err(invalid("string")),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::sweep::ParameterGrid;
+
+    fn recording() -> Vec<SaeEvent> {
+        let mut events = Vec::new();
+        for step in 0..20u16 {
+            let t = 1_000 + step as SaeTime * 1_000;
+            for i in 0..8u16 {
+                for &(row, col) in [(10 + step, 10 + step + i), (10 + step + i, 10 + step)].iter() {
+                    events.push(SaeEvent { row, col, polarity: 1, timestamp: t + i as SaeTime, ..SaeEvent::default() });
+                }
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn test_round_trip_and_rerun() {
+        let header = RecordingHeader::new(48, 48, WarmupConfig { min_populated_fraction: 0.01, min_elapsed: 0 });
+        let events = recording();
+        let mut dead = SaeOccupancy::from_element(48, 48, false);
+        dead[(3, 40)] = true;
+        let point = SweepPoint {
+            detector_label: "c3c4 \"dead\"".to_string(),
+            detector: DetectorConfig { descriptor: false, max_age: Some(50_000), ..DetectorConfig::default() }.with_dead_pixels(dead),
+            denoise_window: Some(3_000),
+            tracker: TrackerConfig { match_radius: 2.7, mode: MatchMode::Geometric(GeometricConfig::default()), ..TrackerConfig::default() },
+        };
+        let manifest = Manifest::record(&header, &events, &point);
+        assert_eq!(manifest.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(manifest.dataset.events, events.len() as u64);
+
+        let mut json = Vec::new();
+        manifest.write_json(&mut json).unwrap();
+        let parsed = Manifest::from_json(std::str::from_utf8(&json).unwrap()).unwrap();
+        assert_eq!(parsed, manifest);
+
+        let rerun = parsed.rerun(&events).unwrap();
+        assert!(parsed.reproduces(&rerun));
+        assert!(!parsed.reproduces(&SweepResult { corners: rerun.corners + 1, ..rerun }));
+        // other data is refused
+        assert!(parsed.rerun(&events[1..]).is_err());
+    }
+
+    #[test]
+    fn test_default_point_and_errors() {
+        let header = RecordingHeader::new(48, 48, WarmupConfig::disabled());
+        let point = &ParameterGrid::default().points()[0];
+        let manifest = Manifest::record(&header, &recording(), point);
+        assert_eq!(Manifest::from_json(&manifest.to_json()).unwrap(), manifest);
+
+        assert!(Manifest::from_json("{\"version\":\"0.1.0\"}").is_err());
+        assert!(Manifest::from_json(&manifest.to_json().replace("\"tracker\"", "\"trackers\"")).is_err());
+        assert!(Manifest::from_json(&format!("{} x", manifest.to_json())).is_err());
+    }
+}
@@ -0,0 +1,44 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Evaluation of detector and tracker output against reference data.
+
+use crate::circle::CircleSpec;
+
+pub mod compression;
+pub mod confidence;
+pub mod denoise;
+pub mod diff;
+pub mod ground_truth;
+pub mod klt;
+#[cfg(feature = "io")]
+pub mod manifest;
+pub mod parity;
+#[cfg(feature = "io")]
+pub mod report;
+pub mod stability;
+pub mod sweep;
+
+
+/// `s` as a quoted JSON string
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// the offsets and arc length limits of `ring` as a JSON object
+pub(crate) fn ring_json(ring: &CircleSpec) -> String {
+    let offsets: Vec<String> = ring.offsets().iter().map(|off| format!("[{},{}]", off[0], off[1])).collect();
+    format!("{{\"offsets\":[{}],\"min_arc_len\":{},\"max_arc_len\":{}}}", offsets.join(","), ring.min_arc_len(), ring.max_arc_len())
+}
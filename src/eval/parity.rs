@@ -0,0 +1,179 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Hardware-in-the-loop parity: replay recorded events through the exact
+//! `static_pipeline::StaticPipeline` configuration flashed to a device, on the host,
+//! and compare its corners with those the firmware logged for the same events.
+//!
+//! A configuration mismatch between host and firmware changes which corners are
+//! found throughout the recording, while platform issues (event loss under load,
+//! timer or floating point differences) show up as sporadic missing or extra corners,
+//! or corners at slightly different positions. `ParityReport::likely_cause` makes
+//! that call from the diff, and `first_divergence` locates where to start looking.
+//! Firmware logs are read in the `sink::CsvSink` format.
+
+use std::io::{self, BufRead};
+
+use crate::detector::CornerDetector;
+use crate::eval::diff::{diff_corners, CornerChange, DiffConfig, StreamDiff};
+use crate::filter::EventFilter;
+use crate::sae_types::*;
+use crate::source::EventSource;
+use crate::static_pipeline::StaticPipeline;
+
+
+/// Read a corner log as written by `sink::CsvSink`: one `timestamp,row,col,polarity`
+/// line per corner. A header line, blank lines and `#` comments are skipped.
+pub fn read_corner_log<R: BufRead>(reader: R) -> io::Result<Vec<SaeEvent>> {
+    let mut corners = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line == "timestamp,row,col,polarity" {
+            continue;
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("corner log: invalid line {}", idx + 1));
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() != 4 {
+            return Err(invalid());
+        }
+        corners.push(SaeEvent {
+            timestamp: fields[0].parse().map_err(|_| invalid())?,
+            row: fields[1].parse().map_err(|_| invalid())?,
+            col: fields[2].parse().map_err(|_| invalid())?,
+            polarity: fields[3].parse().map_err(|_| invalid())?,
+            ..SaeEvent::default()
+        });
+    }
+    Ok(corners)
+}
+
+/// Run every event of `source` through `pipeline`, returning the corners it found
+pub fn replay_static<F, D, E>(pipeline: &mut StaticPipeline<F, D, Vec<SaeEvent>>, source: &mut E) -> io::Result<Vec<SaeEvent>>
+    where F: EventFilter, D: CornerDetector, E: EventSource + ?Sized
+{
+    while let Some(evt) = source.next_event()? {
+        pipeline.process(RawEvent::from(&evt));
+    }
+    Ok(std::mem::take(pipeline.sink_mut()))
+}
+
+/// What most likely makes the host and firmware outputs differ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParityCause {
+    /// the outputs agree
+    None,
+    /// sporadic or small differences, as from event loss or numeric differences
+    Platform,
+    /// differences throughout the output, as from different parameters
+    Configuration,
+}
+
+/// The comparison of host and firmware corners for the same recording
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParityReport {
+    pub events_replayed: u64,
+    pub host_corners: usize,
+    pub firmware_corners: usize,
+    /// firmware corners as baseline, host corners as candidate
+    pub diff: StreamDiff,
+}
+
+impl ParityReport {
+    /// whether the host reproduced the firmware output within the diff tolerances
+    pub fn is_match(&self) -> bool {
+        self.diff.is_identical()
+    }
+
+    /// time of the first differing corner
+    pub fn first_divergence(&self) -> Option<SaeTime> {
+        self.diff.changes.iter().map(CornerChange::timestamp).min()
+    }
+
+    /// Platform issues change at most `platform_fraction` of the corners, or only
+    /// move them; anything more is taken as a configuration mismatch
+    pub fn likely_cause(&self, platform_fraction: f32) -> ParityCause {
+        let counts = self.diff.counts();
+        if self.is_match() {
+            ParityCause::None
+        } else if counts.added + counts.removed == 0 || counts.changed_fraction() <= platform_fraction {
+            ParityCause::Platform
+        } else {
+            ParityCause::Configuration
+        }
+    }
+}
+
+/// Replay `source` through `pipeline` and compare with the `firmware` corners
+pub fn check_parity<F, D, E>(pipeline: &mut StaticPipeline<F, D, Vec<SaeEvent>>, source: &mut E,
+                             firmware: &[SaeEvent], config: &DiffConfig) -> io::Result<ParityReport>
+    where F: EventFilter, D: CornerDetector, E: EventSource + ?Sized
+{
+    let before = pipeline.events_processed();
+    let host = replay_static(pipeline, source)?;
+    Ok(ParityReport {
+        events_replayed: pipeline.events_processed() - before,
+        host_corners: host.len(),
+        firmware_corners: firmware.len(),
+        diff: diff_corners(firmware, &host, config),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detector::{DetectorConfig, OnlineDetector};
+    use crate::sink::{CornerSink, CsvSink};
+    use crate::source::IterSource;
+    use crate::surface::WarmupConfig;
+
+    fn blocks() -> Vec<SaeEvent> {
+        let mut events = Vec::new();
+        for (idx, &(top, left)) in [(5u16, 5u16), (5, 20), (20, 5), (20, 20)].iter().enumerate() {
+            let t = 1_000 * (idx as SaeTime + 1);
+            for row in top..top + 5 {
+                for col in left..left + 5 {
+                    events.push(SaeEvent { row, col, polarity: 1, timestamp: t, ..SaeEvent::default() });
+                }
+            }
+            events.push(SaeEvent { row: top + 4, col: left + 4, polarity: 1, timestamp: t + 10, ..SaeEvent::default() });
+        }
+        events
+    }
+
+    fn pipeline(config: DetectorConfig) -> StaticPipeline<(), OnlineDetector<DetectorConfig>, Vec<SaeEvent>> {
+        StaticPipeline::new(OnlineDetector::new(config, 32, 32, WarmupConfig::disabled()), Vec::new())
+    }
+
+    #[test]
+    fn test_parity_with_firmware_log() {
+        let events = blocks();
+        // the firmware log, as written by a CSV sink on the device
+        let mut log = CsvSink::new(Vec::new()).unwrap();
+        let mut device = pipeline(DetectorConfig::default());
+        for corner in replay_static(&mut device, &mut IterSource::new(events.clone())).unwrap().iter() {
+            log.accept(corner);
+        }
+        let firmware = read_corner_log(&log.finish().unwrap()[..]).unwrap();
+        assert!(!firmware.is_empty());
+
+        let report = check_parity(&mut pipeline(DetectorConfig::default()), &mut IterSource::new(events.clone()),
+                                  &firmware, &DiffConfig::default()).unwrap();
+        assert_eq!(report.events_replayed, events.len() as u64);
+        assert_eq!(report.likely_cause(0.1), ParityCause::None);
+
+        // a device that dropped one corner
+        let report = check_parity(&mut pipeline(DetectorConfig::default()), &mut IterSource::new(events.clone()),
+                                  &firmware[1..], &DiffConfig::default()).unwrap();
+        assert_eq!(report.first_divergence(), Some(firmware[0].timestamp));
+        assert_eq!(report.likely_cause(0.5), ParityCause::Platform);
+
+        // a host configured differently
+        let config = DetectorConfig { min_border_inset: 8, ..DetectorConfig::default() };
+        let report = check_parity(&mut pipeline(config), &mut IterSource::new(events), &firmware, &DiffConfig::default()).unwrap();
+        assert_eq!(report.likely_cause(0.5), ParityCause::Configuration);
+
+        assert!(read_corner_log("1,2,3\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,311 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Whole-recording summaries, for triaging new datasets and sensor setups.
+//!
+//! `RecordingSummary::new` runs the pipeline and tracker over a recording, as one
+//! `eval::sweep` point, and collects the event and corner rates over time, the
+//! polarity balance, track lifetime statistics and survival, and coarse heatmaps of
+//! where events and corners fall on the sensor. The summary is written as JSON, with
+//! the run's `Manifest` echoing every parameter, or as a self-contained HTML page.
+
+use std::io::{self, Write};
+
+use crate::eval::manifest::Manifest;
+use crate::eval::sweep::{evaluate_point_detailed, SweepPoint};
+use crate::sae_types::*;
+use crate::surface::RecordingHeader;
+
+
+/// Resolution of the summary
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReportConfig {
+    /// width of the time bins of the rate timelines
+    pub bin_width: SaeTime,
+    /// side length (pixels) of the square heatmap cells
+    pub heatmap_cell: usize,
+    /// lifetime step of the track survival curve
+    pub survival_step: SaeTime,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        ReportConfig {
+            bin_width: 100_000,
+            heatmap_cell: 8,
+            survival_step: 10_000,
+        }
+    }
+}
+
+/// Counts per square cell of the sensor
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heatmap {
+    /// side length of the cells, in pixels
+    pub cell: usize,
+    pub rows: usize,
+    pub cols: usize,
+    /// counts in row-major order
+    pub counts: Vec<u64>,
+}
+
+impl Heatmap {
+    fn new(nrows: usize, ncols: usize, cell: usize) -> Self {
+        let cell = cell.max(1);
+        let rows = nrows.div_ceil(cell);
+        let cols = ncols.div_ceil(cell);
+        Heatmap { cell, rows, cols, counts: vec![0; rows * cols] }
+    }
+
+    fn add(&mut self, evt: &SaeEvent) {
+        let (row, col) = (evt.row as usize / self.cell, evt.col as usize / self.cell);
+        if row < self.rows && col < self.cols {
+            self.counts[row * self.cols + col] += 1;
+        }
+    }
+
+    /// count in the cell holding `(row, col)`, in cell units
+    pub fn get(&self, row: usize, col: usize) -> u64 {
+        self.counts[row * self.cols + col]
+    }
+
+    pub fn max(&self) -> u64 {
+        self.counts.iter().copied().max().unwrap_or(0)
+    }
+
+    fn to_json(&self) -> String {
+        let rows: Vec<String> = self.counts.chunks(self.cols.max(1)).map(|row| format!("[{}]", join(row))).collect();
+        format!("{{\"cell\":{},\"rows\":{},\"cols\":{},\"counts\":[{}]}}", self.cell, self.rows, self.cols, rows.join(","))
+    }
+
+    /// an SVG image of the heatmap, cells shaded by count
+    fn to_svg(&self, scale: usize) -> String {
+        let max = self.max().max(1) as f64;
+        let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">",
+                              self.cols * scale, self.rows * scale);
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                let shade = 255 - (255.0 * self.get(row, col) as f64 / max).round() as u8;
+                svg.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"rgb({},{},{})\"/>",
+                                      col * scale, row * scale, scale, scale, shade, shade, shade));
+            }
+        }
+        svg.push_str("</svg>");
+        svg
+    }
+}
+
+/// Summary of one processed recording
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordingSummary {
+    /// the run's parameters, dataset fingerprint and headline metrics
+    pub manifest: Manifest,
+    /// timestamp of the first event
+    pub start: SaeTime,
+    /// time from the first event to the last
+    pub duration: SaeTime,
+    pub on_events: u64,
+    pub off_events: u64,
+    pub bin_width: SaeTime,
+    /// events in each time bin from `start`
+    pub event_counts: Vec<u64>,
+    /// corners in each time bin from `start`
+    pub corner_counts: Vec<u64>,
+    pub median_lifetime: SaeTime,
+    pub max_lifetime: SaeTime,
+    /// (lifetime, fraction of tracks living at least that long)
+    pub survival: Vec<(SaeTime, f64)>,
+    pub event_heatmap: Heatmap,
+    pub corner_heatmap: Heatmap,
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// an SVG bar chart of the counts
+fn bar_chart_svg(counts: &[u64], height: usize) -> String {
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let bar = 4;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">", counts.len().max(1) * bar, height);
+    for (i, &count) in counts.iter().enumerate() {
+        let h = (height as f64 * count as f64 / max).round() as usize;
+        svg.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"steelblue\"/>",
+                              i * bar, height - h, bar - 1, h));
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+impl RecordingSummary {
+    /// Process `events` with the parameters of `point`, and summarize the run
+    pub fn new(header: &RecordingHeader, events: &[SaeEvent], point: &SweepPoint, config: &ReportConfig) -> Self {
+        let (result, corners, stability) = evaluate_point_detailed(header, events, point);
+        let start = events.first().map_or(0, |evt| evt.timestamp);
+        let duration = events.last().map_or(0, |evt| evt.timestamp.saturating_sub(start));
+        let bin_width = config.bin_width.max(1);
+        let bins = (duration / bin_width) as usize + 1;
+        let bin_of = |evt: &SaeEvent| ((evt.timestamp.saturating_sub(start) / bin_width) as usize).min(bins - 1);
+
+        let (nrows, ncols) = (header.nrows as usize, header.ncols as usize);
+        let mut event_counts = vec![0; bins];
+        let mut event_heatmap = Heatmap::new(nrows, ncols, config.heatmap_cell);
+        let mut on_events = 0;
+        for evt in events.iter() {
+            event_counts[bin_of(evt)] += 1;
+            event_heatmap.add(evt);
+            on_events += (evt.polarity > 0) as u64;
+        }
+        let mut corner_counts = vec![0; bins];
+        let mut corner_heatmap = Heatmap::new(nrows, ncols, config.heatmap_cell);
+        for corner in corners.iter() {
+            corner_counts[bin_of(corner)] += 1;
+            corner_heatmap.add(corner);
+        }
+
+        RecordingSummary {
+            manifest: Manifest::new(header, events, result),
+            start,
+            duration,
+            on_events,
+            off_events: events.len() as u64 - on_events,
+            bin_width,
+            event_counts,
+            corner_counts,
+            median_lifetime: stability.median_lifetime(),
+            max_lifetime: stability.max_lifetime(),
+            survival: stability.survival_curve(config.survival_step),
+            event_heatmap,
+            corner_heatmap,
+        }
+    }
+
+    /// fraction of events with ON polarity
+    pub fn on_fraction(&self) -> f64 {
+        let total = self.on_events + self.off_events;
+        if total == 0 { 0.0 } else { self.on_events as f64 / total as f64 }
+    }
+
+    pub fn to_json(&self) -> String {
+        let survival: Vec<String> = self.survival.iter().map(|(t, frac)| format!("[{},{}]", t, frac)).collect();
+        format!(concat!(
+            "{{\"manifest\":{},\n",
+            "\"start\":{},\"duration\":{},\"on_events\":{},\"off_events\":{},\n",
+            "\"bin_width\":{},\"event_counts\":[{}],\"corner_counts\":[{}],\n",
+            "\"median_lifetime\":{},\"max_lifetime\":{},\"survival\":[{}],\n",
+            "\"event_heatmap\":{},\n\"corner_heatmap\":{}}}\n"),
+            self.manifest.to_json().trim_end(),
+            self.start, self.duration, self.on_events, self.off_events,
+            self.bin_width, join(&self.event_counts), join(&self.corner_counts),
+            self.median_lifetime, self.max_lifetime, survival.join(","),
+            self.event_heatmap.to_json(), self.corner_heatmap.to_json())
+    }
+
+    pub fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.to_json().as_bytes())
+    }
+
+    /// A self-contained HTML page: parameter and metric tables, rate timelines and heatmaps
+    pub fn to_html(&self) -> String {
+        let r = &self.manifest.result;
+        let p = &r.point;
+        let header = &self.manifest.header;
+        let rows = [
+            ("sensor".to_string(), format!("{} x {}", header.ncols, header.nrows)),
+            ("events".to_string(), format!("{} ({} ON, {} OFF)", r.events_processed, self.on_events, self.off_events)),
+            ("duration".to_string(), format!("{:.3} s", self.duration as f64 / 1e6)),
+            ("dataset crc32".to_string(), format!("{:08x}", self.manifest.dataset.crc32)),
+            ("detector".to_string(), p.detector_label.clone()),
+            ("inner ring".to_string(), format!("{} px, arcs {}..={}", p.detector.inner.len(), p.detector.inner.min_arc_len(), p.detector.inner.max_arc_len())),
+            ("outer ring".to_string(), format!("{} px, arcs {}..={}", p.detector.outer.len(), p.detector.outer.min_arc_len(), p.detector.outer.max_arc_len())),
+            ("denoise window".to_string(), p.denoise_window.map_or("none".to_string(), |window| format!("{} us", window))),
+            ("tracker".to_string(), format!("match radius {}, max gap {} us, min likeness {}", p.tracker.match_radius, p.tracker.max_gap, p.tracker.min_likeness)),
+            ("events filtered".to_string(), r.events_filtered.to_string()),
+            ("corners".to_string(), format!("{} ({:.1} /s)", r.corners, r.corner_rate)),
+            ("tracks".to_string(), r.tracks.to_string()),
+            ("track lifetime".to_string(), format!("mean {:.0} us, median {} us, max {} us", r.mean_lifetime, self.median_lifetime, self.max_lifetime)),
+            ("singleton tracks".to_string(), format!("{:.1}%", 100.0 * r.singleton_fraction)),
+            ("re-detection rate".to_string(), format!("{:.1} Hz", r.mean_redetection_rate)),
+            ("crate version".to_string(), self.manifest.version.clone()),
+        ];
+        let table: String = rows.iter()
+            .map(|(name, value)| format!("<tr><th>{}</th><td>{}</td></tr>\n", name, html_escape(value)))
+            .collect();
+        let scale = (320 / self.event_heatmap.cols.max(1)).max(1);
+        format!(concat!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>arcstar recording summary</title>\n",
+            "<style>body{{font-family:sans-serif}} th{{text-align:left;padding-right:1em}} figure{{display:inline-block}}</style>\n",
+            "</head><body>\n<h1>Recording summary</h1>\n<table>\n{}</table>\n",
+            "<h2>Event rate</h2>\n<p>events per {} us bin</p>\n{}\n",
+            "<h2>Corner rate</h2>\n<p>corners per {} us bin</p>\n{}\n",
+            "<h2>Heatmaps</h2>\n<p>counts per {} px cell</p>\n",
+            "<figure>{}<figcaption>events</figcaption></figure>\n",
+            "<figure>{}<figcaption>corners</figcaption></figure>\n",
+            "</body></html>\n"),
+            table,
+            self.bin_width, bar_chart_svg(&self.event_counts, 120),
+            self.bin_width, bar_chart_svg(&self.corner_counts, 120),
+            self.event_heatmap.cell,
+            self.event_heatmap.to_svg(scale), self.corner_heatmap.to_svg(scale))
+    }
+
+    pub fn write_html<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.to_html().as_bytes())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surface::WarmupConfig;
+
+    #[test]
+    fn test_summary() {
+        let header = RecordingHeader::new(48, 48, WarmupConfig::disabled());
+        // a bright square moving diagonally, ON at its leading edges
+        let mut events = Vec::new();
+        for step in 0..20u16 {
+            let t = 1_000 + step as SaeTime * 1_000;
+            for i in 0..8u16 {
+                for &(row, col) in [(10 + step, 10 + step + i), (10 + step + i, 10 + step)].iter() {
+                    events.push(SaeEvent { row, col, polarity: 1, timestamp: t + i as SaeTime, ..SaeEvent::default() });
+                }
+            }
+            events.push(SaeEvent { row: 2, col: 40, polarity: 0, timestamp: t + 20, ..SaeEvent::default() });
+        }
+        let config = ReportConfig { bin_width: 5_000, heatmap_cell: 16, survival_step: 1_000 };
+        let point = SweepPoint {
+            detector_label: "default".to_string(),
+            detector: Default::default(),
+            denoise_window: None,
+            tracker: Default::default(),
+        };
+        let summary = RecordingSummary::new(&header, &events, &point, &config);
+
+        assert_eq!((summary.start, summary.duration), (1_000, 19_020));
+        assert_eq!((summary.on_events, summary.off_events), (320, 20));
+        assert_eq!(summary.event_counts.len(), 4);
+        assert_eq!(summary.event_counts.iter().sum::<u64>(), events.len() as u64);
+        assert_eq!(summary.corner_counts.iter().sum::<u64>(), summary.manifest.result.corners);
+        assert!(summary.manifest.result.corners > 0);
+        assert_eq!((summary.event_heatmap.rows, summary.event_heatmap.cols), (3, 3));
+        assert_eq!(summary.event_heatmap.get(0, 2), 20);
+        assert_eq!(summary.corner_heatmap.counts.iter().sum::<u64>(), summary.manifest.result.corners);
+
+        let json = summary.to_json();
+        assert!(json.starts_with("{\"manifest\":{\"crate\":\"arcstar\""));
+        assert!(json.contains("\"on_events\":320,\"off_events\":20"));
+        assert!(json.contains("\"event_heatmap\":{\"cell\":16,\"rows\":3,\"cols\":3,\"counts\":[["));
+        let html = summary.to_html();
+        assert!(html.contains("<th>events</th><td>340 (320 ON, 20 OFF)</td>"));
+        assert_eq!(html.matches("<svg").count(), 4);
+        // the embedded manifest still reproduces the run
+        let rerun = summary.manifest.rerun(&events).unwrap();
+        assert!(summary.manifest.reproduces(&rerun));
+    }
+}
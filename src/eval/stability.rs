@@ -0,0 +1,202 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Corner lifetime and stability analytics over a `TrackStore` history,
+//! for comparing detector stability across parameter sets.
+//! Stable detectors produce long-lived tracks that are re-detected often.
+
+use std::io::{self, Write};
+
+use crate::sae_types::*;
+use crate::time::EventTime;
+use crate::track::{Track, TrackId, TrackStore};
+
+
+/// Stability figures of a single track
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackStability {
+    pub track_id: TrackId,
+    pub start: SaeTime,
+    pub lifetime: SaeTime,
+    pub observations: usize,
+    /// re-detections per second over the track lifetime (0 for single-observation tracks)
+    pub redetection_rate: f64,
+}
+
+impl TrackStability {
+    pub fn from_track(track: &Track) -> Self {
+        let lifetime = track.lifetime();
+        let secs = EventTime::from_sae_time(lifetime).as_secs_f64();
+        let redetection_rate = if secs > 0.0 {
+            ((track.len() - 1) as f64) / secs
+        } else {
+            0.0
+        };
+        TrackStability {
+            track_id: track.id,
+            start: track.first().timestamp,
+            lifetime,
+            observations: track.len(),
+            redetection_rate,
+        }
+    }
+}
+
+/// Aggregate lifetime statistics over all tracks of a recording
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StabilityReport {
+    pub tracks: Vec<TrackStability>,
+}
+
+impl StabilityReport {
+    pub fn from_store(store: &TrackStore) -> Self {
+        StabilityReport {
+            tracks: store.iter().map(TrackStability::from_track).collect(),
+        }
+    }
+
+    pub fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn mean_lifetime(&self) -> f64 {
+        if self.tracks.is_empty() {
+            return 0.0;
+        }
+        self.tracks.iter().map(|t| t.lifetime as f64).sum::<f64>() / (self.tracks.len() as f64)
+    }
+
+    pub fn median_lifetime(&self) -> SaeTime {
+        let mut lifetimes: Vec<SaeTime> = self.tracks.iter().map(|t| t.lifetime).collect();
+        if lifetimes.is_empty() {
+            return 0;
+        }
+        lifetimes.sort_unstable();
+        lifetimes[lifetimes.len() / 2]
+    }
+
+    pub fn max_lifetime(&self) -> SaeTime {
+        self.tracks.iter().map(|t| t.lifetime).max().unwrap_or(0)
+    }
+
+    /// mean re-detection rate (Hz) over tracks that were re-detected at least once
+    pub fn mean_redetection_rate(&self) -> f64 {
+        let rates: Vec<f64> = self.tracks.iter()
+            .filter(|t| t.observations > 1 && t.lifetime > 0)
+            .map(|t| t.redetection_rate)
+            .collect();
+        if rates.is_empty() {
+            return 0.0;
+        }
+        rates.iter().sum::<f64>() / (rates.len() as f64)
+    }
+
+    /// fraction of tracks that were never re-detected
+    pub fn singleton_fraction(&self) -> f64 {
+        if self.tracks.is_empty() {
+            return 0.0;
+        }
+        let singletons = self.tracks.iter().filter(|t| t.observations == 1).count();
+        (singletons as f64) / (self.tracks.len() as f64)
+    }
+
+    /// Survival curve: for each multiple of `step` up to the longest lifetime,
+    /// the fraction of tracks that lived at least that long
+    pub fn survival_curve(&self, step: SaeTime) -> Vec<(SaeTime, f64)> {
+        if self.tracks.is_empty() || step == 0 {
+            return Vec::new();
+        }
+        let total = self.tracks.len() as f64;
+        let max = self.max_lifetime();
+        let mut curve = Vec::new();
+        let mut t: SaeTime = 0;
+        loop {
+            let alive = self.tracks.iter().filter(|trk| trk.lifetime >= t).count();
+            curve.push((t, (alive as f64) / total));
+            if t >= max {
+                break;
+            }
+            t = t.saturating_add(step);
+        }
+        curve
+    }
+
+    /// Write per-track figures as CSV:
+    /// `track_id,start,lifetime,observations,redetection_rate`
+    pub fn write_tracks_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "track_id,start,lifetime,observations,redetection_rate")?;
+        for t in self.tracks.iter() {
+            writeln!(writer, "{},{},{},{},{:.3}", t.track_id, t.start, t.lifetime, t.observations, t.redetection_rate)?;
+        }
+        Ok(())
+    }
+
+    /// Write the survival curve as CSV: `lifetime,survival`
+    pub fn write_survival_csv<W: Write>(&self, step: SaeTime, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "lifetime,survival")?;
+        for (t, frac) in self.survival_curve(step) {
+            writeln!(writer, "{},{:.4}", t, frac)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corner_at(col: u16, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row: 1, col, timestamp, ..SaeEvent::default() }
+    }
+
+    fn sample_store() -> TrackStore {
+        let mut store = TrackStore::new();
+        // lives 1 second, re-detected 4 times
+        let a = store.start_track(corner_at(1, 0));
+        for i in 1..5 {
+            store.extend_track(a, corner_at(1, i * 250_000));
+        }
+        // lives half a second, re-detected once
+        let b = store.start_track(corner_at(5, 100));
+        store.extend_track(b, corner_at(6, 500_100));
+        // never re-detected
+        store.start_track(corner_at(9, 200));
+        store
+    }
+
+    #[test]
+    fn test_stability_report() {
+        let report = StabilityReport::from_store(&sample_store());
+        assert_eq!(report.track_count(), 3);
+        assert_eq!(report.max_lifetime(), 1_000_000);
+        assert_eq!(report.median_lifetime(), 500_000);
+        assert!((report.mean_lifetime() - 500_000.0).abs() < 1e-6);
+        assert!((report.tracks[0].redetection_rate - 4.0).abs() < 1e-9);
+        assert!((report.mean_redetection_rate() - 3.0).abs() < 1e-9);
+        assert!((report.singleton_fraction() - 1.0 / 3.0).abs() < 1e-9);
+
+        let curve = report.survival_curve(500_000);
+        assert_eq!(curve.len(), 3);
+        assert_eq!(curve[0], (0, 1.0));
+        assert!((curve[1].1 - 2.0 / 3.0).abs() < 1e-9);
+        assert!((curve[2].1 - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let report = StabilityReport::from_store(&sample_store());
+        let mut out = Vec::new();
+        report.write_tracks_csv(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "track_id,start,lifetime,observations,redetection_rate");
+        assert_eq!(lines[1], "0,0,1000000,5,4.000");
+        assert_eq!(lines[3], "2,200,0,1,0.000");
+
+        let mut out = Vec::new();
+        report.write_survival_csv(500_000, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text, "lifetime,survival\n0,1.0000\n500000,0.6667\n1000000,0.3333\n");
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Parameter sweeps: the detector and tracker run over a recording once for every
+//! point of a grid of parameters, in parallel, with the resulting metrics collected
+//! into a table for comparison.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use crate::detector::DetectorConfig;
+use crate::drops::DropReason;
+use crate::eval::stability::StabilityReport;
+use crate::noise::RowColumnDenoiser;
+use crate::pipeline::Pipeline;
+use crate::sae_types::*;
+use crate::surface::RecordingHeader;
+use crate::track::{CornerTracker, TrackerConfig};
+
+
+/// One set of parameters to evaluate
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepPoint {
+    /// names the detector configuration in the results table
+    pub detector_label: String,
+    pub detector: DetectorConfig,
+    /// support window of a `RowColumnDenoiser` run before detection; None for no filtering
+    pub denoise_window: Option<SaeTime>,
+    pub tracker: TrackerConfig,
+}
+
+/// The values to sweep for each parameter; every combination is evaluated
+#[derive(Clone, Debug)]
+pub struct ParameterGrid {
+    pub detectors: Vec<(String, DetectorConfig)>,
+    pub denoise_windows: Vec<Option<SaeTime>>,
+    pub trackers: Vec<TrackerConfig>,
+}
+
+impl Default for ParameterGrid {
+    /// A grid holding only the default configuration
+    fn default() -> Self {
+        ParameterGrid {
+            detectors: vec![("default".to_string(), DetectorConfig::default())],
+            denoise_windows: vec![None],
+            trackers: vec![TrackerConfig::default()],
+        }
+    }
+}
+
+impl ParameterGrid {
+    /// all combinations, detectors varying slowest
+    pub fn points(&self) -> Vec<SweepPoint> {
+        let mut points = Vec::new();
+        for (label, detector) in self.detectors.iter() {
+            for &denoise_window in self.denoise_windows.iter() {
+                for tracker in self.trackers.iter() {
+                    points.push(SweepPoint {
+                        detector_label: label.clone(),
+                        detector: detector.clone(),
+                        denoise_window,
+                        tracker: tracker.clone(),
+                    });
+                }
+            }
+        }
+        points
+    }
+}
+
+/// Metrics of one sweep point
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepResult {
+    pub point: SweepPoint,
+    pub events_processed: u64,
+    /// events removed by the denoiser
+    pub events_filtered: u64,
+    pub corners: u64,
+    /// corners per second of recording
+    pub corner_rate: f64,
+    pub tracks: usize,
+    /// mean track lifetime, microseconds
+    pub mean_lifetime: f64,
+    pub singleton_fraction: f64,
+    pub mean_redetection_rate: f64,
+    /// wall-clock time of the run, seconds
+    pub elapsed: f64,
+}
+
+/// Run the pipeline and tracker over `events` with the parameters of `point`
+pub fn evaluate_point(header: &RecordingHeader, events: &[SaeEvent], point: &SweepPoint) -> SweepResult {
+    evaluate_point_detailed(header, events, point).0
+}
+
+/// Like `evaluate_point`, also returning the detected corners and the track statistics
+pub fn evaluate_point_detailed(header: &RecordingHeader, events: &[SaeEvent], point: &SweepPoint) -> (SweepResult, Vec<SaeEvent>, StabilityReport) {
+    let started = Instant::now();
+    let mut pipeline = Pipeline::new(header, Vec::new());
+    pipeline.set_detector_config(point.detector.clone());
+    if let Some(window) = point.denoise_window {
+        pipeline.add_filter(RowColumnDenoiser::new(header.nrows as usize, header.ncols as usize, window));
+    }
+    pipeline.run(events.iter().cloned());
+    let events_processed = pipeline.events_processed();
+    let events_filtered = pipeline.drops().count(DropReason::Filtered);
+    let corners = pipeline.into_sink();
+
+    let mut tracker = CornerTracker::new(point.tracker.clone());
+    for corner in corners.iter() {
+        tracker.add_corner(corner);
+    }
+    let stability = StabilityReport::from_store(tracker.store());
+
+    let span = match (events.first(), events.last()) {
+        (Some(first), Some(last)) => last.timestamp.saturating_sub(first.timestamp) as f64 / 1e6,
+        _ => 0.0,
+    };
+    let result = SweepResult {
+        point: point.clone(),
+        events_processed,
+        events_filtered,
+        corners: corners.len() as u64,
+        corner_rate: if span > 0.0 { corners.len() as f64 / span } else { 0.0 },
+        tracks: stability.track_count(),
+        mean_lifetime: stability.mean_lifetime(),
+        singleton_fraction: stability.singleton_fraction(),
+        mean_redetection_rate: stability.mean_redetection_rate(),
+        elapsed: started.elapsed().as_secs_f64(),
+    };
+    (result, corners, stability)
+}
+
+/// Evaluate every point on up to `threads` threads, returning results in the order of `points`
+pub fn run_sweep(header: &RecordingHeader, events: &[SaeEvent], points: &[SweepPoint], threads: usize) -> Vec<SweepResult> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; points.len()]);
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, points.len().max(1)) {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                if idx >= points.len() {
+                    break;
+                }
+                let result = evaluate_point(header, events, &points[idx]);
+                results.lock().unwrap()[idx] = Some(result);
+            });
+        }
+    });
+    results.into_inner().unwrap().into_iter().map(|result| result.unwrap()).collect()
+}
+
+/// Write the results as CSV: `detector,denoise_window,match_radius,max_gap,min_likeness,
+/// events,filtered,corners,corner_rate,tracks,mean_lifetime,singleton_fraction,redetection_rate,elapsed`,
+/// with an empty `denoise_window` for unfiltered runs
+pub fn write_csv<W: Write>(results: &[SweepResult], mut writer: W) -> io::Result<()> {
+    writeln!(writer, "detector,denoise_window,match_radius,max_gap,min_likeness,events,filtered,corners,\
+                      corner_rate,tracks,mean_lifetime,singleton_fraction,redetection_rate,elapsed")?;
+    for r in results.iter() {
+        let window = r.point.denoise_window.map_or(String::new(), |window| window.to_string());
+        let tracker = &r.point.tracker;
+        writeln!(writer, "{},{},{},{},{},{},{},{},{:.3},{},{:.1},{:.4},{:.3},{:.3}",
+                 r.point.detector_label, window, tracker.match_radius, tracker.max_gap, tracker.min_likeness,
+                 r.events_processed, r.events_filtered, r.corners, r.corner_rate, r.tracks,
+                 r.mean_lifetime, r.singleton_fraction, r.mean_redetection_rate, r.elapsed)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circle::CircleSpec;
+    use crate::surface::WarmupConfig;
+
+    /// a bright square moving diagonally, with scattered noise events
+    fn recording() -> Vec<SaeEvent> {
+        let mut events = Vec::new();
+        for step in 0..20u16 {
+            let t = 1_000 + step as SaeTime * 1_000;
+            for i in 0..8u16 {
+                for &(row, col) in [(10 + step, 10 + step + i), (10 + step + i, 10 + step)].iter() {
+                    events.push(SaeEvent { row, col, polarity: 1, timestamp: t + i as SaeTime, ..SaeEvent::default() });
+                }
+            }
+            let noise = (step * 7) % 48;
+            events.push(SaeEvent { row: 47 - noise, col: noise, polarity: 1, timestamp: t + 20, ..SaeEvent::default() });
+        }
+        events
+    }
+
+    #[test]
+    fn test_grid_points() {
+        let grid = ParameterGrid {
+            detectors: vec![
+                ("c3c4".to_string(), DetectorConfig::default()),
+                ("c4c5".to_string(), DetectorConfig::new(CircleSpec::c4(), CircleSpec::circle(5).unwrap())),
+            ],
+            denoise_windows: vec![None, Some(5_000)],
+            trackers: vec![TrackerConfig::default(), TrackerConfig { match_radius: 5.0, ..TrackerConfig::default() }],
+        };
+        let points = grid.points();
+        assert_eq!(points.len(), 8);
+        assert_eq!(points[0].detector_label, "c3c4");
+        assert_eq!(points[7].detector_label, "c4c5");
+        assert_eq!(points[1].tracker.match_radius, 5.0);
+        assert_eq!(points[2].denoise_window, Some(5_000));
+    }
+
+    #[test]
+    fn test_parallel_sweep_matches_serial() {
+        let header = RecordingHeader::new(48, 48, WarmupConfig::disabled());
+        let events = recording();
+        let grid = ParameterGrid { denoise_windows: vec![None, Some(2_000)], ..ParameterGrid::default() };
+        let points = grid.points();
+        let results = run_sweep(&header, &events, &points, 4);
+        assert_eq!(results.len(), 2);
+        for (result, point) in results.iter().zip(points.iter()) {
+            let serial = evaluate_point(&header, &events, point);
+            assert_eq!(&result.point, point);
+            assert_eq!((result.corners, result.tracks, result.events_filtered), (serial.corners, serial.tracks, serial.events_filtered));
+        }
+        assert_eq!(results[0].events_processed, events.len() as u64);
+        assert_eq!(results[0].events_filtered, 0);
+        assert!(results[0].corners > 0);
+        assert!(results[1].events_filtered > 0);
+
+        let mut csv = Vec::new();
+        write_csv(&results, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.lines().nth(1).unwrap().starts_with("default,,3,"));
+        assert!(csv.lines().nth(2).unwrap().starts_with("default,2000,3,"));
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Fault injection for robustness testing: corrupt an event stream the way a glitchy
+//! sensor or link would, by dropping bursts of events, duplicating events, jittering
+//! timestamps and flipping polarities. All faults are drawn from a seeded generator,
+//! so a failing run can be replayed exactly.
+
+use std::collections::VecDeque;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::sae_types::*;
+
+
+/// Rates of each kind of fault; all default to zero, passing events through unchanged
+#[derive(Clone, Debug, PartialEq)]
+pub struct FaultConfig {
+    /// probability (0..1), per event, of starting a burst of dropped events
+    pub burst_probability: f32,
+    /// number of consecutive events lost in each burst
+    pub burst_length: usize,
+    /// probability (0..1) that an event is delivered twice
+    pub duplicate_probability: f32,
+    /// timestamps are moved by up to this much either way
+    pub jitter: SaeTime,
+    /// probability (0..1) that an event's polarity is inverted
+    pub flip_probability: f32,
+    /// seed for the fault generator, so that faults are reproducible
+    pub seed: u64,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        FaultConfig {
+            burst_probability: 0.0,
+            burst_length: 100,
+            duplicate_probability: 0.0,
+            jitter: 0,
+            flip_probability: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+/// Counts of injected faults
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FaultStats {
+    pub events_in: u64,
+    pub bursts: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub jittered: u64,
+    pub flipped: u64,
+}
+
+/// Applies faults to events one at a time
+pub struct FaultInjector {
+    config: FaultConfig,
+    rng: StdRng,
+    burst_remaining: usize,
+    stats: FaultStats,
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultConfig) -> Self {
+        FaultInjector {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            burst_remaining: 0,
+            stats: FaultStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> &FaultStats {
+        &self.stats
+    }
+
+    fn chance(&mut self, probability: f32) -> bool {
+        probability > 0.0 && self.rng.gen::<f32>() < probability
+    }
+
+    /// Apply faults to one event, appending what gets delivered (zero, one or two events) to `out`
+    pub fn inject(&mut self, evt: &SaeEvent, out: &mut Vec<SaeEvent>) {
+        self.stats.events_in += 1;
+        if self.burst_remaining == 0 && self.config.burst_length > 0 && self.chance(self.config.burst_probability) {
+            self.burst_remaining = self.config.burst_length;
+            self.stats.bursts += 1;
+        }
+        if self.burst_remaining > 0 {
+            self.burst_remaining -= 1;
+            self.stats.dropped += 1;
+            return;
+        }
+
+        let mut faulty = evt.clone();
+        if self.config.jitter > 0 {
+            let jitter = self.config.jitter as i64;
+            let offset = self.rng.gen_range(-jitter, jitter + 1);
+            if offset != 0 {
+                let shifted = (faulty.timestamp as i64 + offset).max(0).min(SaeTime::MAX as i64);
+                faulty.timestamp = shifted as SaeTime;
+                self.stats.jittered += 1;
+            }
+        }
+        if self.chance(self.config.flip_probability) {
+            faulty.polarity = if faulty.polarity > 0 { 0 } else { 1 };
+            self.stats.flipped += 1;
+        }
+        if self.chance(self.config.duplicate_probability) {
+            out.push(faulty.clone());
+            self.stats.duplicated += 1;
+        }
+        out.push(faulty);
+    }
+}
+
+/// Iterator adapter yielding the events of a stream with faults injected
+pub struct Faulty<I> {
+    inner: I,
+    injector: FaultInjector,
+    pending: VecDeque<SaeEvent>,
+    scratch: Vec<SaeEvent>,
+}
+
+impl<I> Faulty<I> {
+    pub fn stats(&self) -> &FaultStats {
+        self.injector.stats()
+    }
+}
+
+impl<I: Iterator<Item = SaeEvent>> Iterator for Faulty<I> {
+    type Item = SaeEvent;
+
+    fn next(&mut self) -> Option<SaeEvent> {
+        while self.pending.is_empty() {
+            let evt = self.inner.next()?;
+            self.injector.inject(&evt, &mut self.scratch);
+            self.pending.extend(self.scratch.drain(..));
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// Inject faults into an event stream
+pub fn inject_faults<I>(events: I, config: FaultConfig) -> Faulty<I::IntoIter>
+    where I: IntoIterator<Item = SaeEvent>
+{
+    Faulty {
+        inner: events.into_iter(),
+        injector: FaultInjector::new(config),
+        pending: VecDeque::new(),
+        scratch: Vec::new(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events() -> Vec<SaeEvent> {
+        (0..10_000u32).map(|t| SaeEvent { timestamp: 1_000 + t * 10, ..SaeEvent::default() }).collect()
+    }
+
+    #[test]
+    fn test_default_passes_through() {
+        let out: Vec<SaeEvent> = inject_faults(events(), FaultConfig::default()).collect();
+        assert_eq!(out.len(), 10_000);
+        assert!(out.iter().zip(events().iter()).all(|(a, b)| a.key() == b.key()));
+    }
+
+    #[test]
+    fn test_faults_are_reproducible() {
+        let config = FaultConfig {
+            burst_probability: 0.001,
+            burst_length: 50,
+            duplicate_probability: 0.01,
+            jitter: 3,
+            flip_probability: 0.05,
+            seed: 11,
+        };
+        let mut faulty = inject_faults(events(), config.clone());
+        let out: Vec<SaeEvent> = faulty.by_ref().collect();
+        let stats = faulty.stats().clone();
+        assert!(stats.bursts > 0 && stats.dropped <= stats.bursts * 50);
+        assert!(stats.duplicated > 50 && stats.duplicated < 150, "{:?}", stats);
+        assert!(stats.flipped > 350 && stats.flipped < 650, "{:?}", stats);
+        assert_eq!(out.len() as u64, stats.events_in - stats.dropped + stats.duplicated);
+        assert!(out.iter().all(|evt| evt.timestamp >= 997));
+
+        let replay: Vec<SaeEvent> = inject_faults(events(), config).collect();
+        assert_eq!(replay.len(), out.len());
+        assert!(replay.iter().zip(out.iter()).all(|(a, b)| a.key() == b.key()));
+    }
+}
@@ -0,0 +1,397 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Detection and decoding of square binary fiducial markers from events.
+//!
+//! A marker is a square grid of cells: a bright border one cell wide around a
+//! `bits` x `bits` data grid of bright (1) and dark (0) cells, as shown on an
+//! active display or an illuminated print. When the marker appears or flashes,
+//! its bright cells fire ON events while its dark cells stay quiet, and the
+//! outer corners of the bright border are detected as corner events.
+//!
+//! Candidate quads are formed from recent corners, then each cell is read from
+//! the dominant polarity of recent events sampled through the homography from
+//! the marker grid to the quad. Quads whose border reads bright and whose data
+//! bits match a dictionary code in one of the four orientations become observations.
+
+use nalgebra::DMatrix;
+
+use crate::calib::homography::Homography;
+use crate::sae_types::*;
+
+
+/// A set of marker codes. Code bits are the data cells in row-major order,
+/// least significant bit first, with 1 for a bright cell.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MarkerDictionary {
+    bits: usize,
+    codes: Vec<u64>,
+}
+
+impl MarkerDictionary {
+    /// Returns None if the data grid does not fit in a u64
+    pub fn new(bits: usize, codes: Vec<u64>) -> Option<Self> {
+        if bits == 0 || bits * bits > 64 {
+            return None;
+        }
+        Some(MarkerDictionary { bits, codes })
+    }
+
+    /// side length of the data grid, in cells
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    pub fn codes(&self) -> &[u64] {
+        &self.codes
+    }
+
+    /// The cell values of marker `id`, including the border, row-major
+    pub fn marker_cells(&self, id: usize) -> Option<Vec<bool>> {
+        let code = *self.codes.get(id)?;
+        let side = self.bits + 2;
+        let cells = (0..side * side)
+            .map(|idx| {
+                let (r, c) = (idx / side, idx % side);
+                if r == 0 || c == 0 || r == side - 1 || c == side - 1 {
+                    true
+                } else {
+                    code & (1 << ((r - 1) * self.bits + (c - 1))) != 0
+                }
+            })
+            .collect();
+        Some(cells)
+    }
+
+    /// the closest code to `observed`, and its Hamming distance
+    fn closest(&self, observed: u64) -> Option<(usize, u32)> {
+        self.codes.iter()
+            .map(|code| (code ^ observed).count_ones())
+            .enumerate()
+            .min_by_key(|&(_, dist)| dist)
+    }
+}
+
+/// Parameters of marker detection
+#[derive(Clone, Debug, PartialEq)]
+pub struct FiducialConfig {
+    /// events and corners older than this are ignored
+    pub window: SaeTime,
+    /// minimum side length of a marker in the image, in pixels
+    pub min_side: f32,
+    /// allowed mismatch between a quad's predicted fourth corner and a detected corner,
+    /// as a fraction of the side length
+    pub corner_tolerance: f32,
+    /// maximum number of data bits that may differ from the matched code
+    pub max_hamming: u32,
+}
+
+impl Default for FiducialConfig {
+    fn default() -> Self {
+        FiducialConfig {
+            window: 20_000,
+            min_side: 8.0,
+            corner_tolerance: 0.25,
+            max_hamming: 0,
+        }
+    }
+}
+
+/// A decoded marker
+#[derive(Clone, Debug, PartialEq)]
+pub struct MarkerObservation {
+    /// index of the matched code in the dictionary
+    pub id: usize,
+    /// image positions (x = column, y = row) of the marker's top-left, top-right,
+    /// bottom-right and bottom-left corners, in the marker's own frame
+    pub corners: [[f32; 2]; 4],
+    /// number of data bits that differed from the code
+    pub hamming: u32,
+    pub timestamp: SaeTime,
+}
+
+/// Sample points per cell side when reading a cell
+const CELL_SAMPLES: usize = 3;
+
+/// Accumulates events and corners, and decodes markers from them
+pub struct FiducialDetector {
+    config: FiducialConfig,
+    dictionary: MarkerDictionary,
+    /// most recent event timestamp per pixel, one plane per polarity (OFF, ON)
+    latest: [DMatrix<SaeTime>; 2],
+    corners: Vec<([f32; 2], SaeTime)>,
+}
+
+impl FiducialDetector {
+    pub fn new(nrows: usize, ncols: usize, config: FiducialConfig, dictionary: MarkerDictionary) -> Self {
+        FiducialDetector {
+            config,
+            dictionary,
+            latest: [DMatrix::zeros(nrows, ncols), DMatrix::zeros(nrows, ncols)],
+            corners: Vec::new(),
+        }
+    }
+
+    pub fn dictionary(&self) -> &MarkerDictionary {
+        &self.dictionary
+    }
+
+    pub fn add_event(&mut self, evt: &SaeEvent) {
+        let plane = if evt.polarity > 0 { 1 } else { 0 };
+        let (row, col) = (evt.row as usize, evt.col as usize);
+        if row < self.latest[plane].nrows() && col < self.latest[plane].ncols() {
+            self.latest[plane][(row, col)] = evt.timestamp;
+        }
+    }
+
+    pub fn add_corner(&mut self, corner: &SaeEvent) {
+        let (row, col) = corner.subpixel_position();
+        self.corners.push(([col, row], corner.timestamp));
+    }
+
+    /// Decode all markers visible at time `now`, forgetting corners older than the window
+    pub fn detect(&mut self, now: SaeTime) -> Vec<MarkerObservation> {
+        let horizon = now.saturating_sub(self.config.window);
+        self.corners.retain(|&(_, t)| t >= horizon);
+        let points: Vec<[f32; 2]> = self.corners.iter().map(|&(p, _)| p).collect();
+
+        let mut observations: Vec<MarkerObservation> = Vec::new();
+        for quad in self.find_quads(&points) {
+            if let Some(obs) = self.decode(&quad, horizon, now) {
+                let duplicate = observations.iter().any(|other| {
+                    other.id == obs.id && dist2(&other.corners[0], &obs.corners[0]) < self.config.min_side * self.config.min_side
+                });
+                if !duplicate {
+                    observations.push(obs);
+                }
+            }
+        }
+        observations
+    }
+
+    /// Quads of corners, clockwise in the image, approximately parallelograms
+    fn find_quads(&self, points: &[[f32; 2]]) -> Vec<[[f32; 2]; 4]> {
+        let min_side2 = self.config.min_side * self.config.min_side;
+        let mut quads = Vec::new();
+        let mut seen: Vec<[usize; 4]> = Vec::new();
+        for (a, pa) in points.iter().enumerate() {
+            for (b, pb) in points.iter().enumerate() {
+                let ab = [pb[0] - pa[0], pb[1] - pa[1]];
+                let len_ab2 = ab[0] * ab[0] + ab[1] * ab[1];
+                if b == a || len_ab2 < min_side2 {
+                    continue;
+                }
+                for (d, pd) in points.iter().enumerate() {
+                    let ad = [pd[0] - pa[0], pd[1] - pa[1]];
+                    let len_ad2 = ad[0] * ad[0] + ad[1] * ad[1];
+                    if d == a || d == b || len_ad2 < min_side2 {
+                        continue;
+                    }
+                    // clockwise in image coordinates (y down), and roughly square
+                    let cross = ab[0] * ad[1] - ab[1] * ad[0];
+                    if cross < 0.5 * len_ab2.sqrt() * len_ad2.sqrt() || len_ab2 > 4.0 * len_ad2 || len_ad2 > 4.0 * len_ab2 {
+                        continue;
+                    }
+                    let predicted = [pb[0] + pd[0] - pa[0], pb[1] + pd[1] - pa[1]];
+                    let tolerance = self.config.corner_tolerance * len_ab2.sqrt().min(len_ad2.sqrt());
+                    let c = points.iter().enumerate()
+                        .filter(|&(c, _)| c != a && c != b && c != d)
+                        .map(|(c, pc)| (c, dist2(pc, &predicted)))
+                        .filter(|&(_, d2)| d2 <= tolerance * tolerance)
+                        .min_by(|x, y| x.1.partial_cmp(&y.1).unwrap());
+                    if let Some((c, _)) = c {
+                        let mut key = [a, b, c, d];
+                        key.sort_unstable();
+                        if !seen.contains(&key) {
+                            seen.push(key);
+                            quads.push([*pa, *pb, points[c], *pd]);
+                        }
+                    }
+                }
+            }
+        }
+        quads
+    }
+
+    /// +1 for a recent ON event at the pixel, -1 for a recent OFF event, else 0
+    fn polarity_vote(&self, x: f64, y: f64, horizon: SaeTime, now: SaeTime) -> i32 {
+        let (row, col) = (y.round(), x.round());
+        if row < 0.0 || col < 0.0 {
+            return 0;
+        }
+        let (row, col) = (row as usize, col as usize);
+        if row >= self.latest[0].nrows() || col >= self.latest[0].ncols() {
+            return 0;
+        }
+        let recent = |t: SaeTime| t > 0 && t >= horizon && t <= now;
+        let on = self.latest[1][(row, col)];
+        let off = self.latest[0][(row, col)];
+        match (recent(on), recent(off)) {
+            (true, true) => if on >= off { 1 } else { -1 },
+            (true, false) => 1,
+            (false, true) => -1,
+            (false, false) => 0,
+        }
+    }
+
+    /// Read the cells of a quad with corner `start` as the marker's top-left corner
+    fn read_cells(&self, quad: &[[f32; 2]; 4], start: usize, horizon: SaeTime, now: SaeTime) -> Option<Vec<bool>> {
+        let side = self.dictionary.bits + 2;
+        // the quad corners lie on the outline of the marker
+        let s = side as f64;
+        let grid = [[0.0, 0.0], [s, 0.0], [s, s], [0.0, s]];
+        let image: Vec<[f64; 2]> = (0..4)
+            .map(|k| {
+                let p = quad[(start + k) % 4];
+                [p[0] as f64, p[1] as f64]
+            })
+            .collect();
+        let to_image = Homography::estimate(&grid, &image)?;
+        let cells = (0..side * side)
+            .map(|idx| {
+                let (r, c) = ((idx / side) as f64, (idx % side) as f64);
+                let mut votes = 0;
+                for i in 0..CELL_SAMPLES {
+                    for j in 0..CELL_SAMPLES {
+                        let u = c + 0.25 + 0.5 * (j as f64) / ((CELL_SAMPLES - 1) as f64);
+                        let v = r + 0.25 + 0.5 * (i as f64) / ((CELL_SAMPLES - 1) as f64);
+                        let p = to_image.apply([u, v]);
+                        votes += self.polarity_vote(p[0], p[1], horizon, now);
+                    }
+                }
+                votes > 0
+            })
+            .collect();
+        Some(cells)
+    }
+
+    fn decode(&self, quad: &[[f32; 2]; 4], horizon: SaeTime, now: SaeTime) -> Option<MarkerObservation> {
+        let bits = self.dictionary.bits;
+        let side = bits + 2;
+        let mut best: Option<MarkerObservation> = None;
+        for start in 0..4 {
+            let cells = self.read_cells(quad, start, horizon, now)?;
+            let border_bright = (0..side * side)
+                .filter(|idx| {
+                    let (r, c) = (idx / side, idx % side);
+                    r == 0 || c == 0 || r == side - 1 || c == side - 1
+                })
+                .all(|idx| cells[idx]);
+            if !border_bright {
+                return None;
+            }
+            let mut observed = 0u64;
+            for r in 0..bits {
+                for c in 0..bits {
+                    if cells[(r + 1) * side + c + 1] {
+                        observed |= 1 << (r * bits + c);
+                    }
+                }
+            }
+            if let Some((id, hamming)) = self.dictionary.closest(observed) {
+                if hamming <= self.config.max_hamming && best.as_ref().is_none_or(|b| hamming < b.hamming) {
+                    let mut corners = [[0.0; 2]; 4];
+                    for (k, corner) in corners.iter_mut().enumerate() {
+                        *corner = quad[(start + k) % 4];
+                    }
+                    best = Some(MarkerObservation { id, corners, hamming, timestamp: now });
+                }
+            }
+        }
+        best
+    }
+}
+
+fn dist2(a: &[f32; 2], b: &[f32; 2]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CELL: usize = 4;
+
+    fn dictionary() -> MarkerDictionary {
+        // asymmetric codes, so that each orientation is distinct
+        MarkerDictionary::new(3, vec![0b000_010_011, 0b100_110_001]).unwrap()
+    }
+
+    /// Events for marker `id` appearing with its top-left at (row0, col0),
+    /// rotated by `turns` quarter turns clockwise, plus its four corner events.
+    fn show_marker(detector: &mut FiducialDetector, id: usize, row0: usize, col0: usize, turns: usize, timestamp: SaeTime) {
+        let cells = detector.dictionary().marker_cells(id).unwrap();
+        let side = detector.dictionary().bits() + 2;
+        let size = side * CELL;
+        for dr in 0..size {
+            for dc in 0..size {
+                // pixel (dr, dc) of the rotated marker shows marker cell (r, c)
+                let (mut r, mut c) = (dr / CELL, dc / CELL);
+                for _ in 0..turns {
+                    let (pr, pc) = (side - 1 - c, r);
+                    r = pr;
+                    c = pc;
+                }
+                if cells[r * side + c] {
+                    detector.add_event(&SaeEvent {
+                        row: (row0 + dr) as u16,
+                        col: (col0 + dc) as u16,
+                        polarity: 1,
+                        timestamp,
+                        ..SaeEvent::default()
+                    });
+                }
+            }
+        }
+        for &(dr, dc) in [(0, 0), (0, size), (size, size), (size, 0)].iter() {
+            detector.add_corner(&SaeEvent {
+                row: (row0 + dr) as u16,
+                col: (col0 + dc) as u16,
+                timestamp,
+                ..SaeEvent::default()
+            });
+        }
+    }
+
+    #[test]
+    fn test_decode_markers() {
+        let mut detector = FiducialDetector::new(64, 64, FiducialConfig::default(), dictionary());
+        show_marker(&mut detector, 0, 5, 5, 0, 1000);
+        show_marker(&mut detector, 1, 30, 36, 0, 1000);
+        let mut found = detector.detect(1000);
+        found.sort_by_key(|obs| obs.id);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].id, 0);
+        assert_eq!(found[0].corners[0], [5.0, 5.0]);
+        assert_eq!(found[1].id, 1);
+        assert_eq!(found[1].corners[0], [36.0, 30.0]);
+        assert_eq!(found[1].hamming, 0);
+
+        // old corners expire
+        assert!(detector.detect(1000 + 30_000).is_empty());
+    }
+
+    #[test]
+    fn test_rotated_marker_corner_order() {
+        let mut detector = FiducialDetector::new(64, 64, FiducialConfig::default(), dictionary());
+        show_marker(&mut detector, 1, 10, 10, 1, 500);
+        let found = detector.detect(600);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, 1);
+        // a quarter turn clockwise brings the marker's top-left to the image top-right
+        let size = (5 * CELL) as f32;
+        assert_eq!(found[0].corners[0], [10.0 + size, 10.0]);
+    }
+
+    #[test]
+    fn test_rejects_unknown_pattern() {
+        let other = MarkerDictionary::new(3, vec![0b111_000_101]).unwrap();
+        let mut detector = FiducialDetector::new(64, 64, FiducialConfig::default(), dictionary());
+        let mut source = FiducialDetector::new(64, 64, FiducialConfig::default(), other);
+        show_marker(&mut source, 0, 5, 5, 0, 1000);
+        detector.latest = source.latest.clone();
+        detector.corners = source.corners.clone();
+        assert!(detector.detect(1000).is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Event filtering stages that run before the SAE update and corner detection.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::sae_types::*;
+
+
+/// A stage that decides whether each event is passed downstream
+pub trait EventFilter {
+    /// Returns whether the event should be kept
+    fn accept(&mut self, evt: &SaeEvent) -> bool;
+}
+
+impl<F: EventFilter + ?Sized> EventFilter for &mut F {
+    fn accept(&mut self, evt: &SaeEvent) -> bool {
+        (**self).accept(evt)
+    }
+}
+
+impl<F: EventFilter + ?Sized> EventFilter for Box<F> {
+    fn accept(&mut self, evt: &SaeEvent) -> bool {
+        (**self).accept(evt)
+    }
+}
+
+/// No filtering: every event is kept
+impl EventFilter for () {
+    fn accept(&mut self, _evt: &SaeEvent) -> bool {
+        true
+    }
+}
+
+/// Two filters in sequence, with static dispatch: the second sees only the
+/// events the first keeps. Nest pairs for longer chains.
+impl<A: EventFilter, B: EventFilter> EventFilter for (A, B) {
+    fn accept(&mut self, evt: &SaeEvent) -> bool {
+        self.0.accept(evt) && self.1.accept(evt)
+    }
+}
+
+/// A sequence of filters: an event is kept only if every filter keeps it.
+/// Filters after the first rejecting one do not see the event.
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn EventFilter>>,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<F: EventFilter + 'static>(&mut self, filter: F) {
+        self.filters.push(Box::new(filter));
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+}
+
+impl EventFilter for FilterChain {
+    fn accept(&mut self, evt: &SaeEvent) -> bool {
+        self.filters.iter_mut().all(|filter| filter.accept(evt))
+    }
+}
+
+/// Iterator adapter yielding only the events accepted by a filter
+pub struct Filtered<I, F> {
+    inner: I,
+    filter: F,
+}
+
+impl<I, F> Iterator for Filtered<I, F>
+    where I: Iterator<Item = SaeEvent>, F: EventFilter
+{
+    type Item = SaeEvent;
+
+    fn next(&mut self) -> Option<SaeEvent> {
+        let filter = &mut self.filter;
+        self.inner.by_ref().find(|evt| filter.accept(evt))
+    }
+}
+
+/// Apply a filter to an event stream
+pub fn filter_events<I, F>(events: I, filter: F) -> Filtered<I::IntoIter, F>
+    where I: IntoIterator<Item = SaeEvent>, F: EventFilter
+{
+    Filtered { inner: events.into_iter(), filter }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct EvenTimes;
+    impl EventFilter for EvenTimes {
+        fn accept(&mut self, evt: &SaeEvent) -> bool {
+            evt.timestamp.is_multiple_of(2)
+        }
+    }
+
+    struct Before(SaeTime);
+    impl EventFilter for Before {
+        fn accept(&mut self, evt: &SaeEvent) -> bool {
+            evt.timestamp < self.0
+        }
+    }
+
+    #[test]
+    fn test_filter_chain() {
+        let events = (0..10).map(|t| SaeEvent { timestamp: t, ..SaeEvent::default() });
+        let mut chain = FilterChain::new();
+        assert!(chain.is_empty());
+        chain.push(EvenTimes);
+        chain.push(Before(7));
+        assert_eq!(chain.len(), 2);
+
+        let kept: Vec<SaeTime> = filter_events(events, chain).map(|e| e.timestamp).collect();
+        assert_eq!(kept, vec![0, 2, 4, 6]);
+    }
+}
@@ -0,0 +1,171 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Suppression of events caused by mains-powered light flicker.
+//! Lights driven from 50 Hz or 60 Hz mains flicker at twice that frequency,
+//! so an illuminated pixel fires same-polarity events at regular 10 ms or 8.33 ms
+//! intervals. Indoors, these dominate the stream and produce dense false corners
+//! along illuminated edges. This filter tracks, per pixel and polarity, how
+//! consistently event intervals match a flicker period, and drops events from
+//! pixels that are flickering.
+
+use crate::filter::EventFilter;
+use crate::sae_types::*;
+
+
+/// Parameters of the flicker filter
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlickerConfig {
+    /// flicker periods to detect, in SAE time units (microseconds)
+    pub periods: Vec<SaeTime>,
+    /// allowed deviation of an interval from a multiple of a period
+    pub tolerance: SaeTime,
+    /// intervals spanning up to this many periods (eg missed flicker cycles) still count
+    pub max_multiple: u32,
+    /// number of consecutive periodic intervals before a pixel is considered flickering
+    pub min_periodic_hits: u8,
+}
+
+impl Default for FlickerConfig {
+    fn default() -> Self {
+        FlickerConfig {
+            // 100 Hz (50 Hz mains) and 120 Hz (60 Hz mains)
+            periods: vec![10_000, 8_333],
+            tolerance: 300,
+            max_multiple: 3,
+            min_periodic_hits: 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct PixelFlickerState {
+    last_timestamp: Option<SaeTime>,
+    periodic_hits: u8,
+}
+
+/// Drops events from pixels firing periodically at a mains flicker frequency
+pub struct FlickerFilter {
+    config: FlickerConfig,
+    ncols: usize,
+    nrows: usize,
+    /// per-pixel state, one plane per polarity
+    state: [Vec<PixelFlickerState>; 2],
+    suppressed: u64,
+}
+
+impl FlickerFilter {
+    pub fn new(nrows: usize, ncols: usize, config: FlickerConfig) -> Self {
+        let plane = vec![PixelFlickerState::default(); nrows * ncols];
+        FlickerFilter {
+            config,
+            ncols,
+            nrows,
+            state: [plane.clone(), plane],
+            suppressed: 0,
+        }
+    }
+
+    /// number of events suppressed so far
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed
+    }
+
+    /// whether the interval between two events matches a multiple of any flicker period
+    fn is_periodic(&self, interval: SaeTime) -> bool {
+        self.config.periods.iter().any(|&period| {
+            if period == 0 {
+                return false;
+            }
+            let multiple = ((interval + period / 2) / period).max(1);
+            if multiple > self.config.max_multiple {
+                return false;
+            }
+            let expected = multiple * period;
+            let deviation = expected.max(interval) - expected.min(interval);
+            deviation <= self.config.tolerance
+        })
+    }
+}
+
+impl EventFilter for FlickerFilter {
+    fn accept(&mut self, evt: &SaeEvent) -> bool {
+        let row = evt.row as usize;
+        let col = evt.col as usize;
+        if row >= self.nrows || col >= self.ncols {
+            return true;
+        }
+        let plane = if evt.polarity > 0 { 1 } else { 0 };
+        let idx = row * self.ncols + col;
+
+        let pixel = self.state[plane][idx];
+        let periodic = match pixel.last_timestamp.and_then(|last| evt.timestamp.checked_elapsed_since(last)) {
+            Some(interval) => self.is_periodic(interval),
+            None => false,
+        };
+
+        let pixel = &mut self.state[plane][idx];
+        pixel.last_timestamp = Some(evt.timestamp);
+        pixel.periodic_hits = if periodic {
+            pixel.periodic_hits.saturating_add(1)
+        } else {
+            0
+        };
+
+        if pixel.periodic_hits >= self.config.min_periodic_hits {
+            self.suppressed += 1;
+            false
+        } else {
+            true
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_at(col: u16, polarity: u8, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row: 1, col, polarity, timestamp, ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_flickering_pixel_suppressed() {
+        let mut filter = FlickerFilter::new(4, 4, FlickerConfig::default());
+        let mut accepted = 0;
+        for i in 0..10 {
+            // 100 Hz flicker with a little jitter
+            let t = 1_000 + i * 10_000 + (i % 3) * 50;
+            if filter.accept(&event_at(0, 1, t)) {
+                accepted += 1;
+            }
+        }
+        // the first few events pass until the periodicity is established
+        assert_eq!(accepted, 3);
+        assert_eq!(filter.suppressed(), 7);
+    }
+
+    #[test]
+    fn test_missed_cycles_and_60hz() {
+        let mut filter = FlickerFilter::new(4, 4, FlickerConfig::default());
+        let times = [0, 8_333, 16_666, 33_332, 41_665, 49_998];
+        let kept: Vec<bool> = times.iter().map(|&t| filter.accept(&event_at(2, 0, t))).collect();
+        assert_eq!(kept, vec![true, true, true, false, false, false]);
+    }
+
+    #[test]
+    fn test_aperiodic_pixel_passes() {
+        let mut filter = FlickerFilter::new(4, 4, FlickerConfig::default());
+        let times = [0, 3_000, 17_000, 21_500, 40_000, 43_700, 52_000];
+        for &t in times.iter() {
+            assert!(filter.accept(&event_at(3, 0, t)));
+        }
+        // polarities are tracked independently
+        for i in 0..4 {
+            let pol = (i % 2) as u8;
+            assert!(filter.accept(&event_at(1, pol, i * 5_000)));
+        }
+        assert_eq!(filter.suppressed(), 0);
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Run two corner detectors side by side on the same event stream and fuse their output.
+//!
+//! Corners from either detector that fall within a spatiotemporal neighborhood are
+//! treated as one detection. Each detector contributes a weight to the detections it
+//! supports, and a detection is reported once its support reaches a threshold:
+//! the union reports whatever either detector finds, the intersection only what both
+//! find, and confidence weighting lets one detector count for more than the other.
+//! Every detection is reported at most once.
+
+use std::collections::VecDeque;
+
+use crate::detector::CornerDetector;
+use crate::sae_types::*;
+
+
+/// How the outputs of the two detectors are combined
+#[derive(Clone, Debug, PartialEq)]
+pub enum FusionMode {
+    /// corners found by either detector
+    Union,
+    /// corners found by both detectors
+    Intersection,
+    /// corners whose summed detector weights reach `threshold`
+    Weighted { weights: [f32; 2], threshold: f32 },
+}
+
+impl FusionMode {
+    fn weights_and_threshold(&self) -> ([f32; 2], f32) {
+        match *self {
+            FusionMode::Union => ([1.0, 1.0], 1.0),
+            FusionMode::Intersection => ([1.0, 1.0], 2.0),
+            FusionMode::Weighted { weights, threshold } => (weights, threshold),
+        }
+    }
+}
+
+/// Parameters of detector fusion
+#[derive(Clone, Debug, PartialEq)]
+pub struct FusionConfig {
+    pub mode: FusionMode,
+    /// corners closer than this (pixels) are the same detection
+    pub radius: f32,
+    /// corners further apart in time than this are separate detections
+    pub window: SaeTime,
+}
+
+impl Default for FusionConfig {
+    fn default() -> Self {
+        FusionConfig {
+            mode: FusionMode::Union,
+            radius: 2.0,
+            window: 5_000,
+        }
+    }
+}
+
+/// A corner reported by the fusion
+#[derive(Clone, Debug, PartialEq)]
+pub struct FusedCorner {
+    /// the corner whose arrival completed the detection
+    pub corner: SaeEvent,
+    /// which detectors (first, second) had found it when it was reported
+    pub support: [bool; 2],
+    /// summed weight of the supporting detectors over the total weight
+    pub confidence: f32,
+}
+
+/// A detection in the neighborhood of its first corner
+struct Detection {
+    position: (f32, f32),
+    timestamp: SaeTime,
+    support: [bool; 2],
+    reported: bool,
+}
+
+/// Counts of what the two detectors found, for studying their complementarity
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FusionStats {
+    /// corners from each detector, before deduplication
+    pub corners: [u64; 2],
+    /// corners dropped as repeats of a detection by the same detector
+    pub duplicates: u64,
+    /// detections found by the first detector only, the second only, and both,
+    /// counted as they expire from the window
+    pub only_first: u64,
+    pub only_second: u64,
+    pub both: u64,
+    pub reported: u64,
+}
+
+/// Feeds every event to two detectors and fuses their corners
+pub struct DetectorFusion<A: CornerDetector, B: CornerDetector> {
+    first: A,
+    second: B,
+    config: FusionConfig,
+    detections: VecDeque<Detection>,
+    stats: FusionStats,
+}
+
+impl<A: CornerDetector, B: CornerDetector> DetectorFusion<A, B> {
+    pub fn new(first: A, second: B, config: FusionConfig) -> Self {
+        DetectorFusion {
+            first,
+            second,
+            config,
+            detections: VecDeque::new(),
+            stats: FusionStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> &FusionStats {
+        &self.stats
+    }
+
+    /// Process one event with both detectors, returning the corners reported as a result
+    pub fn process(&mut self, evt: &SaeEvent) -> Vec<FusedCorner> {
+        self.expire(evt.timestamp);
+        let mut out = Vec::new();
+        if let Some(corner) = self.first.process(evt) {
+            out.extend(self.add_corner(0, corner));
+        }
+        if let Some(corner) = self.second.process(evt) {
+            out.extend(self.add_corner(1, corner));
+        }
+        out
+    }
+
+    /// Close the window, counting all detections still pending in the stats
+    pub fn finish(&mut self) {
+        while let Some(detection) = self.detections.pop_front() {
+            self.count_expired(&detection);
+        }
+    }
+
+    fn count_expired(&mut self, detection: &Detection) {
+        match detection.support {
+            [true, true] => self.stats.both += 1,
+            [true, false] => self.stats.only_first += 1,
+            _ => self.stats.only_second += 1,
+        }
+    }
+
+    fn expire(&mut self, now: SaeTime) {
+        while let Some(oldest) = self.detections.front() {
+            if now.saturating_sub(oldest.timestamp) <= self.config.window {
+                break;
+            }
+            let detection = self.detections.pop_front().unwrap();
+            self.count_expired(&detection);
+        }
+    }
+
+    fn add_corner(&mut self, source: usize, corner: SaeEvent) -> Option<FusedCorner> {
+        self.stats.corners[source] += 1;
+        let (weights, threshold) = self.config.mode.weights_and_threshold();
+        let position = corner.subpixel_position();
+        let radius_2 = self.config.radius * self.config.radius;
+        let near = self.detections.iter_mut().find(|det| {
+            let (dr, dc) = (det.position.0 - position.0, det.position.1 - position.1);
+            dr * dr + dc * dc <= radius_2
+        });
+        let detection = match near {
+            Some(det) => {
+                if det.support[source] {
+                    self.stats.duplicates += 1;
+                    return None;
+                }
+                det.support[source] = true;
+                det
+            }
+            None => {
+                let mut support = [false; 2];
+                support[source] = true;
+                self.detections.push_back(Detection { position, timestamp: corner.timestamp, support, reported: false });
+                self.detections.back_mut().unwrap()
+            }
+        };
+
+        let score: f32 = weights.iter().zip(detection.support.iter())
+            .filter(|(_, &supported)| supported)
+            .map(|(weight, _)| weight)
+            .sum();
+        if detection.reported || score < threshold {
+            return None;
+        }
+        detection.reported = true;
+        let total = weights[0] + weights[1];
+        let fused = FusedCorner {
+            corner,
+            support: detection.support,
+            confidence: if total > 0.0 { score / total } else { 0.0 },
+        };
+        self.stats.reported += 1;
+        Some(fused)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// reports a corner at each of a fixed set of pixels
+    struct FixedDetector(Vec<(u16, u16)>);
+
+    impl CornerDetector for FixedDetector {
+        fn process(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+            if self.0.contains(&(evt.row, evt.col)) {
+                Some(evt.clone())
+            } else {
+                None
+            }
+        }
+    }
+
+    fn run(mode: FusionMode) -> (Vec<FusedCorner>, FusionStats) {
+        // the first detector finds corners at A and B, the second at A (one pixel off) and C
+        let first = FixedDetector(vec![(10, 10), (30, 30)]);
+        let second = FixedDetector(vec![(10, 11), (50, 50)]);
+        let mut fusion = DetectorFusion::new(first, second, FusionConfig { mode, ..FusionConfig::default() });
+        let events = [(10, 10, 100), (10, 11, 200), (10, 10, 300), (30, 30, 400), (50, 50, 500), (10, 11, 9_000)];
+        let mut out = Vec::new();
+        for &(row, col, timestamp) in events.iter() {
+            out.extend(fusion.process(&SaeEvent { row, col, timestamp, ..SaeEvent::default() }));
+        }
+        fusion.finish();
+        (out, *fusion.stats())
+    }
+
+    #[test]
+    fn test_union() {
+        let (out, stats) = run(FusionMode::Union);
+        let pixels: Vec<(u16, u16)> = out.iter().map(|fc| (fc.corner.row, fc.corner.col)).collect();
+        // the repeat of A within the window is dropped; after the window A is new again
+        assert_eq!(pixels, vec![(10, 10), (30, 30), (50, 50), (10, 11)]);
+        assert_eq!(out[0].confidence, 0.5);
+        assert_eq!(stats.corners, [3, 3]);
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!((stats.only_first, stats.only_second, stats.both), (1, 2, 1));
+    }
+
+    #[test]
+    fn test_intersection() {
+        let (out, stats) = run(FusionMode::Intersection);
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].corner.col, out[0].corner.timestamp), (11, 200));
+        assert_eq!(out[0].support, [true, true]);
+        assert_eq!(out[0].confidence, 1.0);
+        assert_eq!(stats.reported, 1);
+    }
+
+    #[test]
+    fn test_weighted() {
+        // trust the first detector alone, but the second only with confirmation
+        let (out, _) = run(FusionMode::Weighted { weights: [0.7, 0.3], threshold: 0.6 });
+        let pixels: Vec<(u16, u16)> = out.iter().map(|fc| (fc.corner.row, fc.corner.col)).collect();
+        assert_eq!(pixels, vec![(10, 10), (30, 30)]);
+    }
+}
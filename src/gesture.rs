@@ -0,0 +1,284 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Trajectory utilities for recognizing motion gestures from corner tracks.
+//! A track's recent trajectory is resampled at evenly spaced times and normalized
+//! for position and scale, so that trajectories of different speed and extent can
+//! be compared. `GestureClassifier` matches normalized trajectories against
+//! templates by dynamic time warping as tracks end.
+
+use crate::sae_types::*;
+use crate::track::{Track, TrackId, TrackObserver};
+
+
+/// Positions (x = column, y = row) of the track at `samples` evenly spaced times
+/// over the last `window` of its lifetime, linearly interpolated between observations.
+/// Returns None if the track spans less than the window, or fewer than two samples are requested.
+pub fn resample(track: &Track, window: SaeTime, samples: usize) -> Option<Vec<[f32; 2]>> {
+    if samples < 2 || window == 0 || track.lifetime() < window {
+        return None;
+    }
+    let end = track.last().timestamp;
+    let start = end.before(window);
+    let points: Vec<(SaeTime, [f32; 2])> = track.observations.iter()
+        .map(|obs| {
+            let (row, col) = obs.subpixel_position();
+            (obs.timestamp, [col, row])
+        })
+        .collect();
+
+    let mut out = Vec::with_capacity(samples);
+    let mut seg = 0;
+    for k in 0..samples {
+        let t = start as f64 + (window as f64) * (k as f64) / ((samples - 1) as f64);
+        while seg + 2 < points.len() && (points[seg + 1].0 as f64) < t {
+            seg += 1;
+        }
+        let (t0, p0) = points[seg];
+        let (t1, p1) = points[seg + 1];
+        let frac = if t1 > t0 { ((t - t0 as f64) / (t1 - t0) as f64).clamp(0.0, 1.0) as f32 } else { 1.0 };
+        out.push([p0[0] + (p1[0] - p0[0]) * frac, p0[1] + (p1[1] - p0[1]) * frac]);
+    }
+    Some(out)
+}
+
+/// Resample a polyline at `samples` points evenly spaced along its length
+pub fn resample_polyline(points: &[[f32; 2]], samples: usize) -> Vec<[f32; 2]> {
+    if points.len() < 2 || samples < 2 {
+        return points.to_vec();
+    }
+    let mut cumulative = vec![0.0f32];
+    for w in points.windows(2) {
+        let len = ((w[1][0] - w[0][0]).powi(2) + (w[1][1] - w[0][1]).powi(2)).sqrt();
+        cumulative.push(cumulative[cumulative.len() - 1] + len);
+    }
+    let total = cumulative[cumulative.len() - 1];
+    let mut out = Vec::with_capacity(samples);
+    let mut seg = 0;
+    for k in 0..samples {
+        let s = total * (k as f32) / ((samples - 1) as f32);
+        while seg + 2 < points.len() && cumulative[seg + 1] < s {
+            seg += 1;
+        }
+        let seg_len = cumulative[seg + 1] - cumulative[seg];
+        let frac = if seg_len > 0.0 { ((s - cumulative[seg]) / seg_len).clamp(0.0, 1.0) } else { 1.0 };
+        let (p0, p1) = (points[seg], points[seg + 1]);
+        out.push([p0[0] + (p1[0] - p0[0]) * frac, p0[1] + (p1[1] - p0[1]) * frac]);
+    }
+    out
+}
+
+/// Translate a trajectory to zero mean and scale its largest extent to one.
+/// A stationary trajectory is only translated.
+pub fn normalize(points: &[[f32; 2]]) -> Vec<[f32; 2]> {
+    if points.is_empty() {
+        return Vec::new();
+    }
+    let n = points.len() as f32;
+    let mean = [
+        points.iter().map(|p| p[0]).sum::<f32>() / n,
+        points.iter().map(|p| p[1]).sum::<f32>() / n,
+    ];
+    let extent = points.iter()
+        .map(|p| (p[0] - mean[0]).abs().max((p[1] - mean[1]).abs()))
+        .fold(0.0f32, f32::max);
+    let scale = if extent > 0.0 { 1.0 / extent } else { 1.0 };
+    points.iter()
+        .map(|p| [(p[0] - mean[0]) * scale, (p[1] - mean[1]) * scale])
+        .collect()
+}
+
+/// Dynamic time warping distance between two trajectories: the least total
+/// point-to-point distance over all monotonic alignments, divided by the longer length.
+/// With `band`, alignments are restricted to within that many samples of the diagonal.
+pub fn dtw_distance(a: &[[f32; 2]], b: &[[f32; 2]], band: Option<usize>) -> f32 {
+    if a.is_empty() || b.is_empty() {
+        return f32::INFINITY;
+    }
+    let (n, m) = (a.len(), b.len());
+    let band = band.unwrap_or(n.max(m)).max(n.max(m) - n.min(m));
+    let mut cost = vec![f32::INFINITY; (n + 1) * (m + 1)];
+    cost[0] = 0.0;
+    for i in 1..=n {
+        let lo = i.saturating_sub(band).max(1);
+        let hi = (i + band).min(m);
+        for j in lo..=hi {
+            let d = ((a[i - 1][0] - b[j - 1][0]).powi(2) + (a[i - 1][1] - b[j - 1][1]).powi(2)).sqrt();
+            let best = cost[(i - 1) * (m + 1) + j]
+                .min(cost[i * (m + 1) + j - 1])
+                .min(cost[(i - 1) * (m + 1) + j - 1]);
+            cost[i * (m + 1) + j] = d + best;
+        }
+    }
+    cost[n * (m + 1) + m] / n.max(m) as f32
+}
+
+/// A named reference trajectory
+#[derive(Clone, Debug, PartialEq)]
+pub struct GestureTemplate {
+    pub name: String,
+    /// normalized trajectory
+    pub points: Vec<[f32; 2]>,
+}
+
+impl GestureTemplate {
+    /// The template is normalized on creation
+    pub fn new(name: &str, points: &[[f32; 2]]) -> Self {
+        GestureTemplate { name: name.to_string(), points: normalize(points) }
+    }
+}
+
+/// Parameters of gesture classification
+#[derive(Clone, Debug, PartialEq)]
+pub struct GestureConfig {
+    /// duration of track history compared against templates
+    pub window: SaeTime,
+    /// number of resampled points per trajectory
+    pub samples: usize,
+    /// Sakoe-Chiba band for dynamic time warping, in samples
+    pub band: Option<usize>,
+    /// trajectories farther than this from every template are not classified
+    pub max_distance: f32,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        GestureConfig {
+            window: 500_000,
+            samples: 32,
+            band: Some(8),
+            max_distance: 0.25,
+        }
+    }
+}
+
+/// A track recognized as a gesture
+#[derive(Clone, Debug, PartialEq)]
+pub struct GestureMatch {
+    pub track: TrackId,
+    /// index of the matched template
+    pub gesture: usize,
+    pub distance: f32,
+    /// time of the last observation of the track
+    pub timestamp: SaeTime,
+}
+
+/// Classifies the trajectories of ending tracks against gesture templates
+pub struct GestureClassifier {
+    config: GestureConfig,
+    templates: Vec<GestureTemplate>,
+    matches: Vec<GestureMatch>,
+}
+
+impl GestureClassifier {
+    /// Templates are resampled to the configured number of samples
+    pub fn new(config: GestureConfig, templates: Vec<GestureTemplate>) -> Self {
+        let templates = templates.into_iter()
+            .map(|template| GestureTemplate {
+                points: normalize(&resample_polyline(&template.points, config.samples)),
+                ..template
+            })
+            .collect();
+        GestureClassifier {
+            config,
+            templates,
+            matches: Vec::new(),
+        }
+    }
+
+    pub fn templates(&self) -> &[GestureTemplate] {
+        &self.templates
+    }
+
+    /// The best-matching template for a track, if any is close enough
+    pub fn classify(&self, track: &Track) -> Option<GestureMatch> {
+        let points = resample(track, self.config.window, self.config.samples)?;
+        let points = normalize(&points);
+        self.templates.iter()
+            .map(|template| dtw_distance(&points, &template.points, self.config.band))
+            .enumerate()
+            .filter(|&(_, distance)| distance <= self.config.max_distance)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(gesture, distance)| GestureMatch {
+                track: track.id,
+                gesture,
+                distance,
+                timestamp: track.last().timestamp,
+            })
+    }
+
+    /// take the gestures recognized so far
+    pub fn take_matches(&mut self) -> Vec<GestureMatch> {
+        std::mem::take(&mut self.matches)
+    }
+}
+
+impl TrackObserver for GestureClassifier {
+    fn track_ended(&mut self, track: &Track) {
+        if let Some(found) = self.classify(track) {
+            self.matches.push(found);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::track::TrackStore;
+
+    fn circle(steps: usize) -> Vec<[f32; 2]> {
+        (0..steps)
+            .map(|k| {
+                let a = (k as f32) / (steps - 1) as f32 * std::f32::consts::PI * 2.0;
+                [a.cos(), a.sin()]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_resample_interpolates() {
+        let mut store = TrackStore::new();
+        let id = store.start_track(corner_at(0.0, 0.0, 0));
+        store.extend_track(id, corner_at(10.0, 0.0, 100));
+        store.extend_track(id, corner_at(10.0, 20.0, 300));
+        let track = store.get(id).unwrap();
+        let points = resample(track, 300, 4).unwrap();
+        assert_eq!(points, vec![[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [10.0, 20.0]]);
+        assert!(resample(track, 400, 4).is_none());
+    }
+
+    #[test]
+    fn test_classify_ended_tracks() {
+        let templates = vec![
+            GestureTemplate::new("swipe", &[[0.0, 0.0], [1.0, 0.0]]),
+            GestureTemplate::new("circle", &circle(32)),
+        ];
+        let config = GestureConfig { window: 1_000, ..GestureConfig::default() };
+        let mut classifier = GestureClassifier::new(config, templates);
+        let mut store = TrackStore::new();
+
+        // a fast, large circle, with uneven observation spacing
+        let circle_id = store.start_track(corner_at(80.0, 50.0, 0));
+        let mut t = 0;
+        for k in 1..40 {
+            t += 20 + (k % 3) * 10;
+            let a = (t as f32) / 1_000.0 * std::f32::consts::PI * 2.0;
+            store.extend_track_observed(circle_id, corner_at(50.0 + 30.0 * a.cos(), 50.0 + 30.0 * a.sin(), t), &mut classifier);
+        }
+        // a slow horizontal swipe
+        let swipe_id = store.start_track(corner_at(5.0, 5.0, 0));
+        for k in 1..=10 {
+            store.extend_track_observed(swipe_id, corner_at(5.0 + 4.0 * k as f32, 5.0, k * 100), &mut classifier);
+        }
+        assert!(classifier.take_matches().is_empty());
+
+        store.end_track(circle_id, &mut classifier).unwrap();
+        store.end_track(swipe_id, &mut classifier).unwrap();
+        let matches = classifier.take_matches();
+        assert_eq!(matches.len(), 2);
+        assert_eq!((matches[0].track, matches[0].gesture), (circle_id, 1));
+        assert_eq!((matches[1].track, matches[1].gesture), (swipe_id, 0));
+        assert!(store.is_empty());
+    }
+}
@@ -0,0 +1,514 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Reading the recording formats of common event cameras: jAER / iniVation AEDAT 2.0
+//! and 3.1 files, and Prophesee EVT 2.0 and 3.0 `.raw` files.
+//!
+//! The format is recognized from the text header: `#!AER-DAT2.0` or `#!AER-DAT3.1`
+//! on the first line for AEDAT, and `% evt 2.0` / `% evt 3.0` (or `% format EVT2` /
+//! `% format EVT3`) among the `%` lines of a raw file, which may also give the sensor
+//! geometry. Only polarity (change detection) events are read; frames, IMU samples,
+//! triggers and other packets are skipped.
+//!
+//! The narrow hardware timestamps roll over (32 bits for AEDAT 2.0, 34 for EVT 2.0,
+//! 24 for EVT 3.0) and are unwrapped into a continuous clock: a backward step of more
+//! than half the counter range is taken as a rollover. Event timestamps are reported
+//! relative to the first event, whose hardware time is available from
+//! `AedatReader::origin`, and saturate at the largest `SaeTime`.
+//!
+//! Coordinates are as encoded: jAER mirrors x for display, which is not done here.
+//! Polarity follows each format's convention for ON events, decoded as 1.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::io::decode::{DecodeError, DecodeErrorKind, OffsetReader};
+use crate::sae_types::*;
+use crate::source::EventSource;
+
+
+/// Address layout of AEDAT 2.0 events, which depends on the camera
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Aedat2Layout {
+    /// DAVIS cameras: y in bits 22-30, x in bits 12-21, polarity in bit 11
+    #[default]
+    Davis,
+    /// DVS128: x in bits 1-7, y in bits 8-14, polarity (0 for ON) in bit 0
+    Dvs128,
+}
+
+/// The recording formats read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawFormat {
+    Aedat2(Aedat2Layout),
+    Aedat3,
+    Evt2,
+    Evt3,
+}
+
+const AEDAT3_HEADER_END: &str = "#!END-HEADER";
+/// AEDAT 3.1 packet header length, and the type and size of polarity events
+const AEDAT3_PACKET_HEADER_LEN: usize = 28;
+const AEDAT3_POLARITY_EVENT: i16 = 1;
+const AEDAT3_POLARITY_EVENT_LEN: usize = 8;
+
+fn invalid(offset: u64, what: &'static str) -> io::Error {
+    DecodeError::new(offset, DecodeErrorKind::Invalid(what)).into()
+}
+
+/// Extends a rolling-over hardware counter into a continuous clock
+#[derive(Clone, Copy, Debug)]
+struct Unwrapper {
+    bits: u32,
+    epoch: u64,
+    last: Option<u64>,
+}
+
+impl Unwrapper {
+    fn new(bits: u32) -> Self {
+        Unwrapper { bits, epoch: 0, last: None }
+    }
+
+    fn unwrap(&mut self, raw: u64) -> u64 {
+        if let Some(last) = self.last {
+            if raw + (1 << (self.bits - 1)) < last {
+                self.epoch += 1 << self.bits;
+            }
+        }
+        self.last = Some(raw);
+        self.epoch + raw
+    }
+}
+
+/// Decoding state of the word-oriented EVT formats
+#[derive(Clone, Copy, Debug)]
+struct EvtState {
+    clock: Unwrapper,
+    time_high: u64,
+    time_low: u64,
+    /// unwrapped time of the events being decoded
+    time: u64,
+    y: u16,
+    base_x: u16,
+    polarity: u8,
+}
+
+/// Reads polarity events from an AEDAT or EVT recording
+pub struct AedatReader<R> {
+    reader: OffsetReader<BufReader<R>>,
+    format: RawFormat,
+    /// (rows, cols), if the header gives them
+    geometry: Option<(u16, u16)>,
+    evt: EvtState,
+    aedat2_clock: Unwrapper,
+    pending: Vec<SaeEvent>,
+    next: usize,
+    origin: Option<u64>,
+}
+
+impl AedatReader<File> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(File::open(path)?)
+    }
+}
+
+/// Read a header line, without its line ending
+fn read_line<R: BufRead>(reader: &mut R, consumed: &mut u64) -> io::Result<String> {
+    let mut line = Vec::new();
+    *consumed += reader.read_until(b'\n', &mut line)? as u64;
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+/// whether the next byte is `marker`, without consuming it
+fn next_is<R: BufRead>(reader: &mut R, marker: u8) -> io::Result<bool> {
+    Ok(reader.fill_buf()?.first() == Some(&marker))
+}
+
+/// (format, geometry) from the `%` header lines of a raw file
+fn parse_raw_header<R: BufRead>(reader: &mut R, consumed: &mut u64) -> io::Result<(RawFormat, Option<(u16, u16)>)> {
+    let mut format = None;
+    let (mut width, mut height) = (None, None);
+    while next_is(reader, b'%')? {
+        let line = read_line(reader, consumed)?;
+        let line = line.trim_start_matches('%').trim();
+        if line == "end" {
+            break;
+        }
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "evt" => format = match value.trim() {
+                "2.0" => Some(RawFormat::Evt2),
+                "3.0" => Some(RawFormat::Evt3),
+                _ => format,
+            },
+            "format" => {
+                let mut fields = value.trim().split(';');
+                format = match fields.next() {
+                    Some("EVT2") => Some(RawFormat::Evt2),
+                    Some("EVT3") => Some(RawFormat::Evt3),
+                    _ => format,
+                };
+                for field in fields {
+                    match field.split_once('=') {
+                        Some(("width", w)) => width = w.parse().ok(),
+                        Some(("height", h)) => height = h.parse().ok(),
+                        _ => {}
+                    }
+                }
+            }
+            "geometry" => if let Some((w, h)) = value.trim().split_once('x') {
+                width = w.parse().ok();
+                height = h.parse().ok();
+            },
+            _ => {}
+        }
+    }
+    let format = format.ok_or_else(|| invalid(0, "raw header (no EVT 2.0 or 3.0 format)"))?;
+    Ok((format, height.zip(width)))
+}
+
+impl<R: Read> AedatReader<R> {
+    /// Read the header, recognizing the format
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut consumed = 0;
+        let (format, geometry) = if next_is(&mut reader, b'%')? {
+            parse_raw_header(&mut reader, &mut consumed)?
+        } else {
+            let version = read_line(&mut reader, &mut consumed)?;
+            match version.as_str() {
+                "#!AER-DAT2.0" => {
+                    let mut layout = Aedat2Layout::Davis;
+                    while next_is(&mut reader, b'#')? {
+                        if read_line(&mut reader, &mut consumed)?.contains("DVS128") {
+                            layout = Aedat2Layout::Dvs128;
+                        }
+                    }
+                    (RawFormat::Aedat2(layout), None)
+                }
+                "#!AER-DAT3.1" => {
+                    loop {
+                        let line = read_line(&mut reader, &mut consumed)?;
+                        if line == AEDAT3_HEADER_END {
+                            break;
+                        }
+                        if line.is_empty() {
+                            return Err(DecodeError::new(consumed, DecodeErrorKind::Truncated).into());
+                        }
+                    }
+                    (RawFormat::Aedat3, None)
+                }
+                _ => match version.strip_prefix("#!AER-DAT").and_then(|v| v.split('.').next()?.parse::<u16>().ok()) {
+                    Some(major) => return Err(DecodeError::new(9, DecodeErrorKind::UnsupportedVersion(major)).into()),
+                    None => return Err(DecodeError::new(0, DecodeErrorKind::BadMagic).into()),
+                },
+            }
+        };
+        let mut aedat = Self::headerless(reader, format, consumed);
+        aedat.geometry = geometry;
+        Ok(aedat)
+    }
+
+    /// Read a stream of the given format that has no text header, eg the raw
+    /// EVT datagrams a Prophesee camera streams over the network
+    pub fn with_format(reader: R, format: RawFormat) -> Self {
+        Self::headerless(BufReader::new(reader), format, 0)
+    }
+
+    /// A reader positioned after a header of `consumed` bytes
+    fn headerless(reader: BufReader<R>, format: RawFormat, consumed: u64) -> Self {
+        let evt_bits = if format == RawFormat::Evt3 { 24 } else { 34 };
+        AedatReader {
+            reader: OffsetReader::with_offset(reader, consumed),
+            format,
+            geometry: None,
+            evt: EvtState { clock: Unwrapper::new(evt_bits), time_high: 0, time_low: 0, time: 0, y: 0, base_x: 0, polarity: 0 },
+            aedat2_clock: Unwrapper::new(32),
+            pending: Vec::new(),
+            next: 0,
+            origin: None,
+        }
+    }
+
+    pub fn format(&self) -> RawFormat {
+        self.format
+    }
+
+    /// Choose the AEDAT 2.0 address layout, where the header doesn't identify the camera
+    pub fn set_aedat2_layout(&mut self, layout: Aedat2Layout) {
+        if let RawFormat::Aedat2(_) = self.format {
+            self.format = RawFormat::Aedat2(layout);
+        }
+    }
+
+    /// the sensor (rows, cols), if the header gives them
+    pub fn geometry(&self) -> Option<(u16, u16)> {
+        self.geometry
+    }
+
+    /// hardware time, in microseconds, of the first event read
+    pub fn origin(&self) -> Option<u64> {
+        self.origin
+    }
+
+    /// Fill `buf`, or return false at the end of the input
+    fn read_unit(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        match self.reader.read_bytes(buf) {
+            Ok(()) => Ok(true),
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn push(&mut self, row: u16, col: u16, polarity: u8, time: u64) {
+        let origin = *self.origin.get_or_insert(time);
+        self.pending.push(SaeEvent {
+            row,
+            col,
+            polarity,
+            timestamp: time.saturating_sub(origin).min(SaeTime::MAX as u64) as SaeTime,
+            ..SaeEvent::default()
+        });
+    }
+
+    /// Decode input until some events are pending; false at the end of the input
+    fn fill(&mut self) -> io::Result<bool> {
+        self.pending.clear();
+        self.next = 0;
+        while self.pending.is_empty() {
+            let more = match self.format {
+                RawFormat::Aedat2(layout) => self.read_aedat2(layout)?,
+                RawFormat::Aedat3 => self.read_aedat3_packet()?,
+                RawFormat::Evt2 => self.read_evt2()?,
+                RawFormat::Evt3 => self.read_evt3()?,
+            };
+            if !more {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn read_aedat2(&mut self, layout: Aedat2Layout) -> io::Result<bool> {
+        let mut raw = [0u8; 8];
+        if !self.read_unit(&mut raw)? {
+            return Ok(false);
+        }
+        let address = u32::from_be_bytes([raw[0], raw[1], raw[2], raw[3]]);
+        let time = self.aedat2_clock.unwrap(u32::from_be_bytes([raw[4], raw[5], raw[6], raw[7]]) as u64);
+        match layout {
+            Aedat2Layout::Davis => {
+                // bit 31 marks frame (APS) samples and bit 10 external events
+                if address & 0x8000_0000 == 0 && address & 0x400 == 0 {
+                    let (row, col) = ((address >> 22) & 0x1FF, (address >> 12) & 0x3FF);
+                    self.push(row as u16, col as u16, ((address >> 11) & 1) as u8, time);
+                }
+            }
+            Aedat2Layout::Dvs128 => {
+                // bit 15 marks external events
+                if address & 0x8000 == 0 {
+                    let (row, col) = ((address >> 8) & 0x7F, (address >> 1) & 0x7F);
+                    self.push(row as u16, col as u16, (address & 1 == 0) as u8, time);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn read_aedat3_packet(&mut self) -> io::Result<bool> {
+        let mut header = [0u8; AEDAT3_PACKET_HEADER_LEN];
+        if !self.read_unit(&mut header)? {
+            return Ok(false);
+        }
+        let offset = self.reader.offset();
+        let word = |pos: usize| i32::from_le_bytes([header[pos], header[pos + 1], header[pos + 2], header[pos + 3]]);
+        let event_type = i16::from_le_bytes([header[0], header[1]]);
+        let (event_size, overflow, capacity) = (word(4), word(12), word(16));
+        if event_size <= 0 || capacity < 0 {
+            return Err(invalid(offset - AEDAT3_PACKET_HEADER_LEN as u64, "AEDAT 3.1 packet header"));
+        }
+        let mut body = vec![0u8; event_size as usize * capacity as usize];
+        self.reader.read_bytes(&mut body)?;
+        if event_type != AEDAT3_POLARITY_EVENT {
+            return Ok(true);
+        }
+        if event_size as usize != AEDAT3_POLARITY_EVENT_LEN {
+            return Err(invalid(offset - AEDAT3_PACKET_HEADER_LEN as u64, "AEDAT 3.1 polarity event size"));
+        }
+        for raw in body.chunks(AEDAT3_POLARITY_EVENT_LEN) {
+            let data = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+            if data & 1 == 0 {
+                // invalidated event
+                continue;
+            }
+            let timestamp = i32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]) as u32 as u64;
+            let time = ((overflow as u32 as u64) << 31) | timestamp;
+            self.push(((data >> 2) & 0x7FFF) as u16, ((data >> 17) & 0x7FFF) as u16, ((data >> 1) & 1) as u8, time);
+        }
+        Ok(true)
+    }
+
+    fn read_evt2(&mut self) -> io::Result<bool> {
+        let mut raw = [0u8; 4];
+        if !self.read_unit(&mut raw)? {
+            return Ok(false);
+        }
+        let word = u32::from_le_bytes(raw);
+        let evt = &mut self.evt;
+        match word >> 28 {
+            // CD_OFF, CD_ON
+            kind @ 0x0..=0x1 => {
+                let time = evt.clock.unwrap((evt.time_high << 6) | ((word >> 22) & 0x3F) as u64);
+                let (row, col) = ((word & 0x7FF) as u16, ((word >> 11) & 0x7FF) as u16);
+                self.push(row, col, kind as u8, time);
+            }
+            // EVT_TIME_HIGH
+            0x8 => evt.time_high = (word & 0x0FFF_FFFF) as u64,
+            // triggers and vendor events
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn read_evt3(&mut self) -> io::Result<bool> {
+        let mut raw = [0u8; 2];
+        if !self.read_unit(&mut raw)? {
+            return Ok(false);
+        }
+        let word = u16::from_le_bytes(raw);
+        let value = word & 0x7FF;
+        let evt = self.evt;
+        match word >> 12 {
+            // EVT_ADDR_Y
+            0x0 => self.evt.y = value,
+            // EVT_ADDR_X: a single event
+            0x2 => self.push(evt.y, value, ((word >> 11) & 1) as u8, evt.time),
+            // VECT_BASE_X
+            0x3 => {
+                self.evt.base_x = value;
+                self.evt.polarity = ((word >> 11) & 1) as u8;
+            }
+            // VECT_12, VECT_8: events at the set bits, from the base x
+            kind @ 0x4..=0x5 => {
+                let width = if kind == 0x4 { 12 } else { 8 };
+                let mask = word & ((1 << width) - 1);
+                for bit in (0..width).filter(|bit| mask & (1 << bit) != 0) {
+                    self.push(evt.y, evt.base_x + bit, evt.polarity, evt.time);
+                }
+                self.evt.base_x += width;
+            }
+            // EVT_TIME_LOW, EVT_TIME_HIGH
+            0x6 | 0x8 => {
+                let state = &mut self.evt;
+                if word >> 12 == 0x6 {
+                    state.time_low = (word & 0xFFF) as u64;
+                } else {
+                    state.time_high = (word & 0xFFF) as u64;
+                }
+                state.time = state.clock.unwrap((state.time_high << 12) | state.time_low);
+            }
+            // triggers, vendor events and their continuations
+            _ => {}
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> EventSource for AedatReader<R> {
+    fn next_event(&mut self) -> io::Result<Option<SaeEvent>> {
+        if self.next == self.pending.len() && !self.fill()? {
+            return Ok(None);
+        }
+        let evt = self.pending[self.next].clone();
+        self.next += 1;
+        Ok(Some(evt))
+    }
+}
+
+impl<R: Read> Iterator for AedatReader<R> {
+    type Item = io::Result<SaeEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(bytes: &[u8]) -> io::Result<Vec<SaeEvent>> {
+        AedatReader::new(bytes)?.collect()
+    }
+
+    fn positions(events: &[SaeEvent]) -> Vec<(u16, u16, u8, SaeTime)> {
+        events.iter().map(|evt| (evt.row, evt.col, evt.polarity, evt.timestamp)).collect()
+    }
+
+    #[test]
+    fn test_aedat_files() {
+        // AEDAT 2.0, DAVIS layout, with a timestamp rollover and a frame sample skipped
+        let mut bytes = b"#!AER-DAT2.0\r\n# This is a raw AE data file\r\n".to_vec();
+        for (address, timestamp) in [((5u32 << 22) | (7 << 12) | (1 << 11), 0xFFFF_FF00u32),
+                                     (0x8000_0000, 0xFFFF_FF80), ((6 << 22) | (8 << 12), 0x0000_0010)].iter() {
+            bytes.extend_from_slice(&address.to_be_bytes());
+            bytes.extend_from_slice(&timestamp.to_be_bytes());
+        }
+        assert_eq!(positions(&read_all(&bytes).unwrap()), vec![(5, 7, 1, 0), (6, 8, 0, 0x110)]);
+
+        // AEDAT 3.1: a polarity packet with an invalid event, and a packet of another type
+        let mut bytes = b"#!AER-DAT3.1\r\n#Format: RAW\r\n#!END-HEADER\r\n".to_vec();
+        for (event_type, events) in [(AEDAT3_POLARITY_EVENT, vec![(1u32 | 2 | (3 << 2) | (4 << 17), 100i32), (0, 150), (1 | (9 << 2) | (2 << 17), 200)]),
+                                     (2, vec![(0, 0)])].iter() {
+            for field in [*event_type as i32 & 0xFFFF, 8, 4, 1, events.len() as i32, events.len() as i32, events.len() as i32].iter() {
+                bytes.extend_from_slice(&field.to_le_bytes());
+            }
+            for (data, timestamp) in events.iter() {
+                bytes.extend_from_slice(&data.to_le_bytes());
+                bytes.extend_from_slice(&timestamp.to_le_bytes());
+            }
+        }
+        let mut reader = AedatReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.format(), RawFormat::Aedat3);
+        let events: Vec<SaeEvent> = reader.by_ref().collect::<io::Result<_>>().unwrap();
+        assert_eq!(positions(&events), vec![(3, 4, 1, 0), (9, 2, 0, 100)]);
+        assert_eq!(reader.origin(), Some((1 << 31) | 100));
+
+        let err = AedatReader::new(&b"#!AER-DAT4.0\r\n"[..]).err().unwrap();
+        assert_eq!(DecodeError::of(&err).unwrap().kind, DecodeErrorKind::UnsupportedVersion(4));
+        let err = AedatReader::new(&b"\x01\x02"[..]).err().unwrap();
+        assert_eq!(DecodeError::of(&err).unwrap().kind, DecodeErrorKind::BadMagic);
+    }
+
+    #[test]
+    fn test_evt_raw_files() {
+        // EVT 2.0: time high, an ON and an OFF event, a trigger
+        let mut bytes = b"% camera_integrator_name Prophesee\n% format EVT2;height=720;width=1280\n% end\n".to_vec();
+        for word in [(0x8u32 << 28) | 3, (1 << 28) | (5 << 22) | (10 << 11) | 20, (0xA << 28), (6 << 22) | (11 << 11) | 21].iter() {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        let mut reader = AedatReader::new(&bytes[..]).unwrap();
+        assert_eq!((reader.format(), reader.geometry()), (RawFormat::Evt2, Some((720, 1280))));
+        let events: Vec<SaeEvent> = reader.by_ref().collect::<io::Result<_>>().unwrap();
+        assert_eq!(positions(&events), vec![(20, 10, 1, 0), (21, 11, 0, 1)]);
+        assert_eq!(reader.origin(), Some((3 << 6) | 5));
+
+        // EVT 3.0: a single event, a 12-bit vector, then a rollover of the 24-bit clock
+        let mut bytes = b"% evt 3.0\n% geometry 640x480\n".to_vec();
+        let words: [u16; 9] = [
+            (0x8 << 12) | 0xFFF, (0x6 << 12) | 0xFF0,
+            42, (0x2 << 12) | (1 << 11) | 7,
+            (0x3 << 12) | 100, (0x4 << 12) | 0b1000_0000_0101,
+            (0x8 << 12), (0x6 << 12) | 0x020, (0x2 << 12) | 9,
+        ];
+        for word in words.iter() {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        let mut reader = AedatReader::new(&bytes[..]).unwrap();
+        assert_eq!((reader.format(), reader.geometry()), (RawFormat::Evt3, Some((480, 640))));
+        let events: Vec<SaeEvent> = reader.by_ref().collect::<io::Result<_>>().unwrap();
+        assert_eq!(positions(&events), vec![(42, 7, 1, 0), (42, 100, 0, 0), (42, 102, 0, 0), (42, 111, 0, 0), (42, 9, 0, 0x30)]);
+    }
+}
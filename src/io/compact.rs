@@ -0,0 +1,1318 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! The crate's own compact binary recording format.
+//!
+//! A recording consists of a header describing the sensor geometry and the detector
+//! configuration in use, followed by fixed-size little-endian event records:
+//! ```text
+//! header:  magic "ARCSTAR\0" | version u16 | nrows u16 | ncols u16 |
+//!          warmup.min_populated_fraction f32 | warmup.min_elapsed u32 |
+//!          settings_len u32 | settings
+//! record:  row u16 | col u16 | polarity u8 | timestamp u32
+//! ```
+//! The settings are those of `RecordingHeader` beyond the geometry, which
+//! `ReplayDetector` applies so that a replay matches the live run:
+//! ```text
+//! settings: settings_version u16 | detection_policy u8 | update_policy u8 |
+//!           has_subpixel u8 [radius u32 | tau f32 | max_offset f32] |
+//!           has_detector u8 [inner ring | outer ring | min_border_inset u32 |
+//!                            descriptor u8 | binary_descriptor u8 | has_max_age u8 | max_age u32 |
+//!                            has_dead_pixels u8 [nrows u16 | ncols u16 | count u32 | (row u16 | col u16)...]]
+//! ring:     count u16 | (row i32 | col i32)... | min_arc_len u16 | max_arc_len u16
+//! ```
+//! This is format version 3. Versions 1 and 2 have no settings, and replay with the
+//! defaults.
+//! Because records are fixed-size, a time-ordered recording can be positioned at
+//! an arbitrary timestamp by binary search, without decoding from the beginning.
+//! Readers can optionally check records for corruption, and either fail or skip
+//! ahead to the next run of consistent records (see `CorruptionPolicy`).
+//!
+//! Recordings written with `CompactWriter::chunked` (format version 4) group the
+//! records into chunks of a fixed number of records, each followed by a trailer
+//! carrying a CRC-32 over the chunk:
+//! ```text
+//! header:  magic "ARCSTAR\0" | version u16 | nrows u16 | ncols u16 |
+//!          warmup.min_populated_fraction f32 | warmup.min_elapsed u32 | chunk_records u32 |
+//!          settings_len u32 | settings
+//! trailer: marker "CHNK" | records u32 | first timestamp u32 | last timestamp u32 | crc u32
+//! ```
+//! Only the last chunk may hold fewer records. Chunks stay at fixed offsets, so
+//! seeking still works, and a damaged stretch of a long recording costs only the
+//! chunks it touches: `recover_recording` keeps the intact chunks and reports the
+//! time ranges that were lost.
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::circle::CircleSpec;
+use crate::detector::{DetectionPolicy, DetectorConfig};
+use crate::drops::{DropObserver, DropReason};
+use crate::io::decode::{DecodeError, DecodeErrorKind, OffsetReader};
+use crate::io::npy::crc32_update;
+use crate::sae_types::*;
+use crate::source::EventSource;
+use crate::subpixel::SubpixelConfig;
+use crate::surface::{UpdatePolicy, WarmupConfig};
+
+pub use crate::surface::RecordingHeader;
+
+
+const MAGIC: &[u8; 8] = b"ARCSTAR\0";
+const FORMAT_VERSION: u16 = 1;
+const CHUNKED_FORMAT_VERSION: u16 = 2;
+/// versions 1 and 2 followed by the detector settings
+const SETTINGS_FORMAT_VERSION: u16 = 3;
+const CHUNKED_SETTINGS_FORMAT_VERSION: u16 = 4;
+/// version of the encoding of the detector settings
+const SETTINGS_VERSION: u16 = 1;
+const CHUNK_MARKER: &[u8; 4] = b"CHNK";
+/// size in bytes of the recording header, up to the detector settings
+pub const HEADER_LEN: usize = 22;
+/// size in bytes of the header of a chunked recording, up to the detector settings
+pub const CHUNKED_HEADER_LEN: usize = 26;
+/// size in bytes of one encoded event record
+pub const RECORD_LEN: usize = 9;
+/// size in bytes of the trailer closing each chunk of a chunked recording
+pub const TRAILER_LEN: usize = 20;
+
+/// Where the records of a recording lie
+#[derive(Clone, Copy, Debug)]
+struct Layout {
+    /// byte offset of the first record
+    start: u64,
+    /// records per chunk of a chunked recording
+    chunk_records: Option<u32>,
+}
+
+impl Layout {
+    /// Byte offset of record `index`
+    fn record_offset(&self, index: u64) -> u64 {
+        match self.chunk_records {
+            None => self.start + index * (RECORD_LEN as u64),
+            Some(chunk_records) => {
+                let chunk_records = chunk_records as u64;
+                let chunk_len = chunk_records * (RECORD_LEN as u64) + TRAILER_LEN as u64;
+                self.start + (index / chunk_records) * chunk_len + (index % chunk_records) * (RECORD_LEN as u64)
+            }
+        }
+    }
+}
+
+fn write_ring(buf: &mut Vec<u8>, ring: &CircleSpec) {
+    buf.extend_from_slice(&(ring.len() as u16).to_le_bytes());
+    for offset in ring.offsets() {
+        buf.extend_from_slice(&offset[0].to_le_bytes());
+        buf.extend_from_slice(&offset[1].to_le_bytes());
+    }
+    buf.extend_from_slice(&(ring.min_arc_len() as u16).to_le_bytes());
+    buf.extend_from_slice(&(ring.max_arc_len() as u16).to_le_bytes());
+}
+
+fn read_ring<R: Read>(reader: &mut OffsetReader<R>) -> io::Result<CircleSpec> {
+    let len = reader.read_u16()?;
+    let mut offsets = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        offsets.push([reader.read_u32()? as i32, reader.read_u32()? as i32]);
+    }
+    let min_arc_len = reader.read_u16()? as usize;
+    let max_arc_len = reader.read_u16()? as usize;
+    CircleSpec::new(offsets, min_arc_len, max_arc_len).ok_or_else(|| reader.error(DecodeErrorKind::Invalid("detector ring")))
+}
+
+impl RecordingHeader {
+    pub(crate) fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.write_layout(writer, None)
+    }
+
+    fn write_layout<W: Write>(&self, writer: &mut W, chunk_records: Option<u32>) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        let version = if chunk_records.is_some() { CHUNKED_SETTINGS_FORMAT_VERSION } else { SETTINGS_FORMAT_VERSION };
+        writer.write_all(&version.to_le_bytes())?;
+        writer.write_all(&self.nrows.to_le_bytes())?;
+        writer.write_all(&self.ncols.to_le_bytes())?;
+        writer.write_all(&self.warmup.min_populated_fraction.to_bits().to_le_bytes())?;
+        writer.write_all(&self.warmup.min_elapsed.to_le_bytes())?;
+        if let Some(chunk_records) = chunk_records {
+            writer.write_all(&chunk_records.to_le_bytes())?;
+        }
+        let settings = self.settings();
+        writer.write_all(&(settings.len() as u32).to_le_bytes())?;
+        writer.write_all(&settings)
+    }
+
+    /// size in bytes of the header as written, for a chunked recording or not
+    pub fn encoded_len(&self, chunked: bool) -> usize {
+        let fixed = if chunked { CHUNKED_HEADER_LEN } else { HEADER_LEN };
+        fixed + 4 + self.settings().len()
+    }
+
+    /// Encode the detector settings
+    fn settings(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&SETTINGS_VERSION.to_le_bytes());
+        buf.push(match self.detection_policy {
+            DetectionPolicy::OnSurfaceOnly => 0,
+            DetectionPolicy::OffSurfaceOnly => 1,
+            DetectionPolicy::MatchEventPolarity => 2,
+            DetectionPolicy::CombinedMaxSurface => 3,
+        });
+        buf.push(match self.update_policy {
+            UpdatePolicy::Always => 0,
+            UpdatePolicy::IgnoreOlder => 1,
+            UpdatePolicy::IgnoreOlderOrEqual => 2,
+        });
+        buf.push(self.subpixel.is_some() as u8);
+        if let Some(subpixel) = self.subpixel.as_ref() {
+            buf.extend_from_slice(&(subpixel.radius as u32).to_le_bytes());
+            buf.extend_from_slice(&subpixel.tau.to_bits().to_le_bytes());
+            buf.extend_from_slice(&subpixel.max_offset.to_bits().to_le_bytes());
+        }
+        buf.push(self.detector.is_some() as u8);
+        if let Some(config) = self.detector.as_ref() {
+            write_ring(&mut buf, &config.inner);
+            write_ring(&mut buf, &config.outer);
+            buf.extend_from_slice(&(config.min_border_inset as u32).to_le_bytes());
+            buf.push(config.descriptor as u8);
+            buf.push(config.binary_descriptor as u8);
+            buf.push(config.max_age.is_some() as u8);
+            buf.extend_from_slice(&config.max_age.unwrap_or(0).to_le_bytes());
+            buf.push(config.dead_pixels.is_some() as u8);
+            if let Some(dead) = config.dead_pixels.as_ref() {
+                buf.extend_from_slice(&(dead.nrows() as u16).to_le_bytes());
+                buf.extend_from_slice(&(dead.ncols() as u16).to_le_bytes());
+                let pixels: Vec<(usize, usize)> = (0..dead.nrows())
+                    .flat_map(|row| (0..dead.ncols()).map(move |col| (row, col)))
+                    .filter(|&(row, col)| dead[(row, col)])
+                    .collect();
+                buf.extend_from_slice(&(pixels.len() as u32).to_le_bytes());
+                for (row, col) in pixels {
+                    buf.extend_from_slice(&(row as u16).to_le_bytes());
+                    buf.extend_from_slice(&(col as u16).to_le_bytes());
+                }
+            }
+        }
+        buf
+    }
+
+    /// Decode the detector settings into the header
+    fn read_settings<R: Read>(&mut self, reader: &mut OffsetReader<R>) -> io::Result<()> {
+        let offset = reader.offset();
+        let version = reader.read_u16()?;
+        if version != SETTINGS_VERSION {
+            return Err(DecodeError::new(offset, DecodeErrorKind::UnsupportedVersion(version)).into());
+        }
+        self.detection_policy = match reader.read_u8()? {
+            0 => DetectionPolicy::OnSurfaceOnly,
+            1 => DetectionPolicy::OffSurfaceOnly,
+            2 => DetectionPolicy::MatchEventPolarity,
+            3 => DetectionPolicy::CombinedMaxSurface,
+            _ => return Err(DecodeError::new(reader.offset() - 1, DecodeErrorKind::Invalid("detection policy")).into()),
+        };
+        self.update_policy = match reader.read_u8()? {
+            0 => UpdatePolicy::Always,
+            1 => UpdatePolicy::IgnoreOlder,
+            2 => UpdatePolicy::IgnoreOlderOrEqual,
+            _ => return Err(DecodeError::new(reader.offset() - 1, DecodeErrorKind::Invalid("update policy")).into()),
+        };
+        if reader.read_u8()? != 0 {
+            self.subpixel = Some(SubpixelConfig {
+                radius: reader.read_u32()? as usize,
+                tau: reader.read_f32()?,
+                max_offset: reader.read_f32()?,
+            });
+        }
+        if reader.read_u8()? != 0 {
+            let inner = read_ring(reader)?;
+            let outer = read_ring(reader)?;
+            let mut config = DetectorConfig::new(inner, outer);
+            config.min_border_inset = reader.read_u32()? as usize;
+            config.descriptor = reader.read_u8()? != 0;
+            config.binary_descriptor = reader.read_u8()? != 0;
+            let has_max_age = reader.read_u8()? != 0;
+            let max_age = reader.read_u32()?;
+            config.max_age = if has_max_age { Some(max_age) } else { None };
+            if reader.read_u8()? != 0 {
+                let nrows = reader.read_u16()? as usize;
+                let ncols = reader.read_u16()? as usize;
+                let mut dead = SaeOccupancy::from_element(nrows, ncols, false);
+                for _ in 0..reader.read_u32()? {
+                    let (row, col) = (reader.read_u16()? as usize, reader.read_u16()? as usize);
+                    if row >= nrows || col >= ncols {
+                        return Err(reader.error(DecodeErrorKind::Invalid("dead pixel")));
+                    }
+                    dead[(row, col)] = true;
+                }
+                config.dead_pixels = Some(dead);
+            }
+            self.detector = Some(config);
+        }
+        Ok(())
+    }
+
+    /// Read the header, and where its records lie
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<(Self, Layout)> {
+        let mut reader = OffsetReader::new(reader);
+        let version = reader.expect_versions(MAGIC, &[FORMAT_VERSION, CHUNKED_FORMAT_VERSION,
+                                                      SETTINGS_FORMAT_VERSION, CHUNKED_SETTINGS_FORMAT_VERSION])?;
+        let nrows = reader.read_u16()?;
+        let ncols = reader.read_u16()?;
+        let min_populated_fraction = reader.read_f32()?;
+        let min_elapsed = reader.read_u32()?;
+        let chunk_records = if version == CHUNKED_FORMAT_VERSION || version == CHUNKED_SETTINGS_FORMAT_VERSION {
+            let offset = reader.offset();
+            let chunk_records = reader.read_u32()?;
+            if chunk_records == 0 {
+                return Err(DecodeError::new(offset, DecodeErrorKind::Invalid("chunk size")).into());
+            }
+            Some(chunk_records)
+        } else {
+            None
+        };
+
+        // recordings from before the settings were recorded replay with the defaults
+        let mut header = RecordingHeader::new(nrows, ncols, WarmupConfig { min_populated_fraction, min_elapsed });
+        if version >= SETTINGS_FORMAT_VERSION {
+            let settings_len = reader.read_u32()?;
+            let offset = reader.offset();
+            let mut settings = vec![0u8; settings_len as usize];
+            reader.read_bytes(&mut settings)?;
+            let mut settings_reader = OffsetReader::with_offset(&settings[..], offset);
+            header.read_settings(&mut settings_reader)?;
+        }
+        Ok((header, Layout { start: reader.offset(), chunk_records }))
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Whether `evt` can belong to a recording of `header`, `previous` being the time of the record before it
+fn is_plausible(header: &RecordingHeader, evt: &SaeEvent, previous: Option<SaeTime>, max_time_jump: SaeTime) -> bool {
+    evt.row < header.nrows && evt.col < header.ncols && evt.polarity <= 1 &&
+        previous.is_none_or(|prev| evt.timestamp.abs_diff(prev) <= max_time_jump)
+}
+
+/// Checksum and extent of the records of one chunk, as written or read so far
+#[derive(Clone, Debug, Default)]
+struct ChunkState {
+    records: u32,
+    crc: u32,
+    first: SaeTime,
+    last: SaeTime,
+    /// whether the chunk's records were seen from its start, so it can be verified
+    complete: bool,
+}
+
+impl ChunkState {
+    fn new() -> Self {
+        ChunkState { complete: true, ..ChunkState::default() }
+    }
+
+    fn add(&mut self, record: &[u8; RECORD_LEN], timestamp: SaeTime) {
+        if self.records == 0 {
+            self.first = timestamp;
+        }
+        self.records += 1;
+        self.last = timestamp;
+        self.crc = crc32_update(self.crc, record);
+    }
+
+    /// the trailer closing the records added so far
+    fn trailer(&self) -> [u8; TRAILER_LEN] {
+        let mut buf = [0u8; TRAILER_LEN];
+        buf[0..4].copy_from_slice(CHUNK_MARKER);
+        buf[4..8].copy_from_slice(&self.records.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.first.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.last.to_le_bytes());
+        let crc = crc32_update(self.crc, &buf[4..16]);
+        buf[16..20].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+}
+
+/// Read until `buf` is full or the input ends, returning the number of bytes read
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(nread) => filled += nread,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Encode the identifying fields of an event (the descriptor is not recorded)
+pub fn encode_event(evt: &SaeEvent) -> [u8; RECORD_LEN] {
+    let mut buf = [0u8; RECORD_LEN];
+    buf[0..2].copy_from_slice(&evt.row.to_le_bytes());
+    buf[2..4].copy_from_slice(&evt.col.to_le_bytes());
+    buf[4] = evt.polarity;
+    buf[5..9].copy_from_slice(&evt.timestamp.to_le_bytes());
+    buf
+}
+
+/// Decode an event record produced by `encode_event`
+pub fn decode_event(buf: &[u8; RECORD_LEN]) -> SaeEvent {
+    SaeEvent {
+        row: u16::from_le_bytes([buf[0], buf[1]]),
+        col: u16::from_le_bytes([buf[2], buf[3]]),
+        polarity: buf[4],
+        timestamp: u32::from_le_bytes([buf[5], buf[6], buf[7], buf[8]]),
+        ..SaeEvent::default()
+    }
+}
+
+/// Writes events to a compact recording
+pub struct CompactWriter<W: Write> {
+    writer: W,
+    count: u64,
+    /// records per chunk, and the chunk being written, for chunked recordings
+    chunking: Option<(u32, ChunkState)>,
+}
+
+impl<W: Write> CompactWriter<W> {
+    /// Writes the header immediately
+    pub fn new(mut writer: W, header: &RecordingHeader) -> io::Result<Self> {
+        header.write_to(&mut writer)?;
+        Ok(CompactWriter { writer, count: 0, chunking: None })
+    }
+
+    /// Write a chunked recording, closing every `chunk_records` records with a checksummed
+    /// trailer. Writes the header immediately. The last, partial chunk is closed by `into_inner`;
+    /// a recording cut short before then still recovers up to its last complete chunk.
+    pub fn chunked(mut writer: W, header: &RecordingHeader, chunk_records: u32) -> io::Result<Self> {
+        if chunk_records == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "chunks must hold at least one record"));
+        }
+        header.write_layout(&mut writer, Some(chunk_records))?;
+        Ok(CompactWriter { writer, count: 0, chunking: Some((chunk_records, ChunkState::new())) })
+    }
+
+    pub fn write_event(&mut self, evt: &SaeEvent) -> io::Result<()> {
+        let record = encode_event(evt);
+        self.writer.write_all(&record)?;
+        self.count += 1;
+        if let Some((chunk_records, chunk)) = self.chunking.as_mut() {
+            chunk.add(&record, evt.timestamp);
+            if chunk.records == *chunk_records {
+                self.writer.write_all(&chunk.trailer())?;
+                *chunk = ChunkState::new();
+            }
+        }
+        Ok(())
+    }
+
+    /// number of events written so far
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// close the last chunk of a chunked recording, then flush and return the underlying writer
+    pub fn into_inner(mut self) -> io::Result<W> {
+        if let Some((_, chunk)) = self.chunking.as_ref() {
+            if chunk.records > 0 {
+                self.writer.write_all(&chunk.trailer())?;
+            }
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// What a reader does with records that cannot belong to the recording:
+/// a position outside the sensor, a polarity other than 0 or 1, or a timestamp
+/// too far from that of the previous record
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CorruptionPolicy {
+    /// decode every record as it is
+    Ignore,
+    /// return an `InvalidData` error carrying a `DecodeError`
+    Fail,
+    /// skip ahead byte by byte until records decode consistently again, so that a
+    /// corrupted stretch of a live stream does not stop the pipeline
+    Resync,
+}
+
+/// How a reader detects and handles corrupted records
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorruptionConfig {
+    pub policy: CorruptionPolicy,
+    /// records whose timestamp differs from the previous record's by more than this are corrupt
+    pub max_time_jump: SaeTime,
+    /// consecutive consistent records required to resume decoding after a resync
+    pub confirm_records: usize,
+}
+
+impl Default for CorruptionConfig {
+    fn default() -> Self {
+        CorruptionConfig {
+            policy: CorruptionPolicy::Ignore,
+            max_time_jump: 1_000_000,
+            confirm_records: 4,
+        }
+    }
+}
+
+/// Reads events from a compact recording, as an iterator
+pub struct CompactReader<R: Read> {
+    reader: R,
+    header: RecordingHeader,
+    /// current byte offset within the recording
+    position: u64,
+    corruption: CorruptionConfig,
+    /// bytes read from the underlying reader but not yet decoded
+    lookahead: VecDeque<u8>,
+    last_timestamp: Option<SaeTime>,
+    skipped_bytes: u64,
+    resyncs: u64,
+    drop_observer: Option<Box<dyn DropObserver>>,
+    layout: Layout,
+    /// the records of the current chunk read so far
+    chunk: ChunkState,
+    damaged_chunks: u64,
+}
+
+impl<R: Read> CompactReader<R> {
+    /// Reads and validates the header immediately
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let (header, layout) = RecordingHeader::read_from(&mut reader)?;
+        Ok(CompactReader {
+            reader,
+            header,
+            position: layout.start,
+            corruption: CorruptionConfig::default(),
+            lookahead: VecDeque::new(),
+            last_timestamp: None,
+            skipped_bytes: 0,
+            resyncs: 0,
+            drop_observer: None,
+            layout,
+            chunk: ChunkState::new(),
+            damaged_chunks: 0,
+        })
+    }
+
+    pub fn header(&self) -> &RecordingHeader {
+        &self.header
+    }
+
+    /// records per chunk, for chunked recordings
+    pub fn chunk_records(&self) -> Option<u32> {
+        self.layout.chunk_records
+    }
+
+    /// byte offset of the first record, after the header
+    pub fn data_offset(&self) -> u64 {
+        self.layout.start
+    }
+
+    /// current byte offset within the recording
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Check records for corruption (they are decoded as-is by default)
+    pub fn set_corruption_config(&mut self, config: CorruptionConfig) {
+        self.corruption = config;
+    }
+
+    /// Report each corrupted stretch skipped while resynchronizing to `observer`
+    pub fn set_drop_observer<O: DropObserver + 'static>(&mut self, observer: O) {
+        self.drop_observer = Some(Box::new(observer));
+    }
+
+    fn report_corruption(&mut self) {
+        if let Some(observer) = self.drop_observer.as_mut() {
+            observer.dropped(DropReason::Corrupted, None);
+        }
+    }
+
+    /// bytes skipped while resynchronizing
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
+    }
+
+    /// number of times decoding lost and regained record alignment
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    /// Number of chunks of a chunked recording whose checksum did not match, or that
+    /// were skipped while resynchronizing. A chunk's checksum is only checked once all its
+    /// records have been read, so the records of a damaged chunk have already been returned;
+    /// use `recover_recording` to keep only intact chunks.
+    pub fn damaged_chunks(&self) -> u64 {
+        self.damaged_chunks
+    }
+
+    /// Read until at least `len` bytes are buffered; false if the input ends first
+    fn fill_lookahead(&mut self, len: usize) -> io::Result<bool> {
+        let mut buf = [0u8; 64];
+        while self.lookahead.len() < len {
+            let nread = match self.reader.read(&mut buf) {
+                Ok(nread) => nread,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            if nread == 0 {
+                return Ok(false);
+            }
+            self.lookahead.extend(&buf[..nread]);
+        }
+        Ok(true)
+    }
+
+    /// decode the buffered record starting `start` bytes into the lookahead
+    fn buffered_record(&self, start: usize) -> SaeEvent {
+        let mut buf = [0u8; RECORD_LEN];
+        for (idx, byte) in buf.iter_mut().enumerate() {
+            *byte = self.lookahead[start + idx];
+        }
+        decode_event(&buf)
+    }
+
+    fn skip(&mut self, len: usize) {
+        self.lookahead.drain(..len);
+        self.position += len as u64;
+        self.skipped_bytes += len as u64;
+    }
+
+    fn is_plausible(&self, evt: &SaeEvent, previous: Option<SaeTime>) -> bool {
+        is_plausible(&self.header, evt, previous, self.corruption.max_time_jump)
+    }
+
+    fn chunk_damaged(&mut self) {
+        self.damaged_chunks += 1;
+        self.report_corruption();
+    }
+
+    /// Whether the next bytes are a chunk trailer: either the current chunk is full,
+    /// or they are the trailer closing the shorter last chunk. Only bytes starting with
+    /// the chunk marker, which no record on a sensor of up to 18498 rows starts with, are
+    /// looked past for the end of the input, so a live stream is not held back.
+    fn at_trailer(&mut self) -> io::Result<bool> {
+        let chunk_records = match self.layout.chunk_records {
+            Some(chunk_records) => chunk_records,
+            None => return Ok(false),
+        };
+        if self.chunk.records >= chunk_records {
+            return Ok(true);
+        }
+        if !self.fill_lookahead(CHUNK_MARKER.len())? ||
+            !self.lookahead.iter().take(CHUNK_MARKER.len()).eq(CHUNK_MARKER.iter()) {
+            return Ok(false);
+        }
+        let more = self.fill_lookahead(TRAILER_LEN + 1)?;
+        Ok(!more && self.lookahead.len() == TRAILER_LEN &&
+            self.lookahead.iter().take(CHUNK_MARKER.len()).eq(CHUNK_MARKER.iter()))
+    }
+
+    /// Read and check the trailer closing the current chunk; false if the input ends first
+    fn read_trailer(&mut self) -> io::Result<bool> {
+        if !self.fill_lookahead(TRAILER_LEN)? {
+            return Ok(false);
+        }
+        let offset = self.position;
+        let mut trailer = [0u8; TRAILER_LEN];
+        for (byte, value) in trailer.iter_mut().zip(self.lookahead.drain(..TRAILER_LEN)) {
+            *byte = value;
+        }
+        self.position += TRAILER_LEN as u64;
+        let chunk = std::mem::replace(&mut self.chunk, ChunkState::new());
+        // a chunk entered partway, after a seek, can only be checked for its marker
+        let intact = if chunk.complete { trailer == chunk.trailer() } else { &trailer[..4] == CHUNK_MARKER };
+        if !intact {
+            self.chunk_damaged();
+            if self.corruption.policy == CorruptionPolicy::Fail {
+                return Err(DecodeError::new(offset, DecodeErrorKind::Invalid("chunk checksum")).into());
+            }
+        }
+        Ok(true)
+    }
+
+    /// Drop the rest of the current chunk, including its trailer: the records of a
+    /// chunked recording realign at the start of the next chunk
+    fn skip_chunk(&mut self, chunk_records: u32) -> io::Result<()> {
+        self.resyncs += 1;
+        self.chunk_damaged();
+        let chunk_len = chunk_records as u64 * RECORD_LEN as u64 + TRAILER_LEN as u64;
+        let remaining = (chunk_len - (self.position - self.layout.start) % chunk_len) as usize;
+        self.fill_lookahead(remaining)?;
+        self.skip(remaining.min(self.lookahead.len()));
+        self.chunk = ChunkState::new();
+        self.last_timestamp = None;
+        Ok(())
+    }
+
+    /// Drop bytes until the buffered records form a consistent run.
+    /// Records after a corrupted stretch may be far from the last good timestamp,
+    /// so only consistency within the run is required.
+    fn resync(&mut self) -> io::Result<()> {
+        self.resyncs += 1;
+        self.report_corruption();
+        let confirm = self.corruption.confirm_records.max(1);
+        loop {
+            self.skip(1);
+            let complete = self.fill_lookahead(confirm * RECORD_LEN)?;
+            let available = if complete { confirm } else { self.lookahead.len() / RECORD_LEN };
+            if available == 0 {
+                return Ok(());
+            }
+            let mut previous = None;
+            let consistent = (0..available).all(|k| {
+                let evt = self.buffered_record(k * RECORD_LEN);
+                let ok = self.is_plausible(&evt, previous);
+                previous = Some(evt.timestamp);
+                ok
+            });
+            if consistent {
+                self.last_timestamp = None;
+                return Ok(());
+            }
+        }
+    }
+
+    /// Read the next event, or `None` at a clean end of the recording
+    pub fn read_event(&mut self) -> io::Result<Option<SaeEvent>> {
+        loop {
+            // where the input ends in place of a trailer, the records read are unverified
+            if self.at_trailer()? && self.read_trailer()? {
+                continue;
+            }
+            if !self.fill_lookahead(RECORD_LEN)? {
+                if self.lookahead.is_empty() {
+                    return Ok(None);
+                }
+                if self.corruption.policy == CorruptionPolicy::Resync {
+                    // a partial record at the end of the input can't be recovered
+                    self.skip(self.lookahead.len());
+                    self.report_corruption();
+                    return Ok(None);
+                }
+                return Err(DecodeError::new(self.position, DecodeErrorKind::Truncated).into());
+            }
+            let evt = self.buffered_record(0);
+            if self.corruption.policy == CorruptionPolicy::Ignore || self.is_plausible(&evt, self.last_timestamp) {
+                let mut record = [0u8; RECORD_LEN];
+                for (byte, value) in record.iter_mut().zip(self.lookahead.drain(..RECORD_LEN)) {
+                    *byte = value;
+                }
+                self.position += RECORD_LEN as u64;
+                self.last_timestamp = Some(evt.timestamp);
+                if self.layout.chunk_records.is_some() {
+                    self.chunk.add(&record, evt.timestamp);
+                }
+                return Ok(Some(evt));
+            }
+            if self.corruption.policy == CorruptionPolicy::Fail {
+                return Err(DecodeError::new(self.position, DecodeErrorKind::Invalid("event record")).into());
+            }
+            match self.layout.chunk_records {
+                Some(chunk_records) => self.skip_chunk(chunk_records)?,
+                None => self.resync()?,
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> CompactReader<R> {
+    /// number of complete event records in the recording
+    pub fn record_count(&mut self) -> io::Result<u64> {
+        let pos = self.reader.stream_position()?;
+        let end = self.reader.seek(SeekFrom::End(0))?;
+        let count = match self.layout.chunk_records {
+            None => end.saturating_sub(self.layout.start) / (RECORD_LEN as u64),
+            Some(chunk_records) => {
+                let chunk_len = chunk_records as u64 * RECORD_LEN as u64 + TRAILER_LEN as u64;
+                let body = end.saturating_sub(self.layout.start);
+                let mut tail = body % chunk_len;
+                // the last chunk is shorter, and closed by a trailer unless writing was cut short
+                if tail >= TRAILER_LEN as u64 && (tail - TRAILER_LEN as u64).is_multiple_of(RECORD_LEN as u64) {
+                    let mut marker = [0u8; 4];
+                    self.reader.seek(SeekFrom::Start(end - TRAILER_LEN as u64))?;
+                    self.reader.read_exact(&mut marker)?;
+                    if &marker == CHUNK_MARKER {
+                        tail -= TRAILER_LEN as u64;
+                    }
+                }
+                (body / chunk_len) * chunk_records as u64 + (tail / RECORD_LEN as u64).min(chunk_records as u64)
+            }
+        };
+        self.reader.seek(SeekFrom::Start(pos))?;
+        Ok(count)
+    }
+
+    /// Position the reader so the next event read is the one at `index`
+    pub fn seek_to_index(&mut self, index: u64) -> io::Result<()> {
+        let offset = self.layout.record_offset(index);
+        self.position = self.reader.seek(SeekFrom::Start(offset))?;
+        self.lookahead.clear();
+        self.last_timestamp = None;
+        if let Some(chunk_records) = self.layout.chunk_records {
+            let records = (index % chunk_records as u64) as u32;
+            self.chunk = ChunkState { records, complete: records == 0, ..ChunkState::default() };
+        }
+        Ok(())
+    }
+
+    /// Position the reader at the first event with a timestamp at or after `time`,
+    /// returning the index of that event (equal to the record count if there is none).
+    /// The recording must be time-ordered.
+    pub fn seek_to_time(&mut self, time: SaeTime) -> io::Result<u64> {
+        let mut lo = 0;
+        let mut hi = self.record_count()?;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            self.seek_to_index(mid)?;
+            let evt = self.read_event()?
+                .ok_or_else(|| invalid_data("record vanished during seek"))?;
+            if evt.timestamp < time {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        self.seek_to_index(lo)?;
+        Ok(lo)
+    }
+}
+
+impl<R: Read> Iterator for CompactReader<R> {
+    type Item = io::Result<SaeEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_event().transpose()
+    }
+}
+
+impl<R: Read> EventSource for CompactReader<R> {
+    fn next_event(&mut self) -> io::Result<Option<SaeEvent>> {
+        self.read_event()
+    }
+}
+
+/// What could be verified of one chunk of a recording
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkStatus {
+    /// the trailer's checksum matches the records
+    Intact,
+    /// the checksum does not match; none of the chunk's records are kept
+    Corrupted,
+    /// the recording ends before the chunk's trailer, eg because writing was cut short,
+    /// or has no checksums at all; the leading run of plausible records is kept
+    Unterminated,
+}
+
+/// The outcome of checking one chunk
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkIntegrity {
+    pub index: usize,
+    /// byte offset of the chunk within the recording
+    pub offset: u64,
+    pub status: ChunkStatus,
+    /// number of records kept from the chunk
+    pub recovered: u64,
+}
+
+/// A stretch of a recording whose events were lost
+#[derive(Clone, Debug, PartialEq)]
+pub struct LostRange {
+    /// time of the last event recovered before the loss; None if the loss starts the recording
+    pub start: Option<SaeTime>,
+    /// time of the first event recovered after the loss; None if the loss runs to the end
+    pub end: Option<SaeTime>,
+    /// number of chunks damaged within the stretch
+    pub chunks: usize,
+}
+
+/// Which parts of a recording survived
+#[derive(Clone, Debug, PartialEq)]
+pub struct IntegrityReport {
+    pub header: RecordingHeader,
+    /// records per chunk; None for an unchunked recording, which has no checksums
+    /// and is checked for plausible records only, as a single unterminated chunk
+    pub chunk_records: Option<u32>,
+    pub chunks: Vec<ChunkIntegrity>,
+    /// the lost stretches, in time order
+    pub lost: Vec<LostRange>,
+}
+
+impl IntegrityReport {
+    /// whether every chunk was verified against its checksum
+    pub fn is_intact(&self) -> bool {
+        self.chunk_records.is_some() && self.chunks.iter().all(|chunk| chunk.status == ChunkStatus::Intact)
+    }
+
+    /// number of records kept
+    pub fn recovered(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.recovered).sum()
+    }
+}
+
+/// The events recovered from a damaged recording
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recovery {
+    pub report: IntegrityReport,
+    pub events: Vec<SaeEvent>,
+}
+
+/// Follows the recovered and damaged chunks, to delimit the lost stretches
+#[derive(Default)]
+struct LossTracker {
+    last: Option<SaeTime>,
+    open: Option<LostRange>,
+    lost: Vec<LostRange>,
+}
+
+impl LossTracker {
+    fn recovered(&mut self, events: &[SaeEvent]) {
+        if let (Some(first), Some(last)) = (events.first(), events.last()) {
+            if let Some(mut range) = self.open.take() {
+                range.end = Some(first.timestamp);
+                self.lost.push(range);
+            }
+            self.last = Some(last.timestamp);
+        }
+    }
+
+    fn damaged(&mut self) {
+        match self.open.as_mut() {
+            Some(range) => range.chunks += 1,
+            None => self.open = Some(LostRange { start: self.last, end: None, chunks: 1 }),
+        }
+    }
+
+    fn finish(mut self) -> Vec<LostRange> {
+        self.lost.extend(self.open.take());
+        self.lost
+    }
+}
+
+/// Decode the leading run of plausible records in `bytes`, up to `max_records`;
+/// the flag is false if the run ends before them, at an implausible or partial record
+fn plausible_run(header: &RecordingHeader, bytes: &[u8], max_records: usize, previous: &mut Option<SaeTime>) -> (Vec<SaeEvent>, bool) {
+    let max_time_jump = CorruptionConfig::default().max_time_jump;
+    let mut events = Vec::new();
+    for record in bytes.chunks(RECORD_LEN).take(max_records) {
+        let record: &[u8; RECORD_LEN] = match record.try_into() {
+            Ok(record) => record,
+            Err(_) => return (events, false),
+        };
+        let evt = decode_event(record);
+        if !is_plausible(header, &evt, *previous, max_time_jump) {
+            return (events, false);
+        }
+        *previous = Some(evt.timestamp);
+        events.push(evt);
+    }
+    (events, true)
+}
+
+fn scan_recording<R: Read>(reader: R, mut events: Option<&mut Vec<SaeEvent>>) -> io::Result<IntegrityReport> {
+    let mut reader = io::BufReader::new(reader);
+    let (header, layout) = RecordingHeader::read_from(&mut reader)?;
+    let chunk_records = layout.chunk_records;
+    let mut chunks = Vec::new();
+    let mut tracker = LossTracker::default();
+    let mut offset = layout.start;
+    match chunk_records {
+        None => {
+            let mut buf = vec![0u8; 4096 * RECORD_LEN];
+            let mut previous = None;
+            let mut recovered = 0;
+            loop {
+                let len = read_up_to(&mut reader, &mut buf)?;
+                let (decoded, clean) = plausible_run(&header, &buf[..len], usize::MAX, &mut previous);
+                tracker.recovered(&decoded);
+                recovered += decoded.len() as u64;
+                if let Some(events) = events.as_mut() {
+                    events.extend(decoded);
+                }
+                if !clean {
+                    tracker.damaged();
+                }
+                if !clean || len < buf.len() {
+                    break;
+                }
+            }
+            chunks.push(ChunkIntegrity { index: 0, offset, status: ChunkStatus::Unterminated, recovered });
+        }
+        Some(chunk_records) => {
+            let chunk_records = chunk_records as usize;
+            let mut buf = vec![0u8; chunk_records * RECORD_LEN + TRAILER_LEN];
+            loop {
+                let len = read_up_to(&mut reader, &mut buf)?;
+                if len == 0 {
+                    break;
+                }
+                let bytes = &buf[..len];
+                let terminated = len >= TRAILER_LEN && (len - TRAILER_LEN).is_multiple_of(RECORD_LEN) &&
+                    &bytes[len - TRAILER_LEN..len - TRAILER_LEN + 4] == CHUNK_MARKER;
+                let (status, recovered) = if terminated || len == buf.len() {
+                    let records = &bytes[..len - TRAILER_LEN];
+                    let mut chunk = ChunkState::new();
+                    let decoded: Vec<SaeEvent> = records.chunks_exact(RECORD_LEN)
+                        .map(|record| {
+                            let record = record.try_into().unwrap();
+                            let evt = decode_event(record);
+                            chunk.add(record, evt.timestamp);
+                            evt
+                        })
+                        .collect();
+                    if chunk.trailer()[..] == bytes[len - TRAILER_LEN..] {
+                        tracker.recovered(&decoded);
+                        (ChunkStatus::Intact, decoded)
+                    } else {
+                        tracker.damaged();
+                        (ChunkStatus::Corrupted, Vec::new())
+                    }
+                } else {
+                    let mut previous = None;
+                    let (decoded, clean) = plausible_run(&header, bytes, chunk_records, &mut previous);
+                    tracker.recovered(&decoded);
+                    if !clean {
+                        tracker.damaged();
+                    }
+                    (ChunkStatus::Unterminated, decoded)
+                };
+                chunks.push(ChunkIntegrity { index: chunks.len(), offset, status, recovered: recovered.len() as u64 });
+                offset += len as u64;
+                if let Some(events) = events.as_mut() {
+                    events.extend(recovered);
+                }
+            }
+        }
+    }
+    Ok(IntegrityReport { header, chunk_records, chunks, lost: tracker.finish() })
+}
+
+/// Recover what survives of a possibly damaged recording: the events of its intact
+/// chunks, and the plausible leading records of a last chunk cut short. Only the header
+/// must be intact. Damage is confined to the chunks it touches, as long as bytes were
+/// overwritten rather than lost.
+pub fn recover_recording<R: Read>(reader: R) -> io::Result<Recovery> {
+    let mut events = Vec::new();
+    let report = scan_recording(reader, Some(&mut events))?;
+    Ok(Recovery { report, events })
+}
+
+/// Check every chunk of a recording, like `recover_recording` without keeping the events
+pub fn validate_recording<R: Read>(reader: R) -> io::Result<IntegrityReport> {
+    scan_recording(reader, None)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_reader_source() {
+        let events: Vec<SaeEvent> = (0..5).map(|t| SaeEvent { timestamp: t, ..SaeEvent::default() }).collect();
+        let header = RecordingHeader::new(4, 4, WarmupConfig::default());
+        let mut writer = CompactWriter::new(Vec::new(), &header).unwrap();
+        for evt in events.iter() {
+            writer.write_event(evt).unwrap();
+        }
+        let bytes = writer.into_inner().unwrap();
+
+        let mut reader = CompactReader::new(bytes.as_slice()).unwrap();
+        let read: Vec<SaeEvent> = reader.events().map(|res| res.unwrap()).collect();
+        assert_eq!(read, events);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let header = RecordingHeader::new(240, 320, WarmupConfig::default());
+        let events: Vec<SaeEvent> = (0..10u16).map(|i| SaeEvent {
+            row: i,
+            col: 300 - i,
+            polarity: (i % 2) as u8,
+            timestamp: 1_000_000 + i as SaeTime,
+            ..SaeEvent::default()
+        }).collect();
+
+        let mut writer = CompactWriter::new(Vec::new(), &header).unwrap();
+        for evt in events.iter() {
+            writer.write_event(evt).unwrap();
+        }
+        assert_eq!(writer.count(), 10);
+        let bytes = writer.into_inner().unwrap();
+
+        let reader = CompactReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.header(), &header);
+        let decoded: Vec<SaeEvent> = reader.map(|res| res.unwrap()).collect();
+        assert_eq!(decoded, events);
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let mut header = RecordingHeader::new(24, 32, WarmupConfig::disabled());
+        let mut config = DetectorConfig::with_arc_limits((4, 5), (5, 7)).unwrap();
+        config.min_border_inset = 2;
+        config.binary_descriptor = true;
+        config.max_age = Some(50_000);
+        let mut dead = SaeOccupancy::from_element(24, 32, false);
+        dead[(3, 30)] = true;
+        dead[(20, 1)] = true;
+        config.dead_pixels = Some(dead);
+        header.detector = Some(config);
+        header.detection_policy = DetectionPolicy::CombinedMaxSurface;
+        header.update_policy = UpdatePolicy::IgnoreOlderOrEqual;
+        header.subpixel = Some(SubpixelConfig { radius: 3, tau: 5_000.0, max_offset: 0.5 });
+
+        for &chunked in [false, true].iter() {
+            let mut writer = if chunked {
+                CompactWriter::chunked(Vec::new(), &header, 4).unwrap()
+            } else {
+                CompactWriter::new(Vec::new(), &header).unwrap()
+            };
+            writer.write_event(&SaeEvent { row: 5, col: 6, timestamp: 7, ..SaeEvent::default() }).unwrap();
+            let bytes = writer.into_inner().unwrap();
+            let mut reader = CompactReader::new(bytes.as_slice()).unwrap();
+            assert_eq!(reader.header(), &header);
+            assert_eq!(reader.data_offset(), header.encoded_len(chunked) as u64);
+            assert_eq!(reader.next().unwrap().unwrap().timestamp, 7);
+        }
+    }
+
+    #[test]
+    fn test_read_version_1() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&10u16.to_le_bytes());
+        bytes.extend_from_slice(&12u16.to_le_bytes());
+        bytes.extend_from_slice(&0.5f32.to_bits().to_le_bytes());
+        bytes.extend_from_slice(&100u32.to_le_bytes());
+        bytes.extend_from_slice(&encode_event(&SaeEvent { row: 1, col: 2, timestamp: 3, ..SaeEvent::default() }));
+        let mut reader = CompactReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.header(), &RecordingHeader::new(10, 12, WarmupConfig { min_populated_fraction: 0.5, min_elapsed: 100 }));
+        assert_eq!(reader.data_offset(), HEADER_LEN as u64);
+        assert_eq!(reader.next().unwrap().unwrap().col, 2);
+    }
+
+    /// Input that is still being written: reading past the bytes so far would block
+    struct Live<'a>(&'a [u8]);
+
+    impl Read for Live<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "no more bytes yet"));
+            }
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_chunked_live_stream_yields_each_record() {
+        let bytes = chunked_recording(5);
+        // the first two records, without the trailer or any other record after them
+        let available = chunked_start() + 2 * RECORD_LEN;
+        let mut reader = CompactReader::new(Live(&bytes[..available])).unwrap();
+        assert_eq!(reader.read_event().unwrap().unwrap().row, 0);
+        assert_eq!(reader.read_event().unwrap().unwrap().row, 1);
+        assert_eq!(reader.read_event().unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn test_seek_to_time() {
+        use std::io::Cursor;
+
+        let header = RecordingHeader::new(10, 10, WarmupConfig::default());
+        let mut writer = CompactWriter::new(Vec::new(), &header).unwrap();
+        for i in 0..1000 {
+            let evt = SaeEvent { timestamp: 100 + i * 10, ..SaeEvent::default() };
+            writer.write_event(&evt).unwrap();
+        }
+        let bytes = writer.into_inner().unwrap();
+        assert_eq!(bytes.len(), unchunked_start() + 1000 * RECORD_LEN);
+
+        let mut reader = CompactReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.record_count().unwrap(), 1000);
+
+        assert_eq!(reader.seek_to_time(605).unwrap(), 51);
+        assert_eq!(reader.read_event().unwrap().unwrap().timestamp, 610);
+
+        assert_eq!(reader.seek_to_time(610).unwrap(), 51);
+        assert_eq!(reader.next().unwrap().unwrap().timestamp, 610);
+
+        assert_eq!(reader.seek_to_time(0).unwrap(), 0);
+        assert_eq!(reader.next().unwrap().unwrap().timestamp, 100);
+
+        assert_eq!(reader.seek_to_time(1_000_000).unwrap(), 1000);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert!(CompactReader::new(&b"NOTARCSTAR"[..]).is_err());
+
+        let header = RecordingHeader::new(10, 10, WarmupConfig::disabled());
+        let mut writer = CompactWriter::new(Vec::new(), &header).unwrap();
+        writer.write_event(&SaeEvent::new()).unwrap();
+        let mut bytes = writer.into_inner().unwrap();
+        bytes.pop();
+
+        let mut reader = CompactReader::new(bytes.as_slice()).unwrap();
+        let err = reader.read_event().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(DecodeError::of(&err), Some(&DecodeError::new(unchunked_start() as u64, DecodeErrorKind::Truncated)));
+
+        // no prefix of a recording panics or reads garbage
+        for len in 0..unchunked_start() {
+            assert!(CompactReader::new(&bytes[..len]).is_err());
+        }
+    }
+
+    /// offset of the first record of the recordings written by the tests
+    fn unchunked_start() -> usize {
+        RecordingHeader::new(100, 100, WarmupConfig::disabled()).encoded_len(false)
+    }
+
+    fn chunked_start() -> usize {
+        RecordingHeader::new(100, 100, WarmupConfig::disabled()).encoded_len(true)
+    }
+
+    fn corrupted_recording() -> Vec<u8> {
+        let header = RecordingHeader::new(100, 100, WarmupConfig::disabled());
+        let mut writer = CompactWriter::new(Vec::new(), &header).unwrap();
+        for i in 0..20u16 {
+            writer.write_event(&SaeEvent { row: i, col: 2 * i, timestamp: 1_000 + i as SaeTime, ..SaeEvent::default() }).unwrap();
+        }
+        let mut bytes = writer.into_inner().unwrap();
+        // a burst of garbage replaces the middle of record 5, and 7 bytes are lost
+        let start = unchunked_start() + 5 * RECORD_LEN + 3;
+        bytes.splice(start..start + 7, vec![0xff; 3]);
+        bytes
+    }
+
+    #[test]
+    fn test_corruption_policies() {
+        let bytes = corrupted_recording();
+
+        let mut reader = CompactReader::new(bytes.as_slice()).unwrap();
+        reader.set_corruption_config(CorruptionConfig { policy: CorruptionPolicy::Fail, ..CorruptionConfig::default() });
+        for _ in 0..5 {
+            reader.read_event().unwrap().unwrap();
+        }
+        let err = reader.read_event().unwrap_err();
+        let expected = DecodeError::new((unchunked_start() + 5 * RECORD_LEN) as u64, DecodeErrorKind::Invalid("event record"));
+        assert_eq!(DecodeError::of(&err), Some(&expected));
+
+        let mut reader = CompactReader::new(bytes.as_slice()).unwrap();
+        reader.set_corruption_config(CorruptionConfig { policy: CorruptionPolicy::Resync, ..CorruptionConfig::default() });
+        let corrupted = std::rc::Rc::new(std::cell::Cell::new(0));
+        let count = corrupted.clone();
+        reader.set_drop_observer(move |reason: DropReason, _: Option<&SaeEvent>| {
+            assert_eq!(reason, DropReason::Corrupted);
+            count.set(count.get() + 1);
+        });
+        let rows: Vec<u16> = reader.by_ref().map(|res| res.unwrap().row).collect();
+        // records 5 and 6 were damaged; decoding picks up again at record 7
+        let expected: Vec<u16> = (0..5).chain(7..20).collect();
+        assert_eq!(rows, expected);
+        assert_eq!(reader.resyncs(), 1);
+        assert_eq!(corrupted.get(), 1);
+        assert_eq!(reader.skipped_bytes(), (2 * RECORD_LEN - 4) as u64);
+        assert_eq!(reader.position(), bytes.len() as u64);
+    }
+
+    fn chunked_recording(count: u16) -> Vec<u8> {
+        let header = RecordingHeader::new(100, 100, WarmupConfig::disabled());
+        let mut writer = CompactWriter::chunked(Vec::new(), &header, 10).unwrap();
+        for i in 0..count {
+            writer.write_event(&SaeEvent { row: i, col: i, timestamp: 1_000 + 10 * i as SaeTime, ..SaeEvent::default() }).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_chunked_round_trip() {
+        use std::io::Cursor;
+
+        let bytes = chunked_recording(25);
+        assert_eq!(bytes.len(), chunked_start() + 2 * (10 * RECORD_LEN + TRAILER_LEN) + 5 * RECORD_LEN + TRAILER_LEN);
+        let mut reader = CompactReader::new(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(reader.chunk_records(), Some(10));
+        let rows: Vec<u16> = reader.by_ref().map(|res| res.unwrap().row).collect();
+        assert_eq!(rows, (0..25).collect::<Vec<u16>>());
+        assert_eq!(reader.damaged_chunks(), 0);
+
+        assert_eq!(reader.record_count().unwrap(), 25);
+        assert_eq!(reader.seek_to_time(1_125).unwrap(), 13);
+        assert_eq!(reader.next().unwrap().unwrap().row, 13);
+        assert_eq!(reader.by_ref().count(), 11);
+        assert_eq!(reader.damaged_chunks(), 0);
+
+        let report = validate_recording(bytes.as_slice()).unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.chunks.len(), 3);
+        assert_eq!(report.recovered(), 25);
+        assert!(report.lost.is_empty());
+    }
+
+    #[test]
+    fn test_chunk_recovery() {
+        let mut bytes = chunked_recording(25);
+        // a plausible change to a timestamp in the second chunk is caught by its checksum
+        bytes[chunked_start() + 10 * RECORD_LEN + TRAILER_LEN + 2 * RECORD_LEN + 5] ^= 0x01;
+        let recovery = recover_recording(bytes.as_slice()).unwrap();
+        let statuses: Vec<ChunkStatus> = recovery.report.chunks.iter().map(|chunk| chunk.status).collect();
+        assert_eq!(statuses, vec![ChunkStatus::Intact, ChunkStatus::Corrupted, ChunkStatus::Intact]);
+        let rows: Vec<u16> = recovery.events.iter().map(|evt| evt.row).collect();
+        assert_eq!(rows, (0..10).chain(20..25).collect::<Vec<u16>>());
+        assert_eq!(recovery.report.lost, vec![LostRange { start: Some(1_090), end: Some(1_200), chunks: 1 }]);
+
+        let mut reader = CompactReader::new(bytes.as_slice()).unwrap();
+        reader.set_corruption_config(CorruptionConfig { policy: CorruptionPolicy::Fail, ..CorruptionConfig::default() });
+        for _ in 0..20 {
+            reader.read_event().unwrap().unwrap();
+        }
+        let err = reader.read_event().unwrap_err();
+        let offset = (chunked_start() + 2 * 10 * RECORD_LEN + TRAILER_LEN) as u64;
+        assert_eq!(DecodeError::of(&err), Some(&DecodeError::new(offset, DecodeErrorKind::Invalid("chunk checksum"))));
+
+        // an implausible record makes a resyncing reader skip to the next chunk
+        let mut bytes = chunked_recording(25);
+        bytes[chunked_start() + 3 * RECORD_LEN + 1] = 0xff;
+        let mut reader = CompactReader::new(bytes.as_slice()).unwrap();
+        reader.set_corruption_config(CorruptionConfig { policy: CorruptionPolicy::Resync, ..CorruptionConfig::default() });
+        let rows: Vec<u16> = reader.by_ref().map(|res| res.unwrap().row).collect();
+        assert_eq!(rows, (0..3).chain(10..25).collect::<Vec<u16>>());
+        assert_eq!(reader.damaged_chunks(), 1);
+        assert_eq!(reader.resyncs(), 1);
+    }
+
+    #[test]
+    fn test_recover_truncated_recording() {
+        // writing stopped partway into the third chunk, in the middle of a record
+        let bytes = chunked_recording(25);
+        let cut = chunked_start() + 2 * (10 * RECORD_LEN + TRAILER_LEN) + 3 * RECORD_LEN + 4;
+        let recovery = recover_recording(&bytes[..cut]).unwrap();
+        assert_eq!(recovery.events.len(), 23);
+        assert_eq!(recovery.report.chunks[2].status, ChunkStatus::Unterminated);
+        assert_eq!(recovery.report.lost, vec![LostRange { start: Some(1_220), end: None, chunks: 1 }]);
+        assert!(!recovery.report.is_intact());
+
+        // streaming readers return the unverified records too
+        let mut reader = CompactReader::new(&bytes[..cut - 4]).unwrap();
+        assert_eq!(reader.by_ref().count(), 23);
+        assert_eq!(reader.damaged_chunks(), 0);
+
+        // unchunked recordings can only be checked for plausibility
+        let recovery = recover_recording(corrupted_recording().as_slice()).unwrap();
+        assert_eq!(recovery.report.chunk_records, None);
+        assert_eq!(recovery.events.len(), 5);
+        assert_eq!(recovery.report.lost, vec![LostRange { start: Some(1_004), end: None, chunks: 1 }]);
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Conversion of events, corners and tracks to and from Polars `DataFrame`s,
+//! for interactive analysis (enabled by the `polars` feature).
+//!
+//! Positions become `row`/`col` columns and timestamps a `timestamp` column of
+//! microsecond `Duration`s since the start of the recording. `with_datetime` adds
+//! a wall-clock `Datetime` column given the time the recording started.
+//! When reading, `timestamp` may be a `Duration` of any unit or an integer
+//! count of microseconds.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use polars::prelude::*;
+
+use crate::sae_types::*;
+use crate::track::{Track, TrackId, TrackStore};
+
+
+fn duration_column(name: &str, timestamps: impl Iterator<Item = SaeTime>) -> Column {
+    let micros: Vec<i64> = timestamps.map(|t| t as i64).collect();
+    Int64Chunked::from_vec(name.into(), micros)
+        .into_duration(TimeUnit::Microseconds)
+        .into_series()
+        .into_column()
+}
+
+/// Events as columns `row` (u16), `col` (u16), `polarity` (u8) and `timestamp` (Duration)
+pub fn events_to_dataframe(events: &[SaeEvent]) -> PolarsResult<DataFrame> {
+    DataFrame::new(vec![
+        Column::new("row".into(), events.iter().map(|evt| evt.row).collect::<Vec<u16>>()),
+        Column::new("col".into(), events.iter().map(|evt| evt.col).collect::<Vec<u16>>()),
+        Column::new("polarity".into(), events.iter().map(|evt| evt.polarity).collect::<Vec<u8>>()),
+        duration_column("timestamp", events.iter().map(|evt| evt.timestamp)),
+    ])
+}
+
+/// Corners as the event columns, plus sub-pixel `row_f` and `col_f` (f32) and the
+/// descriptor elements `desc_0` .. `desc_35` (f32); missing values are null
+pub fn corners_to_dataframe(corners: &[SaeEvent]) -> PolarsResult<DataFrame> {
+    let mut df = events_to_dataframe(corners)?;
+    df.with_column(Column::new("row_f".into(), corners.iter().map(|c| c.row_f).collect::<Vec<Option<f32>>>()))?;
+    df.with_column(Column::new("col_f".into(), corners.iter().map(|c| c.col_f).collect::<Vec<Option<f32>>>()))?;
+    for idx in 0..NORM_DESCRIPTOR_LEN {
+        let values: Vec<Option<f32>> = corners.iter()
+            .map(|c| c.norm_descriptor.as_ref().map(|desc| desc[idx]))
+            .collect();
+        df.with_column(Column::new(format!("desc_{}", idx).into(), values))?;
+    }
+    Ok(df)
+}
+
+/// One row per track observation: `track` (u32), sub-pixel `row` and `col` (f32), and `timestamp` (Duration)
+pub fn tracks_to_dataframe(store: &TrackStore) -> PolarsResult<DataFrame> {
+    let observations: Vec<(TrackId, &SaeEvent)> = store.iter()
+        .flat_map(|track| track.observations.iter().map(move |obs| (track.id, obs)))
+        .collect();
+    let positions: Vec<(f32, f32)> = observations.iter().map(|(_, obs)| obs.subpixel_position()).collect();
+    DataFrame::new(vec![
+        Column::new("track".into(), observations.iter().map(|(id, _)| *id).collect::<Vec<u32>>()),
+        Column::new("row".into(), positions.iter().map(|p| p.0).collect::<Vec<f32>>()),
+        Column::new("col".into(), positions.iter().map(|p| p.1).collect::<Vec<f32>>()),
+        duration_column("timestamp", observations.iter().map(|(_, obs)| obs.timestamp)),
+    ])
+}
+
+/// Add a `datetime` column of microsecond `Datetime`s: the `timestamp` column
+/// offset by `start_micros`, the recording start in microseconds since the Unix epoch
+pub fn with_datetime(df: &mut DataFrame, start_micros: i64) -> PolarsResult<()> {
+    let micros = timestamps(df)?;
+    let datetimes: Vec<i64> = micros.iter().map(|&t| start_micros + t as i64).collect();
+    let column = Int64Chunked::from_vec("datetime".into(), datetimes)
+        .into_datetime(TimeUnit::Microseconds, None)
+        .into_series()
+        .into_column();
+    df.with_column(column)?;
+    Ok(())
+}
+
+/// the `timestamp` column as microseconds
+fn timestamps(df: &DataFrame) -> PolarsResult<Vec<SaeTime>> {
+    let column = df.column("timestamp")?;
+    let column = match column.dtype() {
+        DataType::Duration(_) => column.cast(&DataType::Duration(TimeUnit::Microseconds))?.cast(&DataType::Int64)?,
+        _ => column.cast(&DataType::Int64)?,
+    };
+    column.i64()?.into_iter()
+        .map(|val| {
+            val.and_then(|t| SaeTime::try_from(t).ok())
+                .ok_or_else(|| polars_err!(ComputeError: "timestamp missing or out of range"))
+        })
+        .collect()
+}
+
+/// the named column as non-null values of type `T`, after casting
+fn values<T>(df: &DataFrame, name: &str, dtype: DataType) -> PolarsResult<Vec<T::Native>>
+    where T: PolarsNumericType
+{
+    let column = df.column(name)?.cast(&dtype)?;
+    let chunked: &ChunkedArray<T> = column.as_materialized_series().unpack::<T>()?;
+    chunked.into_iter()
+        .map(|val| val.ok_or_else(|| polars_err!(ComputeError: "null in column {}", name)))
+        .collect()
+}
+
+/// the named column as nullable f32s, or all nulls if the column is absent
+fn optional_f32(df: &DataFrame, name: &str) -> PolarsResult<Vec<Option<f32>>> {
+    match df.column(name) {
+        Ok(column) => Ok(column.cast(&DataType::Float32)?.f32()?.into_iter().collect()),
+        Err(_) => Ok(vec![None; df.height()]),
+    }
+}
+
+/// Events from the columns written by `events_to_dataframe`
+pub fn events_from_dataframe(df: &DataFrame) -> PolarsResult<Vec<SaeEvent>> {
+    let rows = values::<UInt16Type>(df, "row", DataType::UInt16)?;
+    let cols = values::<UInt16Type>(df, "col", DataType::UInt16)?;
+    let polarities = values::<UInt8Type>(df, "polarity", DataType::UInt8)?;
+    let times = timestamps(df)?;
+    Ok((0..df.height())
+        .map(|idx| SaeEvent {
+            row: rows[idx],
+            col: cols[idx],
+            polarity: polarities[idx],
+            timestamp: times[idx],
+            ..SaeEvent::default()
+        })
+        .collect())
+}
+
+/// Corners from the columns written by `corners_to_dataframe`.
+/// A corner has a descriptor only if all its descriptor columns hold values.
+pub fn corners_from_dataframe(df: &DataFrame) -> PolarsResult<Vec<SaeEvent>> {
+    let mut corners = events_from_dataframe(df)?;
+    let rows_f = optional_f32(df, "row_f")?;
+    let cols_f = optional_f32(df, "col_f")?;
+    let descriptors: Vec<Vec<Option<f32>>> = (0..NORM_DESCRIPTOR_LEN)
+        .map(|idx| optional_f32(df, &format!("desc_{}", idx)))
+        .collect::<PolarsResult<_>>()?;
+    for (idx, corner) in corners.iter_mut().enumerate() {
+        corner.row_f = rows_f[idx];
+        corner.col_f = cols_f[idx];
+        let mut desc = [0.0f32; NORM_DESCRIPTOR_LEN];
+        let complete = desc.iter_mut().zip(descriptors.iter()).all(|(val, column)| match column[idx] {
+            Some(v) => {
+                *val = v;
+                true
+            }
+            None => false,
+        });
+        if complete {
+            corner.norm_descriptor = Some(Box::new(desc));
+        }
+    }
+    Ok(corners)
+}
+
+/// Tracks from the columns written by `tracks_to_dataframe`, in order of first appearance.
+/// Observations keep their sub-pixel positions.
+pub fn tracks_from_dataframe(df: &DataFrame) -> PolarsResult<Vec<Track>> {
+    let ids = values::<UInt32Type>(df, "track", DataType::UInt32)?;
+    let rows = values::<Float32Type>(df, "row", DataType::Float32)?;
+    let cols = values::<Float32Type>(df, "col", DataType::Float32)?;
+    let times = timestamps(df)?;
+    let mut tracks: Vec<Track> = Vec::new();
+    let mut index: HashMap<TrackId, usize> = HashMap::new();
+    for idx in 0..df.height() {
+        let (row_f, col_f) = (rows[idx], cols[idx]);
+        if row_f < 0.0 || col_f < 0.0 {
+            return Err(polars_err!(ComputeError: "negative position in row {}", idx));
+        }
+        let obs = SaeEvent {
+            row: row_f.round() as u16,
+            col: col_f.round() as u16,
+            timestamp: times[idx],
+            row_f: Some(row_f),
+            col_f: Some(col_f),
+            ..SaeEvent::default()
+        };
+        match index.get(&ids[idx]) {
+            Some(&pos) => tracks[pos].observations.push(obs),
+            None => {
+                index.insert(ids[idx], tracks.len());
+                tracks.push(Track::new(ids[idx], obs));
+            }
+        }
+    }
+    Ok(tracks)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_round_trip() {
+        let events: Vec<SaeEvent> = (0..5u16)
+            .map(|i| SaeEvent { row: i, col: 10 + i, polarity: (i % 2) as u8, timestamp: 4_000_000_000 + i as SaeTime, ..SaeEvent::default() })
+            .collect();
+        let mut df = events_to_dataframe(&events).unwrap();
+        assert_eq!(df.column("timestamp").unwrap().dtype(), &DataType::Duration(TimeUnit::Microseconds));
+        assert_eq!(events_from_dataframe(&df).unwrap(), events);
+
+        with_datetime(&mut df, 1_000_000).unwrap();
+        let datetime = df.column("datetime").unwrap().cast(&DataType::Int64).unwrap();
+        assert_eq!(datetime.i64().unwrap().get(1), Some(4_001_000_001));
+
+        // millisecond durations are converted
+        let millis = df.column("timestamp").unwrap().cast(&DataType::Duration(TimeUnit::Milliseconds)).unwrap();
+        df.with_column(millis).unwrap();
+        assert_eq!(events_from_dataframe(&df).unwrap()[0].timestamp, 4_000_000_000);
+    }
+
+    #[test]
+    fn test_corners_and_tracks() {
+        let mut desc = [0.25f32; NORM_DESCRIPTOR_LEN];
+        desc[0] = 1.0;
+        let corners = vec![
+            SaeEvent { row: 3, col: 4, timestamp: 10, norm_descriptor: Some(Box::new(desc)), row_f: Some(3.25), col_f: Some(4.5), ..SaeEvent::default() },
+            SaeEvent { row: 7, col: 8, timestamp: 20, ..SaeEvent::default() },
+        ];
+        let df = corners_to_dataframe(&corners).unwrap();
+        assert_eq!(df.width(), 6 + NORM_DESCRIPTOR_LEN);
+        assert_eq!(corners_from_dataframe(&df).unwrap(), corners);
+
+        let mut store = TrackStore::new();
+        let a = store.start_track(corners[0].clone());
+        store.extend_track(a, corners[1].clone());
+        store.start_track(corners[1].clone());
+        let df = tracks_to_dataframe(&store).unwrap();
+        assert_eq!(df.height(), 3);
+        let tracks = tracks_from_dataframe(&df).unwrap();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0].len(), 2);
+        assert_eq!(tracks[0].first().subpixel_position(), (3.25, 4.5));
+        assert_eq!(tracks[1].last().timestamp, 20);
+    }
+}
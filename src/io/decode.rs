@@ -0,0 +1,184 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Shared support for the crate's binary decoders: a reader that tracks its byte
+//! offset, and a typed error recording where and how the input was bad.
+//!
+//! Decoders keep returning `io::Result`; a `DecodeError` travels inside the
+//! `io::Error` (kind `UnexpectedEof` for truncated input, `InvalidData` otherwise)
+//! and can be recovered with `DecodeError::of`.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+
+
+/// What was wrong with the input
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeErrorKind {
+    /// the input does not start with the expected magic bytes
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// the input ended inside a field or record
+    Truncated,
+    /// a field holds a value the format does not allow
+    Invalid(&'static str),
+}
+
+/// Bad input, and the byte offset (from the start of the input) where it was found
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodeError {
+    pub offset: u64,
+    pub kind: DecodeErrorKind,
+}
+
+impl DecodeError {
+    pub fn new(offset: u64, kind: DecodeErrorKind) -> Self {
+        DecodeError { offset, kind }
+    }
+
+    /// The decode error carried by an I/O error returned from a decoder, if any
+    pub fn of(err: &io::Error) -> Option<&DecodeError> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<DecodeError>())
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            DecodeErrorKind::BadMagic => write!(f, "unrecognized format")?,
+            DecodeErrorKind::UnsupportedVersion(version) => write!(f, "unsupported version {}", version)?,
+            DecodeErrorKind::Truncated => write!(f, "truncated input")?,
+            DecodeErrorKind::Invalid(what) => write!(f, "invalid {}", what)?,
+        }
+        write!(f, " at byte {}", self.offset)
+    }
+}
+
+impl Error for DecodeError {}
+
+impl From<DecodeError> for io::Error {
+    fn from(err: DecodeError) -> io::Error {
+        let kind = match err.kind {
+            DecodeErrorKind::Truncated => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// Wraps a reader, counting the bytes consumed so errors can report where they occurred
+pub struct OffsetReader<R> {
+    inner: R,
+    offset: u64,
+}
+
+impl<R: Read> OffsetReader<R> {
+    pub fn new(inner: R) -> Self {
+        OffsetReader { inner, offset: 0 }
+    }
+
+    /// Continue counting from `offset`, for input whose start was consumed elsewhere, eg a text header
+    pub fn with_offset(inner: R, offset: u64) -> Self {
+        OffsetReader { inner, offset }
+    }
+
+    /// bytes consumed so far
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// An error at the current offset
+    pub fn error(&self, kind: DecodeErrorKind) -> io::Error {
+        DecodeError::new(self.offset, kind).into()
+    }
+
+    /// Fill `buf`, reporting a truncation at the offset of the incomplete field
+    pub fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        match self.inner.read_exact(buf) {
+            Ok(()) => {
+                self.offset += buf.len() as u64;
+                Ok(())
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => Err(self.error(DecodeErrorKind::Truncated)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Check the magic bytes and the format version
+    pub fn expect_header(&mut self, magic: &[u8], version: u16) -> io::Result<()> {
+        self.expect_versions(magic, &[version]).map(|_| ())
+    }
+
+    /// Check the magic bytes and that the format version is one of `versions`, returning it
+    pub fn expect_versions(&mut self, magic: &[u8], versions: &[u16]) -> io::Result<u16> {
+        let mut found = vec![0u8; magic.len()];
+        self.read_bytes(&mut found)?;
+        if found != magic {
+            return Err(DecodeError::new(0, DecodeErrorKind::BadMagic).into());
+        }
+        let found_version = self.read_u16()?;
+        if !versions.contains(&found_version) {
+            return Err(DecodeError::new(self.offset - 2, DecodeErrorKind::UnsupportedVersion(found_version)).into());
+        }
+        Ok(found_version)
+    }
+
+    pub fn read_u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0u8; 1];
+        self.read_bytes(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    pub fn read_u16(&mut self) -> io::Result<u16> {
+        let mut buf = [0u8; 2];
+        self.read_bytes(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn read_u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0u8; 4];
+        self.read_bytes(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn read_u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0u8; 8];
+        self.read_bytes(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub fn read_f32(&mut self) -> io::Result<f32> {
+        self.read_u32().map(f32::from_bits)
+    }
+
+    pub fn read_f64(&mut self) -> io::Result<f64> {
+        self.read_u64().map(f64::from_bits)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offsets_in_errors() {
+        let bytes = [b'A', b'B', 1, 0, 7, 0, 0];
+        let mut reader = OffsetReader::new(&bytes[..]);
+        reader.expect_header(b"AB", 1).unwrap();
+        assert_eq!(reader.read_u16().unwrap(), 7);
+        let err = reader.read_u32().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(DecodeError::of(&err), Some(&DecodeError::new(6, DecodeErrorKind::Truncated)));
+
+        let err = OffsetReader::new(&bytes[..]).expect_header(b"AB", 2).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(DecodeError::of(&err).unwrap().kind, DecodeErrorKind::UnsupportedVersion(1));
+        assert_eq!(err.to_string(), "unsupported version 1 at byte 2");
+    }
+}
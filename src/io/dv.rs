@@ -0,0 +1,257 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! A client for the network event output of the iniVation DV software
+//! (the `net_tcp_server` output module, or a dv-processing `NetworkWriter`).
+//!
+//! The stream is AEDAT4 framed: a size-prefixed `IOHeader` flatbuffer, then packets
+//! of a 4-byte stream id and a 4-byte size followed by a flatbuffer payload. Event
+//! packets (identifier `EVTS`) hold 16-byte events of a microsecond Unix timestamp,
+//! x, y and polarity; packets of other types (frames, IMU, triggers) are skipped.
+//! Only uncompressed streams are supported: set the DV output's compression to none.
+//!
+//! Event timestamps are reported relative to the first event received, whose Unix
+//! time is available from `DvClient::origin`.
+
+use std::io::{self, Read};
+use std::net::{TcpStream, ToSocketAddrs};
+
+use crate::io::decode::{DecodeError, DecodeErrorKind, OffsetReader};
+use crate::sae_types::*;
+use crate::source::EventSource;
+
+
+const EVENT_PACKET_ID: &[u8; 4] = b"EVTS";
+const EVENT_LEN: usize = 16;
+
+fn invalid(offset: u64, what: &'static str) -> io::Error {
+    DecodeError::new(offset, DecodeErrorKind::Invalid(what)).into()
+}
+
+/// Minimal reading of a flatbuffer held in `buf`
+struct FlatBuffer<'a> {
+    buf: &'a [u8],
+    /// stream offset of `buf`, for errors
+    offset: u64,
+}
+
+impl<'a> FlatBuffer<'a> {
+    fn u16_at(&self, pos: usize) -> io::Result<u16> {
+        self.buf.get(pos..pos + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .ok_or_else(|| invalid(self.offset + pos as u64, "flatbuffer offset"))
+    }
+
+    fn u32_at(&self, pos: usize) -> io::Result<u32> {
+        self.buf.get(pos..pos + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| invalid(self.offset + pos as u64, "flatbuffer offset"))
+    }
+
+    /// position of the root table
+    fn root(&self) -> io::Result<usize> {
+        Ok(self.u32_at(0)? as usize)
+    }
+
+    /// position of the value of field `index` of the table at `table`, if present
+    fn field(&self, table: usize, index: usize) -> io::Result<Option<usize>> {
+        let vtable = (table as i64 - self.u32_at(table)? as i32 as i64) as usize;
+        let vtable_len = self.u16_at(vtable)? as usize;
+        let slot = 4 + 2 * index;
+        if slot + 2 > vtable_len {
+            return Ok(None);
+        }
+        match self.u16_at(vtable + slot)? {
+            0 => Ok(None),
+            field_offset => Ok(Some(table + field_offset as usize)),
+        }
+    }
+
+    /// (position, length) of the vector referenced by the field at `pos`
+    fn vector(&self, pos: usize) -> io::Result<(usize, usize)> {
+        let start = pos + self.u32_at(pos)? as usize;
+        Ok((start + 4, self.u32_at(start)? as usize))
+    }
+}
+
+/// Receives events from a DV network stream
+pub struct DvClient<R> {
+    reader: OffsetReader<R>,
+    pending: Vec<SaeEvent>,
+    next: usize,
+    origin: Option<i64>,
+    packets_skipped: u64,
+}
+
+impl DvClient<TcpStream> {
+    /// Connect to a DV network output at `addr`
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+}
+
+impl<R: Read> DvClient<R> {
+    /// Read the stream header, failing if the stream is compressed
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = OffsetReader::new(reader);
+        let size = reader.read_u32()? as usize;
+        let mut header = vec![0u8; size];
+        reader.read_bytes(&mut header)?;
+        let buf = FlatBuffer { buf: &header, offset: 4 };
+        // IOHeader field 0: compression type, none by default
+        if let Some(pos) = buf.field(buf.root()?, 0)? {
+            if buf.u32_at(pos)? != 0 {
+                return Err(invalid(4 + pos as u64, "compression (only uncompressed streams are supported)"));
+            }
+        }
+        Ok(DvClient { reader, pending: Vec::new(), next: 0, origin: None, packets_skipped: 0 })
+    }
+
+    /// Unix time, in microseconds, of the first event received
+    pub fn origin(&self) -> Option<i64> {
+        self.origin
+    }
+
+    /// number of packets skipped as not holding events
+    pub fn packets_skipped(&self) -> u64 {
+        self.packets_skipped
+    }
+
+    /// Read packets until one holds events; false at the end of the stream
+    fn read_packet(&mut self) -> io::Result<bool> {
+        loop {
+            let mut stream_id = [0u8; 4];
+            match self.reader.read_bytes(&mut stream_id) {
+                Ok(()) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(err) => return Err(err),
+            }
+            let size = self.reader.read_u32()? as usize;
+            let offset = self.reader.offset();
+            let mut payload = vec![0u8; size];
+            self.reader.read_bytes(&mut payload)?;
+
+            // the payload may carry a size prefix before the flatbuffer itself
+            let body = if payload.get(4..8) == Some(&EVENT_PACKET_ID[..]) {
+                &payload[..]
+            } else if payload.get(8..12) == Some(&EVENT_PACKET_ID[..]) {
+                &payload[4..]
+            } else {
+                self.packets_skipped += 1;
+                continue;
+            };
+            let buf = FlatBuffer { buf: body, offset: offset + (payload.len() - body.len()) as u64 };
+            self.pending.clear();
+            self.next = 0;
+            // EventPacket field 0: the vector of events
+            if let Some(pos) = buf.field(buf.root()?, 0)? {
+                let (start, len) = buf.vector(pos)?;
+                let events = body.get(start..start + len * EVENT_LEN)
+                    .ok_or_else(|| invalid(buf.offset + start as u64, "event vector"))?;
+                for raw in events.chunks(EVENT_LEN) {
+                    let mut timestamp = [0u8; 8];
+                    timestamp.copy_from_slice(&raw[..8]);
+                    let timestamp = i64::from_le_bytes(timestamp);
+                    let x = i16::from_le_bytes([raw[8], raw[9]]);
+                    let y = i16::from_le_bytes([raw[10], raw[11]]);
+                    if x < 0 || y < 0 {
+                        continue;
+                    }
+                    let origin = *self.origin.get_or_insert(timestamp);
+                    self.pending.push(SaeEvent {
+                        row: y as u16,
+                        col: x as u16,
+                        polarity: (raw[12] != 0) as u8,
+                        timestamp: timestamp.saturating_sub(origin).max(0) as SaeTime,
+                        ..SaeEvent::default()
+                    });
+                }
+            }
+            if !self.pending.is_empty() {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl<R: Read> EventSource for DvClient<R> {
+    fn next_event(&mut self) -> io::Result<Option<SaeEvent>> {
+        if self.next == self.pending.len() && !self.read_packet()? {
+            return Ok(None);
+        }
+        let evt = self.pending[self.next].clone();
+        self.next += 1;
+        Ok(Some(evt))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a size-prefixed IOHeader flatbuffer with the given compression type
+    fn io_header(compression: u32) -> Vec<u8> {
+        let mut buf = Vec::new();
+        // root offset, identifier, vtable (len 6, table len 8, field 0 at 4), padding
+        buf.extend_from_slice(&16u32.to_le_bytes());
+        buf.extend_from_slice(b"IOHE");
+        buf.extend_from_slice(&[6, 0, 8, 0, 4, 0, 0, 0]);
+        // table: soffset back to the vtable, then the compression field
+        buf.extend_from_slice(&8i32.to_le_bytes());
+        buf.extend_from_slice(&compression.to_le_bytes());
+        let mut framed = (buf.len() as u32).to_le_bytes().to_vec();
+        framed.extend(buf);
+        framed
+    }
+
+    /// a framed event packet flatbuffer of (timestamp, x, y, polarity) events
+    fn event_packet(stream_id: u32, events: &[(i64, i16, i16, bool)], identifier: &[u8; 4]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&16u32.to_le_bytes());
+        buf.extend_from_slice(identifier);
+        buf.extend_from_slice(&[6, 0, 8, 0, 4, 0, 0, 0]);
+        buf.extend_from_slice(&8i32.to_le_bytes());
+        // offset from this field to the vector, which is placed so its elements are 8-byte aligned
+        buf.extend_from_slice(&8u32.to_le_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&(events.len() as u32).to_le_bytes());
+        for &(timestamp, x, y, polarity) in events.iter() {
+            buf.extend_from_slice(&timestamp.to_le_bytes());
+            buf.extend_from_slice(&x.to_le_bytes());
+            buf.extend_from_slice(&y.to_le_bytes());
+            buf.extend_from_slice(&[polarity as u8, 0, 0, 0]);
+        }
+        let mut framed = stream_id.to_le_bytes().to_vec();
+        framed.extend_from_slice(&(buf.len() as u32).to_le_bytes());
+        framed.extend(buf);
+        framed
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut stream = io_header(0);
+        stream.extend(event_packet(0, &[(1_600_000_000_000_000, 10, 20, true), (1_600_000_000_000_250, 11, 21, false)], b"EVTS"));
+        stream.extend(event_packet(1, &[(1_600_000_000_000_300, 1, 1, true)], b"FRME"));
+        stream.extend(event_packet(0, &[(1_600_000_000_001_000, 12, 22, true)], b"EVTS"));
+
+        let mut client = DvClient::new(stream.as_slice()).unwrap();
+        let events: Vec<SaeEvent> = client.events().collect::<io::Result<_>>().unwrap();
+        let fields: Vec<(u16, u16, u8, SaeTime)> = events.iter().map(|e| (e.row, e.col, e.polarity, e.timestamp)).collect();
+        assert_eq!(fields, vec![(20, 10, 1, 0), (21, 11, 0, 250), (22, 12, 1, 1_000)]);
+        assert_eq!(client.origin(), Some(1_600_000_000_000_000));
+        assert_eq!(client.packets_skipped(), 1);
+    }
+
+    #[test]
+    fn test_rejects_compressed_and_truncated() {
+        assert!(DvClient::new(io_header(3).as_slice()).is_err());
+
+        let mut stream = io_header(0);
+        let packet = event_packet(0, &[(5, 1, 2, true)], b"EVTS");
+        stream.extend_from_slice(&packet[..packet.len() - 3]);
+        let mut client = DvClient::new(stream.as_slice()).unwrap();
+        let err = client.next_event().unwrap_err();
+        assert_eq!(DecodeError::of(&err).unwrap().kind, DecodeErrorKind::Truncated);
+    }
+}
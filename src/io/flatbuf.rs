@@ -0,0 +1,266 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! FlatBuffers encoding of event batches, read in place by consumers
+//! (enabled by the `flatbuffers` feature).
+//!
+//! The layout matches the schema in `proto/event_batch.fbs`, so consumers in other
+//! languages can use `flatc`-generated readers. Events are stored column-wise, and
+//! an `EventBatchView` reads individual events straight from the received buffer,
+//! without copying or decoding the rest of the batch.
+//! `EventBatch` is written as `flatc` would generate it, so building the crate does
+//! not need `flatc`.
+
+use std::io;
+
+use flatbuffers::{FlatBufferBuilder, ForwardsUOffset, Vector, WIPOffset};
+
+use crate::sae_types::*;
+
+
+/// identifies buffers holding an `EventBatch`
+pub const FILE_IDENTIFIER: &str = "ARCE";
+
+/// The `EventBatch` table, with the accessors `flatc` generates for it
+#[derive(Copy, Clone, PartialEq)]
+pub struct EventBatch<'a> {
+    pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for EventBatch<'a> {
+    type Inner = EventBatch<'a>;
+
+    #[inline]
+    unsafe fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+        EventBatch { _tab: flatbuffers::Table::new(buf, loc) }
+    }
+}
+
+impl<'a> EventBatch<'a> {
+    pub const VT_NROWS: flatbuffers::VOffsetT = 4;
+    pub const VT_NCOLS: flatbuffers::VOffsetT = 6;
+    pub const VT_ROWS: flatbuffers::VOffsetT = 8;
+    pub const VT_COLS: flatbuffers::VOffsetT = 10;
+    pub const VT_POLARITIES: flatbuffers::VOffsetT = 12;
+    pub const VT_TIMESTAMPS: flatbuffers::VOffsetT = 14;
+
+    // Safety, for each accessor: the table was verified (by `flatbuffers::root`)
+    // to hold a value of the accessed type in each present slot.
+
+    #[inline]
+    pub fn nrows(&self) -> u16 {
+        unsafe { self._tab.get::<u16>(Self::VT_NROWS, Some(0)).unwrap() }
+    }
+
+    #[inline]
+    pub fn ncols(&self) -> u16 {
+        unsafe { self._tab.get::<u16>(Self::VT_NCOLS, Some(0)).unwrap() }
+    }
+
+    #[inline]
+    pub fn rows(&self) -> Option<Vector<'a, u16>> {
+        unsafe { self._tab.get::<ForwardsUOffset<Vector<'a, u16>>>(Self::VT_ROWS, None) }
+    }
+
+    #[inline]
+    pub fn cols(&self) -> Option<Vector<'a, u16>> {
+        unsafe { self._tab.get::<ForwardsUOffset<Vector<'a, u16>>>(Self::VT_COLS, None) }
+    }
+
+    #[inline]
+    pub fn polarities(&self) -> Option<Vector<'a, u8>> {
+        unsafe { self._tab.get::<ForwardsUOffset<Vector<'a, u8>>>(Self::VT_POLARITIES, None) }
+    }
+
+    #[inline]
+    pub fn timestamps(&self) -> Option<Vector<'a, u32>> {
+        unsafe { self._tab.get::<ForwardsUOffset<Vector<'a, u32>>>(Self::VT_TIMESTAMPS, None) }
+    }
+}
+
+impl flatbuffers::Verifiable for EventBatch<'_> {
+    #[inline]
+    fn run_verifier(v: &mut flatbuffers::Verifier, pos: usize) -> Result<(), flatbuffers::InvalidFlatbuffer> {
+        v.visit_table(pos)?
+            .visit_field::<u16>("nrows", Self::VT_NROWS, false)?
+            .visit_field::<u16>("ncols", Self::VT_NCOLS, false)?
+            .visit_field::<ForwardsUOffset<Vector<'_, u16>>>("rows", Self::VT_ROWS, false)?
+            .visit_field::<ForwardsUOffset<Vector<'_, u16>>>("cols", Self::VT_COLS, false)?
+            .visit_field::<ForwardsUOffset<Vector<'_, u8>>>("polarities", Self::VT_POLARITIES, false)?
+            .visit_field::<ForwardsUOffset<Vector<'_, u32>>>("timestamps", Self::VT_TIMESTAMPS, false)?
+            .finish();
+        Ok(())
+    }
+}
+
+/// Encodes event batches, reusing its buffer from one batch to the next
+pub struct EventBatchWriter {
+    builder: FlatBufferBuilder<'static>,
+    columns: (Vec<u16>, Vec<u16>, Vec<u8>, Vec<u32>),
+}
+
+impl EventBatchWriter {
+    pub fn new() -> Self {
+        EventBatchWriter {
+            builder: FlatBufferBuilder::new(),
+            columns: (Vec::new(), Vec::new(), Vec::new(), Vec::new()),
+        }
+    }
+
+    /// Encode `events`, from a sensor of the given geometry, returning the finished buffer
+    pub fn encode(&mut self, nrows: u16, ncols: u16, events: &[SaeEvent]) -> &[u8] {
+        let (rows, cols, polarities, timestamps) = &mut self.columns;
+        rows.clear();
+        cols.clear();
+        polarities.clear();
+        timestamps.clear();
+        for evt in events.iter() {
+            rows.push(evt.row);
+            cols.push(evt.col);
+            polarities.push(evt.polarity);
+            timestamps.push(evt.timestamp);
+        }
+
+        let fbb = &mut self.builder;
+        fbb.reset();
+        let rows = fbb.create_vector(rows);
+        let cols = fbb.create_vector(cols);
+        let polarities = fbb.create_vector(polarities);
+        let timestamps = fbb.create_vector(timestamps);
+        let start = fbb.start_table();
+        fbb.push_slot_always(EventBatch::VT_TIMESTAMPS, timestamps);
+        fbb.push_slot_always(EventBatch::VT_POLARITIES, polarities);
+        fbb.push_slot_always(EventBatch::VT_COLS, cols);
+        fbb.push_slot_always(EventBatch::VT_ROWS, rows);
+        fbb.push_slot::<u16>(EventBatch::VT_NCOLS, ncols, 0);
+        fbb.push_slot::<u16>(EventBatch::VT_NROWS, nrows, 0);
+        let table = fbb.end_table(start);
+        fbb.finish(WIPOffset::<EventBatch>::new(table.value()), Some(FILE_IDENTIFIER));
+        fbb.finished_data()
+    }
+}
+
+impl Default for EventBatchWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Encode one batch of events
+pub fn encode_event_batch(nrows: u16, ncols: u16, events: &[SaeEvent]) -> Vec<u8> {
+    EventBatchWriter::new().encode(nrows, ncols, events).to_vec()
+}
+
+/// A verified event batch, read in place from its buffer
+#[derive(Clone, Copy)]
+pub struct EventBatchView<'a> {
+    batch: EventBatch<'a>,
+    rows: Vector<'a, u16>,
+    cols: Vector<'a, u16>,
+    polarities: Vector<'a, u8>,
+    timestamps: Vector<'a, u32>,
+}
+
+impl<'a> EventBatchView<'a> {
+    /// Verify that `buf` holds a well-formed event batch. This checks offsets and
+    /// lengths only; no events are decoded.
+    pub fn new(buf: &'a [u8]) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if !flatbuffers::buffer_has_identifier(buf, FILE_IDENTIFIER, false) {
+            return Err(invalid("not an event batch".to_string()));
+        }
+        let batch = flatbuffers::root::<EventBatch>(buf).map_err(|err| invalid(err.to_string()))?;
+        // absent columns are read as empty
+        let view = EventBatchView {
+            batch,
+            rows: batch.rows().unwrap_or_default(),
+            cols: batch.cols().unwrap_or_default(),
+            polarities: batch.polarities().unwrap_or_default(),
+            timestamps: batch.timestamps().unwrap_or_default(),
+        };
+        let len = view.rows.len();
+        if view.cols.len() != len || view.polarities.len() != len || view.timestamps.len() != len {
+            return Err(invalid("event batch columns differ in length".to_string()));
+        }
+        Ok(view)
+    }
+
+    pub fn nrows(&self) -> u16 {
+        self.batch.nrows()
+    }
+
+    pub fn ncols(&self) -> u16 {
+        self.batch.ncols()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The event at `idx`, which must be less than `len()`
+    pub fn get(&self, idx: usize) -> SaeEvent {
+        SaeEvent {
+            row: self.rows.get(idx),
+            col: self.cols.get(idx),
+            polarity: self.polarities.get(idx),
+            timestamp: self.timestamps.get(idx),
+            ..SaeEvent::default()
+        }
+    }
+
+    /// timestamps of all events, in place, eg to select a time range before decoding events
+    pub fn timestamps(&self) -> Vector<'a, u32> {
+        self.timestamps
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = SaeEvent> + '_ {
+        (0..self.len()).map(move |idx| self.get(idx))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_round_trip() {
+        let events: Vec<SaeEvent> = (0..100u16)
+            .map(|i| SaeEvent { row: i, col: 200 + i, polarity: (i % 2) as u8, timestamp: 4_000_000_000 + i as SaeTime, ..SaeEvent::default() })
+            .collect();
+        let mut writer = EventBatchWriter::new();
+        let buf = writer.encode(240, 320, &events).to_vec();
+        let view = EventBatchView::new(&buf).unwrap();
+        assert_eq!((view.nrows(), view.ncols()), (240, 320));
+        assert_eq!(view.len(), events.len());
+        assert_eq!(view.get(42), events[42]);
+        assert_eq!(view.timestamps().get(99), 4_000_000_099);
+        assert_eq!(view.iter().collect::<Vec<SaeEvent>>(), events);
+
+        // the writer is reusable
+        let buf = writer.encode(240, 320, &events[..3]);
+        assert_eq!(EventBatchView::new(buf).unwrap().len(), 3);
+        assert!(EventBatchView::new(&encode_event_batch(1, 1, &[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rejects_malformed() {
+        let buf = encode_event_batch(240, 320, &[SaeEvent::new()]);
+        assert!(EventBatchView::new(&buf[..buf.len() - 4]).is_err());
+        assert!(EventBatchView::new(b"not a flatbuffer").is_err());
+
+        // columns of different lengths
+        let mut fbb = FlatBufferBuilder::new();
+        let rows = fbb.create_vector(&[1u16, 2]);
+        let start = fbb.start_table();
+        fbb.push_slot_always(EventBatch::VT_ROWS, rows);
+        let table = fbb.end_table(start);
+        fbb.finish(WIPOffset::<EventBatch>::new(table.value()), Some(FILE_IDENTIFIER));
+        let err = EventBatchView::new(fbb.finished_data()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,501 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Live camera input over the network: the DV software's TCP output (see `io::dv`)
+//! and the raw EVT datagrams a Prophesee camera streams over UDP, with reconnection.
+//!
+//! A network source ends or fails whenever its peer restarts. `ReconnectingSource`
+//! reconnects with backoff through a connect function, and normalizes timestamps
+//! across connections: each connection reports time relative to its own first event,
+//! so the events of a new connection are shifted to follow the last event before it
+//! by the wall-clock time spent reconnecting. Timestamps stay monotonic for the
+//! surfaces and trackers downstream, and gaps keep about their real length.
+
+use std::io::{self, Read};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "aedat")]
+use crate::io::aedat::{AedatReader, RawFormat};
+use crate::io::dv::DvClient;
+use crate::sae_types::*;
+use crate::source::EventSource;
+
+
+/// largest UDP payload
+const MAX_DATAGRAM_LEN: usize = 65_536;
+
+/// Reads the payloads of the datagrams received on a socket as one byte stream
+pub struct DatagramReader {
+    socket: UdpSocket,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    datagrams: u64,
+}
+
+impl DatagramReader {
+    /// Receive on a socket bound to `addr`
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(Self::new(UdpSocket::bind(addr)?))
+    }
+
+    pub fn new(socket: UdpSocket) -> Self {
+        DatagramReader { socket, buf: vec![0; MAX_DATAGRAM_LEN], pos: 0, len: 0, datagrams: 0 }
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// number of datagrams received
+    pub fn datagrams(&self) -> u64 {
+        self.datagrams
+    }
+}
+
+impl Read for DatagramReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.len {
+            self.len = self.socket.recv(&mut self.buf)?;
+            self.pos = 0;
+            self.datagrams += 1;
+        }
+        let count = out.len().min(self.len - self.pos);
+        out[..count].copy_from_slice(&self.buf[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+/// Receive the raw EVT 2.0 or 3.0 stream of a Prophesee camera, sent as UDP
+/// datagrams of whole words to `addr`
+#[cfg(feature = "aedat")]
+pub fn prophesee_udp<A: ToSocketAddrs>(addr: A, format: RawFormat) -> io::Result<AedatReader<DatagramReader>> {
+    if format != RawFormat::Evt2 && format != RawFormat::Evt3 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Prophesee streams are EVT 2.0 or 3.0"));
+    }
+    Ok(AedatReader::with_format(DatagramReader::bind(addr)?, format))
+}
+
+#[cfg(windows)]
+mod winsock {
+    use std::os::raw::c_void;
+
+    pub type Handle = *mut c_void;
+    pub type Socket = usize;
+
+    pub const WSA_IO_PENDING: i32 = 997;
+
+    #[repr(C)]
+    pub struct Overlapped {
+        pub internal: usize,
+        pub internal_high: usize,
+        pub offset: u32,
+        pub offset_high: u32,
+        pub event: Handle,
+    }
+
+    #[repr(C)]
+    pub struct WsaBuf {
+        pub len: u32,
+        pub buf: *mut u8,
+    }
+
+    #[link(name = "ws2_32")]
+    extern "system" {
+        pub fn WSARecv(socket: Socket, buffers: *mut WsaBuf, buffer_count: u32, received: *mut u32, flags: *mut u32,
+                       overlapped: *mut Overlapped, completion: *mut c_void) -> i32;
+        pub fn WSAGetOverlappedResult(socket: Socket, overlapped: *mut Overlapped, transferred: *mut u32, wait: i32,
+                                      flags: *mut u32) -> i32;
+        pub fn WSAGetLastError() -> i32;
+        pub fn WSACreateEvent() -> Handle;
+        pub fn WSAResetEvent(event: Handle) -> i32;
+        pub fn WSACloseEvent(event: Handle) -> i32;
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub fn CancelIoEx(file: Handle, overlapped: *mut Overlapped) -> i32;
+    }
+}
+
+/// A receive posted to the socket, with the buffer the kernel fills
+#[cfg(windows)]
+struct PostedRecv {
+    overlapped: winsock::Overlapped,
+    buf: Box<[u8]>,
+    flags: u32,
+    pending: bool,
+}
+
+/// Reads the payloads of the datagrams received on a socket as one byte stream, like
+/// `DatagramReader`, but with several overlapped receives posted at all times, so
+/// datagrams arriving in bursts land in buffers rather than in the socket's queue
+#[cfg(windows)]
+pub struct OverlappedDatagramReader {
+    socket: UdpSocket,
+    // boxed so the buffers and OVERLAPPED structures stay put while receives are pending
+    posted: Box<[PostedRecv]>,
+    /// receive to complete next
+    next: usize,
+    /// receive whose datagram is being read
+    current: Option<usize>,
+    pos: usize,
+    len: usize,
+    datagrams: u64,
+}
+
+#[cfg(windows)]
+impl OverlappedDatagramReader {
+    /// Receive on a socket bound to `addr`, with `depth` receives posted
+    pub fn bind<A: ToSocketAddrs>(addr: A, depth: usize) -> io::Result<Self> {
+        Self::new(UdpSocket::bind(addr)?, depth)
+    }
+
+    /// `socket` is used for overlapped IO, which the standard library opens its sockets for
+    pub fn new(socket: UdpSocket, depth: usize) -> io::Result<Self> {
+        let mut posted = Vec::with_capacity(depth.max(1));
+        for _ in 0..depth.max(1) {
+            let event = unsafe { winsock::WSACreateEvent() };
+            if event.is_null() {
+                return Err(Self::last_error());
+            }
+            posted.push(PostedRecv {
+                overlapped: winsock::Overlapped { internal: 0, internal_high: 0, offset: 0, offset_high: 0, event },
+                buf: vec![0; MAX_DATAGRAM_LEN].into_boxed_slice(),
+                flags: 0,
+                pending: false,
+            });
+        }
+        let mut reader = OverlappedDatagramReader {
+            socket, posted: posted.into_boxed_slice(), next: 0, current: None, pos: 0, len: 0, datagrams: 0,
+        };
+        for index in 0..reader.posted.len() {
+            reader.post(index)?;
+        }
+        Ok(reader)
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// number of datagrams received
+    pub fn datagrams(&self) -> u64 {
+        self.datagrams
+    }
+
+    fn raw_socket(&self) -> winsock::Socket {
+        use std::os::windows::io::AsRawSocket;
+        self.socket.as_raw_socket() as winsock::Socket
+    }
+
+    fn last_error() -> io::Error {
+        io::Error::from_raw_os_error(unsafe { winsock::WSAGetLastError() })
+    }
+
+    fn post(&mut self, index: usize) -> io::Result<()> {
+        let socket = self.raw_socket();
+        let recv = &mut self.posted[index];
+        let event = recv.overlapped.event;
+        recv.overlapped = winsock::Overlapped { internal: 0, internal_high: 0, offset: 0, offset_high: 0, event };
+        recv.flags = 0;
+        let mut buf = winsock::WsaBuf { len: recv.buf.len() as u32, buf: recv.buf.as_mut_ptr() };
+        let ret = unsafe {
+            winsock::WSAResetEvent(event);
+            winsock::WSARecv(socket, &mut buf, 1, std::ptr::null_mut(), &mut recv.flags, &mut recv.overlapped,
+                             std::ptr::null_mut())
+        };
+        if ret != 0 {
+            let err = unsafe { winsock::WSAGetLastError() };
+            if err != winsock::WSA_IO_PENDING {
+                return Err(io::Error::from_raw_os_error(err));
+            }
+        }
+        recv.pending = true;
+        Ok(())
+    }
+
+    /// Wait for the receive `index` to complete, returning the length of its datagram
+    fn complete(&mut self, index: usize) -> io::Result<usize> {
+        if !self.posted[index].pending {
+            self.post(index)?;
+        }
+        let socket = self.raw_socket();
+        let recv = &mut self.posted[index];
+        let mut transferred = 0u32;
+        let mut flags = 0u32;
+        let ok = unsafe { winsock::WSAGetOverlappedResult(socket, &mut recv.overlapped, &mut transferred, 1, &mut flags) };
+        recv.pending = false;
+        if ok == 0 {
+            return Err(Self::last_error());
+        }
+        Ok(transferred as usize)
+    }
+}
+
+#[cfg(windows)]
+impl Read for OverlappedDatagramReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.len {
+            // the datagram just read is done with: hand its buffer back to the socket
+            if let Some(index) = self.current.take() {
+                self.post(index)?;
+            }
+            let index = self.next;
+            self.next = (index + 1) % self.posted.len();
+            self.len = self.complete(index)?;
+            self.pos = 0;
+            self.current = Some(index);
+            self.datagrams += 1;
+        }
+        let buf = &self.posted[self.current.unwrap_or(0)].buf;
+        let count = out.len().min(self.len - self.pos);
+        out[..count].copy_from_slice(&buf[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+#[cfg(windows)]
+impl Drop for OverlappedDatagramReader {
+    fn drop(&mut self) {
+        let socket = self.raw_socket();
+        unsafe { winsock::CancelIoEx(socket as winsock::Handle, std::ptr::null_mut()) };
+        for recv in self.posted.iter_mut() {
+            let mut transferred = 0u32;
+            let mut flags = 0u32;
+            unsafe {
+                // the kernel may write into the buffer until the cancelled receive completes
+                if recv.pending {
+                    winsock::WSAGetOverlappedResult(socket, &mut recv.overlapped, &mut transferred, 1, &mut flags);
+                }
+                winsock::WSACloseEvent(recv.overlapped.event);
+            }
+        }
+    }
+}
+
+// the buffers and events are owned by the reader, and only touched through it
+#[cfg(windows)]
+unsafe impl Send for OverlappedDatagramReader {}
+
+/// Receive a Prophesee camera's raw EVT stream over UDP as `prophesee_udp` does, with
+/// `depth` overlapped receives posted to keep up with bursts of datagrams
+#[cfg(all(windows, feature = "aedat"))]
+pub fn prophesee_udp_overlapped<A: ToSocketAddrs>(addr: A, format: RawFormat, depth: usize)
+    -> io::Result<AedatReader<OverlappedDatagramReader>>
+{
+    if format != RawFormat::Evt2 && format != RawFormat::Evt3 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Prophesee streams are EVT 2.0 or 3.0"));
+    }
+    Ok(AedatReader::with_format(OverlappedDatagramReader::bind(addr, depth)?, format))
+}
+
+/// When and how often to reconnect
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconnectConfig {
+    /// consecutive failed connection attempts before giving up; None to retry forever
+    pub max_attempts: Option<u32>,
+    /// wait before the first retry, doubled after each failed attempt
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// whether a source that ends is reconnected, as well as one that fails
+    pub reconnect_at_end: bool,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            max_attempts: Some(10),
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            reconnect_at_end: true,
+        }
+    }
+}
+
+/// A source that reconnects through `connect` whenever it fails or ends
+pub struct ReconnectingSource<S, F> {
+    connect: F,
+    config: ReconnectConfig,
+    source: Option<S>,
+    /// added to the timestamps of the current connection
+    offset: SaeTime,
+    /// the last event time delivered, and when
+    last: Option<(SaeTime, Instant)>,
+    /// whether the next event is the first of a new connection
+    rebase: bool,
+    connections: u64,
+    /// the error that ended the last connection
+    last_error: Option<io::Error>,
+}
+
+impl<S: EventSource, F: FnMut() -> io::Result<S>> ReconnectingSource<S, F> {
+    /// Connects on the first pull of an event
+    pub fn new(connect: F, config: ReconnectConfig) -> Self {
+        ReconnectingSource {
+            connect,
+            config,
+            source: None,
+            offset: 0,
+            last: None,
+            rebase: false,
+            connections: 0,
+            last_error: None,
+        }
+    }
+
+    pub fn config(&self) -> &ReconnectConfig {
+        &self.config
+    }
+
+    /// number of successful connections, including the first
+    pub fn connections(&self) -> u64 {
+        self.connections
+    }
+
+    /// the error that ended the last connection, if one failed
+    pub fn last_error(&self) -> Option<&io::Error> {
+        self.last_error.as_ref()
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        let mut backoff = self.config.backoff;
+        let mut attempts = 0;
+        loop {
+            match (self.connect)() {
+                Ok(source) => {
+                    self.source = Some(source);
+                    self.connections += 1;
+                    self.rebase = self.last.is_some();
+                    return Ok(());
+                }
+                Err(err) => {
+                    attempts += 1;
+                    if self.config.max_attempts.is_some_and(|max| attempts >= max) {
+                        return Err(err);
+                    }
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(self.config.max_backoff);
+                }
+            }
+        }
+    }
+}
+
+impl<S: EventSource, F: FnMut() -> io::Result<S>> EventSource for ReconnectingSource<S, F> {
+    fn next_event(&mut self) -> io::Result<Option<SaeEvent>> {
+        loop {
+            if self.source.is_none() {
+                if self.connections > 0 && self.last_error.is_none() && !self.config.reconnect_at_end {
+                    return Ok(None);
+                }
+                self.reconnect()?;
+            }
+            let source = self.source.as_mut().unwrap();
+            match source.next_event() {
+                Ok(Some(mut evt)) => {
+                    if self.rebase {
+                        // follow the last event by the time spent reconnecting
+                        let (last, at) = self.last.unwrap();
+                        let elapsed = at.elapsed().as_micros().clamp(1, SaeTime::MAX as u128) as SaeTime;
+                        self.offset = last.saturating_add(elapsed).saturating_sub(evt.timestamp);
+                        self.rebase = false;
+                    }
+                    evt.timestamp = evt.timestamp.saturating_add(self.offset);
+                    self.last = Some((evt.timestamp, Instant::now()));
+                    return Ok(Some(evt));
+                }
+                Ok(None) => {
+                    self.source = None;
+                    self.last_error = None;
+                    if !self.config.reconnect_at_end {
+                        return Ok(None);
+                    }
+                }
+                Err(err) => {
+                    self.source = None;
+                    self.last_error = Some(err);
+                }
+            }
+        }
+    }
+}
+
+/// A DV network client at `addr` that reconnects whenever the DV software restarts
+pub fn dv_reconnecting<A: ToSocketAddrs + Clone>(addr: A, config: ReconnectConfig)
+    -> ReconnectingSource<DvClient<TcpStream>, impl FnMut() -> io::Result<DvClient<TcpStream>>>
+{
+    ReconnectingSource::new(move || DvClient::connect(addr.clone()), config)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::IterSource;
+
+    #[test]
+    fn test_reconnect_keeps_time_monotonic() {
+        let at = |timestamp: SaeTime| SaeEvent { timestamp, ..SaeEvent::default() };
+        let mut connections = vec![
+            Ok(IterSource::new(vec![at(0), at(5)])),
+            Err(io::Error::new(io::ErrorKind::ConnectionRefused, "restarting")),
+            Ok(IterSource::new(vec![at(0), at(10)])),
+        ].into_iter();
+        let config = ReconnectConfig { max_attempts: Some(2), backoff: Duration::from_millis(1), ..ReconnectConfig::default() };
+        let mut source = ReconnectingSource::new(
+            move || connections.next().unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::ConnectionRefused, "gone"))),
+            config);
+        let mut timestamps = Vec::new();
+        while let Ok(Some(evt)) = source.next_event() {
+            timestamps.push(evt.timestamp);
+        }
+        assert_eq!(timestamps.len(), 4);
+        assert_eq!(&timestamps[..2], &[0, 5]);
+        // shifted past the first connection by at least the backoff
+        assert!(timestamps[2] >= 5 + 1_000);
+        assert_eq!(timestamps[3], timestamps[2] + 10);
+        assert_eq!(source.connections(), 2);
+    }
+
+    #[cfg(feature = "aedat")]
+    #[test]
+    fn test_prophesee_udp_stream() {
+        assert!(prophesee_udp("127.0.0.1:0", RawFormat::Aedat3).is_err());
+        let receiver = DatagramReader::bind("127.0.0.1:0").unwrap();
+        let addr = receiver.socket().local_addr().unwrap();
+        let mut reader = AedatReader::with_format(receiver, RawFormat::Evt2);
+
+        // EVT_TIME_HIGH, then CD_ON events at (row 3, col 4) and (row 5, col 6), split over two datagrams
+        let words = [0x8000_0002u32, 0x1000_0000 | (1 << 22) | (4 << 11) | 3, 0x1000_0000 | (9 << 22) | (6 << 11) | 5];
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(&bytes[..8], addr).unwrap();
+        sender.send_to(&bytes[8..], addr).unwrap();
+
+        let first = reader.next_event().unwrap().unwrap();
+        let second = reader.next_event().unwrap().unwrap();
+        assert_eq!((first.row, first.col, first.polarity, first.timestamp), (3, 4, 1, 0));
+        assert_eq!((second.row, second.col, second.timestamp), (5, 6, 8));
+        assert_eq!(reader.origin(), Some((2 << 6) | 1));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_overlapped_datagram_reader() {
+        let mut receiver = OverlappedDatagramReader::bind("127.0.0.1:0", 4).unwrap();
+        let addr = receiver.socket().local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for datagram in [&b"arc"[..], b"star", b"!"].iter() {
+            sender.send_to(datagram, addr).unwrap();
+        }
+        let mut bytes = [0u8; 8];
+        receiver.read_exact(&mut bytes).unwrap();
+        assert_eq!(&bytes, b"arcstar!");
+        assert_eq!(receiver.datagrams(), 3);
+    }
+}
@@ -0,0 +1,33 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Reading and writing event streams.
+
+#[cfg(feature = "aedat")]
+pub mod aedat;
+#[cfg(feature = "io")]
+pub mod compact;
+#[cfg(feature = "polars")]
+pub mod dataframe;
+pub mod decode;
+#[cfg(feature = "net")]
+pub mod dv;
+#[cfg(feature = "flatbuffers")]
+pub mod flatbuf;
+#[cfg(feature = "net")]
+pub mod live;
+#[cfg(feature = "io")]
+pub mod npy;
+#[cfg(feature = "prost")]
+pub mod proto;
+#[cfg(feature = "io")]
+pub mod ros2;
+pub mod snapshot_codec;
+#[cfg(feature = "io")]
+pub mod shm;
+#[cfg(feature = "io")]
+pub mod tee;
+pub mod track_export;
+pub mod track_graph;
+#[cfg(feature = "io")]
+pub mod transcode;
@@ -0,0 +1,451 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Events and corners as numpy `.npy` structured arrays, and `.npz` archives of them,
+//! for exchange with Python analysis without going through CSV.
+//!
+//! Events use the packed dtype
+//! `[('row', '<u2'), ('col', '<u2'), ('polarity', '|u1'), ('timestamp', '<u4')]`,
+//! so timestamps keep their full integer precision. Corners add
+//! `('row_f', '<f4'), ('col_f', '<f4'), ('descriptor', '<f4', (36,))`, with NaN
+//! where a corner has no sub-pixel position or descriptor.
+//! Archives are written uncompressed, as by `numpy.savez`; compressed archives
+//! (`numpy.savez_compressed`) are not supported.
+
+use std::io::{self, Read, Write};
+
+use crate::io::compact::{decode_event, encode_event, RECORD_LEN};
+use crate::io::decode::{DecodeError, DecodeErrorKind};
+use crate::sae_types::*;
+
+
+const NPY_MAGIC: &[u8; 6] = b"\x93NUMPY";
+pub(crate) const EVENT_DESCR: &str = "[('row', '<u2'), ('col', '<u2'), ('polarity', '|u1'), ('timestamp', '<u4')]";
+const CORNER_DESCR: &str = "[('row', '<u2'), ('col', '<u2'), ('polarity', '|u1'), ('timestamp', '<u4'), \
+    ('row_f', '<f4'), ('col_f', '<f4'), ('descriptor', '<f4', (36,))]";
+const CORNER_LEN: usize = RECORD_LEN + 4 * (2 + NORM_DESCRIPTOR_LEN);
+
+fn invalid(offset: usize, what: &'static str) -> io::Error {
+    DecodeError::new(offset as u64, DecodeErrorKind::Invalid(what)).into()
+}
+
+fn truncated(offset: usize) -> io::Error {
+    DecodeError::new(offset as u64, DecodeErrorKind::Truncated).into()
+}
+
+/// Write a version 1.0 header for a C-order array of the given shape
+pub(crate) fn write_header<W: Write>(writer: &mut W, descr: &str, shape: &[usize]) -> io::Result<()> {
+    let dims: Vec<String> = shape.iter().map(|dim| dim.to_string()).collect();
+    // a one-element tuple needs its trailing comma
+    let shape = if dims.len() == 1 { format!("{},", dims[0]) } else { dims.join(", ") };
+    let mut dict = format!("{{'descr': {}, 'fortran_order': False, 'shape': ({}), }}", descr, shape);
+    // the header, with its terminating newline, pads the data start to a multiple of 64
+    let unpadded = NPY_MAGIC.len() + 4 + dict.len() + 1;
+    dict.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    dict.push('\n');
+    writer.write_all(NPY_MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(dict.len() as u16).to_le_bytes())?;
+    writer.write_all(dict.as_bytes())
+}
+
+/// Parse the header of an `.npy` file held in `bytes`, checking it describes a
+/// one-dimensional array with dtype `descr`. Returns the record count and the data offset.
+fn read_header(bytes: &[u8], descr: &str) -> io::Result<(usize, usize)> {
+    if bytes.len() < 10 {
+        return Err(truncated(bytes.len()));
+    }
+    if &bytes[..6] != NPY_MAGIC {
+        return Err(DecodeError::new(0, DecodeErrorKind::BadMagic).into());
+    }
+    let (header_len, header_start) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        2 | 3 => {
+            if bytes.len() < 12 {
+                return Err(truncated(bytes.len()));
+            }
+            (u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize, 12)
+        }
+        major => return Err(DecodeError::new(6, DecodeErrorKind::UnsupportedVersion(major as u16)).into()),
+    };
+    let data_start = header_start + header_len;
+    let header = bytes.get(header_start..data_start).ok_or_else(|| truncated(bytes.len()))?;
+    let header = std::str::from_utf8(header).map_err(|_| invalid(header_start, "npy header"))?;
+    let compact: String = header.chars().filter(|c| !c.is_whitespace()).collect();
+    let expected: String = descr.chars().filter(|c| !c.is_whitespace()).collect();
+
+    let descr_start = compact.find("'descr':").ok_or_else(|| invalid(header_start, "npy header"))? + 8;
+    let found = compact[descr_start..].split(",'fortran_order'").next().unwrap_or("");
+    let found = found.split(",'shape'").next().unwrap_or("");
+    if found.replace("'u1'", "'|u1'") != expected {
+        return Err(invalid(header_start, "npy dtype"));
+    }
+    if compact.contains("'fortran_order':True") {
+        return Err(invalid(header_start, "npy array order"));
+    }
+    let shape_start = compact.find("'shape':(").ok_or_else(|| invalid(header_start, "npy header"))? + 9;
+    let shape = compact[shape_start..].split(')').next().unwrap_or("");
+    let dims: Vec<&str> = shape.split(',').filter(|dim| !dim.is_empty()).collect();
+    if dims.len() != 1 {
+        return Err(invalid(header_start, "npy shape"));
+    }
+    let count = dims[0].parse::<usize>().map_err(|_| invalid(header_start, "npy shape"))?;
+    Ok((count, data_start))
+}
+
+/// the records of an `.npy` file, after its header
+fn records<'a>(bytes: &'a [u8], descr: &str, record_len: usize) -> io::Result<impl Iterator<Item = &'a [u8]>> {
+    let (count, data_start) = read_header(bytes, descr)?;
+    let data_end = count.checked_mul(record_len)
+        .and_then(|len| len.checked_add(data_start))
+        .ok_or_else(|| invalid(data_start, "npy shape"))?;
+    if bytes.len() < data_end {
+        return Err(truncated(bytes.len()));
+    }
+    Ok(bytes[data_start..data_end].chunks(record_len))
+}
+
+fn encode_corner(corner: &SaeEvent, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&encode_event(corner));
+    buf.extend_from_slice(&corner.row_f.unwrap_or(f32::NAN).to_le_bytes());
+    buf.extend_from_slice(&corner.col_f.unwrap_or(f32::NAN).to_le_bytes());
+    match corner.norm_descriptor {
+        Some(ref desc) => desc.iter().for_each(|val| buf.extend_from_slice(&val.to_le_bytes())),
+        None => (0..NORM_DESCRIPTOR_LEN).for_each(|_| buf.extend_from_slice(&f32::NAN.to_le_bytes())),
+    }
+}
+
+fn decode_corner(record: &[u8]) -> SaeEvent {
+    let mut event = [0u8; RECORD_LEN];
+    event.copy_from_slice(&record[..RECORD_LEN]);
+    let float_at = |idx: usize| {
+        let start = RECORD_LEN + 4 * idx;
+        f32::from_le_bytes([record[start], record[start + 1], record[start + 2], record[start + 3]])
+    };
+    let present = |val: f32| if val.is_nan() { None } else { Some(val) };
+    let mut desc = [0.0f32; NORM_DESCRIPTOR_LEN];
+    for (idx, val) in desc.iter_mut().enumerate() {
+        *val = float_at(2 + idx);
+    }
+    SaeEvent {
+        row_f: present(float_at(0)),
+        col_f: present(float_at(1)),
+        norm_descriptor: if desc.iter().any(|val| val.is_nan()) { None } else { Some(Box::new(desc)) },
+        ..decode_event(&event)
+    }
+}
+
+/// Write events as an `.npy` structured array
+pub fn write_events_npy<W: Write>(events: &[SaeEvent], mut writer: W) -> io::Result<()> {
+    write_header(&mut writer, EVENT_DESCR, &[events.len()])?;
+    for evt in events.iter() {
+        writer.write_all(&encode_event(evt))?;
+    }
+    Ok(())
+}
+
+/// Write corners, with sub-pixel positions and descriptors, as an `.npy` structured array
+pub fn write_corners_npy<W: Write>(corners: &[SaeEvent], mut writer: W) -> io::Result<()> {
+    write_header(&mut writer, CORNER_DESCR, &[corners.len()])?;
+    let mut buf = Vec::with_capacity(CORNER_LEN);
+    for corner in corners.iter() {
+        buf.clear();
+        encode_corner(corner, &mut buf);
+        writer.write_all(&buf)?;
+    }
+    Ok(())
+}
+
+/// Read events from an `.npy` array written by `write_events_npy` or by numpy with the same dtype
+pub fn read_events_npy<R: Read>(mut reader: R) -> io::Result<Vec<SaeEvent>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    parse_events(&bytes)
+}
+
+/// Read corners from an `.npy` array written by `write_corners_npy` or by numpy with the same dtype
+pub fn read_corners_npy<R: Read>(mut reader: R) -> io::Result<Vec<SaeEvent>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    parse_corners(&bytes)
+}
+
+fn parse_events(bytes: &[u8]) -> io::Result<Vec<SaeEvent>> {
+    Ok(records(bytes, EVENT_DESCR, RECORD_LEN)?
+        .map(|record| {
+            let mut buf = [0u8; RECORD_LEN];
+            buf.copy_from_slice(record);
+            decode_event(&buf)
+        })
+        .collect())
+}
+
+fn parse_corners(bytes: &[u8]) -> io::Result<Vec<SaeEvent>> {
+    Ok(records(bytes, CORNER_DESCR, CORNER_LEN)?.map(decode_corner).collect())
+}
+
+/// CRC-32 (IEEE), as used by zip archives
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    crc32_update(0, bytes)
+}
+
+/// Extend the CRC-32 `crc` of some bytes to cover `bytes` following them,
+/// so that `crc32_update(crc32(a), b) == crc32(a ++ b)`
+pub(crate) fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+const ZIP_LOCAL_SIG: u32 = 0x0403_4b50;
+const ZIP_CENTRAL_SIG: u32 = 0x0201_4b50;
+const ZIP_END_SIG: u32 = 0x0605_4b50;
+
+/// Writes an uncompressed `.npz` archive of event and corner arrays
+pub struct NpzWriter<W: Write> {
+    writer: W,
+    /// (name, crc, size, offset) of each stored file
+    entries: Vec<(String, u32, u32, u32)>,
+    offset: u32,
+}
+
+impl<W: Write> NpzWriter<W> {
+    pub fn new(writer: W) -> Self {
+        NpzWriter { writer, entries: Vec::new(), offset: 0 }
+    }
+
+    fn add_file(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let file_name = format!("{}.npy", name);
+        let crc = crc32(data);
+        let size = data.len() as u32;
+        let mut header = Vec::with_capacity(30 + file_name.len());
+        header.extend_from_slice(&ZIP_LOCAL_SIG.to_le_bytes());
+        // version needed, flags, method (stored), mod time, mod date
+        for field in [20u16, 0, 0, 0, 0x21].iter() {
+            header.extend_from_slice(&field.to_le_bytes());
+        }
+        header.extend_from_slice(&crc.to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&(file_name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(file_name.as_bytes());
+        self.writer.write_all(&header)?;
+        self.writer.write_all(data)?;
+        self.entries.push((file_name, crc, size, self.offset));
+        self.offset += header.len() as u32 + size;
+        Ok(())
+    }
+
+    /// Store events as the array `name`
+    pub fn add_events(&mut self, name: &str, events: &[SaeEvent]) -> io::Result<()> {
+        let mut data = Vec::new();
+        write_events_npy(events, &mut data)?;
+        self.add_file(name, &data)
+    }
+
+    /// Store corners as the array `name`
+    pub fn add_corners(&mut self, name: &str, corners: &[SaeEvent]) -> io::Result<()> {
+        let mut data = Vec::new();
+        write_corners_npy(corners, &mut data)?;
+        self.add_file(name, &data)
+    }
+
+    /// Store a plain array of the given shape: `descr` is its numpy type string,
+    /// eg `'<u4'`, and `data` its elements in C order, as little-endian bytes
+    pub fn add_array(&mut self, name: &str, descr: &str, shape: &[usize], data: &[u8]) -> io::Result<()> {
+        let mut file = Vec::with_capacity(128 + data.len());
+        write_header(&mut file, descr, shape)?;
+        file.extend_from_slice(data);
+        self.add_file(name, &file)
+    }
+
+    /// Write the archive directory, returning the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let directory_start = self.offset;
+        let mut directory = Vec::new();
+        for (name, crc, size, offset) in self.entries.iter() {
+            directory.extend_from_slice(&ZIP_CENTRAL_SIG.to_le_bytes());
+            // version made by, version needed, flags, method, mod time, mod date
+            for field in [20u16, 20, 0, 0, 0, 0x21].iter() {
+                directory.extend_from_slice(&field.to_le_bytes());
+            }
+            directory.extend_from_slice(&crc.to_le_bytes());
+            directory.extend_from_slice(&size.to_le_bytes());
+            directory.extend_from_slice(&size.to_le_bytes());
+            directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            // extra length, comment length, disk number, internal attributes
+            directory.extend_from_slice(&[0u8; 8]);
+            // external attributes
+            directory.extend_from_slice(&0u32.to_le_bytes());
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+        let count = self.entries.len() as u16;
+        directory.extend_from_slice(&ZIP_END_SIG.to_le_bytes());
+        directory.extend_from_slice(&[0u8; 4]);
+        directory.extend_from_slice(&count.to_le_bytes());
+        directory.extend_from_slice(&count.to_le_bytes());
+        let directory_len = directory.len() as u32 - 12;
+        directory.extend_from_slice(&directory_len.to_le_bytes());
+        directory.extend_from_slice(&directory_start.to_le_bytes());
+        directory.extend_from_slice(&0u16.to_le_bytes());
+        self.writer.write_all(&directory)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// The arrays of an uncompressed `.npz` archive
+pub struct NpzArchive {
+    /// (array name, `.npy` bytes)
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl NpzArchive {
+    /// Read a whole archive. Entries are located from their local headers,
+    /// so an archive missing its directory can still be read.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let u16_at = |pos: usize| bytes.get(pos..pos + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize);
+        let u32_at = |pos: usize| bytes.get(pos..pos + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+        let mut files = Vec::new();
+        let mut pos = 0;
+        while u32_at(pos) == Some(ZIP_LOCAL_SIG) {
+            let method = u16_at(pos + 8).ok_or_else(|| truncated(bytes.len()))?;
+            let flags = u16_at(pos + 6).ok_or_else(|| truncated(bytes.len()))?;
+            if method != 0 {
+                return Err(invalid(pos + 8, "npz compression (only stored entries are supported)"));
+            }
+            if flags & 0x08 != 0 {
+                return Err(invalid(pos + 6, "npz entry flags"));
+            }
+            let crc = u32_at(pos + 14).ok_or_else(|| truncated(bytes.len()))?;
+            let size = u32_at(pos + 18).ok_or_else(|| truncated(bytes.len()))? as usize;
+            let name_len = u16_at(pos + 26).ok_or_else(|| truncated(bytes.len()))?;
+            let extra_len = u16_at(pos + 28).ok_or_else(|| truncated(bytes.len()))?;
+            let name_start = pos + 30;
+            let data_start = name_start + name_len + extra_len;
+            let name = bytes.get(name_start..name_start + name_len).ok_or_else(|| truncated(bytes.len()))?;
+            let name = String::from_utf8_lossy(name);
+            let data = bytes.get(data_start..data_start + size).ok_or_else(|| truncated(bytes.len()))?;
+            if crc32(data) != crc {
+                return Err(invalid(data_start, "npz entry checksum"));
+            }
+            let array = name.strip_suffix(".npy").unwrap_or(&name).to_string();
+            files.push((array, data.to_vec()));
+            pos = data_start + size;
+        }
+        if files.is_empty() && !bytes.is_empty() {
+            return Err(DecodeError::new(0, DecodeErrorKind::BadMagic).into());
+        }
+        Ok(NpzArchive { files })
+    }
+
+    /// names of the arrays in the archive
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// The whole `.npy` file of the array `name`, eg for arrays stored with `add_array`
+    pub fn npy_bytes(&self, name: &str) -> io::Result<&[u8]> {
+        self.files.iter()
+            .find(|(array, _)| array == name)
+            .map(|(_, data)| data.as_slice())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no array {} in archive", name)))
+    }
+
+    /// The array `name` as events
+    pub fn events(&self, name: &str) -> io::Result<Vec<SaeEvent>> {
+        parse_events(self.npy_bytes(name)?)
+    }
+
+    /// The array `name` as corners
+    pub fn corners(&self, name: &str) -> io::Result<Vec<SaeEvent>> {
+        parse_corners(self.npy_bytes(name)?)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_events() -> Vec<SaeEvent> {
+        (0..4u16)
+            .map(|i| SaeEvent { row: i, col: 300 + i, polarity: (i % 2) as u8, timestamp: 4_000_000_000 + i as SaeTime, ..SaeEvent::default() })
+            .collect()
+    }
+
+    fn sample_corners() -> Vec<SaeEvent> {
+        let mut desc = [0.5f32; NORM_DESCRIPTOR_LEN];
+        desc[0] = 1.0;
+        vec![
+            SaeEvent { row: 5, col: 6, timestamp: 77, row_f: Some(5.25), col_f: Some(6.5), norm_descriptor: Some(Box::new(desc)), ..SaeEvent::default() },
+            SaeEvent { row: 9, col: 1, polarity: 1, timestamp: 78, ..SaeEvent::default() },
+        ]
+    }
+
+    #[test]
+    fn test_npy_round_trip() {
+        let events = sample_events();
+        let mut bytes = Vec::new();
+        write_events_npy(&events, &mut bytes).unwrap();
+        // the data starts 64-byte aligned, right after the header
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        assert_eq!((bytes.len() - events.len() * RECORD_LEN) % 64, 0);
+        assert_eq!(read_events_npy(bytes.as_slice()).unwrap(), events);
+
+        let corners = sample_corners();
+        let mut bytes = Vec::new();
+        write_corners_npy(&corners, &mut bytes).unwrap();
+        assert_eq!(read_corners_npy(bytes.as_slice()).unwrap(), corners);
+        // corner arrays are not event arrays
+        assert!(read_events_npy(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_npy_from_numpy() {
+        // as written by numpy.save for an array of two events
+        let header = "{'descr': [('row', '<u2'), ('col', '<u2'), ('polarity', 'u1'), ('timestamp', '<u4')], \
+            'fortran_order': False, 'shape': (2,), }";
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u16 + 1).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.push(b'\n');
+        for evt in sample_events().iter().take(2) {
+            bytes.extend_from_slice(&encode_event(evt));
+        }
+        assert_eq!(read_events_npy(bytes.as_slice()).unwrap(), sample_events()[..2].to_vec());
+
+        bytes.pop();
+        let err = read_events_npy(bytes.as_slice()).unwrap_err();
+        assert_eq!(DecodeError::of(&err).unwrap().kind, DecodeErrorKind::Truncated);
+    }
+
+    #[test]
+    fn test_npz_round_trip() {
+        let mut writer = NpzWriter::new(Vec::new());
+        writer.add_events("events", &sample_events()).unwrap();
+        writer.add_corners("corners", &sample_corners()).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let archive = NpzArchive::read_from(bytes.as_slice()).unwrap();
+        assert_eq!(archive.names().collect::<Vec<&str>>(), vec!["events", "corners"]);
+        assert_eq!(archive.events("events").unwrap(), sample_events());
+        assert_eq!(archive.corners("corners").unwrap(), sample_corners());
+        assert_eq!(archive.events("other").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let mut corrupt = bytes.clone();
+        corrupt[100] ^= 0xff;
+        assert!(NpzArchive::read_from(corrupt.as_slice()).is_err());
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
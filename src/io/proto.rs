@@ -0,0 +1,280 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Protobuf encoding of events, corners, tracks and pipeline statistics
+//! (enabled by the `prost` feature).
+//!
+//! The messages match the schema in `proto/arcstar.proto`, which non-Rust
+//! subscribers can compile with their own protobuf toolchain. They are declared
+//! here by hand rather than generated, so building the crate does not need `protoc`.
+
+use std::convert::TryFrom;
+use std::io;
+
+use prost::Message;
+
+use crate::drops::DropReason;
+use crate::pipeline::Pipeline;
+use crate::sae_types::*;
+use crate::sink::CornerSink;
+use crate::track::TrackStore;
+
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Event {
+    #[prost(uint32, tag = "1")]
+    pub row: u32,
+    #[prost(uint32, tag = "2")]
+    pub col: u32,
+    #[prost(uint32, tag = "3")]
+    pub polarity: u32,
+    #[prost(uint32, tag = "4")]
+    pub timestamp: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct EventBatch {
+    #[prost(message, repeated, tag = "1")]
+    pub events: Vec<Event>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Corner {
+    #[prost(message, optional, tag = "1")]
+    pub event: Option<Event>,
+    #[prost(float, optional, tag = "2")]
+    pub row_f: Option<f32>,
+    #[prost(float, optional, tag = "3")]
+    pub col_f: Option<f32>,
+    /// the normalized descriptor, or empty
+    #[prost(float, repeated, tag = "4")]
+    pub descriptor: Vec<f32>,
+    #[prost(float, optional, tag = "5")]
+    pub confidence: Option<f32>,
+    #[prost(float, optional, tag = "6")]
+    pub orientation: Option<f32>,
+    #[prost(bool, optional, tag = "7")]
+    pub inside: Option<bool>,
+    /// the binary descriptor, 16 bytes little-endian, if computed
+    #[prost(bytes = "vec", optional, tag = "8")]
+    pub binary_descriptor: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CornerBatch {
+    #[prost(message, repeated, tag = "1")]
+    pub corners: Vec<Corner>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Track {
+    #[prost(uint32, tag = "1")]
+    pub id: u32,
+    #[prost(message, repeated, tag = "2")]
+    pub observations: Vec<Corner>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TrackBatch {
+    #[prost(message, repeated, tag = "1")]
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct PipelineStats {
+    #[prost(uint64, tag = "1")]
+    pub events_processed: u64,
+    #[prost(uint64, tag = "2")]
+    pub corners_emitted: u64,
+    #[prost(uint64, tag = "3")]
+    pub dropped_out_of_bounds: u64,
+    #[prost(uint64, tag = "4")]
+    pub dropped_filtered: u64,
+    #[prost(uint64, tag = "5")]
+    pub dropped_stale: u64,
+    #[prost(uint64, tag = "6")]
+    pub dropped_corrupted: u64,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl From<&SaeEvent> for Event {
+    fn from(evt: &SaeEvent) -> Self {
+        Event {
+            row: evt.row as u32,
+            col: evt.col as u32,
+            polarity: evt.polarity as u32,
+            timestamp: evt.timestamp,
+        }
+    }
+}
+
+impl TryFrom<&Event> for SaeEvent {
+    type Error = io::Error;
+
+    fn try_from(msg: &Event) -> io::Result<Self> {
+        let narrow = |val: u32| u16::try_from(val).map_err(|_| invalid_data("event position out of range"));
+        Ok(SaeEvent {
+            row: narrow(msg.row)?,
+            col: narrow(msg.col)?,
+            polarity: u8::try_from(msg.polarity).map_err(|_| invalid_data("event polarity out of range"))?,
+            timestamp: msg.timestamp,
+            ..SaeEvent::default()
+        })
+    }
+}
+
+impl From<&SaeEvent> for Corner {
+    fn from(corner: &SaeEvent) -> Self {
+        Corner {
+            event: Some(Event::from(corner)),
+            row_f: corner.row_f,
+            col_f: corner.col_f,
+            descriptor: corner.norm_descriptor.as_ref().map_or_else(Vec::new, |desc| desc.to_vec()),
+            confidence: corner.confidence,
+            orientation: corner.orientation,
+            inside: corner.corner_kind.map(|kind| kind == CornerKind::Inside),
+            binary_descriptor: corner.binary_descriptor.map(|desc| desc.0.to_le_bytes().to_vec()),
+        }
+    }
+}
+
+impl TryFrom<&Corner> for SaeEvent {
+    type Error = io::Error;
+
+    fn try_from(msg: &Corner) -> io::Result<Self> {
+        let event = msg.event.as_ref().ok_or_else(|| invalid_data("corner without event"))?;
+        let norm_descriptor = match msg.descriptor.len() {
+            0 => None,
+            NORM_DESCRIPTOR_LEN => {
+                let mut desc = [0.0f32; NORM_DESCRIPTOR_LEN];
+                desc.copy_from_slice(&msg.descriptor);
+                Some(Box::new(desc))
+            }
+            _ => return Err(invalid_data("corner descriptor length")),
+        };
+        let binary_descriptor = match &msg.binary_descriptor {
+            None => None,
+            Some(bytes) => {
+                let bytes = <[u8; 16]>::try_from(&bytes[..]).map_err(|_| invalid_data("corner binary descriptor length"))?;
+                Some(BinaryDescriptor(u128::from_le_bytes(bytes)))
+            }
+        };
+        Ok(SaeEvent {
+            row_f: msg.row_f,
+            col_f: msg.col_f,
+            norm_descriptor,
+            confidence: msg.confidence,
+            orientation: msg.orientation,
+            corner_kind: msg.inside.map(|inside| if inside { CornerKind::Inside } else { CornerKind::Outside }),
+            binary_descriptor,
+            ..SaeEvent::try_from(event)?
+        })
+    }
+}
+
+impl PipelineStats {
+    /// The current counts of `pipeline`
+    pub fn of<S: CornerSink>(pipeline: &Pipeline<S>) -> Self {
+        let drops = pipeline.drops();
+        PipelineStats {
+            events_processed: pipeline.events_processed(),
+            corners_emitted: pipeline.corners_emitted(),
+            dropped_out_of_bounds: drops.count(DropReason::OutOfBounds),
+            dropped_filtered: drops.count(DropReason::Filtered),
+            dropped_stale: drops.count(DropReason::Stale),
+            dropped_corrupted: drops.count(DropReason::Corrupted),
+        }
+    }
+}
+
+fn decode<M: Message + Default>(bytes: &[u8]) -> io::Result<M> {
+    M::decode(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Encode events as an `EventBatch`
+pub fn encode_events(events: &[SaeEvent]) -> Vec<u8> {
+    EventBatch { events: events.iter().map(Event::from).collect() }.encode_to_vec()
+}
+
+pub fn decode_events(bytes: &[u8]) -> io::Result<Vec<SaeEvent>> {
+    decode::<EventBatch>(bytes)?.events.iter().map(SaeEvent::try_from).collect()
+}
+
+/// Encode corners, with sub-pixel positions and descriptors, as a `CornerBatch`
+pub fn encode_corners(corners: &[SaeEvent]) -> Vec<u8> {
+    CornerBatch { corners: corners.iter().map(Corner::from).collect() }.encode_to_vec()
+}
+
+pub fn decode_corners(bytes: &[u8]) -> io::Result<Vec<SaeEvent>> {
+    decode::<CornerBatch>(bytes)?.corners.iter().map(SaeEvent::try_from).collect()
+}
+
+/// Encode all tracks of `store`, in id order, as a `TrackBatch`
+pub fn encode_tracks(store: &TrackStore) -> Vec<u8> {
+    let tracks = store.iter()
+        .map(|track| Track {
+            id: track.id,
+            observations: track.observations.iter().map(Corner::from).collect(),
+        })
+        .collect();
+    TrackBatch { tracks }.encode_to_vec()
+}
+
+pub fn decode_tracks(bytes: &[u8]) -> io::Result<Vec<crate::track::Track>> {
+    decode::<TrackBatch>(bytes)?.tracks.iter()
+        .map(|msg| {
+            let observations = msg.observations.iter()
+                .map(SaeEvent::try_from)
+                .collect::<io::Result<Vec<SaeEvent>>>()?;
+            if observations.is_empty() {
+                return Err(invalid_data("track without observations"));
+            }
+            Ok(crate::track::Track { id: msg.id, observations })
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips() {
+        let mut desc = [0.25f32; NORM_DESCRIPTOR_LEN];
+        desc[0] = 1.0;
+        let corners = vec![
+            SaeEvent { row: 3, col: 400, polarity: 1, timestamp: 4_000_000_000, row_f: Some(3.25), col_f: Some(400.5), norm_descriptor: Some(Box::new(desc)), confidence: Some(0.5), orientation: Some(-1.5), corner_kind: Some(CornerKind::Inside), scale: None, binary_descriptor: Some(BinaryDescriptor(u128::MAX - 5)) },
+            SaeEvent { row: 7, col: 8, timestamp: 20, ..SaeEvent::default() },
+        ];
+        assert_eq!(decode_events(&encode_events(&corners)).unwrap()[0].timestamp, 4_000_000_000);
+        assert_eq!(decode_corners(&encode_corners(&corners)).unwrap(), corners);
+        let decoded = decode_corners(&encode_corners(&corners)).unwrap();
+        assert_eq!((decoded[0].confidence, decoded[0].orientation, decoded[1].orientation), (Some(0.5), Some(-1.5), None));
+        assert_eq!((decoded[0].corner_kind, decoded[1].corner_kind), (Some(CornerKind::Inside), None));
+        assert_eq!((decoded[0].binary_descriptor, decoded[1].binary_descriptor), (Some(BinaryDescriptor(u128::MAX - 5)), None));
+
+        let mut store = TrackStore::new();
+        let id = store.start_track(corners[1].clone());
+        store.extend_track(id, corners[0].clone());
+        let tracks = decode_tracks(&encode_tracks(&store)).unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].observations, store.get(id).unwrap().observations);
+    }
+
+    #[test]
+    fn test_rejects_out_of_range() {
+        let batch = EventBatch { events: vec![Event { row: 70_000, ..Event::default() }] };
+        let err = decode_events(&batch.encode_to_vec()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let corner = Corner { event: Some(Event::default()), descriptor: vec![1.0; 3], ..Corner::default() };
+        assert!(decode_corners(&CornerBatch { corners: vec![corner] }.encode_to_vec()).is_err());
+        let corner = Corner { event: Some(Event::default()), binary_descriptor: Some(vec![0; 8]), ..Corner::default() };
+        assert!(decode_corners(&CornerBatch { corners: vec![corner] }.encode_to_vec()).is_err());
+        assert!(decode_corners(&[0xff, 0xff]).is_err());
+    }
+}
@@ -0,0 +1,416 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! The ROS2 `event_camera_msgs/EventPacket` message and its `mono` and `evt3`
+//! event encodings, implemented without a ROS runtime, so that messages read from
+//! bags recorded with the ROS2 event camera drivers can be ingested directly.
+//!
+//! `EventPacket::from_cdr` and `to_cdr` convert whole messages to and from the
+//! CDR serialization stored in bags. The event payload is decoded with a
+//! `PacketDecoder`, which carries timing state from one packet to the next:
+//!
+//! - `mono`: 8 bytes per event, a little-endian u64 holding the time offset in
+//!   nanoseconds from the packet's `time_base` (bits 0..32), x (bits 32..46),
+//!   y (bits 46..60) and polarity (bit 63). Timestamps are reported in
+//!   microseconds since the `time_base` of the first packet decoded.
+//! - `evt3`: the Prophesee EVT 3.0 stream of 16-bit words, whose 24-bit
+//!   microsecond sensor time is unwrapped across rollovers.
+
+use std::io;
+
+use crate::io::decode::{DecodeError, DecodeErrorKind};
+use crate::sae_types::*;
+
+
+pub const MONO: &str = "mono";
+pub const EVT3: &str = "evt3";
+
+/// An `event_camera_msgs/EventPacket` message
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventPacket {
+    /// header stamp, seconds and nanoseconds
+    pub stamp_sec: i32,
+    pub stamp_nanosec: u32,
+    pub frame_id: String,
+    pub height: u32,
+    pub width: u32,
+    pub seq: u64,
+    /// time reference of the events, nanoseconds
+    pub time_base: u64,
+    pub encoding: String,
+    pub is_bigendian: bool,
+    /// encoded events
+    pub events: Vec<u8>,
+}
+
+/// CDR little-endian encapsulation
+const CDR_LE: [u8; 4] = [0x00, 0x01, 0x00, 0x00];
+
+struct CdrWriter {
+    buf: Vec<u8>,
+}
+
+impl CdrWriter {
+    fn align(&mut self, size: usize) {
+        // alignment is relative to the end of the encapsulation header
+        while !(self.buf.len() - CDR_LE.len()).is_multiple_of(size) {
+            self.buf.push(0);
+        }
+    }
+
+    fn u32(&mut self, val: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    fn u64(&mut self, val: u64) {
+        self.align(8);
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    fn string(&mut self, val: &str) {
+        self.u32(val.len() as u32 + 1);
+        self.buf.extend_from_slice(val.as_bytes());
+        self.buf.push(0);
+    }
+}
+
+struct CdrReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> CdrReader<'a> {
+    fn error(&self, kind: DecodeErrorKind) -> io::Error {
+        DecodeError::new(self.pos as u64, kind).into()
+    }
+
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.buf.get(self.pos..self.pos + len).ok_or_else(|| self.error(DecodeErrorKind::Truncated))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn align(&mut self, size: usize) {
+        while !(self.pos - CDR_LE.len()).is_multiple_of(size) {
+            self.pos += 1;
+        }
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.align(4);
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.align(8);
+        let b = self.bytes(8)?;
+        let mut word = [0u8; 8];
+        word.copy_from_slice(b);
+        Ok(u64::from_le_bytes(word))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        let start = self.pos;
+        let bytes = self.bytes(len)?;
+        match bytes.split_last() {
+            Some((0, text)) => String::from_utf8(text.to_vec())
+                .map_err(|_| DecodeError::new(start as u64, DecodeErrorKind::Invalid("string")).into()),
+            _ => Err(DecodeError::new(start as u64, DecodeErrorKind::Invalid("string")).into()),
+        }
+    }
+}
+
+impl EventPacket {
+    /// Serialize as CDR, as stored in bags
+    pub fn to_cdr(&self) -> Vec<u8> {
+        let mut writer = CdrWriter { buf: CDR_LE.to_vec() };
+        writer.u32(self.stamp_sec as u32);
+        writer.u32(self.stamp_nanosec);
+        writer.string(&self.frame_id);
+        writer.u32(self.height);
+        writer.u32(self.width);
+        writer.u64(self.seq);
+        writer.u64(self.time_base);
+        writer.string(&self.encoding);
+        writer.buf.push(self.is_bigendian as u8);
+        writer.u32(self.events.len() as u32);
+        writer.buf.extend_from_slice(&self.events);
+        writer.buf
+    }
+
+    /// Deserialize a CDR-serialized message
+    pub fn from_cdr(bytes: &[u8]) -> io::Result<Self> {
+        match bytes.get(..4) {
+            Some(header) if header == CDR_LE => {}
+            Some(_) => return Err(DecodeError::new(0, DecodeErrorKind::BadMagic).into()),
+            None => return Err(DecodeError::new(bytes.len() as u64, DecodeErrorKind::Truncated).into()),
+        }
+        let mut reader = CdrReader { buf: bytes, pos: CDR_LE.len() };
+        Ok(EventPacket {
+            stamp_sec: reader.u32()? as i32,
+            stamp_nanosec: reader.u32()?,
+            frame_id: reader.string()?,
+            height: reader.u32()?,
+            width: reader.u32()?,
+            seq: reader.u64()?,
+            time_base: reader.u64()?,
+            encoding: reader.string()?,
+            is_bigendian: reader.u8()? != 0,
+            events: {
+                let len = reader.u32()? as usize;
+                reader.bytes(len)?.to_vec()
+            },
+        })
+    }
+}
+
+/// Encode events in the `mono` encoding: event timestamps (microseconds) are
+/// offsets from `time_base` (nanoseconds), and must not precede it
+pub fn encode_mono(events: &[SaeEvent], time_base: u64, width: u32, height: u32, seq: u64) -> EventPacket {
+    let mut payload = Vec::with_capacity(events.len() * 8);
+    for evt in events.iter() {
+        let offset = (evt.timestamp as u64 * 1000).saturating_sub(time_base) & 0xFFFF_FFFF;
+        let word = offset
+            | ((evt.col as u64 & 0x3FFF) << 32)
+            | ((evt.row as u64 & 0x3FFF) << 46)
+            | ((evt.polarity.min(1) as u64) << 63);
+        payload.extend_from_slice(&word.to_le_bytes());
+    }
+    EventPacket {
+        height,
+        width,
+        seq,
+        time_base,
+        encoding: MONO.to_string(),
+        events: payload,
+        ..EventPacket::default()
+    }
+}
+
+const EVT3_ADDR_Y: u16 = 0x0;
+const EVT3_ADDR_X: u16 = 0x2;
+const EVT3_VECT_BASE_X: u16 = 0x3;
+const EVT3_VECT_12: u16 = 0x4;
+const EVT3_VECT_8: u16 = 0x5;
+const EVT3_TIME_LOW: u16 = 0x6;
+const EVT3_TIME_HIGH: u16 = 0x8;
+
+/// Encode events in the `evt3` encoding, one address word per event.
+/// Timestamps are taken as microseconds of sensor time, modulo 2^24.
+pub fn encode_evt3(events: &[SaeEvent], width: u32, height: u32, seq: u64) -> EventPacket {
+    let mut words: Vec<u16> = Vec::with_capacity(events.len() * 2);
+    let (mut time_high, mut time_low, mut row) = (None, None, None);
+    for evt in events.iter() {
+        let high = ((evt.timestamp >> 12) & 0xFFF) as u16;
+        let low = (evt.timestamp & 0xFFF) as u16;
+        if time_high != Some(high) {
+            words.push(EVT3_TIME_HIGH << 12 | high);
+            time_high = Some(high);
+            time_low = None;
+        }
+        if time_low != Some(low) {
+            words.push(EVT3_TIME_LOW << 12 | low);
+            time_low = Some(low);
+        }
+        if row != Some(evt.row) {
+            words.push(EVT3_ADDR_Y << 12 | (evt.row & 0x7FF));
+            row = Some(evt.row);
+        }
+        words.push(EVT3_ADDR_X << 12 | ((evt.polarity.min(1) as u16) << 11) | (evt.col & 0x7FF));
+    }
+    EventPacket {
+        height,
+        width,
+        seq,
+        encoding: EVT3.to_string(),
+        events: words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect(),
+        ..EventPacket::default()
+    }
+}
+
+/// Decodes the events of successive packets of one stream
+#[derive(Clone, Debug, Default)]
+pub struct PacketDecoder {
+    /// `time_base` of the first `mono` packet
+    mono_origin: Option<u64>,
+    /// evt3 time state: current high and low time bits, and the rollovers seen
+    evt3_high: Option<u32>,
+    evt3_low: u32,
+    evt3_epoch: u32,
+    evt3_row: u16,
+    evt3_base_x: u16,
+    evt3_polarity: u8,
+}
+
+impl PacketDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode the events of a packet, in the order they are stored.
+    /// Events outside the packet's `width` and `height` are skipped.
+    pub fn decode(&mut self, packet: &EventPacket) -> io::Result<Vec<SaeEvent>> {
+        if packet.is_bigendian {
+            return Err(DecodeError::new(0, DecodeErrorKind::Invalid("big-endian event packet")).into());
+        }
+        let mut events = match packet.encoding.as_str() {
+            MONO => self.decode_mono(packet)?,
+            EVT3 => self.decode_evt3(packet)?,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                           format!("unsupported encoding {}", packet.encoding))),
+        };
+        events.retain(|evt| (evt.row as u32) < packet.height && (evt.col as u32) < packet.width);
+        Ok(events)
+    }
+
+    fn decode_mono(&mut self, packet: &EventPacket) -> io::Result<Vec<SaeEvent>> {
+        if !packet.events.len().is_multiple_of(8) {
+            return Err(DecodeError::new(packet.events.len() as u64 / 8 * 8, DecodeErrorKind::Truncated).into());
+        }
+        let origin = *self.mono_origin.get_or_insert(packet.time_base);
+        let base = packet.time_base.saturating_sub(origin);
+        Ok(packet.events.chunks(8)
+            .map(|chunk| {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(chunk);
+                let word = u64::from_le_bytes(bytes);
+                SaeEvent {
+                    row: ((word >> 46) & 0x3FFF) as u16,
+                    col: ((word >> 32) & 0x3FFF) as u16,
+                    polarity: (word >> 63) as u8,
+                    timestamp: ((base + (word & 0xFFFF_FFFF)) / 1000) as SaeTime,
+                    ..SaeEvent::default()
+                }
+            })
+            .collect())
+    }
+
+    fn decode_evt3(&mut self, packet: &EventPacket) -> io::Result<Vec<SaeEvent>> {
+        if !packet.events.len().is_multiple_of(2) {
+            return Err(DecodeError::new(packet.events.len() as u64 - 1, DecodeErrorKind::Truncated).into());
+        }
+        let mut events = Vec::new();
+        for chunk in packet.events.chunks(2) {
+            let word = u16::from_le_bytes([chunk[0], chunk[1]]);
+            let payload = word & 0xFFF;
+            match word >> 12 {
+                EVT3_ADDR_Y => self.evt3_row = payload & 0x7FF,
+                EVT3_ADDR_X => {
+                    if let Some(evt) = self.evt3_event(payload & 0x7FF, (payload >> 11) as u8) {
+                        events.push(evt);
+                    }
+                }
+                EVT3_VECT_BASE_X => {
+                    self.evt3_base_x = payload & 0x7FF;
+                    self.evt3_polarity = (payload >> 11) as u8;
+                }
+                EVT3_VECT_12 | EVT3_VECT_8 => {
+                    let width = if word >> 12 == EVT3_VECT_12 { 12 } else { 8 };
+                    for bit in 0..width {
+                        if payload & (1 << bit) != 0 {
+                            if let Some(evt) = self.evt3_event(self.evt3_base_x + bit, self.evt3_polarity) {
+                                events.push(evt);
+                            }
+                        }
+                    }
+                    self.evt3_base_x += width;
+                }
+                EVT3_TIME_LOW => self.evt3_low = payload as u32,
+                EVT3_TIME_HIGH => {
+                    let high = payload as u32;
+                    // the high bits only decrease when the 24-bit time rolls over
+                    if self.evt3_high.is_some_and(|prev| high < prev) {
+                        self.evt3_epoch = self.evt3_epoch.wrapping_add(1 << 24);
+                    }
+                    self.evt3_high = Some(high);
+                }
+                // triggers and vendor-specific words carry no events
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+
+    fn evt3_event(&self, col: u16, polarity: u8) -> Option<SaeEvent> {
+        // events before the first time high word have no usable timestamp
+        let high = self.evt3_high?;
+        Some(SaeEvent {
+            row: self.evt3_row,
+            col,
+            polarity,
+            timestamp: self.evt3_epoch.wrapping_add(high << 12 | self.evt3_low),
+            ..SaeEvent::default()
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_events() -> Vec<SaeEvent> {
+        [(5u16, 7u16, 1u8, 100u32), (5, 9, 0, 100), (6, 1, 1, 4_196), (479, 639, 0, 9_000)].iter()
+            .map(|&(row, col, polarity, timestamp)| SaeEvent { row, col, polarity, timestamp, ..SaeEvent::default() })
+            .collect()
+    }
+
+    #[test]
+    fn test_cdr_round_trip() {
+        let mut packet = encode_mono(&sample_events(), 50_000, 640, 480, 3);
+        packet.stamp_sec = 1_700_000_000;
+        packet.frame_id = "camera".to_string();
+        let bytes = packet.to_cdr();
+        assert_eq!(EventPacket::from_cdr(&bytes).unwrap(), packet);
+        let err = EventPacket::from_cdr(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(DecodeError::of(&err).unwrap().kind, DecodeErrorKind::Truncated);
+    }
+
+    #[test]
+    fn test_mono() {
+        let events = sample_events();
+        let mut decoder = PacketDecoder::new();
+        let first = encode_mono(&events, 50_000, 640, 480, 0);
+        assert_eq!(decoder.decode(&first).unwrap(), events.iter().map(|evt| SaeEvent { timestamp: evt.timestamp - 50, ..evt.clone() }).collect::<Vec<_>>());
+        // later packets are timed relative to the first
+        let second = encode_mono(&events[3..], 8_000_000, 640, 480, 1);
+        assert_eq!(decoder.decode(&second).unwrap()[0].timestamp, 9_000 - 50);
+        // events outside the sensor are skipped
+        let small = encode_mono(&events, 50_000, 10, 10, 2);
+        assert_eq!(decoder.decode(&small).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_evt3() {
+        let events = sample_events();
+        let packet = encode_evt3(&events, 640, 480, 0);
+        let mut decoder = PacketDecoder::new();
+        assert_eq!(decoder.decode(&packet).unwrap(), events);
+
+        // vectorized events, after a rollover of the 24-bit sensor time
+        let words: Vec<u16> = vec![
+            EVT3_TIME_HIGH << 12 | 0xFFF, EVT3_TIME_HIGH << 12 | 0x001, EVT3_TIME_LOW << 12 | 0x002,
+            EVT3_ADDR_Y << 12 | 12, EVT3_VECT_BASE_X << 12 | 1 << 11 | 100,
+            EVT3_VECT_12 << 12 | 0b1000_0000_0001, EVT3_VECT_8 << 12 | 0b10,
+        ];
+        let packet = EventPacket {
+            width: 640,
+            height: 480,
+            encoding: EVT3.to_string(),
+            events: words.iter().flat_map(|w| w.to_le_bytes().to_vec()).collect(),
+            ..EventPacket::default()
+        };
+        let decoded = decoder.decode(&packet).unwrap();
+        let cols: Vec<u16> = decoded.iter().map(|evt| evt.col).collect();
+        assert_eq!(cols, vec![100, 111, 113]);
+        assert!(decoded.iter().all(|evt| evt.row == 12 && evt.polarity == 1));
+        assert_eq!(decoded[0].timestamp, (1 << 24) + (1 << 12) + 2);
+    }
+}
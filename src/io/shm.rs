@@ -0,0 +1,542 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Events passed between processes through a ring buffer in shared memory.
+//!
+//! A camera bridge process (eg one running the Prophesee SDK) writes events into the
+//! ring with `ShmWriter`, and the detector process reads them with `ShmSource`, an
+//! `EventSource`, with no copying through sockets or pipes. On Windows the ring lives
+//! in a `NamedSharedMemory` mapping; `HeapRegion` holds one within a process.
+//!
+//! The ring layout, all little-endian, is a 64-byte header followed by the records:
+//!
+//! | offset | size | field |
+//! |--------|------|-------|
+//! | 0      | 8    | magic `ARCSHM\0\0` |
+//! | 8      | 4    | version, 1 |
+//! | 12     | 4    | record length, 9 |
+//! | 16     | 8    | capacity, in records |
+//! | 24     | 8    | number of records ever written |
+//! | 32     | 4    | closed flag, set once the writer is done |
+//!
+//! Each record is an event in the `io::compact` record format, record `n` stored in
+//! slot `n % capacity`. The writer stores a record before publishing the new count, so
+//! once a reader is `capacity` records behind, the writer may be overwriting the oldest
+//! one: the reader skips ahead past it and the records already overwritten, and counts
+//! the overrun. A count that goes backwards means the writer re-created the ring, and
+//! the reader starts over on the new one.
+
+use std::io;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::io::compact::{decode_event, encode_event, RECORD_LEN};
+use crate::sae_types::*;
+use crate::source::EventSource;
+
+
+pub const SHM_MAGIC: [u8; 8] = *b"ARCSHM\0\0";
+pub const SHM_VERSION: u32 = 1;
+/// length of the ring header, ahead of the records
+pub const SHM_HEADER_LEN: usize = 64;
+
+const CAPACITY_OFFSET: usize = 16;
+const WRITTEN_OFFSET: usize = 24;
+const CLOSED_OFFSET: usize = 32;
+
+/// Bytes of shared memory needed by a ring of `capacity` records
+pub fn ring_len(capacity: usize) -> usize {
+    SHM_HEADER_LEN + capacity * RECORD_LEN
+}
+
+/// Memory shared with another process or thread, mapped for the lifetime of the value
+///
+/// # Safety
+///
+/// `as_ptr` must return the same pointer for the lifetime of the value, aligned to 8
+/// bytes and valid for reads and writes of `size()` bytes, which may change at any time
+/// through other processes or threads.
+pub unsafe trait SharedRegion {
+    /// start of the region, aligned to 8 bytes
+    fn as_ptr(&self) -> *mut u8;
+
+    /// length of the region in bytes
+    fn size(&self) -> usize;
+}
+
+unsafe impl<R: SharedRegion> SharedRegion for Arc<R> {
+    fn as_ptr(&self) -> *mut u8 {
+        (**self).as_ptr()
+    }
+
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+}
+
+/// A region on the heap, for a ring shared between threads of one process
+pub struct HeapRegion {
+    words: Box<[AtomicU64]>,
+}
+
+impl HeapRegion {
+    /// A zeroed region of at least `len` bytes
+    pub fn new(len: usize) -> Self {
+        HeapRegion { words: (0..len.div_ceil(8)).map(|_| AtomicU64::new(0)).collect() }
+    }
+}
+
+unsafe impl SharedRegion for HeapRegion {
+    fn as_ptr(&self) -> *mut u8 {
+        self.words.as_ptr() as *mut u8
+    }
+
+    fn size(&self) -> usize {
+        self.words.len() * 8
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// The header fields of a ring, accessed in place
+struct Ring {
+    base: *mut u8,
+    capacity: u64,
+}
+
+impl Ring {
+    /// Check the header of the ring in `region`
+    fn open<M: SharedRegion>(region: &M) -> io::Result<Self> {
+        let size = region.size();
+        if size < SHM_HEADER_LEN {
+            return Err(invalid("shared memory region too small for a ring header"));
+        }
+        let base = region.as_ptr();
+        let header = unsafe { std::slice::from_raw_parts(base as *const u8, SHM_HEADER_LEN) };
+        if header[0..8] != SHM_MAGIC {
+            return Err(invalid("not an event ring: bad magic"));
+        }
+        let version = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        if version != SHM_VERSION {
+            return Err(invalid(&format!("unsupported event ring version {}", version)));
+        }
+        let record_len = u32::from_le_bytes([header[12], header[13], header[14], header[15]]);
+        if record_len as usize != RECORD_LEN {
+            return Err(invalid(&format!("unsupported event ring record length {}", record_len)));
+        }
+        let mut capacity = [0u8; 8];
+        capacity.copy_from_slice(&header[CAPACITY_OFFSET..CAPACITY_OFFSET + 8]);
+        let capacity = u64::from_le_bytes(capacity);
+        let len = capacity.checked_mul(RECORD_LEN as u64).and_then(|records| records.checked_add(SHM_HEADER_LEN as u64));
+        if capacity == 0 || len.is_none_or(|len| len > size as u64) {
+            return Err(invalid("event ring capacity does not fit the shared memory region"));
+        }
+        Ok(Ring { base, capacity })
+    }
+
+    fn written(&self) -> &AtomicU64 {
+        unsafe { &*(self.base.add(WRITTEN_OFFSET) as *const AtomicU64) }
+    }
+
+    fn closed(&self) -> &AtomicU32 {
+        unsafe { &*(self.base.add(CLOSED_OFFSET) as *const AtomicU32) }
+    }
+
+    fn slot(&self, index: u64) -> *mut u8 {
+        unsafe { self.base.add(SHM_HEADER_LEN + (index % self.capacity) as usize * RECORD_LEN) }
+    }
+}
+
+/// Writes events into a ring in shared memory
+pub struct ShmWriter<M: SharedRegion> {
+    region: M,
+    ring: Ring,
+    written: u64,
+}
+
+impl<M: SharedRegion> ShmWriter<M> {
+    /// Lay out a new, empty ring of `capacity` records in `region`, which must hold
+    /// `ring_len(capacity)` bytes
+    pub fn create(region: M, capacity: usize) -> io::Result<Self> {
+        if capacity == 0 || ring_len(capacity) > region.size() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "event ring capacity does not fit the shared memory region"));
+        }
+        let mut header = [0u8; SHM_HEADER_LEN];
+        header[0..8].copy_from_slice(&SHM_MAGIC);
+        header[8..12].copy_from_slice(&SHM_VERSION.to_le_bytes());
+        header[12..16].copy_from_slice(&(RECORD_LEN as u32).to_le_bytes());
+        header[CAPACITY_OFFSET..CAPACITY_OFFSET + 8].copy_from_slice(&(capacity as u64).to_le_bytes());
+        unsafe { std::ptr::copy_nonoverlapping(header.as_ptr(), region.as_ptr(), SHM_HEADER_LEN) };
+        let ring = Ring::open(&region)?;
+        Ok(ShmWriter { region, ring, written: 0 })
+    }
+
+    /// Store an event and publish it to readers
+    pub fn write_event(&mut self, evt: &SaeEvent) {
+        let record = encode_event(evt);
+        let slot = self.ring.slot(self.written);
+        for (i, byte) in record.iter().enumerate() {
+            unsafe { slot.add(i).write_volatile(*byte) };
+        }
+        self.written += 1;
+        self.ring.written().store(self.written, Ordering::Release);
+    }
+
+    /// Mark the ring as finished: readers end once they have read every event
+    pub fn close(&mut self) {
+        self.ring.closed().store(1, Ordering::Release);
+    }
+
+    /// number of events written
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn region(&self) -> &M {
+        &self.region
+    }
+}
+
+/// Reads the events of a ring in shared memory, waiting for the writer as needed
+pub struct ShmSource<M: SharedRegion> {
+    region: M,
+    ring: Ring,
+    read: u64,
+    overruns: u64,
+    poll_interval: Duration,
+    idle_timeout: Option<Duration>,
+}
+
+impl<M: SharedRegion> ShmSource<M> {
+    /// Read the ring laid out in `region`, from the oldest event the writer is not
+    /// about to overwrite
+    pub fn new(region: M) -> io::Result<Self> {
+        let ring = Ring::open(&region)?;
+        let read = Self::oldest(&ring);
+        Ok(ShmSource { region, ring, read, overruns: 0, poll_interval: Duration::from_micros(100), idle_timeout: None })
+    }
+
+    fn oldest(ring: &Ring) -> u64 {
+        ring.written().load(Ordering::Acquire).saturating_sub(ring.capacity - 1)
+    }
+
+    /// Skip the events held in the ring, to read only those written from now on
+    pub fn skip_to_latest(&mut self) {
+        self.read = self.ring.written().load(Ordering::Acquire);
+    }
+
+    /// How long to sleep between checks for new events, 100us by default
+    pub fn set_poll_interval(&mut self, interval: Duration) {
+        self.poll_interval = interval;
+    }
+
+    /// Fail with `TimedOut` when no event arrives for `timeout`, eg to reconnect to a
+    /// bridge process that died without closing the ring. By default waits forever.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    /// number of events overwritten by the writer before they could be read
+    pub fn overruns(&self) -> u64 {
+        self.overruns
+    }
+
+    /// capacity of the ring in events
+    pub fn capacity(&self) -> u64 {
+        self.ring.capacity
+    }
+
+    pub fn region(&self) -> &M {
+        &self.region
+    }
+}
+
+impl<M: SharedRegion> EventSource for ShmSource<M> {
+    fn next_event(&mut self) -> io::Result<Option<SaeEvent>> {
+        let mut idle_since: Option<Instant> = None;
+        loop {
+            // read the closed flag first: once set, no more events follow the count
+            let closed = self.ring.closed().load(Ordering::Acquire) != 0;
+            let written = self.ring.written().load(Ordering::Acquire);
+            let behind = match written.checked_sub(self.read) {
+                Some(behind) => behind,
+                None => {
+                    // the writer re-created the ring, maybe with another capacity
+                    self.ring = Ring::open(&self.region)?;
+                    self.read = Self::oldest(&self.ring);
+                    continue;
+                }
+            };
+            if behind >= self.ring.capacity {
+                let oldest = written - self.ring.capacity + 1;
+                self.overruns += oldest - self.read;
+                self.read = oldest;
+            }
+            if self.read < written {
+                let slot = self.ring.slot(self.read);
+                let mut record = [0u8; RECORD_LEN];
+                for (i, byte) in record.iter_mut().enumerate() {
+                    *byte = unsafe { slot.add(i).read_volatile() };
+                }
+                // the writer may have lapped the slot while it was being copied: it stores
+                // record `w` before publishing `w + 1`, so the slot of record `read` is
+                // safe only while fewer than `capacity` records follow it
+                fence(Ordering::Acquire);
+                match self.ring.written().load(Ordering::Acquire).checked_sub(self.read) {
+                    Some(behind) if behind < self.ring.capacity => {},
+                    _ => continue,
+                }
+                self.read += 1;
+                return Ok(Some(decode_event(&record)));
+            }
+            if closed {
+                return Ok(None);
+            }
+            let since = *idle_since.get_or_insert_with(Instant::now);
+            if let Some(timeout) = self.idle_timeout {
+                if since.elapsed() >= timeout {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "no events written to the shared memory ring"));
+                }
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+// The region outlives the ring, and the header fields are only accessed atomically
+// or, for the records, checked against the published count
+unsafe impl<M: SharedRegion + Send> Send for ShmWriter<M> {}
+unsafe impl<M: SharedRegion + Send> Send for ShmSource<M> {}
+
+#[cfg(windows)]
+mod ffi {
+    use std::os::raw::c_void;
+
+    pub type Handle = *mut c_void;
+
+    pub const INVALID_HANDLE_VALUE: Handle = -1isize as Handle;
+    pub const PAGE_READWRITE: u32 = 0x04;
+    pub const FILE_MAP_ALL_ACCESS: u32 = 0x000F_001F;
+
+    #[repr(C)]
+    pub struct MemoryBasicInformation {
+        pub base_address: *mut c_void,
+        pub allocation_base: *mut c_void,
+        pub allocation_protect: u32,
+        pub region_size: usize,
+        pub state: u32,
+        pub protect: u32,
+        pub kind: u32,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub fn CreateFileMappingW(file: Handle, attributes: *mut c_void, protect: u32, size_high: u32, size_low: u32, name: *const u16) -> Handle;
+        pub fn OpenFileMappingW(access: u32, inherit: i32, name: *const u16) -> Handle;
+        pub fn MapViewOfFile(mapping: Handle, access: u32, offset_high: u32, offset_low: u32, len: usize) -> *mut c_void;
+        pub fn UnmapViewOfFile(base: *const c_void) -> i32;
+        pub fn VirtualQuery(address: *const c_void, info: *mut MemoryBasicInformation, len: usize) -> usize;
+        pub fn CloseHandle(handle: Handle) -> i32;
+    }
+}
+
+/// A named shared memory mapping backed by the paging file, as created by
+/// `CreateFileMappingW`, eg `Local\\arcstar` or `Global\\arcstar`
+#[cfg(windows)]
+pub struct NamedSharedMemory {
+    mapping: ffi::Handle,
+    view: *mut u8,
+    size: usize,
+}
+
+#[cfg(windows)]
+impl NamedSharedMemory {
+    /// Create the mapping `name` of `size` bytes, or open it if it already exists
+    pub fn create(name: &str, size: usize) -> io::Result<Self> {
+        let name = wide(name);
+        let mapping = unsafe {
+            ffi::CreateFileMappingW(ffi::INVALID_HANDLE_VALUE, std::ptr::null_mut(), ffi::PAGE_READWRITE,
+                                    (size as u64 >> 32) as u32, size as u32, name.as_ptr())
+        };
+        Self::map(mapping)
+    }
+
+    /// Open the existing mapping `name`, eg one created by a camera bridge process
+    pub fn open(name: &str) -> io::Result<Self> {
+        let name = wide(name);
+        let mapping = unsafe { ffi::OpenFileMappingW(ffi::FILE_MAP_ALL_ACCESS, 0, name.as_ptr()) };
+        Self::map(mapping)
+    }
+
+    fn map(mapping: ffi::Handle) -> io::Result<Self> {
+        if mapping.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let view = unsafe { ffi::MapViewOfFile(mapping, ffi::FILE_MAP_ALL_ACCESS, 0, 0, 0) };
+        if view.is_null() {
+            let err = io::Error::last_os_error();
+            unsafe { ffi::CloseHandle(mapping) };
+            return Err(err);
+        }
+        let mut info: ffi::MemoryBasicInformation = unsafe { std::mem::zeroed() };
+        let queried = unsafe { ffi::VirtualQuery(view, &mut info, std::mem::size_of::<ffi::MemoryBasicInformation>()) };
+        if queried == 0 {
+            let err = io::Error::last_os_error();
+            unsafe {
+                ffi::UnmapViewOfFile(view);
+                ffi::CloseHandle(mapping);
+            }
+            return Err(err);
+        }
+        Ok(NamedSharedMemory { mapping, view: view as *mut u8, size: info.region_size })
+    }
+}
+
+#[cfg(windows)]
+fn wide(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+#[cfg(windows)]
+unsafe impl SharedRegion for NamedSharedMemory {
+    fn as_ptr(&self) -> *mut u8 {
+        self.view
+    }
+
+    /// length of the view, rounded up to whole pages
+    fn size(&self) -> usize {
+        self.size
+    }
+}
+
+#[cfg(windows)]
+impl Drop for NamedSharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::UnmapViewOfFile(self.view as *const _);
+            ffi::CloseHandle(self.mapping);
+        }
+    }
+}
+
+// a mapped view may be used from any thread
+#[cfg(windows)]
+unsafe impl Send for NamedSharedMemory {}
+#[cfg(windows)]
+unsafe impl Sync for NamedSharedMemory {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row: (timestamp % 7) as u16, col: 3, polarity: (timestamp % 2) as u8, timestamp, ..SaeEvent::default() }
+    }
+
+    fn ring(capacity: usize) -> (ShmWriter<Arc<HeapRegion>>, ShmSource<Arc<HeapRegion>>) {
+        let region = Arc::new(HeapRegion::new(ring_len(capacity)));
+        let writer = ShmWriter::create(region.clone(), capacity).unwrap();
+        (writer, ShmSource::new(region).unwrap())
+    }
+
+    #[test]
+    fn test_ring_round_trip() {
+        let (mut writer, mut source) = ring(16);
+        for timestamp in 1..=10 {
+            writer.write_event(&event(timestamp));
+        }
+        writer.close();
+        let events: Vec<SaeEvent> = source.events().map(|evt| evt.unwrap()).collect();
+        assert_eq!(events, (1..=10).map(event).collect::<Vec<_>>());
+        assert_eq!(source.overruns(), 0);
+    }
+
+    #[test]
+    fn test_ring_overrun_skips_to_oldest() {
+        let (mut writer, mut source) = ring(4);
+        for timestamp in 1..=10 {
+            writer.write_event(&event(timestamp));
+        }
+        writer.close();
+        let times: Vec<SaeTime> = source.events().map(|evt| evt.unwrap().timestamp).collect();
+        assert_eq!(times, vec![8, 9, 10]);
+        assert_eq!(source.overruns(), 7);
+    }
+
+    #[test]
+    fn test_ring_across_threads() {
+        let (mut writer, mut source) = ring(1_024);
+        let producer = thread::spawn(move || {
+            for timestamp in 1..=1_000 {
+                writer.write_event(&event(timestamp));
+            }
+            writer.close();
+        });
+        let mut last = 0;
+        while let Some(evt) = source.next_event().unwrap() {
+            assert!(evt.timestamp > last);
+            last = evt.timestamp;
+        }
+        producer.join().unwrap();
+        assert_eq!(last, 1_000);
+    }
+
+    #[test]
+    fn test_ring_recreated_by_writer() {
+        let region = Arc::new(HeapRegion::new(ring_len(8)));
+        let mut writer = ShmWriter::create(region.clone(), 8).unwrap();
+        let mut source = ShmSource::new(region.clone()).unwrap();
+        for timestamp in 1..=5 {
+            writer.write_event(&event(timestamp));
+        }
+        for _ in 0..5 {
+            source.next_event().unwrap();
+        }
+        // a restarted bridge lays out a smaller ring over the same memory
+        let mut writer = ShmWriter::create(region, 4).unwrap();
+        writer.write_event(&event(100));
+        writer.close();
+        let times: Vec<SaeTime> = source.events().map(|evt| evt.unwrap().timestamp).collect();
+        assert_eq!(times, vec![100]);
+        assert_eq!(source.capacity(), 4);
+    }
+
+    #[test]
+    fn test_ring_idle_timeout_and_skip() {
+        let (mut writer, mut source) = ring(8);
+        writer.write_event(&event(1));
+        source.skip_to_latest();
+        source.set_idle_timeout(Some(Duration::from_millis(5)));
+        let err = source.next_event().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        writer.write_event(&event(2));
+        assert_eq!(source.next_event().unwrap().map(|evt| evt.timestamp), Some(2));
+    }
+
+    #[test]
+    fn test_ring_header_checks() {
+        let region = HeapRegion::new(ring_len(4));
+        assert_eq!(ShmSource::new(region).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert!(ShmWriter::create(HeapRegion::new(ring_len(4)), 5).is_err());
+        let writer = ShmWriter::create(HeapRegion::new(ring_len(4)), 4).unwrap();
+        assert_eq!(ShmSource::new(writer.region).unwrap().capacity(), 4);
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_named_shared_memory_ring() {
+        let name = format!("Local\\arcstar-test-{}", std::process::id());
+        let mut writer = ShmWriter::create(NamedSharedMemory::create(&name, ring_len(32)).unwrap(), 32).unwrap();
+        let mut source = ShmSource::new(NamedSharedMemory::open(&name).unwrap()).unwrap();
+        for timestamp in 1..=5 {
+            writer.write_event(&event(timestamp));
+        }
+        writer.close();
+        assert_eq!(source.events().count(), 5);
+    }
+}
@@ -0,0 +1,405 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Compression of SAE snapshots, for shipping surfaces to remote viewers at a high
+//! cadence without saturating the link.
+//!
+//! Between two snapshots a few milliseconds apart, most pixels keep their timestamp.
+//! `SnapshotEncoder` sends each snapshot as a frame of differences from the previous
+//! one, with runs of unchanged pixels collapsed, and sends full keyframes periodically
+//! so a viewer that joins late or loses a frame catches up. Viewers that only draw
+//! the recency of activity can take a lossy frame instead, of pixel ages quantized
+//! to a few levels. With the `zstd` feature, frames can further be compressed with zstd.
+//! ```text
+//! frame:   magic "SAEF" | version u16 | kind u8 | flags u8 | nrows u16 | ncols u16 |
+//!          sequence u64 | base sequence u64 | timestamp u32 | horizon u32 | levels u8 |
+//!          payload length u32 | payload
+//! payload: repeated (zero run varint | literal count varint | literal varints),
+//!          one value per pixel in column-major order
+//! ```
+
+use std::io::{self, Read};
+
+use crate::io::decode::{DecodeError, DecodeErrorKind, OffsetReader};
+use crate::sae_types::*;
+use crate::snapshot::SaeSnapshot;
+
+
+const MAGIC: &[u8; 4] = b"SAEF";
+const FORMAT_VERSION: u16 = 1;
+/// every pixel's timestamp, as a difference from zero
+const KIND_KEY: u8 = 0;
+/// every pixel's timestamp, as a difference from the base snapshot
+const KIND_DELTA: u8 = 1;
+/// every pixel's quantized age
+const KIND_AGES: u8 = 2;
+/// the payload is zstd-compressed
+const FLAG_ZSTD: u8 = 1;
+
+/// How snapshot timestamps are represented in frames
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SnapshotCompression {
+    /// exact timestamps, as differences from the previous frame
+    #[default]
+    Lossless,
+    /// Ages relative to the snapshot time, quantized to `levels` steps across `horizon`.
+    /// Pixels older than the horizon, and never observed, decode as zero.
+    AgeQuantized { horizon: SaeTime, levels: u8 },
+}
+
+/// Parameters of snapshot encoding
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotCodecConfig {
+    pub compression: SnapshotCompression,
+    /// a lossless keyframe is sent every this many frames; zero sends only the first
+    pub keyframe_interval: u32,
+    /// compress frames with zstd at this level
+    #[cfg(feature = "zstd")]
+    pub zstd_level: Option<i32>,
+}
+
+impl Default for SnapshotCodecConfig {
+    fn default() -> Self {
+        SnapshotCodecConfig {
+            compression: SnapshotCompression::Lossless,
+            keyframe_interval: 30,
+            #[cfg(feature = "zstd")]
+            zstd_level: None,
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint<R: Read>(reader: &mut OffsetReader<R>) -> io::Result<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let byte = reader.read_u8()?;
+        if shift == 28 && byte > 0x0f {
+            return Err(reader.error(DecodeErrorKind::Invalid("varint")));
+        }
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    unreachable!()
+}
+
+/// Encode values as alternating runs of zeros and of literal values
+fn encode_runs(values: &[u32]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut idx = 0;
+    while idx < values.len() {
+        let zeros = values[idx..].iter().take_while(|&&value| value == 0).count();
+        idx += zeros;
+        let literals = values[idx..].iter().take_while(|&&value| value != 0).count();
+        write_varint(&mut out, zeros as u32);
+        write_varint(&mut out, literals as u32);
+        for &value in values[idx..idx + literals].iter() {
+            write_varint(&mut out, value);
+        }
+        idx += literals;
+    }
+    out
+}
+
+fn decode_runs<R: Read>(reader: &mut OffsetReader<R>, count: usize) -> io::Result<Vec<u32>> {
+    let mut values = Vec::with_capacity(count);
+    while values.len() < count {
+        let zeros = read_varint(reader)? as usize;
+        let literals = read_varint(reader)? as usize;
+        if values.len() + zeros + literals > count {
+            return Err(reader.error(DecodeErrorKind::Invalid("run length")));
+        }
+        values.resize(values.len() + zeros, 0);
+        for _ in 0..literals {
+            values.push(read_varint(reader)?);
+        }
+    }
+    Ok(values)
+}
+
+/// quantized age level of a pixel: zero for stale or unobserved pixels, else
+/// `levels` for the freshest down to 1 for ages approaching the horizon
+fn age_level(value: SaeTime, now: SaeTime, horizon: SaeTime, levels: u8) -> u32 {
+    let age = now.saturating_sub(value) as u64;
+    if value == 0 || horizon == 0 || age >= horizon as u64 {
+        return 0;
+    }
+    levels as u32 - (age * levels as u64 / horizon as u64) as u32
+}
+
+/// the timestamp at the middle of a quantized age level
+fn level_timestamp(level: u32, now: SaeTime, horizon: SaeTime, levels: u8) -> SaeTime {
+    if level == 0 {
+        return 0;
+    }
+    let step = horizon as u64 / levels as u64;
+    let age = (levels as u64 - level as u64) * step + step / 2;
+    now.saturating_sub(age as SaeTime)
+}
+
+/// Encodes a sequence of snapshots into frames
+pub struct SnapshotEncoder {
+    config: SnapshotCodecConfig,
+    /// sequence number and contents of the last snapshot sent, as the decoder has it
+    previous: Option<(u64, SaeMatrix)>,
+    frames_since_key: u32,
+    force_keyframe: bool,
+    bytes_out: u64,
+}
+
+impl SnapshotEncoder {
+    pub fn new(config: SnapshotCodecConfig) -> Self {
+        SnapshotEncoder { config, previous: None, frames_since_key: 0, force_keyframe: false, bytes_out: 0 }
+    }
+
+    pub fn config(&self) -> &SnapshotCodecConfig {
+        &self.config
+    }
+
+    /// Send the next frame whole, eg when a viewer connects
+    pub fn request_keyframe(&mut self) {
+        self.force_keyframe = true;
+    }
+
+    /// total size of the frames encoded so far, in bytes
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out
+    }
+
+    /// Encode a snapshot as the next frame
+    pub fn encode(&mut self, snapshot: &SaeSnapshot) -> io::Result<Vec<u8>> {
+        let (nrows, ncols) = snapshot.sae.shape();
+        let (kind, base, values, horizon, levels) = match self.config.compression {
+            SnapshotCompression::AgeQuantized { horizon, levels } => {
+                let levels = levels.max(1);
+                let values: Vec<u32> = snapshot.sae.iter()
+                    .map(|&value| age_level(value, snapshot.timestamp, horizon, levels))
+                    .collect();
+                (KIND_AGES, 0, values, horizon, levels)
+            }
+            SnapshotCompression::Lossless => {
+                let due = self.config.keyframe_interval > 0 && self.frames_since_key >= self.config.keyframe_interval;
+                let delta_base = self.previous.as_ref()
+                    .filter(|(_, previous)| !due && !self.force_keyframe && previous.shape() == (nrows, ncols));
+                let (kind, base, values) = match delta_base {
+                    Some((sequence, previous)) => {
+                        let values = snapshot.sae.iter().zip(previous.iter())
+                            .map(|(&value, &prev)| value.wrapping_sub(prev))
+                            .collect();
+                        (KIND_DELTA, *sequence, values)
+                    }
+                    None => (KIND_KEY, 0, snapshot.sae.iter().cloned().collect()),
+                };
+                if kind == KIND_KEY {
+                    self.frames_since_key = 0;
+                    self.force_keyframe = false;
+                }
+                self.frames_since_key += 1;
+                self.previous = Some((snapshot.sequence, snapshot.sae.clone()));
+                (kind, base, values, 0, 0)
+            }
+        };
+
+        let payload = encode_runs(&values);
+        let (flags, payload) = self.compress(payload)?;
+        let mut frame = Vec::with_capacity(payload.len() + 40);
+        frame.extend_from_slice(MAGIC);
+        frame.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        frame.push(kind);
+        frame.push(flags);
+        frame.extend_from_slice(&(nrows as u16).to_le_bytes());
+        frame.extend_from_slice(&(ncols as u16).to_le_bytes());
+        frame.extend_from_slice(&snapshot.sequence.to_le_bytes());
+        frame.extend_from_slice(&base.to_le_bytes());
+        frame.extend_from_slice(&snapshot.timestamp.to_le_bytes());
+        frame.extend_from_slice(&horizon.to_le_bytes());
+        frame.push(levels);
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        self.bytes_out += frame.len() as u64;
+        Ok(frame)
+    }
+
+    #[cfg(feature = "zstd")]
+    fn compress(&self, payload: Vec<u8>) -> io::Result<(u8, Vec<u8>)> {
+        match self.config.zstd_level {
+            Some(level) => Ok((FLAG_ZSTD, zstd::encode_all(payload.as_slice(), level)?)),
+            None => Ok((0, payload)),
+        }
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn compress(&self, payload: Vec<u8>) -> io::Result<(u8, Vec<u8>)> {
+        Ok((0, payload))
+    }
+}
+
+/// Decodes frames back into snapshots, without their change tiles
+#[derive(Default)]
+pub struct SnapshotDecoder {
+    latest: Option<SaeSnapshot>,
+}
+
+impl SnapshotDecoder {
+    pub fn new() -> Self {
+        SnapshotDecoder { latest: None }
+    }
+
+    /// the most recently decoded snapshot
+    pub fn latest(&self) -> Option<&SaeSnapshot> {
+        self.latest.as_ref()
+    }
+
+    /// Decode the next frame. A delta frame whose base is not the latest snapshot,
+    /// eg after a lost frame, is an error; wait for the next keyframe.
+    pub fn decode(&mut self, frame: &[u8]) -> io::Result<&SaeSnapshot> {
+        let mut reader = OffsetReader::new(frame);
+        reader.expect_header(MAGIC, FORMAT_VERSION)?;
+        let kind_offset = reader.offset();
+        let kind = reader.read_u8()?;
+        let flags = reader.read_u8()?;
+        let nrows = reader.read_u16()? as usize;
+        let ncols = reader.read_u16()? as usize;
+        let sequence = reader.read_u64()?;
+        let base = reader.read_u64()?;
+        let timestamp = reader.read_u32()?;
+        let horizon = reader.read_u32()?;
+        let levels = reader.read_u8()?;
+        let payload_len = reader.read_u32()? as usize;
+        let offset = reader.offset();
+        let mut payload = vec![0u8; payload_len];
+        reader.read_bytes(&mut payload)?;
+        if flags & FLAG_ZSTD != 0 {
+            payload = Self::decompress(&payload, &reader)?;
+        }
+
+        let mut payload_reader = OffsetReader::new(payload.as_slice());
+        let values = decode_runs(&mut payload_reader, nrows * ncols)
+            .map_err(|err| shift_offset(err, offset))?;
+        let sae = match kind {
+            KIND_KEY => SaeMatrix::from_vec(nrows, ncols, values),
+            KIND_DELTA => {
+                let previous = self.latest.as_ref()
+                    .filter(|latest| latest.sequence == base && latest.sae.shape() == (nrows, ncols))
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "delta frame without its base snapshot"))?;
+                let mut sae = previous.sae.clone();
+                for (value, delta) in sae.iter_mut().zip(values) {
+                    *value = value.wrapping_add(delta);
+                }
+                sae
+            }
+            KIND_AGES => {
+                if levels == 0 || values.iter().any(|&level| level > levels as u32) {
+                    return Err(DecodeError::new(offset, DecodeErrorKind::Invalid("age level")).into());
+                }
+                let values = values.into_iter().map(|level| level_timestamp(level, timestamp, horizon, levels)).collect();
+                SaeMatrix::from_vec(nrows, ncols, values)
+            }
+            _ => return Err(DecodeError::new(kind_offset, DecodeErrorKind::Invalid("frame kind")).into()),
+        };
+        Ok(self.latest.insert(SaeSnapshot { sae, timestamp, sequence, changed: Vec::new() }))
+    }
+
+    #[cfg(feature = "zstd")]
+    fn decompress(payload: &[u8], _reader: &OffsetReader<&[u8]>) -> io::Result<Vec<u8>> {
+        zstd::decode_all(payload)
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn decompress(_payload: &[u8], reader: &OffsetReader<&[u8]>) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported,
+                           format!("zstd-compressed frame at byte {}: build with the zstd feature", reader.offset())))
+    }
+}
+
+/// report a payload decode error at its offset within the frame
+fn shift_offset(err: io::Error, offset: u64) -> io::Error {
+    match DecodeError::of(&err) {
+        Some(inner) => DecodeError::new(inner.offset + offset, inner.kind.clone()).into(),
+        None => err,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(sequence: u64, timestamp: SaeTime, pixels: &[(usize, usize, SaeTime)]) -> SaeSnapshot {
+        let mut sae = SaeMatrix::zeros(32, 48);
+        for &(row, col, value) in pixels.iter() {
+            sae[(row, col)] = value;
+        }
+        SaeSnapshot { sae, timestamp, sequence, changed: Vec::new() }
+    }
+
+    #[test]
+    fn test_lossless_frames() {
+        let mut encoder = SnapshotEncoder::new(SnapshotCodecConfig { keyframe_interval: 3, ..SnapshotCodecConfig::default() });
+        let mut decoder = SnapshotDecoder::new();
+        let mut pixels = vec![(1, 1, 100), (5, 40, 2_000_000)];
+        let mut frames = Vec::new();
+        for sequence in 1..=5u64 {
+            pixels.push((sequence as usize, 20, 3_000_000 + sequence as SaeTime));
+            let snap = snapshot(sequence, 3_000_010, &pixels);
+            let frame = encoder.encode(&snap).unwrap();
+            let decoded = decoder.decode(&frame).unwrap();
+            assert_eq!(decoded.sae, snap.sae);
+            assert_eq!((decoded.sequence, decoded.timestamp), (sequence, 3_000_010));
+            frames.push(frame);
+        }
+        let kinds: Vec<u8> = frames.iter().map(|frame| frame[6]).collect();
+        assert_eq!(kinds, vec![KIND_KEY, KIND_DELTA, KIND_DELTA, KIND_KEY, KIND_DELTA]);
+        // far smaller than the raw surface
+        assert!(frames[1].len() < 64);
+        assert_eq!(encoder.bytes_out(), frames.iter().map(|frame| frame.len() as u64).sum::<u64>());
+
+        // a viewer joining mid-stream needs a keyframe
+        let mut late = SnapshotDecoder::new();
+        assert!(late.decode(&frames[4]).is_err());
+        encoder.request_keyframe();
+        let frame = encoder.encode(&snapshot(6, 3_000_020, &pixels)).unwrap();
+        assert_eq!(late.decode(&frame).unwrap().sae[(5, 20)], 3_000_005);
+
+        assert!(decoder.decode(&frame[..frame.len() - 1]).is_err());
+        assert!(decoder.decode(b"SAEX").is_err());
+    }
+
+    #[test]
+    fn test_quantized_ages() {
+        let config = SnapshotCodecConfig {
+            compression: SnapshotCompression::AgeQuantized { horizon: 10_000, levels: 10 },
+            ..SnapshotCodecConfig::default()
+        };
+        let mut encoder = SnapshotEncoder::new(config);
+        let snap = snapshot(1, 50_000, &[(0, 0, 49_990), (3, 3, 44_100), (7, 7, 30_000)]);
+        let frame = encoder.encode(&snap).unwrap();
+        let decoded = SnapshotDecoder::new().decode(&frame).unwrap().clone();
+        // ages within half a level, and stale pixels dropped
+        assert_eq!(decoded.sae[(0, 0)], 49_500);
+        assert_eq!(decoded.sae[(3, 3)], 44_500);
+        assert_eq!(decoded.sae[(7, 7)], 0);
+        assert_eq!(decoded.sae[(9, 9)], 0);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_frames() {
+        let config = SnapshotCodecConfig { zstd_level: Some(3), ..SnapshotCodecConfig::default() };
+        let mut encoder = SnapshotEncoder::new(config);
+        let pixels: Vec<(usize, usize, SaeTime)> = (0..32).map(|row| (row, row, 1_000 + row as SaeTime)).collect();
+        let snap = snapshot(1, 2_000, &pixels);
+        let frame = encoder.encode(&snap).unwrap();
+        assert_eq!(frame[7], FLAG_ZSTD);
+        assert_eq!(SnapshotDecoder::new().decode(&frame).unwrap().sae, snap.sae);
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Record a live event stream while it is being processed, and replay it offline.
+//! Placing a `Tee` directly after any filtering stages captures the exact stream
+//! seen by the detector; replaying the recording with `replay_corners` reproduces
+//! the detector output for offline debugging. The detector itself is
+//! `detector::ReplayDetector`, re-exported here.
+
+use std::io::{self, Read, Write};
+
+use crate::io::compact::{CompactReader, CompactWriter, RecordingHeader};
+use crate::progress::JobControl;
+use crate::sae_types::*;
+
+pub use crate::detector::{DetectionPolicy, ReplayDetector};
+
+
+/// An iterator adapter that passes events through unchanged,
+/// recording each one to a compact recording.
+pub struct Tee<I, W: Write> {
+    inner: I,
+    writer: CompactWriter<W>,
+    error: Option<io::Error>,
+}
+
+impl<I, W> Tee<I, W>
+    where I: Iterator<Item = SaeEvent>, W: Write
+{
+    /// Writes the recording header immediately
+    pub fn new(inner: I, writer: W, header: &RecordingHeader) -> io::Result<Self> {
+        Ok(Tee {
+            inner,
+            writer: CompactWriter::new(writer, header)?,
+            error: None,
+        })
+    }
+
+    /// number of events recorded so far
+    pub fn recorded(&self) -> u64 {
+        self.writer.count()
+    }
+
+    /// Flush the recording and return the underlying writer.
+    /// Recording stops at the first write error, which is reported here:
+    /// the live stream itself is never interrupted by recording failures.
+    pub fn finish(self) -> io::Result<W> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.writer.into_inner()
+    }
+}
+
+impl<I, W> Iterator for Tee<I, W>
+    where I: Iterator<Item = SaeEvent>, W: Write
+{
+    type Item = SaeEvent;
+
+    fn next(&mut self) -> Option<SaeEvent> {
+        let evt = self.inner.next()?;
+        if self.error.is_none() {
+            if let Err(err) = self.writer.write_event(&evt) {
+                self.error = Some(err);
+            }
+        }
+        Some(evt)
+    }
+}
+
+/// Replay a recording through a detector configured from its header,
+/// returning the detected corners in order
+pub fn replay_corners<R: Read>(reader: CompactReader<R>) -> io::Result<Vec<SaeEvent>> {
+    replay_corners_with(reader, &mut JobControl::default())
+}
+
+/// Like `replay_corners`, reporting progress and checking for cancellation via `control`.
+/// A cancelled replay returns an `Interrupted` error.
+pub fn replay_corners_with<R: Read>(mut reader: CompactReader<R>, control: &mut JobControl) -> io::Result<Vec<SaeEvent>> {
+    let mut detector = ReplayDetector::new(reader.header());
+    let mut corners = Vec::new();
+    let mut count = 0;
+    while let Some(evt) = reader.read_event()? {
+        if let Some(corner) = detector.process(&evt) {
+            corners.push(corner);
+        }
+        count += 1;
+        control.checkpoint(count, reader.position())?;
+    }
+    control.finish(count, reader.position());
+    Ok(corners)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surface::WarmupConfig;
+
+    /// a moving corner: events filling the rows of a growing block
+    fn generate_events() -> Vec<SaeEvent> {
+        let mut events = Vec::new();
+        let mut timestamp = 1;
+        for step in 0..8u16 {
+            for polarity in 0..2u8 {
+                for row in 10..(16 + step) {
+                    events.push(SaeEvent {
+                        row,
+                        col: 10 + step,
+                        polarity,
+                        timestamp,
+                        ..SaeEvent::default()
+                    });
+                    timestamp += 10;
+                }
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn test_tee_replay_matches_live() {
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let events = generate_events();
+
+        let mut live_detector = ReplayDetector::new(&header);
+        let mut tee = Tee::new(events.clone().into_iter(), Vec::new(), &header).unwrap();
+        let live_corners: Vec<SaeEvent> = tee.by_ref()
+            .filter_map(|evt| live_detector.process(&evt))
+            .collect();
+        assert_eq!(tee.recorded(), events.len() as u64);
+        let bytes = tee.finish().unwrap();
+
+        let reader = CompactReader::new(bytes.as_slice()).unwrap();
+        let replayed = replay_corners(reader).unwrap();
+
+        assert!(!live_corners.is_empty());
+        assert_eq!(replayed, live_corners);
+        for (a, b) in replayed.iter().zip(live_corners.iter()) {
+            assert!(a.descriptor_approx_eq(b, 0.0));
+        }
+    }
+
+    #[test]
+    fn test_replay_uses_recorded_settings() {
+        use crate::detector::DetectorConfig;
+        use crate::subpixel::SubpixelConfig;
+        use crate::surface::UpdatePolicy;
+
+        let mut header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        header.detector = Some(DetectorConfig::with_arc_limits((3, 5), (4, 7)).unwrap());
+        header.detection_policy = DetectionPolicy::CombinedMaxSurface;
+        header.update_policy = UpdatePolicy::IgnoreOlderOrEqual;
+        header.subpixel = Some(SubpixelConfig::default());
+        let events = generate_events();
+
+        let mut live_detector = ReplayDetector::new(&header);
+        assert_eq!(live_detector.detection_policy(), DetectionPolicy::CombinedMaxSurface);
+        let mut tee = Tee::new(events.clone().into_iter(), Vec::new(), &header).unwrap();
+        let live_corners: Vec<SaeEvent> = tee.by_ref().filter_map(|evt| live_detector.process(&evt)).collect();
+        let bytes = tee.finish().unwrap();
+
+        let replayed = replay_corners(CompactReader::new(bytes.as_slice()).unwrap()).unwrap();
+        assert_eq!(replayed, live_corners);
+        // the default settings find other corners in the same stream
+        let mut default_detector = ReplayDetector::new(&RecordingHeader::new(32, 32, WarmupConfig::disabled()));
+        let default_corners: Vec<SaeEvent> = events.iter().filter_map(|evt| default_detector.process(evt)).collect();
+        assert_ne!(replayed, default_corners);
+    }
+
+    #[test]
+    fn test_replay_progress_and_cancel() {
+        use crate::progress::{CancelToken, Progress};
+
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let events = generate_events();
+        let mut writer = CompactWriter::new(Vec::new(), &header).unwrap();
+        for evt in events.iter() {
+            writer.write_event(evt).unwrap();
+        }
+        let bytes = writer.into_inner().unwrap();
+        let total_bytes = bytes.len() as u64;
+
+        let mut last_progress = None;
+        {
+            let mut control = JobControl::new()
+                .with_total_bytes(total_bytes)
+                .with_report_interval(10)
+                .with_progress(|p: &Progress| last_progress = Some(p.clone()));
+            let reader = CompactReader::new(bytes.as_slice()).unwrap();
+            replay_corners_with(reader, &mut control).unwrap();
+        }
+        let last_progress = last_progress.unwrap();
+        assert_eq!(last_progress.events_processed, events.len() as u64);
+        assert_eq!(last_progress.bytes_read, total_bytes);
+        assert_eq!(last_progress.fraction(), Some(1.0));
+
+        let token = CancelToken::new();
+        token.cancel();
+        let mut control = JobControl::new().with_cancel(token).with_report_interval(1);
+        let reader = CompactReader::new(bytes.as_slice()).unwrap();
+        let err = replay_corners_with(reader, &mut control).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    }
+
+    #[test]
+    fn test_detection_policies() {
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let events = generate_events();
+        let detect = |policy: DetectionPolicy, events: &[SaeEvent]| -> Vec<SaeEvent> {
+            let mut detector = ReplayDetector::new(&header);
+            detector.set_detection_policy(policy);
+            events.iter().filter_map(|evt| detector.process(evt)).collect()
+        };
+        let matched = detect(DetectionPolicy::MatchEventPolarity, &events);
+        let of_polarity = |polarity: u8| -> Vec<SaeEvent> {
+            matched.iter().filter(|corner| corner.polarity == polarity).cloned().collect()
+        };
+        assert!(!of_polarity(0).is_empty() && !of_polarity(1).is_empty());
+        assert_eq!(detect(DetectionPolicy::OnSurfaceOnly, &events), of_polarity(1));
+        assert_eq!(detect(DetectionPolicy::OffSurfaceOnly, &events), of_polarity(0));
+
+        let combined = detect(DetectionPolicy::CombinedMaxSurface, &events);
+        assert!(!combined.is_empty());
+        assert_ne!(combined, matched);
+        // with a single polarity, the combined surface is that polarity's surface
+        let on_only: Vec<SaeEvent> = events.iter().map(|evt| SaeEvent { polarity: 1, ..evt.clone() }).collect();
+        assert_eq!(detect(DetectionPolicy::CombinedMaxSurface, &on_only), detect(DetectionPolicy::MatchEventPolarity, &on_only));
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Export of per-track corner trajectories, for comparison against ground-truth
+//! feature tracks (eg KLT on frames) with standard tooling.
+//! Positions are written as image coordinates: `x` is the column, `y` the row.
+
+use std::io::{self, Write};
+
+use crate::track::{Track, TrackStore};
+
+
+/// Write all tracks as CSV with the columns `track_id,t,x,y`,
+/// where `t` is in seconds
+pub fn write_tracks_csv<W: Write>(store: &TrackStore, mut writer: W) -> io::Result<()> {
+    writeln!(writer, "track_id,t,x,y")?;
+    for track in store.iter() {
+        for obs in track.observations.iter() {
+            writeln!(writer, "{},{:.6},{},{}", track.id, obs.event_time().as_secs_f64(), obs.col, obs.row)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a single track in a TUM-like trajectory format:
+/// one `t x y` line per observation, space-separated, with `t` in seconds
+pub fn write_track_tum<W: Write>(track: &Track, mut writer: W) -> io::Result<()> {
+    writeln!(writer, "# track {}", track.id)?;
+    writeln!(writer, "# timestamp x y")?;
+    for obs in track.observations.iter() {
+        writeln!(writer, "{:.6} {} {}", obs.event_time().as_secs_f64(), obs.col, obs.row)?;
+    }
+    Ok(())
+}
+
+/// Write every track in the TUM-like format, one block per track
+pub fn write_tracks_tum<W: Write>(store: &TrackStore, mut writer: W) -> io::Result<()> {
+    for track in store.iter() {
+        write_track_tum(track, &mut writer)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sae_types::*;
+
+    fn sample_store() -> TrackStore {
+        let mut store = TrackStore::new();
+        let id = store.start_track(SaeEvent { row: 3, col: 4, timestamp: 1_500_000, ..SaeEvent::default() });
+        store.extend_track(id, SaeEvent { row: 3, col: 5, timestamp: 1_500_250, ..SaeEvent::default() });
+        store.start_track(SaeEvent { row: 9, col: 1, timestamp: 2_000_000, ..SaeEvent::default() });
+        store
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let mut out = Vec::new();
+        write_tracks_csv(&sample_store(), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text, "track_id,t,x,y\n\
+                          0,1.500000,4,3\n\
+                          0,1.500250,5,3\n\
+                          1,2.000000,1,9\n");
+    }
+
+    #[test]
+    fn test_tum_export() {
+        let mut out = Vec::new();
+        write_tracks_tum(&sample_store(), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines, vec![
+            "# track 0", "# timestamp x y", "1.500000 4 3", "1.500250 5 3",
+            "# track 1", "# timestamp x y", "2.000000 1 9",
+        ]);
+    }
+}
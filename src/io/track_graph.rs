@@ -0,0 +1,290 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Export of the observation graph in GraphML or DOT, for inspecting corner
+//! association with graph tooling (yEd, Gephi, Graphviz).
+//!
+//! Every observation of every track is a node, and each match that extended a track
+//! is an edge from the previous observation to the next, carrying the time step,
+//! the displacement and the descriptor distance of the match. Key slices are nodes
+//! too, with an edge to each observation they froze.
+
+use std::io::{self, Write};
+
+use crate::sae_types::*;
+use crate::track::{TrackId, TrackStore};
+use crate::vo::keyslice::{KeySlice, KeySliceId};
+
+
+/// What a graph node stands for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GraphNodeKind {
+    /// observation `index` of track `track`
+    Observation { track: TrackId, index: usize },
+    KeySlice(KeySliceId),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GraphNode {
+    pub kind: GraphNodeKind,
+    pub timestamp: SaeTime,
+    /// position of an observation (x = column, y = row)
+    pub position: Option<[f32; 2]>,
+    /// corner confidence of an observation
+    pub confidence: Option<f32>,
+}
+
+impl GraphNode {
+    /// identifier of the node, unique within a graph
+    pub fn name(&self) -> String {
+        match self.kind {
+            GraphNodeKind::Observation { track, index } => format!("t{}_{}", track, index),
+            GraphNodeKind::KeySlice(id) => format!("k{}", id),
+        }
+    }
+}
+
+/// What a graph edge stands for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphEdgeKind {
+    /// the target observation extended the track ending at the source
+    Match,
+    /// the source key slice froze the target observation
+    Member,
+}
+
+impl GraphEdgeKind {
+    fn label(self) -> &'static str {
+        match self {
+            GraphEdgeKind::Match => "match",
+            GraphEdgeKind::Member => "member",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GraphEdge {
+    pub kind: GraphEdgeKind,
+    /// indices into the graph's nodes
+    pub source: usize,
+    pub target: usize,
+    /// time from source to target observation
+    pub dt: Option<SaeTime>,
+    /// distance (pixels) from source to target observation
+    pub displacement: Option<f32>,
+    /// one minus the descriptor likeness, if both observations carry descriptors
+    pub descriptor_distance: Option<f32>,
+}
+
+/// The observation graph of a track store and, optionally, its key slices
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl TrackGraph {
+    /// Build the graph of every track in `store`, linking each of `key_slices` to the
+    /// observations it froze that are still held in `store`
+    pub fn new(store: &TrackStore, key_slices: &[KeySlice]) -> Self {
+        let mut graph = TrackGraph::default();
+        // node index of the first observation of each track, by track id
+        let mut first_nodes: Vec<(TrackId, usize)> = Vec::with_capacity(store.len());
+        for track in store.iter() {
+            first_nodes.push((track.id, graph.nodes.len()));
+            for (index, obs) in track.observations.iter().enumerate() {
+                let (row, col) = obs.subpixel_position();
+                graph.nodes.push(GraphNode {
+                    kind: GraphNodeKind::Observation { track: track.id, index },
+                    timestamp: obs.timestamp,
+                    position: Some([col, row]),
+                    confidence: obs.confidence,
+                });
+                if index > 0 {
+                    let prev = &track.observations[index - 1];
+                    let (prev_row, prev_col) = prev.subpixel_position();
+                    let descriptor_distance = match (&prev.norm_descriptor, &obs.norm_descriptor) {
+                        (Some(_), Some(_)) => Some(1.0 - prev.likeness(obs)),
+                        _ => None,
+                    };
+                    let target = graph.nodes.len() - 1;
+                    graph.edges.push(GraphEdge {
+                        kind: GraphEdgeKind::Match,
+                        source: target - 1,
+                        target,
+                        dt: Some(obs.timestamp.saturating_sub(prev.timestamp)),
+                        displacement: Some(((row - prev_row).powi(2) + (col - prev_col).powi(2)).sqrt()),
+                        descriptor_distance,
+                    });
+                }
+            }
+        }
+        first_nodes.sort_unstable_by_key(|(id, _)| *id);
+
+        for slice in key_slices.iter() {
+            let source = graph.nodes.len();
+            graph.nodes.push(GraphNode {
+                kind: GraphNodeKind::KeySlice(slice.id),
+                timestamp: slice.timestamp,
+                position: None,
+                confidence: None,
+            });
+            for (track_id, corner) in slice.corners.iter() {
+                let first = match first_nodes.binary_search_by_key(track_id, |(id, _)| *id) {
+                    Ok(idx) => first_nodes[idx].1,
+                    Err(_) => continue,
+                };
+                let track = store.get(*track_id).unwrap();
+                let found = track.observations.iter()
+                    .position(|obs| obs.timestamp == corner.timestamp && obs.row == corner.row && obs.col == corner.col);
+                if let Some(index) = found {
+                    graph.edges.push(GraphEdge {
+                        kind: GraphEdgeKind::Member,
+                        source,
+                        target: first + index,
+                        dt: None,
+                        displacement: None,
+                        descriptor_distance: None,
+                    });
+                }
+            }
+        }
+        graph
+    }
+
+    /// Write the graph in GraphML, with every attribute declared as a typed key
+    pub fn write_graphml<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(writer, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+        let keys = [
+            ("kind", "node", "string"), ("track", "node", "long"), ("timestamp", "node", "long"),
+            ("x", "node", "double"), ("y", "node", "double"), ("confidence", "node", "double"),
+            ("edge_kind", "edge", "string"), ("dt", "edge", "long"), ("displacement", "edge", "double"),
+            ("descriptor_distance", "edge", "double"),
+        ];
+        for (name, domain, kind) in keys.iter() {
+            writeln!(writer, r#"  <key id="{0}" for="{1}" attr.name="{0}" attr.type="{2}"/>"#, name, domain, kind)?;
+        }
+        writeln!(writer, r#"  <graph id="tracks" edgedefault="directed">"#)?;
+        for node in self.nodes.iter() {
+            writeln!(writer, r#"    <node id="{}">"#, node.name())?;
+            match node.kind {
+                GraphNodeKind::Observation { track, .. } => {
+                    writeln!(writer, r#"      <data key="kind">observation</data>"#)?;
+                    writeln!(writer, r#"      <data key="track">{}</data>"#, track)?;
+                }
+                GraphNodeKind::KeySlice(_) => writeln!(writer, r#"      <data key="kind">keyslice</data>"#)?,
+            }
+            writeln!(writer, r#"      <data key="timestamp">{}</data>"#, node.timestamp)?;
+            if let Some([x, y]) = node.position {
+                writeln!(writer, r#"      <data key="x">{}</data>"#, x)?;
+                writeln!(writer, r#"      <data key="y">{}</data>"#, y)?;
+            }
+            if let Some(confidence) = node.confidence {
+                writeln!(writer, r#"      <data key="confidence">{}</data>"#, confidence)?;
+            }
+            writeln!(writer, "    </node>")?;
+        }
+        for edge in self.edges.iter() {
+            writeln!(writer, r#"    <edge source="{}" target="{}">"#,
+                self.nodes[edge.source].name(), self.nodes[edge.target].name())?;
+            writeln!(writer, r#"      <data key="edge_kind">{}</data>"#, edge.kind.label())?;
+            if let Some(dt) = edge.dt {
+                writeln!(writer, r#"      <data key="dt">{}</data>"#, dt)?;
+            }
+            if let Some(displacement) = edge.displacement {
+                writeln!(writer, r#"      <data key="displacement">{}</data>"#, displacement)?;
+            }
+            if let Some(distance) = edge.descriptor_distance {
+                writeln!(writer, r#"      <data key="descriptor_distance">{}</data>"#, distance)?;
+            }
+            writeln!(writer, "    </edge>")?;
+        }
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")
+    }
+
+    /// Write the graph in Graphviz DOT, with the attributes as node and edge attributes
+    pub fn write_dot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "digraph tracks {{")?;
+        for node in self.nodes.iter() {
+            write!(writer, "  {} [timestamp={}", node.name(), node.timestamp)?;
+            match node.kind {
+                GraphNodeKind::Observation { track, .. } => write!(writer, ", kind=observation, track={}", track)?,
+                GraphNodeKind::KeySlice(_) => write!(writer, ", kind=keyslice, shape=box")?,
+            }
+            if let Some([x, y]) = node.position {
+                write!(writer, ", x={}, y={}", x, y)?;
+            }
+            if let Some(confidence) = node.confidence {
+                write!(writer, ", confidence={}", confidence)?;
+            }
+            writeln!(writer, "];")?;
+        }
+        for edge in self.edges.iter() {
+            write!(writer, "  {} -> {} [kind={}", self.nodes[edge.source].name(), self.nodes[edge.target].name(),
+                edge.kind.label())?;
+            if let Some(dt) = edge.dt {
+                write!(writer, ", dt={}", dt)?;
+            }
+            if let Some(displacement) = edge.displacement {
+                write!(writer, ", displacement={}", displacement)?;
+            }
+            if let Some(distance) = edge.descriptor_distance {
+                write!(writer, ", descriptor_distance={}", distance)?;
+            }
+            if edge.kind == GraphEdgeKind::Member {
+                write!(writer, ", style=dashed")?;
+            }
+            writeln!(writer, "];")?;
+        }
+        writeln!(writer, "}}")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vo::keyslice::KeySliceReason;
+
+    #[test]
+    fn test_graph_export() {
+        let mut store = TrackStore::new();
+        let desc = Some(Box::new([0.5; NORM_DESCRIPTOR_LEN]));
+        let first = SaeEvent { row: 3, col: 4, timestamp: 100, confidence: Some(0.5), norm_descriptor: desc.clone(), ..SaeEvent::default() };
+        let second = SaeEvent { row: 3, col: 7, timestamp: 350, confidence: Some(0.75), norm_descriptor: desc, ..SaeEvent::default() };
+        let id = store.start_track(first);
+        store.extend_track(id, second.clone());
+        let other = store.start_track(SaeEvent { row: 9, col: 1, timestamp: 200, ..SaeEvent::default() });
+        let slice = KeySlice { id: 0, timestamp: 400, reason: KeySliceReason::First, corners: vec![(id, second)] };
+        // a track no longer in the store is left out
+        let gone = KeySlice { id: 1, timestamp: 500, reason: KeySliceReason::Elapsed, corners: vec![(other + 1, SaeEvent::default())] };
+
+        let graph = TrackGraph::new(&store, &[slice, gone]);
+        assert_eq!(graph.nodes.len(), 5);
+        assert_eq!(graph.edges.len(), 2);
+        let matched = &graph.edges[0];
+        assert_eq!((matched.kind, matched.source, matched.target), (GraphEdgeKind::Match, 0, 1));
+        assert_eq!((matched.dt, matched.displacement), (Some(250), Some(3.0)));
+        assert!(matched.descriptor_distance.unwrap().abs() < 1e-6);
+        assert_eq!((graph.edges[1].kind, graph.edges[1].source, graph.edges[1].target), (GraphEdgeKind::Member, 3, 1));
+
+        let mut dot = Vec::new();
+        graph.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph tracks {\n"));
+        assert!(dot.contains("  t0_1 [timestamp=350, kind=observation, track=0, x=7, y=3, confidence=0.75];\n"));
+        assert!(dot.contains("  t0_0 -> t0_1 [kind=match, dt=250, displacement=3, descriptor_distance=0];\n"));
+        assert!(dot.contains("  k0 -> t0_1 [kind=member, style=dashed];\n"));
+
+        let mut graphml = Vec::new();
+        graph.write_graphml(&mut graphml).unwrap();
+        let graphml = String::from_utf8(graphml).unwrap();
+        assert_eq!(graphml.matches("<node ").count(), 5);
+        assert_eq!(graphml.matches("<edge ").count(), 2);
+        assert!(graphml.contains(r#"<edge source="t0_0" target="t0_1">"#));
+        assert!(graphml.contains(r#"<data key="kind">keyslice</data>"#));
+        assert!(graphml.trim_end().ends_with("</graphml>"));
+    }
+}
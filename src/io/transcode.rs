@@ -0,0 +1,350 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Conversion of event recordings between the file formats the crate supports:
+//! the compact format (`io::compact`), numpy `.npy` event arrays (`io::npy`), and
+//! Prophesee EVT 3.0 RAW files (the `evt3` encoding of `io::ros2`, after the RAW
+//! file's `%` header lines).
+//!
+//! Events are split into chunks, which are encoded on several threads and written
+//! in order. Each chunk is checksummed (CRC-32 over its events' compact records)
+//! before encoding and, when verification is enabled, again after decoding the
+//! output back, so that a lossy conversion, such as EVT3's 24-bit timestamps on a
+//! long recording, is reported by chunk and time range rather than going unnoticed.
+//!
+//! Bag files (ROS1 bags, ROS2 sqlite3 or MCAP) and AEDAT4 files are not supported:
+//! the crate reads ROS2 event packets and AEDAT4 streams, but not their containers.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::io::compact::{decode_event, encode_event, CompactReader, RecordingHeader, RECORD_LEN};
+use crate::io::npy::{crc32, read_events_npy, write_header, EVENT_DESCR};
+use crate::io::ros2::{encode_evt3, EventPacket, PacketDecoder, EVT3};
+use crate::sae_types::*;
+use crate::surface::WarmupConfig;
+
+
+/// A recording file format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventFormat {
+    /// the crate's compact format
+    Compact,
+    /// a numpy `.npy` array of events
+    Npy,
+    /// a Prophesee EVT 3.0 RAW file
+    Evt3Raw,
+}
+
+impl EventFormat {
+    /// The format conventionally stored with a file extension: `arcstar`, `npy` or `raw`
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "arcstar" => Some(EventFormat::Compact),
+            "npy" => Some(EventFormat::Npy),
+            "raw" => Some(EventFormat::Evt3Raw),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for EventFormat {
+    type Err = io::Error;
+
+    /// Parse a format name: `compact`, `npy` or `evt3`
+    fn from_str(name: &str) -> io::Result<Self> {
+        match name {
+            "compact" => Ok(EventFormat::Compact),
+            "npy" => Ok(EventFormat::Npy),
+            EVT3 => Ok(EventFormat::Evt3Raw),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown event format {}", name))),
+        }
+    }
+}
+
+/// Parameters of a conversion
+#[derive(Clone, Debug, PartialEq)]
+pub struct TranscodeConfig {
+    /// number of events per chunk
+    pub chunk_events: usize,
+    /// number of threads encoding chunks
+    pub threads: usize,
+    /// whether to decode the output back and compare it with the input, chunk by chunk
+    pub verify: bool,
+    /// sensor geometry for outputs that record it, where the input does not;
+    /// by default, that just covering the events
+    pub header: Option<RecordingHeader>,
+}
+
+impl Default for TranscodeConfig {
+    fn default() -> Self {
+        TranscodeConfig {
+            chunk_events: 1 << 18,
+            threads: 4,
+            verify: true,
+            header: None,
+        }
+    }
+}
+
+/// The integrity check of one chunk
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkCheck {
+    pub index: usize,
+    pub events: usize,
+    pub first_timestamp: SaeTime,
+    pub last_timestamp: SaeTime,
+    /// CRC-32 of the chunk's events as read
+    pub crc: u32,
+    /// whether the output decodes back to the same events; None without verification
+    pub intact: Option<bool>,
+}
+
+/// The outcome of a conversion
+#[derive(Clone, Debug, PartialEq)]
+pub struct TranscodeReport {
+    pub events: u64,
+    /// the sensor geometry written, or found in the input
+    pub header: RecordingHeader,
+    pub chunks: Vec<ChunkCheck>,
+}
+
+impl TranscodeReport {
+    /// whether every chunk was verified intact
+    pub fn is_intact(&self) -> bool {
+        self.chunks.iter().all(|chunk| chunk.intact == Some(true))
+    }
+
+    /// the chunks whose output did not decode back to the input
+    pub fn damaged_chunks(&self) -> impl Iterator<Item = &ChunkCheck> {
+        self.chunks.iter().filter(|chunk| chunk.intact == Some(false))
+    }
+}
+
+/// CRC-32 over the compact records of `events`
+fn events_crc(events: &[SaeEvent]) -> u32 {
+    let bytes: Vec<u8> = events.iter().flat_map(|evt| encode_event(evt).to_vec()).collect();
+    crc32(&bytes)
+}
+
+/// Parse the `%` header lines of a RAW file, returning (height, width) if given
+fn read_raw_header<R: BufRead>(reader: &mut R) -> io::Result<Option<(u32, u32)>> {
+    let mut geometry = None;
+    while reader.fill_buf()?.first() == Some(&b'%') {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_start_matches('%').trim();
+        // `format EVT3;height=720;width=1280`, or the older `geometry 1280x720`
+        if let Some(format) = line.strip_prefix("format ") {
+            let field = |name: &str| format.split(';')
+                .find_map(|field| field.strip_prefix(name))
+                .and_then(|value| value.parse::<u32>().ok());
+            if let (Some(height), Some(width)) = (field("height="), field("width=")) {
+                geometry = Some((height, width));
+            }
+        } else if let Some(size) = line.strip_prefix("geometry ") {
+            let mut dims = size.split('x').map(|dim| dim.trim().parse::<u32>().ok());
+            if let (Some(Some(width)), Some(Some(height))) = (dims.next(), dims.next()) {
+                geometry = Some((height, width));
+            }
+        } else if line == "end" {
+            break;
+        }
+    }
+    Ok(geometry)
+}
+
+fn raw_header(header: &RecordingHeader) -> Vec<u8> {
+    format!("% evt 3.0\n% format EVT3;height={};width={}\n% end\n", header.nrows, header.ncols).into_bytes()
+}
+
+/// Read every event of a recording in `format`, with the sensor geometry if the format records it
+pub fn read_recording<R: Read>(format: EventFormat, reader: R) -> io::Result<(Option<RecordingHeader>, Vec<SaeEvent>)> {
+    match format {
+        EventFormat::Compact => {
+            let reader = CompactReader::new(reader)?;
+            let header = reader.header().clone();
+            let events = reader.collect::<io::Result<Vec<SaeEvent>>>()?;
+            Ok((Some(header), events))
+        }
+        EventFormat::Npy => Ok((None, read_events_npy(reader)?)),
+        EventFormat::Evt3Raw => {
+            let mut reader = BufReader::new(reader);
+            let geometry = read_raw_header(&mut reader)?;
+            let mut packet = EventPacket { encoding: EVT3.to_string(), ..EventPacket::default() };
+            reader.read_to_end(&mut packet.events)?;
+            let (height, width) = geometry.unwrap_or((u32::MAX, u32::MAX));
+            packet.height = height;
+            packet.width = width;
+            let events = PacketDecoder::new().decode(&packet)?;
+            let header = geometry.map(|(height, width)| {
+                RecordingHeader::new(height.min(u16::MAX as u32) as u16, width.min(u16::MAX as u32) as u16, WarmupConfig::default())
+            });
+            Ok((header, events))
+        }
+    }
+}
+
+/// Encode the body of one chunk, without any file header
+fn encode_chunk(format: EventFormat, header: &RecordingHeader, events: &[SaeEvent]) -> Vec<u8> {
+    match format {
+        EventFormat::Compact | EventFormat::Npy => events.iter().flat_map(|evt| encode_event(evt).to_vec()).collect(),
+        EventFormat::Evt3Raw => encode_evt3(events, header.ncols as u32, header.nrows as u32, 0).events,
+    }
+}
+
+/// Decode the body of one chunk, continuing the decoder state of the previous chunks
+fn decode_chunk(format: EventFormat, header: &RecordingHeader, decoder: &mut PacketDecoder, bytes: &[u8]) -> io::Result<Vec<SaeEvent>> {
+    match format {
+        EventFormat::Compact | EventFormat::Npy => Ok(bytes.chunks(RECORD_LEN)
+            .map(|record| {
+                let mut buf = [0u8; RECORD_LEN];
+                buf.copy_from_slice(record);
+                decode_event(&buf)
+            })
+            .collect()),
+        EventFormat::Evt3Raw => decoder.decode(&EventPacket {
+            height: header.nrows as u32,
+            width: header.ncols as u32,
+            encoding: EVT3.to_string(),
+            events: bytes.to_vec(),
+            ..EventPacket::default()
+        }),
+    }
+}
+
+/// Write `events` in `format`, encoding chunks in parallel, and check each chunk
+pub fn write_recording<W: Write>(format: EventFormat, mut writer: W, header: &RecordingHeader, events: &[SaeEvent],
+                                 config: &TranscodeConfig) -> io::Result<TranscodeReport> {
+    match format {
+        EventFormat::Compact => header.write_to(&mut writer)?,
+        EventFormat::Npy => write_header(&mut writer, EVENT_DESCR, &[events.len()])?,
+        EventFormat::Evt3Raw => writer.write_all(&raw_header(header))?,
+    }
+
+    let chunks: Vec<&[SaeEvent]> = events.chunks(config.chunk_events.max(1)).collect();
+    let next = AtomicUsize::new(0);
+    let encoded = Mutex::new(vec![None; chunks.len()]);
+    thread::scope(|scope| {
+        for _ in 0..config.threads.clamp(1, chunks.len().max(1)) {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                if idx >= chunks.len() {
+                    break;
+                }
+                let bytes = encode_chunk(format, header, chunks[idx]);
+                let crc = events_crc(chunks[idx]);
+                encoded.lock().unwrap()[idx] = Some((bytes, crc));
+            });
+        }
+    });
+
+    let mut decoder = PacketDecoder::new();
+    let mut checks = Vec::with_capacity(chunks.len());
+    for (index, (chunk, encoded)) in chunks.iter().zip(encoded.into_inner().unwrap()).enumerate() {
+        let (bytes, crc) = encoded.unwrap();
+        writer.write_all(&bytes)?;
+        let intact = if config.verify {
+            let decoded = decode_chunk(format, header, &mut decoder, &bytes)?;
+            Some(decoded.len() == chunk.len() && events_crc(&decoded) == crc)
+        } else {
+            None
+        };
+        checks.push(ChunkCheck {
+            index,
+            events: chunk.len(),
+            first_timestamp: chunk[0].timestamp,
+            last_timestamp: chunk[chunk.len() - 1].timestamp,
+            crc,
+            intact,
+        });
+    }
+    writer.flush()?;
+    Ok(TranscodeReport { events: events.len() as u64, header: header.clone(), chunks: checks })
+}
+
+/// The smallest geometry covering every event
+pub(crate) fn covering_header(events: &[SaeEvent]) -> RecordingHeader {
+    let nrows = events.iter().map(|evt| evt.row.saturating_add(1)).max().unwrap_or(0);
+    let ncols = events.iter().map(|evt| evt.col.saturating_add(1)).max().unwrap_or(0);
+    RecordingHeader::new(nrows, ncols, WarmupConfig::default())
+}
+
+/// Convert a recording from one format to another. The geometry written is that of the
+/// input where it records one, else that of the configuration, else that covering the events.
+pub fn transcode<R: Read, W: Write>(input: EventFormat, reader: R, output: EventFormat, writer: W,
+                                    config: &TranscodeConfig) -> io::Result<TranscodeReport> {
+    let (header, events) = read_recording(input, reader)?;
+    let header = header.or_else(|| config.header.clone()).unwrap_or_else(|| covering_header(&events));
+    write_recording(output, writer, &header, &events, config)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::compact::CompactWriter;
+
+    fn recording(count: u32, start: SaeTime) -> Vec<SaeEvent> {
+        (0..count)
+            .map(|i| SaeEvent {
+                row: (i % 40) as u16,
+                col: (i * 7 % 60) as u16,
+                polarity: (i % 3 == 0) as u8,
+                timestamp: start + i * 37,
+                ..SaeEvent::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_transcode_round_trips() {
+        let events = recording(1_000, 500);
+        let header = RecordingHeader::new(48, 64, WarmupConfig::default());
+        let mut writer = CompactWriter::new(Vec::new(), &header).unwrap();
+        for evt in events.iter() {
+            writer.write_event(evt).unwrap();
+        }
+        let compact = writer.into_inner().unwrap();
+        let config = TranscodeConfig { chunk_events: 64, threads: 3, ..TranscodeConfig::default() };
+
+        let mut raw = Vec::new();
+        let report = transcode(EventFormat::Compact, compact.as_slice(), EventFormat::Evt3Raw, &mut raw, &config).unwrap();
+        assert_eq!(report.events, 1_000);
+        assert_eq!(report.chunks.len(), 16);
+        assert!(report.is_intact());
+        assert!(raw.starts_with(b"% evt 3.0\n% format EVT3;height=48;width=64\n"));
+
+        let mut npy = Vec::new();
+        let report = transcode(EventFormat::Evt3Raw, raw.as_slice(), EventFormat::Npy, &mut npy, &config).unwrap();
+        assert_eq!(report.header, header);
+        assert!(report.is_intact());
+
+        let mut back = Vec::new();
+        transcode(EventFormat::Npy, npy.as_slice(), EventFormat::Compact, &mut back, &TranscodeConfig { header: Some(header.clone()), ..config.clone() }).unwrap();
+        assert_eq!(back, compact);
+        assert_eq!(read_recording(EventFormat::Npy, npy.as_slice()).unwrap().1, events);
+        assert_eq!("evt3".parse::<EventFormat>().unwrap(), EventFormat::Evt3Raw);
+        assert_eq!(EventFormat::from_extension("NPY"), Some(EventFormat::Npy));
+    }
+
+    #[test]
+    fn test_lossy_conversion_is_reported() {
+        // EVT3 keeps 24 bits of time: rollovers are unwrapped across chunks,
+        // but a recording starting past 2^24 us decodes early
+        let events = recording(200, (1 << 24) - 3_000);
+        let header = RecordingHeader::new(40, 60, WarmupConfig::default());
+        let config = TranscodeConfig { chunk_events: 50, ..TranscodeConfig::default() };
+        let report = write_recording(EventFormat::Evt3Raw, Vec::new(), &header, &events, &config).unwrap();
+        assert!(report.is_intact());
+
+        let late = recording(100, (1 << 24) + 5_000);
+        let report = write_recording(EventFormat::Evt3Raw, Vec::new(), &header, &late, &config).unwrap();
+        let damaged: Vec<usize> = report.damaged_chunks().map(|chunk| chunk.index).collect();
+        assert_eq!(damaged, vec![0, 1]);
+        assert_eq!(report.chunks[0].first_timestamp, (1 << 24) + 5_000);
+    }
+}
@@ -1,8 +1,171 @@
 // Copyright 2019, Todd Stellanova
 // License: see LICENSE file
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
 pub mod sae_types;
+#[cfg(feature = "std")]
+pub mod arena;
+#[cfg(feature = "std")]
+pub mod attention;
+#[cfg(feature = "std")]
+pub mod backlog;
+#[cfg(feature = "std")]
+pub mod balance;
+#[cfg(feature = "std")]
+pub mod beacon;
+#[cfg(feature = "std")]
+pub mod budget;
+#[cfg(feature = "std")]
+pub mod burst;
+#[cfg(feature = "std")]
+pub mod calib;
+#[cfg(feature = "std")]
+pub mod circle;
+#[cfg(feature = "std")]
+pub mod concurrent;
+#[cfg(feature = "std")]
+pub mod control;
+#[cfg(feature = "std")]
+pub mod covis;
+#[cfg(feature = "std")]
+pub mod dataset;
+#[cfg(feature = "std")]
+pub mod decimate;
+#[cfg(feature = "std")]
+pub mod descriptor;
 pub mod detector;
+#[cfg(feature = "std")]
+pub mod drift;
+#[cfg(feature = "std")]
+pub mod drops;
+#[cfg(feature = "std")]
+pub mod dual;
+#[cfg(feature = "std")]
+pub mod efast;
+#[cfg(feature = "std")]
+pub mod epipolar;
+#[cfg(feature = "std")]
+pub mod eval;
+#[cfg(feature = "std")]
+pub mod faults;
+#[cfg(feature = "std")]
+pub mod fiducial;
+pub mod filter;
+#[cfg(feature = "std")]
+pub mod flicker;
+#[cfg(feature = "std")]
+pub mod fusion;
+#[cfg(feature = "std")]
+pub mod gesture;
+#[cfg(feature = "std")]
+pub mod io;
+#[cfg(feature = "std")]
+pub mod lifetime;
+#[cfg(feature = "std")]
+pub mod lsh;
+#[cfg(feature = "std")]
+pub mod mask;
+#[cfg(feature = "std")]
+pub mod matcher;
+#[cfg(feature = "std")]
+pub mod merge;
+#[cfg(feature = "std")]
+pub mod motion;
+#[cfg(feature = "net")]
+pub mod mqtt;
+#[cfg(feature = "std")]
+pub mod nms;
+#[cfg(feature = "std")]
+pub mod noise;
+#[cfg(feature = "std")]
+pub mod objects;
+#[cfg(feature = "std")]
+pub mod patch_track;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod predict;
+#[cfg(feature = "std")]
+pub mod prelude;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod progress;
+#[cfg(feature = "std")]
+pub mod projection;
+#[cfg(feature = "std")]
+pub mod pyramid;
+#[cfg(feature = "std")]
+pub mod raster;
+#[cfg(feature = "io")]
+pub mod recording;
+#[cfg(feature = "std")]
+pub mod redetect;
+#[cfg(feature = "registry")]
+pub mod registry;
+#[cfg(feature = "std")]
+pub mod rejects;
+#[cfg(all(feature = "image", feature = "std"))]
+pub mod render;
+#[cfg(feature = "std")]
+pub mod reverse;
+#[cfg(feature = "std")]
+pub mod sae_filter;
+#[cfg(feature = "std")]
+pub mod sae_tracker;
+#[cfg(feature = "std")]
+pub mod scheduler;
+#[cfg(feature = "std")]
+pub mod selftest;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod sink;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod sim;
+#[cfg(feature = "std")]
+pub mod source;
+#[cfg(feature = "std")]
+pub mod speed;
+#[cfg(feature = "std")]
+pub mod split;
+pub mod static_pipeline;
+pub mod storage;
+#[cfg(feature = "std")]
+pub mod stabilize;
+#[cfg(feature = "std")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod subpixel;
+#[cfg(feature = "std")]
+pub mod surface;
+#[cfg(all(feature = "candle-core", feature = "std"))]
+pub mod tensor;
+#[cfg(feature = "std")]
+pub mod thinning;
+#[cfg(feature = "std")]
+pub mod tiles;
+pub mod time;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod track;
+#[cfg(feature = "std")]
+pub mod validate;
+pub mod view;
+#[cfg(feature = "std")]
+pub mod voxel;
+#[cfg(feature = "std")]
+pub mod vo;
+#[cfg(feature = "std")]
+pub mod watchdog;
+
+#[cfg(feature = "io")]
+pub use crate::recording::{process_recording, ProcessConfig, RecordingResult};
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,215 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Per-event lifetime estimation after Mueggler, Forster, Baumli, Gallego & Scaramuzza,
+//! "Lifetime Estimation of Events from Dynamic Vision Sensors" (ICRA 2015).
+//!
+//! A plane `t = a*col + b*row + c` is fit to the recent timestamps around each event
+//! in the SAE. The gradient `(a, b)` is the inverse of the local edge velocity,
+//! so its magnitude is the time the edge takes to move one pixel: the event lifetime.
+//! Lifetimes can be used to age out SAE entries adaptively, rather than with a fixed window.
+
+use nalgebra::{Matrix3, Vector3};
+
+use crate::sae_types::*;
+use crate::surface::SaeSurface;
+
+
+/// Parameters of the lifetime estimator
+#[derive(Clone, Debug, PartialEq)]
+pub struct LifetimeConfig {
+    /// half-size of the square neighborhood used for the plane fit
+    pub radius: usize,
+    /// neighbors older than this (relative to the event) are ignored
+    pub window: SaeTime,
+    /// minimum number of neighbors (including the event itself) for a fit
+    pub min_support: usize,
+    /// neighbors with a larger residual than this are dropped before refitting
+    pub max_residual: f32,
+}
+
+impl Default for LifetimeConfig {
+    fn default() -> Self {
+        LifetimeConfig {
+            radius: 2,
+            window: 50_000,
+            min_support: 5,
+            max_residual: 5_000.0,
+        }
+    }
+}
+
+/// Estimates the lifetime of events from the local SAE gradient
+pub struct LifetimeEstimator {
+    config: LifetimeConfig,
+}
+
+/// Least-squares plane fit over (dcol, drow, dt) samples; returns (a, b, c)
+fn fit_plane(samples: &[(f32, f32, f32)]) -> Option<Vector3<f32>> {
+    let mut ata = Matrix3::<f32>::zeros();
+    let mut atb = Vector3::<f32>::zeros();
+    for &(x, y, t) in samples {
+        let row = Vector3::new(x, y, 1.0);
+        ata += row * row.transpose();
+        atb += row * t;
+    }
+    ata.try_inverse().map(|inv| inv * atb)
+}
+
+impl LifetimeEstimator {
+    pub fn new(config: LifetimeConfig) -> Self {
+        LifetimeEstimator { config }
+    }
+
+    pub fn config(&self) -> &LifetimeConfig {
+        &self.config
+    }
+
+    /// Estimate the lifetime of `evt`, which should already have been applied
+    /// to `surface` (the surface for the event's polarity).
+    /// Returns None where the neighborhood doesn't support a plane fit,
+    /// or the fitted plane is flat (no measurable motion).
+    pub fn estimate(&self, surface: &SaeSurface, evt: &SaeEvent) -> Option<SaeTime> {
+        self.gradient(surface, evt).map(|gradient| gradient.round() as SaeTime)
+    }
+
+    /// Estimate the local edge speed at `evt`, in pixels per second: the inverse of its lifetime
+    pub fn speed(&self, surface: &SaeSurface, evt: &SaeEvent) -> Option<f32> {
+        self.gradient(surface, evt).map(|gradient| 1e6 / gradient)
+    }
+
+    /// magnitude of the fitted plane's gradient, in microseconds per pixel
+    fn gradient(&self, surface: &SaeSurface, evt: &SaeEvent) -> Option<f32> {
+        let (nrows, ncols) = surface.shape();
+        let row = evt.row as usize;
+        let col = evt.col as usize;
+        if row >= nrows || col >= ncols {
+            return None;
+        }
+        let radius = self.config.radius;
+        let sae = surface.matrix();
+
+        let mut samples = Vec::with_capacity((2 * radius + 1) * (2 * radius + 1));
+        for r in row.saturating_sub(radius)..=(row + radius).min(nrows - 1) {
+            for c in col.saturating_sub(radius)..=(col + radius).min(ncols - 1) {
+                if !surface.is_observed(r, c) {
+                    continue;
+                }
+                let ts = sae[(r, c)];
+                if ts > evt.timestamp || evt.timestamp - ts > self.config.window {
+                    continue;
+                }
+                // timestamps relative to the event keep the fit well conditioned
+                let dt = -((evt.timestamp - ts) as f32);
+                samples.push((c as f32 - col as f32, r as f32 - row as f32, dt));
+            }
+        }
+        if samples.len() < self.config.min_support {
+            return None;
+        }
+
+        let mut plane = fit_plane(&samples)?;
+        // drop outliers once, then refit
+        let max_residual = self.config.max_residual;
+        samples.retain(|&(x, y, t)| (plane.x * x + plane.y * y + plane.z - t).abs() <= max_residual);
+        if samples.len() < self.config.min_support {
+            return None;
+        }
+        plane = fit_plane(&samples)?;
+
+        let gradient = (plane.x * plane.x + plane.y * plane.y).sqrt();
+        if !gradient.is_finite() || gradient < 1.0 {
+            return None;
+        }
+        Some(gradient)
+    }
+}
+
+/// Per-pixel expiry times derived from event lifetimes:
+/// a pixel is active until its most recent event's lifetime has elapsed.
+pub struct ActiveEventMap {
+    expiry: SaeMatrix,
+    /// lifetime used for events whose lifetime couldn't be estimated
+    default_lifetime: SaeTime,
+}
+
+impl ActiveEventMap {
+    pub fn new(nrows: usize, ncols: usize, default_lifetime: SaeTime) -> Self {
+        ActiveEventMap {
+            expiry: SaeMatrix::zeros(nrows, ncols),
+            default_lifetime,
+        }
+    }
+
+    /// Record an event with its (optional) estimated lifetime
+    pub fn update(&mut self, evt: &SaeEvent, lifetime: Option<SaeTime>) {
+        let (nrows, ncols) = self.expiry.shape();
+        let row = evt.row as usize;
+        let col = evt.col as usize;
+        if row < nrows && col < ncols {
+            let lifetime = lifetime.unwrap_or(self.default_lifetime);
+            self.expiry[(row, col)] = evt.timestamp.saturating_add(lifetime);
+        }
+    }
+
+    /// whether the pixel's most recent event is still valid at time `now`
+    pub fn is_active(&self, row: usize, col: usize, now: SaeTime) -> bool {
+        self.expiry.get((row, col)).is_some_and(|&expiry| expiry > now)
+    }
+
+    /// number of pixels active at time `now`
+    pub fn active_count(&self, now: SaeTime) -> usize {
+        self.expiry.iter().filter(|&&expiry| expiry > now).count()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// an edge sweeping along the columns, taking `period` to advance one column
+    fn sweep_surface(period: SaeTime, up_to_col: u16) -> (SaeSurface, SaeEvent) {
+        let mut surface = SaeSurface::new(16, 16);
+        let mut last = SaeEvent::new();
+        for col in 0..=up_to_col {
+            for row in 0..16 {
+                last = SaeEvent { row, col, timestamp: 1_000 + period * col as SaeTime, ..SaeEvent::default() };
+                surface.update(&last);
+            }
+        }
+        (surface, SaeEvent { row: 8, ..last })
+    }
+
+    #[test]
+    fn test_lifetime_of_moving_edge() {
+        let estimator = LifetimeEstimator::new(LifetimeConfig::default());
+        let (surface, evt) = sweep_surface(1_000, 10);
+        assert_eq!(estimator.estimate(&surface, &evt), Some(1_000));
+
+        // a faster edge has a shorter lifetime
+        let (surface, evt) = sweep_surface(200, 10);
+        assert_eq!(estimator.estimate(&surface, &evt), Some(200));
+    }
+
+    #[test]
+    fn test_insufficient_support() {
+        let estimator = LifetimeEstimator::new(LifetimeConfig::default());
+        let mut surface = SaeSurface::new(16, 16);
+        let evt = SaeEvent { row: 8, col: 8, timestamp: 500, ..SaeEvent::default() };
+        surface.update(&evt);
+        assert_eq!(estimator.estimate(&surface, &evt), None);
+    }
+
+    #[test]
+    fn test_active_event_map() {
+        let mut map = ActiveEventMap::new(4, 4, 100);
+        map.update(&SaeEvent { row: 1, col: 1, timestamp: 1_000, ..SaeEvent::default() }, Some(500));
+        map.update(&SaeEvent { row: 2, col: 2, timestamp: 1_000, ..SaeEvent::default() }, None);
+        assert!(map.is_active(1, 1, 1_400));
+        assert!(!map.is_active(2, 2, 1_400));
+        assert_eq!(map.active_count(1_050), 2);
+        assert_eq!(map.active_count(1_500), 0);
+        assert!(!map.is_active(9, 9, 0));
+    }
+}
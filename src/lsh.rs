@@ -0,0 +1,212 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Locality-sensitive hashing (LSH) index over corner descriptors, for approximate
+//! nearest-neighbor retrieval from descriptor databases too large for brute force,
+//! eg when relocalizing against a previously built map.
+//!
+//! Each table hashes a descriptor to one bit per random hyperplane. Similar descriptors
+//! fall on the same side of most hyperplanes, so they tend to share a bucket in at
+//! least one table. More tables raise recall; more bits per table shrink the buckets.
+
+use std::collections::HashMap;
+
+use rand::distributions::StandardNormal;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::sae_types::*;
+
+
+/// Parameters of the LSH index
+#[derive(Clone, Debug, PartialEq)]
+pub struct LshConfig {
+    /// number of independent hash tables
+    pub tables: usize,
+    /// hyperplanes (hash bits) per table, at most 64
+    pub bits: usize,
+    /// seed for the random hyperplanes, so that indexes can be rebuilt identically
+    pub seed: u64,
+}
+
+impl Default for LshConfig {
+    fn default() -> Self {
+        LshConfig {
+            tables: 8,
+            bits: 12,
+            seed: 0,
+        }
+    }
+}
+
+/// A hyperplane `normal . x = offset`
+struct Hyperplane {
+    normal: NormDescriptor,
+    offset: f32,
+}
+
+fn dot(a: &NormDescriptor, b: &NormDescriptor) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
+
+fn squared_distance(a: &NormDescriptor, b: &NormDescriptor) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+/// Approximate nearest-neighbor index from descriptors to values of type `T`
+pub struct LshIndex<T> {
+    config: LshConfig,
+    /// hyperplanes for each table
+    planes: Vec<Vec<Hyperplane>>,
+    /// bucket hash to entry indexes, for each table
+    buckets: Vec<HashMap<u64, Vec<usize>>>,
+    entries: Vec<(NormDescriptor, T)>,
+}
+
+impl<T> LshIndex<T> {
+    pub fn new(config: LshConfig) -> Self {
+        let bits = config.bits.clamp(1, 64);
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let planes = (0..config.tables).map(|_| {
+            (0..bits).map(|_| {
+                let mut normal = [0.0; NORM_DESCRIPTOR_LEN];
+                // a random point in the descriptor range [0, 1] for the plane to pass through
+                let mut through = [0.0; NORM_DESCRIPTOR_LEN];
+                for (n, p) in normal.iter_mut().zip(through.iter_mut()) {
+                    *n = rng.sample::<f64, _>(StandardNormal) as f32;
+                    *p = rng.gen();
+                }
+                let offset = dot(&normal, &through);
+                Hyperplane { normal, offset }
+            }).collect()
+        }).collect();
+
+        LshIndex {
+            buckets: (0..config.tables).map(|_| HashMap::new()).collect(),
+            config: LshConfig { bits, ..config },
+            planes,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &LshConfig {
+        &self.config
+    }
+
+    fn hash(planes: &[Hyperplane], desc: &NormDescriptor) -> u64 {
+        planes.iter().enumerate().fold(0u64, |hash, (bit, plane)| {
+            if dot(&plane.normal, desc) >= plane.offset {
+                hash | (1 << bit)
+            } else {
+                hash
+            }
+        })
+    }
+
+    /// Add a descriptor and its associated value
+    pub fn insert(&mut self, desc: NormDescriptor, value: T) {
+        let idx = self.entries.len();
+        for (planes, buckets) in self.planes.iter().zip(self.buckets.iter_mut()) {
+            buckets.entry(Self::hash(planes, &desc)).or_insert_with(Vec::new).push(idx);
+        }
+        self.entries.push((desc, value));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Up to `k` approximate nearest neighbors of the descriptor, closest first,
+    /// with their Euclidean distances. Only entries sharing a bucket with the
+    /// query in some table are considered, and entries at a non-finite distance,
+    /// as from a descriptor holding NaN, are left out.
+    pub fn query(&self, desc: &NormDescriptor, k: usize) -> Vec<(&T, f32)> {
+        let mut candidates: Vec<usize> = self.planes.iter().zip(self.buckets.iter())
+            .filter_map(|(planes, buckets)| buckets.get(&Self::hash(planes, desc)))
+            .flat_map(|bucket| bucket.iter().cloned())
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut scored: Vec<(usize, f32)> = candidates.into_iter()
+            .map(|idx| (idx, squared_distance(&self.entries[idx].0, desc)))
+            .filter(|(_, dist2)| dist2.is_finite())
+            .collect();
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1));
+        scored.into_iter()
+            .take(k)
+            .map(|(idx, dist2)| (&self.entries[idx].1, dist2.sqrt()))
+            .collect()
+    }
+
+    /// The single approximate nearest neighbor, if any entry shares a bucket with the query
+    pub fn nearest(&self, desc: &NormDescriptor) -> Option<(&T, f32)> {
+        self.query(desc, 1).into_iter().next()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_descriptors(count: usize, seed: u64) -> Vec<NormDescriptor> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count).map(|_| {
+            let mut desc = [0.0; NORM_DESCRIPTOR_LEN];
+            for val in desc.iter_mut() {
+                *val = rng.gen();
+            }
+            desc
+        }).collect()
+    }
+
+    #[test]
+    fn test_finds_near_duplicates() {
+        let database = random_descriptors(2_000, 1);
+        let mut index = LshIndex::new(LshConfig::default());
+        for (i, desc) in database.iter().enumerate() {
+            index.insert(*desc, i);
+        }
+        assert_eq!(index.len(), 2_000);
+
+        // slightly perturbed copies of database entries should mostly find their originals
+        let mut found = 0;
+        for i in (0..2_000).step_by(20) {
+            let mut query = database[i];
+            for val in query.iter_mut() {
+                *val += 0.01;
+            }
+            if let Some((&idx, dist)) = index.nearest(&query) {
+                if idx == i {
+                    found += 1;
+                    assert!(dist < 0.1);
+                }
+            }
+        }
+        assert!(found >= 90, "found {}", found);
+    }
+
+    #[test]
+    fn test_query_ordering() {
+        let mut index = LshIndex::new(LshConfig { tables: 4, bits: 2, seed: 9 });
+        let base = [0.5; NORM_DESCRIPTOR_LEN];
+        index.insert([0.52; NORM_DESCRIPTOR_LEN], "near");
+        index.insert(base, "same");
+        let results = index.query(&base, 5);
+        assert_eq!(results[0].0, &"same");
+        assert!(results[0].1 < 1e-6);
+        assert!(results.len() <= 2);
+
+        // a blank-ring descriptor of NaN neither panics nor matches
+        let mut index = LshIndex::new(LshConfig { tables: 2, bits: 1, seed: 9 });
+        for (i, desc) in random_descriptors(50, 2).into_iter().enumerate() {
+            index.insert(desc, i);
+        }
+        assert!(index.query(&[f32::NAN; NORM_DESCRIPTOR_LEN], 5).is_empty());
+    }
+}
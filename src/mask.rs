@@ -0,0 +1,211 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Masking of dead or occluded sensor regions.
+//!
+//! A dead column keeps the timestamps it last received forever, and the detector sees
+//! the boundary between it and its live neighbors as a persistent edge. A `SensorMask`
+//! marks such pixels, built from whole rows, columns and rectangular regions or read
+//! from a mask image. As an `EventFilter` it drops events from masked pixels, and as
+//! the dead pixels of a `DetectorConfig` it keeps them out of circle sampling and
+//! descriptors; `Pipeline::set_sensor_mask` does both.
+
+use std::io::{self, Read};
+use std::ops::Range;
+
+use crate::filter::EventFilter;
+use crate::sae_types::*;
+
+
+/// Dead regions of a sensor, as ranges of pixels
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MaskConfig {
+    pub rows: Vec<Range<usize>>,
+    pub cols: Vec<Range<usize>>,
+    /// rectangles, as (rows, cols)
+    pub regions: Vec<(Range<usize>, Range<usize>)>,
+}
+
+/// Flags the pixels whose events should be ignored
+#[derive(Clone, Debug, PartialEq)]
+pub struct SensorMask {
+    masked: SaeOccupancy,
+    rejected: u64,
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("mask image: {}", what))
+}
+
+impl SensorMask {
+    /// A mask with no pixels masked
+    pub fn new(nrows: usize, ncols: usize) -> Self {
+        SensorMask { masked: SaeOccupancy::from_element(nrows, ncols, false), rejected: 0 }
+    }
+
+    pub fn from_config(nrows: usize, ncols: usize, config: &MaskConfig) -> Self {
+        let mut mask = Self::new(nrows, ncols);
+        for rows in config.rows.iter() {
+            mask = mask.with_rows(rows.clone());
+        }
+        for cols in config.cols.iter() {
+            mask = mask.with_cols(cols.clone());
+        }
+        for (rows, cols) in config.regions.iter() {
+            mask = mask.with_region(rows.clone(), cols.clone());
+        }
+        mask
+    }
+
+    /// Read a mask from a PGM image (binary `P5` or plain `P2`), the size of the sensor:
+    /// pixels with a nonzero value are masked
+    pub fn from_pgm<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        // the header: magic, width, height and maxval, separated by whitespace and comments
+        let mut pos = 0;
+        let mut fields = Vec::with_capacity(4);
+        while fields.len() < 4 {
+            match data.get(pos) {
+                Some(b'#') => {
+                    while data.get(pos).is_some_and(|&b| b != b'\n') {
+                        pos += 1;
+                    }
+                }
+                Some(b) if b.is_ascii_whitespace() => pos += 1,
+                Some(_) => {
+                    let start = pos;
+                    while data.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+                        pos += 1;
+                    }
+                    fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+                }
+                None => return Err(invalid("truncated header")),
+            }
+        }
+        let number = |field: &str| field.parse::<usize>().map_err(|_| invalid("header"));
+        let (ncols, nrows, maxval) = (number(&fields[1])?, number(&fields[2])?, number(&fields[3])?);
+        if maxval == 0 || maxval > 65_535 {
+            return Err(invalid("maxval"));
+        }
+        let values: Vec<usize> = match fields[0].as_str() {
+            "P5" => {
+                // a single whitespace byte separates the header from the raster
+                let raster = data.get(pos + 1..).unwrap_or(&[]);
+                if maxval < 256 {
+                    raster.iter().map(|&b| b as usize).collect()
+                } else {
+                    raster.chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]]) as usize).collect()
+                }
+            }
+            "P2" => String::from_utf8_lossy(&data[pos..])
+                .split_ascii_whitespace()
+                .map(number)
+                .collect::<io::Result<_>>()?,
+            _ => return Err(invalid("not a PGM image")),
+        };
+        if values.len() < nrows * ncols {
+            return Err(invalid("truncated raster"));
+        }
+        let mut mask = Self::new(nrows, ncols);
+        for row in 0..nrows {
+            for col in 0..ncols {
+                mask.masked[(row, col)] = values[row * ncols + col] != 0;
+            }
+        }
+        Ok(mask)
+    }
+
+    /// Mask whole rows; ranges beyond the sensor are clipped
+    pub fn with_rows(self, rows: Range<usize>) -> Self {
+        let ncols = self.masked.ncols();
+        self.with_region(rows, 0..ncols)
+    }
+
+    /// Mask whole columns; ranges beyond the sensor are clipped
+    pub fn with_cols(self, cols: Range<usize>) -> Self {
+        let nrows = self.masked.nrows();
+        self.with_region(0..nrows, cols)
+    }
+
+    /// Mask a rectangle; ranges beyond the sensor are clipped
+    pub fn with_region(mut self, rows: Range<usize>, cols: Range<usize>) -> Self {
+        let (nrows, ncols) = self.masked.shape();
+        for row in rows.start..rows.end.min(nrows) {
+            for col in cols.start..cols.end.min(ncols) {
+                self.masked[(row, col)] = true;
+            }
+        }
+        self
+    }
+
+    pub fn with_pixel(self, row: usize, col: usize) -> Self {
+        self.with_region(row..row + 1, col..col + 1)
+    }
+
+    /// whether the pixel is masked; pixels outside the sensor are not
+    pub fn is_masked(&self, row: usize, col: usize) -> bool {
+        row < self.masked.nrows() && col < self.masked.ncols() && self.masked[(row, col)]
+    }
+
+    pub fn masked_count(&self) -> usize {
+        self.masked.iter().filter(|&&masked| masked).count()
+    }
+
+    /// the mask as flags, for `DetectorConfig::with_dead_pixels`
+    pub fn occupancy(&self) -> &SaeOccupancy {
+        &self.masked
+    }
+
+    /// number of events dropped as coming from masked pixels
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+}
+
+impl EventFilter for SensorMask {
+    fn accept(&mut self, evt: &SaeEvent) -> bool {
+        if self.is_masked(evt.row as usize, evt.col as usize) {
+            self.rejected += 1;
+            return false;
+        }
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_and_filter() {
+        let config = MaskConfig { rows: vec![0..1, 1..2], cols: vec![5..6, 6..7], regions: vec![(8..10, 8..20)] };
+        let mut mask = SensorMask::from_config(10, 12, &config).with_pixel(3, 3);
+        assert_eq!(mask.masked_count(), 2 * 12 + 2 * 8 + 2 * 4 + 1);
+        assert!(mask.is_masked(4, 6) && mask.is_masked(9, 11) && mask.is_masked(3, 3));
+        assert!(!mask.is_masked(4, 4) && !mask.is_masked(10, 0));
+
+        assert!(!mask.accept(&SaeEvent { row: 2, col: 5, ..SaeEvent::default() }));
+        assert!(mask.accept(&SaeEvent { row: 2, col: 4, ..SaeEvent::default() }));
+        assert_eq!(mask.rejected(), 1);
+    }
+
+    #[test]
+    fn test_from_pgm() {
+        let plain = b"P2\n# dead column 1\n3 2\n255\n0 255 0\n0 1 0\n";
+        let mask = SensorMask::from_pgm(&plain[..]).unwrap();
+        assert_eq!(mask.occupancy().shape(), (2, 3));
+        assert_eq!(mask.masked_count(), 2);
+        assert!(mask.is_masked(0, 1) && mask.is_masked(1, 1));
+
+        let mut binary = b"P5 3 2 255\n".to_vec();
+        binary.extend_from_slice(&[0, 0, 9, 0, 0, 0]);
+        let mask = SensorMask::from_pgm(&binary[..]).unwrap();
+        assert_eq!(mask.masked_count(), 1);
+        assert!(mask.is_masked(0, 2));
+
+        assert!(SensorMask::from_pgm(&b"P5 3 2 255\n\0\0"[..]).is_err());
+        assert!(SensorMask::from_pgm(&b"P6 3 2 255\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Matching of corners between two time slices by descriptor likeness, within a
+//! spatial gate, eg for the feature association of a visual odometry front end.
+//!
+//! Each corner of the first slice is matched to the most alike corner of the second
+//! within `MatchOptions::radius`. Candidates are found through a grid of cells as
+//! large as the gate, so that each corner is compared only with those in the
+//! neighboring cells rather than with all of them. Matches can be cross-checked,
+//! kept only if each corner is the other's best match, and ratio-tested, kept only
+//! if the best candidate is clearly better than the second best.
+
+use std::collections::HashMap;
+
+use crate::sae_types::*;
+
+
+/// How candidates are found
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatchSearch {
+    /// compare every pair of corners
+    BruteForce,
+    /// compare corners in neighboring grid cells only
+    #[default]
+    Grid,
+}
+
+/// Parameters of matching
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchOptions {
+    /// greatest distance (pixels) between matched corners
+    pub radius: f32,
+    /// least descriptor likeness of a match
+    pub min_likeness: f32,
+    pub weights: DescriptorWeights,
+    /// keep only mutual best matches
+    pub cross_check: bool,
+    /// Lowe's ratio test on descriptor distance (1 - likeness): keep a match only if
+    /// its distance is under this fraction of the second best candidate's
+    pub ratio: Option<f32>,
+    pub search: MatchSearch,
+    /// compare binary descriptors by Hamming distance where both corners carry one,
+    /// instead of the weighted descriptor likeness
+    pub binary: bool,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        MatchOptions {
+            radius: 8.0,
+            min_likeness: 0.5,
+            weights: DescriptorWeights::uniform(),
+            cross_check: true,
+            ratio: None,
+            search: MatchSearch::Grid,
+            binary: false,
+        }
+    }
+}
+
+/// Corner indices bucketed by square cells of the gate radius
+struct GridIndex {
+    cell: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl GridIndex {
+    fn new(corners: &[SaeEvent], radius: f32) -> Self {
+        let mut index = GridIndex { cell: radius.max(1.0), cells: HashMap::new() };
+        for (idx, corner) in corners.iter().enumerate() {
+            let cell = index.cell_of(corner);
+            index.cells.entry(cell).or_default().push(idx);
+        }
+        index
+    }
+
+    fn cell_of(&self, corner: &SaeEvent) -> (i32, i32) {
+        let (row, col) = corner.subpixel_position();
+        ((row / self.cell).floor() as i32, (col / self.cell).floor() as i32)
+    }
+
+    /// indices of the corners in the cells around `corner`
+    fn candidates<'a>(&'a self, corner: &SaeEvent) -> impl Iterator<Item = usize> + 'a {
+        let (row, col) = self.cell_of(corner);
+        (-1..=1)
+            .flat_map(move |dr| (-1..=1).map(move |dc| (row + dr, col + dc)))
+            .filter_map(move |cell| self.cells.get(&cell))
+            .flat_map(|indices| indices.iter().copied())
+    }
+}
+
+/// The best candidate for `query` among `targets`: (index, likeness), if it passes
+/// the gate, likeness threshold and ratio test. Ties go to the lowest index.
+fn best_match<I: Iterator<Item = usize>>(query: &SaeEvent, targets: &[SaeEvent], candidates: I, opts: &MatchOptions)
+    -> Option<(usize, f32)> {
+    let (qrow, qcol) = query.subpixel_position();
+    let radius2 = opts.radius * opts.radius;
+    let mut best: Option<(usize, f32)> = None;
+    let mut second = 0.0f32;
+    for idx in candidates {
+        let target = &targets[idx];
+        let (row, col) = target.subpixel_position();
+        if (row - qrow).powi(2) + (col - qcol).powi(2) > radius2 {
+            continue;
+        }
+        let likeness = match (query.binary_descriptor, target.binary_descriptor) {
+            (Some(a), Some(b)) if opts.binary => a.likeness(&b),
+            _ => query.likeness_weighted(target, &opts.weights),
+        };
+        match best {
+            Some((best_idx, best_likeness)) if likeness < best_likeness || (likeness == best_likeness && idx > best_idx) => {
+                second = second.max(likeness);
+            }
+            _ => {
+                if let Some((_, previous)) = best {
+                    second = second.max(previous);
+                }
+                best = Some((idx, likeness));
+            }
+        }
+    }
+    let (idx, likeness) = best?;
+    if likeness < opts.min_likeness {
+        return None;
+    }
+    if let Some(ratio) = opts.ratio {
+        if (1.0 - likeness) >= ratio * (1.0 - second) {
+            return None;
+        }
+    }
+    Some((idx, likeness))
+}
+
+/// Match the corners of `frame_a` to those of `frame_b`, returning
+/// (index in a, index in b, likeness) in order of the index in a
+pub fn match_events(frame_a: &[SaeEvent], frame_b: &[SaeEvent], opts: &MatchOptions) -> Vec<(usize, usize, f32)> {
+    let (grid_a, grid_b) = match opts.search {
+        MatchSearch::Grid => (Some(GridIndex::new(frame_a, opts.radius)), Some(GridIndex::new(frame_b, opts.radius))),
+        MatchSearch::BruteForce => (None, None),
+    };
+    let best_in = |query: &SaeEvent, targets: &[SaeEvent], grid: &Option<GridIndex>| match grid {
+        Some(grid) => best_match(query, targets, grid.candidates(query), opts),
+        None => best_match(query, targets, 0..targets.len(), opts),
+    };
+    frame_a.iter()
+        .enumerate()
+        .filter_map(|(idx_a, corner)| {
+            let (idx_b, likeness) = best_in(corner, frame_b, &grid_b)?;
+            if opts.cross_check && best_in(&frame_b[idx_b], frame_a, &grid_a).map(|(back, _)| back) != Some(idx_a) {
+                return None;
+            }
+            Some((idx_a, idx_b, likeness))
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn corner(row: f32, col: f32, desc: NormDescriptor) -> SaeEvent {
+        SaeEvent { row: row as u16, col: col as u16, row_f: Some(row), col_f: Some(col),
+                   norm_descriptor: Some(Box::new(desc)), ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_grid_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut frame_a = Vec::new();
+        let mut frame_b = Vec::new();
+        for _ in 0..300 {
+            let mut desc = [0.0; NORM_DESCRIPTOR_LEN];
+            desc.iter_mut().for_each(|value| *value = rng.gen_range(0.0, 1.0));
+            let (row, col) = (rng.gen_range(0.0, 200.0), rng.gen_range(0.0, 200.0));
+            frame_a.push(corner(row, col, desc));
+            // moved a little, with a slightly changed descriptor
+            desc[0] *= 0.9;
+            frame_b.push(corner(row + rng.gen_range(-3.0, 3.0), col + rng.gen_range(-3.0, 3.0), desc));
+        }
+        let grid = match_events(&frame_a, &frame_b, &MatchOptions::default());
+        let brute = match_events(&frame_a, &frame_b, &MatchOptions { search: MatchSearch::BruteForce, ..MatchOptions::default() });
+        assert_eq!(grid, brute);
+        let correct = grid.iter().filter(|&&(a, b, _)| a == b).count();
+        assert!(correct > 250 && correct == grid.len(), "{} of {}", correct, grid.len());
+    }
+
+    #[test]
+    fn test_cross_check_and_ratio() {
+        let mut strong = [0.1; NORM_DESCRIPTOR_LEN];
+        strong[0] = 1.0;
+        let weak = [0.1; NORM_DESCRIPTOR_LEN];
+        // two corners of a compete for the one corner of b
+        let frame_a = vec![corner(10.0, 10.0, strong), corner(11.0, 10.0, weak)];
+        let frame_b = vec![corner(10.5, 10.0, strong)];
+        let opts = MatchOptions { min_likeness: 0.0, cross_check: false, ..MatchOptions::default() };
+        assert_eq!(match_events(&frame_a, &frame_b, &opts).len(), 2);
+        let checked = match_events(&frame_a, &frame_b, &MatchOptions { cross_check: true, ..opts.clone() });
+        assert_eq!(checked.iter().map(|m| (m.0, m.1)).collect::<Vec<_>>(), vec![(0, 0)]);
+
+        // an ambiguous match fails the ratio test
+        let frame_b = vec![corner(10.0, 10.0, weak), corner(10.0, 11.0, weak)];
+        let ratio = MatchOptions { ratio: Some(0.8), ..opts };
+        assert!(match_events(&frame_a[1..], &frame_b, &ratio).is_empty());
+        assert_eq!(match_events(&frame_a[1..], &frame_b[..1], &ratio).len(), 1);
+    }
+}
@@ -0,0 +1,335 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Merging of corners detected more than once for the same feature.
+//!
+//! A moving feature is often detected twice: on the ON surface at its leading edge and
+//! on the OFF surface at its trailing edge, or vice versa, at nearly the same place and
+//! time. `PolarityMerger` sits in front of any `CornerSink` and fuses such pairs into
+//! a single corner. Likewise multi-scale detection (see `pyramid`) finds one feature at
+//! several pyramid levels, and `ScaleMerger` fuses those into a single corner with an
+//! estimated scale. Each corner is held back for the merge window, so output lags the
+//! input by that much event time; call `flush` at the end of the stream.
+
+use std::collections::VecDeque;
+
+use crate::sae_types::*;
+use crate::sink::CornerSink;
+
+
+/// Configuration for `PolarityMerger`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PolarityMergeConfig {
+    /// maximum distance (pixels) between the corners of a pair
+    pub radius: f32,
+    /// maximum time between the corners of a pair
+    pub window: SaeTime,
+}
+
+impl Default for PolarityMergeConfig {
+    fn default() -> Self {
+        PolarityMergeConfig {
+            radius: 2.0,
+            window: 5_000,
+        }
+    }
+}
+
+struct Pending {
+    corner: SaeEvent,
+    merged: bool,
+}
+
+/// Fuses ON/OFF corner pairs before passing corners on to the inner sink, in time order.
+///
+/// A fused corner keeps the pixel, timestamp and polarity of the earlier corner, takes
+/// the confidence-weighted mean of both positions as its sub-pixel position, the
+/// descriptor and orientation of the more confident corner, and the combined
+/// confidence `1 - (1 - a)(1 - b)`.
+pub struct PolarityMerger<S> {
+    config: PolarityMergeConfig,
+    inner: S,
+    pending: VecDeque<Pending>,
+    merged: u64,
+}
+
+impl<S: CornerSink> PolarityMerger<S> {
+    pub fn new(inner: S, config: PolarityMergeConfig) -> Self {
+        PolarityMerger { config, inner, pending: VecDeque::new(), merged: 0 }
+    }
+
+    /// number of pairs fused so far
+    pub fn merged(&self) -> u64 {
+        self.merged
+    }
+
+    /// Pass on every held corner
+    pub fn flush(&mut self) {
+        while let Some(pending) = self.pending.pop_front() {
+            self.inner.accept(&pending.corner);
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Flush, and return the inner sink
+    pub fn into_inner(mut self) -> S {
+        self.flush();
+        self.inner
+    }
+
+    fn fuse(lead: &SaeEvent, trail: &SaeEvent) -> SaeEvent {
+        let (ca, cb) = (lead.confidence.unwrap_or(0.0), trail.confidence.unwrap_or(0.0));
+        let (wa, wb) = if ca + cb > 0.0 { (ca, cb) } else { (1.0, 1.0) };
+        let (arow, acol) = lead.subpixel_position();
+        let (brow, bcol) = trail.subpixel_position();
+        let best = if trail.confidence > lead.confidence { trail } else { lead };
+        SaeEvent {
+            row_f: Some((arow * wa + brow * wb) / (wa + wb)),
+            col_f: Some((acol * wa + bcol * wb) / (wa + wb)),
+            confidence: lead.confidence.or(trail.confidence).map(|_| 1.0 - (1.0 - ca) * (1.0 - cb)),
+            norm_descriptor: best.norm_descriptor.clone(),
+            orientation: best.orientation,
+            corner_kind: best.corner_kind,
+            ..lead.clone()
+        }
+    }
+}
+
+impl<S: CornerSink> CornerSink for PolarityMerger<S> {
+    fn accept(&mut self, corner: &SaeEvent) {
+        let window = self.config.window;
+        while self.pending.front().is_some_and(|pending| corner.timestamp.saturating_sub(pending.corner.timestamp) > window) {
+            let pending = self.pending.pop_front().unwrap();
+            self.inner.accept(&pending.corner);
+        }
+
+        let (row, col) = corner.subpixel_position();
+        let radius2 = self.config.radius * self.config.radius;
+        let partner = self.pending.iter()
+            .enumerate()
+            .filter(|(_, pending)| !pending.merged && pending.corner.polarity != corner.polarity)
+            .map(|(idx, pending)| {
+                let (prow, pcol) = pending.corner.subpixel_position();
+                (idx, (prow - row).powi(2) + (pcol - col).powi(2))
+            })
+            .filter(|&(_, d2)| d2 <= radius2)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        match partner {
+            Some((idx, _)) => {
+                let pending = &mut self.pending[idx];
+                pending.corner = Self::fuse(&pending.corner, corner);
+                pending.merged = true;
+                self.merged += 1;
+            }
+            None => self.pending.push_back(Pending { corner: corner.clone(), merged: false }),
+        }
+    }
+}
+
+/// Configuration for `ScaleMerger`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScaleMergeConfig {
+    /// maximum distance between corners of one feature, in units of the coarser corner's scale
+    pub radius: f32,
+    /// maximum time from the first corner of a feature to the others
+    pub window: SaeTime,
+}
+
+impl Default for ScaleMergeConfig {
+    fn default() -> Self {
+        ScaleMergeConfig {
+            radius: 1.5,
+            window: 5_000,
+        }
+    }
+}
+
+/// The corners of one feature gathered so far
+struct ScaleGroup {
+    /// the first corner, with the position and attributes of the finest one
+    corner: SaeEvent,
+    finest: f32,
+    coarsest: f32,
+    /// pyramid levels present, as bits
+    levels: u32,
+    /// confidence-weighted sum of log2 scales, and the sum of weights
+    log_scale_sum: f32,
+    weight_sum: f32,
+    /// product of (1 - confidence) over the corners
+    miss: f32,
+}
+
+impl ScaleGroup {
+    fn new(corner: &SaeEvent) -> Self {
+        let scale = corner.scale.unwrap_or(1.0);
+        let mut group = ScaleGroup {
+            corner: corner.clone(),
+            finest: scale,
+            coarsest: scale,
+            levels: 0,
+            log_scale_sum: 0.0,
+            weight_sum: 0.0,
+            miss: 1.0,
+        };
+        group.add(corner);
+        group
+    }
+
+    fn level(scale: f32) -> u32 {
+        scale.log2().round().clamp(0.0, 31.0) as u32
+    }
+
+    fn add(&mut self, corner: &SaeEvent) {
+        let scale = corner.scale.unwrap_or(1.0);
+        if scale < self.finest {
+            // the finest scale locates the feature best
+            self.corner = SaeEvent {
+                row: self.corner.row,
+                col: self.corner.col,
+                polarity: self.corner.polarity,
+                timestamp: self.corner.timestamp,
+                ..corner.clone()
+            };
+            self.finest = scale;
+        }
+        self.coarsest = self.coarsest.max(scale);
+        self.levels |= 1 << Self::level(scale);
+        let confidence = corner.confidence.unwrap_or(0.0);
+        let weight = confidence.max(f32::EPSILON);
+        self.log_scale_sum += weight * scale.log2();
+        self.weight_sum += weight;
+        self.miss *= 1.0 - confidence;
+    }
+
+    fn fused(&self) -> SaeEvent {
+        SaeEvent {
+            scale: Some((self.log_scale_sum / self.weight_sum).exp2()),
+            confidence: Some(1.0 - self.miss),
+            ..self.corner.clone()
+        }
+    }
+}
+
+/// Fuses the corners of one feature found at several pyramid levels before passing
+/// corners on to the inner sink, in time order.
+///
+/// Corners at different levels join a feature when they are within the merge radius,
+/// scaled by the coarser of the two scales; a feature holds at most one corner per level.
+/// The fused corner keeps the pixel, timestamp and polarity of the feature's first
+/// corner, takes the sub-pixel position, descriptor and orientation of the finest-scale
+/// corner, the combined confidence `1 - Π(1 - c)`, and as its scale the
+/// confidence-weighted geometric mean of the scales.
+pub struct ScaleMerger<S> {
+    config: ScaleMergeConfig,
+    inner: S,
+    pending: VecDeque<ScaleGroup>,
+    merged: u64,
+}
+
+impl<S: CornerSink> ScaleMerger<S> {
+    pub fn new(inner: S, config: ScaleMergeConfig) -> Self {
+        ScaleMerger { config, inner, pending: VecDeque::new(), merged: 0 }
+    }
+
+    /// number of corners fused into an earlier corner so far
+    pub fn merged(&self) -> u64 {
+        self.merged
+    }
+
+    /// Pass on every held corner
+    pub fn flush(&mut self) {
+        while let Some(group) = self.pending.pop_front() {
+            self.inner.accept(&group.fused());
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Flush, and return the inner sink
+    pub fn into_inner(mut self) -> S {
+        self.flush();
+        self.inner
+    }
+}
+
+impl<S: CornerSink> CornerSink for ScaleMerger<S> {
+    fn accept(&mut self, corner: &SaeEvent) {
+        let window = self.config.window;
+        while self.pending.front().is_some_and(|group| corner.timestamp.saturating_sub(group.corner.timestamp) > window) {
+            let group = self.pending.pop_front().unwrap();
+            self.inner.accept(&group.fused());
+        }
+
+        let (row, col) = corner.subpixel_position();
+        let scale = corner.scale.unwrap_or(1.0);
+        let level = 1 << ScaleGroup::level(scale);
+        let partner = self.pending.iter()
+            .enumerate()
+            .filter(|(_, group)| group.levels & level == 0)
+            .map(|(idx, group)| {
+                let (grow, gcol) = group.corner.subpixel_position();
+                let radius = self.config.radius * group.coarsest.max(scale);
+                (idx, (grow - row).hypot(gcol - col) / radius)
+            })
+            .filter(|&(_, distance)| distance <= 1.0)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        match partner {
+            Some((idx, _)) => {
+                self.pending[idx].add(corner);
+                self.merged += 1;
+            }
+            None => self.pending.push_back(ScaleGroup::new(corner)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corner(row: u16, col: u16, polarity: u8, timestamp: SaeTime, confidence: f32) -> SaeEvent {
+        SaeEvent { row, col, polarity, timestamp, confidence: Some(confidence), ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_merges_polarity_pairs() {
+        let mut merger = PolarityMerger::new(Vec::new(), PolarityMergeConfig::default());
+        merger.accept(&corner(10, 10, 1, 1_000, 0.5));
+        // same polarity: never merged
+        merger.accept(&corner(10, 11, 1, 1_500, 0.5));
+        // the trailing edge of the first corner
+        merger.accept(&corner(11, 10, 0, 2_000, 0.5));
+        // too far away
+        merger.accept(&corner(20, 20, 0, 2_500, 0.5));
+        // too late for the second corner's window
+        merger.accept(&corner(10, 11, 0, 7_000, 0.5));
+        assert_eq!(merger.inner().len(), 2);
+        let corners = merger.into_inner();
+
+        assert_eq!(corners.len(), 4);
+        let fused = &corners[0];
+        assert_eq!((fused.row, fused.col, fused.polarity, fused.timestamp), (10, 10, 1, 1_000));
+        assert_eq!(fused.subpixel_position(), (10.5, 10.0));
+        assert_eq!(fused.confidence, Some(0.75));
+        assert!(corners.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        assert_eq!(corners[3].timestamp, 7_000);
+    }
+
+    #[test]
+    fn test_fused_corner_is_not_merged_again() {
+        let mut merger = PolarityMerger::new(Vec::new(), PolarityMergeConfig::default());
+        merger.accept(&corner(10, 10, 1, 1_000, 0.2));
+        merger.accept(&corner(10, 10, 0, 1_100, 0.8));
+        merger.accept(&corner(10, 10, 0, 1_200, 0.8));
+        assert_eq!(merger.merged(), 1);
+        let corners = merger.into_inner();
+        assert_eq!(corners.len(), 2);
+        assert!((corners[0].confidence.unwrap() - 0.84).abs() < 1e-6);
+    }
+}
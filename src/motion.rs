@@ -0,0 +1,148 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Segmentation of corner tracks into camera ego-motion and independent motion.
+//! Over a recent time window, the displacement of each track is compared with the
+//! dominant image motion: a homography fitted robustly to all track displacements.
+//! For a camera moving through a mostly static scene (or rotating, or viewing a
+//! distant or planar scene) the homography describes the ego-motion, so tracks
+//! that agree with it are labelled ego-motion and the rest are independently moving.
+
+use crate::calib::homography::Homography;
+use crate::sae_types::*;
+use crate::track::{TrackId, TrackStore};
+
+
+/// How a track moved over the window
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MotionLabel {
+    /// consistent with the dominant (camera) motion
+    EgoMotion,
+    /// moving independently of the camera
+    Independent,
+    /// the track doesn't span the window
+    Unknown,
+}
+
+/// Parameters of motion segmentation
+#[derive(Clone, Debug, PartialEq)]
+pub struct MotionSegmentationConfig {
+    /// time window over which track displacements are compared
+    pub window: SaeTime,
+    /// maximum deviation (pixels) from the dominant motion for ego-motion tracks
+    pub inlier_threshold: f64,
+    /// minimum number of tracks spanning the window to estimate the dominant motion
+    pub min_tracks: usize,
+    /// random samples tried when fitting the dominant motion
+    pub iterations: usize,
+    /// seed for sample selection, so that results are repeatable
+    pub seed: u64,
+}
+
+impl Default for MotionSegmentationConfig {
+    fn default() -> Self {
+        MotionSegmentationConfig {
+            window: 20_000,
+            inlier_threshold: 1.5,
+            min_tracks: 8,
+            iterations: 200,
+            seed: 0,
+        }
+    }
+}
+
+/// Result of segmenting the tracks
+#[derive(Clone, Debug)]
+pub struct MotionSegmentation {
+    /// dominant image motion from the start to the end of the window
+    pub dominant: Homography,
+    /// label of every track, in track id order
+    pub labels: Vec<(TrackId, MotionLabel)>,
+}
+
+impl MotionSegmentation {
+    pub fn label(&self, id: TrackId) -> Option<MotionLabel> {
+        self.labels.iter().find(|(track, _)| *track == id).map(|&(_, label)| label)
+    }
+
+    /// the tracks labelled as independently moving
+    pub fn independent(&self) -> Vec<TrackId> {
+        self.labels.iter()
+            .filter(|(_, label)| *label == MotionLabel::Independent)
+            .map(|&(id, _)| id)
+            .collect()
+    }
+}
+
+/// Label the tracks in `store` by their motion over the window ending at `now`.
+/// Returns None if too few tracks span the window to estimate the dominant motion.
+pub fn segment_motion(store: &TrackStore, now: SaeTime, config: &MotionSegmentationConfig) -> Option<MotionSegmentation> {
+    let start = now.checked_sub(config.window)?;
+    let mut ids = Vec::new();
+    let mut from = Vec::new();
+    let mut to = Vec::new();
+    for track in store.iter() {
+        if let (Some(p0), Some(p1)) = (track.position_at(start), track.position_at(now)) {
+            ids.push(track.id);
+            from.push([p0[0] as f64, p0[1] as f64]);
+            to.push([p1[0] as f64, p1[1] as f64]);
+        }
+    }
+    if ids.len() < config.min_tracks.max(4) {
+        return None;
+    }
+    let (dominant, inliers) = Homography::estimate_ransac(&from, &to, config.inlier_threshold, config.iterations, config.seed)?;
+
+    let labels = store.iter()
+        .map(|track| {
+            let label = match ids.iter().position(|&id| id == track.id) {
+                Some(idx) if inliers.contains(&idx) => MotionLabel::EgoMotion,
+                Some(_) => MotionLabel::Independent,
+                None => MotionLabel::Unknown,
+            };
+            (track.id, label)
+        })
+        .collect();
+    Some(MotionSegmentation { dominant, labels })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segment_independent_tracks() {
+        let mut store = TrackStore::new();
+        // the camera pans: the static scene drifts left by 1 pixel per millisecond
+        let mut scene = Vec::new();
+        for k in 0..12 {
+            let (x, y) = (10.0 + (k % 4) as f32 * 25.0, 10.0 + (k / 4) as f32 * 30.0);
+            let id = store.start_track(corner_at(x, y, 0));
+            for step in 1..=30u32 {
+                store.extend_track(id, corner_at(x - step as f32, y, step * 1_000));
+            }
+            scene.push(id);
+        }
+        // an object moving down through the scene
+        let mover = store.start_track(corner_at(50.0, 20.0, 0));
+        for step in 1..=30u32 {
+            store.extend_track(mover, corner_at(50.0 - step as f32, 20.0 + 2.0 * step as f32, step * 1_000));
+        }
+        // a track too recent to span the window
+        let young = store.start_track(corner_at(5.0, 5.0, 25_000));
+        store.extend_track(young, corner_at(4.0, 5.0, 30_000));
+
+        let segmentation = segment_motion(&store, 30_000, &MotionSegmentationConfig::default()).unwrap();
+        assert_eq!(segmentation.independent(), vec![mover]);
+        assert_eq!(segmentation.label(young), Some(MotionLabel::Unknown));
+        for id in scene {
+            assert_eq!(segmentation.label(id), Some(MotionLabel::EgoMotion));
+        }
+        let shifted = segmentation.dominant.apply([40.0, 40.0]);
+        assert!((shifted[0] - 20.0).abs() < 1e-6 && (shifted[1] - 40.0).abs() < 1e-6);
+
+        let config = MotionSegmentationConfig { min_tracks: 20, ..MotionSegmentationConfig::default() };
+        assert!(segment_motion(&store, 30_000, &config).is_none());
+    }
+}
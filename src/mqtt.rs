@@ -0,0 +1,318 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! A corner sink publishing to an MQTT broker, for lightweight monitoring of
+//! deployed sensor nodes.
+//!
+//! The sink publishes a summary of the corner rate once per period of event time,
+//! on `<prefix>/summary`, as a small JSON object:
+//! `{"start":0,"end":1000000,"corners":1234,"rate_hz":1234.0}`.
+//! It can also publish every Nth corner on `<prefix>/corners`, as a
+//! `timestamp,row,col,polarity` line.
+//! Only what a publisher needs of MQTT 3.1.1 is implemented: messages are sent at
+//! QoS 0 (at most once), and delivery is best-effort, like `UdpSink`.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use crate::sae_types::*;
+use crate::sink::CornerSink;
+
+
+/// Configuration for `MqttSink`
+#[derive(Clone, Debug, PartialEq)]
+pub struct MqttConfig {
+    pub client_id: String,
+    /// topics are published under this prefix
+    pub topic_prefix: String,
+    /// seconds of broker inactivity allowed before the connection is considered lost;
+    /// zero disables keep-alive
+    pub keep_alive: u16,
+    /// event time covered by each summary
+    pub summary_period: SaeTime,
+    /// publish every Nth corner, or none if None
+    pub corner_decimation: Option<u32>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            client_id: "arcstar".to_string(),
+            topic_prefix: "arcstar".to_string(),
+            keep_alive: 60,
+            summary_period: 1_000_000,
+            corner_decimation: None,
+        }
+    }
+}
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: u8 = 0xC0;
+const DISCONNECT: u8 = 0xE0;
+
+/// Append an MQTT variable-length "remaining length"
+fn push_length(buf: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        buf.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+fn push_str(buf: &mut Vec<u8>, val: &str) {
+    buf.extend_from_slice(&(val.len() as u16).to_be_bytes());
+    buf.extend_from_slice(val.as_bytes());
+}
+
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = vec![kind];
+    push_length(&mut buf, body.len());
+    buf.extend_from_slice(body);
+    buf
+}
+
+fn connect_packet(config: &MqttConfig) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_str(&mut body, "MQTT");
+    // protocol level 4 (3.1.1), clean session
+    body.push(4);
+    body.push(0x02);
+    body.extend_from_slice(&config.keep_alive.to_be_bytes());
+    push_str(&mut body, &config.client_id);
+    packet(CONNECT, &body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(2 + topic.len() + payload.len());
+    push_str(&mut body, topic);
+    body.extend_from_slice(payload);
+    packet(PUBLISH, &body)
+}
+
+/// Publishes corner-rate summaries, and optionally decimated corners, to an MQTT broker
+pub struct MqttSink<W: Write> {
+    writer: W,
+    config: MqttConfig,
+    summary_topic: String,
+    corners_topic: String,
+    /// start of the current summary period, once a corner has been seen
+    period_start: Option<SaeTime>,
+    period_corners: u64,
+    corners_seen: u64,
+    last_sent: Instant,
+    send_failures: u64,
+}
+
+impl MqttSink<TcpStream> {
+    /// Connect to the broker at `addr`, failing if it refuses the connection
+    pub fn connect<A: ToSocketAddrs>(addr: A, config: MqttConfig) -> io::Result<Self> {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let sink = Self::new(stream.try_clone()?, config)?;
+        let mut ack = [0u8; 4];
+        stream.read_exact(&mut ack)?;
+        if ack[0] != CONNACK || ack[1] != 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected reply from broker"));
+        }
+        if ack[3] != 0 {
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused,
+                                      format!("broker refused connection, code {}", ack[3])));
+        }
+        Ok(sink)
+    }
+}
+
+impl<W: Write> MqttSink<W> {
+    /// Send the connect request on an established stream to the broker.
+    /// The broker's acknowledgement is not awaited.
+    pub fn new(mut writer: W, config: MqttConfig) -> io::Result<Self> {
+        writer.write_all(&connect_packet(&config))?;
+        writer.flush()?;
+        Ok(MqttSink {
+            writer,
+            summary_topic: format!("{}/summary", config.topic_prefix),
+            corners_topic: format!("{}/corners", config.topic_prefix),
+            config,
+            period_start: None,
+            period_corners: 0,
+            corners_seen: 0,
+            last_sent: Instant::now(),
+            send_failures: 0,
+        })
+    }
+
+    /// number of messages that could not be sent
+    pub fn send_failures(&self) -> u64 {
+        self.send_failures
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        if self.writer.write_all(bytes).and_then(|_| self.writer.flush()).is_err() {
+            self.send_failures += 1;
+        }
+        self.last_sent = Instant::now();
+    }
+
+    fn publish_summary(&mut self, start: SaeTime, end: SaeTime, corners: u64) {
+        let span = end.saturating_sub(start).max(1);
+        let rate = corners as f64 * 1e6 / span as f64;
+        let payload = format!("{{\"start\":{},\"end\":{},\"corners\":{},\"rate_hz\":{:.1}}}", start, end, corners, rate);
+        let bytes = publish_packet(&self.summary_topic, payload.as_bytes());
+        self.send(&bytes);
+    }
+
+    /// Publish summaries for the periods that ended before `timestamp`.
+    /// A run of periods without corners is summarized once.
+    fn close_periods(&mut self, timestamp: SaeTime) {
+        let period = self.config.summary_period.max(1);
+        let start = match self.period_start {
+            Some(start) => start,
+            None => {
+                self.period_start = Some(timestamp - timestamp % period);
+                return;
+            }
+        };
+        if timestamp < start.saturating_add(period) {
+            return;
+        }
+        let end = start.saturating_add(period);
+        self.publish_summary(start, end, self.period_corners);
+        let next = timestamp - timestamp % period;
+        if next > end {
+            self.publish_summary(end, next, 0);
+        }
+        self.period_start = Some(next);
+        self.period_corners = 0;
+    }
+
+    /// Keep the connection alive while no corners are being published
+    fn keep_alive(&mut self) {
+        let keep_alive = Duration::from_secs(self.config.keep_alive as u64);
+        if keep_alive > Duration::from_secs(0) && self.last_sent.elapsed() >= keep_alive / 2 {
+            self.send(&[PINGREQ, 0]);
+        }
+    }
+
+    /// Publish the summary of the current, partial, period ending at `end`
+    /// and disconnect from the broker
+    pub fn finish(mut self, end: SaeTime) -> io::Result<W> {
+        if let Some(start) = self.period_start {
+            self.publish_summary(start, end.max(start), self.period_corners);
+        }
+        self.writer.write_all(&[DISCONNECT, 0])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> CornerSink for MqttSink<W> {
+    fn accept(&mut self, corner: &SaeEvent) {
+        self.close_periods(corner.timestamp);
+        self.period_corners += 1;
+        self.corners_seen += 1;
+        match self.config.corner_decimation {
+            Some(n) if self.corners_seen.is_multiple_of(n.max(1) as u64) => {
+                let line = format!("{},{},{},{}", corner.timestamp, corner.row, corner.col, corner.polarity);
+                let bytes = publish_packet(&self.corners_topic, line.as_bytes());
+                self.send(&bytes);
+            }
+            _ => self.keep_alive(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// (topic, payload) of each PUBLISH packet in `bytes`, which starts after CONNECT
+    fn publishes(mut bytes: &[u8]) -> Vec<(String, String)> {
+        let mut found = Vec::new();
+        while !bytes.is_empty() {
+            let (kind, mut len, mut pos) = (bytes[0], 0usize, 1);
+            let mut shift = 0;
+            loop {
+                len |= ((bytes[pos] & 0x7f) as usize) << shift;
+                shift += 7;
+                pos += 1;
+                if bytes[pos - 1] & 0x80 == 0 {
+                    break;
+                }
+            }
+            let body = &bytes[pos..pos + len];
+            if kind == PUBLISH {
+                let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                let topic = String::from_utf8(body[2..2 + topic_len].to_vec()).unwrap();
+                found.push((topic, String::from_utf8(body[2 + topic_len..].to_vec()).unwrap()));
+            }
+            bytes = &bytes[pos + len..];
+        }
+        found
+    }
+
+    fn corner_at(timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row: 1, col: 2, timestamp, ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_summaries_and_decimation() {
+        let config = MqttConfig { corner_decimation: Some(2), keep_alive: 0, ..MqttConfig::default() };
+        let connect = connect_packet(&config);
+        let mut sink = MqttSink::new(Vec::new(), config).unwrap();
+        for &t in [100_000, 200_000, 900_000, 1_500_000, 4_200_000].iter() {
+            sink.accept(&corner_at(t));
+        }
+        let bytes = sink.finish(4_700_000).unwrap();
+        assert_eq!(&bytes[..connect.len()], &connect[..]);
+        assert_eq!(&bytes[bytes.len() - 2..], &[DISCONNECT, 0]);
+
+        let found = publishes(&bytes[connect.len()..]);
+        let summaries: Vec<&str> = found.iter().filter(|(topic, _)| topic == "arcstar/summary").map(|(_, p)| p.as_str()).collect();
+        assert_eq!(summaries, vec![
+            "{\"start\":0,\"end\":1000000,\"corners\":3,\"rate_hz\":3.0}",
+            "{\"start\":1000000,\"end\":2000000,\"corners\":1,\"rate_hz\":1.0}",
+            "{\"start\":2000000,\"end\":4000000,\"corners\":0,\"rate_hz\":0.0}",
+            "{\"start\":4000000,\"end\":4700000,\"corners\":1,\"rate_hz\":1.4}",
+        ]);
+        let corners: Vec<&str> = found.iter().filter(|(topic, _)| topic == "arcstar/corners").map(|(_, p)| p.as_str()).collect();
+        assert_eq!(corners, vec!["200000,1,2,0", "1500000,1,2,0"]);
+    }
+
+    #[test]
+    fn test_connect_to_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8; 2];
+            stream.read_exact(&mut header).unwrap();
+            let mut connect = vec![0u8; header[1] as usize];
+            stream.read_exact(&mut connect).unwrap();
+            stream.write_all(&[CONNACK, 2, 0, 0]).unwrap();
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).unwrap();
+            rest
+        });
+
+        let mut sink = MqttSink::connect(addr, MqttConfig::default()).unwrap();
+        sink.accept(&corner_at(10));
+        assert_eq!(sink.send_failures(), 0);
+        sink.finish(500_000).unwrap();
+        let received = broker.join().unwrap();
+        let found = publishes(&received);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1, "{\"start\":0,\"end\":500000,\"corners\":1,\"rate_hz\":2.0}");
+    }
+}
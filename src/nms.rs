@@ -0,0 +1,238 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Incremental spatiotemporal non-maximum suppression of corners.
+//!
+//! A feature crossing the sensor fires a burst of corners at neighboring pixels within
+//! a short time. `NmsGrid` keeps the first corner of such a burst, and any later one
+//! that is stronger, and suppresses the rest. Recent corners are binned into a grid
+//! of cells no smaller than the suppression radius, each holding a fixed number of
+//! slots, so judging a corner only looks at the slots of the 3x3 cells around it:
+//! constant work per corner however high the corner rate, and memory fixed by the
+//! sensor size. Slots expire by time rather than being swept, and a full cell
+//! overwrites its oldest slot.
+
+use crate::sae_types::*;
+use crate::sink::CornerSink;
+
+
+/// Parameters of non-maximum suppression
+#[derive(Clone, Debug, PartialEq)]
+pub struct NmsConfig {
+    /// corners within this distance (pixels) of a stronger recent corner are suppressed
+    pub radius: f32,
+    /// how long a corner suppresses weaker ones after it
+    pub window: SaeTime,
+    /// recent corners remembered per grid cell
+    pub slots_per_cell: usize,
+    /// largest scale of the radius allowed by `NmsGrid::admit_scaled`; cells are sized for it
+    pub max_scale: f32,
+}
+
+impl Default for NmsConfig {
+    fn default() -> Self {
+        NmsConfig {
+            radius: 2.0,
+            window: 5_000,
+            slots_per_cell: 2,
+            max_scale: 1.0,
+        }
+    }
+}
+
+/// A corner remembered in the grid
+#[derive(Clone, Copy, Debug)]
+struct Slot {
+    timestamp: SaeTime,
+    confidence: f32,
+    row: f32,
+    col: f32,
+}
+
+/// Suppresses corners dominated by a stronger, or equally strong earlier, corner
+/// nearby in space and time. Corners are judged as they arrive, in time order:
+/// a corner already passed is not recalled when a stronger one follows it.
+pub struct NmsGrid {
+    config: NmsConfig,
+    /// side length of the square cells, in pixels
+    cell: usize,
+    cell_rows: usize,
+    cell_cols: usize,
+    slots: Vec<Option<Slot>>,
+    kept: u64,
+    suppressed: u64,
+}
+
+impl NmsGrid {
+    /// A grid covering a sensor of `nrows` by `ncols` pixels
+    pub fn new(nrows: usize, ncols: usize, config: NmsConfig) -> Self {
+        let cell = ((config.radius * config.max_scale.max(1.0)).ceil() as usize).max(1);
+        let cell_rows = nrows.div_ceil(cell).max(1);
+        let cell_cols = ncols.div_ceil(cell).max(1);
+        let slots = vec![None; cell_rows * cell_cols * config.slots_per_cell.max(1)];
+        NmsGrid { config, cell, cell_rows, cell_cols, slots, kept: 0, suppressed: 0 }
+    }
+
+    pub fn config(&self) -> &NmsConfig {
+        &self.config
+    }
+
+    /// number of corners kept so far
+    pub fn kept(&self) -> u64 {
+        self.kept
+    }
+
+    /// number of corners suppressed so far
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed
+    }
+
+    /// number of corners the grid can remember, fixed at creation
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Forget every remembered corner, eg after a gap in the stream
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+    }
+
+    fn cell_slots(&self, cell_row: usize, cell_col: usize) -> std::ops::Range<usize> {
+        let per_cell = self.config.slots_per_cell.max(1);
+        let start = (cell_row * self.cell_cols + cell_col) * per_cell;
+        start..start + per_cell
+    }
+
+    fn is_live(&self, slot: &Slot, now: SaeTime) -> bool {
+        slot.timestamp.is_within(now, self.config.window)
+    }
+
+    /// Judge a corner: true if it survives suppression, in which case it is remembered
+    pub fn admit(&mut self, corner: &SaeEvent) -> bool {
+        self.admit_scaled(corner, 1.0)
+    }
+
+    /// Like `admit`, with the suppression radius scaled for this corner, eg by the
+    /// local speed (see `speed::SpeedSurface`). The scale is capped at `max_scale`.
+    pub fn admit_scaled(&mut self, corner: &SaeEvent, scale: f32) -> bool {
+        let (row, col) = corner.subpixel_position();
+        let cell_row = (corner.row as usize / self.cell).min(self.cell_rows - 1);
+        let cell_col = (corner.col as usize / self.cell).min(self.cell_cols - 1);
+        let radius = self.config.radius * scale.clamp(0.0, self.config.max_scale.max(1.0));
+        let radius2 = radius * radius;
+        let now = corner.timestamp;
+        let confidence = corner.confidence.unwrap_or(0.0);
+
+        for nrow in cell_row.saturating_sub(1)..=(cell_row + 1).min(self.cell_rows - 1) {
+            for ncol in cell_col.saturating_sub(1)..=(cell_col + 1).min(self.cell_cols - 1) {
+                let dominated = self.slots[self.cell_slots(nrow, ncol)].iter()
+                    .flatten()
+                    .any(|slot| self.is_live(slot, now) && slot.confidence >= confidence &&
+                        (slot.row - row).powi(2) + (slot.col - col).powi(2) <= radius2);
+                if dominated {
+                    self.suppressed += 1;
+                    return false;
+                }
+            }
+        }
+
+        // take a free or expired slot, or else the oldest
+        let range = self.cell_slots(cell_row, cell_col);
+        let window = self.config.window;
+        let target = self.slots[range.clone()].iter()
+            .enumerate()
+            .min_by_key(|(_, slot)| match slot {
+                Some(slot) if slot.timestamp.is_within(now, window) => (1, slot.timestamp),
+                _ => (0, 0),
+            })
+            .map(|(idx, _)| range.start + idx)
+            .unwrap();
+        self.slots[target] = Some(Slot { timestamp: now, confidence, row, col });
+        self.kept += 1;
+        true
+    }
+}
+
+/// Passes on only the corners that survive non-maximum suppression
+pub struct NmsSink<S> {
+    grid: NmsGrid,
+    inner: S,
+}
+
+impl<S: CornerSink> NmsSink<S> {
+    pub fn new(inner: S, grid: NmsGrid) -> Self {
+        NmsSink { grid, inner }
+    }
+
+    pub fn grid(&self) -> &NmsGrid {
+        &self.grid
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: CornerSink> CornerSink for NmsSink<S> {
+    fn accept(&mut self, corner: &SaeEvent) {
+        if self.grid.admit(corner) {
+            self.inner.accept(corner);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corner(row: u16, col: u16, timestamp: SaeTime, confidence: f32) -> SaeEvent {
+        SaeEvent { row, col, timestamp, confidence: Some(confidence), ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_suppression() {
+        let mut sink = NmsSink::new(Vec::new(), NmsGrid::new(40, 40, NmsConfig::default()));
+        let corners = [
+            corner(10, 10, 100, 0.8),
+            // weaker, or as strong but later, and nearby: suppressed
+            corner(11, 10, 200, 0.5),
+            corner(10, 11, 300, 0.8),
+            // stronger: kept
+            corner(11, 11, 400, 0.9),
+            // too far away
+            corner(10, 14, 500, 0.1),
+            // across a cell boundary, but within the radius of the strongest corner
+            corner(12, 12, 600, 0.85),
+            // after the window
+            corner(10, 11, 6_000, 0.2),
+        ];
+        for corner in corners.iter() {
+            sink.accept(corner);
+        }
+        let kept: Vec<SaeTime> = sink.inner().iter().map(|corner| corner.timestamp).collect();
+        assert_eq!(kept, vec![100, 400, 500, 6_000]);
+        assert_eq!(sink.grid().suppressed(), 3);
+        assert_eq!(sink.grid().kept(), 4);
+    }
+
+    #[test]
+    fn test_memory_is_bounded() {
+        let mut grid = NmsGrid::new(20, 30, NmsConfig::default());
+        let capacity = grid.capacity();
+        assert_eq!(capacity, 10 * 15 * 2);
+        for step in 0..10_000u32 {
+            grid.admit(&corner((step % 20) as u16, (step % 30) as u16, step * 10, (step % 7) as f32 / 7.0));
+        }
+        assert_eq!(grid.capacity(), capacity);
+        assert_eq!(grid.kept() + grid.suppressed(), 10_000);
+        assert!(grid.suppressed() > 0);
+
+        grid.clear();
+        assert!(grid.admit(&corner(0, 0, 100_000, 0.0)));
+    }
+}
@@ -0,0 +1,349 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Filters for sensor noise (background activity) in the event stream.
+//!
+//! Shot noise fires isolated pixels, which `BackgroundActivityFilter` and the
+//! lighter `RowColumnDenoiser` reject for lack of recent neighbors. Hot pixels fire
+//! steadily whatever the scene, often supported by each other's noise, so
+//! `HotPixelFilter` finds them by their rate and masks them. Each is an `EventFilter`:
+//! a stage of a `pipeline::Pipeline` (`add_filter`), or an iterator adapter over an
+//! event stream through `filter::filter_events`.
+
+use nalgebra::DMatrix;
+
+use crate::filter::EventFilter;
+use crate::sae_types::*;
+
+
+/// The most recent event seen in one row or column
+#[derive(Clone, Copy)]
+struct LineCell {
+    timestamp: SaeTime,
+    /// position of the event along the line (col for a row cell, row for a column cell)
+    pos: u16,
+    polarity: u8,
+}
+
+/// Constant-memory spatiotemporal noise filter after Khodamoradi & Etienne-Cummings,
+/// "O(N)-Space Spatiotemporal Filter for Reducing Noise in Neuromorphic Vision Sensors" (2018).
+///
+/// Rather than a per-pixel timestamp map, only the most recent event of each row and
+/// each column is stored: O(rows + cols) memory, suited to embedded targets where
+/// per-pixel neighbor lookups are too expensive.
+/// An event is kept if a recent event in an adjacent (or the same) column lies within
+/// one row of it, or a recent event in an adjacent row lies within one column of it.
+pub struct RowColumnDenoiser {
+    /// support window: neighboring events older than this don't count
+    window: SaeTime,
+    /// whether supporting events must have the same polarity
+    match_polarity: bool,
+    rows: Vec<Option<LineCell>>,
+    cols: Vec<Option<LineCell>>,
+    rejected: u64,
+}
+
+impl RowColumnDenoiser {
+    pub fn new(nrows: usize, ncols: usize, window: SaeTime) -> Self {
+        RowColumnDenoiser {
+            window,
+            match_polarity: false,
+            rows: vec![None; nrows],
+            cols: vec![None; ncols],
+            rejected: 0,
+        }
+    }
+
+    /// require supporting events to share the polarity of the filtered event
+    pub fn with_matching_polarity(mut self, match_polarity: bool) -> Self {
+        self.match_polarity = match_polarity;
+        self
+    }
+
+    /// number of events rejected as noise so far
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    fn supports(&self, cell: &Option<LineCell>, evt: &SaeEvent, pos: u16) -> bool {
+        match cell {
+            Some(cell) => {
+                let dpos = cell.pos.max(pos) - cell.pos.min(pos);
+                dpos <= 1 &&
+                    cell.timestamp.is_within(evt.timestamp, self.window) &&
+                    (!self.match_polarity || cell.polarity == evt.polarity)
+            }
+            None => false,
+        }
+    }
+
+    fn has_support(lines: &[Option<LineCell>], idx: usize, check: impl Fn(&Option<LineCell>) -> bool) -> bool {
+        let lo = idx.saturating_sub(1);
+        let hi = (idx + 1).min(lines.len() - 1);
+        lines[lo..=hi].iter().any(check)
+    }
+}
+
+impl EventFilter for RowColumnDenoiser {
+    fn accept(&mut self, evt: &SaeEvent) -> bool {
+        let row = evt.row as usize;
+        let col = evt.col as usize;
+        if row >= self.rows.len() || col >= self.cols.len() {
+            return true;
+        }
+
+        let supported =
+            Self::has_support(&self.cols, col, |cell| self.supports(cell, evt, evt.row)) ||
+            Self::has_support(&self.rows, row, |cell| self.supports(cell, evt, evt.col));
+
+        self.cols[col] = Some(LineCell { timestamp: evt.timestamp, pos: evt.row, polarity: evt.polarity });
+        self.rows[row] = Some(LineCell { timestamp: evt.timestamp, pos: evt.col, polarity: evt.polarity });
+
+        if !supported {
+            self.rejected += 1;
+        }
+        supported
+    }
+}
+
+/// The background activity filter of Delbruck, "Frame-free dynamic digital vision" (2008):
+/// an event is kept if one of the eight neighbors of its pixel fired within the window.
+/// Each event stamps its neighbors, so the check is a single lookup.
+pub struct BackgroundActivityFilter {
+    window: SaeTime,
+    /// latest time each pixel's neighborhood fired
+    support: SaeMatrix,
+    supported: SaeOccupancy,
+    rejected: u64,
+}
+
+impl BackgroundActivityFilter {
+    pub fn new(nrows: usize, ncols: usize, window: SaeTime) -> Self {
+        BackgroundActivityFilter {
+            window,
+            support: SaeMatrix::zeros(nrows, ncols),
+            supported: SaeOccupancy::from_element(nrows, ncols, false),
+            rejected: 0,
+        }
+    }
+
+    /// number of events rejected as noise so far
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+}
+
+impl EventFilter for BackgroundActivityFilter {
+    fn accept(&mut self, evt: &SaeEvent) -> bool {
+        let (row, col) = (evt.row as usize, evt.col as usize);
+        let (nrows, ncols) = self.support.shape();
+        if row >= nrows || col >= ncols {
+            return true;
+        }
+        let supported = self.supported[(row, col)] && self.support[(row, col)].is_within(evt.timestamp, self.window);
+        for nrow in row.saturating_sub(1)..(row + 2).min(nrows) {
+            for ncol in col.saturating_sub(1)..(col + 2).min(ncols) {
+                if (nrow, ncol) != (row, col) {
+                    self.support[(nrow, ncol)] = evt.timestamp;
+                    self.supported[(nrow, ncol)] = true;
+                }
+            }
+        }
+        if !supported {
+            self.rejected += 1;
+        }
+        supported
+    }
+}
+
+/// Parameters of hot pixel detection
+#[derive(Clone, Debug, PartialEq)]
+pub struct HotPixelConfig {
+    /// length of each counting window
+    pub window: SaeTime,
+    /// a pixel firing more than this many times the mean rate in a window is hot
+    pub factor: f32,
+    /// ... as long as it fired at least this many events in the window
+    pub min_events: u32,
+}
+
+impl Default for HotPixelConfig {
+    fn default() -> Self {
+        HotPixelConfig {
+            window: 1_000_000,
+            factor: 20.0,
+            min_events: 50,
+        }
+    }
+}
+
+/// Learns a mask of hot pixels online and rejects their events.
+/// Events are counted per pixel over consecutive windows; at the end of each window
+/// the pixels that fired far more than the mean are added to the mask, where they
+/// stay until `clear_mask`. Events of a pixel are rejected only once it is masked.
+pub struct HotPixelFilter {
+    config: HotPixelConfig,
+    counts: DMatrix<u32>,
+    /// total count of the current window
+    total: u64,
+    window_start: Option<SaeTime>,
+    mask: SaeOccupancy,
+    hot: Vec<(u16, u16)>,
+    rejected: u64,
+}
+
+impl HotPixelFilter {
+    pub fn new(nrows: usize, ncols: usize, config: HotPixelConfig) -> Self {
+        HotPixelFilter {
+            config,
+            counts: DMatrix::zeros(nrows, ncols),
+            total: 0,
+            window_start: None,
+            mask: SaeOccupancy::from_element(nrows, ncols, false),
+            hot: Vec::new(),
+            rejected: 0,
+        }
+    }
+
+    pub fn config(&self) -> &HotPixelConfig {
+        &self.config
+    }
+
+    /// pixels masked as hot, as (row, col), in the order found
+    pub fn hot_pixels(&self) -> &[(u16, u16)] {
+        &self.hot
+    }
+
+    /// the hot pixel mask, eg for `detector::DetectorConfig::dead_pixels`
+    pub fn mask(&self) -> &SaeOccupancy {
+        &self.mask
+    }
+
+    /// number of events of masked pixels rejected so far
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// Unmask every pixel, eg after the sensor biases changed
+    pub fn clear_mask(&mut self) {
+        self.mask.fill(false);
+        self.hot.clear();
+    }
+
+    /// Mask the pixels that were hot in the window just ended, and start a new one
+    fn end_window(&mut self) {
+        let mean = self.total as f32 / self.counts.len().max(1) as f32;
+        let limit = (mean * self.config.factor).max(self.config.min_events as f32);
+        for (idx, count) in self.counts.iter().enumerate() {
+            // column-major storage
+            let (row, col) = (idx % self.counts.nrows(), idx / self.counts.nrows());
+            if *count as f32 > limit && !self.mask[(row, col)] {
+                self.mask[(row, col)] = true;
+                self.hot.push((row as u16, col as u16));
+            }
+        }
+        self.counts.fill(0);
+        self.total = 0;
+    }
+}
+
+impl EventFilter for HotPixelFilter {
+    fn accept(&mut self, evt: &SaeEvent) -> bool {
+        let (row, col) = (evt.row as usize, evt.col as usize);
+        let (nrows, ncols) = self.counts.shape();
+        if row >= nrows || col >= ncols {
+            return true;
+        }
+        let start = *self.window_start.get_or_insert(evt.timestamp);
+        if evt.timestamp.elapsed_since(start) >= self.config.window {
+            self.end_window();
+            self.window_start = Some(evt.timestamp);
+        }
+        self.counts[(row, col)] += 1;
+        self.total += 1;
+        if self.mask[(row, col)] {
+            self.rejected += 1;
+            return false;
+        }
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_at(row: u16, col: u16, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row, col, timestamp, ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_isolated_events_rejected() {
+        let mut filter = RowColumnDenoiser::new(20, 20, 1_000);
+        assert!(!filter.accept(&event_at(2, 2, 100)));
+        assert!(!filter.accept(&event_at(15, 9, 200)));
+        // neighbor, but too old
+        assert!(!filter.accept(&event_at(3, 3, 5_000)));
+        assert_eq!(filter.rejected(), 3);
+    }
+
+    #[test]
+    fn test_supported_events_kept() {
+        let mut filter = RowColumnDenoiser::new(20, 20, 1_000);
+        // a short vertical edge: each event is supported by its predecessor
+        assert!(!filter.accept(&event_at(5, 5, 100)));
+        assert!(filter.accept(&event_at(6, 5, 150)));
+        assert!(filter.accept(&event_at(7, 6, 200)));
+        // a horizontal neighbor supported via the row cell
+        assert!(filter.accept(&event_at(7, 7, 250)));
+        // edges of the sensor
+        assert!(!filter.accept(&event_at(0, 19, 300)));
+        assert!(filter.accept(&event_at(1, 19, 320)));
+        assert_eq!(filter.rejected(), 2);
+    }
+
+    #[test]
+    fn test_matching_polarity() {
+        let mut filter = RowColumnDenoiser::new(10, 10, 1_000).with_matching_polarity(true);
+        filter.accept(&event_at(4, 4, 100));
+        let off = SaeEvent { polarity: 1, ..event_at(5, 4, 150) };
+        assert!(!filter.accept(&off));
+        let off_neighbor = SaeEvent { polarity: 1, ..event_at(5, 5, 180) };
+        assert!(filter.accept(&off_neighbor));
+    }
+
+    #[test]
+    fn test_background_activity_filter() {
+        let mut filter = BackgroundActivityFilter::new(20, 20, 1_000);
+        assert!(!filter.accept(&event_at(5, 5, 100)));
+        assert!(filter.accept(&event_at(6, 6, 150)));
+        // the same pixel again doesn't support itself
+        assert!(!filter.accept(&event_at(12, 12, 200)));
+        assert!(!filter.accept(&event_at(12, 12, 250)));
+        // too old a neighbor
+        assert!(!filter.accept(&event_at(5, 6, 5_000)));
+        assert_eq!(filter.rejected(), 4);
+    }
+
+    #[test]
+    fn test_hot_pixel_filter_as_iterator_adapter() {
+        use crate::filter::filter_events;
+
+        let config = HotPixelConfig { window: 10_000, factor: 20.0, min_events: 5 };
+        let mut filter = HotPixelFilter::new(10, 10, config);
+        // one event per pixel and a pixel firing every 100 us, over three windows
+        let mut events: Vec<SaeEvent> = (0..100u32).map(|i| event_at((i / 10) as u16, (i % 10) as u16, i * 300)).collect();
+        events.extend((0..300u32).map(|i| event_at(3, 7, i * 100)));
+        events.sort_by_key(|evt| evt.timestamp);
+        let kept: Vec<SaeEvent> = filter_events(events, &mut filter).collect();
+        assert_eq!(filter.hot_pixels(), &[(3, 7)]);
+        assert!(filter.mask()[(3, 7)]);
+        // masked after the first window
+        let hot_kept = kept.iter().filter(|evt| (evt.row, evt.col) == (3, 7)).count();
+        assert!(hot_kept > 90 && hot_kept < 110, "{}", hot_kept);
+        assert_eq!(kept.len() + filter.rejected() as usize, 400);
+        filter.clear_mask();
+        assert!(filter.hot_pixels().is_empty());
+    }
+}
@@ -0,0 +1,288 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! A lightweight tracker for moving objects, built on clusters of corners.
+//! Corners gathered between updates are grouped by proximity; each group with
+//! enough corners gives a bounding box. Tracked objects are moved forward by
+//! their estimated velocity, matched to the boxes by overlap, and their boxes
+//! and velocities updated from the matches. Unmatched boxes start new objects,
+//! and objects that go unmatched for too long are dropped.
+
+use crate::sae_types::*;
+
+
+/// Axis-aligned box in image coordinates (x = column, y = row), in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl BoundingBox {
+    pub fn center(&self) -> [f32; 2] {
+        [(self.min[0] + self.max[0]) / 2.0, (self.min[1] + self.max[1]) / 2.0]
+    }
+
+    pub fn area(&self) -> f32 {
+        (self.max[0] - self.min[0]).max(0.0) * (self.max[1] - self.min[1]).max(0.0)
+    }
+
+    /// the box moved by `offset`
+    pub fn translated(&self, offset: [f32; 2]) -> Self {
+        BoundingBox {
+            min: [self.min[0] + offset[0], self.min[1] + offset[1]],
+            max: [self.max[0] + offset[0], self.max[1] + offset[1]],
+        }
+    }
+
+    /// the box grown by `margin` on every side
+    pub fn inflated(&self, margin: f32) -> Self {
+        BoundingBox {
+            min: [self.min[0] - margin, self.min[1] - margin],
+            max: [self.max[0] + margin, self.max[1] + margin],
+        }
+    }
+
+    /// intersection over union
+    pub fn iou(&self, other: &BoundingBox) -> f32 {
+        let inter = BoundingBox {
+            min: [self.min[0].max(other.min[0]), self.min[1].max(other.min[1])],
+            max: [self.max[0].min(other.max[0]), self.max[1].min(other.max[1])],
+        };
+        let inter_area = inter.area();
+        let union = self.area() + other.area() - inter_area;
+        if union > 0.0 { inter_area / union } else { 0.0 }
+    }
+}
+
+/// Parameters of the object tracker
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectTrackerConfig {
+    /// corners closer than this (pixels) join the same cluster
+    pub cluster_radius: f32,
+    /// minimum corners in a cluster for it to be an object
+    pub min_corners: usize,
+    /// minimum overlap between a predicted object box and a cluster box for them to match
+    pub min_iou: f32,
+    /// weight of the newest velocity measurement, in (0, 1]
+    pub velocity_smoothing: f32,
+    /// objects unmatched for longer than this are dropped
+    pub max_age: SaeTime,
+}
+
+impl Default for ObjectTrackerConfig {
+    fn default() -> Self {
+        ObjectTrackerConfig {
+            cluster_radius: 4.0,
+            min_corners: 4,
+            min_iou: 0.1,
+            velocity_smoothing: 0.5,
+            max_age: 50_000,
+        }
+    }
+}
+
+pub type ObjectId = u32;
+
+/// An object followed over time
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackedObject {
+    pub id: ObjectId,
+    pub bbox: BoundingBox,
+    /// estimated velocity of the box center, in pixels per second
+    pub velocity: [f32; 2],
+    /// time of the latest matching cluster
+    pub last_seen: SaeTime,
+    /// number of updates in which the object was matched
+    pub hits: u32,
+}
+
+impl TrackedObject {
+    /// the box expected at time `now`, assuming constant velocity
+    pub fn predicted_bbox(&self, now: SaeTime) -> BoundingBox {
+        let dt = now.saturating_sub(self.last_seen) as f32 * 1e-6;
+        self.bbox.translated([self.velocity[0] * dt, self.velocity[1] * dt])
+    }
+}
+
+/// Tracks clusters of corners as objects with bounding boxes
+pub struct ObjectTracker {
+    config: ObjectTrackerConfig,
+    pending: Vec<[f32; 2]>,
+    objects: Vec<TrackedObject>,
+    next_id: ObjectId,
+}
+
+impl ObjectTracker {
+    pub fn new(config: ObjectTrackerConfig) -> Self {
+        ObjectTracker {
+            config,
+            pending: Vec::new(),
+            objects: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn objects(&self) -> &[TrackedObject] {
+        &self.objects
+    }
+
+    /// Add a corner, to be clustered at the next update
+    pub fn add_corner(&mut self, corner: &SaeEvent) {
+        let (row, col) = corner.subpixel_position();
+        self.pending.push([col, row]);
+    }
+
+    /// Bounding boxes of clusters of the pending corners
+    fn cluster_boxes(&self) -> Vec<BoundingBox> {
+        let n = self.pending.len();
+        let mut parent: Vec<usize> = (0..n).collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        let radius2 = self.config.cluster_radius * self.config.cluster_radius;
+        for i in 0..n {
+            for j in (i + 1)..n {
+                let (a, b) = (self.pending[i], self.pending[j]);
+                if (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) <= radius2 {
+                    let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+                    parent[ri] = rj;
+                }
+            }
+        }
+        let mut clusters: Vec<(usize, BoundingBox, usize)> = Vec::new();
+        for i in 0..n {
+            let r = root(&mut parent, i);
+            let p = self.pending[i];
+            match clusters.iter_mut().find(|(cr, _, _)| *cr == r) {
+                Some((_, bbox, count)) => {
+                    bbox.min = [bbox.min[0].min(p[0]), bbox.min[1].min(p[1])];
+                    bbox.max = [bbox.max[0].max(p[0]), bbox.max[1].max(p[1])];
+                    *count += 1;
+                }
+                None => clusters.push((r, BoundingBox { min: p, max: p }, 1)),
+            }
+        }
+        clusters.into_iter()
+            .filter(|&(_, _, count)| count >= self.config.min_corners)
+            .map(|(_, bbox, _)| bbox)
+            .collect()
+    }
+
+    /// Cluster the corners added since the last update and update the tracked objects
+    pub fn update(&mut self, now: SaeTime) -> &[TrackedObject] {
+        let boxes = self.cluster_boxes();
+        self.pending.clear();
+
+        // candidate matches, best overlap first; boxes are inflated by the cluster
+        // radius so that small or fast-moving objects still overlap their prediction
+        let margin = self.config.cluster_radius;
+        let mut candidates: Vec<(f32, usize, usize)> = Vec::new();
+        for (oi, obj) in self.objects.iter().enumerate() {
+            let predicted = obj.predicted_bbox(now).inflated(margin);
+            for (bi, bbox) in boxes.iter().enumerate() {
+                let iou = predicted.iou(&bbox.inflated(margin));
+                if iou >= self.config.min_iou {
+                    candidates.push((iou, oi, bi));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+
+        let mut object_matched = vec![false; self.objects.len()];
+        let mut box_matched = vec![false; boxes.len()];
+        let alpha = self.config.velocity_smoothing;
+        for (_, oi, bi) in candidates {
+            if object_matched[oi] || box_matched[bi] {
+                continue;
+            }
+            object_matched[oi] = true;
+            box_matched[bi] = true;
+            let obj = &mut self.objects[oi];
+            let dt = now.saturating_sub(obj.last_seen) as f32 * 1e-6;
+            if dt > 0.0 {
+                let (old, new) = (obj.bbox.center(), boxes[bi].center());
+                let measured = [(new[0] - old[0]) / dt, (new[1] - old[1]) / dt];
+                // the first measurement replaces the initial zero velocity
+                let weight = if obj.hits == 1 { 1.0 } else { alpha };
+                obj.velocity = [
+                    obj.velocity[0] + weight * (measured[0] - obj.velocity[0]),
+                    obj.velocity[1] + weight * (measured[1] - obj.velocity[1]),
+                ];
+            }
+            obj.bbox = boxes[bi];
+            obj.last_seen = now;
+            obj.hits += 1;
+        }
+
+        let max_age = self.config.max_age;
+        self.objects.retain(|obj| now.saturating_sub(obj.last_seen) <= max_age);
+        for (bi, bbox) in boxes.into_iter().enumerate() {
+            if !box_matched[bi] {
+                self.objects.push(TrackedObject {
+                    id: self.next_id,
+                    bbox,
+                    velocity: [0.0, 0.0],
+                    last_seen: now,
+                    hits: 1,
+                });
+                self.next_id += 1;
+            }
+        }
+        &self.objects
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// corners on the outline of a 6x6 square with top-left at (x, y)
+    fn add_square(tracker: &mut ObjectTracker, x: f32, y: f32, timestamp: SaeTime) {
+        for &(dx, dy) in [(0.0, 0.0), (3.0, 0.0), (6.0, 0.0), (6.0, 3.0), (6.0, 6.0), (3.0, 6.0), (0.0, 6.0), (0.0, 3.0)].iter() {
+            tracker.add_corner(&SaeEvent {
+                row_f: Some(y + dy),
+                col_f: Some(x + dx),
+                timestamp,
+                ..SaeEvent::default()
+            });
+        }
+    }
+
+    #[test]
+    fn test_track_two_objects() {
+        let mut tracker = ObjectTracker::new(ObjectTrackerConfig::default());
+        // two objects moving in opposite directions at 1 pixel per millisecond
+        for step in 0..10u32 {
+            let now = step * 1_000;
+            let offset = step as f32;
+            add_square(&mut tracker, 10.0 + offset, 10.0, now);
+            add_square(&mut tracker, 60.0 - offset, 40.0, now);
+            // a stray corner, too isolated to be an object
+            tracker.add_corner(&SaeEvent { row_f: Some(80.0), col_f: Some(5.0 * offset), ..SaeEvent::default() });
+            tracker.update(now);
+        }
+        let objects = tracker.objects();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0].id, 0);
+        assert_eq!(objects[0].hits, 10);
+        assert_eq!(objects[0].bbox, BoundingBox { min: [19.0, 10.0], max: [25.0, 16.0] });
+        assert!((objects[0].velocity[0] - 1_000.0).abs() < 1.0);
+        assert!((objects[1].velocity[0] + 1_000.0).abs() < 1.0);
+        assert!(objects[1].velocity[1].abs() < 1e-3);
+
+        let predicted = objects[0].predicted_bbox(11_000);
+        assert!((predicted.min[0] - 21.0).abs() < 1e-3);
+
+        // objects unseen for longer than the maximum age are dropped
+        tracker.update(40_000);
+        assert_eq!(tracker.objects().len(), 2);
+        tracker.update(70_000);
+        assert!(tracker.objects().is_empty());
+    }
+}
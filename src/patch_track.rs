@@ -0,0 +1,256 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Asynchronous patch tracking on decayed time surfaces, in the spirit of EKLT and HASTE.
+//!
+//! Rather than waiting for a corner to be re-detected, each feature keeps a small
+//! patch of the exponentially decayed time surface around it. At every update the
+//! patch is aligned with the current surface by Lucas-Kanade, giving a sub-pixel
+//! position that moves continuously between corner detections. The patch is then
+//! refreshed, so features are followed from update to update.
+
+use std::collections::BTreeMap;
+
+use nalgebra::DMatrix;
+
+use crate::eval::klt::{track_point, KltParams};
+use crate::sae_types::*;
+use crate::track::{TrackId, TrackStore};
+
+
+/// Parameters of the patch tracker
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatchTrackerConfig {
+    /// time constant of the surface decay
+    pub decay: SaeTime,
+    /// largest displacement (pixels) a feature may make between two updates
+    pub max_displacement: usize,
+    /// Lucas-Kanade window and convergence parameters
+    pub klt: KltParams,
+}
+
+impl Default for PatchTrackerConfig {
+    fn default() -> Self {
+        PatchTrackerConfig {
+            decay: 10_000,
+            max_displacement: 4,
+            klt: KltParams {
+                half_window: 5,
+                ..KltParams::default()
+            },
+        }
+    }
+}
+
+/// Decayed time surface value of a pixel last updated at `timestamp`:
+/// 1 for an event at `now`, falling towards 0 for older events, and 0 where no event was seen
+pub fn decayed_value(timestamp: SaeTime, now: SaeTime, decay: SaeTime) -> f32 {
+    if timestamp == 0 {
+        return 0.0;
+    }
+    let age = now.saturating_sub(timestamp) as f32;
+    (-age / decay.max(1) as f32).exp()
+}
+
+/// The decayed time surface over a `size` x `size` square with top-left pixel (`row0`, `col0`),
+/// which may extend beyond the surface; pixels outside it are 0
+pub fn decayed_patch(sae: &SaeMatrix, now: SaeTime, decay: SaeTime, row0: i32, col0: i32, size: usize) -> DMatrix<f32> {
+    let (nrows, ncols) = sae.shape();
+    DMatrix::from_fn(size, size, |r, c| {
+        let row = row0 + r as i32;
+        let col = col0 + c as i32;
+        if row < 0 || col < 0 || row as usize >= nrows || col as usize >= ncols {
+            return 0.0;
+        }
+        decayed_value(sae[(row as usize, col as usize)], now, decay)
+    })
+}
+
+/// A feature being followed
+#[derive(Clone, Debug)]
+struct Feature {
+    /// position (x = col, y = row)
+    position: [f32; 2],
+    /// top-left pixel (row, col) of the patch
+    origin: (i32, i32),
+    patch: DMatrix<f32>,
+}
+
+/// Results of one tracker update
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PatchUpdate {
+    /// features whose tracks were extended with a refined position
+    pub tracked: Vec<TrackId>,
+    /// features that could no longer be aligned and were dropped; their tracks remain in the store
+    pub lost: Vec<TrackId>,
+}
+
+/// Follows features by aligning time-surface patches
+pub struct PatchTracker {
+    config: PatchTrackerConfig,
+    sae: SaeMatrix,
+    features: BTreeMap<TrackId, Feature>,
+    store: TrackStore,
+}
+
+impl PatchTracker {
+    pub fn new(nrows: usize, ncols: usize, config: PatchTrackerConfig) -> Self {
+        PatchTracker {
+            config,
+            sae: SaeMatrix::zeros(nrows, ncols),
+            features: BTreeMap::new(),
+            store: TrackStore::new(),
+        }
+    }
+
+    /// the tracks of all features, including lost ones
+    pub fn store(&self) -> &TrackStore {
+        &self.store
+    }
+
+    /// positions (x = col, y = row) of the features currently followed
+    pub fn features(&self) -> impl Iterator<Item = (TrackId, [f32; 2])> + '_ {
+        self.features.iter().map(|(&id, feature)| (id, feature.position))
+    }
+
+    /// Record an event, of either polarity, in the time surface
+    pub fn add_event(&mut self, evt: &SaeEvent) {
+        let (nrows, ncols) = self.sae.shape();
+        let (row, col) = (evt.row as usize, evt.col as usize);
+        if row < nrows && col < ncols {
+            self.sae[(row, col)] = evt.timestamp;
+        }
+    }
+
+    /// patch side length: the LK window, plus room to move, plus a border for gradients
+    fn patch_size(&self) -> usize {
+        2 * (self.config.klt.half_window + self.config.max_displacement + 2) + 1
+    }
+
+    fn capture(&self, position: [f32; 2], now: SaeTime) -> Feature {
+        let radius = (self.patch_size() / 2) as i32;
+        let origin = (position[1].round() as i32 - radius, position[0].round() as i32 - radius);
+        let patch = decayed_patch(&self.sae, now, self.config.decay, origin.0, origin.1, self.patch_size());
+        Feature { position, origin, patch }
+    }
+
+    /// Start following a detected corner, returning the id of its track
+    pub fn add_feature(&mut self, corner: &SaeEvent) -> TrackId {
+        let (row, col) = corner.subpixel_position();
+        let feature = self.capture([col, row], corner.timestamp);
+        let id = self.store.start_track(corner.clone());
+        self.features.insert(id, feature);
+        id
+    }
+
+    /// Align every feature's patch with the surface at time `now`,
+    /// extending the tracks of those that could be followed
+    pub fn update(&mut self, now: SaeTime) -> PatchUpdate {
+        let mut result = PatchUpdate::default();
+        let size = self.patch_size();
+        let max_disp = self.config.max_displacement as f32;
+        let ids: Vec<TrackId> = self.features.keys().cloned().collect();
+        for id in ids {
+            let feature = &self.features[&id];
+            let (row0, col0) = feature.origin;
+            let current = decayed_patch(&self.sae, now, self.config.decay, row0, col0, size);
+            let local = (feature.position[0] - col0 as f32, feature.position[1] - row0 as f32);
+            let moved = track_point(&feature.patch, &current, local, &self.config.klt)
+                .filter(|&(x, y)| (x - local.0).abs() <= max_disp && (y - local.1).abs() <= max_disp);
+            let position = moved.map(|(x, y)| [x + col0 as f32, y + row0 as f32]);
+            let (nrows, ncols) = self.sae.shape();
+            match position {
+                Some(pos) if pos[0] >= 0.0 && pos[1] >= 0.0 && pos[0] < ncols as f32 && pos[1] < nrows as f32 => {
+                    let corner = SaeEvent {
+                        row: pos[1].round().min((nrows - 1) as f32) as u16,
+                        col: pos[0].round().min((ncols - 1) as f32) as u16,
+                        row_f: Some(pos[1]),
+                        col_f: Some(pos[0]),
+                        timestamp: now,
+                        ..SaeEvent::default()
+                    };
+                    self.store.extend_track(id, corner);
+                    let refreshed = self.capture(pos, now);
+                    self.features.insert(id, refreshed);
+                    result.tracked.push(id);
+                }
+                _ => {
+                    self.features.remove(&id);
+                    result.lost.push(id);
+                }
+            }
+        }
+        result
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// events of a 10 x 10 square moving right at 0.5 pixels per millisecond,
+    /// from its leading and trailing edges, for one millisecond step
+    fn square_events(step: u32) -> Vec<SaeEvent> {
+        let timestamp = 1_000 + step * 1_000;
+        let lead = 30 + step / 2;
+        let mut events = Vec::new();
+        for row in 20..30u16 {
+            for &col in [lead, lead - 10].iter() {
+                events.push(SaeEvent { row, col: col as u16, timestamp, ..SaeEvent::default() });
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn test_decayed_patch() {
+        let mut sae = SaeMatrix::zeros(4, 4);
+        sae[(1, 1)] = 100;
+        sae[(2, 2)] = 90;
+        let patch = decayed_patch(&sae, 100, 10, 0, 0, 5);
+        assert_eq!(patch[(1, 1)], 1.0);
+        assert!((patch[(2, 2)] - (-1.0f32).exp()).abs() < 1e-6);
+        assert_eq!(patch[(0, 0)], 0.0);
+        assert_eq!(patch[(4, 4)], 0.0);
+    }
+
+    #[test]
+    fn test_follows_moving_corner() {
+        let mut tracker = PatchTracker::new(60, 80, PatchTrackerConfig::default());
+        for step in 0..10 {
+            for evt in square_events(step) {
+                tracker.add_event(&evt);
+            }
+        }
+        // the top-right corner of the square, at 10 ms
+        let corner = SaeEvent { row: 20, col: 34, timestamp: 10_000, ..SaeEvent::default() };
+        let id = tracker.add_feature(&corner);
+
+        for step in 10..30 {
+            for evt in square_events(step) {
+                tracker.add_event(&evt);
+            }
+            if step % 2 == 1 {
+                let update = tracker.update(1_000 + step * 1_000);
+                assert_eq!(update.tracked, vec![id]);
+            }
+        }
+        let track = tracker.store().get(id).unwrap();
+        assert_eq!(track.len(), 11);
+        // twenty milliseconds later the corner has moved ten pixels
+        let (row, col) = track.last().subpixel_position();
+        assert!((col - 44.0).abs() < 1.0, "{}", col);
+        assert!((row - 20.0).abs() < 1.0, "{}", row);
+    }
+
+    #[test]
+    fn test_loses_featureless_patch() {
+        let mut tracker = PatchTracker::new(60, 80, PatchTrackerConfig::default());
+        let id = tracker.add_feature(&SaeEvent { row: 30, col: 30, timestamp: 1_000, ..SaeEvent::default() });
+        let update = tracker.update(2_000);
+        assert_eq!(update.lost, vec![id]);
+        assert_eq!(tracker.features().count(), 0);
+        assert_eq!(tracker.store().len(), 1);
+    }
+}
@@ -0,0 +1,487 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! An end-to-end event processing pipeline: event filtering, per-polarity SAE
+//! maintenance and corner detection, consuming any `EventSource` and delivering
+//! every detected corner to a `CornerSink`, optionally after non-maximum suppression.
+
+use std::io;
+
+use crate::backlog::{BacklogBatch, BacklogQueue, PrecisionConfig, PrecisionController, PrecisionMode};
+use crate::budget::{BudgetStats, RegionBudget};
+use crate::detector::{DetectionPolicy, DetectorConfig, ReplayDetector};
+use crate::drops::{DropCounter, DropObserver, DropReason};
+use crate::filter::{EventFilter, FilterChain};
+use crate::mask::SensorMask;
+use crate::nms::{NmsConfig, NmsGrid};
+use crate::profile::WorkProfile;
+use crate::sae_filter::{SaeFilter, SaeFilterConfig};
+use crate::sae_types::*;
+use crate::sink::CornerSink;
+use crate::source::EventSource;
+use crate::surface::{RecordingHeader, UpdatePolicy, UpdateStats};
+
+
+/// Filters events, routes them to per-polarity surfaces, detects corners, and delivers them to the sink
+pub struct Pipeline<S: CornerSink> {
+    filters: FilterChain,
+    sae_filter: Option<SaeFilter>,
+    detector: ReplayDetector,
+    /// pixels of the sensor mask, left out of every detector config
+    masked_pixels: Option<SaeOccupancy>,
+    budget: Option<RegionBudget>,
+    precision: Option<PrecisionController>,
+    nms: Option<NmsGrid>,
+    /// events waiting behind the one being processed, as last reported
+    queue_depth: usize,
+    sink: S,
+    nrows: u16,
+    ncols: u16,
+    drops: DropCounter,
+    drop_observer: Option<Box<dyn DropObserver>>,
+    events_processed: u64,
+    corners_emitted: u64,
+}
+
+impl<S: CornerSink> Pipeline<S> {
+    /// A pipeline configured to match a recording, so that live and replayed output agree
+    pub fn new(header: &RecordingHeader, sink: S) -> Self {
+        Pipeline {
+            filters: FilterChain::new(),
+            sae_filter: None,
+            detector: ReplayDetector::new(header),
+            masked_pixels: None,
+            budget: None,
+            precision: None,
+            nms: None,
+            queue_depth: 0,
+            sink,
+            nrows: header.nrows,
+            ncols: header.ncols,
+            drops: DropCounter::new(),
+            drop_observer: None,
+            events_processed: 0,
+            corners_emitted: 0,
+        }
+    }
+
+    /// Append a filter stage, run on every event before it reaches the surfaces
+    pub fn add_filter<F: EventFilter + 'static>(&mut self, filter: F) {
+        self.filters.push(filter);
+    }
+
+    /// Update the surfaces only with the events passing the Arc* event filter,
+    /// as the paper prescribes, after every other filter stage.
+    /// The events it rejects are dropped as filtered.
+    pub fn set_sae_filter(&mut self, config: SaeFilterConfig) {
+        self.sae_filter = Some(SaeFilter::new(self.nrows as usize, self.ncols as usize, config));
+    }
+
+    /// Detect corners with custom circle geometry, eg anisotropic rings.
+    /// The pixels of any sensor mask are added to its dead pixels.
+    pub fn set_detector_config(&mut self, mut config: DetectorConfig) {
+        if let Some(masked) = &self.masked_pixels {
+            let dead_pixels = match config.dead_pixels.take() {
+                Some(dead) => dead.zip_map(masked, |dead, masked| dead || masked),
+                None => masked.clone(),
+            };
+            config = config.with_dead_pixels(dead_pixels);
+        }
+        self.detector.set_detector_config(config);
+    }
+
+    /// Ignore masked pixels: their events are dropped as filtered, and their stale
+    /// timestamps are left out of circle sampling and descriptors,
+    /// whether the detector config is set before or after the mask.
+    pub fn set_sensor_mask(&mut self, mask: SensorMask) {
+        let config = self.detector.detector_config().cloned().unwrap_or_default();
+        self.masked_pixels = Some(mask.occupancy().clone());
+        self.set_detector_config(config);
+        self.add_filter(mask);
+    }
+
+    /// Limit corner detection to a per-region budget; events over budget
+    /// still update the surfaces but are not checked for corners
+    pub fn set_detection_budget(&mut self, budget: RegionBudget) {
+        self.budget = Some(budget);
+    }
+
+    /// Ignore events older than (or, by policy, as old as) the timestamp already at their pixel
+    pub fn set_update_policy(&mut self, policy: UpdatePolicy) {
+        self.detector.set_update_policy(policy);
+    }
+
+    /// Choose which surface events are checked on for corners
+    pub fn set_detection_policy(&mut self, policy: DetectionPolicy) {
+        self.detector.set_detection_policy(policy);
+    }
+
+    /// Count the detector work of every event reaching the detector, or stop counting:
+    /// see `profile` for predicting cycle budgets from the counts
+    pub fn set_work_profiling(&mut self, enabled: bool) {
+        self.detector.set_work_profiling(enabled);
+    }
+
+    /// Switch to quick detection (inner ring only, no descriptors) while the input
+    /// queue is deep, and back to full precision once it catches up.
+    /// The queue depth is reported with `set_queue_depth`, or by `drain_backlog`.
+    pub fn set_dynamic_precision(&mut self, config: PrecisionConfig) {
+        self.precision = Some(PrecisionController::new(config));
+    }
+
+    /// Deliver only the corners surviving non-maximum suppression by their confidence,
+    /// so that a burst of corners around one feature reaches the sink as one
+    pub fn set_non_max_suppression(&mut self, config: NmsConfig) {
+        self.nms = Some(NmsGrid::new(self.nrows as usize, self.ncols as usize, config));
+    }
+
+    /// the suppression grid and its counts, if enabled
+    pub fn non_max_suppression(&self) -> Option<&NmsGrid> {
+        self.nms.as_ref()
+    }
+
+    /// the number of events queued behind the next one to be processed
+    pub fn set_queue_depth(&mut self, depth: usize) {
+        self.queue_depth = depth;
+    }
+
+    /// the dynamic precision mode, its switches and counts, if enabled
+    pub fn precision(&self) -> Option<&PrecisionController> {
+        self.precision.as_ref()
+    }
+
+    /// Report every discarded event to `observer`, in addition to the built-in counts
+    pub fn set_drop_observer<O: DropObserver + 'static>(&mut self, observer: O) {
+        self.drop_observer = Some(Box::new(observer));
+    }
+
+    fn drop_event(&mut self, reason: DropReason, evt: &SaeEvent) {
+        self.drops.dropped(reason, Some(evt));
+        if let Some(observer) = self.drop_observer.as_mut() {
+            observer.dropped(reason, Some(evt));
+        }
+    }
+
+    /// Process one event, returning whether it was detected as a corner
+    pub fn process(&mut self, evt: &SaeEvent) -> bool {
+        self.admit(evt) && self.detect_and_deliver(evt, true)
+    }
+
+    /// Count the event and pass it through the bounds check and filters,
+    /// returning whether it may reach the surfaces
+    fn admit(&mut self, evt: &SaeEvent) -> bool {
+        self.events_processed += 1;
+        if evt.row >= self.nrows || evt.col >= self.ncols {
+            self.drop_event(DropReason::OutOfBounds, evt);
+            return false;
+        }
+        if !self.filters.accept(evt) || !self.sae_filter.as_mut().is_none_or(|filter| filter.accept(evt)) {
+            self.drop_event(DropReason::Filtered, evt);
+            return false;
+        }
+        true
+    }
+
+    /// Check an admitted event for a corner, delivering it to the sink, and returning
+    /// whether it was delivered. With `update` the surfaces are updated with the event
+    /// first; otherwise it was already applied to them.
+    fn detect_and_deliver(&mut self, evt: &SaeEvent, update: bool) -> bool {
+        if let Some(budget) = self.budget.as_mut() {
+            if !budget.allow(evt) {
+                if update {
+                    self.detector.update(evt);
+                }
+                return false;
+            }
+        }
+        let mode = match self.precision.as_mut() {
+            Some(precision) => precision.select(self.queue_depth, evt.timestamp),
+            None => PrecisionMode::Full,
+        };
+        let corner = match (mode, update) {
+            (PrecisionMode::Full, true) => self.detector.process(evt),
+            (PrecisionMode::Quick, true) => self.detector.process_quick(evt),
+            (PrecisionMode::Full, false) => self.detector.detect(evt),
+            (PrecisionMode::Quick, false) => self.detector.detect_quick(evt),
+        };
+        match corner {
+            Some(corner) if self.nms.as_mut().is_none_or(|nms| nms.admit(&corner)) => {
+                self.sink.accept(&corner);
+                self.corners_emitted += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Process all events from the iterator
+    pub fn run<I: IntoIterator<Item = SaeEvent>>(&mut self, events: I) {
+        for evt in events {
+            self.process(&evt);
+        }
+    }
+
+    /// Process all events from the source until it is exhausted
+    pub fn run_source<E: EventSource>(&mut self, source: &mut E) -> io::Result<()> {
+        while let Some(evt) = source.next_event()? {
+            self.process(&evt);
+        }
+        Ok(())
+    }
+
+    /// Process queued events until the queue is empty, reporting its depth for dynamic precision.
+    /// A backlog released newest first updates the surfaces in arrival order,
+    /// and only then are its events checked for corners, newest first.
+    pub fn drain_backlog(&mut self, queue: &mut BacklogQueue) {
+        while let Some(batch) = queue.pop_batch() {
+            match batch {
+                BacklogBatch::Event(evt) => {
+                    self.queue_depth = queue.len();
+                    self.process(&evt);
+                }
+                BacklogBatch::NewestFirst(events) => {
+                    let applied: Vec<bool> = events.iter()
+                        .map(|evt| self.admit(evt) && self.detector.update(evt))
+                        .collect();
+                    for (idx, evt) in events.iter().enumerate().rev().filter(|(idx, _)| applied[*idx]) {
+                        self.queue_depth = queue.len() + idx;
+                        self.detect_and_deliver(evt, false);
+                    }
+                }
+            }
+        }
+        self.queue_depth = 0;
+    }
+
+    pub fn events_processed(&self) -> u64 {
+        self.events_processed
+    }
+
+    pub fn corners_emitted(&self) -> u64 {
+        self.corners_emitted
+    }
+
+    /// counts of events discarded before reaching the surfaces
+    pub fn drops(&self) -> &DropCounter {
+        &self.drops
+    }
+
+    /// detection budget counts, if a budget is set
+    pub fn budget_stats(&self) -> Option<&BudgetStats> {
+        self.budget.as_ref().map(|budget| budget.stats())
+    }
+
+    /// counts of events ignored by the update policy
+    pub fn update_stats(&self) -> UpdateStats {
+        self.detector.update_stats()
+    }
+
+    /// detector work counted so far, if profiling
+    pub fn work_profile(&self) -> Option<WorkProfile> {
+        self.detector.work_profile()
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RingBufferSink;
+    use crate::source::IterSource;
+    use crate::surface::WarmupConfig;
+
+    #[test]
+    fn test_pipeline_delivers_corners_to_sink() {
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let events = corner_block_events(10, 10, 0, 7);
+
+        let mut pipeline = Pipeline::new(&header, RingBufferSink::new(4));
+        pipeline.run_source(&mut IterSource::new(events.clone())).unwrap();
+        assert_eq!(pipeline.events_processed(), events.len() as u64);
+        assert!(pipeline.corners_emitted() > 0);
+
+        let sink = pipeline.into_sink();
+        let last = sink.corners().last().unwrap();
+        assert_eq!((last.row, last.col, last.timestamp), (14, 14, 9));
+        assert!(last.norm_descriptor.is_some());
+    }
+
+    #[test]
+    fn test_pipeline_dynamic_precision() {
+        use crate::backlog::{BacklogConfig, BacklogPolicy};
+
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let mut queue = BacklogQueue::new(BacklogConfig { threshold: 100, policy: BacklogPolicy::Fifo });
+        for evt in corner_block_events(10, 10, 0, 7) {
+            queue.push(evt);
+        }
+
+        let mut pipeline = Pipeline::new(&header, Vec::new());
+        pipeline.set_dynamic_precision(PrecisionConfig { enter_quick_depth: 10, exit_quick_depth: 2 });
+        pipeline.drain_backlog(&mut queue);
+        let precision = pipeline.precision().unwrap();
+        let modes: Vec<PrecisionMode> = precision.switches().iter().map(|switch| switch.mode).collect();
+        assert_eq!(modes, vec![PrecisionMode::Quick, PrecisionMode::Full]);
+        assert_eq!(precision.switches()[0].depth, 25);
+        assert_eq!(precision.stats().quick_events + precision.stats().full_events, 26);
+
+        let corners = pipeline.into_sink();
+        let last = corners.last().unwrap();
+        assert_eq!((last.row, last.col, last.timestamp), (14, 14, 9));
+        assert!(last.norm_descriptor.is_some());
+        assert!(corners.iter().any(|corner| corner.norm_descriptor.is_none()));
+    }
+
+    #[test]
+    fn test_pipeline_newest_first_backlog() {
+        use crate::backlog::{BacklogConfig, BacklogPolicy};
+
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let mut queue = BacklogQueue::new(BacklogConfig { threshold: 10, policy: BacklogPolicy::NewestFirst });
+        for evt in corner_block_events(10, 10, 0, 7) {
+            queue.push(evt);
+        }
+
+        let mut pipeline = Pipeline::new(&header, Vec::new());
+        pipeline.drain_backlog(&mut queue);
+        assert_eq!(pipeline.events_processed(), 26);
+        // the newest event is checked first, on surfaces already holding the whole block
+        let corners = pipeline.into_sink();
+        let first = corners.first().unwrap();
+        assert_eq!((first.row, first.col, first.timestamp), (14, 14, 9));
+        assert!(first.norm_descriptor.is_some());
+        assert!(corners.windows(2).all(|pair| pair[0].timestamp >= pair[1].timestamp));
+    }
+
+    #[test]
+    fn test_pipeline_sensor_mask() {
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        // stuck columns beside a pixel that sees a single event: without the mask the
+        // columns' stale timestamps form arcs on both rings around it
+        let mut events = Vec::new();
+        for row in 0..32 {
+            events.push(SaeEvent { row, col: 11, timestamp: 5, ..SaeEvent::default() });
+            events.push(SaeEvent { row, col: 12, timestamp: 5, ..SaeEvent::default() });
+        }
+        events.push(SaeEvent { row: 16, col: 15, timestamp: 9, ..SaeEvent::default() });
+
+        let mut unmasked = Pipeline::new(&header, Vec::new());
+        unmasked.run(events.clone());
+        let mut masked = Pipeline::new(&header, Vec::new());
+        masked.set_sensor_mask(SensorMask::new(32, 32).with_cols(11..13));
+        masked.run(events.clone());
+        assert_eq!(masked.drops().count(DropReason::Filtered), 64);
+        assert!(unmasked.sink().iter().any(|corner| corner.col == 15));
+        assert!(masked.sink().is_empty());
+
+    }
+
+    #[test]
+    fn test_pipeline_sensor_mask_survives_detector_config() {
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let mask = SensorMask::new(32, 32).with_cols(11..13);
+        let mut own_dead = SaeOccupancy::from_element(32, 32, false);
+        own_dead[(3, 3)] = true;
+
+        let mut pipeline = Pipeline::new(&header, Vec::new());
+        pipeline.set_sensor_mask(mask.clone());
+        pipeline.set_detector_config(DetectorConfig::default().with_dead_pixels(own_dead));
+        let dead = pipeline.detector.detector_config().unwrap().dead_pixels.as_ref().unwrap();
+        assert!(dead[(3, 3)] && dead[(20, 11)] && dead[(20, 12)]);
+        assert!(!dead[(20, 13)]);
+    }
+
+    #[test]
+    fn test_pipeline_sae_filter() {
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        // a corner, then a burst at its tip that would keep producing corners
+        // the block without its tip event, which would be filtered too
+        let mut events: Vec<SaeEvent> = corner_block_events(10, 10, 0, 1).into_iter().take(25).collect();
+        for i in 0..5 {
+            events.push(SaeEvent { row: 14, col: 14, timestamp: 1_000 + i * 1_000, ..SaeEvent::default() });
+        }
+        let mut unfiltered = Pipeline::new(&header, Vec::new());
+        unfiltered.run(events.clone());
+        let mut filtered = Pipeline::new(&header, Vec::new());
+        filtered.set_sae_filter(SaeFilterConfig::default());
+        filtered.run(events);
+        assert_eq!(filtered.drops().count(DropReason::Filtered), 5);
+        assert!(unfiltered.sink().iter().filter(|corner| corner.timestamp >= 1_000).count() >= 5);
+        assert!(filtered.sink().iter().all(|corner| corner.timestamp < 1_000));
+    }
+
+    #[test]
+    fn test_pipeline_filters_before_detection() {
+        use crate::flicker::{FlickerConfig, FlickerFilter};
+
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let mut pipeline = Pipeline::new(&header, Vec::new());
+        pipeline.add_filter(FlickerFilter::new(32, 32, FlickerConfig::default()));
+
+        // a single flickering pixel in the middle of a corner-shaped neighborhood
+        let mut events = corner_block_events(10, 10, 0, 1);
+        for i in 0..6 {
+            events.push(SaeEvent { row: 14, col: 14, timestamp: 10 + i * 10_000, ..SaeEvent::default() });
+        }
+        events.push(SaeEvent { row: 40, col: 3, timestamp: 70_000, ..SaeEvent::default() });
+        let logged = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let log = logged.clone();
+        pipeline.set_drop_observer(move |reason: DropReason, evt: Option<&SaeEvent>| {
+            log.borrow_mut().push((reason, evt.map(|e| e.timestamp)));
+        });
+        pipeline.run(events);
+        let drops = pipeline.drops().clone();
+        assert_eq!(drops.count(DropReason::OutOfBounds), 1);
+        assert!(drops.count(DropReason::Filtered) > 0);
+        assert_eq!(logged.borrow().len() as u64, drops.total());
+        assert_eq!(logged.borrow().last(), Some(&(DropReason::OutOfBounds, Some(70_000))));
+        let corners = pipeline.into_sink();
+        assert!(!corners.is_empty());
+        assert!(corners.iter().all(|c| c.timestamp <= 20_010));
+    }
+
+    #[test]
+    fn test_pipeline_detection_budget() {
+        use crate::budget::{BudgetConfig, RegionBudget};
+
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let mut pipeline = Pipeline::new(&header, Vec::new());
+        let config = BudgetConfig { tile_size: 32, window: 1_000_000, global_budget: 3, min_per_tile: 1 };
+        pipeline.set_detection_budget(RegionBudget::new(32, 32, config));
+        for evt in corner_block_events(10, 10, 0, 7).iter().take(25) {
+            pipeline.process(evt);
+        }
+        let stats = pipeline.budget_stats().unwrap();
+        assert_eq!(stats.detections_allowed, 3);
+        assert_eq!(stats.detections_skipped, 22);
+    }
+
+    #[test]
+    fn test_pipeline_non_max_suppression() {
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let events = corner_block_events(10, 10, 0, 7);
+
+        let mut plain = Pipeline::new(&header, Vec::new());
+        plain.run(events.clone());
+        let mut suppressed = Pipeline::new(&header, Vec::new());
+        suppressed.set_non_max_suppression(NmsConfig { radius: 8.0, window: 1_000, ..NmsConfig::default() });
+        suppressed.run(events);
+        let nms = suppressed.non_max_suppression().unwrap();
+        assert!(nms.suppressed() > 0);
+        assert_eq!(nms.kept() + nms.suppressed(), plain.corners_emitted());
+        assert_eq!(suppressed.corners_emitted(), nms.kept());
+        assert_eq!(suppressed.sink().len() as u64, nms.kept());
+    }
+}
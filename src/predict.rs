@@ -0,0 +1,215 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Dead-reckoned prediction of the next corner of a track.
+//!
+//! Given a track, `CornerPredictor` estimates when the next corner of its feature
+//! should arrive, from the track's recent observation interval, and where, by
+//! dead reckoning from its recent velocity. With a camera model and a gyro rate the
+//! motion is instead that induced by the camera rotation, which reacts at once to
+//! changes of motion that the track's own history only shows later. Each prediction
+//! carries a gate, a radius growing with the prediction horizon and a time window,
+//! for accepting the corners that continue the track, and a region of interest, eg
+//! to program hardware ROIs on sensors that support them.
+
+use nalgebra::{Point3, UnitQuaternion, Vector3};
+
+use crate::calib::camera::CameraIntrinsics;
+use crate::objects::BoundingBox;
+use crate::sae_types::*;
+use crate::track::{Track, TrackId};
+
+
+/// Parameters of corner prediction
+#[derive(Clone, Debug, PartialEq)]
+pub struct PredictionConfig {
+    /// number of recent observation intervals the velocity and interval are estimated over
+    pub history: usize,
+    /// gate radius (pixels) at zero prediction horizon
+    pub base_radius: f32,
+    /// growth of the gate radius with the prediction horizon, in pixels per millisecond
+    pub radius_growth: f32,
+    /// half-width of the time window around the predicted time, as a fraction of the observation interval
+    pub time_tolerance: f32,
+}
+
+impl Default for PredictionConfig {
+    fn default() -> Self {
+        PredictionConfig {
+            history: 5,
+            base_radius: 2.0,
+            radius_growth: 0.5,
+            time_tolerance: 1.0,
+        }
+    }
+}
+
+/// Where and when the next corner of a track is expected
+#[derive(Clone, Debug, PartialEq)]
+pub struct CornerPrediction {
+    pub track: TrackId,
+    /// the expected time of the corner
+    pub timestamp: SaeTime,
+    /// the expected position (x = column, y = row), in pixels
+    pub position: [f32; 2],
+    /// gate radius around the position, in pixels
+    pub radius: f32,
+    /// start of the time window, inclusive
+    pub earliest: SaeTime,
+    /// end of the time window, inclusive
+    pub latest: SaeTime,
+}
+
+impl CornerPrediction {
+    /// whether `corner` lies within the gate
+    pub fn contains(&self, corner: &SaeEvent) -> bool {
+        let (row, col) = corner.subpixel_position();
+        (self.earliest..=self.latest).contains(&corner.timestamp) &&
+            (col - self.position[0]).hypot(row - self.position[1]) <= self.radius
+    }
+
+    /// the square bounding the gate, eg for a hardware ROI
+    pub fn region(&self) -> BoundingBox {
+        BoundingBox { min: self.position, max: self.position }.inflated(self.radius)
+    }
+}
+
+/// Predicts the next corner of tracks
+pub struct CornerPredictor {
+    config: PredictionConfig,
+    camera: Option<CameraIntrinsics>,
+}
+
+impl CornerPredictor {
+    pub fn new(config: PredictionConfig) -> Self {
+        CornerPredictor { config, camera: None }
+    }
+
+    /// Use the camera model to predict from gyro rates; without one, gyro rates are ignored
+    pub fn with_camera(mut self, camera: CameraIntrinsics) -> Self {
+        self.camera = Some(camera);
+        self
+    }
+
+    pub fn config(&self) -> &PredictionConfig {
+        &self.config
+    }
+
+    /// (mean observation interval, velocity in pixels per microsecond) over the recent history
+    fn recent_motion(&self, track: &Track) -> Option<(f32, [f32; 2])> {
+        let count = track.len();
+        let span = self.config.history.max(1).min(count.checked_sub(1)?);
+        if span == 0 {
+            return None;
+        }
+        let first = &track.observations[count - 1 - span];
+        let last = track.last();
+        let elapsed = last.timestamp.checked_sub(first.timestamp).filter(|&elapsed| elapsed > 0)? as f32;
+        let (row0, col0) = first.subpixel_position();
+        let (row1, col1) = last.subpixel_position();
+        Some((elapsed / span as f32, [(col1 - col0) / elapsed, (row1 - row0) / elapsed]))
+    }
+
+    /// velocity (pixels per microsecond, x = column, y = row) over the track's recent history;
+    /// None for tracks too short to estimate it
+    pub fn velocity(&self, track: &Track) -> Option<[f32; 2]> {
+        self.recent_motion(track).map(|(_, velocity)| velocity)
+    }
+
+    /// Displacement (pixels) of a static point at `position` after the camera rotates at
+    /// `gyro` (rad/s, in the camera frame) for `dt` microseconds
+    fn rotation_displacement(camera: &CameraIntrinsics, position: [f32; 2], gyro: [f64; 3], dt: f64) -> Option<[f32; 2]> {
+        let ray = camera.unproject([position[0] as f64, position[1] as f64]);
+        // the camera turning by `gyro * dt` turns the scene the other way
+        let rotation = UnitQuaternion::from_scaled_axis(Vector3::from(gyro) * (-dt * 1e-6));
+        let moved = camera.project(&Point3::from(rotation * ray))?;
+        let [x, y] = camera.project(&Point3::from(ray))?;
+        Some([(moved[0] - x) as f32, (moved[1] - y) as f32])
+    }
+
+    /// Predict the corner of `track` at `timestamp`, using `gyro` (rad/s, in the camera
+    /// frame) if given and a camera is set. None for tracks too short to estimate their
+    /// motion, or if the rotated feature leaves the camera's view.
+    pub fn predict_at(&self, track: &Track, timestamp: SaeTime, gyro: Option<[f64; 3]>) -> Option<CornerPrediction> {
+        let (interval, velocity) = self.recent_motion(track)?;
+        let last = track.last();
+        let (row, col) = last.subpixel_position();
+        let dt = timestamp.saturating_sub(last.timestamp) as f32;
+        let displacement = match (gyro, self.camera.as_ref()) {
+            (Some(gyro), Some(camera)) => Self::rotation_displacement(camera, [col, row], gyro, dt as f64)?,
+            _ => [velocity[0] * dt, velocity[1] * dt],
+        };
+        let tolerance = (interval * self.config.time_tolerance) as SaeTime;
+        Some(CornerPrediction {
+            track: track.id,
+            timestamp,
+            position: [col + displacement[0], row + displacement[1]],
+            radius: self.config.base_radius + self.config.radius_growth * dt / 1000.0,
+            earliest: timestamp.saturating_sub(tolerance),
+            latest: timestamp.saturating_add(tolerance),
+        })
+    }
+
+    /// Predict the next corner of `track`, one observation interval after its latest
+    pub fn predict_next(&self, track: &Track, gyro: Option<[f64; 3]>) -> Option<CornerPrediction> {
+        let (interval, _) = self.recent_motion(track)?;
+        self.predict_at(track, track.last().timestamp.saturating_add(interval.round() as SaeTime), gyro)
+    }
+
+    /// Whether `corner` falls within the gate of `track` predicted for the corner's own time.
+    /// Tracks without a prediction accept every corner.
+    pub fn gate(&self, track: &Track, corner: &SaeEvent, gyro: Option<[f64; 3]>) -> bool {
+        self.predict_at(track, corner.timestamp, gyro)
+            .is_none_or(|prediction| prediction.contains(corner))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(steps: u32) -> Track {
+        let mut track = Track::new(3, SaeEvent { row: 40, col: 20, timestamp: 0, ..SaeEvent::default() });
+        for step in 1..steps {
+            track.observations.push(SaeEvent { row: 40, col: 20 + step as u16, timestamp: step * 1_000, ..SaeEvent::default() });
+        }
+        track
+    }
+
+    #[test]
+    fn test_constant_velocity_prediction() {
+        let predictor = CornerPredictor::new(PredictionConfig::default());
+        assert!(predictor.predict_next(&track(1), None).is_none());
+
+        let track = track(8);
+        let prediction = predictor.predict_next(&track, None).unwrap();
+        assert_eq!(prediction.track, 3);
+        assert_eq!(prediction.timestamp, 8_000);
+        assert!((prediction.position[0] - 28.0).abs() < 1e-4);
+        assert!((prediction.position[1] - 40.0).abs() < 1e-4);
+        assert!((prediction.radius - 2.5).abs() < 1e-6);
+        assert_eq!((prediction.earliest, prediction.latest), (7_000, 9_000));
+        let region = prediction.region();
+        assert!((region.min[0] - 25.5).abs() < 1e-4 && (region.max[1] - 42.5).abs() < 1e-4);
+
+        let next = SaeEvent { row: 41, col: 28, timestamp: 8_200, ..SaeEvent::default() };
+        assert!(prediction.contains(&next));
+        assert!(predictor.gate(&track, &next, None));
+        assert!(!predictor.gate(&track, &SaeEvent { row: 40, col: 33, ..next }, None));
+    }
+
+    #[test]
+    fn test_gyro_prediction() {
+        let camera = CameraIntrinsics::pinhole(100.0, 100.0, 27.0, 40.0);
+        let predictor = CornerPredictor::new(PredictionConfig::default()).with_camera(camera);
+        let track = track(8);
+        // turning right (about +y) for a millisecond moves the scene 0.01 rad to the left
+        let prediction = predictor.predict_at(&track, 8_000, Some([0.0, 10.0, 0.0])).unwrap();
+        assert!((prediction.position[0] - (27.0 - 100.0 * 0.01f32.tan())).abs() < 1e-3);
+        assert!((prediction.position[1] - 40.0).abs() < 1e-3);
+        // without a gyro rate the track's own motion is used
+        let prediction = predictor.predict_at(&track, 8_000, None).unwrap();
+        assert!((prediction.position[0] - 28.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,46 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! The stable core of the API, for `use arcstar::prelude::*`.
+//!
+//! The crate grows one module per feature, and those modules change as features
+//! mature. The items re-exported here do not: they are the surface, detector,
+//! filter, tracker and I/O types and traits that every pipeline is built from, and
+//! they are only removed or changed incompatibly in a release that bumps the
+//! leading nonzero version number, with a deprecation in a release before it.
+//! Downstream crates that import only from the prelude are insulated from the
+//! layout of the modules. Items reached through their modules carry no such guarantee.
+
+pub use crate::sae_types::{SaeEvent, RawEvent, SaeMatrix, SaeOccupancy, SaeTime, SaeTimeExt, NormDescriptor, BinaryDescriptor};
+pub use crate::time::{EventTime, TimeRebaser, TimeUnit};
+
+pub use crate::surface::{RecordingHeader, SaeSurface, UpdatePolicy, WarmupConfig};
+pub use crate::detector::{CornerDetector, DetectorConfig, OnlineDetector, ReplayDetector, SurfaceDetector};
+pub use crate::filter::{EventFilter, FilterChain};
+pub use crate::sae_filter::{SaeFilter, SaeFilterConfig};
+pub use crate::sae_tracker::SaeTracker;
+pub use crate::track::{CornerTracker, Track, TrackerConfig};
+
+pub use crate::pipeline::Pipeline;
+pub use crate::static_pipeline::StaticPipeline;
+#[cfg(feature = "io")]
+pub use crate::io::compact::{CompactReader, CompactWriter};
+pub use crate::sink::CornerSink;
+pub use crate::source::{EventSource, IterSource};
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prelude_builds_a_pipeline() {
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let mut pipeline = Pipeline::new(&header, Vec::new());
+        let mut source = IterSource::new((0..5).map(|col| SaeEvent { row: 10, col, timestamp: 7, ..SaeEvent::default() }));
+        while let Some(evt) = source.next_event().unwrap() {
+            pipeline.process(&evt);
+        }
+        assert_eq!(pipeline.events_processed(), 5);
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Work profiling, for predicting the detector's cycle budget on embedded targets.
+//!
+//! Timing a desktop run says little about an MCU or DSP, but the detector's work is
+//! made of a few elementary operations whose counts don't depend on the machine:
+//! ring samples, flag reads, arc expansion steps and descriptor computations.
+//! With profiling enabled on a surface (or a `Pipeline`), those counts are collected
+//! per event over a recorded workload; weighting them with the measured cost of each
+//! operation on the target, as `CycleCosts`, gives the mean and worst-case cycles per event.
+
+use crate::detector::DetectorWork;
+
+
+/// Cost in cycles of each elementary operation on some target, eg measured with a
+/// cycle counter around a few thousand repetitions of each
+#[derive(Clone, Debug, PartialEq)]
+pub struct CycleCosts {
+    /// fixed overhead of every event: SAE update, border and warmup checks
+    pub per_event: f64,
+    pub per_circle_sample: f64,
+    pub per_occupancy_sample: f64,
+    pub per_expansion_step: f64,
+    /// descriptor, confidence, orientation and kind of a corner
+    pub per_descriptor: f64,
+}
+
+impl Default for CycleCosts {
+    /// One cycle per operation: the estimates are then plain operation counts
+    fn default() -> Self {
+        CycleCosts {
+            per_event: 1.0,
+            per_circle_sample: 1.0,
+            per_occupancy_sample: 1.0,
+            per_expansion_step: 1.0,
+            per_descriptor: 1.0,
+        }
+    }
+}
+
+impl CycleCosts {
+    /// cycles for one event doing `work`
+    pub fn cycles(&self, work: &DetectorWork) -> f64 {
+        self.per_event +
+            self.per_circle_sample * work.circle_samples as f64 +
+            self.per_occupancy_sample * work.occupancy_samples as f64 +
+            self.per_expansion_step * work.expansion_steps as f64 +
+            self.per_descriptor * work.descriptors as f64
+    }
+}
+
+/// Detector work accumulated over the events of a workload
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorkProfile {
+    /// events that reached the detector, including those rejected before any work
+    pub events: u64,
+    pub corners: u64,
+    /// work summed over all events
+    pub total: DetectorWork,
+    /// the largest count of each operation in any one event
+    pub worst: DetectorWork,
+}
+
+impl WorkProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one event, which did `work` and was or wasn't a corner
+    pub fn record(&mut self, work: &DetectorWork, corner: bool) {
+        self.events += 1;
+        self.corners += corner as u64;
+        self.total.add(work);
+        self.worst = self.worst.max(work);
+    }
+
+    /// Combine with the profile of another part of the workload, eg the other polarity
+    pub fn merge(&mut self, other: &WorkProfile) {
+        self.events += other.events;
+        self.corners += other.corners;
+        self.total.add(&other.total);
+        self.worst = self.worst.max(&other.worst);
+    }
+
+    /// mean cycles per event
+    pub fn mean_cycles(&self, costs: &CycleCosts) -> f64 {
+        if self.events == 0 {
+            return 0.0;
+        }
+        // `cycles` counts the per-event overhead once
+        (costs.cycles(&self.total) - costs.per_event) / self.events as f64 + costs.per_event
+    }
+
+    /// An upper bound on the cycles of any one event: the worst counts of each
+    /// operation need not all come from the same event
+    pub fn worst_cycles(&self, costs: &CycleCosts) -> f64 {
+        costs.cycles(&self.worst)
+    }
+
+    /// cycles for the whole workload
+    pub fn total_cycles(&self, costs: &CycleCosts) -> f64 {
+        self.mean_cycles(costs) * self.events as f64
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sae_types::*;
+    use crate::surface::{SaeSurface, WarmupConfig};
+
+    #[test]
+    fn test_profile_counts() {
+        let mut surface = SaeSurface::with_warmup(32, 32, WarmupConfig::disabled());
+        surface.set_work_profiling(true);
+        // a corner: a fresh quadrant of timestamps to the right of and below the event
+        for row in 10..20u16 {
+            for col in 10..20u16 {
+                let timestamp = 1_000 + (row as SaeTime) * 10 + col as SaeTime;
+                surface.update(&SaeEvent { row, col, timestamp, ..SaeEvent::default() });
+            }
+        }
+        let corner = surface.update_and_detect(&SaeEvent { row: 10, col: 10, timestamp: 5_000, ..SaeEvent::default() });
+        // too close to the border: no work
+        surface.update_and_detect(&SaeEvent { row: 1, col: 1, timestamp: 5_001, ..SaeEvent::default() });
+
+        let profile = surface.work_profile().unwrap();
+        assert!(corner.is_some());
+        assert_eq!((profile.events, profile.corners), (2, 1));
+        assert_eq!(profile.total, DetectorWork {
+            circle_samples: 16 + 20,
+            occupancy_samples: 16 + 20,
+            expansion_steps: 15 + 19,
+            descriptors: 1,
+        });
+        assert_eq!(profile.worst, profile.total);
+
+        let costs = CycleCosts::default();
+        assert_eq!(profile.worst_cycles(&costs), 1.0 + 36.0 + 36.0 + 34.0 + 1.0);
+        assert_eq!(profile.mean_cycles(&costs), (2.0 + 36.0 + 36.0 + 34.0 + 1.0) / 2.0);
+        assert_eq!(profile.total_cycles(&costs), 109.0);
+
+        let mut merged = WorkProfile::new();
+        merged.merge(profile);
+        merged.merge(profile);
+        assert_eq!(merged.events, 4);
+        assert_eq!(merged.worst, profile.worst);
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Progress reporting and cooperative cancellation for long-running batch jobs,
+//! such as processing a whole recording.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+
+/// Snapshot of progress through a batch job
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    pub events_processed: u64,
+    pub bytes_read: u64,
+    /// total input size, if known up front
+    pub total_bytes: Option<u64>,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// fraction (0..1) of the input consumed, if the total size is known
+    pub fn fraction(&self) -> Option<f32> {
+        match self.total_bytes {
+            Some(0) => Some(1.0),
+            Some(total) => Some(((self.bytes_read as f64) / (total as f64)).min(1.0) as f32),
+            None => None,
+        }
+    }
+
+    /// estimated time remaining, extrapolated from the rate so far
+    pub fn eta(&self) -> Option<Duration> {
+        let total = self.total_bytes?;
+        if self.bytes_read == 0 {
+            return None;
+        }
+        let remaining = total.saturating_sub(self.bytes_read) as f64;
+        let secs_per_byte = self.elapsed.as_secs_f64() / (self.bytes_read as f64);
+        Some(Duration::from_secs_f64(remaining * secs_per_byte))
+    }
+}
+
+/// A cooperative cancellation flag, shared between a batch job and whoever controls it.
+/// Clones refer to the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// request that the job stop at its next checkpoint
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Callback receiving periodic progress reports
+pub type ProgressCallback<'a> = Box<dyn FnMut(&Progress) + 'a>;
+
+/// Optional progress callback and cancellation token passed to a batch job.
+/// The default performs no reporting and is never cancelled.
+pub struct JobControl<'a> {
+    callback: Option<ProgressCallback<'a>>,
+    cancel: Option<CancelToken>,
+    total_bytes: Option<u64>,
+    /// number of events between progress callbacks and cancellation checks
+    report_interval: u64,
+    started: Instant,
+}
+
+impl<'a> Default for JobControl<'a> {
+    fn default() -> Self {
+        JobControl {
+            callback: None,
+            cancel: None,
+            total_bytes: None,
+            report_interval: 10_000,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl<'a> JobControl<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_progress<F: FnMut(&Progress) + 'a>(mut self, callback: F) -> Self {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    pub fn with_cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// total input size, enabling fraction and ETA estimates
+    pub fn with_total_bytes(mut self, total_bytes: u64) -> Self {
+        self.total_bytes = Some(total_bytes);
+        self
+    }
+
+    pub fn with_report_interval(mut self, events: u64) -> Self {
+        self.report_interval = events.max(1);
+        self
+    }
+
+    fn progress(&self, events_processed: u64, bytes_read: u64) -> Progress {
+        Progress {
+            events_processed,
+            bytes_read,
+            total_bytes: self.total_bytes,
+            elapsed: self.started.elapsed(),
+        }
+    }
+
+    /// Checkpoint called by the job after each event.
+    /// Reports progress every `report_interval` events, and returns an
+    /// `Interrupted` error if the job has been cancelled.
+    pub fn checkpoint(&mut self, events_processed: u64, bytes_read: u64) -> io::Result<()> {
+        if !events_processed.is_multiple_of(self.report_interval) {
+            return Ok(());
+        }
+        if let Some(ref token) = self.cancel {
+            if token.is_cancelled() {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "job cancelled"));
+            }
+        }
+        let progress = self.progress(events_processed, bytes_read);
+        if let Some(ref mut callback) = self.callback {
+            callback(&progress);
+        }
+        Ok(())
+    }
+
+    /// Report final progress when the job completes
+    pub fn finish(&mut self, events_processed: u64, bytes_read: u64) {
+        let progress = self.progress(events_processed, bytes_read);
+        if let Some(ref mut callback) = self.callback {
+            callback(&progress);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_estimates() {
+        let progress = Progress {
+            events_processed: 10,
+            bytes_read: 250,
+            total_bytes: Some(1000),
+            elapsed: Duration::from_secs(1),
+        };
+        assert_eq!(progress.fraction(), Some(0.25));
+        assert_eq!(progress.eta(), Some(Duration::from_secs(3)));
+
+        let unknown = Progress { total_bytes: None, ..progress };
+        assert_eq!(unknown.fraction(), None);
+        assert_eq!(unknown.eta(), None);
+    }
+
+    #[test]
+    fn test_checkpoint_reporting_and_cancel() {
+        let mut reports = Vec::new();
+        let token = CancelToken::new();
+        {
+            let mut control = JobControl::new()
+                .with_progress(|p: &Progress| reports.push(p.events_processed))
+                .with_cancel(token.clone())
+                .with_report_interval(5);
+
+            for count in 1..=12 {
+                control.checkpoint(count, count * 9).unwrap();
+            }
+            token.cancel();
+            assert!(control.checkpoint(13, 0).is_ok());
+            let err = control.checkpoint(15, 0).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        }
+        assert_eq!(reports, vec![5, 10]);
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Linear (PCA) compression of corner descriptors.
+//! A projection is trained offline from a sample of descriptors, stored,
+//! and applied to each corner at detection time, reducing the 36-element
+//! descriptor to a handful of dimensions for cheaper matching and storage.
+
+use std::io::{self, Read, Write};
+
+use nalgebra::{DMatrix, DVector};
+
+use crate::io::decode::{DecodeError, DecodeErrorKind, OffsetReader};
+use crate::sae_types::*;
+
+
+const PROJECTION_MAGIC: &[u8; 4] = b"APCA";
+
+/// A trained projection from descriptor space to a lower-dimensional space
+#[derive(Clone, Debug, PartialEq)]
+pub struct DescriptorProjection {
+    /// mean descriptor of the training set
+    mean: DVector<f32>,
+    /// one principal axis per row, strongest first
+    basis: DMatrix<f32>,
+    /// variance captured by each axis
+    variances: Vec<f32>,
+    /// total variance of the training set
+    total_variance: f32,
+}
+
+/// A corner with its projected descriptor
+#[derive(Clone, Debug)]
+pub struct ProjectedCorner {
+    pub event: SaeEvent,
+    pub descriptor: DVector<f32>,
+}
+
+fn descriptor_vector(desc: &NormDescriptor) -> DVector<f32> {
+    DVector::from_row_slice(desc)
+}
+
+impl DescriptorProjection {
+    /// Train a projection onto the `dims` strongest principal axes of the descriptors.
+    /// Descriptors with non-finite elements are skipped. Returns None if fewer than two
+    /// remain, or `dims` is out of range.
+    pub fn train(descriptors: &[NormDescriptor], dims: usize) -> Option<Self> {
+        let descriptors: Vec<&NormDescriptor> = descriptors.iter()
+            .filter(|desc| desc.iter().all(|val| val.is_finite()))
+            .collect();
+        if descriptors.len() < 2 || dims == 0 || dims > NORM_DESCRIPTOR_LEN {
+            return None;
+        }
+        let count = descriptors.len() as f32;
+        let mut mean = DVector::<f32>::zeros(NORM_DESCRIPTOR_LEN);
+        for desc in descriptors.iter() {
+            mean += descriptor_vector(desc);
+        }
+        mean /= count;
+
+        let mut covariance = DMatrix::<f32>::zeros(NORM_DESCRIPTOR_LEN, NORM_DESCRIPTOR_LEN);
+        for desc in descriptors {
+            let centered = descriptor_vector(desc) - &mean;
+            covariance += &centered * centered.transpose();
+        }
+        covariance /= count - 1.0;
+
+        let total_variance = covariance.trace().max(0.0);
+        // decomposed in f64: in f32, the tiny off-diagonal terms of a near-degenerate
+        // covariance (eg descriptors varying along one direction) underflow into NaN
+        let eigen = covariance.map(|v| v as f64).symmetric_eigen();
+        let mut order: Vec<usize> = (0..NORM_DESCRIPTOR_LEN).collect();
+        order.sort_by(|&a, &b| eigen.eigenvalues[b].total_cmp(&eigen.eigenvalues[a]));
+        let mut basis = DMatrix::<f32>::zeros(dims, NORM_DESCRIPTOR_LEN);
+        let mut variances = Vec::with_capacity(dims);
+        for (axis, &idx) in order.iter().take(dims).enumerate() {
+            basis.set_row(axis, &eigen.eigenvectors.column(idx).transpose().map(|v| v as f32));
+            // rounding can leave the eigenvalues of a semidefinite matrix slightly negative
+            variances.push(eigen.eigenvalues[idx].max(0.0) as f32);
+        }
+
+        Some(DescriptorProjection { mean, basis, variances, total_variance })
+    }
+
+    /// number of output dimensions
+    pub fn dims(&self) -> usize {
+        self.basis.nrows()
+    }
+
+    /// fraction (0..1) of the training set variance retained by the projection
+    pub fn explained_variance(&self) -> f32 {
+        if self.total_variance <= 0.0 {
+            return 1.0;
+        }
+        self.variances.iter().sum::<f32>() / self.total_variance
+    }
+
+    pub fn project(&self, desc: &NormDescriptor) -> DVector<f32> {
+        &self.basis * (descriptor_vector(desc) - &self.mean)
+    }
+
+    /// Approximate the original descriptor from its projection
+    pub fn reconstruct(&self, projected: &DVector<f32>) -> NormDescriptor {
+        let full = self.basis.transpose() * projected + &self.mean;
+        let mut desc = [0.0; NORM_DESCRIPTOR_LEN];
+        desc.copy_from_slice(full.as_slice());
+        desc
+    }
+
+    /// Project the descriptor of a detected corner; None if the corner has no descriptor
+    pub fn apply(&self, corner: &SaeEvent) -> Option<ProjectedCorner> {
+        corner.norm_descriptor.as_ref().map(|desc| ProjectedCorner {
+            event: SaeEvent { norm_descriptor: None, ..corner.clone() },
+            descriptor: self.project(desc),
+        })
+    }
+
+    /// Store the projection (little-endian f32s after a short header)
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(PROJECTION_MAGIC)?;
+        writer.write_all(&(self.dims() as u32).to_le_bytes())?;
+        writer.write_all(&self.total_variance.to_le_bytes())?;
+        // basis rows are stored one after another
+        let row_major = self.basis.transpose();
+        let values = self.mean.iter()
+            .chain(self.variances.iter())
+            .chain(row_major.iter());
+        for val in values {
+            writer.write_all(&val.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Load a projection stored with `write_to`
+    pub fn read_from<R: Read>(reader: R) -> io::Result<Self> {
+        let mut reader = OffsetReader::new(reader);
+        let mut magic = [0u8; 4];
+        reader.read_bytes(&mut magic)?;
+        if &magic != PROJECTION_MAGIC {
+            return Err(DecodeError::new(0, DecodeErrorKind::BadMagic).into());
+        }
+        let dims = reader.read_u32()? as usize;
+        if dims == 0 || dims > NORM_DESCRIPTOR_LEN {
+            return Err(DecodeError::new(4, DecodeErrorKind::Invalid("projection dimensions")).into());
+        }
+        let mut read_f32 = || reader.read_f32();
+        let total_variance = read_f32()?;
+        let mean = (0..NORM_DESCRIPTOR_LEN).map(|_| read_f32()).collect::<io::Result<Vec<f32>>>()?;
+        let variances = (0..dims).map(|_| read_f32()).collect::<io::Result<Vec<f32>>>()?;
+        let basis = (0..dims * NORM_DESCRIPTOR_LEN).map(|_| read_f32()).collect::<io::Result<Vec<f32>>>()?;
+        Ok(DescriptorProjection {
+            mean: DVector::from_vec(mean),
+            basis: DMatrix::from_row_slice(dims, NORM_DESCRIPTOR_LEN, &basis),
+            variances,
+            total_variance,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// descriptors that mostly vary along two directions
+    fn training_set() -> Vec<NormDescriptor> {
+        let mut rng = StdRng::seed_from_u64(3);
+        (0..200).map(|_| {
+            let a: f32 = rng.gen();
+            let b: f32 = rng.gen();
+            let mut desc = [0.0; NORM_DESCRIPTOR_LEN];
+            for (i, val) in desc.iter_mut().enumerate() {
+                let noise: f32 = rng.gen::<f32>() * 0.01;
+                *val = if i < DESCRIPTOR_C3_LEN { a } else { b } + noise;
+            }
+            desc
+        }).collect()
+    }
+
+    #[test]
+    fn test_train_and_project() {
+        let descriptors = training_set();
+        let projection = DescriptorProjection::train(&descriptors, 2).unwrap();
+        assert_eq!(projection.dims(), 2);
+        assert!(projection.explained_variance() > 0.95);
+
+        let rebuilt = projection.reconstruct(&projection.project(&descriptors[0]));
+        for (orig, approx) in descriptors[0].iter().zip(rebuilt.iter()) {
+            assert!((orig - approx).abs() < 0.05);
+        }
+        assert!(DescriptorProjection::train(&descriptors[..1], 2).is_none());
+
+        // a NaN descriptor, as from a blank ring, is left out of training
+        let mut with_blank = descriptors.clone();
+        with_blank.push([f32::NAN; NORM_DESCRIPTOR_LEN]);
+        let trained = DescriptorProjection::train(&with_blank, 2).unwrap();
+        assert!((trained.explained_variance() - projection.explained_variance()).abs() < 1e-4);
+        assert!(trained.project(&descriptors[0]).iter().all(|val| val.is_finite()));
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let projection = DescriptorProjection::train(&training_set(), 8).unwrap();
+        let mut stored = Vec::new();
+        projection.write_to(&mut stored).unwrap();
+        assert_eq!(stored.len(), 12 + 4 * (NORM_DESCRIPTOR_LEN + 8 + 8 * NORM_DESCRIPTOR_LEN));
+        let loaded = DescriptorProjection::read_from(&stored[..]).unwrap();
+        assert_eq!(loaded, projection);
+        assert!(DescriptorProjection::read_from(&b"nope0000"[..]).is_err());
+    }
+
+    #[test]
+    fn test_apply_to_corner() {
+        let descriptors = training_set();
+        let projection = DescriptorProjection::train(&descriptors, 4).unwrap();
+        let corner = SaeEvent { norm_descriptor: Some(Box::new(descriptors[5])), ..SaeEvent::new() };
+        let projected = projection.apply(&corner).unwrap();
+        assert_eq!(projected.descriptor.len(), 4);
+        assert!(projected.event.norm_descriptor.is_none());
+        assert!(projection.apply(&SaeEvent::new()).is_none());
+    }
+}
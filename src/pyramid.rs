@@ -0,0 +1,121 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Multi-scale corner detection over a pyramid of surfaces.
+//!
+//! Level `k` of the pyramid is a surface of the sensor downsampled by `2^k`: each event
+//! also updates the pixel `(row >> k, col >> k)` of every level, and is checked for a
+//! corner there. Coarse levels respond to features too large, or too blurred, for the
+//! fixed-size Arc* circles at full resolution. Each corner carries the scale of the level
+//! it was found at, and the pixel of the event that triggered it. The same feature is
+//! usually found at several levels: pass the corners through a `merge::ScaleMerger`
+//! to fuse them into one corner per feature.
+
+use crate::sae_types::*;
+use crate::sink::CornerSink;
+use crate::surface::{SaeSurface, WarmupConfig};
+
+
+/// Parameters of multi-scale detection
+#[derive(Clone, Debug, PartialEq)]
+pub struct PyramidConfig {
+    /// number of levels, including the full-resolution one
+    pub levels: usize,
+    pub warmup: WarmupConfig,
+}
+
+impl Default for PyramidConfig {
+    fn default() -> Self {
+        PyramidConfig {
+            levels: 3,
+            warmup: WarmupConfig::default(),
+        }
+    }
+}
+
+/// Detects corners at several scales of one polarity surface
+pub struct PyramidDetector {
+    levels: Vec<SaeSurface>,
+}
+
+impl PyramidDetector {
+    /// A pyramid for a sensor of `nrows` by `ncols` pixels
+    pub fn new(nrows: usize, ncols: usize, config: &PyramidConfig) -> Self {
+        let levels = (0..config.levels.max(1))
+            .map(|level| SaeSurface::with_warmup(nrows.div_ceil(1 << level), ncols.div_ceil(1 << level), config.warmup.clone()))
+            .collect();
+        PyramidDetector { levels }
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// the surface of level `level`
+    pub fn surface(&self, level: usize) -> &SaeSurface {
+        &self.levels[level]
+    }
+
+    /// Update every level with the event, passing the corners found to `sink`,
+    /// finest level first. Returns the number of corners found.
+    pub fn process<S: CornerSink + ?Sized>(&mut self, evt: &SaeEvent, sink: &mut S) -> usize {
+        let mut found = 0;
+        for (level, surface) in self.levels.iter_mut().enumerate() {
+            let factor = (1u32 << level) as f32;
+            let scaled = SaeEvent { row: evt.row >> level, col: evt.col >> level, ..evt.clone() };
+            if let Some(corner) = surface.update_and_detect(&scaled) {
+                // a refined position is mapped back from the level's pixel grid
+                let full = |pos: f32| (pos + 0.5) * factor - 0.5;
+                sink.accept(&SaeEvent {
+                    row: evt.row,
+                    col: evt.col,
+                    row_f: corner.row_f.map(full),
+                    col_f: corner.col_f.map(full),
+                    scale: Some(factor),
+                    ..corner
+                });
+                found += 1;
+            }
+        }
+        found
+    }
+
+    /// Clear every level, restarting their warm-up periods
+    pub fn reset(&mut self) {
+        self.levels.iter_mut().for_each(SaeSurface::reset);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{ScaleMergeConfig, ScaleMerger};
+
+    #[test]
+    fn test_pyramid_finds_scaled_corners() {
+        let config = PyramidConfig { levels: 2, warmup: WarmupConfig::disabled() };
+        let mut pyramid = PyramidDetector::new(64, 64, &config);
+        assert_eq!(pyramid.surface(1).shape(), (32, 32));
+
+        // a large block, a corner at both scales
+        let mut corners: Vec<SaeEvent> = Vec::new();
+        for row in 20..30 {
+            for col in 20..30 {
+                pyramid.process(&SaeEvent { row, col, timestamp: 7, ..SaeEvent::default() }, &mut corners);
+            }
+        }
+        pyramid.process(&SaeEvent { row: 29, col: 29, timestamp: 9, ..SaeEvent::default() }, &mut corners);
+        let last: Vec<&SaeEvent> = corners.iter().filter(|corner| corner.timestamp == 9).collect();
+        assert_eq!(last.iter().map(|corner| corner.scale).collect::<Vec<_>>(), vec![Some(1.0), Some(2.0)]);
+        assert!(last.iter().all(|corner| (corner.row, corner.col) == (29, 29)));
+
+        let mut merger = ScaleMerger::new(Vec::new(), ScaleMergeConfig::default());
+        for corner in last {
+            merger.accept(corner);
+        }
+        let merged = merger.into_inner();
+        assert_eq!(merged.len(), 1);
+        assert!((merged[0].scale.unwrap() - 2f32.sqrt()).abs() < 0.1);
+    }
+}
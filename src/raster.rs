@@ -0,0 +1,254 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Rasterization of corner detections into fixed-rate corner frames.
+//!
+//! Corners arrive asynchronously, but many consumers (controllers, CNNs) run at a
+//! fixed frequency. `CornerRaster` is a `CornerSink` that collects corners into frames
+//! of a fixed interval, aligned to multiples of the interval in event time, and reduces
+//! each frame by an aggregation policy when it closes. A frame closes once a corner
+//! past its end arrives, or once `advance` is called with a later time, eg from the
+//! event clock while no corners are detected.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::sae_types::*;
+use crate::sink::CornerSink;
+
+
+/// How the corners of a frame are reduced when it closes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RasterAggregation {
+    /// every corner, in arrival order
+    All,
+    /// the latest corner at each pixel
+    LatestPerPixel,
+    /// the most confident corner in each square cell of `cell` pixels
+    BestPerCell { cell: u16 },
+    /// the `count` most confident corners
+    Strongest { count: usize },
+}
+
+/// Configuration for `CornerRaster`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RasterConfig {
+    /// length of each frame
+    pub interval: SaeTime,
+    pub aggregation: RasterAggregation,
+    /// whether frames without corners are emitted, keeping the output at a fixed rate
+    pub emit_empty: bool,
+}
+
+impl Default for RasterConfig {
+    fn default() -> Self {
+        RasterConfig {
+            interval: 10_000,
+            aggregation: RasterAggregation::All,
+            emit_empty: true,
+        }
+    }
+}
+
+/// The corners of one interval
+#[derive(Clone, Debug, PartialEq)]
+pub struct CornerFrame {
+    /// the frame's start time divided by the interval
+    pub index: u64,
+    /// start time, inclusive
+    pub start: SaeTime,
+    /// end time, exclusive
+    pub end: SaeTime,
+    /// the aggregated corners, in time order
+    pub corners: Vec<SaeEvent>,
+}
+
+impl CornerFrame {
+    fn new(index: u64, interval: SaeTime) -> Self {
+        let start = (index * interval as u64) as SaeTime;
+        CornerFrame { index, start, end: start.saturating_add(interval), corners: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.corners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.corners.is_empty()
+    }
+
+    /// Row-major count of corners at each pixel, saturating at 255.
+    /// Corners outside the given shape are left out.
+    pub fn to_bitmap(&self, nrows: usize, ncols: usize) -> Vec<u8> {
+        let mut bitmap = vec![0u8; nrows * ncols];
+        for corner in self.corners.iter() {
+            let (row, col) = (corner.row as usize, corner.col as usize);
+            if row < nrows && col < ncols {
+                let cell = &mut bitmap[row * ncols + col];
+                *cell = cell.saturating_add(1);
+            }
+        }
+        bitmap
+    }
+
+    fn aggregate(&mut self, aggregation: RasterAggregation) {
+        match aggregation {
+            RasterAggregation::All => {}
+            RasterAggregation::LatestPerPixel => self.keep_best_per_cell(1, |_, _| true),
+            RasterAggregation::BestPerCell { cell } => {
+                self.keep_best_per_cell(cell, |corner, best| corner.confidence >= best.confidence)
+            }
+            RasterAggregation::Strongest { count } => {
+                let mut order: Vec<usize> = (0..self.corners.len()).collect();
+                // stable: among equally confident corners, the earlier ones are kept
+                order.sort_by(|&a, &b| self.corners[b].confidence.partial_cmp(&self.corners[a].confidence).unwrap());
+                order.truncate(count);
+                order.sort_unstable();
+                self.keep(order);
+            }
+        }
+    }
+
+    /// Keep one corner per cell: each corner replaces the kept one if `replaces` says so
+    fn keep_best_per_cell<F: Fn(&SaeEvent, &SaeEvent) -> bool>(&mut self, cell: u16, replaces: F) {
+        let cell = cell.max(1);
+        let mut best: HashMap<(u16, u16), usize> = HashMap::new();
+        for (idx, corner) in self.corners.iter().enumerate() {
+            let kept = best.entry((corner.row / cell, corner.col / cell)).or_insert(idx);
+            if replaces(corner, &self.corners[*kept]) {
+                *kept = idx;
+            }
+        }
+        let mut order: Vec<usize> = best.into_values().collect();
+        order.sort_unstable();
+        self.keep(order);
+    }
+
+    /// Keep the corners at the given ascending indices
+    fn keep(&mut self, order: Vec<usize>) {
+        let mut corners: Vec<Option<SaeEvent>> = self.corners.drain(..).map(Some).collect();
+        self.corners = order.into_iter().filter_map(|idx| corners[idx].take()).collect();
+    }
+}
+
+/// Collects corners into fixed-rate frames.
+/// Late corners, from before the current frame, join the current frame.
+pub struct CornerRaster {
+    config: RasterConfig,
+    current: Option<CornerFrame>,
+    completed: VecDeque<CornerFrame>,
+}
+
+impl CornerRaster {
+    pub fn new(config: RasterConfig) -> Self {
+        CornerRaster { config, current: None, completed: VecDeque::new() }
+    }
+
+    pub fn config(&self) -> &RasterConfig {
+        &self.config
+    }
+
+    /// Close every frame ending at or before `now`
+    pub fn advance(&mut self, now: SaeTime) {
+        let interval = self.config.interval.max(1);
+        let index = (now / interval) as u64;
+        while let Some(frame) = self.current.take_if(|frame| frame.index < index) {
+            let next = if self.config.emit_empty { frame.index + 1 } else { index };
+            self.close(frame);
+            self.current = Some(CornerFrame::new(next, interval));
+        }
+        if self.current.is_none() {
+            self.current = Some(CornerFrame::new(index, interval));
+        }
+    }
+
+    fn close(&mut self, mut frame: CornerFrame) {
+        frame.aggregate(self.config.aggregation);
+        if self.config.emit_empty || !frame.is_empty() {
+            self.completed.push_back(frame);
+        }
+    }
+
+    /// Take the closed frames, oldest first
+    pub fn take_frames(&mut self) -> Vec<CornerFrame> {
+        self.completed.drain(..).collect()
+    }
+
+    /// Close the current frame, eg at the end of the stream, and take every closed frame
+    pub fn flush(&mut self) -> Vec<CornerFrame> {
+        if let Some(frame) = self.current.take() {
+            self.close(frame);
+        }
+        self.take_frames()
+    }
+}
+
+impl CornerSink for CornerRaster {
+    fn accept(&mut self, corner: &SaeEvent) {
+        self.advance(corner.timestamp);
+        if let Some(frame) = self.current.as_mut() {
+            frame.corners.push(corner.clone());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corner(row: u16, col: u16, timestamp: SaeTime, confidence: f32) -> SaeEvent {
+        SaeEvent { row, col, timestamp, confidence: Some(confidence), ..SaeEvent::default() }
+    }
+
+    fn corners() -> Vec<SaeEvent> {
+        vec![
+            corner(1, 1, 100, 0.5),
+            corner(1, 1, 200, 0.2),
+            corner(2, 3, 300, 0.9),
+            corner(5, 5, 400, 0.4),
+            corner(4, 4, 2_500, 0.7),
+        ]
+    }
+
+    #[test]
+    fn test_fixed_rate_frames() {
+        let mut raster = CornerRaster::new(RasterConfig { interval: 1_000, ..RasterConfig::default() });
+        for corner in corners().iter() {
+            raster.accept(corner);
+        }
+        let frames = raster.take_frames();
+        assert_eq!(frames.iter().map(|f| (f.index, f.len())).collect::<Vec<_>>(), vec![(0, 4), (1, 0)]);
+        assert_eq!((frames[1].start, frames[1].end), (1_000, 2_000));
+        raster.advance(4_000);
+        let frames = raster.take_frames();
+        assert_eq!(frames.iter().map(|f| (f.index, f.len())).collect::<Vec<_>>(), vec![(2, 1), (3, 0)]);
+        assert_eq!(raster.flush().len(), 1);
+
+        let bitmap = frames[0].to_bitmap(8, 8);
+        assert_eq!(bitmap.iter().map(|&v| v as u32).sum::<u32>(), 1);
+        assert_eq!(bitmap[4 * 8 + 4], 1);
+
+        let mut sparse = CornerRaster::new(RasterConfig { interval: 1_000, emit_empty: false, ..RasterConfig::default() });
+        for corner in corners().iter() {
+            sparse.accept(corner);
+        }
+        sparse.advance(10_000);
+        let frames = sparse.flush();
+        assert_eq!(frames.iter().map(|f| f.index).collect::<Vec<_>>(), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_aggregation_policies() {
+        let frame_with = |aggregation| {
+            let mut raster = CornerRaster::new(RasterConfig { interval: 1_000, aggregation, emit_empty: true });
+            for corner in corners()[..4].iter() {
+                raster.accept(corner);
+            }
+            raster.flush().remove(0).corners.iter().map(|c| c.timestamp).collect::<Vec<_>>()
+        };
+        assert_eq!(frame_with(RasterAggregation::All), vec![100, 200, 300, 400]);
+        assert_eq!(frame_with(RasterAggregation::LatestPerPixel), vec![200, 300, 400]);
+        assert_eq!(frame_with(RasterAggregation::BestPerCell { cell: 4 }), vec![300, 400]);
+        assert_eq!(frame_with(RasterAggregation::Strongest { count: 2 }), vec![100, 300]);
+    }
+}
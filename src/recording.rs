@@ -0,0 +1,141 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! One-call processing of a recording file: decode, filter, detect and track with
+//! sensible defaults, for when assembling a `pipeline::Pipeline` by hand is more
+//! than the job needs.
+//!
+//! ```no_run
+//! let result = arcstar::process_recording("drive.arcstar", &arcstar::ProcessConfig::default()).unwrap();
+//! println!("{} corners in {} tracks", result.corners.len(), result.tracks.len());
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use crate::detector::DetectorConfig;
+use crate::io::compact::RecordingHeader;
+use crate::io::transcode::{covering_header, read_recording, EventFormat};
+use crate::pipeline::Pipeline;
+use crate::sae_filter::SaeFilterConfig;
+use crate::sae_types::*;
+use crate::track::{CornerTracker, Track, TrackerConfig};
+
+
+/// What `process_recording` does with the events
+#[derive(Clone, Debug)]
+pub struct ProcessConfig {
+    /// sensor geometry for files that don't record one;
+    /// by default, that just covering the events
+    pub header: Option<RecordingHeader>,
+    /// the Arc* event filter; None updates the surfaces with every event
+    pub sae_filter: Option<SaeFilterConfig>,
+    pub detector: DetectorConfig,
+    /// None skips tracking
+    pub tracker: Option<TrackerConfig>,
+}
+
+impl Default for ProcessConfig {
+    fn default() -> Self {
+        ProcessConfig {
+            header: None,
+            sae_filter: Some(SaeFilterConfig::default()),
+            detector: DetectorConfig::default(),
+            tracker: Some(TrackerConfig::default()),
+        }
+    }
+}
+
+/// Counts summarizing a processed recording
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecordingStats {
+    pub events: u64,
+    /// events discarded before reaching the surfaces, eg by the event filter
+    pub dropped: u64,
+    pub corners: u64,
+    /// time from the first to the last event
+    pub duration: SaeTime,
+}
+
+/// Everything found in a recording
+#[derive(Clone, Debug)]
+pub struct RecordingResult {
+    /// the sensor geometry the events were processed with
+    pub header: RecordingHeader,
+    pub corners: Vec<SaeEvent>,
+    /// every track, in id order; empty if tracking was skipped
+    pub tracks: Vec<Track>,
+    pub stats: RecordingStats,
+}
+
+/// Process a recording file, in the format given by its extension
+/// (see `io::transcode::EventFormat::from_extension`)
+pub fn process_recording<P: AsRef<Path>>(path: P, config: &ProcessConfig) -> io::Result<RecordingResult> {
+    let path = path.as_ref();
+    let format = path.extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(EventFormat::from_extension)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("unknown recording format: {}", path.display())))?;
+    let (header, events) = read_recording(format, BufReader::new(File::open(path)?))?;
+    let header = header.or_else(|| config.header.clone()).unwrap_or_else(|| covering_header(&events));
+    Ok(process_events(&header, &events, config))
+}
+
+/// Process events already in memory, as `process_recording` does
+pub fn process_events(header: &RecordingHeader, events: &[SaeEvent], config: &ProcessConfig) -> RecordingResult {
+    let mut pipeline = Pipeline::new(header, Vec::new());
+    pipeline.set_detector_config(config.detector.clone());
+    if let Some(sae_filter) = config.sae_filter.as_ref() {
+        pipeline.set_sae_filter(sae_filter.clone());
+    }
+    pipeline.run(events.iter().cloned());
+
+    let stats = RecordingStats {
+        events: pipeline.events_processed(),
+        dropped: pipeline.drops().total(),
+        corners: pipeline.corners_emitted(),
+        duration: match (events.first(), events.last()) {
+            (Some(first), Some(last)) => last.timestamp.elapsed_since(first.timestamp),
+            _ => 0,
+        },
+    };
+    let corners = std::mem::take(pipeline.sink_mut());
+    let tracks = match config.tracker.as_ref() {
+        Some(tracker_config) => {
+            let mut tracker = CornerTracker::new(tracker_config.clone());
+            for corner in corners.iter() {
+                tracker.add_corner(corner);
+            }
+            tracker.store().iter().cloned().collect()
+        }
+        None => Vec::new(),
+    };
+    RecordingResult { header: header.clone(), corners, tracks, stats }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surface::WarmupConfig;
+
+    #[test]
+    fn test_process_events() {
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let mut events = corner_block_events(10, 10, 1, 7);
+        events.push(SaeEvent { row: 40, col: 14, polarity: 1, timestamp: 100, ..SaeEvent::default() });
+
+        let config = ProcessConfig { sae_filter: None, ..ProcessConfig::default() };
+        let result = process_events(&header, &events, &config);
+        assert!(!result.corners.is_empty());
+        assert_eq!(result.stats, RecordingStats { events: 27, dropped: 1, corners: result.corners.len() as u64, duration: 93 });
+        let observations: usize = result.tracks.iter().map(|track| track.observations.len()).sum();
+        assert_eq!(observations, result.corners.len());
+
+        let untracked = process_events(&header, &events, &ProcessConfig { tracker: None, ..config });
+        assert!(untracked.tracks.is_empty());
+        assert_eq!(process_recording("events.txt", &ProcessConfig::default()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Recovery of stalled tracks by re-detection around their predicted positions.
+//!
+//! A feature passing through a patch of weak texture briefly stops producing
+//! corners the standard detector accepts, and its track ends and restarts as a new
+//! one on the far side. `Redetector` keeps the recent events, and when an active
+//! track has had no corner for `RedetectConfig::stall`, re-evaluates the recent
+//! events near the track's predicted position with a relaxed detector, and extends
+//! the track with the newest one accepted. An event is re-evaluated only while it is
+//! still the latest at its pixel, so that it is checked against the surface it formed.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::detector::{detect_and_compute_configured, DetectorConfig};
+use crate::predict::{CornerPredictor, PredictionConfig};
+use crate::sae_types::*;
+use crate::surface::{SaeSurface, WarmupConfig};
+use crate::track::{CornerTracker, TrackId};
+
+
+/// Parameters of re-detection
+#[derive(Clone, Debug)]
+pub struct RedetectConfig {
+    /// time without a corner after which an active track is searched for
+    pub stall: SaeTime,
+    /// how long events are kept for re-evaluation
+    pub history: SaeTime,
+    /// the relaxed detector events are re-evaluated with;
+    /// by default, the standard rings with wider arc length limits
+    pub detector: DetectorConfig,
+    /// search radius (pixels) around the last position of tracks without a prediction
+    pub radius: f32,
+    pub prediction: PredictionConfig,
+}
+
+impl Default for RedetectConfig {
+    fn default() -> Self {
+        RedetectConfig {
+            stall: 10_000,
+            history: 20_000,
+            detector: DetectorConfig::with_arc_limits((2, 7), (3, 10)).unwrap_or_default(),
+            radius: 4.0,
+            prediction: PredictionConfig::default(),
+        }
+    }
+}
+
+/// Counts of re-detection searches
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RedetectStats {
+    pub searches: u64,
+    /// searches that extended their track
+    pub recovered: u64,
+}
+
+/// Keeps recent events and searches them for the corners of stalled tracks
+pub struct Redetector {
+    config: RedetectConfig,
+    predictor: CornerPredictor,
+    /// OFF and ON surfaces, updated with every event
+    surfaces: [SaeSurface; 2],
+    recent: VecDeque<SaeEvent>,
+    /// time of the last search for each track, so a track is searched once per stall
+    last_search: HashMap<TrackId, SaeTime>,
+    stats: RedetectStats,
+}
+
+impl Redetector {
+    pub fn new(nrows: usize, ncols: usize, config: RedetectConfig) -> Self {
+        let surface = || SaeSurface::with_warmup(nrows, ncols, WarmupConfig::disabled());
+        Redetector {
+            predictor: CornerPredictor::new(config.prediction.clone()),
+            config,
+            surfaces: [surface(), surface()],
+            recent: VecDeque::new(),
+            last_search: HashMap::new(),
+            stats: RedetectStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> &RedetectConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &RedetectStats {
+        &self.stats
+    }
+
+    /// Record an event, in time order; every event of the stream should be observed
+    pub fn observe(&mut self, evt: &SaeEvent) {
+        if !self.surfaces[(evt.polarity > 0) as usize].update(evt) {
+            return;
+        }
+        self.recent.push_back(evt.clone());
+        let history = self.config.history;
+        while self.recent.front().is_some_and(|old| !old.timestamp.is_within(evt.timestamp, history)) {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Search for the active tracks of `tracker` stalled at `now`, extending those
+    /// recovered. Returns the recovered tracks with the corners found for them.
+    pub fn recover(&mut self, tracker: &mut CornerTracker, now: SaeTime) -> Vec<(TrackId, SaeEvent)> {
+        let mut recovered = Vec::new();
+        let active: Vec<TrackId> = tracker.active().to_vec();
+        self.last_search.retain(|id, _| active.contains(id));
+        for id in active {
+            let track = match tracker.store().get(id) {
+                Some(track) => track,
+                None => continue,
+            };
+            let last = track.last().timestamp;
+            let stalled_since = last.after(self.config.stall);
+            if now < stalled_since || self.last_search.get(&id).is_some_and(|&searched| searched >= stalled_since) {
+                continue;
+            }
+            self.last_search.insert(id, now);
+            self.stats.searches += 1;
+            let (last_row, last_col) = track.last().subpixel_position();
+            let found = self.recent.iter().rev()
+                .take_while(|evt| evt.timestamp > last)
+                .filter(|evt| {
+                    let (row, col) = evt.subpixel_position();
+                    let (position, radius) = match self.predictor.predict_at(track, evt.timestamp, None) {
+                        Some(prediction) => (prediction.position, prediction.radius.max(self.config.radius)),
+                        None => ([last_col, last_row], self.config.radius),
+                    };
+                    (col - position[0]).hypot(row - position[1]) <= radius
+                })
+                .find_map(|evt| {
+                    let surface = &self.surfaces[(evt.polarity > 0) as usize];
+                    if surface.matrix()[(evt.row as usize, evt.col as usize)] != evt.timestamp {
+                        return None;
+                    }
+                    detect_and_compute_configured(&self.config.detector, surface.matrix(), Some(surface.occupancy()), evt)
+                });
+            if let Some(corner) = found {
+                if tracker.extend_track(id, &corner) {
+                    self.stats.recovered += 1;
+                    recovered.push((id, corner));
+                }
+            }
+        }
+        recovered
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::track::TrackerConfig;
+
+    #[test]
+    fn test_recover_stalled_track() {
+        let mut tracker = CornerTracker::new(TrackerConfig { max_gap: 100_000, ..TrackerConfig::default() });
+        let id = tracker.add_corner(&SaeEvent { row: 12, col: 12, polarity: 1, timestamp: 1_000, ..SaeEvent::default() });
+        let mut redetector = Redetector::new(32, 32, RedetectConfig::default());
+
+        // a weak corner near the track: a block whose tip only the relaxed detector accepts
+        let mut events = Vec::new();
+        for row in 10..16 {
+            for col in 10..16 {
+                events.push(SaeEvent { row, col, polarity: 1, timestamp: 2_000, ..SaeEvent::default() });
+            }
+        }
+        events.push(SaeEvent { row: 14, col: 14, polarity: 1, timestamp: 2_100, ..SaeEvent::default() });
+        for evt in events.iter() {
+            redetector.observe(evt);
+        }
+        // not stalled yet
+        assert!(redetector.recover(&mut tracker, 5_000).is_empty());
+        let recovered = redetector.recover(&mut tracker, 12_000);
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].0, id);
+        assert_eq!(tracker.store().get(id).map(|track| track.len()), Some(2));
+        // searched once per stall: stalled again since the recovered corner, with nothing newer to find
+        assert!(redetector.recover(&mut tracker, 13_000).is_empty());
+        assert!(redetector.recover(&mut tracker, 14_000).is_empty());
+        assert_eq!(*redetector.stats(), RedetectStats { searches: 2, recovered: 1 });
+    }
+}
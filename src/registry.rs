@@ -0,0 +1,619 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! A registry of pipeline stages, so pipelines can be assembled from a config file
+//! and extended by downstream crates without forking.
+//!
+//! Filters, detectors and sinks are registered under a name, as factories building
+//! the stage from its own config section. A pipeline config is a small TOML document:
+//! the `[pipeline]` section names the stages, and every other section configures the
+//! stage of that name. A section's `type` selects the registered implementation,
+//! defaulting to the section name, so one implementation can appear twice with
+//! different settings:
+//!
+//! ```toml
+//! [pipeline]
+//! filters = ["denoise", "flicker"]
+//! detector = "arcstar"
+//! sink = "csv"
+//!
+//! [denoise]
+//! type = "row_column_denoiser"
+//! window = 2000
+//!
+//! [csv]
+//! path = "corners.csv"
+//! ```
+//!
+//! Only the subset of TOML needed here is understood: sections, and `key = value`
+//! lines whose values are strings, integers, floats, booleans or single-line arrays.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter};
+
+use crate::detector::{CornerDetector, DetectionPolicy, OnlineDetector, ReplayDetector};
+use crate::efast::EfastDetector;
+use crate::filter::{EventFilter, FilterChain};
+use crate::flicker::{FlickerConfig, FlickerFilter};
+use crate::noise::{BackgroundActivityFilter, HotPixelConfig, HotPixelFilter, RowColumnDenoiser};
+use crate::sae_filter::{SaeFilter, SaeFilterConfig};
+use crate::sae_types::*;
+use crate::sink::{CornerSink, CsvSink, RingBufferSink};
+#[cfg(feature = "net")]
+use crate::sink::UdpSink;
+use crate::source::EventSource;
+use crate::surface::RecordingHeader;
+
+
+/// A value in a config section
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<ConfigValue>),
+}
+
+/// The settings of one stage: a `[name]` section of the config
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigSection {
+    pub name: String,
+    pub values: BTreeMap<String, ConfigValue>,
+}
+
+impl ConfigSection {
+    pub fn new(name: &str) -> Self {
+        ConfigSection { name: name.to_string(), values: BTreeMap::new() }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ConfigValue> {
+        self.values.get(key)
+    }
+
+    fn wrong_type(&self, key: &str, expected: &str) -> io::Error {
+        invalid(&format!("[{}] {} should be {}", self.name, key, expected))
+    }
+
+    pub fn bool_or(&self, key: &str, default: bool) -> io::Result<bool> {
+        match self.get(key) {
+            None => Ok(default),
+            Some(ConfigValue::Bool(value)) => Ok(*value),
+            Some(_) => Err(self.wrong_type(key, "a boolean")),
+        }
+    }
+
+    /// an integer setting, checked to fit `T`
+    pub fn integer_or<T: std::convert::TryFrom<i64>>(&self, key: &str, default: T) -> io::Result<T> {
+        match self.get(key) {
+            None => Ok(default),
+            Some(ConfigValue::Integer(value)) => T::try_from(*value).map_err(|_| self.wrong_type(key, "in range")),
+            Some(_) => Err(self.wrong_type(key, "an integer")),
+        }
+    }
+
+    /// a numeric setting; integers are accepted too
+    pub fn float_or(&self, key: &str, default: f64) -> io::Result<f64> {
+        match self.get(key) {
+            None => Ok(default),
+            Some(ConfigValue::Float(value)) => Ok(*value),
+            Some(ConfigValue::Integer(value)) => Ok(*value as f64),
+            Some(_) => Err(self.wrong_type(key, "a number")),
+        }
+    }
+
+    pub fn string(&self, key: &str) -> io::Result<Option<&str>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(ConfigValue::String(value)) => Ok(Some(value)),
+            Some(_) => Err(self.wrong_type(key, "a string")),
+        }
+    }
+
+    /// a string setting that must be present
+    pub fn required_string(&self, key: &str) -> io::Result<&str> {
+        self.string(key)?.ok_or_else(|| invalid(&format!("[{}] needs {}", self.name, key)))
+    }
+
+    /// an array of integers, each checked to fit `T`
+    pub fn integers<T: std::convert::TryFrom<i64>>(&self, key: &str) -> io::Result<Option<Vec<T>>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(ConfigValue::Array(items)) => items.iter()
+                .map(|item| match item {
+                    ConfigValue::Integer(value) => T::try_from(*value).ok(),
+                    _ => None,
+                })
+                .collect::<Option<Vec<T>>>()
+                .map(Some)
+                .ok_or_else(|| self.wrong_type(key, "an array of integers")),
+            Some(_) => Err(self.wrong_type(key, "an array")),
+        }
+    }
+
+    /// an array of strings
+    pub fn strings(&self, key: &str) -> io::Result<Option<Vec<&str>>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(ConfigValue::Array(items)) => items.iter()
+                .map(|item| match item {
+                    ConfigValue::String(value) => Some(value.as_str()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<&str>>>()
+                .map(Some)
+                .ok_or_else(|| self.wrong_type(key, "an array of strings")),
+            Some(_) => Err(self.wrong_type(key, "an array")),
+        }
+    }
+}
+
+/// A parsed pipeline config
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PipelineConfig {
+    pub sections: BTreeMap<String, ConfigSection>,
+}
+
+impl PipelineConfig {
+    pub fn from_toml(text: &str) -> io::Result<Self> {
+        let mut sections: BTreeMap<String, ConfigSection> = BTreeMap::new();
+        let mut current = String::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let at_line = |what: &str| invalid(&format!("line {}: {}", idx + 1, what));
+            if line.starts_with('[') {
+                let name = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']'))
+                    .map(str::trim)
+                    .filter(|name| is_bare_key(name))
+                    .ok_or_else(|| at_line("bad section header"))?;
+                if sections.contains_key(name) {
+                    return Err(at_line("repeated section"));
+                }
+                sections.insert(name.to_string(), ConfigSection::new(name));
+                current = name.to_string();
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| at_line("expected key = value"))?;
+            let key = key.trim();
+            if !is_bare_key(key) {
+                return Err(at_line("bad key"));
+            }
+            let mut parser = ValueParser { text: value.trim(), pos: 0 };
+            let value = parser.value().ok_or_else(|| at_line("bad value"))?;
+            if !parser.rest().trim().is_empty() {
+                return Err(at_line("trailing characters"));
+            }
+            let section = sections.entry(current.clone()).or_insert_with(|| ConfigSection::new(&current));
+            if section.values.insert(key.to_string(), value).is_some() {
+                return Err(at_line("repeated key"));
+            }
+        }
+        Ok(PipelineConfig { sections })
+    }
+
+    /// the section configuring stage `name`, empty if there is none
+    pub fn section(&self, name: &str) -> ConfigSection {
+        self.sections.get(name).cloned().unwrap_or_else(|| ConfigSection::new(name))
+    }
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("pipeline config: {}", what))
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty() && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+/// the line without any comment, leaving `#` inside strings alone
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (idx, ch) in line.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+struct ValueParser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> ValueParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn value(&mut self) -> Option<ConfigValue> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        if rest.starts_with('"') {
+            return self.string().map(ConfigValue::String);
+        }
+        if rest.starts_with('[') {
+            return self.array();
+        }
+        let end = rest.find(|ch: char| ch == ',' || ch == ']' || ch.is_whitespace()).unwrap_or(rest.len());
+        let token = &rest[..end];
+        self.pos += end;
+        match token {
+            "true" => return Some(ConfigValue::Bool(true)),
+            "false" => return Some(ConfigValue::Bool(false)),
+            _ => {}
+        }
+        let digits = token.replace('_', "");
+        if let Ok(value) = digits.parse::<i64>() {
+            return Some(ConfigValue::Integer(value));
+        }
+        digits.parse::<f64>().ok().filter(|_| !token.is_empty()).map(ConfigValue::Float)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let mut out = String::new();
+        let mut chars = self.rest().char_indices().skip(1);
+        while let Some((idx, ch)) = chars.next() {
+            match ch {
+                '"' => {
+                    self.pos += idx + 1;
+                    return Some(out);
+                }
+                '\\' => out.push(match chars.next()?.1 {
+                    'n' => '\n',
+                    't' => '\t',
+                    '"' => '"',
+                    '\\' => '\\',
+                    _ => return None,
+                }),
+                _ => out.push(ch),
+            }
+        }
+        None
+    }
+
+    fn array(&mut self) -> Option<ConfigValue> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with(']') {
+                self.pos += 1;
+                return Some(ConfigValue::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_whitespace();
+            if self.rest().starts_with(',') {
+                self.pos += 1;
+            } else if !self.rest().starts_with(']') {
+                return None;
+            }
+        }
+    }
+}
+
+/// What a stage factory is given to build its stage
+pub struct StageContext<'a> {
+    /// geometry and warmup of the stream the pipeline will process
+    pub header: &'a RecordingHeader,
+    /// the stage's own settings
+    pub section: &'a ConfigSection,
+}
+
+/// Builds a stage of type `T` from its context
+pub type StageFactory<T> = Box<dyn Fn(&StageContext) -> io::Result<T>>;
+pub type FilterFactory = StageFactory<Box<dyn EventFilter>>;
+pub type DetectorFactory = StageFactory<Box<dyn CornerDetector>>;
+pub type SinkFactory = StageFactory<Box<dyn CornerSink>>;
+
+/// Named factories for filters, detectors and sinks
+#[derive(Default)]
+pub struct StageRegistry {
+    filters: BTreeMap<String, FilterFactory>,
+    detectors: BTreeMap<String, DetectorFactory>,
+    sinks: BTreeMap<String, SinkFactory>,
+}
+
+impl StageRegistry {
+    /// An empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry holding the stages of this crate:
+    /// - filters `row_column_denoiser` (`window`, `match_polarity`),
+    ///   `background_activity` (`window`), `hot_pixels` (`window`, `factor`, `min_events`),
+    ///   `flicker` (`periods`, `tolerance`, `max_multiple`, `min_periodic_hits`)
+    ///   and `sae_filter` (`threshold`)
+    /// - detectors `arcstar` (`policy`: one of `on_surface_only`, `off_surface_only`,
+    ///   `match_event_polarity`, `combined_max_surface`) and `efast`
+    /// - sinks `csv` (`path`), `udp` (`address`, with the `net` feature) and `ring_buffer` (`capacity`)
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register_filter("row_column_denoiser", |ctx| {
+            let section = ctx.section;
+            let denoiser = RowColumnDenoiser::new(ctx.header.nrows as usize, ctx.header.ncols as usize,
+                section.integer_or("window", 2_000)?)
+                .with_matching_polarity(section.bool_or("match_polarity", false)?);
+            Ok(Box::new(denoiser))
+        });
+        registry.register_filter("background_activity", |ctx| {
+            Ok(Box::new(BackgroundActivityFilter::new(ctx.header.nrows as usize, ctx.header.ncols as usize,
+                                                      ctx.section.integer_or("window", 2_000)?)))
+        });
+        registry.register_filter("hot_pixels", |ctx| {
+            let section = ctx.section;
+            let defaults = HotPixelConfig::default();
+            let config = HotPixelConfig {
+                window: section.integer_or("window", defaults.window)?,
+                factor: section.float_or("factor", defaults.factor as f64)? as f32,
+                min_events: section.integer_or("min_events", defaults.min_events)?,
+            };
+            Ok(Box::new(HotPixelFilter::new(ctx.header.nrows as usize, ctx.header.ncols as usize, config)))
+        });
+        registry.register_filter("flicker", |ctx| {
+            let section = ctx.section;
+            let defaults = FlickerConfig::default();
+            let config = FlickerConfig {
+                periods: section.integers("periods")?.unwrap_or(defaults.periods),
+                tolerance: section.integer_or("tolerance", defaults.tolerance)?,
+                max_multiple: section.integer_or("max_multiple", defaults.max_multiple)?,
+                min_periodic_hits: section.integer_or("min_periodic_hits", defaults.min_periodic_hits)?,
+            };
+            Ok(Box::new(FlickerFilter::new(ctx.header.nrows as usize, ctx.header.ncols as usize, config)))
+        });
+        registry.register_filter("sae_filter", |ctx| {
+            let config = SaeFilterConfig {
+                threshold: ctx.section.integer_or("threshold", SaeFilterConfig::default().threshold)?,
+            };
+            Ok(Box::new(SaeFilter::new(ctx.header.nrows as usize, ctx.header.ncols as usize, config)))
+        });
+        registry.register_detector("arcstar", |ctx| {
+            let mut detector = ReplayDetector::new(ctx.header);
+            let policy = match ctx.section.string("policy")? {
+                None => DetectionPolicy::default(),
+                Some("on_surface_only") => DetectionPolicy::OnSurfaceOnly,
+                Some("off_surface_only") => DetectionPolicy::OffSurfaceOnly,
+                Some("match_event_polarity") => DetectionPolicy::MatchEventPolarity,
+                Some("combined_max_surface") => DetectionPolicy::CombinedMaxSurface,
+                Some(other) => return Err(invalid(&format!("[{}] unknown policy {}", ctx.section.name, other))),
+            };
+            detector.set_detection_policy(policy);
+            Ok(Box::new(detector))
+        });
+        registry.register_detector("efast", |ctx| {
+            let header = ctx.header;
+            Ok(Box::new(OnlineDetector::new(EfastDetector::default(), header.nrows as usize, header.ncols as usize,
+                                            header.warmup.clone())))
+        });
+        registry.register_sink("csv", |ctx| {
+            let file = File::create(ctx.section.required_string("path")?)?;
+            Ok(Box::new(CsvSink::new(BufWriter::new(file))?))
+        });
+        #[cfg(feature = "net")]
+        registry.register_sink("udp", |ctx| {
+            Ok(Box::new(UdpSink::connect(ctx.section.required_string("address")?)?))
+        });
+        registry.register_sink("ring_buffer", |ctx| {
+            Ok(Box::new(RingBufferSink::new(ctx.section.integer_or("capacity", 1024)?)))
+        });
+        registry
+    }
+
+    /// Register a filter implementation, replacing any previously registered under `name`
+    pub fn register_filter<F>(&mut self, name: &str, factory: F)
+        where F: Fn(&StageContext) -> io::Result<Box<dyn EventFilter>> + 'static
+    {
+        self.filters.insert(name.to_string(), Box::new(factory));
+    }
+
+    /// Register a detector implementation, replacing any previously registered under `name`
+    pub fn register_detector<F>(&mut self, name: &str, factory: F)
+        where F: Fn(&StageContext) -> io::Result<Box<dyn CornerDetector>> + 'static
+    {
+        self.detectors.insert(name.to_string(), Box::new(factory));
+    }
+
+    /// Register a sink implementation, replacing any previously registered under `name`
+    pub fn register_sink<F>(&mut self, name: &str, factory: F)
+        where F: Fn(&StageContext) -> io::Result<Box<dyn CornerSink>> + 'static
+    {
+        self.sinks.insert(name.to_string(), Box::new(factory));
+    }
+
+    /// names of the registered filters, detectors and sinks
+    pub fn names(&self) -> (Vec<&str>, Vec<&str>, Vec<&str>) {
+        (self.filters.keys().map(String::as_str).collect(),
+         self.detectors.keys().map(String::as_str).collect(),
+         self.sinks.keys().map(String::as_str).collect())
+    }
+
+    /// Build every stage named by the config's `[pipeline]` section.
+    /// The pipeline needs a `detector` and a `sink`; `filters` is optional.
+    pub fn build(&self, config: &PipelineConfig, header: &RecordingHeader) -> io::Result<ConfiguredPipeline> {
+        let pipeline = config.section("pipeline");
+        let mut filters = FilterChain::new();
+        for name in pipeline.strings("filters")?.unwrap_or_default() {
+            filters.push(instantiate(&self.filters, "filter", name, config, header)?);
+        }
+        let detector = instantiate(&self.detectors, "detector", pipeline.required_string("detector")?, config, header)?;
+        let sink = instantiate(&self.sinks, "sink", pipeline.required_string("sink")?, config, header)?;
+        Ok(ConfiguredPipeline { filters, detector, sink, events_processed: 0, corners_emitted: 0 })
+    }
+}
+
+/// Build the stage `name`, with the implementation its section's `type` selects
+fn instantiate<T>(factories: &BTreeMap<String, StageFactory<T>>, role: &str,
+                  name: &str, config: &PipelineConfig, header: &RecordingHeader) -> io::Result<T> {
+    let section = config.section(name);
+    let kind = section.string("type")?.unwrap_or(name);
+    let factory = factories.get(kind)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("pipeline config: no {} named {}", role, kind)))?;
+    factory(&StageContext { header, section: &section })
+}
+
+/// A pipeline assembled from registered stages: filters, then a detector, then a sink
+pub struct ConfiguredPipeline {
+    filters: FilterChain,
+    detector: Box<dyn CornerDetector>,
+    sink: Box<dyn CornerSink>,
+    events_processed: u64,
+    corners_emitted: u64,
+}
+
+impl ConfiguredPipeline {
+    /// Process one event, returning whether it produced a corner
+    pub fn process(&mut self, evt: &SaeEvent) -> bool {
+        self.events_processed += 1;
+        if !self.filters.accept(evt) {
+            return false;
+        }
+        match self.detector.process(evt) {
+            Some(corner) => {
+                self.sink.accept(&corner);
+                self.corners_emitted += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn run<I: IntoIterator<Item = SaeEvent>>(&mut self, events: I) {
+        for evt in events {
+            self.process(&evt);
+        }
+    }
+
+    /// Process every event from the source until it is exhausted
+    pub fn run_source<E: EventSource>(&mut self, source: &mut E) -> io::Result<()> {
+        while let Some(evt) = source.next_event()? {
+            self.process(&evt);
+        }
+        Ok(())
+    }
+
+    pub fn events_processed(&self) -> u64 {
+        self.events_processed
+    }
+
+    pub fn corners_emitted(&self) -> u64 {
+        self.corners_emitted
+    }
+
+    pub fn sink_mut(&mut self) -> &mut dyn CornerSink {
+        self.sink.as_mut()
+    }
+
+    pub fn into_sink(self) -> Box<dyn CornerSink> {
+        self.sink
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::surface::WarmupConfig;
+
+    struct SharedSink(Rc<RefCell<Vec<SaeEvent>>>);
+
+    impl CornerSink for SharedSink {
+        fn accept(&mut self, corner: &SaeEvent) {
+            self.0.borrow_mut().push(corner.clone());
+        }
+    }
+
+    /// keeps only events from columns below a limit
+    struct ColumnLimit(u16);
+
+    impl EventFilter for ColumnLimit {
+        fn accept(&mut self, evt: &SaeEvent) -> bool {
+            evt.col < self.0
+        }
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = PipelineConfig::from_toml(r#"
+            # a comment
+            [pipeline]
+            filters = ["a", "b"]   # trailing comment
+            detector = "arcstar"
+
+            [a]
+            type = "flicker"
+            periods = [10_000, 8333]
+            tolerance = 250
+            scale = 1.5e0
+            enabled = true
+            label = "x # y \"z\""
+        "#).unwrap();
+        let pipeline = config.section("pipeline");
+        assert_eq!(pipeline.strings("filters").unwrap(), Some(vec!["a", "b"]));
+        let a = config.section("a");
+        assert_eq!(a.integers::<SaeTime>("periods").unwrap(), Some(vec![10_000, 8_333]));
+        assert_eq!(a.integer_or::<SaeTime>("tolerance", 0).unwrap(), 250);
+        assert_eq!(a.float_or("scale", 0.0).unwrap(), 1.5);
+        assert!(a.bool_or("enabled", false).unwrap());
+        assert_eq!(a.string("label").unwrap(), Some("x # y \"z\""));
+        assert!(a.integer_or::<i8>("tolerance", 0).is_err());
+        assert!(a.string("tolerance").is_err());
+        assert_eq!(config.section("missing").values.len(), 0);
+
+        for bad in ["[pipeline", "key", "key = ", "key = \"open", "a = 1\na = 2", "a = [1, 2", "a = 1 2"].iter() {
+            assert!(PipelineConfig::from_toml(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_build_pipeline() {
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let corners = Rc::new(RefCell::new(Vec::new()));
+        let mut registry = StageRegistry::with_builtins();
+        registry.register_filter("column_limit", |ctx| Ok(Box::new(ColumnLimit(ctx.section.integer_or("limit", 32)?))));
+        let shared = corners.clone();
+        registry.register_sink("shared", move |_| Ok(Box::new(SharedSink(shared.clone()))));
+
+        let config = PipelineConfig::from_toml(r#"
+            [pipeline]
+            filters = ["left"]
+            detector = "arcstar"
+            sink = "shared"
+
+            [left]
+            type = "column_limit"
+            limit = 20
+        "#).unwrap();
+        let mut pipeline = registry.build(&config, &header).unwrap();
+        let mut events = corner_block_events(10, 10, 0, 7);
+        events.push(SaeEvent { row: 14, col: 24, timestamp: 9, ..SaeEvent::default() });
+        pipeline.run(events.clone());
+        assert_eq!(pipeline.events_processed(), events.len() as u64);
+        assert!(pipeline.corners_emitted() > 0);
+        let corners = corners.borrow();
+        assert_eq!(corners.len() as u64, pipeline.corners_emitted());
+        let last = corners.last().unwrap();
+        assert_eq!((last.row, last.col, last.timestamp), (14, 14, 9));
+
+        let unknown = PipelineConfig::from_toml("[pipeline]\ndetector = \"arcstar\"\nsink = \"nowhere\"").unwrap();
+        assert_eq!(registry.build(&unknown, &header).err().unwrap().kind(), io::ErrorKind::NotFound);
+        let missing = PipelineConfig::from_toml("[pipeline]\nsink = \"shared\"").unwrap();
+        assert_eq!(registry.build(&missing, &header).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
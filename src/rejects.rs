@@ -0,0 +1,249 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Diagnostic dumps of rejected corner candidates, for finding out offline why
+//! expected corners are missed in a scene.
+//!
+//! `classify_rejection` names the first check of the configured detector that an
+//! event fails. `RejectDumper` writes a sample of rejected events to disk, one JSON
+//! line each: the event, the reason, the detector and dump configuration, and the
+//! patch of the surface around the event, with `null` for unobserved pixels and
+//! those outside the surface. Dumps are rate-limited in event time, and capped, so
+//! that a dump can be left enabled over a long recording.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::detector::{is_arc_valid, DetectorConfig, DetectorWork};
+use crate::eval::ring_json;
+use crate::sae_types::*;
+use crate::sink::LatchedWriter;
+use crate::surface::SaeSurface;
+
+
+/// The check a corner candidate failed, in the order the detector applies them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RejectReason {
+    /// the surface was still warming up
+    NotWarmedUp,
+    /// too close to the surface border for the rings
+    Border,
+    /// the event's own pixel is flagged dead
+    DeadPixel,
+    /// too few observed pixels on the inner ring to hold an arc
+    InnerUnobserved,
+    /// no valid arc on the inner ring
+    InnerArc,
+    /// too few observed pixels on the outer ring to hold an arc
+    OuterUnobserved,
+    /// no valid arc on the outer ring
+    OuterArc,
+}
+
+impl RejectReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RejectReason::NotWarmedUp => "not_warmed_up",
+            RejectReason::Border => "border",
+            RejectReason::DeadPixel => "dead_pixel",
+            RejectReason::InnerUnobserved => "inner_unobserved",
+            RejectReason::InnerArc => "inner_arc",
+            RejectReason::OuterUnobserved => "outer_unobserved",
+            RejectReason::OuterArc => "outer_arc",
+        }
+    }
+}
+
+/// Why the detector of `surface` (the default one, if it has none) rejects `evt`,
+/// which should already have been applied to the surface. None if it is a corner.
+pub fn classify_rejection(surface: &SaeSurface, evt: &SaeEvent) -> Option<RejectReason> {
+    if !surface.is_warmed_up() {
+        return Some(RejectReason::NotWarmedUp);
+    }
+    let default_config;
+    let config = match surface.detector_config() {
+        Some(config) => config,
+        None => {
+            default_config = DetectorConfig::default();
+            &default_config
+        }
+    };
+    let (row, col) = (evt.row as usize, evt.col as usize);
+    let inset = config.border_inset();
+    let (nrows, ncols) = surface.shape();
+    if row < inset || col < inset || row + inset >= nrows || col + inset >= ncols {
+        return Some(RejectReason::Border);
+    }
+    if config.dead_pixels.as_ref().is_some_and(|dead| dead[(row, col)]) {
+        return Some(RejectReason::DeadPixel);
+    }
+    let mut work = DetectorWork::default();
+    let rings = [
+        (&config.inner, RejectReason::InnerUnobserved, RejectReason::InnerArc),
+        (&config.outer, RejectReason::OuterUnobserved, RejectReason::OuterArc),
+    ];
+    for (ring, unobserved, arc) in rings.iter() {
+        let (vals, _, observed) = config.ring_vals(ring, surface.matrix(), Some(surface.occupancy()), evt, &mut work);
+        if observed < ring.min_arc_len() {
+            return Some(*unobserved);
+        }
+        if !is_arc_valid(&vals, ring) {
+            return Some(*arc);
+        }
+    }
+    None
+}
+
+/// What to dump, and how often
+#[derive(Clone, Debug, PartialEq)]
+pub struct RejectDumpConfig {
+    /// half-size of the dumped patch: (2 * radius + 1) pixels square
+    pub radius: usize,
+    /// least event time between two dumps
+    pub min_interval: SaeTime,
+    /// no more dumps are written after this many
+    pub max_dumps: u64,
+    /// reasons worth dumping; empty for all
+    pub reasons: Vec<RejectReason>,
+}
+
+impl Default for RejectDumpConfig {
+    fn default() -> Self {
+        RejectDumpConfig {
+            radius: DetectorConfig::default().border_inset(),
+            min_interval: 100_000,
+            max_dumps: 1_000,
+            reasons: Vec::new(),
+        }
+    }
+}
+
+/// Writes a rate-limited sample of rejected candidates as JSON lines, through a `LatchedWriter`
+pub struct RejectDumper<W: Write> {
+    writer: LatchedWriter<W>,
+    config: RejectDumpConfig,
+    last_dump: Option<SaeTime>,
+    dumped: u64,
+    rate_limited: u64,
+}
+
+impl RejectDumper<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, config: RejectDumpConfig) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?), config))
+    }
+}
+
+impl<W: Write> RejectDumper<W> {
+    pub fn new(writer: W, config: RejectDumpConfig) -> Self {
+        RejectDumper { writer: LatchedWriter::new(writer), config, last_dump: None, dumped: 0, rate_limited: 0 }
+    }
+
+    pub fn config(&self) -> &RejectDumpConfig {
+        &self.config
+    }
+
+    /// Consider an event after it was applied to `surface` and `detected` as a corner
+    /// or not. Returns the reason it was dumped for, if it was.
+    pub fn observe(&mut self, surface: &SaeSurface, evt: &SaeEvent, detected: bool) -> Option<RejectReason> {
+        if detected || self.writer.error().is_some() || self.dumped >= self.config.max_dumps {
+            return None;
+        }
+        let reason = classify_rejection(surface, evt)?;
+        if !self.config.reasons.is_empty() && !self.config.reasons.contains(&reason) {
+            return None;
+        }
+        if self.last_dump.is_some_and(|last| last.is_within(evt.timestamp, self.config.min_interval)) {
+            self.rate_limited += 1;
+            return None;
+        }
+        let line = self.dump_json(surface, evt, reason);
+        if !self.writer.write_with(|writer| writeln!(writer, "{}", line)) {
+            return None;
+        }
+        self.last_dump = Some(evt.timestamp);
+        self.dumped += 1;
+        Some(reason)
+    }
+
+    fn dump_json(&self, surface: &SaeSurface, evt: &SaeEvent, reason: RejectReason) -> String {
+        let default_config = DetectorConfig::default();
+        let detector = surface.detector_config().unwrap_or(&default_config);
+        let radius = self.config.radius as i64;
+        let (nrows, ncols) = surface.shape();
+        let patch: Vec<String> = (-radius..=radius)
+            .map(|dr| {
+                let values: Vec<String> = (-radius..=radius)
+                    .map(|dc| {
+                        let (row, col) = (evt.row as i64 + dr, evt.col as i64 + dc);
+                        if row < 0 || col < 0 || row as usize >= nrows || col as usize >= ncols ||
+                            !surface.is_observed(row as usize, col as usize) {
+                            return "null".to_string();
+                        }
+                        surface.matrix()[(row as usize, col as usize)].to_string()
+                    })
+                    .collect();
+                format!("[{}]", values.join(","))
+            })
+            .collect();
+        format!("{{\"timestamp\":{},\"row\":{},\"col\":{},\"polarity\":{},\"reason\":\"{}\",\
+                 \"config\":{{\"inner\":{},\"outer\":{},\"dead_pixels\":{},\"radius\":{},\"min_interval\":{}}},\
+                 \"patch\":[{}]}}",
+                evt.timestamp, evt.row, evt.col, evt.polarity, reason.as_str(),
+                ring_json(&detector.inner), ring_json(&detector.outer), detector.dead_pixels.is_some(),
+                self.config.radius, self.config.min_interval, patch.join(","))
+    }
+
+    /// number of candidates dumped
+    pub fn dumped(&self) -> u64 {
+        self.dumped
+    }
+
+    /// number of candidates worth dumping that were skipped by the rate limit
+    pub fn rate_limited(&self) -> u64 {
+        self.rate_limited
+    }
+
+    /// the first write error encountered, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.writer.error()
+    }
+
+    /// flush and return the underlying writer
+    pub fn finish(self) -> io::Result<W> {
+        self.writer.finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surface::WarmupConfig;
+
+    #[test]
+    fn test_rejects_are_classified_and_rate_limited() {
+        let mut surface = SaeSurface::with_warmup(32, 32, WarmupConfig::disabled());
+        let mut dumper = RejectDumper::new(Vec::new(), RejectDumpConfig { radius: 1, min_interval: 1_000, ..RejectDumpConfig::default() });
+
+        let border = SaeEvent { row: 1, col: 1, timestamp: 10, ..SaeEvent::default() };
+        surface.update(&border);
+        assert_eq!(dumper.observe(&surface, &border, false), Some(RejectReason::Border));
+
+        // an isolated event: its rings are unobserved, and the dump is rate-limited
+        let lone = SaeEvent { row: 16, col: 16, timestamp: 20, ..SaeEvent::default() };
+        surface.update(&lone);
+        assert_eq!(classify_rejection(&surface, &lone), Some(RejectReason::InnerUnobserved));
+        assert_eq!(dumper.observe(&surface, &lone, false), None);
+        assert_eq!(dumper.observe(&surface, &SaeEvent { timestamp: 2_000, ..lone.clone() }, false),
+                   Some(RejectReason::InnerUnobserved));
+        assert_eq!(dumper.observe(&surface, &lone, true), None);
+        assert_eq!((dumper.dumped(), dumper.rate_limited()), (2, 1));
+
+        let text = String::from_utf8(dumper.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("{\"timestamp\":10,\"row\":1,\"col\":1,\"polarity\":0,\"reason\":\"border\",\"config\":{\"inner\":"));
+        assert!(lines[1].ends_with("\"patch\":[[null,null,null],[null,20,null],[null,null,null]]}"));
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Rendering of surfaces, corners and tracks to images, for tuning the detector
+//! by eye without leaving the pipeline.
+//!
+//! `render_sae` draws a surface as a time-decayed image: each observed pixel is lit
+//! by `exp(-age / decay)` of its latest event, through a colormap, so fresh edges
+//! stand out against older ones. Corners are overlaid as crosses and tracks as paths.
+//! `FrameRenderer` does the same at a fixed frame rate in event time, fed with the
+//! event and corner streams, and its frames can be written as numbered PNG files
+//! with `save_png_frames`.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::Path;
+
+use image::{Rgb, RgbImage};
+
+use crate::sae_types::*;
+use crate::sink::CornerSink;
+use crate::track::{Track, TrackId};
+
+
+/// How surface brightness (0..1) maps to color
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Colormap {
+    #[default]
+    Gray,
+    /// black through red and yellow to white
+    Heat,
+}
+
+impl Colormap {
+    pub fn color(self, value: f32) -> Rgb<u8> {
+        let value = value.clamp(0.0, 1.0);
+        match self {
+            Colormap::Gray => {
+                let level = (value * 255.0).round() as u8;
+                Rgb([level, level, level])
+            }
+            Colormap::Heat => {
+                let channel = |start: f32| (((value - start) * 3.0).clamp(0.0, 1.0) * 255.0).round() as u8;
+                Rgb([channel(0.0), channel(1.0 / 3.0), channel(2.0 / 3.0)])
+            }
+        }
+    }
+}
+
+/// Parameters of rendering
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderConfig {
+    /// time constant of the brightness decay of surface pixels
+    pub decay: SaeTime,
+    pub colormap: Colormap,
+    pub corner_color: [u8; 3],
+    pub track_color: [u8; 3],
+    /// half-length of the arms of corner crosses, in pixels
+    pub marker_radius: u32,
+    /// how long corners and track paths stay drawn by `FrameRenderer`
+    pub trail: SaeTime,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfig {
+            decay: 30_000,
+            colormap: Colormap::Gray,
+            corner_color: [255, 0, 0],
+            track_color: [0, 255, 0],
+            marker_radius: 2,
+            trail: 100_000,
+        }
+    }
+}
+
+/// Render a surface as seen at `now`; pixels unobserved in `occupancy`, or without
+/// it, those still at zero, are black
+pub fn render_sae(sae: &SaeMatrix, occupancy: Option<&SaeOccupancy>, now: SaeTime, config: &RenderConfig) -> RgbImage {
+    let (nrows, ncols) = sae.shape();
+    let decay = config.decay.max(1) as f32;
+    RgbImage::from_fn(ncols as u32, nrows as u32, |x, y| {
+        let pos = (y as usize, x as usize);
+        let observed = match occupancy {
+            Some(occupancy) => occupancy[pos],
+            None => sae[pos] != 0,
+        };
+        if !observed {
+            return Rgb([0, 0, 0]);
+        }
+        let age = now.saturating_sub(sae[pos]) as f32;
+        config.colormap.color((-age / decay).exp())
+    })
+}
+
+fn put_pixel(img: &mut RgbImage, row: i64, col: i64, color: Rgb<u8>) {
+    if row >= 0 && col >= 0 && (row as u32) < img.height() && (col as u32) < img.width() {
+        img.put_pixel(col as u32, row as u32, color);
+    }
+}
+
+/// Draw a cross at a (sub-pixel) position, clipped to the image
+pub fn draw_marker(img: &mut RgbImage, row: f32, col: f32, radius: u32, color: Rgb<u8>) {
+    let (row, col) = (row.round() as i64, col.round() as i64);
+    let radius = radius as i64;
+    for offset in -radius..=radius {
+        put_pixel(img, row + offset, col, color);
+        put_pixel(img, row, col + offset, color);
+    }
+}
+
+/// Draw a straight line between two (row, col) positions, clipped to the image
+pub fn draw_line(img: &mut RgbImage, from: (f32, f32), to: (f32, f32), color: Rgb<u8>) {
+    let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().max(1.0) as usize;
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+        let row = from.0 + (to.0 - from.0) * t;
+        let col = from.1 + (to.1 - from.1) * t;
+        put_pixel(img, row.round() as i64, col.round() as i64, color);
+    }
+}
+
+/// Overlay corners as crosses
+pub fn draw_corners(img: &mut RgbImage, corners: &[SaeEvent], config: &RenderConfig) {
+    for corner in corners.iter() {
+        let (row, col) = corner.subpixel_position();
+        draw_marker(img, row, col, config.marker_radius, Rgb(config.corner_color));
+    }
+}
+
+/// Overlay the path of a track through its observations since `since`, marking its last position
+pub fn draw_track(img: &mut RgbImage, track: &Track, since: SaeTime, config: &RenderConfig) {
+    draw_path(img, track.observations.iter().filter(|obs| obs.timestamp >= since), config);
+}
+
+fn draw_path<'a, I: Iterator<Item = &'a SaeEvent>>(img: &mut RgbImage, observations: I, config: &RenderConfig) {
+    let color = Rgb(config.track_color);
+    let mut previous = None;
+    for obs in observations {
+        let position = obs.subpixel_position();
+        if let Some(previous) = previous {
+            draw_line(img, previous, position, color);
+        }
+        previous = Some(position);
+    }
+    if let Some((row, col)) = previous {
+        draw_marker(img, row, col, config.marker_radius, color);
+    }
+}
+
+/// A frame rendered by `FrameRenderer`
+#[derive(Clone, Debug)]
+pub struct RenderedFrame {
+    /// the time the frame shows, a multiple of the frame interval
+    pub timestamp: SaeTime,
+    pub image: RgbImage,
+}
+
+/// Renders the event stream at a fixed frame rate in event time: the surface of the
+/// latest events of either polarity, with the corners and track paths of the last
+/// `RenderConfig::trail`. A frame is rendered once an event at or past its time arrives,
+/// or once `advance` is called with such a time.
+pub struct FrameRenderer {
+    config: RenderConfig,
+    interval: SaeTime,
+    sae: SaeMatrix,
+    seen: SaeOccupancy,
+    corners: VecDeque<SaeEvent>,
+    tracks: HashMap<TrackId, VecDeque<SaeEvent>>,
+    next_frame: Option<SaeTime>,
+    frames: Vec<RenderedFrame>,
+}
+
+impl FrameRenderer {
+    /// Render frames every `interval` for a sensor of `nrows` by `ncols` pixels
+    pub fn new(nrows: usize, ncols: usize, interval: SaeTime, config: RenderConfig) -> Self {
+        FrameRenderer {
+            config,
+            interval: interval.max(1),
+            sae: SaeMatrix::zeros(nrows, ncols),
+            seen: SaeOccupancy::from_element(nrows, ncols, false),
+            corners: VecDeque::new(),
+            tracks: HashMap::new(),
+            next_frame: None,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &RenderConfig {
+        &self.config
+    }
+
+    /// Render every frame due before `now`
+    pub fn advance(&mut self, now: SaeTime) {
+        let interval = self.interval;
+        let mut next = *self.next_frame.get_or_insert((now / interval + 1) * interval);
+        while now >= next {
+            let image = self.render(next);
+            self.frames.push(RenderedFrame { timestamp: next, image });
+            next = next.saturating_add(interval);
+        }
+        self.next_frame = Some(next);
+    }
+
+    /// Apply an event to the surface, after rendering the frames due before it
+    pub fn observe_event(&mut self, evt: &SaeEvent) {
+        self.advance(evt.timestamp);
+        let pos = (evt.row as usize, evt.col as usize);
+        if pos.0 < self.sae.nrows() && pos.1 < self.sae.ncols() {
+            self.sae[pos] = evt.timestamp;
+            self.seen[pos] = true;
+        }
+    }
+
+    /// Add a corner to the overlay, extending the path of its track if it has one
+    pub fn observe_corner(&mut self, corner: &SaeEvent, track: Option<TrackId>) {
+        self.advance(corner.timestamp);
+        match track {
+            Some(id) => self.tracks.entry(id).or_default().push_back(corner.clone()),
+            None => self.corners.push_back(corner.clone()),
+        }
+    }
+
+    /// Render the current state as seen at `now`, leaving out corners older than the trail
+    pub fn render(&mut self, now: SaeTime) -> RgbImage {
+        let since = now.saturating_sub(self.config.trail);
+        while self.corners.front().is_some_and(|corner| corner.timestamp < since) {
+            self.corners.pop_front();
+        }
+        self.tracks.retain(|_, path| {
+            while path.front().is_some_and(|obs| obs.timestamp < since) {
+                path.pop_front();
+            }
+            !path.is_empty()
+        });
+        let mut img = render_sae(&self.sae, Some(&self.seen), now, &self.config);
+        let visible: Vec<SaeEvent> = self.corners.iter().filter(|corner| corner.timestamp <= now).cloned().collect();
+        draw_corners(&mut img, &visible, &self.config);
+        for path in self.tracks.values() {
+            draw_path(&mut img, path.iter().filter(|obs| obs.timestamp <= now), &self.config);
+        }
+        img
+    }
+
+    /// Take the frames rendered so far
+    pub fn take_frames(&mut self) -> Vec<RenderedFrame> {
+        std::mem::take(&mut self.frames)
+    }
+}
+
+impl CornerSink for FrameRenderer {
+    fn accept(&mut self, corner: &SaeEvent) {
+        self.observe_corner(corner, None);
+    }
+}
+
+/// Write frames as `frame_NNNNNN.png` in `dir`, numbered from `first_index`
+pub fn save_png_frames<P: AsRef<Path>>(frames: &[RenderedFrame], dir: P, first_index: usize) -> io::Result<()> {
+    for (idx, frame) in frames.iter().enumerate() {
+        let path = dir.as_ref().join(format!("frame_{:06}.png", first_index + idx));
+        frame.image.save(&path).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_frames_with_overlays() {
+        let config = RenderConfig { decay: 1_000, ..RenderConfig::default() };
+        let mut sae = SaeMatrix::zeros(8, 10);
+        sae[(2, 3)] = 1_000;
+        let img = render_sae(&sae, None, 1_000, &config);
+        assert_eq!((img.width(), img.height()), (10, 8));
+        assert_eq!(*img.get_pixel(3, 2), Rgb([255, 255, 255]));
+        assert_eq!(*img.get_pixel(0, 0), Rgb([0, 0, 0]));
+        assert_eq!((Colormap::Heat.color(0.0), Colormap::Heat.color(1.0)), (Rgb([0, 0, 0]), Rgb([255, 255, 255])));
+
+        let mut renderer = FrameRenderer::new(16, 16, 1_000, config);
+        renderer.observe_event(&SaeEvent { row: 8, col: 8, timestamp: 100, ..SaeEvent::default() });
+        renderer.observe_corner(&SaeEvent { row: 4, col: 4, timestamp: 200, ..SaeEvent::default() }, None);
+        renderer.observe_corner(&SaeEvent { row: 12, col: 2, timestamp: 300, ..SaeEvent::default() }, Some(7));
+        renderer.observe_corner(&SaeEvent { row: 12, col: 6, timestamp: 400, ..SaeEvent::default() }, Some(7));
+        // frames at 1000 and 2000
+        renderer.observe_event(&SaeEvent { row: 8, col: 9, timestamp: 2_500, ..SaeEvent::default() });
+        let frames = renderer.take_frames();
+        assert_eq!(frames.iter().map(|frame| frame.timestamp).collect::<Vec<_>>(), vec![1_000, 2_000]);
+        let img = &frames[0].image;
+        assert_eq!(*img.get_pixel(4, 4), Rgb([255, 0, 0]));
+        assert_eq!(*img.get_pixel(4, 12), Rgb([0, 255, 0]));
+        assert_eq!(*img.get_pixel(8, 9), Rgb([0, 0, 0]));
+        let lit = img.get_pixel(8, 8)[0];
+        assert!(lit > 0 && lit < 255);
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Time-reversed processing of recordings, for offline analysis.
+//!
+//! A recording played backwards is a valid event stream in its own right: timestamps
+//! are mirrored about the end of the recording, so that the freshest pixels of the
+//! surface are those earliest in real time, and polarities are inverted, since a pixel
+//! brightening forward in time darkens backward in time. Tracks built from the reversed
+//! stream, once restored to forward time, should retrace the forward tracks of the
+//! same features. `check_consistency` compares the two, and `prune_inconsistent` keeps
+//! only the forward tracks confirmed by a backward track: a slow but effective filter
+//! for producing high-purity reference tracks.
+
+use crate::detector::DetectorConfig;
+use crate::pipeline::Pipeline;
+use crate::sae_types::*;
+use crate::surface::RecordingHeader;
+use crate::track::{CornerTracker, Track, TrackId, TrackStore, TrackerConfig};
+
+
+/// Mirrors timestamps about a fixed end time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeReversal {
+    end: SaeTime,
+}
+
+impl TimeReversal {
+    pub fn new(end: SaeTime) -> Self {
+        TimeReversal { end }
+    }
+
+    /// Mirrors about the last of time-ordered `events`, so that the reversed stream starts at zero
+    pub fn for_events(events: &[SaeEvent]) -> Option<Self> {
+        events.last().map(|last| Self::new(last.timestamp))
+    }
+
+    pub fn end(&self) -> SaeTime {
+        self.end
+    }
+
+    /// the mirrored time; times after the end map to zero
+    pub fn time(&self, timestamp: SaeTime) -> SaeTime {
+        self.end.saturating_sub(timestamp)
+    }
+
+    /// An event as seen in reversed time: mirrored timestamp and inverted polarity.
+    /// The mapping is its own inverse, so it also restores reversed corners to forward time.
+    pub fn event(&self, evt: &SaeEvent) -> SaeEvent {
+        SaeEvent {
+            timestamp: self.time(evt.timestamp),
+            polarity: if evt.polarity > 0 { 0 } else { 1 },
+            ..evt.clone()
+        }
+    }
+
+    /// The reversed stream of time-ordered `events`, itself in time order
+    pub fn events(&self, events: &[SaeEvent]) -> Vec<SaeEvent> {
+        events.iter().rev().map(|evt| self.event(evt)).collect()
+    }
+
+    /// A track built in reversed time, restored to forward time, with time-ordered observations
+    pub fn restore_track(&self, track: &Track) -> Track {
+        Track {
+            id: track.id,
+            observations: track.observations.iter().rev().map(|obs| self.event(obs)).collect(),
+        }
+    }
+
+    /// Tracks built in reversed time, restored to forward time.
+    /// Tracks are renumbered, in the order of their original ids.
+    pub fn restore_store(&self, store: &TrackStore) -> TrackStore {
+        let mut restored = TrackStore::new();
+        for track in store.iter() {
+            let mut observations = self.restore_track(track).observations.into_iter();
+            if let Some(first) = observations.next() {
+                let id = restored.start_track(first);
+                for obs in observations {
+                    restored.extend_track(id, obs);
+                }
+            }
+        }
+        restored
+    }
+}
+
+/// Detect and track corners in time-ordered `events` both forward and in reverse.
+/// Returns the forward tracks and the backward tracks restored to forward time.
+pub fn track_both_ways(header: &RecordingHeader, events: &[SaeEvent], detector: &DetectorConfig,
+                       tracker: &TrackerConfig) -> (TrackStore, TrackStore) {
+    let track = |events: Vec<SaeEvent>| {
+        let mut pipeline = Pipeline::new(header, Vec::new());
+        pipeline.set_detector_config(detector.clone());
+        pipeline.run(events);
+        let mut tracker = CornerTracker::new(tracker.clone());
+        for corner in pipeline.into_sink().iter() {
+            tracker.add_corner(corner);
+        }
+        tracker.store().clone()
+    };
+    let reversal = match TimeReversal::for_events(events) {
+        Some(reversal) => reversal,
+        None => return (TrackStore::new(), TrackStore::new()),
+    };
+    let forward = track(events.to_vec());
+    let backward = track(reversal.events(events));
+    (forward, reversal.restore_store(&backward))
+}
+
+/// Tolerances for confirming a forward track by a backward track
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConsistencyConfig {
+    /// largest distance (pixels) of a forward observation from the backward track
+    /// at the same time, for the observation to be confirmed
+    pub max_distance: f32,
+    /// smallest fraction of the forward observations that must be confirmed
+    pub min_coverage: f32,
+}
+
+impl Default for ConsistencyConfig {
+    fn default() -> Self {
+        ConsistencyConfig {
+            max_distance: 2.0,
+            min_coverage: 0.8,
+        }
+    }
+}
+
+/// How well a forward track is retraced by its best matching backward track
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackConsistency {
+    pub forward: TrackId,
+    /// the best matching backward track, if any overlaps the forward track
+    pub backward: Option<TrackId>,
+    /// fraction of the forward observations confirmed by the backward track
+    pub coverage: f32,
+    /// mean distance (pixels) of the confirmed observations from the backward track
+    pub mean_distance: f32,
+}
+
+impl TrackConsistency {
+    pub fn is_consistent(&self, config: &ConsistencyConfig) -> bool {
+        self.backward.is_some() && self.coverage >= config.min_coverage
+    }
+}
+
+/// (coverage, mean distance) of `forward` against `backward`
+fn compare_tracks(forward: &Track, backward: &Track, max_distance: f32) -> (f32, f32) {
+    let mut confirmed = 0;
+    let mut total_distance = 0.0;
+    for obs in forward.observations.iter() {
+        if let Some([x, y]) = backward.position_at(obs.timestamp) {
+            let (row, col) = obs.subpixel_position();
+            let distance = (x - col).hypot(y - row);
+            if distance <= max_distance {
+                confirmed += 1;
+                total_distance += distance;
+            }
+        }
+    }
+    let coverage = confirmed as f32 / forward.len() as f32;
+    let mean_distance = if confirmed > 0 { total_distance / confirmed as f32 } else { 0.0 };
+    (coverage, mean_distance)
+}
+
+/// Match each forward track to the backward track (restored to forward time)
+/// that best retraces it, in forward track id order
+pub fn check_consistency(forward: &TrackStore, backward: &TrackStore, config: &ConsistencyConfig) -> Vec<TrackConsistency> {
+    forward.iter()
+        .map(|track| {
+            let mut best = TrackConsistency { forward: track.id, backward: None, coverage: 0.0, mean_distance: 0.0 };
+            let overlapping = backward.iter().filter(|candidate|
+                candidate.first().timestamp <= track.last().timestamp && candidate.last().timestamp >= track.first().timestamp);
+            for candidate in overlapping {
+                let (coverage, mean_distance) = compare_tracks(track, candidate, config.max_distance);
+                if coverage == 0.0 {
+                    continue;
+                }
+                if best.backward.is_none() || coverage > best.coverage ||
+                    (coverage == best.coverage && mean_distance < best.mean_distance) {
+                    best = TrackConsistency { forward: track.id, backward: Some(candidate.id), coverage, mean_distance };
+                }
+            }
+            best
+        })
+        .collect()
+}
+
+/// Ids of the forward tracks not confirmed by any backward track
+pub fn inconsistent_tracks(forward: &TrackStore, backward: &TrackStore, config: &ConsistencyConfig) -> Vec<TrackId> {
+    check_consistency(forward, backward, config).into_iter()
+        .filter(|result| !result.is_consistent(config))
+        .map(|result| result.forward)
+        .collect()
+}
+
+/// Like `inconsistent_tracks`, removing them from the forward store
+pub fn prune_inconsistent(forward: &mut TrackStore, backward: &TrackStore, config: &ConsistencyConfig) -> Vec<TrackId> {
+    let flagged = inconsistent_tracks(forward, backward, config);
+    for id in flagged.iter() {
+        forward.remove(*id);
+    }
+    flagged
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surface::WarmupConfig;
+
+    fn corner_at(row: u16, col: u16, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row, col, timestamp, polarity: 1, ..SaeEvent::default() }
+    }
+
+    fn store_of(tracks: &[Vec<SaeEvent>]) -> TrackStore {
+        let mut store = TrackStore::new();
+        for observations in tracks.iter() {
+            let id = store.start_track(observations[0].clone());
+            for obs in observations[1..].iter() {
+                store.extend_track(id, obs.clone());
+            }
+        }
+        store
+    }
+
+    #[test]
+    fn test_reversal_round_trip() {
+        let events = vec![corner_at(1, 1, 100), corner_at(2, 2, 150), corner_at(3, 3, 400)];
+        let reversal = TimeReversal::for_events(&events).unwrap();
+        let reversed = reversal.events(&events);
+        let times: Vec<SaeTime> = reversed.iter().map(|evt| evt.timestamp).collect();
+        assert_eq!(times, vec![0, 250, 300]);
+        assert!(reversed.iter().all(|evt| evt.polarity == 0));
+        assert_eq!(reversed[0].row, 3);
+        let restored: Vec<SaeEvent> = reversal.events(&reversed);
+        assert_eq!(restored, events);
+
+        // a track found in reversed time runs forward once restored
+        let backward = store_of(std::slice::from_ref(&reversed));
+        let restored = reversal.restore_store(&backward);
+        let track = restored.iter().next().unwrap();
+        assert_eq!(track.observations, events);
+    }
+
+    #[test]
+    fn test_consistency_check() {
+        let forward = store_of(&[
+            (0..10).map(|i| corner_at(10, 10 + i, i as SaeTime * 100)).collect(),
+            // a spurious track with no backward counterpart
+            (0..5).map(|i| corner_at(30, 30, i as SaeTime * 100)).collect(),
+            // retraced for only its first half
+            (0..10).map(|i| corner_at(20, 10 + i, i as SaeTime * 100)).collect(),
+        ]);
+        let backward = store_of(&[
+            (0..10).map(|i| corner_at(20, 10 + i.min(4), i as SaeTime * 100)).collect(),
+            (0..6).map(|i| corner_at(11, 10 + 2 * i, i as SaeTime * 200)).collect(),
+        ]);
+        let config = ConsistencyConfig::default();
+        let results = check_consistency(&forward, &backward, &config);
+        assert_eq!(results[0].backward, Some(1));
+        assert_eq!(results[0].coverage, 1.0);
+        assert!((results[0].mean_distance - 1.0).abs() < 1e-6);
+        assert_eq!(results[1].backward, None);
+        assert_eq!(results[2].backward, Some(0));
+        assert!((results[2].coverage - 0.7).abs() < 1e-6);
+
+        let mut pruned = forward.clone();
+        assert_eq!(prune_inconsistent(&mut pruned, &backward, &config), vec![1, 2]);
+        assert_eq!(pruned.len(), 1);
+
+        let header = RecordingHeader::new(16, 16, WarmupConfig::disabled());
+        let (forward, backward) = track_both_ways(&header, &[], &DetectorConfig::default(), &TrackerConfig::default());
+        assert!(forward.is_empty() && backward.is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! The event filter of the Arc* paper, which maintains the "filtered SAE".
+//!
+//! A moving edge makes each pixel fire a burst of events of the same polarity.
+//! Only the first event of a burst carries new information about the edge, and
+//! the rest, checked for corners against a surface they keep refreshing, mostly
+//! produce redundant or spurious detections. The filter keeps the time of the
+//! latest event of each polarity at every pixel, whether or not it passed, and
+//! passes an event only if it comes more than a threshold after the latest event
+//! of its polarity at its pixel, or after a more recent event of the other polarity.
+//! Events that don't pass neither update the surface nor are checked for corners,
+//! as in the reference implementation.
+
+use crate::filter::EventFilter;
+use crate::sae_types::*;
+use crate::time::rebase_sae;
+
+
+/// Parameters of the Arc* event filter
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaeFilterConfig {
+    /// least time after the latest event of the same polarity at a pixel
+    /// for an event to pass; 50 ms in the paper
+    pub threshold: SaeTime,
+}
+
+impl Default for SaeFilterConfig {
+    fn default() -> Self {
+        SaeFilterConfig { threshold: 50_000 }
+    }
+}
+
+/// Passes the events that update the filtered SAE
+pub struct SaeFilter {
+    config: SaeFilterConfig,
+    /// latest event time of each polarity at each pixel, OFF then ON
+    latest: [SaeMatrix; 2],
+    seen: [SaeOccupancy; 2],
+    rejected: u64,
+}
+
+impl SaeFilter {
+    pub fn new(nrows: usize, ncols: usize, config: SaeFilterConfig) -> Self {
+        SaeFilter {
+            config,
+            latest: [SaeMatrix::zeros(nrows, ncols), SaeMatrix::zeros(nrows, ncols)],
+            seen: [SaeOccupancy::from_element(nrows, ncols, false), SaeOccupancy::from_element(nrows, ncols, false)],
+            rejected: 0,
+        }
+    }
+
+    pub fn config(&self) -> &SaeFilterConfig {
+        &self.config
+    }
+
+    /// number of events rejected so far
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// Forget every pixel's history, eg after a gap in the stream
+    pub fn reset(&mut self) {
+        self.seen.iter_mut().for_each(|seen| seen.fill(false));
+    }
+
+    /// Shift the latest event times back by `offset`, along with the surfaces
+    pub fn rebase(&mut self, offset: SaeTime) {
+        self.latest.iter_mut().for_each(|latest| rebase_sae(latest, offset));
+    }
+}
+
+impl EventFilter for SaeFilter {
+    fn accept(&mut self, evt: &SaeEvent) -> bool {
+        let pos = (evt.row as usize, evt.col as usize);
+        let (nrows, ncols) = self.latest[0].shape();
+        if pos.0 >= nrows || pos.1 >= ncols {
+            return true;
+        }
+        let (same, other) = if evt.polarity > 0 { (1, 0) } else { (0, 1) };
+        let passes = !self.seen[same][pos] ||
+            evt.timestamp > self.latest[same][pos].saturating_add(self.config.threshold) ||
+            (self.seen[other][pos] && self.latest[other][pos] > self.latest[same][pos]);
+        self.latest[same][pos] = evt.timestamp;
+        self.seen[same][pos] = true;
+        if !passes {
+            self.rejected += 1;
+        }
+        passes
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_passes_first_of_burst() {
+        let mut filter = SaeFilter::new(8, 8, SaeFilterConfig { threshold: 1_000 });
+        let at = |timestamp: SaeTime, polarity: u8| SaeEvent { row: 3, col: 4, polarity, timestamp, ..SaeEvent::default() };
+        assert!(filter.accept(&at(100, 1)));
+        // the rest of the burst, each within the threshold of the one before
+        assert!(!filter.accept(&at(600, 1)));
+        assert!(!filter.accept(&at(1_400, 1)));
+        // after the other polarity fired
+        assert!(filter.accept(&at(1_500, 0)));
+        assert!(filter.accept(&at(1_600, 1)));
+        // the threshold counts from the latest event, passed or not
+        assert!(!filter.accept(&at(2_500, 1)));
+        assert!(filter.accept(&at(3_600, 1)));
+        assert_eq!(filter.rejected(), 3);
+        // elsewhere, and outside the sensor, events pass
+        assert!(filter.accept(&SaeEvent { row: 3, col: 5, polarity: 1, timestamp: 3_700, ..SaeEvent::default() }));
+        assert!(filter.accept(&SaeEvent { row: 30, col: 5, ..SaeEvent::default() }));
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! End-to-end corner detection from a raw event stream, without building or
+//! mutating surfaces by hand.
+//!
+//! `SaeTracker` owns one Surface of Active Events per polarity, sized to the sensor.
+//! Each event passed to `process_event` updates the surface of its polarity and is
+//! checked for a corner in the same call. The surfaces can be cleared, eg after a
+//! gap in the stream, or resized for a different sensor, keeping the detector
+//! configuration.
+
+use crate::detector::ReplayDetector;
+use crate::filter::EventFilter;
+use crate::sae_filter::{SaeFilter, SaeFilterConfig};
+use crate::sae_types::*;
+use crate::surface::{RecordingHeader, SaeSurface, WarmupConfig};
+
+
+/// Maintains the surfaces of a sensor and detects corners as events arrive
+pub struct SaeTracker {
+    header: RecordingHeader,
+    detector: ReplayDetector,
+    sae_filter: Option<SaeFilter>,
+    events_processed: u64,
+}
+
+impl SaeTracker {
+    /// A tracker for a sensor of `nrows` by `ncols` pixels, with the default warm-up
+    pub fn new(nrows: u16, ncols: u16) -> Self {
+        Self::with_warmup(nrows, ncols, WarmupConfig::default())
+    }
+
+    pub fn with_warmup(nrows: u16, ncols: u16, warmup: WarmupConfig) -> Self {
+        Self::from_header(&RecordingHeader::new(nrows, ncols, warmup))
+    }
+
+    /// A tracker matching a recording, so it detects the same corners as replay
+    pub fn from_header(header: &RecordingHeader) -> Self {
+        SaeTracker {
+            header: header.clone(),
+            detector: ReplayDetector::new(header),
+            sae_filter: None,
+            events_processed: 0,
+        }
+    }
+
+    /// Maintain filtered surfaces: only the events passing the Arc* event filter
+    /// update them and are checked for corners. None updates with every event.
+    pub fn set_sae_filter(&mut self, config: Option<SaeFilterConfig>) {
+        let (nrows, ncols) = self.shape();
+        self.sae_filter = config.map(|config| SaeFilter::new(nrows, ncols, config));
+    }
+
+    /// Update the surface of the event's polarity and check the event for a corner.
+    /// Events outside the sensor, or rejected by the event filter, are ignored.
+    pub fn process_event(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        if !self.admit(evt) {
+            return None;
+        }
+        self.detector.process(evt)
+    }
+
+    /// Update the surface of the event's polarity without checking for a corner
+    pub fn update(&mut self, evt: &SaeEvent) {
+        if self.admit(evt) {
+            self.detector.update(evt);
+        }
+    }
+
+    /// whether the event should update the surfaces, counting it as processed if so
+    fn admit(&mut self, evt: &SaeEvent) -> bool {
+        if evt.row >= self.header.nrows || evt.col >= self.header.ncols {
+            return false;
+        }
+        self.events_processed += 1;
+        self.sae_filter.as_mut().is_none_or(|filter| filter.accept(evt))
+    }
+
+    /// (rows, columns) of the sensor
+    pub fn shape(&self) -> (usize, usize) {
+        (self.header.nrows as usize, self.header.ncols as usize)
+    }
+
+    /// the surface of events of `polarity`
+    pub fn surface(&self, polarity: u8) -> &SaeSurface {
+        self.detector.surface(polarity)
+    }
+
+    /// events processed since creation or the last reset
+    pub fn events_processed(&self) -> u64 {
+        self.events_processed
+    }
+
+    /// The detector, eg to set the detection policy or circle geometry
+    pub fn detector_mut(&mut self) -> &mut ReplayDetector {
+        &mut self.detector
+    }
+
+    /// Clear both surfaces, restarting the warm-up period
+    pub fn reset(&mut self) {
+        self.detector.reset();
+        if let Some(filter) = self.sae_filter.as_mut() {
+            filter.reset();
+        }
+        self.events_processed = 0;
+    }
+
+    /// Move the time origin of the surfaces forward by `offset`, as told by a
+    /// `time::TimeRebaser`, so that a stream can run past the `SaeTime` range
+    pub fn rebase(&mut self, offset: SaeTime) {
+        self.detector.rebase(offset);
+        if let Some(filter) = self.sae_filter.as_mut() {
+            filter.rebase(offset);
+        }
+    }
+
+    /// Resize the surfaces for a sensor of `nrows` by `ncols` pixels, clearing them
+    pub fn resize(&mut self, nrows: u16, ncols: u16) {
+        self.header.nrows = nrows;
+        self.header.ncols = ncols;
+        self.detector.resize(nrows as usize, ncols as usize);
+        if let Some(filter) = self.sae_filter.as_mut() {
+            *filter = SaeFilter::new(nrows as usize, ncols as usize, filter.config().clone());
+        }
+        self.events_processed = 0;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::{EventTime, TimeRebaser};
+    use std::time::Duration;
+
+    fn corner_events(polarity: u8) -> Vec<SaeEvent> {
+        corner_block_events(10, 10, polarity, 7)
+    }
+
+    #[test]
+    fn test_tracker_detects_reset_and_resize() {
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let mut tracker = SaeTracker::from_header(&header);
+        let mut reference = ReplayDetector::new(&header);
+        let events = corner_events(1);
+        let corners: Vec<SaeEvent> = events.iter().filter_map(|evt| tracker.process_event(evt)).collect();
+        let expected: Vec<SaeEvent> = events.iter().filter_map(|evt| reference.process(evt)).collect();
+        assert!(!corners.is_empty());
+        assert_eq!(corners, expected);
+        // only the ON surface was touched
+        assert_eq!(tracker.surface(1).matrix()[(14, 14)], 9);
+        assert_eq!(tracker.surface(0).populated_count(), 0);
+        assert_eq!(tracker.events_processed(), events.len() as u64);
+
+        tracker.reset();
+        assert_eq!(tracker.surface(1).populated_count(), 0);
+
+        tracker.resize(12, 12);
+        assert_eq!(tracker.shape(), (12, 12));
+        assert_eq!(tracker.surface(0).shape(), (12, 12));
+        assert!(events.iter().filter_map(|evt| tracker.process_event(evt)).next().is_none());
+        assert_eq!(tracker.events_processed(), 4);
+    }
+
+    #[test]
+    fn test_tracker_rebased_past_sae_time_range() {
+        let mut tracker = SaeTracker::with_warmup(32, 32, WarmupConfig::disabled());
+        let mut rebaser = TimeRebaser::new(1_000_000, 100);
+        let start = EventTime::from_micros(SaeTime::MAX as u64 + 5_000_000);
+        let mut corners = Vec::new();
+        for evt in corner_events(1) {
+            let time = start + Duration::from_micros(evt.timestamp as u64);
+            let (timestamp, shift) = rebaser.to_sae_time(time);
+            if let Some(offset) = shift {
+                tracker.rebase(offset);
+            }
+            if let Some(corner) = tracker.process_event(&SaeEvent { timestamp, ..evt }) {
+                corners.push((corner.row, corner.col, rebaser.to_event_time(corner.timestamp)));
+            }
+        }
+        // the same corners as the stream within range, at the same times
+        let mut reference = SaeTracker::with_warmup(32, 32, WarmupConfig::disabled());
+        let expected: Vec<_> = corner_events(1).iter()
+            .filter_map(|evt| reference.process_event(evt))
+            .map(|corner| (corner.row, corner.col, start + Duration::from_micros(corner.timestamp as u64)))
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(corners, expected);
+
+        // history older than the offset saturates at zero
+        tracker.rebase(100);
+        assert_eq!(tracker.surface(1).matrix()[(10, 10)], 0);
+        assert_eq!(tracker.surface(1).matrix()[(14, 14)], 2);
+        assert!(tracker.surface(1).is_observed(10, 10));
+    }
+}
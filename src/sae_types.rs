@@ -1,8 +1,13 @@
 // Copyright 2019, Todd Stellanova
 // License: see LICENSE file
+#![allow(clippy::empty_line_after_doc_comments)]
 
+#[cfg(feature = "std")]
 use nalgebra::{DMatrix};
-use std::fmt;
+use alloc::boxed::Box;
+use core::fmt;
+
+pub use crate::time::SaeTimeExt;
 
 /// Defining types for Surface of Active Events (SAE), commonly used for Event Cameras (DVS &c.).
 /// The SAE is a matrix of  timestamps (one per pixel), indicating when a change event
@@ -12,22 +17,121 @@ use std::fmt;
 /// The type used to store timestamps in the SAE
 pub type SaeTime = u32;
 /// Type used to store a Surface of Active Events
+#[cfg(feature = "std")]
 pub type SaeMatrix = DMatrix<SaeTime>;
+/// Per-pixel flags marking which SAE cells have ever been updated,
+/// distinguishing "no event ever" from "event at timestamp 0"
+#[cfg(feature = "std")]
+pub type SaeOccupancy = DMatrix<bool>;
 
 
-pub const NORM_DESCRIPTOR_LEN: usize = 36;
+/// number of inner (radius 3) ring samples at the start of a descriptor
+pub const DESCRIPTOR_C3_LEN: usize = 16;
+/// number of outer (radius 4) ring samples following the inner ring in a descriptor
+pub const DESCRIPTOR_C4_LEN: usize = 20;
+pub const NORM_DESCRIPTOR_LEN: usize = DESCRIPTOR_C3_LEN + DESCRIPTOR_C4_LEN;
 /// a crude feature descriptor allowing limited number of comparison points
 pub type NormDescriptor = [f32; NORM_DESCRIPTOR_LEN];
 
+/// number of bits of a binary descriptor
+pub const BINARY_DESCRIPTOR_BITS: usize = 128;
+
+/// A compact descriptor stored inline: one bit per comparison of a fixed pair of
+/// ring timestamps, set where the first is the fresher. Compared by Hamming distance,
+/// it is much cheaper to match than a `NormDescriptor`, if less discriminative.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BinaryDescriptor(pub u128);
+
+impl BinaryDescriptor {
+  /// number of differing bits
+  pub fn hamming_distance(&self, other: &BinaryDescriptor) -> u32 {
+    (self.0 ^ other.0).count_ones()
+  }
+
+  /// fraction of bits in agreement: 1 for identical descriptors, on the scale of `SaeEvent::likeness`
+  pub fn likeness(&self, other: &BinaryDescriptor) -> f32 {
+    1.0 - self.hamming_distance(other) as f32 / BINARY_DESCRIPTOR_BITS as f32
+  }
+}
+
+/// Per-element weights applied when comparing two descriptors.
+/// A zero weight excludes that element from the comparison entirely.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DescriptorWeights {
+  pub weights: NormDescriptor,
+}
+
+impl Default for DescriptorWeights {
+  fn default() -> Self {
+    Self::uniform()
+  }
+}
+
+impl DescriptorWeights {
+  /// every descriptor element counts equally
+  pub fn uniform() -> Self {
+    Self::rings(1.0, 1.0)
+  }
+
+  /// weight the inner C3 ring and the outer C4 ring separately
+  pub fn rings(c3_weight: f32, c4_weight: f32) -> Self {
+    let mut weights = [c4_weight; NORM_DESCRIPTOR_LEN];
+    for w in weights.iter_mut().take(DESCRIPTOR_C3_LEN) {
+      *w = c3_weight;
+    }
+    DescriptorWeights { weights }
+  }
+
+  /// compare only the inner C3 ring
+  pub fn c3_only() -> Self {
+    Self::rings(1.0, 0.0)
+  }
+
+  /// compare only the outer C4 ring
+  pub fn c4_only() -> Self {
+    Self::rings(0.0, 1.0)
+  }
+}
+
+
+/// Which side of a corner an event lies on, from the Arc* acceptance path
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CornerKind {
+  /// the freshest arc is accepted by its own length: the corner of a convex region
+  Outside,
+  /// the freshest arc is accepted by the length of its complement: a concave corner
+  Inside,
+}
+
 
 /// The main change event struct
-#[derive(Clone)]
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SaeEvent {
   pub row: u16,
   pub col: u16,
   pub polarity: u8,
   pub timestamp: SaeTime,
+  #[cfg_attr(feature = "serde", serde(with = "crate::serialize::descriptor"))]
   pub norm_descriptor: Option<Box<NormDescriptor>>,
+  /// sub-pixel row of a refined corner
+  pub row_f: Option<f32>,
+  /// sub-pixel column of a refined corner
+  pub col_f: Option<f32>,
+  /// detector confidence (0..1) in a corner, from its arc margins, time contrast
+  /// and local support; None for events that were not detected as corners
+  pub confidence: Option<f32>,
+  /// direction (radians) of a corner's freshest arcs, measured from the +col axis
+  /// toward +row: a cheap estimate of the corner orientation
+  pub orientation: Option<f32>,
+  /// inside or outside corner, if both rings took the same acceptance path
+  pub corner_kind: Option<CornerKind>,
+  /// feature scale (full-resolution pixels) of a corner from multi-scale detection
+  pub scale: Option<f32>,
+  /// compact descriptor of a corner, if the detector was configured to compute one
+  pub binary_descriptor: Option<BinaryDescriptor>,
 }
 
 impl fmt::Debug for SaeEvent {
@@ -38,40 +142,93 @@ impl fmt::Debug for SaeEvent {
       let total:f32 = values.iter().sum();
       avg_desc = total / (NORM_DESCRIPTOR_LEN as f32);
     }
-    write!(f, "SaeEvent {{ row: {}, col: {} time: {} pol: {} avg_desc: {} }}",
-           self.row, self.col, self.timestamp, self.polarity, avg_desc)
+    write!(f, "SaeEvent {{ row: {}, col: {} time: {} pol: {} avg_desc: {} row_f: {:?} col_f: {:?} \
+               confidence: {:?} orientation: {:?} kind: {:?} scale: {:?} binary_desc: {:?} }}",
+           self.row, self.col, self.timestamp, self.polarity, avg_desc, self.row_f, self.col_f,
+           self.confidence, self.orientation, self.corner_kind, self.scale, self.binary_descriptor.map(|desc| desc.0))
   }
 }
 
+/// Events compare equal when they occurred at the same pixel, time, and polarity:
+/// the descriptor is derived data and is ignored.
+/// Use `descriptor_approx_eq` to compare descriptors.
 impl PartialEq for SaeEvent {
   fn eq(&self, other: &SaeEvent) -> bool {
-    self.row == other.row &&
-      self.col == other.col &&
-      self.polarity == other.polarity &&
-      self.timestamp == other.timestamp
+    self.key() == other.key()
+  }
+}
+
+/// Identifies a single change event, independent of any computed descriptor.
+/// Suitable for deduplicating events or as a `HashMap` key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SaeEventKey {
+  pub row: u16,
+  pub col: u16,
+  pub timestamp: SaeTime,
+  pub polarity: u8,
+}
 
-    // TODO implement PartialEq for norm_descriptor ?
+impl From<&SaeEvent> for SaeEventKey {
+  fn from(evt: &SaeEvent) -> Self {
+    evt.key()
   }
 }
 
+/// A change event as read from a sensor interface, eg in an interrupt handler:
+/// only the fields the sensor reports, small enough to pass by value
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RawEvent {
+  pub row: u16,
+  pub col: u16,
+  pub polarity: u8,
+  pub timestamp: SaeTime,
+}
 
-impl std::default::Default for SaeEvent {
-  fn default() -> Self {
-    SaeEvent {
-      row: 0,
-      col: 0,
-      polarity: 0,
-      timestamp: 0,
-      norm_descriptor: None,
-    }
+impl From<RawEvent> for SaeEvent {
+  fn from(raw: RawEvent) -> Self {
+    SaeEvent { row: raw.row, col: raw.col, polarity: raw.polarity, timestamp: raw.timestamp, ..SaeEvent::default() }
+  }
+}
+
+impl From<&SaeEvent> for RawEvent {
+  fn from(evt: &SaeEvent) -> Self {
+    RawEvent { row: evt.row, col: evt.col, polarity: evt.polarity, timestamp: evt.timestamp }
   }
 }
 
+
 impl SaeEvent {
   pub fn new() -> Self {
     Self::default()
   }
 
+  /// (row, col) position, using the sub-pixel refinement where available
+  pub fn subpixel_position(&self) -> (f32, f32) {
+    (self.row_f.unwrap_or(self.row as f32), self.col_f.unwrap_or(self.col as f32))
+  }
+
+  /// the identifying fields of this event
+  pub fn key(&self) -> SaeEventKey {
+    SaeEventKey {
+      row: self.row,
+      col: self.col,
+      timestamp: self.timestamp,
+      polarity: self.polarity,
+    }
+  }
+
+  /// whether both events carry descriptors whose elements all differ by no more than `tolerance`.
+  /// Two events without descriptors are considered equal.
+  pub fn descriptor_approx_eq(&self, other: &Self, tolerance: f32) -> bool {
+    match (&self.norm_descriptor, &other.norm_descriptor) {
+      (Some(a_desc), Some(b_desc)) => a_desc.iter()
+        .zip(b_desc.iter())
+        .all(|(da, db)| (da - db).abs() <= tolerance),
+      (None, None) => true,
+      _ => false,
+    }
+  }
+
   /// square of the euclidean distance between two events
   pub fn spatial_dist_2(&self, other: &Self) -> u32 {
     let drow: u32 = (self.row.max(other.row) - self.row.min(other.row)) as u32;
@@ -88,7 +245,14 @@ impl SaeEvent {
     drow + dcol
   }
 
+  /// similarity of the two event descriptors, in the range 0..1
   pub fn likeness(&self, b: &SaeEvent) -> f32 {
+    self.likeness_weighted(b, &DescriptorWeights::uniform())
+  }
+
+  /// similarity of the two event descriptors, in the range 0..1,
+  /// with each descriptor element scaled by the given weights
+  pub fn likeness_weighted(&self, b: &SaeEvent, weights: &DescriptorWeights) -> f32 {
     if self.norm_descriptor.is_none() || b.norm_descriptor.is_none() {
       return 0.0;
     }
@@ -103,25 +267,63 @@ impl SaeEvent {
       _ => unreachable!()
     };
 
-    let mut da_total:f32 = 0.0;
-    let mut db_total:f32 = 0.0;
-    let mut min_total:f32 = 0.0;
+    descriptor_likeness(a_desc, b_desc, weights)
+  }
+}
 
-    for i in 0..a_desc.len() {
-      let da = a_desc[i];
-      let db = b_desc[i];
-      da_total += da;
-      db_total += db;
-      min_total += da.min(db);
-    }
+/// similarity of two descriptors, in the range 0..1,
+/// with each descriptor element scaled by the given weights
+pub fn descriptor_likeness(a_desc: &NormDescriptor, b_desc: &NormDescriptor, weights: &DescriptorWeights) -> f32 {
+  let mut da_total:f32 = 0.0;
+  let mut db_total:f32 = 0.0;
+  let mut min_total:f32 = 0.0;
+
+  for i in 0..a_desc.len() {
+    let w = weights.weights[i];
+    let da = a_desc[i];
+    let db = b_desc[i];
+    da_total += w * da;
+    db_total += w * db;
+    min_total += w * da.min(db);
+  }
+
+  let max_total = da_total.max(db_total);
+  if max_total <= 0.0 {
+    return 0.0;
+  }
 
-    let max_total = da_total.max(db_total);
+  //likeness is 0..1
+  let likeness: f32 = min_total/max_total;
+  //println!("likeness: {}", likeness);
 
-    //likeness is 0..1
-    let likeness: f32 = min_total/max_total;
-    //println!("likeness: {}", likeness);
+  likeness
+}
+
+/// Test fixture: a 5x5 block of events at `timestamp`, with its top left pixel at
+/// (`row`, `col`), then its bottom right pixel again 2us later, forming a corner there
+#[cfg(all(test, feature = "std"))]
+pub(crate) fn corner_block_events(row: u16, col: u16, polarity: u8, timestamp: SaeTime) -> Vec<SaeEvent> {
+  let mut events = Vec::new();
+  for block_row in row..row + 5 {
+    for block_col in col..col + 5 {
+      events.push(SaeEvent { row: block_row, col: block_col, polarity, timestamp, ..SaeEvent::default() });
+    }
+  }
+  events.push(SaeEvent { row: row + 4, col: col + 4, polarity, timestamp: timestamp + 2, ..SaeEvent::default() });
+  events
+}
 
-    likeness
+/// Test fixture: a corner at the sub-pixel position (`x`, `y`), ie column and row,
+/// at the nearest pixel
+#[cfg(all(test, feature = "std"))]
+pub(crate) fn corner_at(x: f32, y: f32, timestamp: SaeTime) -> SaeEvent {
+  SaeEvent {
+    row: y.round() as u16,
+    col: x.round() as u16,
+    row_f: Some(y),
+    col_f: Some(x),
+    timestamp,
+    ..SaeEvent::default()
   }
 }
 
@@ -167,6 +369,7 @@ mod tests {
       polarity: 0,
       timestamp: 0,
       norm_descriptor: Some(Box::new([1.0; NORM_DESCRIPTOR_LEN])),
+      ..SaeEvent::default()
     };
 
     let mut evt_b = SaeEvent {
@@ -175,6 +378,7 @@ mod tests {
       polarity: 0,
       timestamp: 0,
       norm_descriptor: Some(Box::new([1.0; NORM_DESCRIPTOR_LEN])),
+      ..SaeEvent::default()
     };
 
     let likeness = evt_a.likeness(&evt_b);
@@ -193,4 +397,99 @@ mod tests {
     assert_approx_eq!(likeness, 0.0);
   }
 
-}
\ No newline at end of file
+  #[test]
+  fn test_event_likeness_weighted() {
+    let mut a_desc = [1.0; NORM_DESCRIPTOR_LEN];
+    let b_desc = [1.0; NORM_DESCRIPTOR_LEN];
+    // outer ring of a differs completely from b
+    for val in a_desc.iter_mut().skip(DESCRIPTOR_C3_LEN) {
+      *val = 0.0;
+    }
+
+    let evt_a = SaeEvent {
+      norm_descriptor: Some(Box::new(a_desc)),
+      ..SaeEvent::default()
+    };
+    let evt_b = SaeEvent {
+      norm_descriptor: Some(Box::new(b_desc)),
+      ..SaeEvent::default()
+    };
+
+    let likeness = evt_a.likeness_weighted(&evt_b, &DescriptorWeights::c3_only());
+    assert_approx_eq!(likeness, 1.0);
+
+    let likeness = evt_a.likeness_weighted(&evt_b, &DescriptorWeights::c4_only());
+    assert_approx_eq!(likeness, 0.0);
+
+    let likeness = evt_a.likeness_weighted(&evt_b, &DescriptorWeights::uniform());
+    assert_approx_eq!(likeness, evt_a.likeness(&evt_b));
+    assert_approx_eq!(likeness, 16.0 / 36.0);
+
+    // weighting the inner ring more increases likeness
+    let likeness = evt_a.likeness_weighted(&evt_b, &DescriptorWeights::rings(2.0, 1.0));
+    assert_approx_eq!(likeness, 32.0 / 52.0);
+  }
+
+  #[test]
+  fn test_event_key() {
+    use std::collections::HashSet;
+
+    let evt_a = SaeEvent {
+      row: 1,
+      col: 2,
+      polarity: 1,
+      timestamp: 100,
+      norm_descriptor: Some(Box::new([1.0; NORM_DESCRIPTOR_LEN])),
+      ..SaeEvent::default()
+    };
+    let mut evt_b = evt_a.clone();
+    evt_b.norm_descriptor = None;
+
+    // descriptor does not participate in equality
+    assert_eq!(evt_a, evt_b);
+    assert_eq!(evt_a.key(), SaeEventKey::from(&evt_b));
+
+    let mut seen = HashSet::new();
+    assert!(seen.insert(evt_a.key()));
+    assert!(!seen.insert(evt_b.key()));
+
+    evt_b.timestamp = 101;
+    assert_ne!(evt_a, evt_b);
+    assert!(seen.insert(evt_b.key()));
+  }
+
+  #[test]
+  fn test_descriptor_approx_eq() {
+    let evt_a = SaeEvent {
+      norm_descriptor: Some(Box::new([0.5; NORM_DESCRIPTOR_LEN])),
+      ..SaeEvent::default()
+    };
+    let mut evt_b = SaeEvent {
+      norm_descriptor: Some(Box::new([0.52; NORM_DESCRIPTOR_LEN])),
+      ..SaeEvent::default()
+    };
+
+    assert!(evt_a.descriptor_approx_eq(&evt_b, 0.05));
+    assert!(!evt_a.descriptor_approx_eq(&evt_b, 0.01));
+
+    evt_b.norm_descriptor = None;
+    assert!(!evt_a.descriptor_approx_eq(&evt_b, 1.0));
+    assert!(evt_b.descriptor_approx_eq(&SaeEvent::new(), 0.0));
+  }
+
+  #[test]
+  fn test_debug_corner_fields() {
+    let corner = SaeEvent {
+      row: 3,
+      col: 4,
+      row_f: Some(3.5),
+      confidence: Some(0.75),
+      corner_kind: Some(CornerKind::Inside),
+      ..SaeEvent::default()
+    };
+    let text = format!("{:?}", corner);
+    assert!(text.contains("row_f: Some(3.5) col_f: None"));
+    assert!(text.contains("confidence: Some(0.75) orientation: None kind: Some(Inside) scale: None binary_desc: None"));
+  }
+
+}
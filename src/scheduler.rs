@@ -0,0 +1,317 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Time-multiplexing of independent event streams (several cameras, or a batch of
+//! recordings) over a shared pool of worker threads.
+//!
+//! Each stream is a `StreamTask` owning all of its state, so streams never share
+//! surfaces, trackers or sinks. Streams wait in a run queue; a free worker takes the
+//! stream at the front, processes one quantum of its events, and puts it back at the
+//! end, so every stream gets turns in rotation however many workers there are, and
+//! a slow or busy stream can't starve the others. A stream leaves the rotation once
+//! it is exhausted or fails; a failure is recorded in that stream's metrics and
+//! does not stop the rest.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::detector::ReplayDetector;
+use crate::sink::CornerSink;
+use crate::source::EventSource;
+use crate::surface::RecordingHeader;
+
+
+/// What one turn of a stream did
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StepOutcome {
+    pub events: u64,
+    pub corners: u64,
+    /// whether the stream is exhausted and needs no more turns
+    pub finished: bool,
+}
+
+/// One independent stream and all the state needed to process it
+pub trait StreamTask: Send {
+    /// Process up to `budget` events
+    fn step(&mut self, budget: usize) -> io::Result<StepOutcome>;
+}
+
+impl<T: StreamTask + ?Sized> StreamTask for Box<T> {
+    fn step(&mut self, budget: usize) -> io::Result<StepOutcome> {
+        (**self).step(budget)
+    }
+}
+
+/// Corner detection on one stream: events from a source, through a detector configured
+/// from the stream's header, with corners delivered to the stream's own sink
+pub struct DetectionTask<E, S> {
+    source: E,
+    detector: ReplayDetector,
+    sink: S,
+}
+
+impl<E: EventSource + Send, S: CornerSink + Send> DetectionTask<E, S> {
+    pub fn new(source: E, header: &RecordingHeader, sink: S) -> Self {
+        DetectionTask { source, detector: ReplayDetector::new(header), sink }
+    }
+
+    pub fn detector_mut(&mut self) -> &mut ReplayDetector {
+        &mut self.detector
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+}
+
+impl<E: EventSource + Send, S: CornerSink + Send> StreamTask for DetectionTask<E, S> {
+    fn step(&mut self, budget: usize) -> io::Result<StepOutcome> {
+        let mut outcome = StepOutcome::default();
+        for _ in 0..budget {
+            let evt = match self.source.next_event()? {
+                Some(evt) => evt,
+                None => {
+                    outcome.finished = true;
+                    break;
+                }
+            };
+            outcome.events += 1;
+            if let Some(corner) = self.detector.process(&evt) {
+                self.sink.accept(&corner);
+                outcome.corners += 1;
+            }
+        }
+        Ok(outcome)
+    }
+}
+
+/// Parameters of the scheduler
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchedulerConfig {
+    /// number of worker threads
+    pub workers: usize,
+    /// events a stream processes per turn: smaller quanta interleave streams more finely
+    pub quantum: usize,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            workers: 4,
+            quantum: 4_096,
+        }
+    }
+}
+
+/// What has been done on one stream
+#[derive(Debug, Default)]
+pub struct StreamMetrics {
+    pub events: u64,
+    pub corners: u64,
+    /// turns the stream has had
+    pub turns: u64,
+    /// time spent processing the stream
+    pub busy: Duration,
+    /// longest time the stream waited in the run queue for a turn
+    pub max_wait: Duration,
+    pub finished: bool,
+    /// the error the stream failed with, if it did
+    pub error: Option<io::Error>,
+}
+
+impl StreamMetrics {
+    /// events processed per second of processing time
+    pub fn event_rate(&self) -> f64 {
+        if self.busy.as_secs_f64() > 0.0 { self.events as f64 / self.busy.as_secs_f64() } else { 0.0 }
+    }
+}
+
+struct StreamSlot<T> {
+    task: T,
+    metrics: StreamMetrics,
+}
+
+/// The run queue shared by the workers
+struct RunQueue {
+    /// streams waiting for a turn, and when they started waiting
+    waiting: VecDeque<(usize, Instant)>,
+    /// streams not yet finished, waiting or being processed
+    active: usize,
+}
+
+/// Interleaves independent streams over a pool of workers
+pub struct StreamScheduler<T> {
+    config: SchedulerConfig,
+    streams: Vec<Mutex<StreamSlot<T>>>,
+}
+
+impl<T: StreamTask> StreamScheduler<T> {
+    pub fn new(config: SchedulerConfig) -> Self {
+        StreamScheduler { config, streams: Vec::new() }
+    }
+
+    /// Add a stream, returning its index
+    pub fn add_stream(&mut self, task: T) -> usize {
+        self.streams.push(Mutex::new(StreamSlot { task, metrics: StreamMetrics::default() }));
+        self.streams.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Process every unfinished stream until all are exhausted or have failed
+    pub fn run(&mut self) {
+        let waiting: VecDeque<(usize, Instant)> = self.streams.iter_mut()
+            .enumerate()
+            .filter_map(|(idx, slot)| if slot.get_mut().unwrap().metrics.finished { None } else { Some((idx, Instant::now())) })
+            .collect();
+        let active = waiting.len();
+        if active == 0 {
+            return;
+        }
+        let queue = Mutex::new(RunQueue { waiting, active });
+        let ready = Condvar::new();
+        let streams = &self.streams;
+        let quantum = self.config.quantum.max(1);
+        thread::scope(|scope| {
+            for _ in 0..self.config.workers.clamp(1, active) {
+                scope.spawn(|| loop {
+                    let (idx, queued) = {
+                        let mut queue = queue.lock().unwrap();
+                        loop {
+                            if let Some(next) = queue.waiting.pop_front() {
+                                break next;
+                            }
+                            if queue.active == 0 {
+                                return;
+                            }
+                            queue = ready.wait(queue).unwrap();
+                        }
+                    };
+
+                    let finished = {
+                        let mut slot = streams[idx].lock().unwrap();
+                        let started = Instant::now();
+                        let result = slot.task.step(quantum);
+                        let metrics = &mut slot.metrics;
+                        metrics.busy += started.elapsed();
+                        metrics.max_wait = metrics.max_wait.max(started.duration_since(queued));
+                        metrics.turns += 1;
+                        match result {
+                            Ok(outcome) => {
+                                metrics.events += outcome.events;
+                                metrics.corners += outcome.corners;
+                                metrics.finished = outcome.finished;
+                            }
+                            Err(err) => {
+                                metrics.error = Some(err);
+                                metrics.finished = true;
+                            }
+                        }
+                        metrics.finished
+                    };
+
+                    let mut queue = queue.lock().unwrap();
+                    if finished {
+                        queue.active -= 1;
+                    } else {
+                        queue.waiting.push_back((idx, Instant::now()));
+                    }
+                    ready.notify_all();
+                });
+            }
+        });
+    }
+
+    /// Metrics of stream `idx`
+    pub fn metrics(&mut self, idx: usize) -> &StreamMetrics {
+        &self.streams[idx].get_mut().unwrap().metrics
+    }
+
+    /// The task of stream `idx`, eg to read its sink
+    pub fn task_mut(&mut self, idx: usize) -> &mut T {
+        &mut self.streams[idx].get_mut().unwrap().task
+    }
+
+    /// Every task with its metrics, in the order added
+    pub fn into_streams(self) -> Vec<(T, StreamMetrics)> {
+        self.streams.into_iter()
+            .map(|slot| {
+                let slot = slot.into_inner().unwrap();
+                (slot.task, slot.metrics)
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sae_types::*;
+    use crate::source::IterSource;
+    use crate::surface::WarmupConfig;
+
+    fn corner_events(offset: u16, repeats: u32) -> Vec<SaeEvent> {
+        (0..repeats).flat_map(|repeat| corner_block_events(10, 10 + offset, 0, 7 + repeat * 10)).collect()
+    }
+
+    struct Failing;
+
+    impl StreamTask for Failing {
+        fn step(&mut self, _budget: usize) -> io::Result<StepOutcome> {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "broken stream"))
+        }
+    }
+
+    #[test]
+    fn test_streams_isolated_and_complete() {
+        let header = RecordingHeader::new(32, 48, WarmupConfig::disabled());
+        let inputs: Vec<Vec<SaeEvent>> = (0..5).map(|k| corner_events(k * 4, 3 + k as u32)).collect();
+        let mut scheduler = StreamScheduler::new(SchedulerConfig { workers: 3, quantum: 10 });
+        for events in inputs.iter() {
+            scheduler.add_stream(DetectionTask::new(IterSource::new(events.clone()), &header, Vec::new()));
+        }
+        scheduler.run();
+
+        let streams = scheduler.into_streams();
+        for ((task, metrics), events) in streams.into_iter().zip(inputs.iter()) {
+            // each stream's corners are exactly those of a detector run on it alone
+            let mut detector = ReplayDetector::new(&header);
+            let expected: Vec<SaeEvent> = events.iter().filter_map(|evt| detector.process(evt)).collect();
+            assert!(!expected.is_empty());
+            assert_eq!(task.into_sink(), expected);
+            assert!(metrics.finished && metrics.error.is_none());
+            assert_eq!(metrics.events, events.len() as u64);
+            assert_eq!(metrics.corners, expected.len() as u64);
+            assert_eq!(metrics.turns, events.len() as u64 / 10 + 1);
+        }
+    }
+
+    #[test]
+    fn test_failed_stream_does_not_stop_others() {
+        let header = RecordingHeader::new(32, 32, WarmupConfig::disabled());
+        let mut scheduler: StreamScheduler<Box<dyn StreamTask>> = StreamScheduler::new(SchedulerConfig { workers: 2, quantum: 4 });
+        scheduler.add_stream(Box::new(Failing));
+        let source = IterSource::new(corner_events(0, 2));
+        scheduler.add_stream(Box::new(DetectionTask::new(source, &header, Vec::new())));
+        scheduler.run();
+        assert_eq!(scheduler.metrics(0).error.as_ref().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(scheduler.metrics(0).turns, 1);
+        assert!(scheduler.metrics(1).error.is_none());
+        assert_eq!(scheduler.metrics(1).events, 52);
+        assert!(scheduler.metrics(1).corners > 0);
+    }
+}
@@ -0,0 +1,254 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! A pre-flight health check of an event camera, run over a short capture of a
+//! static scene, or of a known stimulus, before detection output is trusted.
+//!
+//! `SelfTest` measures the event rate per pixel (background noise, over a static
+//! scene), hot pixels firing far more often than the rest, the balance of ON and
+//! OFF events (see `balance`), and timestamps running backwards, and reports each
+//! against its threshold. A check with too few events to judge is inconclusive,
+//! and an inconclusive check fails the test as a whole.
+
+use crate::balance::PolarityCounts;
+use crate::sae_types::*;
+
+
+/// Thresholds of the self-test
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelfTestConfig {
+    /// acceptable range of the mean event rate, in events per pixel per second:
+    /// for a static scene, up to the background noise rate the sensor is rated for
+    pub event_rate: (f64, f64),
+    /// a pixel firing more than this many times the mean rate is hot
+    pub hot_pixel_factor: f64,
+    /// ... as long as it fired at least this many events
+    pub min_hot_events: u64,
+    /// largest acceptable fraction of hot pixels
+    pub max_hot_fraction: f64,
+    /// largest acceptable distance of the ON fraction from one half
+    pub max_imbalance: f32,
+    /// largest acceptable number of timestamps earlier than the one before
+    pub max_backward_steps: u64,
+    /// fewer events leave the checks inconclusive
+    pub min_events: u64,
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        SelfTestConfig {
+            event_rate: (0.0, 1.0),
+            hot_pixel_factor: 20.0,
+            min_hot_events: 10,
+            max_hot_fraction: 0.001,
+            max_imbalance: 0.15,
+            max_backward_steps: 0,
+            min_events: 1_000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    /// too few events to judge
+    Inconclusive,
+}
+
+/// The outcome of one check
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub measured: f64,
+    /// the limit the measurement was held to
+    pub threshold: f64,
+    pub status: CheckStatus,
+}
+
+/// The outcome of the self-test
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelfTestReport {
+    pub events: u64,
+    /// time from the first to the last event
+    pub duration: SaeTime,
+    /// events outside the sensor, which are counted nowhere else
+    pub out_of_bounds: u64,
+    /// (row, col, events) of each hot pixel, busiest first
+    pub hot_pixels: Vec<(u16, u16, u64)>,
+    pub polarity: PolarityCounts,
+    pub backward_steps: u64,
+    pub checks: Vec<CheckResult>,
+}
+
+impl SelfTestReport {
+    /// whether every check passed
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.status == CheckStatus::Pass)
+    }
+
+    /// the checks that did not pass
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.checks.iter().filter(|check| check.status != CheckStatus::Pass)
+    }
+}
+
+/// Accumulates the measurements of a self-test
+pub struct SelfTest {
+    config: SelfTestConfig,
+    ncols: usize,
+    /// events per pixel, row-major
+    counts: Vec<u64>,
+    polarity: PolarityCounts,
+    first: Option<SaeTime>,
+    last: SaeTime,
+    events: u64,
+    out_of_bounds: u64,
+    backward_steps: u64,
+}
+
+impl SelfTest {
+    pub fn new(nrows: usize, ncols: usize, config: SelfTestConfig) -> Self {
+        SelfTest {
+            config,
+            ncols,
+            counts: vec![0; nrows * ncols],
+            polarity: PolarityCounts::default(),
+            first: None,
+            last: 0,
+            events: 0,
+            out_of_bounds: 0,
+            backward_steps: 0,
+        }
+    }
+
+    pub fn config(&self) -> &SelfTestConfig {
+        &self.config
+    }
+
+    pub fn add(&mut self, evt: &SaeEvent) {
+        self.events += 1;
+        if self.first.is_some() && evt.timestamp < self.last {
+            self.backward_steps += 1;
+        }
+        self.first.get_or_insert(evt.timestamp);
+        self.last = evt.timestamp;
+        let (row, col) = (evt.row as usize, evt.col as usize);
+        if col >= self.ncols || row * self.ncols + col >= self.counts.len() {
+            self.out_of_bounds += 1;
+            return;
+        }
+        self.counts[row * self.ncols + col] += 1;
+        self.polarity.add(evt);
+    }
+
+    pub fn run<I: IntoIterator<Item = SaeEvent>>(&mut self, events: I) {
+        for evt in events {
+            self.add(&evt);
+        }
+    }
+
+    pub fn report(&self) -> SelfTestReport {
+        let config = &self.config;
+        let enough = self.events >= config.min_events.max(1);
+        let judge = |name, measured: f64, threshold: f64, pass: bool| CheckResult {
+            name,
+            measured,
+            threshold,
+            status: if !enough { CheckStatus::Inconclusive } else if pass { CheckStatus::Pass } else { CheckStatus::Fail },
+        };
+        let duration = self.first.map_or(0, |first| self.last.elapsed_since(first));
+        let pixels = self.counts.len().max(1) as f64;
+        let in_bounds = self.polarity.total();
+        let mean = in_bounds as f64 / pixels;
+
+        let hot_limit = (mean * config.hot_pixel_factor).max(config.min_hot_events as f64);
+        let mut hot_pixels: Vec<(u16, u16, u64)> = self.counts.iter()
+            .enumerate()
+            .filter(|&(_, &count)| count as f64 > hot_limit)
+            .map(|(idx, &count)| ((idx / self.ncols) as u16, (idx % self.ncols) as u16, count))
+            .collect();
+        hot_pixels.sort_by(|a, b| b.2.cmp(&a.2).then((a.0, a.1).cmp(&(b.0, b.1))));
+
+        // the rate of the pixels that aren't hot, so that a few hot pixels don't mask a quiet sensor
+        let hot_events: u64 = hot_pixels.iter().map(|pixel| pixel.2).sum();
+        let secs = duration as f64 / 1e6;
+        let rate = if secs > 0.0 { (in_bounds - hot_events) as f64 / (pixels - hot_pixels.len() as f64).max(1.0) / secs } else { 0.0 };
+        let (min_rate, max_rate) = config.event_rate;
+        let hot_fraction = hot_pixels.len() as f64 / pixels;
+        let imbalance = self.polarity.imbalance(1).unwrap_or(0.0);
+
+        let checks = vec![
+            judge("event_rate", rate, if rate < min_rate { min_rate } else { max_rate },
+                  secs > 0.0 && rate >= min_rate && rate <= max_rate),
+            judge("hot_pixels", hot_fraction, config.max_hot_fraction, hot_fraction <= config.max_hot_fraction),
+            judge("polarity_balance", imbalance.abs() as f64, config.max_imbalance as f64,
+                  imbalance.abs() <= config.max_imbalance),
+            judge("timestamp_monotonicity", self.backward_steps as f64, config.max_backward_steps as f64,
+                  self.backward_steps <= config.max_backward_steps),
+        ];
+        SelfTestReport {
+            events: self.events,
+            duration,
+            out_of_bounds: self.out_of_bounds,
+            hot_pixels,
+            polarity: self.polarity,
+            backward_steps: self.backward_steps,
+            checks,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// background noise of 0.2 events per pixel per second over a 40 x 50 sensor, for 5 s
+    fn static_scene() -> Vec<SaeEvent> {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut timestamp = 0;
+        (0..2_000)
+            .map(|_| {
+                timestamp += rng.gen_range(1, 5_000);
+                SaeEvent { row: rng.gen_range(0, 40), col: rng.gen_range(0, 50), polarity: rng.gen_range(0, 2),
+                           timestamp, ..SaeEvent::default() }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_healthy_and_faulty_sensor() {
+        // no hot pixels allowed on so small a sensor
+        let config = SelfTestConfig { max_hot_fraction: 0.0, ..SelfTestConfig::default() };
+        let mut healthy = SelfTest::new(40, 50, config.clone());
+        healthy.run(static_scene());
+        let report = healthy.report();
+        assert!(report.passed(), "{:?}", report.checks);
+        assert!(report.hot_pixels.is_empty());
+
+        let mut faulty = SelfTest::new(40, 50, config);
+        let mut events = static_scene();
+        // a hot pixel, a timestamp glitch, and events beyond the sensor
+        let hot: Vec<SaeEvent> = events.iter().step_by(20)
+            .map(|evt| SaeEvent { row: 7, col: 9, polarity: 1, ..evt.clone() })
+            .collect();
+        events.extend(hot);
+        events.sort_by_key(|evt| evt.timestamp);
+        events.swap(500, 501);
+        events.push(SaeEvent { row: 40, timestamp: events.last().unwrap().timestamp, ..SaeEvent::default() });
+        faulty.run(events);
+        let report = faulty.report();
+        assert!(!report.passed());
+        assert_eq!(report.hot_pixels.first().map(|pixel| (pixel.0, pixel.1)), Some((7, 9)));
+        assert_eq!(report.out_of_bounds, 1);
+        let failed: Vec<&str> = report.failures().map(|check| check.name).collect();
+        assert_eq!(failed, vec!["hot_pixels", "timestamp_monotonicity"]);
+
+        let mut short = SelfTest::new(40, 50, SelfTestConfig::default());
+        short.run(static_scene().into_iter().take(10));
+        assert!(short.report().checks.iter().all(|check| check.status == CheckStatus::Inconclusive));
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! serde support, with the `serde` feature: `SaeEvent` (including its descriptor)
+//! and `CornerKind` derive `Serialize` and `Deserialize`, `SparseSae` is a compact
+//! serializable form of an SAE, and corner streams can be logged as JSON lines.
+//!
+//! The derives work with any serde format, eg bincode for compact binary logs.
+//! Descriptors don't fit serde's fixed-size array support, so they are written as
+//! a sequence of floats through the `descriptor` module, which custom types holding
+//! a descriptor can use with `#[serde(with = "arcstar::serialize::descriptor")]`.
+
+use std::io::{self, BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::sae_types::*;
+use crate::sink::{CornerSink, LatchedWriter};
+
+
+/// serde functions for an optional boxed descriptor
+pub mod descriptor {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::sae_types::*;
+
+    pub fn serialize<S: Serializer>(desc: &Option<Box<NormDescriptor>>, serializer: S) -> Result<S::Ok, S::Error> {
+        desc.as_ref().map(|desc| &desc[..]).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Box<NormDescriptor>>, D::Error> {
+        let values: Option<Vec<f32>> = Option::deserialize(deserializer)?;
+        values
+            .map(|values| {
+                if values.len() != NORM_DESCRIPTOR_LEN {
+                    return Err(D::Error::invalid_length(values.len(), &"a descriptor of 36 values"));
+                }
+                let mut desc = [0.0; NORM_DESCRIPTOR_LEN];
+                desc.copy_from_slice(&values);
+                Ok(Box::new(desc))
+            })
+            .transpose()
+    }
+}
+
+/// An SAE as the list of its nonzero pixels, which is much smaller than the full
+/// matrix for the sparse surfaces of most scenes
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SparseSae {
+    pub nrows: u32,
+    pub ncols: u32,
+    /// (row-major pixel index, timestamp), in index order
+    pub pixels: Vec<(u32, SaeTime)>,
+}
+
+impl SparseSae {
+    pub fn from_matrix(sae: &SaeMatrix) -> Self {
+        let ncols = sae.ncols();
+        let pixels = (0..sae.nrows())
+            .flat_map(|row| (0..ncols).map(move |col| (row, col)))
+            .filter(|&pos| sae[pos] != 0)
+            .map(|(row, col)| ((row * ncols + col) as u32, sae[(row, col)]))
+            .collect();
+        SparseSae { nrows: sae.nrows() as u32, ncols: ncols as u32, pixels }
+    }
+
+    /// The full matrix; pixels outside it are ignored
+    pub fn to_matrix(&self) -> SaeMatrix {
+        let mut sae = SaeMatrix::zeros(self.nrows as usize, self.ncols as usize);
+        for &(idx, timestamp) in self.pixels.iter() {
+            let (row, col) = ((idx / self.ncols.max(1)) as usize, (idx % self.ncols.max(1)) as usize);
+            if row < sae.nrows() && col < sae.ncols() {
+                sae[(row, col)] = timestamp;
+            }
+        }
+        sae
+    }
+}
+
+/// Writes one JSON object per corner and line, with every field including the descriptor,
+/// through a `LatchedWriter`
+pub struct JsonLinesSink<W: Write> {
+    writer: LatchedWriter<W>,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        JsonLinesSink { writer: LatchedWriter::new(writer) }
+    }
+
+    /// the first write error encountered, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.writer.error()
+    }
+
+    /// flush and return the underlying writer
+    pub fn finish(self) -> io::Result<W> {
+        self.writer.finish()
+    }
+}
+
+impl<W: Write> CornerSink for JsonLinesSink<W> {
+    fn accept(&mut self, corner: &SaeEvent) {
+        self.writer.write_with(|writer| {
+            serde_json::to_writer(&mut *writer, corner)?;
+            writer.write_all(b"\n")
+        });
+    }
+}
+
+/// Read corners as written by `JsonLinesSink`, skipping blank lines
+pub fn read_json_lines<R: BufRead>(reader: R) -> io::Result<Vec<SaeEvent>> {
+    let mut corners = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        corners.push(serde_json::from_str(&line)?);
+    }
+    Ok(corners)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corner_log_round_trip() {
+        let mut desc = [0.0; NORM_DESCRIPTOR_LEN];
+        desc[35] = 0.5;
+        let corners = vec![
+            SaeEvent { row: 3, col: 4, polarity: 1, timestamp: 99, norm_descriptor: Some(Box::new(desc)),
+                       confidence: Some(0.75), corner_kind: Some(CornerKind::Inside), ..SaeEvent::default() },
+            SaeEvent { row: 5, col: 6, timestamp: 120, row_f: Some(5.25), ..SaeEvent::default() },
+        ];
+        let mut sink = JsonLinesSink::new(Vec::new());
+        corners.iter().for_each(|corner| sink.accept(corner));
+        let bytes = sink.finish().unwrap();
+        let read = read_json_lines(&bytes[..]).unwrap();
+        assert_eq!(read, corners);
+        assert_eq!(read[0].norm_descriptor.as_ref().map(|desc| desc[35]), Some(0.5));
+        assert_eq!((read[0].confidence, read[0].corner_kind), (Some(0.75), Some(CornerKind::Inside)));
+        assert_eq!((read[1].norm_descriptor.is_none(), read[1].row_f), (true, Some(5.25)));
+
+        let short = "{\"row\":1,\"col\":1,\"polarity\":0,\"timestamp\":1,\"norm_descriptor\":[1.0],\"row_f\":null,\
+                     \"col_f\":null,\"confidence\":0.0,\"orientation\":null,\"corner_kind\":null,\"scale\":null}";
+        assert!(read_json_lines(short.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_sparse_sae() {
+        let mut sae = SaeMatrix::zeros(4, 5);
+        sae[(1, 2)] = 7;
+        sae[(3, 4)] = 9;
+        let sparse = SparseSae::from_matrix(&sae);
+        assert_eq!(sparse.pixels, vec![(7, 7), (19, 9)]);
+        let json = serde_json::to_string(&sparse).unwrap();
+        assert_eq!(serde_json::from_str::<SparseSae>(&json).unwrap().to_matrix(), sae);
+    }
+}
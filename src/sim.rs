@@ -0,0 +1,378 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! An event camera simulator with sensor noise, for tuning parameters on synthetic data.
+//!
+//! Events are generated from a sequence of intensity frames as in ESIM (Rebecq et al.,
+//! "ESIM: an Open Event Camera Simulator", CoRL 2018): each pixel emits an event
+//! whenever its log intensity, interpolated linearly between frames, moves a contrast
+//! threshold away from the level of its previous event. On top of this ideal sensor,
+//! `SensorNoiseConfig` models the non-idealities of real pixels:
+//!
+//! - threshold mismatch: each pixel's ON and OFF thresholds are drawn once, normally
+//!   distributed around the nominal threshold;
+//! - shot noise: background events of random polarity, a Poisson process at each pixel;
+//! - leak events: ON events caused by junction leakage, a Poisson process at each pixel;
+//! - refractory period: a pixel emits no event for a while after each event;
+//! - hot pixels: a few pixels firing at a high rate regardless of the scene.
+//!
+//! The presets use approximate values from published sensor characterizations;
+//! measure the sensor at hand for closer agreement.
+
+use rand::distributions::{Exp1, StandardNormal};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::eval::klt::GrayFrame;
+use crate::sae_types::*;
+
+
+/// added to intensities before taking their log, so that black pixels stay finite
+const LOG_EPS: f32 = 1e-3;
+
+/// Sensor non-idealities applied by `EventSimulator`. Rates are per pixel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SensorNoiseConfig {
+    /// nominal log-intensity change triggering an ON event
+    pub on_threshold: f32,
+    /// nominal log-intensity change triggering an OFF event
+    pub off_threshold: f32,
+    /// standard deviation of per-pixel thresholds, as a fraction of the nominal threshold
+    pub threshold_mismatch: f32,
+    /// background events of random polarity, Hz
+    pub shot_noise_rate: f32,
+    /// leakage ON events, Hz
+    pub leak_rate: f32,
+    /// time after an event during which a pixel cannot fire again
+    pub refractory_period: SaeTime,
+    /// fraction of pixels that are hot
+    pub hot_pixel_fraction: f32,
+    /// event rate of each hot pixel, Hz
+    pub hot_pixel_rate: f32,
+    pub seed: u64,
+}
+
+impl SensorNoiseConfig {
+    /// An ideal sensor: no noise, exact thresholds of 0.2
+    pub fn ideal() -> Self {
+        SensorNoiseConfig {
+            on_threshold: 0.2,
+            off_threshold: 0.2,
+            threshold_mismatch: 0.0,
+            shot_noise_rate: 0.0,
+            leak_rate: 0.0,
+            refractory_period: 0,
+            hot_pixel_fraction: 0.0,
+            hot_pixel_rate: 0.0,
+            seed: 0,
+        }
+    }
+
+    /// A DVS128, after Lichtsteiner et al., "A 128x128 120 dB 15 us Latency Asynchronous
+    /// Temporal Contrast Vision Sensor" (2008): 2.1% contrast mismatch, and background
+    /// activity near 0.05 Hz per pixel at room temperature
+    pub fn dvs128() -> Self {
+        SensorNoiseConfig {
+            on_threshold: 0.15,
+            off_threshold: 0.15,
+            threshold_mismatch: 0.021 / 0.15,
+            shot_noise_rate: 0.05,
+            leak_rate: 0.01,
+            refractory_period: 50,
+            hot_pixel_fraction: 0.0005,
+            hot_pixel_rate: 100.0,
+            ..Self::ideal()
+        }
+    }
+
+    /// A DAVIS346 under indoor lighting, after Taverni et al., "Front and Back
+    /// Illuminated Dynamic and Active Pixel Vision Sensors Comparison" (2018)
+    pub fn davis346() -> Self {
+        SensorNoiseConfig {
+            on_threshold: 0.25,
+            off_threshold: 0.25,
+            threshold_mismatch: 0.1,
+            shot_noise_rate: 0.1,
+            leak_rate: 0.05,
+            refractory_period: 100,
+            hot_pixel_fraction: 0.001,
+            hot_pixel_rate: 200.0,
+            ..Self::ideal()
+        }
+    }
+}
+
+impl Default for SensorNoiseConfig {
+    fn default() -> Self {
+        Self::ideal()
+    }
+}
+
+/// Counts of simulated events by origin
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimulatorStats {
+    /// events caused by intensity changes
+    pub signal: u64,
+    pub shot_noise: u64,
+    pub leak: u64,
+    pub hot_pixel: u64,
+    /// events of any origin suppressed during a refractory period
+    pub refractory_suppressed: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Origin {
+    Signal,
+    ShotNoise,
+    Leak,
+    HotPixel,
+}
+
+/// Generates events, with sensor noise, from a sequence of intensity frames
+pub struct EventSimulator {
+    config: SensorNoiseConfig,
+    nrows: usize,
+    ncols: usize,
+    /// per-pixel [ON, OFF] thresholds, row-major
+    thresholds: Vec<[f32; 2]>,
+    /// per-pixel log intensity at the last event, row-major
+    reference: Vec<f32>,
+    /// per-pixel time of the last event emitted
+    last_event: Vec<Option<SaeTime>>,
+    /// row-major indices of the hot pixels
+    hot_pixels: Vec<usize>,
+    /// log intensities and time of the previous frame
+    previous: Option<(Vec<f32>, SaeTime)>,
+    rng: StdRng,
+    stats: SimulatorStats,
+}
+
+impl EventSimulator {
+    pub fn new(nrows: usize, ncols: usize, config: SensorNoiseConfig) -> Self {
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let npixels = nrows * ncols;
+        let draw_threshold = |nominal: f32, rng: &mut StdRng| {
+            let deviation = rng.sample::<f64, _>(StandardNormal) as f32 * config.threshold_mismatch;
+            // thresholds stay positive however wide the mismatch
+            (nominal * (1.0 + deviation)).max(nominal * 0.05)
+        };
+        let thresholds = (0..npixels)
+            .map(|_| [draw_threshold(config.on_threshold, &mut rng), draw_threshold(config.off_threshold, &mut rng)])
+            .collect();
+        let hot_count = (config.hot_pixel_fraction * npixels as f32).round() as usize;
+        let hot_pixels = rand::seq::index::sample(&mut rng, npixels, hot_count.min(npixels)).into_vec();
+        EventSimulator {
+            nrows,
+            ncols,
+            thresholds,
+            reference: vec![0.0; npixels],
+            last_event: vec![None; npixels],
+            hot_pixels,
+            previous: None,
+            rng,
+            stats: SimulatorStats::default(),
+            config,
+        }
+    }
+
+    pub fn stats(&self) -> &SimulatorStats {
+        &self.stats
+    }
+
+    /// (row, col) of the hot pixels
+    pub fn hot_pixels(&self) -> Vec<(usize, usize)> {
+        self.hot_pixels.iter().map(|&idx| (idx / self.ncols, idx % self.ncols)).collect()
+    }
+
+    /// Add the next frame, returning the events of the interval since the previous
+    /// frame, in time order. The first frame only sets the initial intensities.
+    /// Frames must match the simulator's size and come in time order.
+    pub fn add_frame(&mut self, frame: &GrayFrame) -> Vec<SaeEvent> {
+        assert_eq!(frame.pixels.shape(), (self.nrows, self.ncols), "frame size");
+        let mut log_frame = Vec::with_capacity(self.nrows * self.ncols);
+        for row in 0..self.nrows {
+            for col in 0..self.ncols {
+                log_frame.push((frame.pixels[(row, col)].max(0.0) + LOG_EPS).ln());
+            }
+        }
+        let (prev, start) = match self.previous.take() {
+            Some(previous) => previous,
+            None => {
+                self.reference = log_frame.clone();
+                self.previous = Some((log_frame, frame.timestamp));
+                return Vec::new();
+            }
+        };
+        let end = frame.timestamp.max(start);
+
+        let mut candidates: Vec<(SaeTime, usize, u8, Origin)> = Vec::new();
+        self.signal_events(&prev, &log_frame, start, end, &mut candidates);
+        self.noise_events(start, end, &mut candidates);
+        candidates.sort_by_key(|&(timestamp, idx, _, _)| (timestamp, idx));
+
+        let mut events = Vec::with_capacity(candidates.len());
+        for (timestamp, idx, polarity, origin) in candidates {
+            let refractory = self.config.refractory_period;
+            if self.last_event[idx].is_some_and(|last| timestamp < last.saturating_add(refractory)) {
+                self.stats.refractory_suppressed += 1;
+                continue;
+            }
+            self.last_event[idx] = Some(timestamp);
+            match origin {
+                Origin::Signal => self.stats.signal += 1,
+                Origin::ShotNoise => self.stats.shot_noise += 1,
+                Origin::Leak => self.stats.leak += 1,
+                Origin::HotPixel => self.stats.hot_pixel += 1,
+            }
+            events.push(SaeEvent {
+                row: (idx / self.ncols) as u16,
+                col: (idx % self.ncols) as u16,
+                polarity,
+                timestamp,
+                ..SaeEvent::default()
+            });
+        }
+        self.previous = Some((log_frame, end));
+        events
+    }
+
+    /// Threshold crossings of the log intensity, interpolated linearly between frames
+    fn signal_events(&mut self, prev: &[f32], next: &[f32], start: SaeTime, end: SaeTime,
+                     out: &mut Vec<(SaeTime, usize, u8, Origin)>) {
+        let span = (end - start) as f32;
+        for idx in 0..next.len() {
+            let (from, to) = (prev[idx], next[idx]);
+            let change = to - from;
+            if change == 0.0 {
+                continue;
+            }
+            let [on, off] = self.thresholds[idx];
+            let reference = &mut self.reference[idx];
+            loop {
+                let (level, polarity) = if to >= *reference + on {
+                    (*reference + on, 1)
+                } else if to <= *reference - off {
+                    (*reference - off, 0)
+                } else {
+                    break;
+                };
+                *reference = level;
+                let frac = ((level - from) / change).clamp(0.0, 1.0);
+                out.push((start + (frac * span).round() as SaeTime, idx, polarity, Origin::Signal));
+            }
+        }
+    }
+
+    /// Times of a Poisson process of `rate` Hz over the interval
+    fn poisson_times(rng: &mut StdRng, rate: f64, start: SaeTime, end: SaeTime) -> Vec<SaeTime> {
+        let mut times = Vec::new();
+        if rate <= 0.0 {
+            return times;
+        }
+        let mut t = start as f64;
+        loop {
+            t += rng.sample::<f64, _>(Exp1) / rate * 1e6;
+            if t >= end as f64 {
+                return times;
+            }
+            times.push(t as SaeTime);
+        }
+    }
+
+    fn noise_events(&mut self, start: SaeTime, end: SaeTime, out: &mut Vec<(SaeTime, usize, u8, Origin)>) {
+        let npixels = self.nrows * self.ncols;
+        if npixels == 0 {
+            return;
+        }
+        // the superposition of per-pixel processes, each event at a uniformly random pixel
+        let shot_rate = self.config.shot_noise_rate as f64 * npixels as f64;
+        for timestamp in Self::poisson_times(&mut self.rng, shot_rate, start, end) {
+            let idx = self.rng.gen_range(0, npixels);
+            let polarity = self.rng.gen_range(0, 2);
+            out.push((timestamp, idx, polarity, Origin::ShotNoise));
+        }
+        let leak_rate = self.config.leak_rate as f64 * npixels as f64;
+        for timestamp in Self::poisson_times(&mut self.rng, leak_rate, start, end) {
+            let idx = self.rng.gen_range(0, npixels);
+            out.push((timestamp, idx, 1, Origin::Leak));
+        }
+        for i in 0..self.hot_pixels.len() {
+            let idx = self.hot_pixels[i];
+            for timestamp in Self::poisson_times(&mut self.rng, self.config.hot_pixel_rate as f64, start, end) {
+                let polarity = self.rng.gen_range(0, 2);
+                out.push((timestamp, idx, polarity, Origin::HotPixel));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::DMatrix;
+
+    fn frame(timestamp: SaeTime, value: f32) -> GrayFrame {
+        GrayFrame { timestamp, pixels: DMatrix::from_element(8, 8, value) }
+    }
+
+    #[test]
+    fn test_ideal_sensor() {
+        let mut sim = EventSimulator::new(8, 8, SensorNoiseConfig::ideal());
+        assert!(sim.add_frame(&frame(0, 1.0)).is_empty());
+        // log change of ln(e^0.5) = 0.5 crosses two thresholds of 0.2 at every pixel
+        let events = sim.add_frame(&frame(1_000, 0.5f32.exp() * (1.0 + LOG_EPS) - LOG_EPS));
+        assert_eq!(events.len(), 2 * 64);
+        assert!(events.iter().all(|evt| evt.polarity == 1));
+        assert!(events.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        assert_eq!(events[0].timestamp, 400);
+        assert_eq!(events[64].timestamp, 800);
+        // down by 0.6 from the last event level of 0.4: OFF events at 0.2 and 0.0
+        let events = sim.add_frame(&frame(2_000, (-0.1f32).exp() * (1.0 + LOG_EPS) - LOG_EPS));
+        assert_eq!(events.len(), 2 * 64);
+        assert!(events.iter().all(|evt| evt.polarity == 0));
+        assert_eq!(sim.stats().signal, 4 * 64);
+    }
+
+    #[test]
+    fn test_noise_models() {
+        let config = SensorNoiseConfig {
+            shot_noise_rate: 10.0,
+            leak_rate: 5.0,
+            hot_pixel_fraction: 2.0 / 64.0,
+            hot_pixel_rate: 1_000.0,
+            seed: 3,
+            ..SensorNoiseConfig::ideal()
+        };
+        let mut sim = EventSimulator::new(8, 8, config.clone());
+        sim.add_frame(&frame(0, 1.0));
+        // a static scene for one second: only noise
+        let events = sim.add_frame(&frame(1_000_000, 1.0));
+        let stats = sim.stats().clone();
+        assert_eq!(stats.signal, 0);
+        assert_eq!(events.len() as u64, stats.shot_noise + stats.leak + stats.hot_pixel);
+        // within about four standard deviations of the expected counts
+        assert!((stats.shot_noise as f64 - 640.0).abs() < 100.0);
+        assert!((stats.leak as f64 - 320.0).abs() < 75.0);
+        assert!((stats.hot_pixel as f64 - 2_000.0).abs() < 180.0);
+        assert_eq!(sim.hot_pixels().len(), 2);
+
+        // the same seed reproduces the same events
+        let mut again = EventSimulator::new(8, 8, config);
+        again.add_frame(&frame(0, 1.0));
+        assert_eq!(again.add_frame(&frame(1_000_000, 1.0)), events);
+    }
+
+    #[test]
+    fn test_mismatch_and_refractory() {
+        let config = SensorNoiseConfig { threshold_mismatch: 0.2, refractory_period: 1_000, seed: 1, ..SensorNoiseConfig::ideal() };
+        let mut sim = EventSimulator::new(8, 8, config);
+        let thresholds: Vec<f32> = sim.thresholds.iter().map(|t| t[0]).collect();
+        assert!(thresholds.iter().any(|&t| (t - 0.2).abs() > 0.01));
+        sim.add_frame(&frame(0, 1.0));
+        // a large step within one refractory period: one event per pixel
+        let events = sim.add_frame(&frame(500, 100.0));
+        assert_eq!(events.len(), 64);
+        assert!(sim.stats().refractory_suppressed > 0);
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Destinations for detected corners.
+//! Any `CornerSink` can be attached to a pipeline, so consumers aren't forced
+//! to adapt to a single output style.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+#[cfg(feature = "net")]
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+#[cfg(feature = "std")]
+use std::sync::mpsc::Sender;
+
+#[cfg(feature = "net")]
+use crate::io::compact::encode_event;
+use crate::sae_types::*;
+
+
+/// Receives each detected corner
+pub trait CornerSink {
+    fn accept(&mut self, corner: &SaeEvent);
+}
+
+impl CornerSink for Vec<SaeEvent> {
+    fn accept(&mut self, corner: &SaeEvent) {
+        self.push(corner.clone());
+    }
+}
+
+impl<S: CornerSink + ?Sized> CornerSink for &mut S {
+    fn accept(&mut self, corner: &SaeEvent) {
+        (**self).accept(corner)
+    }
+}
+
+impl<S: CornerSink + ?Sized> CornerSink for Box<S> {
+    fn accept(&mut self, corner: &SaeEvent) {
+        (**self).accept(corner)
+    }
+}
+
+/// Forwards corners to a channel, eg to hand them to another thread.
+/// Corners are silently discarded once the receiver hangs up.
+#[cfg(feature = "std")]
+pub struct ChannelSink {
+    sender: Sender<SaeEvent>,
+}
+
+#[cfg(feature = "std")]
+impl ChannelSink {
+    pub fn new(sender: Sender<SaeEvent>) -> Self {
+        ChannelSink { sender }
+    }
+}
+
+#[cfg(feature = "std")]
+impl CornerSink for ChannelSink {
+    fn accept(&mut self, corner: &SaeEvent) {
+        let _ = self.sender.send(corner.clone());
+    }
+}
+
+/// Keeps only the most recent corners, up to a fixed capacity
+pub struct RingBufferSink {
+    buf: VecDeque<SaeEvent>,
+    capacity: usize,
+}
+
+impl RingBufferSink {
+    pub fn new(capacity: usize) -> Self {
+        RingBufferSink {
+            buf: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// buffered corners, oldest first
+    pub fn corners(&self) -> impl Iterator<Item = &SaeEvent> {
+        self.buf.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn drain(&mut self) -> Vec<SaeEvent> {
+        self.buf.drain(..).collect()
+    }
+}
+
+impl CornerSink for RingBufferSink {
+    fn accept(&mut self, corner: &SaeEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.buf.len() == self.capacity {
+            self.buf.pop_front();
+        }
+        self.buf.push_back(corner.clone());
+    }
+}
+
+/// A writer for sinks and branches that can't report errors as they accept events:
+/// output stops at the first write error, which is kept for inspection
+#[cfg(feature = "std")]
+pub struct LatchedWriter<W> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<W> LatchedWriter<W> {
+    pub fn new(writer: W) -> Self {
+        LatchedWriter { writer, error: None }
+    }
+
+    /// Write with `write`, unless an earlier write failed, keeping its error if it fails.
+    /// Returns whether it was written.
+    pub fn write_with<F: FnOnce(&mut W) -> io::Result<()>>(&mut self, write: F) -> bool {
+        if self.error.is_some() {
+            return false;
+        }
+        match write(&mut self.writer) {
+            Ok(()) => true,
+            Err(err) => {
+                self.error = Some(err);
+                false
+            }
+        }
+    }
+
+    /// the first write error encountered, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// the underlying writer, or the first write error
+    pub fn into_inner(self) -> io::Result<W> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.writer),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> LatchedWriter<W> {
+    /// flush and return the underlying writer, or the first write error
+    pub fn finish(self) -> io::Result<W> {
+        let mut writer = self.into_inner()?;
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+/// Writes one CSV line per corner: `timestamp,row,col,polarity`, through a `LatchedWriter`
+#[cfg(feature = "std")]
+pub struct CsvSink<W: Write> {
+    writer: LatchedWriter<W>,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> CsvSink<W> {
+    /// Writes the CSV header line immediately
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "timestamp,row,col,polarity")?;
+        Ok(CsvSink { writer: LatchedWriter::new(writer) })
+    }
+
+    /// the first write error encountered, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.writer.error()
+    }
+
+    /// flush and return the underlying writer
+    pub fn finish(self) -> io::Result<W> {
+        self.writer.finish()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> CornerSink for CsvSink<W> {
+    fn accept(&mut self, corner: &SaeEvent) {
+        self.writer.write_with(|writer| writeln!(writer, "{},{},{},{}",
+                                                 corner.timestamp, corner.row, corner.col, corner.polarity));
+    }
+}
+
+/// Publishes each corner as a compact binary record in its own UDP datagram.
+/// Delivery is best-effort: send failures are counted rather than reported.
+#[cfg(feature = "net")]
+pub struct UdpSink {
+    socket: UdpSocket,
+    send_failures: u64,
+}
+
+#[cfg(feature = "net")]
+impl UdpSink {
+    /// Bind a local socket and direct datagrams to `dest`. The socket is bound to
+    /// an ephemeral port on the unspecified address of each resolved destination's
+    /// family in turn, so IPv6 destinations are reached from an IPv6 socket.
+    pub fn connect<A: ToSocketAddrs>(dest: A) -> io::Result<Self> {
+        let mut last_err = None;
+        for addr in dest.to_socket_addrs()? {
+            let bind: SocketAddr = match addr {
+                SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+                SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+            };
+            match Self::connect_from(bind, addr) {
+                Ok(sink) => return Ok(sink),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no destination address")))
+    }
+
+    /// Bind a local socket to `bind`, eg a specific interface, and direct datagrams to `dest`
+    pub fn connect_from<B: ToSocketAddrs, A: ToSocketAddrs>(bind: B, dest: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind(bind)?;
+        socket.connect(dest)?;
+        Ok(UdpSink { socket, send_failures: 0 })
+    }
+
+    /// number of corners that could not be sent
+    pub fn send_failures(&self) -> u64 {
+        self.send_failures
+    }
+}
+
+#[cfg(feature = "net")]
+impl CornerSink for UdpSink {
+    fn accept(&mut self, corner: &SaeEvent) {
+        if self.socket.send(&encode_event(corner)).is_err() {
+            self.send_failures += 1;
+        }
+    }
+}
+
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    fn corner_at(timestamp: SaeTime) -> SaeEvent {
+        SaeEvent {
+            row: 1,
+            col: 2,
+            timestamp,
+            ..SaeEvent::default()
+        }
+    }
+
+    #[test]
+    fn test_ring_buffer_sink() {
+        let mut sink = RingBufferSink::new(2);
+        for t in 0..5 {
+            sink.accept(&corner_at(t));
+        }
+        assert_eq!(sink.len(), 2);
+        let times: Vec<SaeTime> = sink.corners().map(|c| c.timestamp).collect();
+        assert_eq!(times, vec![3, 4]);
+        assert_eq!(sink.drain().len(), 2);
+        assert!(sink.is_empty());
+    }
+
+    #[test]
+    fn test_csv_sink() {
+        let mut sink = CsvSink::new(Vec::new()).unwrap();
+        sink.accept(&corner_at(7));
+        let text = String::from_utf8(sink.finish().unwrap()).unwrap();
+        assert_eq!(text, "timestamp,row,col,polarity\n7,1,2,0\n");
+    }
+
+    #[test]
+    fn test_csv_sink_latches_write_error() {
+        // room for the header and one line
+        let mut buf = [0u8; 36];
+        let mut sink = CsvSink::new(&mut buf[..]).unwrap();
+        sink.accept(&corner_at(7));
+        assert!(sink.error().is_none());
+        sink.accept(&corner_at(8));
+        assert_eq!(sink.error().map(|err| err.kind()), Some(io::ErrorKind::WriteZero));
+        sink.accept(&corner_at(9));
+        assert!(sink.finish().is_err());
+        assert!(buf.ends_with(b"7,1,2,0\n8"));
+    }
+
+    #[test]
+    fn test_channel_and_generic_sinks() {
+        let (tx, rx) = channel();
+        let mut sink = ChannelSink::new(tx);
+        sink.accept(&corner_at(1));
+        assert_eq!(rx.recv().unwrap(), corner_at(1));
+
+        let mut boxed: Box<dyn CornerSink> = Box::new(Vec::new());
+        boxed.accept(&corner_at(2));
+
+        fn feed<S: CornerSink>(mut sink: S, corner: &SaeEvent) {
+            sink.accept(corner);
+        }
+        let mut collected: Vec<SaeEvent> = Vec::new();
+        feed(&mut collected, &corner_at(3));
+        assert_eq!(collected, vec![corner_at(3)]);
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn test_udp_sink() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sink = UdpSink::connect(receiver.local_addr().unwrap()).unwrap();
+        sink.accept(&corner_at(42));
+
+        let mut buf = [0u8; 64];
+        let nread = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..nread], &encode_event(&corner_at(42))[..]);
+        assert_eq!(sink.send_failures(), 0);
+
+        // an IPv6 destination is reached from an IPv6 socket
+        if let Ok(receiver) = UdpSocket::bind("[::1]:0") {
+            let mut sink = UdpSink::connect(receiver.local_addr().unwrap()).unwrap();
+            sink.accept(&corner_at(43));
+            let nread = receiver.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..nread], &encode_event(&corner_at(43))[..]);
+        }
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sink = UdpSink::connect_from("127.0.0.1:0", receiver.local_addr().unwrap()).unwrap();
+        sink.accept(&corner_at(44));
+        let nread = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..nread], &encode_event(&corner_at(44))[..]);
+    }
+}
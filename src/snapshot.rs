@@ -0,0 +1,229 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Consistent SAE snapshots for readers on other threads (renderers, analyzers)
+//! while the updater keeps writing.
+//!
+//! The publisher alternates between two snapshot buffers. On each publish, only the
+//! tiles that changed since that buffer was last written are copied from the surface;
+//! readers always see a complete, immutable snapshot. If a reader still holds the
+//! buffer about to be reused, that buffer is copied rather than overwritten.
+
+use std::sync::{Arc, Mutex};
+
+use crate::sae_types::*;
+use crate::surface::SaeSurface;
+use crate::tiles::{DirtyTiles, TileRect};
+
+
+/// A point-in-time copy of an SAE
+#[derive(Clone, Debug)]
+pub struct SaeSnapshot {
+    pub sae: SaeMatrix,
+    /// timestamp of the most recent event included in the snapshot
+    pub timestamp: SaeTime,
+    /// incremented with every published snapshot
+    pub sequence: u64,
+    /// tiles that changed since the previous snapshot
+    pub changed: Vec<TileRect>,
+}
+
+/// How often snapshots are published
+#[derive(Clone, Debug, PartialEq)]
+pub enum SnapshotCadence {
+    /// after every n events
+    EveryEvents(u64),
+    /// once this much event time has passed since the last snapshot
+    Interval(SaeTime),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotConfig {
+    pub cadence: SnapshotCadence,
+    /// side length of the square tiles tracked for changes
+    pub tile_size: usize,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig {
+            // about 30 snapshots per second of event time
+            cadence: SnapshotCadence::Interval(33_333),
+            tile_size: 32,
+        }
+    }
+}
+
+/// Read side of a snapshot publisher: cheap to clone and send to other threads
+#[derive(Clone)]
+pub struct SnapshotReader {
+    latest: Arc<Mutex<Arc<SaeSnapshot>>>,
+}
+
+impl SnapshotReader {
+    /// the most recently published snapshot
+    pub fn latest(&self) -> Arc<SaeSnapshot> {
+        self.latest.lock().unwrap().clone()
+    }
+}
+
+/// Write side: observes surface updates and publishes snapshots at the configured cadence
+pub struct SnapshotPublisher {
+    config: SnapshotConfig,
+    buffers: [Arc<SaeSnapshot>; 2],
+    /// tiles changed since each buffer was last written
+    dirty: [DirtyTiles; 2],
+    /// tiles changed since the last published snapshot
+    since_publish: DirtyTiles,
+    /// index of the buffer to write next
+    back: usize,
+    shared: Arc<Mutex<Arc<SaeSnapshot>>>,
+    events_since_publish: u64,
+    last_publish_time: Option<SaeTime>,
+    sequence: u64,
+    tiles_copied: u64,
+}
+
+impl SnapshotPublisher {
+    pub fn new(nrows: usize, ncols: usize, config: SnapshotConfig) -> Self {
+        let empty = Arc::new(SaeSnapshot {
+            sae: SaeMatrix::zeros(nrows, ncols),
+            timestamp: 0,
+            sequence: 0,
+            changed: Vec::new(),
+        });
+        // both buffers need a full copy at first
+        let mut dirty = DirtyTiles::new(nrows, ncols, config.tile_size);
+        dirty.mark_all();
+        SnapshotPublisher {
+            config,
+            buffers: [empty.clone(), Arc::new((*empty).clone())],
+            since_publish: DirtyTiles::new(nrows, ncols, dirty.tile_size()),
+            dirty: [dirty.clone(), dirty],
+            back: 0,
+            shared: Arc::new(Mutex::new(empty)),
+            events_since_publish: 0,
+            last_publish_time: None,
+            sequence: 0,
+            tiles_copied: 0,
+        }
+    }
+
+    /// a handle for reading published snapshots
+    pub fn reader(&self) -> SnapshotReader {
+        SnapshotReader { latest: self.shared.clone() }
+    }
+
+    /// tiles changed since the last published snapshot
+    pub fn pending_changes(&self) -> &DirtyTiles {
+        &self.since_publish
+    }
+
+    /// total tiles copied into snapshot buffers so far
+    pub fn tiles_copied(&self) -> u64 {
+        self.tiles_copied
+    }
+
+    /// Note an event that has just been applied to `surface`,
+    /// publishing a snapshot if one is due. Returns whether a snapshot was published.
+    pub fn observe(&mut self, surface: &SaeSurface, evt: &SaeEvent) -> bool {
+        for dirty in self.dirty.iter_mut() {
+            dirty.mark(evt.row as usize, evt.col as usize);
+        }
+        self.since_publish.mark(evt.row as usize, evt.col as usize);
+        self.events_since_publish += 1;
+
+        let due = match self.config.cadence {
+            SnapshotCadence::EveryEvents(n) => self.events_since_publish >= n,
+            SnapshotCadence::Interval(interval) => match self.last_publish_time {
+                Some(last) => evt.timestamp.saturating_sub(last) >= interval,
+                None => true,
+            },
+        };
+        if due {
+            self.publish(surface, evt.timestamp);
+        }
+        due
+    }
+
+    /// Publish a snapshot of the surface now, regardless of cadence
+    pub fn publish(&mut self, surface: &SaeSurface, timestamp: SaeTime) {
+        self.sequence += 1;
+        let back = self.back;
+        // copies the whole buffer only if a reader still holds it
+        let snapshot = Arc::make_mut(&mut self.buffers[back]);
+        self.tiles_copied += self.dirty[back].copy_dirty(surface.matrix(), &mut snapshot.sae) as u64;
+        snapshot.timestamp = timestamp;
+        snapshot.sequence = self.sequence;
+        snapshot.changed = self.since_publish.dirty_tiles().collect();
+        self.since_publish.clear();
+
+        *self.shared.lock().unwrap() = self.buffers[back].clone();
+        self.back = 1 - back;
+        self.events_since_publish = 0;
+        self.last_publish_time = Some(timestamp);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_at(row: u16, col: u16, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row, col, timestamp, ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_snapshots_are_consistent() {
+        let mut surface = SaeSurface::new(8, 8);
+        let config = SnapshotConfig { cadence: SnapshotCadence::EveryEvents(2), tile_size: 4 };
+        let mut publisher = SnapshotPublisher::new(8, 8, config);
+        let reader = publisher.reader();
+
+        for (i, evt) in [event_at(1, 1, 10), event_at(6, 6, 20)].iter().enumerate() {
+            surface.update(evt);
+            assert_eq!(publisher.observe(&surface, evt), i == 1);
+        }
+        let first = reader.latest();
+        assert_eq!(first.sequence, 1);
+        assert_eq!(first.sae[(1, 1)], 10);
+        assert_eq!(first.sae[(6, 6)], 20);
+
+        // the held snapshot doesn't change while the updater keeps writing
+        for ts in 30..40 {
+            let evt = event_at(1, 1, ts);
+            surface.update(&evt);
+            publisher.observe(&surface, &evt);
+        }
+        assert_eq!(first.sae[(1, 1)], 10);
+        let latest = reader.latest();
+        assert_eq!(latest.sequence, 6);
+        assert_eq!(latest.sae[(1, 1)], 39);
+        assert_eq!(latest.sae[(6, 6)], 20);
+    }
+
+    #[test]
+    fn test_only_dirty_tiles_copied() {
+        let mut surface = SaeSurface::new(8, 8);
+        let config = SnapshotConfig { cadence: SnapshotCadence::Interval(100), tile_size: 4 };
+        let mut publisher = SnapshotPublisher::new(8, 8, config);
+        // both buffers start out fully dirty: 4 tiles each
+        let evt = event_at(0, 0, 0);
+        surface.update(&evt);
+        assert!(publisher.observe(&surface, &evt));
+        let evt = event_at(0, 0, 100);
+        surface.update(&evt);
+        assert!(publisher.observe(&surface, &evt));
+        assert_eq!(publisher.tiles_copied(), 8);
+
+        // afterwards only tiles changed since the buffer was last written are copied
+        let evt = event_at(5, 5, 250);
+        surface.update(&evt);
+        assert!(publisher.observe(&surface, &evt));
+        assert_eq!(publisher.tiles_copied(), 10);
+        let latest = publisher.reader().latest();
+        assert_eq!(latest.sae[(5, 5)], 250);
+        assert_eq!(latest.changed, vec![TileRect { row: 4, col: 4, nrows: 4, ncols: 4 }]);
+    }
+}
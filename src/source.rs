@@ -0,0 +1,116 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Producers of change events: files, network streams, cameras.
+//! `EventSource` is the counterpart of `CornerSink`, allowing pipelines
+//! to be constructed generically over where events come from.
+
+use std::io;
+use std::sync::mpsc::Receiver;
+
+use crate::sae_types::*;
+
+
+/// A source of time-ordered change events, supporting both pull and callback styles
+pub trait EventSource {
+    /// Pull the next event, or `None` once the source is exhausted
+    fn next_event(&mut self) -> io::Result<Option<SaeEvent>>;
+
+    /// Deliver every remaining event to the callback, returning the number delivered
+    fn for_each_event<F: FnMut(&SaeEvent)>(&mut self, mut callback: F) -> io::Result<u64>
+        where Self: Sized
+    {
+        let mut count = 0;
+        while let Some(evt) = self.next_event()? {
+            callback(&evt);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Adapt the source into an iterator of results
+    fn events(&mut self) -> SourceEvents<'_, Self>
+        where Self: Sized
+    {
+        SourceEvents { source: self }
+    }
+}
+
+/// Iterator over the events of an `EventSource`
+pub struct SourceEvents<'a, S: EventSource> {
+    source: &'a mut S,
+}
+
+impl<'a, S: EventSource> Iterator for SourceEvents<'a, S> {
+    type Item = io::Result<SaeEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.source.next_event().transpose()
+    }
+}
+
+impl<S: EventSource + ?Sized> EventSource for &mut S {
+    fn next_event(&mut self) -> io::Result<Option<SaeEvent>> {
+        (**self).next_event()
+    }
+}
+
+/// Events pushed from another thread (eg by a camera driver callback).
+/// The source is exhausted once all senders hang up.
+impl EventSource for Receiver<SaeEvent> {
+    fn next_event(&mut self) -> io::Result<Option<SaeEvent>> {
+        Ok(self.recv().ok())
+    }
+}
+
+/// Wraps any in-memory iterator of events
+pub struct IterSource<I> {
+    inner: I,
+}
+
+impl<I: Iterator<Item = SaeEvent>> IterSource<I> {
+    pub fn new<T: IntoIterator<IntoIter = I, Item = SaeEvent>>(events: T) -> Self {
+        IterSource { inner: events.into_iter() }
+    }
+}
+
+impl<I: Iterator<Item = SaeEvent>> EventSource for IterSource<I> {
+    fn next_event(&mut self) -> io::Result<Option<SaeEvent>> {
+        Ok(self.inner.next())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    fn events() -> Vec<SaeEvent> {
+        (0..5).map(|t| SaeEvent { timestamp: t, ..SaeEvent::default() }).collect()
+    }
+
+    #[test]
+    fn test_iter_source() {
+        let mut source = IterSource::new(events());
+        let mut times = Vec::new();
+        let count = source.for_each_event(|evt| times.push(evt.timestamp)).unwrap();
+        assert_eq!(count, 5);
+        assert_eq!(times, vec![0, 1, 2, 3, 4]);
+        assert!(source.next_event().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_channel_source() {
+        let (tx, mut rx) = channel();
+        let producer = thread::spawn(move || {
+            for evt in events() {
+                tx.send(evt).unwrap();
+            }
+        });
+        let count = rx.for_each_event(|_| {}).unwrap();
+        producer.join().unwrap();
+        assert_eq!(count, 5);
+    }
+}
@@ -0,0 +1,181 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! A surface of recent local motion speed, for adapting suppression and matching
+//! to scenes where parts move at very different speeds.
+//!
+//! At each corner the edge speed is measured from a plane fit to the SAE around it
+//! (see `lifetime::LifetimeEstimator::speed`), then spread over the neighborhood
+//! with a weight falling off with distance, blending into what is already there.
+//! Speeds older than a window are forgotten. `SpeedSurface::scale_at` turns the local
+//! speed into a scale for distances that grow with motion: the NMS radius
+//! (`nms::NmsGrid::admit_scaled`) and tracker gates (`track::CornerTracker::add_corner_scaled`),
+//! so fast regions suppress and match over wider distances, and slow regions tighter.
+
+use nalgebra::DMatrix;
+
+use crate::lifetime::{LifetimeConfig, LifetimeEstimator};
+use crate::sae_types::*;
+use crate::surface::SaeSurface;
+
+
+/// Parameters of the speed surface
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpeedConfig {
+    /// the plane fit measuring speed at each corner
+    pub fit: LifetimeConfig,
+    /// distance (pixels) each measurement is spread over
+    pub spread: usize,
+    /// weight of a new measurement at its own pixel, against the speed already there
+    pub blend: f32,
+    /// speeds not refreshed for longer than this are forgotten
+    pub window: SaeTime,
+    /// speed (pixels per second) for which distances are not scaled
+    pub reference_speed: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+}
+
+impl Default for SpeedConfig {
+    fn default() -> Self {
+        SpeedConfig {
+            fit: LifetimeConfig::default(),
+            spread: 4,
+            blend: 0.5,
+            window: 100_000,
+            reference_speed: 200.0,
+            min_scale: 0.5,
+            max_scale: 3.0,
+        }
+    }
+}
+
+/// Recent local speed at each pixel
+pub struct SpeedSurface {
+    config: SpeedConfig,
+    estimator: LifetimeEstimator,
+    /// pixels per second
+    speed: DMatrix<f32>,
+    /// when each pixel's speed was last refreshed
+    refreshed: SaeMatrix,
+    observed: SaeOccupancy,
+}
+
+impl SpeedSurface {
+    pub fn new(nrows: usize, ncols: usize, config: SpeedConfig) -> Self {
+        SpeedSurface {
+            estimator: LifetimeEstimator::new(config.fit.clone()),
+            config,
+            speed: DMatrix::zeros(nrows, ncols),
+            refreshed: SaeMatrix::zeros(nrows, ncols),
+            observed: SaeOccupancy::from_element(nrows, ncols, false),
+        }
+    }
+
+    pub fn config(&self) -> &SpeedConfig {
+        &self.config
+    }
+
+    /// Measure the speed at `corner`, which should already have been applied to
+    /// `surface`, and spread it around. Returns the speed measured, if any.
+    pub fn observe(&mut self, surface: &SaeSurface, corner: &SaeEvent) -> Option<f32> {
+        let speed = self.estimator.speed(surface, corner)?;
+        self.add_measurement(corner.row as usize, corner.col as usize, speed, corner.timestamp);
+        Some(speed)
+    }
+
+    /// Spread a speed measured at a pixel over its neighborhood
+    pub fn add_measurement(&mut self, row: usize, col: usize, speed: f32, timestamp: SaeTime) {
+        let (nrows, ncols) = self.speed.shape();
+        if row >= nrows || col >= ncols {
+            return;
+        }
+        let spread = self.config.spread;
+        let falloff = (spread + 1) as f32;
+        for r in row.saturating_sub(spread)..=(row + spread).min(nrows - 1) {
+            for c in col.saturating_sub(spread)..=(col + spread).min(ncols - 1) {
+                let distance = (r as f32 - row as f32).hypot(c as f32 - col as f32);
+                if distance > spread as f32 {
+                    continue;
+                }
+                let weight = self.config.blend * (1.0 - distance / falloff);
+                let current = self.speed_at(r, c, timestamp);
+                self.speed[(r, c)] = match current {
+                    Some(current) => current + weight * (speed - current),
+                    None => speed,
+                };
+                self.refreshed[(r, c)] = self.refreshed[(r, c)].max(timestamp);
+                self.observed[(r, c)] = true;
+            }
+        }
+    }
+
+    /// the speed (pixels per second) at a pixel at time `now`, if measured recently
+    pub fn speed_at(&self, row: usize, col: usize, now: SaeTime) -> Option<f32> {
+        if !self.observed.get((row, col)).copied().unwrap_or(false) ||
+            !self.refreshed[(row, col)].is_within(now, self.config.window) {
+            return None;
+        }
+        Some(self.speed[(row, col)])
+    }
+
+    /// The scale for motion-dependent distances at a pixel: the local speed relative to
+    /// the reference speed, within the configured bounds, or 1 where no speed is known
+    pub fn scale_at(&self, row: usize, col: usize, now: SaeTime) -> f32 {
+        match self.speed_at(row, col, now) {
+            Some(speed) => (speed / self.config.reference_speed).clamp(self.config.min_scale, self.config.max_scale),
+            None => 1.0,
+        }
+    }
+
+    /// the scale at a corner's position and time
+    pub fn scale_for(&self, corner: &SaeEvent) -> f32 {
+        self.scale_at(corner.row as usize, corner.col as usize, corner.timestamp)
+    }
+
+    /// Forget every speed, eg after a gap in the stream
+    pub fn clear(&mut self) {
+        self.observed.fill(false);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nms::{NmsConfig, NmsGrid};
+
+    #[test]
+    fn test_speed_scales_suppression() {
+        // an edge sweeping along the columns at 1000 pixels per second
+        let mut surface = SaeSurface::new(32, 32);
+        for col in 0..=16u16 {
+            for row in 0..32 {
+                surface.update(&SaeEvent { row, col, timestamp: 1_000 + 1_000 * col as SaeTime, ..SaeEvent::default() });
+            }
+        }
+        let corner = SaeEvent { row: 8, col: 16, timestamp: 17_000, confidence: Some(1.0), ..SaeEvent::default() };
+        let mut speeds = SpeedSurface::new(32, 32, SpeedConfig::default());
+        let measured = speeds.observe(&surface, &corner).unwrap();
+        assert!((measured - 1_000.0).abs() < 1.0);
+        assert!((speeds.speed_at(10, 17, 17_000).unwrap() - 1_000.0).abs() < 1.0);
+        assert_eq!(speeds.scale_for(&corner), 3.0);
+        // far away, and after the window, nothing is known
+        assert_eq!(speeds.speed_at(20, 16, 17_000), None);
+        assert_eq!(speeds.scale_at(8, 16, 200_000), 1.0);
+
+        // a slow measurement pulls the speed down, more at its own pixel
+        speeds.add_measurement(8, 16, 100.0, 18_000);
+        let center = speeds.speed_at(8, 16, 18_000).unwrap();
+        assert!((center - 550.0).abs() < 1.0);
+        assert!(speeds.speed_at(8, 18, 18_000).unwrap() > center);
+
+        let config = NmsConfig { max_scale: 3.0, ..NmsConfig::default() };
+        let mut grid = NmsGrid::new(32, 32, config);
+        assert!(grid.admit(&corner));
+        let neighbor = SaeEvent { col: 20, timestamp: 17_500, confidence: Some(0.5), ..corner.clone() };
+        // 4 pixels away: outside the base radius, inside the scaled one
+        assert!(!grid.admit_scaled(&neighbor, speeds.scale_for(&neighbor)));
+        assert!(grid.admit(&neighbor));
+    }
+}
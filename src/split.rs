@@ -0,0 +1,314 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Route one event stream to several downstream branches by predicate, eg to detect
+//! corners only in one region of interest while recording the raw events of another.
+//!
+//! Each branch of a `Splitter` has a `Route`: a polarity, a region, a rate class, or
+//! any `EventFilter`. The rate class of an event is given by the time since the
+//! previous event at its pixel, of either polarity, so that busy pixels (flicker,
+//! fast edges) can be handled apart from quiet ones. Events go to every branch whose
+//! route matches, or only the first, as the `SplitMode` says.
+
+#[cfg(feature = "io")]
+use std::io::{self, Write};
+use std::ops::Range;
+
+use crate::detector::CornerDetector;
+use crate::filter::EventFilter;
+#[cfg(feature = "io")]
+use crate::io::compact::{CompactWriter, RecordingHeader};
+use crate::sae_types::*;
+use crate::sink::CornerSink;
+#[cfg(feature = "io")]
+use crate::sink::LatchedWriter;
+
+
+/// A downstream branch of a `Splitter`, consuming the events routed to it
+pub trait EventBranch {
+    fn accept(&mut self, evt: &SaeEvent);
+}
+
+impl EventBranch for Vec<SaeEvent> {
+    fn accept(&mut self, evt: &SaeEvent) {
+        self.push(evt.clone());
+    }
+}
+
+impl<B: EventBranch + ?Sized> EventBranch for &mut B {
+    fn accept(&mut self, evt: &SaeEvent) {
+        (**self).accept(evt)
+    }
+}
+
+impl<B: EventBranch + ?Sized> EventBranch for Box<B> {
+    fn accept(&mut self, evt: &SaeEvent) {
+        (**self).accept(evt)
+    }
+}
+
+/// Detects corners among the events routed to it, delivering them to a sink
+pub struct DetectBranch<D, S> {
+    detector: D,
+    sink: S,
+}
+
+impl<D: CornerDetector, S: CornerSink> DetectBranch<D, S> {
+    pub fn new(detector: D, sink: S) -> Self {
+        DetectBranch { detector, sink }
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn into_parts(self) -> (D, S) {
+        (self.detector, self.sink)
+    }
+}
+
+impl<D: CornerDetector, S: CornerSink> EventBranch for DetectBranch<D, S> {
+    fn accept(&mut self, evt: &SaeEvent) {
+        if let Some(corner) = self.detector.process(evt) {
+            self.sink.accept(&corner);
+        }
+    }
+}
+
+/// Records the events routed to it as a compact recording, through a `LatchedWriter`
+#[cfg(feature = "io")]
+pub struct RecordBranch<W: Write> {
+    writer: LatchedWriter<CompactWriter<W>>,
+}
+
+#[cfg(feature = "io")]
+impl<W: Write> RecordBranch<W> {
+    /// Writes the recording header immediately
+    pub fn new(writer: W, header: &RecordingHeader) -> io::Result<Self> {
+        Ok(RecordBranch { writer: LatchedWriter::new(CompactWriter::new(writer, header)?) })
+    }
+
+    /// number of events recorded so far
+    pub fn recorded(&self) -> u64 {
+        self.writer.get_ref().count()
+    }
+
+    /// the first write error encountered, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.writer.error()
+    }
+
+    /// flush and return the underlying writer
+    pub fn finish(self) -> io::Result<W> {
+        self.writer.into_inner()?.into_inner()
+    }
+}
+
+#[cfg(feature = "io")]
+impl<W: Write> EventBranch for RecordBranch<W> {
+    fn accept(&mut self, evt: &SaeEvent) {
+        self.writer.write_with(|writer| writer.write_event(evt));
+    }
+}
+
+/// Classes of events by the time since the previous event at their pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateClass {
+    /// the previous event was less than `SplitConfig::fast_interval` before
+    Fast,
+    Medium,
+    /// the first event at the pixel, or the previous one was more than
+    /// `SplitConfig::slow_interval` before
+    Slow,
+}
+
+/// Which events a branch receives
+pub enum Route<'a> {
+    All,
+    Polarity(u8),
+    /// events within the rows and columns
+    Region { rows: Range<u16>, cols: Range<u16> },
+    Rate(RateClass),
+    /// events the filter accepts; the filter sees only the events not yet
+    /// taken by an earlier branch, in `SplitMode::FirstMatch`
+    Filter(Box<dyn EventFilter + 'a>),
+}
+
+/// Whether an event may go to more than one branch
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SplitMode {
+    /// every branch whose route matches, as a tee
+    #[default]
+    AllMatching,
+    /// only the first branch, in order of addition, whose route matches
+    FirstMatch,
+}
+
+/// Parameters of a splitter
+#[derive(Clone, Debug, PartialEq)]
+pub struct SplitConfig {
+    pub mode: SplitMode,
+    pub fast_interval: SaeTime,
+    pub slow_interval: SaeTime,
+}
+
+impl Default for SplitConfig {
+    fn default() -> Self {
+        SplitConfig {
+            mode: SplitMode::AllMatching,
+            fast_interval: 1_000,
+            slow_interval: 100_000,
+        }
+    }
+}
+
+struct Branch<'a> {
+    route: Route<'a>,
+    branch: Box<dyn EventBranch + 'a>,
+    routed: u64,
+}
+
+/// Routes events to branches by predicate
+pub struct Splitter<'a> {
+    config: SplitConfig,
+    /// latest event time at each pixel, for rate classes
+    latest: SaeMatrix,
+    seen: SaeOccupancy,
+    branches: Vec<Branch<'a>>,
+    unrouted: u64,
+}
+
+impl<'a> Splitter<'a> {
+    pub fn new(nrows: usize, ncols: usize, config: SplitConfig) -> Self {
+        Splitter {
+            config,
+            latest: SaeMatrix::zeros(nrows, ncols),
+            seen: SaeOccupancy::from_element(nrows, ncols, false),
+            branches: Vec::new(),
+            unrouted: 0,
+        }
+    }
+
+    /// Add a branch after those already added, returning its index
+    pub fn add_branch<B: EventBranch + 'a>(&mut self, route: Route<'a>, branch: B) -> usize {
+        self.branches.push(Branch { route, branch: Box::new(branch), routed: 0 });
+        self.branches.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.branches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.branches.is_empty()
+    }
+
+    /// the rate class of an event at its pixel, before it is processed
+    pub fn rate_class(&self, evt: &SaeEvent) -> RateClass {
+        let pos = (evt.row as usize, evt.col as usize);
+        if !self.seen.get(pos).copied().unwrap_or(false) {
+            return RateClass::Slow;
+        }
+        let interval = evt.timestamp.saturating_sub(self.latest[pos]);
+        if interval < self.config.fast_interval {
+            RateClass::Fast
+        } else if interval > self.config.slow_interval {
+            RateClass::Slow
+        } else {
+            RateClass::Medium
+        }
+    }
+
+    /// Pass the event to the branches whose routes match it, returning how many
+    pub fn process(&mut self, evt: &SaeEvent) -> usize {
+        let rate = self.rate_class(evt);
+        let mut delivered = 0;
+        for branch in self.branches.iter_mut() {
+            let matches = match &mut branch.route {
+                Route::All => true,
+                Route::Polarity(polarity) => evt.polarity == *polarity,
+                Route::Region { rows, cols } => rows.contains(&evt.row) && cols.contains(&evt.col),
+                Route::Rate(class) => rate == *class,
+                Route::Filter(filter) => filter.accept(evt),
+            };
+            if matches {
+                branch.branch.accept(evt);
+                branch.routed += 1;
+                delivered += 1;
+                if self.config.mode == SplitMode::FirstMatch {
+                    break;
+                }
+            }
+        }
+        let pos = (evt.row as usize, evt.col as usize);
+        if pos.0 < self.latest.nrows() && pos.1 < self.latest.ncols() {
+            self.latest[pos] = evt.timestamp;
+            self.seen[pos] = true;
+        }
+        if delivered == 0 {
+            self.unrouted += 1;
+        }
+        delivered
+    }
+
+    /// events passed to branch `idx`
+    pub fn routed(&self, idx: usize) -> u64 {
+        self.branches[idx].routed
+    }
+
+    /// events no branch received
+    pub fn unrouted(&self) -> u64 {
+        self.unrouted
+    }
+}
+
+
+#[cfg(all(test, feature = "io"))]
+mod tests {
+    use super::*;
+    use crate::io::compact::CompactReader;
+    use crate::detector::ReplayDetector;
+    use crate::surface::WarmupConfig;
+
+    #[test]
+    fn test_split_by_region_polarity_and_rate() {
+        let header = RecordingHeader::new(32, 64, WarmupConfig::disabled());
+        let mut corners = Vec::new();
+        let mut off_events = Vec::new();
+        let mut fast_events = Vec::new();
+        let recorded = {
+            let mut splitter = Splitter::new(32, 64, SplitConfig::default());
+            splitter.add_branch(Route::Region { rows: 0..32, cols: 0..32 },
+                                DetectBranch::new(ReplayDetector::new(&header), &mut corners));
+            let record = splitter.add_branch(Route::Region { rows: 0..32, cols: 32..64 },
+                                             RecordBranch::new(Vec::new(), &header).unwrap());
+            splitter.add_branch(Route::Polarity(0), &mut off_events);
+            splitter.add_branch(Route::Rate(RateClass::Fast), &mut fast_events);
+            // a corner in region A, and the same pattern of OFF events in region B
+            for &(left, polarity) in [(10u16, 1u8), (42, 0)].iter() {
+                for evt in corner_block_events(10, left, polarity, 7) {
+                    splitter.process(&evt);
+                }
+            }
+            assert_eq!(splitter.routed(record), 26);
+            assert_eq!(splitter.unrouted(), 0);
+            splitter.len()
+        };
+        assert_eq!(recorded, 4);
+        assert!(!corners.is_empty() && corners.iter().all(|corner| corner.col < 32));
+        assert_eq!(off_events.len(), 26);
+        // only the repeated events were fast
+        assert_eq!(fast_events.iter().map(|evt| evt.col).collect::<Vec<_>>(), vec![14, 46]);
+
+        let mut recording = RecordBranch::new(Vec::new(), &header).unwrap();
+        let mut first = Splitter::new(32, 64, SplitConfig { mode: SplitMode::FirstMatch, ..SplitConfig::default() });
+        first.add_branch(Route::Polarity(1), &mut recording);
+        first.add_branch(Route::All, Vec::new());
+        assert_eq!(first.process(&SaeEvent { polarity: 1, ..SaeEvent::default() }), 1);
+        assert_eq!(first.process(&SaeEvent::default()), 1);
+        assert_eq!((first.routed(0), first.routed(1)), (1, 1));
+        drop(first);
+        let bytes = recording.finish().unwrap();
+        assert_eq!(CompactReader::new(&bytes[..]).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Image stabilization from corner tracks.
+//!
+//! Time is cut into slices, and the dominant image motion over each slice is fitted
+//! to the tracks spanning it, as in `motion::segment_motion`. Chaining the slice
+//! motions gives the camera's path since a reference time, and its inverse warps the
+//! view at any later time back onto the reference view. `Stabilizer` outputs these
+//! per-slice warps, maps points and events through them, and warps or accumulates
+//! frames into the stabilized view: for visualization, or for modules that expect a
+//! steady camera.
+
+use nalgebra::{DMatrix, Matrix3};
+
+use crate::calib::homography::Homography;
+use crate::motion::{segment_motion, MotionSegmentationConfig};
+use crate::sae_types::*;
+use crate::track::TrackStore;
+
+
+/// Parameters of stabilization
+#[derive(Clone, Debug, PartialEq)]
+pub struct StabilizationConfig {
+    /// how the motion over each slice is estimated; its window is the length of the slices
+    pub motion: MotionSegmentationConfig,
+}
+
+impl Default for StabilizationConfig {
+    fn default() -> Self {
+        StabilizationConfig {
+            motion: MotionSegmentationConfig { window: 10_000, ..MotionSegmentationConfig::default() },
+        }
+    }
+}
+
+/// The transforms of one time slice
+#[derive(Clone, Debug, PartialEq)]
+pub struct StabilizationTransform {
+    pub start: SaeTime,
+    pub end: SaeTime,
+    /// image motion over the slice, mapping positions at its start to positions at its end
+    pub motion: Homography,
+    /// maps positions at the end of the slice into the stabilized (reference) view
+    pub warp: Homography,
+    /// whether enough tracks spanned the slice to estimate its motion; if not, it is taken as still
+    pub estimated: bool,
+}
+
+/// Derives per-slice stabilizing warps from the tracks of a `TrackStore`
+pub struct Stabilizer {
+    config: StabilizationConfig,
+    reference: SaeTime,
+    /// camera path: maps reference positions to positions at the end of the latest slice
+    path: Homography,
+    transforms: Vec<StabilizationTransform>,
+}
+
+impl Stabilizer {
+    /// Stabilize onto the view at `reference`, the start of the first slice
+    pub fn new(config: StabilizationConfig, reference: SaeTime) -> Self {
+        let identity = Homography { matrix: Matrix3::identity() };
+        Stabilizer { config, reference, path: identity, transforms: Vec::new() }
+    }
+
+    pub fn config(&self) -> &StabilizationConfig {
+        &self.config
+    }
+
+    /// the transforms of every slice processed so far, in time order
+    pub fn transforms(&self) -> &[StabilizationTransform] {
+        &self.transforms
+    }
+
+    /// Process every slice ending at or before `now`, returning their transforms.
+    /// The tracks in `store` must cover the slices: call at least once per slice,
+    /// before old observations are pruned.
+    pub fn update(&mut self, store: &TrackStore, now: SaeTime) -> &[StabilizationTransform] {
+        let slice = self.config.motion.window.max(1);
+        let first_new = self.transforms.len();
+        loop {
+            let start = self.transforms.last().map_or(self.reference, |last| last.end);
+            let end = start.saturating_add(slice);
+            if end > now || end == start {
+                break;
+            }
+            let estimate = segment_motion(store, end, &self.config.motion);
+            let estimated = estimate.is_some();
+            let motion = estimate.map_or(Homography { matrix: Matrix3::identity() }, |segmentation| segmentation.dominant);
+            let path = Homography { matrix: motion.matrix * self.path.matrix };
+            // a degenerate fit leaves the path as it was
+            let (path, warp) = match path.inverse() {
+                Some(warp) => (path, warp),
+                None => (self.path, self.path.inverse().unwrap_or(self.path)),
+            };
+            self.path = path;
+            self.transforms.push(StabilizationTransform { start, end, motion, warp, estimated });
+        }
+        &self.transforms[first_new..]
+    }
+
+    /// The warp into the stabilized view for positions at `timestamp`: that of the latest
+    /// slice ending at or before it, or the identity before the first slice ends
+    pub fn warp_at(&self, timestamp: SaeTime) -> Homography {
+        let idx = self.transforms.partition_point(|transform| transform.end <= timestamp);
+        match idx.checked_sub(1) {
+            Some(idx) => self.transforms[idx].warp,
+            None => Homography { matrix: Matrix3::identity() },
+        }
+    }
+
+    /// position (x = column, y = row) in the stabilized view of `point`, seen at `timestamp`
+    pub fn stabilize_point(&self, point: [f32; 2], timestamp: SaeTime) -> [f32; 2] {
+        let mapped = self.warp_at(timestamp).apply([point[0] as f64, point[1] as f64]);
+        [mapped[0] as f32, mapped[1] as f32]
+    }
+
+    /// Count `events` at their positions in the stabilized view, in a frame of
+    /// `nrows` by `ncols`: a motion-compensated event image
+    pub fn accumulate(&self, events: &[SaeEvent], nrows: usize, ncols: usize) -> DMatrix<f32> {
+        let mut frame = DMatrix::<f32>::zeros(nrows, ncols);
+        for evt in events.iter() {
+            let (row, col) = evt.subpixel_position();
+            let [x, y] = self.stabilize_point([col, row], evt.timestamp);
+            let (row, col) = (y.round(), x.round());
+            if row >= 0.0 && col >= 0.0 && (row as usize) < nrows && (col as usize) < ncols {
+                frame[(row as usize, col as usize)] += 1.0;
+            }
+        }
+        frame
+    }
+}
+
+/// Warp a frame by `warp` (mapping positions in `frame` to positions in the result),
+/// sampling bilinearly; pixels mapped from outside the frame are zero
+pub fn warp_frame(frame: &DMatrix<f32>, warp: &Homography) -> DMatrix<f32> {
+    let (nrows, ncols) = frame.shape();
+    let mut out = DMatrix::<f32>::zeros(nrows, ncols);
+    let inverse = match warp.inverse() {
+        Some(inverse) => inverse,
+        None => return out,
+    };
+    for col in 0..ncols {
+        for row in 0..nrows {
+            let [x, y] = inverse.apply([col as f64, row as f64]);
+            if !(x >= 0.0 && y >= 0.0 && x <= (ncols - 1) as f64 && y <= (nrows - 1) as f64) {
+                continue;
+            }
+            let (c0, r0) = (x.floor() as usize, y.floor() as usize);
+            let (c1, r1) = ((c0 + 1).min(ncols - 1), (r0 + 1).min(nrows - 1));
+            let (fx, fy) = ((x - c0 as f64) as f32, (y - r0 as f64) as f32);
+            out[(row, col)] = frame[(r0, c0)] * (1.0 - fx) * (1.0 - fy) + frame[(r0, c1)] * fx * (1.0 - fy) +
+                frame[(r1, c0)] * (1.0 - fx) * fy + frame[(r1, c1)] * fx * fy;
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stabilize_pan() {
+        // the camera pans: the scene drifts left by 1 pixel per millisecond
+        let mut store = TrackStore::new();
+        for k in 0..12 {
+            let (x, y) = (40.0 + (k % 4) as f32 * 15.0, 10.0 + (k / 4) as f32 * 12.0);
+            let id = store.start_track(corner_at(x, y, 0));
+            for step in 1..=30u32 {
+                store.extend_track(id, corner_at(x - step as f32, y, step * 1_000));
+            }
+        }
+        let mut stabilizer = Stabilizer::new(StabilizationConfig::default(), 0);
+        assert_eq!(stabilizer.update(&store, 25_000).len(), 2);
+        assert_eq!(stabilizer.update(&store, 30_000).len(), 1);
+        let last = stabilizer.transforms().last().unwrap();
+        assert!(last.estimated);
+        assert_eq!((last.start, last.end), (20_000, 30_000));
+        assert!((last.motion.apply([50.0, 20.0])[0] - 40.0).abs() < 1e-6);
+
+        // a feature seen at x = 15 after 30 ms was at x = 45 in the reference view
+        let point = stabilizer.stabilize_point([15.0, 20.0], 30_000);
+        assert!((point[0] - 45.0).abs() < 1e-4 && (point[1] - 20.0).abs() < 1e-4);
+        assert_eq!(stabilizer.stabilize_point([15.0, 20.0], 5_000), [15.0, 20.0]);
+
+        // a feature's events over time all land on its reference position
+        let events: Vec<SaeEvent> = (0..=30u32).map(|step| corner_at(60.0 - step as f32, 22.0, step * 1_000)).collect();
+        let frame = stabilizer.accumulate(&events, 40, 80);
+        assert_eq!(frame[(22, 60)], 4.0);
+
+        let mut image = DMatrix::<f32>::zeros(40, 80);
+        image[(20, 15)] = 1.0;
+        let warped = warp_frame(&image, &stabilizer.warp_at(30_000));
+        assert!((warped[(20, 45)] - 1.0).abs() < 1e-4);
+        assert!(warped[(20, 15)].abs() < 1e-6);
+    }
+}
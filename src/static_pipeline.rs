@@ -0,0 +1,131 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! A fixed pipeline for embedded targets: a filter chain, a detector and a sink
+//! composed entirely with static dispatch.
+//!
+//! Unlike `pipeline::Pipeline`, whose filter chain and observers are boxed, every
+//! stage of a `StaticPipeline` is a type parameter, so each event is processed by
+//! one monomorphized call that can be fully inlined. The builder grows the filter
+//! chain as nested pairs of filters. `process`, taking a `RawEvent` by value, is the
+//! single entry point, suitable for interrupt-driven ingestion. Nothing is allocated
+//! per event as long as the stages don't allocate: a `storage::FixedOnlineDetector`
+//! keeps its surfaces inline, and detectors without descriptors, eg a `StaticDetector`
+//! with `DESCRIPTOR` false, report corners without boxing a descriptor.
+//! The module builds without the `std` feature.
+
+use crate::detector::CornerDetector;
+use crate::filter::EventFilter;
+use crate::sae_types::*;
+use crate::sink::CornerSink;
+
+
+/// Filters, then a detector, then a sink, each of a fixed type
+pub struct StaticPipeline<F, D, S> {
+    filter: F,
+    detector: D,
+    sink: S,
+    events_processed: u64,
+    corners_emitted: u64,
+}
+
+impl<D: CornerDetector, S: CornerSink> StaticPipeline<(), D, S> {
+    /// A pipeline without filters
+    pub fn new(detector: D, sink: S) -> Self {
+        StaticPipeline { filter: (), detector, sink, events_processed: 0, corners_emitted: 0 }
+    }
+}
+
+impl<F: EventFilter, D: CornerDetector, S: CornerSink> StaticPipeline<F, D, S> {
+    /// Append a filter, run after those already in the pipeline
+    pub fn with_filter<G: EventFilter>(self, filter: G) -> StaticPipeline<(F, G), D, S> {
+        StaticPipeline {
+            filter: (self.filter, filter),
+            detector: self.detector,
+            sink: self.sink,
+            events_processed: self.events_processed,
+            corners_emitted: self.corners_emitted,
+        }
+    }
+
+    /// Process one event, as read from a sensor interface, returning whether it produced a corner
+    #[inline]
+    pub fn process(&mut self, raw: RawEvent) -> bool {
+        self.events_processed += 1;
+        let evt = SaeEvent::from(raw);
+        if !self.filter.accept(&evt) {
+            return false;
+        }
+        match self.detector.process(&evt) {
+            Some(corner) => {
+                self.sink.accept(&corner);
+                self.corners_emitted += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn detector(&self) -> &D {
+        &self.detector
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    pub fn events_processed(&self) -> u64 {
+        self.events_processed
+    }
+
+    pub fn corners_emitted(&self) -> u64 {
+        self.corners_emitted
+    }
+
+    /// the filters, detector and sink
+    pub fn into_parts(self) -> (F, D, S) {
+        (self.filter, self.detector, self.sink)
+    }
+}
+
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::detector::{OnlineDetector, StaticDetector, StaticRing};
+    use crate::surface::WarmupConfig;
+
+    struct MaxRow(u16);
+
+    impl EventFilter for MaxRow {
+        fn accept(&mut self, evt: &SaeEvent) -> bool {
+            evt.row < self.0
+        }
+    }
+
+    #[test]
+    fn test_static_pipeline() {
+        let detector: StaticDetector<StaticRing<3, 3, 6>, StaticRing<4, 4, 8>, false> = StaticDetector::new();
+        let online = OnlineDetector::new(detector, 32, 32, WarmupConfig::disabled());
+        let mut pipeline = StaticPipeline::new(online, Vec::new())
+            .with_filter(MaxRow(30))
+            .with_filter(MaxRow(20));
+        let events = corner_block_events(10, 10, 1, 7);
+        let (last, block) = events.split_last().unwrap();
+        for evt in block {
+            pipeline.process(RawEvent::from(evt));
+        }
+        assert!(pipeline.process(RawEvent::from(last)));
+        // filtered out before reaching the detector
+        assert!(!pipeline.process(RawEvent { row: 25, col: 14, polarity: 1, timestamp: 9 }));
+        assert_eq!(pipeline.detector().surface(1).matrix()[(25, 14)], 0);
+        assert_eq!(pipeline.events_processed(), 27);
+        assert_eq!(pipeline.corners_emitted() as usize, pipeline.sink().len());
+        let (_, _, corners) = pipeline.into_parts();
+        assert!(corners.iter().all(|corner| corner.norm_descriptor.is_none()));
+    }
+}
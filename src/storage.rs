@@ -0,0 +1,152 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! SAE storage backends, for running the detector without a heap-allocated matrix.
+//!
+//! The corner check itself needs only indexed timestamp access, `view::SaeView`;
+//! `SaeStorage` adds writing, to keep a surface up to date. `SaeMatrix` is one backend; `FixedSae` is another, a fixed-size array
+//! whose dimensions are known at compile time, suitable for a `static` on a
+//! microcontroller paired directly with a sensor. `static_pipeline::StaticPipeline`
+//! can run a `FixedOnlineDetector`, which keeps both polarity surfaces in `FixedSae`s
+//! and checks events with a `detector::StaticDetector`.
+//!
+//! The storage and the static detector's corner check use nothing from `std` beyond
+//! what `core` offers, apart from boxing the descriptor of a corner: without
+//! descriptors, nothing is allocated per event.
+
+use crate::detector::{CornerDetector, StaticDetector, StaticRingGeometry};
+use crate::sae_types::*;
+use crate::view::SaeView;
+
+
+/// Writable storage of the timestamps of an SAE
+pub trait SaeStorage: SaeView {
+    /// set the timestamp at a pixel within the shape
+    fn set(&mut self, row: usize, col: usize, timestamp: SaeTime);
+}
+
+#[cfg(feature = "std")]
+impl SaeStorage for SaeMatrix {
+    #[inline]
+    fn set(&mut self, row: usize, col: usize, timestamp: SaeTime) {
+        self[(row, col)] = timestamp;
+    }
+}
+
+/// An SAE of `ROWS` by `COLS` pixels stored inline, with timestamp 0 for never observed
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixedSae<const ROWS: usize, const COLS: usize> {
+    timestamps: [[SaeTime; COLS]; ROWS],
+}
+
+impl<const ROWS: usize, const COLS: usize> FixedSae<ROWS, COLS> {
+    /// usable in a `static` initializer
+    pub const fn new() -> Self {
+        FixedSae { timestamps: [[0; COLS]; ROWS] }
+    }
+
+    /// clear every timestamp
+    pub fn reset(&mut self) {
+        self.timestamps = [[0; COLS]; ROWS];
+    }
+}
+
+impl<const ROWS: usize, const COLS: usize> Default for FixedSae<ROWS, COLS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const ROWS: usize, const COLS: usize> SaeView for FixedSae<ROWS, COLS> {
+    #[inline]
+    fn shape(&self) -> (usize, usize) {
+        (ROWS, COLS)
+    }
+
+    #[inline]
+    fn timestamp(&self, row: usize, col: usize) -> SaeTime {
+        self.timestamps[row][col]
+    }
+}
+
+impl<const ROWS: usize, const COLS: usize> SaeStorage for FixedSae<ROWS, COLS> {
+    #[inline]
+    fn set(&mut self, row: usize, col: usize, timestamp: SaeTime) {
+        self.timestamps[row][col] = timestamp;
+    }
+}
+
+/// Runs a `StaticDetector` on a stream, with one `FixedSae` per polarity.
+/// There is no warm-up period: events are checked from the first one.
+pub struct FixedOnlineDetector<Inner, Outer, const DESCRIPTOR: bool, const ROWS: usize, const COLS: usize> {
+    detector: StaticDetector<Inner, Outer, DESCRIPTOR>,
+    /// OFF and ON surfaces
+    surfaces: [FixedSae<ROWS, COLS>; 2],
+}
+
+impl<Inner, Outer, const DESCRIPTOR: bool, const ROWS: usize, const COLS: usize> FixedOnlineDetector<Inner, Outer, DESCRIPTOR, ROWS, COLS>
+    where Inner: StaticRingGeometry, Outer: StaticRingGeometry
+{
+    pub fn new() -> Self {
+        FixedOnlineDetector { detector: StaticDetector::new(), surfaces: [FixedSae::new(), FixedSae::new()] }
+    }
+
+    /// the surface of events of `polarity`
+    pub fn surface(&self, polarity: u8) -> &FixedSae<ROWS, COLS> {
+        &self.surfaces[(polarity > 0) as usize]
+    }
+
+    /// Clear both surfaces
+    pub fn reset(&mut self) {
+        self.surfaces.iter_mut().for_each(FixedSae::reset);
+    }
+}
+
+impl<Inner, Outer, const DESCRIPTOR: bool, const ROWS: usize, const COLS: usize> Default for FixedOnlineDetector<Inner, Outer, DESCRIPTOR, ROWS, COLS>
+    where Inner: StaticRingGeometry, Outer: StaticRingGeometry
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Inner, Outer, const DESCRIPTOR: bool, const ROWS: usize, const COLS: usize> CornerDetector for FixedOnlineDetector<Inner, Outer, DESCRIPTOR, ROWS, COLS>
+    where Inner: StaticRingGeometry, Outer: StaticRingGeometry
+{
+    fn process(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        let (row, col) = (evt.row as usize, evt.col as usize);
+        if row >= ROWS || col >= COLS {
+            return None;
+        }
+        let surface = &mut self.surfaces[(evt.polarity > 0) as usize];
+        surface.set(row, col, evt.timestamp);
+        self.detector.detect(surface, evt)
+    }
+}
+
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::detector::{ring_descriptor, OnlineDetector, StandardStaticDetector, StaticRing};
+    use crate::surface::WarmupConfig;
+
+    type Fixed = FixedOnlineDetector<StaticRing<3, 3, 6>, StaticRing<4, 4, 8>, true, 32, 32>;
+
+    #[test]
+    fn test_fixed_storage_matches_matrix() {
+        let mut fixed = Fixed::new();
+        let mut online = OnlineDetector::new(StandardStaticDetector::new(), 32, 32, WarmupConfig::disabled());
+        let mut events = corner_block_events(10, 10, 1, 7);
+        events.push(SaeEvent { row: 40, col: 14, polarity: 1, timestamp: 9, ..SaeEvent::default() });
+        let corners: Vec<SaeEvent> = events.iter().filter_map(|evt| fixed.process(evt)).collect();
+        let expected: Vec<SaeEvent> = events.iter().filter_map(|evt| online.process(evt)).collect();
+        assert!(!corners.is_empty());
+        assert_eq!(corners, expected);
+        assert!(corners.iter().zip(expected.iter()).all(|(a, b)| a.norm_descriptor == b.norm_descriptor));
+        assert_eq!(fixed.surface(1).timestamp(14, 14), 9);
+        assert_eq!(fixed.surface(0).shape(), (32, 32));
+        // any detector function reading a `SaeView` reads a `FixedSae`
+        assert_eq!(ring_descriptor(fixed.surface(1), 12, 12), ring_descriptor(online.surface(1).matrix(), 12, 12));
+    }
+}
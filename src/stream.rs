@@ -0,0 +1,159 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Utilities for ordering and combining streams of change events.
+//! Downstream stages (SAE updates, detection, tracking) assume time-ordered input.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::sae_types::*;
+
+
+/// An event tagged with a sequence number (eg arrival order), giving events
+/// a total ordering: by timestamp first, then by sequence number.
+#[derive(Clone, Debug)]
+pub struct SequencedEvent {
+    pub seq: u64,
+    pub evt: SaeEvent,
+}
+
+impl SequencedEvent {
+    pub fn new(seq: u64, evt: SaeEvent) -> Self {
+        SequencedEvent { seq, evt }
+    }
+
+    fn sort_key(&self) -> (SaeTime, u64) {
+        (self.evt.timestamp, self.seq)
+    }
+}
+
+impl PartialEq for SequencedEvent {
+    fn eq(&self, other: &Self) -> bool {
+        self.sort_key() == other.sort_key()
+    }
+}
+
+impl Eq for SequencedEvent {}
+
+impl PartialOrd for SequencedEvent {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SequencedEvent {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+/// Sort events by timestamp. The sort is stable: events with equal timestamps keep their relative order.
+pub fn sort_events(events: &mut [SaeEvent]) {
+    events.sort_by_key(|evt| evt.timestamp);
+}
+
+/// Whether the events are in non-decreasing timestamp order
+pub fn is_time_ordered(events: &[SaeEvent]) -> bool {
+    events.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp)
+}
+
+/// Merges several individually time-ordered event sources into a single time-ordered stream.
+/// Events with equal timestamps are emitted in source order (lower source index first),
+/// so the merged output is deterministic.
+pub struct MergeSorted<I: Iterator<Item = SaeEvent>> {
+    sources: Vec<I>,
+    pending: Vec<Option<SaeEvent>>,
+    heap: BinaryHeap<Reverse<(SaeTime, usize)>>,
+}
+
+impl<I: Iterator<Item = SaeEvent>> MergeSorted<I> {
+    pub fn new(sources: Vec<I>) -> Self {
+        let mut merge = MergeSorted {
+            pending: vec![None; sources.len()],
+            heap: BinaryHeap::with_capacity(sources.len()),
+            sources,
+        };
+        for idx in 0..merge.sources.len() {
+            merge.refill(idx);
+        }
+        merge
+    }
+
+    /// pull the next event from the given source into the heap
+    fn refill(&mut self, idx: usize) {
+        if let Some(evt) = self.sources[idx].next() {
+            self.heap.push(Reverse((evt.timestamp, idx)));
+            self.pending[idx] = Some(evt);
+        }
+    }
+}
+
+impl<I: Iterator<Item = SaeEvent>> Iterator for MergeSorted<I> {
+    type Item = SaeEvent;
+
+    fn next(&mut self) -> Option<SaeEvent> {
+        let Reverse((_, idx)) = self.heap.pop()?;
+        let evt = self.pending[idx].take();
+        self.refill(idx);
+        evt
+    }
+}
+
+/// Merge multiple time-ordered event sources into one time-ordered stream
+pub fn merge_sorted<S>(sources: Vec<S>) -> MergeSorted<S::IntoIter>
+    where S: IntoIterator<Item = SaeEvent>
+{
+    MergeSorted::new(sources.into_iter().map(|src| src.into_iter()).collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_at(timestamp: SaeTime, col: u16) -> SaeEvent {
+        SaeEvent {
+            col,
+            timestamp,
+            ..SaeEvent::default()
+        }
+    }
+
+    #[test]
+    fn test_sequenced_ordering() {
+        let a = SequencedEvent::new(1, event_at(10, 0));
+        let b = SequencedEvent::new(0, event_at(10, 1));
+        let c = SequencedEvent::new(2, event_at(5, 2));
+
+        let mut all = [a, b, c];
+        all.sort();
+        let cols: Vec<u16> = all.iter().map(|s| s.evt.col).collect();
+        assert_eq!(cols, vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn test_sort_events_stable() {
+        let mut events = vec![event_at(3, 0), event_at(1, 1), event_at(3, 2), event_at(2, 3)];
+        assert!(!is_time_ordered(&events));
+        sort_events(&mut events);
+        assert!(is_time_ordered(&events));
+        let cols: Vec<u16> = events.iter().map(|e| e.col).collect();
+        assert_eq!(cols, vec![1, 3, 0, 2]);
+    }
+
+    #[test]
+    fn test_merge_sorted() {
+        let cam_a = vec![event_at(1, 0), event_at(4, 0), event_at(9, 0)];
+        let cam_b = vec![event_at(2, 1), event_at(4, 1), event_at(5, 1)];
+        let empty: Vec<SaeEvent> = vec![];
+
+        let merged: Vec<SaeEvent> = merge_sorted(vec![cam_a, empty, cam_b]).collect();
+        assert_eq!(merged.len(), 6);
+        assert!(is_time_ordered(&merged));
+
+        let order: Vec<(SaeTime, u16)> = merged.iter().map(|e| (e.timestamp, e.col)).collect();
+        // equal timestamps are emitted in source order
+        assert_eq!(order, vec![(1, 0), (2, 1), (4, 0), (4, 1), (5, 1), (9, 0)]);
+    }
+}
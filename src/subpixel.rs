@@ -0,0 +1,124 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Sub-pixel refinement of corner positions.
+//! An exponentially decaying time surface is computed over the SAE patch around
+//! the corner, and a 2D quadratic fitted to it by least squares; the stationary
+//! point of the quadratic, if it is a maximum close to the corner pixel,
+//! gives the refined position.
+
+use nalgebra::{Matrix2, Matrix6, Vector2, Vector6};
+
+use crate::sae_types::*;
+
+
+/// Parameters of the sub-pixel refinement
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubpixelConfig {
+    /// half-size of the square patch fitted
+    pub radius: usize,
+    /// decay constant of the time surface
+    pub tau: f32,
+    /// refinements moving the corner further than this (pixels, per axis) are rejected
+    pub max_offset: f32,
+}
+
+impl Default for SubpixelConfig {
+    fn default() -> Self {
+        SubpixelConfig {
+            radius: 2,
+            tau: 10_000.0,
+            max_offset: 1.0,
+        }
+    }
+}
+
+/// Refine the corner position in place, setting `row_f` and `col_f`.
+/// Returns false, leaving the corner unchanged, where the patch doesn't fit
+/// in the SAE or its time surface has no well-defined maximum.
+pub fn refine_corner(sae_pol: &SaeMatrix, corner: &mut SaeEvent, config: &SubpixelConfig) -> bool {
+    let (nrows, ncols) = sae_pol.shape();
+    let row = corner.row as usize;
+    let col = corner.col as usize;
+    let radius = config.radius.max(1);
+    if row < radius || col < radius || row + radius >= nrows || col + radius >= ncols {
+        return false;
+    }
+
+    // least squares fit of v = a*x^2 + b*y^2 + c*x*y + d*x + e*y + f, with x along cols
+    let mut ata = Matrix6::<f32>::zeros();
+    let mut atv = Vector6::<f32>::zeros();
+    let r = radius as i32;
+    for dy in -r..=r {
+        for dx in -r..=r {
+            let ts = sae_pol[((row as i32 + dy) as usize, (col as i32 + dx) as usize)];
+            let value = if ts > corner.timestamp {
+                1.0
+            } else {
+                (-((corner.timestamp - ts) as f32) / config.tau).exp()
+            };
+            let (x, y) = (dx as f32, dy as f32);
+            let terms = Vector6::new(x * x, y * y, x * y, x, y, 1.0);
+            ata += terms * terms.transpose();
+            atv += terms * value;
+        }
+    }
+    let coeffs = match ata.try_inverse() {
+        Some(inv) => inv * atv,
+        None => return false,
+    };
+
+    // stationary point: gradient [2a*x + c*y + d, c*x + 2b*y + e] = 0
+    let hessian = Matrix2::new(2.0 * coeffs[0], coeffs[2], coeffs[2], 2.0 * coeffs[1]);
+    if hessian[(0, 0)] >= 0.0 || hessian.determinant() <= 0.0 {
+        // not a maximum
+        return false;
+    }
+    let offset = match hessian.try_inverse() {
+        Some(inv) => -(inv * Vector2::new(coeffs[3], coeffs[4])),
+        None => return false,
+    };
+    if offset.x.abs() > config.max_offset || offset.y.abs() > config.max_offset {
+        return false;
+    }
+
+    corner.col_f = Some(col as f32 + offset.x);
+    corner.row_f = Some(row as f32 + offset.y);
+    true
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a time surface peaked at the (sub-pixel) point (prow, pcol)
+    fn peaked_sae(prow: f32, pcol: f32) -> SaeMatrix {
+        SaeMatrix::from_fn(11, 11, |row, col| {
+            let d2 = (row as f32 - prow).powi(2) + (col as f32 - pcol).powi(2);
+            (100_000.0 - 2_000.0 * d2) as SaeTime
+        })
+    }
+
+    #[test]
+    fn test_refine_corner() {
+        let sae = peaked_sae(5.3, 4.8);
+        let mut corner = SaeEvent { row: 5, col: 5, timestamp: 100_000, ..SaeEvent::default() };
+        assert!(refine_corner(&sae, &mut corner, &SubpixelConfig::default()));
+        let (row_f, col_f) = corner.subpixel_position();
+        assert!((row_f - 5.3).abs() < 0.1, "row_f {}", row_f);
+        assert!((col_f - 4.8).abs() < 0.1, "col_f {}", col_f);
+    }
+
+    #[test]
+    fn test_refine_rejects_flat_and_border() {
+        let flat = SaeMatrix::from_element(11, 11, 500);
+        let mut corner = SaeEvent { row: 5, col: 5, timestamp: 500, ..SaeEvent::default() };
+        assert!(!refine_corner(&flat, &mut corner, &SubpixelConfig::default()));
+        assert_eq!(corner.subpixel_position(), (5.0, 5.0));
+
+        let mut near_border = SaeEvent { row: 1, col: 5, timestamp: 500, ..SaeEvent::default() };
+        assert!(!refine_corner(&peaked_sae(1.0, 5.0), &mut near_border, &SubpixelConfig::default()));
+        assert!(near_border.row_f.is_none());
+    }
+}
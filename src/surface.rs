@@ -0,0 +1,688 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! An owned Surface of Active Events (SAE) for a single polarity.
+//! The surface tracks how much of itself has been populated since the last reset,
+//! so that detection can be suppressed during the warm-up period when most circle
+//! samples around an event are still untouched.
+//! Each pixel carries an occupancy flag, so that "never observed" is distinct
+//! from an event at timestamp 0.
+
+use crate::detector::{detect_and_compute_configured_counted, detect_and_compute_one_observed_counted, detect_quick_configured,
+                      detect_quick_observed, DetectionPolicy, DetectorConfig, DetectorWork};
+use crate::profile::WorkProfile;
+use crate::sae_types::*;
+use crate::subpixel::{refine_corner, SubpixelConfig};
+use crate::time::rebase_sae;
+
+
+/// Controls when a freshly reset surface is considered warmed up.
+/// The surface is warmed up once *either* condition is met.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WarmupConfig {
+    /// fraction (0..1) of surface pixels that must have been populated
+    pub min_populated_fraction: f32,
+    /// time elapsed since the first event after reset
+    pub min_elapsed: SaeTime,
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        WarmupConfig {
+            min_populated_fraction: 0.05,
+            min_elapsed: 100_000,
+        }
+    }
+}
+
+impl WarmupConfig {
+    /// a config that treats the surface as warmed up immediately
+    pub fn disabled() -> Self {
+        WarmupConfig {
+            min_populated_fraction: 0.0,
+            min_elapsed: 0,
+        }
+    }
+}
+
+/// Counts of observed pixels by the age of their latest event
+#[derive(Clone, Debug, PartialEq)]
+pub struct AgeHistogram {
+    pub bin_width: SaeTime,
+    /// `counts[i]` pixels have an age in `[i * bin_width, (i + 1) * bin_width)`
+    pub counts: Vec<u64>,
+    /// pixels older than the last bin
+    pub older: u64,
+    /// pixels never observed since the last reset
+    pub unobserved: u64,
+}
+
+impl AgeHistogram {
+    /// the age below which `quantile` (0..1) of observed pixels fall, to bin resolution;
+    /// None if there are no observed pixels or the quantile lies beyond the last bin
+    pub fn quantile(&self, quantile: f32) -> Option<SaeTime> {
+        let observed = self.counts.iter().sum::<u64>() + self.older;
+        if observed == 0 {
+            return None;
+        }
+        let target = (quantile.clamp(0.0, 1.0) as f64 * observed as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Some((i as SaeTime + 1) * self.bin_width);
+            }
+        }
+        None
+    }
+}
+
+/// Which events may overwrite the timestamp stored at their pixel.
+/// Out-of-order input can move a pixel backwards in time, leaving it older than the
+/// pixels around it and breaking the arc logic; the guards ignore such events
+/// instead, which is cheaper than a reordering buffer for mildly disordered input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum UpdatePolicy {
+    /// every event overwrites its pixel
+    #[default]
+    Always,
+    /// ignore events older than the pixel's timestamp
+    IgnoreOlder,
+    /// ignore events older than the pixel's timestamp, or at the same time (duplicates)
+    IgnoreOlderOrEqual,
+}
+
+/// Counts of events ignored by the `UpdatePolicy`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UpdateStats {
+    pub ignored_older: u64,
+    pub ignored_duplicate: u64,
+}
+
+impl UpdateStats {
+    pub fn ignored(&self) -> u64 {
+        self.ignored_older + self.ignored_duplicate
+    }
+}
+
+/// A Surface of Active Events that owns its timestamp matrix
+pub struct SaeSurface {
+    sae: SaeMatrix,
+    occupancy: SaeOccupancy,
+    warmup: WarmupConfig,
+    /// custom circle geometry; None uses the standard detector
+    detector: Option<DetectorConfig>,
+    /// if set, detected corners are refined to sub-pixel positions
+    subpixel: Option<SubpixelConfig>,
+    /// detector work per event, if profiling
+    work_profile: Option<WorkProfile>,
+    update_policy: UpdatePolicy,
+    update_stats: UpdateStats,
+    /// number of pixels populated since the last reset
+    populated: usize,
+    /// timestamp of the first event since the last reset
+    first_timestamp: Option<SaeTime>,
+    last_timestamp: SaeTime,
+}
+
+impl SaeSurface {
+    pub fn new(nrows: usize, ncols: usize) -> Self {
+        Self::with_warmup(nrows, ncols, WarmupConfig::default())
+    }
+
+    pub fn with_warmup(nrows: usize, ncols: usize, warmup: WarmupConfig) -> Self {
+        SaeSurface {
+            sae: SaeMatrix::zeros(nrows, ncols),
+            occupancy: SaeOccupancy::from_element(nrows, ncols, false),
+            warmup,
+            detector: None,
+            subpixel: None,
+            work_profile: None,
+            update_policy: UpdatePolicy::Always,
+            update_stats: UpdateStats::default(),
+            populated: 0,
+            first_timestamp: None,
+            last_timestamp: 0,
+        }
+    }
+
+    /// A surface holding, at each pixel, the latest timestamp among `events`, which
+    /// need not be in time order: eg to start detection mid-recording, or to
+    /// reconstruct the state at a seek point from the preceding events.
+    /// Events outside the surface are ignored; pass the events of one polarity
+    /// to build that polarity's surface.
+    pub fn from_events(events: &[SaeEvent], nrows: usize, ncols: usize, warmup: WarmupConfig) -> Self {
+        let mut surface = Self::with_warmup(nrows, ncols, warmup);
+        let mut span: Option<(SaeTime, SaeTime)> = None;
+        {
+            // column-major, as nalgebra stores matrices
+            let sae = surface.sae.as_mut_slice();
+            let occupancy = surface.occupancy.as_mut_slice();
+            for evt in events.iter() {
+                let (row, col) = (evt.row as usize, evt.col as usize);
+                if row >= nrows || col >= ncols {
+                    continue;
+                }
+                let idx = col * nrows + row;
+                if !occupancy[idx] {
+                    occupancy[idx] = true;
+                    surface.populated += 1;
+                    sae[idx] = evt.timestamp;
+                } else {
+                    sae[idx] = sae[idx].max(evt.timestamp);
+                }
+                span = Some(span.map_or((evt.timestamp, evt.timestamp), |(first, last)| {
+                    (first.min(evt.timestamp), last.max(evt.timestamp))
+                }));
+            }
+        }
+        if let Some((first, last)) = span {
+            surface.first_timestamp = Some(first);
+            surface.last_timestamp = last;
+        }
+        surface
+    }
+
+    /// A surface holding, at each pixel, the later timestamp of `a` and `b`, which must
+    /// have the same shape: eg the two polarity surfaces combined into one.
+    /// The new surface is configured like `a`, with fresh statistics.
+    pub fn combined(a: &SaeSurface, b: &SaeSurface) -> Self {
+        assert_eq!(a.shape(), b.shape(), "combined surfaces must have the same shape");
+        let (nrows, ncols) = a.shape();
+        let mut surface = Self::with_warmup(nrows, ncols, a.warmup.clone());
+        surface.detector = a.detector.clone();
+        surface.subpixel = a.subpixel.clone();
+        surface.work_profile = a.work_profile.as_ref().map(|_| WorkProfile::new());
+        surface.update_policy = a.update_policy;
+        surface.sae.zip_zip_apply(&a.sae, &b.sae, |_, a, b| a.max(b));
+        surface.occupancy.zip_zip_apply(&a.occupancy, &b.occupancy, |_, a, b| a || b);
+        surface.populated = surface.occupancy.iter().filter(|&&observed| observed).count();
+        surface.first_timestamp = match (a.first_timestamp, b.first_timestamp) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (first, None) | (None, first) => first,
+        };
+        surface.last_timestamp = a.last_timestamp.max(b.last_timestamp);
+        surface
+    }
+
+    /// clear all timestamps and restart the warm-up period
+    pub fn reset(&mut self) {
+        self.sae.fill(0);
+        self.occupancy.fill(false);
+        self.populated = 0;
+        self.first_timestamp = None;
+        self.last_timestamp = 0;
+    }
+
+    /// Move the time origin forward by `offset`: every timestamp is shifted back by it,
+    /// saturating at zero, so a long stream can continue within the `SaeTime` range.
+    /// Pixels stay observed, and the warm-up state is kept.
+    pub fn rebase(&mut self, offset: SaeTime) {
+        rebase_sae(&mut self.sae, offset);
+        self.first_timestamp = self.first_timestamp.map(|first| first.before(offset));
+        self.last_timestamp = self.last_timestamp.before(offset);
+    }
+
+    /// Change the surface dimensions, clearing it as `reset` does; the configuration is kept
+    pub fn resize(&mut self, nrows: usize, ncols: usize) {
+        self.sae = SaeMatrix::zeros(nrows, ncols);
+        self.occupancy = SaeOccupancy::from_element(nrows, ncols, false);
+        self.reset();
+    }
+
+    /// the underlying timestamp matrix
+    pub fn matrix(&self) -> &SaeMatrix {
+        &self.sae
+    }
+
+    /// flags marking which pixels have been observed since the last reset
+    pub fn occupancy(&self) -> &SaeOccupancy {
+        &self.occupancy
+    }
+
+    /// whether the pixel has received any event since the last reset
+    pub fn is_observed(&self, row: usize, col: usize) -> bool {
+        self.occupancy[(row, col)]
+    }
+
+    /// (nrows, ncols)
+    pub fn shape(&self) -> (usize, usize) {
+        self.sae.shape()
+    }
+
+    pub fn warmup_config(&self) -> &WarmupConfig {
+        &self.warmup
+    }
+
+    pub fn set_warmup_config(&mut self, warmup: WarmupConfig) {
+        self.warmup = warmup;
+    }
+
+    pub fn detector_config(&self) -> Option<&DetectorConfig> {
+        self.detector.as_ref()
+    }
+
+    /// Detect corners using custom circle geometry rather than the standard detector
+    pub fn set_detector_config(&mut self, config: DetectorConfig) {
+        self.detector = Some(config);
+    }
+
+    /// Refine detected corners to sub-pixel positions (`row_f`, `col_f`), or stop refining with None
+    pub fn set_subpixel_config(&mut self, config: Option<SubpixelConfig>) {
+        self.subpixel = config;
+    }
+
+    /// Count the detector work of every event passed to `update_and_detect`, or stop counting.
+    /// Enabling restarts the profile.
+    pub fn set_work_profiling(&mut self, enabled: bool) {
+        self.work_profile = if enabled { Some(WorkProfile::new()) } else { None };
+    }
+
+    /// the detector work counted so far, if profiling
+    pub fn work_profile(&self) -> Option<&WorkProfile> {
+        self.work_profile.as_ref()
+    }
+
+    /// Guard pixels against out-of-order or duplicate events
+    pub fn set_update_policy(&mut self, policy: UpdatePolicy) {
+        self.update_policy = policy;
+    }
+
+    pub fn update_policy(&self) -> UpdatePolicy {
+        self.update_policy
+    }
+
+    /// counts of events ignored by the update policy
+    pub fn update_stats(&self) -> &UpdateStats {
+        &self.update_stats
+    }
+
+    /// Record the event timestamp at its pixel.
+    /// Returns false if the event lies outside the surface, or is ignored by the update policy.
+    pub fn update(&mut self, evt: &SaeEvent) -> bool {
+        let row = evt.row as usize;
+        let col = evt.col as usize;
+        let (nrows, ncols) = self.sae.shape();
+        if row >= nrows || col >= ncols {
+            return false;
+        }
+
+        if self.occupancy[(row, col)] && self.update_policy != UpdatePolicy::Always {
+            let stored = self.sae[(row, col)];
+            if evt.timestamp < stored {
+                self.update_stats.ignored_older += 1;
+                return false;
+            }
+            if evt.timestamp == stored && self.update_policy == UpdatePolicy::IgnoreOlderOrEqual {
+                self.update_stats.ignored_duplicate += 1;
+                return false;
+            }
+        }
+
+        let observed = &mut self.occupancy[(row, col)];
+        if !*observed {
+            *observed = true;
+            self.populated += 1;
+        }
+        self.sae[(row, col)] = evt.timestamp;
+
+        if self.first_timestamp.is_none() {
+            self.first_timestamp = Some(evt.timestamp);
+        }
+        self.last_timestamp = self.last_timestamp.max(evt.timestamp);
+        true
+    }
+
+    /// number of pixels that have been populated since the last reset
+    pub fn populated_count(&self) -> usize {
+        self.populated
+    }
+
+    /// fraction (0..1) of pixels populated since the last reset
+    pub fn populated_fraction(&self) -> f32 {
+        let total = self.sae.len();
+        if total == 0 {
+            return 0.0;
+        }
+        (self.populated as f32) / (total as f32)
+    }
+
+    /// time elapsed between the first and the most recent event since the last reset
+    pub fn elapsed(&self) -> SaeTime {
+        match self.first_timestamp {
+            Some(first) => self.last_timestamp.elapsed_since(first),
+            None => 0,
+        }
+    }
+
+    /// whether the surface has been populated enough for detections to be trustworthy
+    pub fn is_warmed_up(&self) -> bool {
+        if self.populated_fraction() >= self.warmup.min_populated_fraction {
+            return true;
+        }
+        self.first_timestamp.is_some() && self.elapsed() >= self.warmup.min_elapsed
+    }
+
+    /// timestamp of the most recent event since the last reset
+    pub fn latest_timestamp(&self) -> Option<SaeTime> {
+        self.first_timestamp.map(|_| self.last_timestamp)
+    }
+
+    /// whether the pixel has received an event within `window` before `now`
+    fn is_active(&self, row: usize, col: usize, now: SaeTime, window: SaeTime) -> bool {
+        self.occupancy[(row, col)] && now.elapsed_since(self.sae[(row, col)]) < window
+    }
+
+    /// Histogram of the age at `now` of every observed pixel's latest event, in `bins` bins
+    /// of `bin_width`. Pixels with timestamps after `now` count as age zero.
+    pub fn age_histogram(&self, now: SaeTime, bin_width: SaeTime, bins: usize) -> AgeHistogram {
+        let bin_width = bin_width.max(1);
+        let mut histogram = AgeHistogram { bin_width, counts: vec![0; bins], older: 0, unobserved: 0 };
+        for (t, &observed) in self.sae.iter().zip(self.occupancy.iter()) {
+            if !observed {
+                histogram.unobserved += 1;
+                continue;
+            }
+            match histogram.counts.get_mut((now.elapsed_since(*t) / bin_width) as usize) {
+                Some(count) => *count += 1,
+                None => histogram.older += 1,
+            }
+        }
+        histogram
+    }
+
+    /// fraction (0..1) of all pixels with an event within `window` before `now`
+    pub fn active_fraction(&self, now: SaeTime, window: SaeTime) -> f32 {
+        let total = self.sae.len();
+        if total == 0 {
+            return 0.0;
+        }
+        let active = self.sae.iter().zip(self.occupancy.iter())
+            .filter(|&(t, &observed)| observed && now.elapsed_since(*t) < window)
+            .count();
+        (active as f32) / (total as f32)
+    }
+
+    /// number of pixels in each row with an event within `window` before `now`,
+    /// eg to spot dead or flickering rows
+    pub fn row_activity(&self, now: SaeTime, window: SaeTime) -> Vec<u32> {
+        let (nrows, ncols) = self.sae.shape();
+        (0..nrows)
+            .map(|row| (0..ncols).filter(|&col| self.is_active(row, col, now, window)).count() as u32)
+            .collect()
+    }
+
+    /// number of pixels in each column with an event within `window` before `now`
+    pub fn col_activity(&self, now: SaeTime, window: SaeTime) -> Vec<u32> {
+        let (nrows, ncols) = self.sae.shape();
+        (0..ncols)
+            .map(|col| (0..nrows).filter(|&row| self.is_active(row, col, now, window)).count() as u32)
+            .collect()
+    }
+
+    /// Update the surface with the event and check whether it is a corner.
+    /// Detections are suppressed until the surface is warmed up,
+    /// and for events surrounded mostly by unobserved pixels.
+    pub fn update_and_detect(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        let recorded = self.update(evt);
+        self.detect_recorded(evt, recorded)
+    }
+
+    /// Check whether an event already recorded with `update` is a corner, on the surface
+    /// as it now stands, eg after later events of a batch, as `update_and_detect` does
+    pub fn detect(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        self.detect_recorded(evt, true)
+    }
+
+    fn detect_recorded(&mut self, evt: &SaeEvent, recorded: bool) -> Option<SaeEvent> {
+        let mut work = DetectorWork::default();
+        let corner = if recorded && self.is_warmed_up() {
+            match self.detector.as_ref() {
+                Some(config) => detect_and_compute_configured_counted(config, &self.sae, Some(&self.occupancy), evt, &mut work),
+                None => detect_and_compute_one_observed_counted(&self.sae, &self.occupancy, evt, &mut work),
+            }
+        } else {
+            None
+        };
+        if let Some(profile) = self.work_profile.as_mut() {
+            profile.record(&work, corner.is_some());
+        }
+        let mut corner = corner?;
+        if let Some(config) = self.subpixel.as_ref() {
+            refine_corner(&self.sae, &mut corner, config);
+        }
+        Some(corner)
+    }
+
+    /// Like `update_and_detect`, in quick mode: see `detector::detect_quick_observed`.
+    /// Quick corners are not refined to sub-pixel positions, nor counted by work profiling.
+    pub fn update_and_detect_quick(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+        if !self.update(evt) {
+            return None;
+        }
+        self.detect_quick(evt)
+    }
+
+    /// Like `detect`, in quick mode
+    pub fn detect_quick(&self, evt: &SaeEvent) -> Option<SaeEvent> {
+        if !self.is_warmed_up() {
+            return None;
+        }
+        match self.detector.as_ref() {
+            Some(config) => detect_quick_configured(config, &self.sae, Some(&self.occupancy), evt),
+            None => detect_quick_observed(&self.sae, &self.occupancy, evt),
+        }
+    }
+}
+
+
+/// Sensor geometry and detector configuration captured alongside a recording,
+/// sufficient to reproduce the detector output on replay.
+/// Written and read by `io::compact`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordingHeader {
+    pub nrows: u16,
+    pub ncols: u16,
+    pub warmup: WarmupConfig,
+    /// custom circle geometry and settings; None for the standard detector
+    pub detector: Option<DetectorConfig>,
+    pub detection_policy: DetectionPolicy,
+    pub update_policy: UpdatePolicy,
+    pub subpixel: Option<SubpixelConfig>,
+}
+
+impl RecordingHeader {
+    /// A header for the standard detector with default settings
+    pub fn new(nrows: u16, ncols: u16, warmup: WarmupConfig) -> Self {
+        RecordingHeader {
+            nrows,
+            ncols,
+            warmup,
+            detector: None,
+            detection_policy: DetectionPolicy::default(),
+            update_policy: UpdatePolicy::default(),
+            subpixel: None,
+        }
+    }
+
+    fn configure(&self, mut surface: SaeSurface) -> SaeSurface {
+        if let Some(config) = self.detector.as_ref() {
+            surface.set_detector_config(config.clone());
+        }
+        surface.set_update_policy(self.update_policy);
+        surface.set_subpixel_config(self.subpixel.clone());
+        surface
+    }
+
+    /// a fresh surface matching the recorded geometry and configuration
+    pub fn surface(&self) -> SaeSurface {
+        self.configure(SaeSurface::with_warmup(self.nrows as usize, self.ncols as usize, self.warmup.clone()))
+    }
+
+    /// a surface matching the recording, holding the latest timestamps of `events`
+    pub fn surface_from_events(&self, events: &[SaeEvent]) -> SaeSurface {
+        self.configure(SaeSurface::from_events(events, self.nrows as usize, self.ncols as usize, self.warmup.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_at(row: u16, col: u16, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent {
+            row,
+            col,
+            timestamp,
+            ..SaeEvent::default()
+        }
+    }
+
+    #[test]
+    fn test_populated_tracking() {
+        let mut surface = SaeSurface::new(10, 10);
+        assert_eq!(surface.populated_count(), 0);
+
+        assert!(surface.update(&event_at(1, 1, 10)));
+        assert!(surface.update(&event_at(1, 1, 20)));
+        assert!(surface.update(&event_at(2, 3, 30)));
+        assert!(!surface.update(&event_at(10, 3, 40)));
+        assert_eq!(surface.populated_count(), 2);
+        assert_eq!(surface.elapsed(), 20);
+        assert!((surface.populated_fraction() - 0.02).abs() < 1e-6);
+
+        surface.reset();
+        assert_eq!(surface.populated_count(), 0);
+        assert_eq!(surface.elapsed(), 0);
+        assert_eq!(surface.matrix()[(1, 1)], 0);
+        assert!(!surface.is_observed(1, 1));
+
+        // an event at timestamp zero still counts as observed
+        surface.update(&event_at(5, 5, 0));
+        assert!(surface.is_observed(5, 5));
+        assert_eq!(surface.populated_count(), 1);
+    }
+
+    #[test]
+    fn test_update_policy() {
+        let mut surface = SaeSurface::with_warmup(10, 10, WarmupConfig::disabled());
+        assert!(surface.update(&event_at(1, 1, 20)));
+        assert!(surface.update(&event_at(1, 1, 10)));
+        assert_eq!(surface.matrix()[(1, 1)], 10);
+
+        surface.set_update_policy(UpdatePolicy::IgnoreOlder);
+        assert!(surface.update(&event_at(1, 1, 20)));
+        assert!(!surface.update(&event_at(1, 1, 15)));
+        assert!(surface.update(&event_at(1, 1, 20)));
+        assert_eq!(surface.matrix()[(1, 1)], 20);
+        // a first event at a pixel is always taken
+        assert!(surface.update(&event_at(2, 2, 5)));
+
+        surface.set_update_policy(UpdatePolicy::IgnoreOlderOrEqual);
+        assert!(!surface.update(&event_at(1, 1, 20)));
+        assert!(surface.update_and_detect(&event_at(1, 1, 19)).is_none());
+        assert_eq!(surface.update_stats(), &UpdateStats { ignored_older: 2, ignored_duplicate: 1 });
+        assert_eq!(surface.update_stats().ignored(), 3);
+    }
+
+    #[test]
+    fn test_out_of_order_event_keeps_latest_timestamp() {
+        let mut surface = SaeSurface::with_warmup(10, 10, WarmupConfig::disabled());
+        assert!(surface.update(&event_at(1, 1, 10)));
+        assert!(surface.update(&event_at(2, 2, 30)));
+        // taken under UpdatePolicy::Always, but older than the latest event
+        assert!(surface.update(&event_at(3, 3, 20)));
+        assert_eq!(surface.matrix()[(3, 3)], 20);
+        assert_eq!(surface.latest_timestamp(), Some(30));
+        assert_eq!(surface.elapsed(), 20);
+    }
+
+    #[test]
+    fn test_activity_statistics() {
+        let mut surface = SaeSurface::new(4, 5);
+        assert_eq!(surface.latest_timestamp(), None);
+        surface.update(&event_at(0, 0, 100));
+        surface.update(&event_at(0, 1, 950));
+        surface.update(&event_at(2, 1, 990));
+        surface.update(&event_at(3, 4, 1_000));
+        assert_eq!(surface.latest_timestamp(), Some(1_000));
+
+        let histogram = surface.age_histogram(1_000, 100, 5);
+        assert_eq!(histogram.counts, vec![3, 0, 0, 0, 0]);
+        assert_eq!((histogram.older, histogram.unobserved), (1, 16));
+        assert_eq!(histogram.quantile(0.5), Some(100));
+        assert_eq!(histogram.quantile(1.0), None);
+
+        assert!((surface.active_fraction(1_000, 100) - 0.15).abs() < 1e-6);
+        assert!((surface.active_fraction(1_000, 1_000) - 0.2).abs() < 1e-6);
+        assert_eq!(surface.row_activity(1_000, 100), vec![1, 0, 1, 1]);
+        assert_eq!(surface.col_activity(1_000, 100), vec![0, 2, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_from_events_matches_updates() {
+        let events = vec![
+            event_at(1, 2, 30), event_at(3, 4, 10), event_at(1, 2, 20),
+            event_at(0, 0, 0), event_at(9, 9, 50), event_at(3, 4, 40),
+        ];
+        let surface = SaeSurface::from_events(&events, 5, 6, WarmupConfig::default());
+        // the latest timestamp wins, whatever the order
+        assert_eq!(surface.matrix()[(1, 2)], 30);
+        assert_eq!(surface.matrix()[(3, 4)], 40);
+        assert!(surface.is_observed(0, 0));
+        assert_eq!(surface.populated_count(), 3);
+        assert_eq!(surface.elapsed(), 40);
+        assert_eq!(surface.latest_timestamp(), Some(40));
+
+        let mut sorted = events.clone();
+        sorted.sort_by_key(|evt| evt.timestamp);
+        let mut updated = SaeSurface::new(5, 6);
+        for evt in sorted.iter() {
+            updated.update(evt);
+        }
+        assert_eq!(updated.matrix(), surface.matrix());
+        assert_eq!(updated.occupancy(), surface.occupancy());
+    }
+
+    #[test]
+    fn test_warmup() {
+        let warmup = WarmupConfig {
+            min_populated_fraction: 0.5,
+            min_elapsed: 1000,
+        };
+        let mut surface = SaeSurface::with_warmup(4, 4, warmup);
+        assert!(!surface.is_warmed_up());
+
+        surface.update(&event_at(0, 0, 100));
+        assert!(!surface.is_warmed_up());
+
+        // enough time has elapsed
+        surface.update(&event_at(0, 1, 1100));
+        assert!(surface.is_warmed_up());
+
+        surface.reset();
+        assert!(!surface.is_warmed_up());
+        // enough pixels populated
+        for col in 0..4 {
+            surface.update(&event_at(0, col, 1 + col as SaeTime));
+            surface.update(&event_at(1, col, 5 + col as SaeTime));
+        }
+        assert!(surface.is_warmed_up());
+    }
+
+    #[test]
+    fn test_detection_suppressed_during_warmup() {
+        let mut surface = SaeSurface::new(40, 40);
+        // a corner-shaped neighborhood, but only a tiny fraction of a fresh surface
+        for row in 16..21 {
+            for col in 16..21 {
+                surface.update(&event_at(row, col, 7));
+            }
+        }
+        let evt = event_at(20, 20, 9);
+        assert!(surface.update_and_detect(&evt).is_none());
+
+        surface.set_warmup_config(WarmupConfig::disabled());
+        assert!(surface.update_and_detect(&evt).is_some());
+    }
+}
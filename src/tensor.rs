@@ -0,0 +1,131 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Conversion of voxel grids, SAE patches and descriptors to and from candle
+//! tensors, for training and running learned components such as corner
+//! verifiers and descriptors (enabled by the `candle-core` feature).
+//!
+//! Tensors are `f32` and use the layouts common to image models: a voxel grid is
+//! (bins, rows, cols), a batch of patches is (N, 1, size, size), and a batch of
+//! descriptors is (N, 36).
+
+use candle_core::{bail, Device, Result, Tensor};
+use nalgebra::DMatrix;
+
+use crate::patch_track::decayed_patch;
+use crate::sae_types::*;
+use crate::voxel::VoxelGrid;
+
+
+/// A voxel grid as a (bins, rows, cols) tensor
+pub fn voxel_tensor(grid: &VoxelGrid, device: &Device) -> Result<Tensor> {
+    let (bins, nrows, ncols) = grid.shape();
+    Tensor::from_slice(grid.as_slice(), (bins, nrows, ncols), device)
+}
+
+/// the values of a patch in row-major order
+fn row_major(patch: &DMatrix<f32>) -> Vec<f32> {
+    patch.transpose().as_slice().to_vec()
+}
+
+/// A patch as a (rows, cols) tensor
+pub fn patch_tensor(patch: &DMatrix<f32>, device: &Device) -> Result<Tensor> {
+    Tensor::from_vec(row_major(patch), patch.shape(), device)
+}
+
+/// Patches of equal size as an (N, 1, rows, cols) tensor
+pub fn patch_batch_tensor(patches: &[DMatrix<f32>], device: &Device) -> Result<Tensor> {
+    let (nrows, ncols) = patches.first().map_or((0, 0), |patch| patch.shape());
+    let mut values = Vec::with_capacity(patches.len() * nrows * ncols);
+    for patch in patches.iter() {
+        if patch.shape() != (nrows, ncols) {
+            bail!("patch of shape {:?} in a batch of {:?}", patch.shape(), (nrows, ncols));
+        }
+        values.extend(row_major(patch));
+    }
+    Tensor::from_vec(values, (patches.len(), 1, nrows, ncols), device)
+}
+
+/// Decayed SAE patches of `size` x `size` centered on each corner, as an
+/// (N, 1, size, size) tensor: the input of a learned corner verifier.
+/// Values are 1 for an event at `now`, decaying with time constant `decay`.
+pub fn sae_patch_tensor(sae: &SaeMatrix, corners: &[SaeEvent], size: usize, now: SaeTime,
+                        decay: SaeTime, device: &Device) -> Result<Tensor> {
+    let half = (size / 2) as i32;
+    let patches: Vec<DMatrix<f32>> = corners.iter()
+        .map(|corner| decayed_patch(sae, now, decay, corner.row as i32 - half, corner.col as i32 - half, size))
+        .collect();
+    patch_batch_tensor(&patches, device)
+}
+
+/// Descriptors of corners as an (N, 36) tensor; every corner must have a descriptor
+pub fn descriptor_tensor(corners: &[SaeEvent], device: &Device) -> Result<Tensor> {
+    let mut values = Vec::with_capacity(corners.len() * NORM_DESCRIPTOR_LEN);
+    for (idx, corner) in corners.iter().enumerate() {
+        match corner.norm_descriptor {
+            Some(ref desc) => values.extend_from_slice(&desc[..]),
+            None => bail!("corner {} has no descriptor", idx),
+        }
+    }
+    Tensor::from_vec(values, (corners.len(), NORM_DESCRIPTOR_LEN), device)
+}
+
+/// Descriptors from an (N, 36) tensor, eg the output of a learned descriptor
+pub fn descriptors_from_tensor(tensor: &Tensor) -> Result<Vec<NormDescriptor>> {
+    let rows: Vec<Vec<f32>> = tensor.to_dtype(candle_core::DType::F32)?.to_vec2()?;
+    rows.iter()
+        .map(|row| {
+            if row.len() != NORM_DESCRIPTOR_LEN {
+                bail!("descriptor of length {}", row.len());
+            }
+            let mut desc = [0.0f32; NORM_DESCRIPTOR_LEN];
+            desc.copy_from_slice(row);
+            Ok(desc)
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layouts() {
+        let device = Device::Cpu;
+        let events = vec![
+            SaeEvent { row: 1, col: 2, polarity: 1, timestamp: 10, ..SaeEvent::default() },
+            SaeEvent { row: 0, col: 1, polarity: 0, timestamp: 20, ..SaeEvent::default() },
+        ];
+        let grid = VoxelGrid::from_events(&events, 2, 2, 3);
+        let voxels = voxel_tensor(&grid, &device).unwrap();
+        assert_eq!(voxels.dims(), &[2, 2, 3]);
+        assert_eq!(voxels.to_vec3::<f32>().unwrap()[0][1][2], 1.0);
+        assert_eq!(voxels.to_vec3::<f32>().unwrap()[1][0][1], -1.0);
+
+        let patch = DMatrix::from_row_slice(2, 3, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let tensor = patch_tensor(&patch, &device).unwrap();
+        assert_eq!(tensor.to_vec2::<f32>().unwrap(), vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]);
+        assert!(patch_batch_tensor(&[patch.clone(), DMatrix::zeros(3, 3)], &device).is_err());
+
+        let mut sae = SaeMatrix::zeros(8, 8);
+        sae[(4, 4)] = 100;
+        let corner = SaeEvent { row: 4, col: 4, timestamp: 100, ..SaeEvent::default() };
+        let patches = sae_patch_tensor(&sae, &[corner.clone(), corner], 5, 100, 1000, &device).unwrap();
+        assert_eq!(patches.dims(), &[2, 1, 5, 5]);
+        assert_eq!(patches.sum_all().unwrap().to_scalar::<f32>().unwrap(), 2.0);
+    }
+
+    #[test]
+    fn test_descriptors() {
+        let device = Device::Cpu;
+        let mut desc = [0.5f32; NORM_DESCRIPTOR_LEN];
+        desc[0] = 1.0;
+        let corner = SaeEvent { norm_descriptor: Some(Box::new(desc)), ..SaeEvent::default() };
+        let tensor = descriptor_tensor(&[corner.clone(), corner], &device).unwrap();
+        assert_eq!(tensor.dims(), &[2, NORM_DESCRIPTOR_LEN]);
+        assert_eq!(descriptors_from_tensor(&tensor).unwrap(), vec![desc, desc]);
+        assert!(descriptor_tensor(&[SaeEvent::new()], &device).is_err());
+        assert!(descriptors_from_tensor(&Tensor::zeros((1, 4), candle_core::DType::F32, &device).unwrap()).is_err());
+    }
+}
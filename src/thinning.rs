@@ -0,0 +1,197 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Corner-aware thinning of an event stream, for logging or wireless transmission.
+//! All corner events are kept, plus a configurable random fraction of the other
+//! events, which retains the geometric information in a drastically smaller stream.
+//! `StreamThinner::thin` applies the thinning to a stream as an iterator adapter,
+//! detecting corners along the way.
+
+use crate::detector::CornerDetector;
+use crate::sae_types::*;
+
+
+/// Parameters of the stream thinner
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThinningConfig {
+    /// fraction (0..1) of non-corner events to keep
+    pub keep_fraction: f32,
+    /// seed for the sampling, so that thinning is reproducible
+    pub seed: u64,
+}
+
+impl Default for ThinningConfig {
+    fn default() -> Self {
+        ThinningConfig {
+            keep_fraction: 0.01,
+            seed: 0,
+        }
+    }
+}
+
+/// Counts of events offered to and kept by the thinner
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ThinningStats {
+    pub events_in: u64,
+    pub corners_kept: u64,
+    pub others_kept: u64,
+}
+
+impl ThinningStats {
+    pub fn events_out(&self) -> u64 {
+        self.corners_kept + self.others_kept
+    }
+
+    /// ratio of output events to input events (0..1): smaller is more reduction
+    pub fn output_ratio(&self) -> f32 {
+        if self.events_in == 0 {
+            return 1.0;
+        }
+        (self.events_out() as f32) / (self.events_in as f32)
+    }
+}
+
+/// Keeps every corner, and a random fraction of non-corner events
+pub struct StreamThinner {
+    config: ThinningConfig,
+    /// xorshift64 state, never zero
+    state: u64,
+    stats: ThinningStats,
+}
+
+impl StreamThinner {
+    pub fn new(config: ThinningConfig) -> Self {
+        // splitmix64, so that nearby seeds start far apart
+        let mut state = config.seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        state ^= state >> 31;
+        StreamThinner {
+            config,
+            state: state.max(1),
+            stats: ThinningStats::default(),
+        }
+    }
+
+    /// a uniform sample in 0..1
+    fn next_fraction(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Decide whether to keep the next event, given whether the detector found it to be a corner
+    pub fn keep(&mut self, is_corner: bool) -> bool {
+        self.stats.events_in += 1;
+        if is_corner {
+            self.stats.corners_kept += 1;
+            return true;
+        }
+        let keep = self.config.keep_fraction > 0.0 &&
+            self.next_fraction() < self.config.keep_fraction;
+        if keep {
+            self.stats.others_kept += 1;
+        }
+        keep
+    }
+
+    pub fn stats(&self) -> &ThinningStats {
+        &self.stats
+    }
+
+    /// Thin a stream: each event is checked for a corner by `detector`,
+    /// and passed on if this thinner keeps it
+    pub fn thin<I, D>(self, events: I, detector: D) -> Thinned<I::IntoIter, D>
+        where I: IntoIterator<Item = SaeEvent>, D: CornerDetector
+    {
+        Thinned { inner: events.into_iter(), detector, thinner: self }
+    }
+}
+
+/// Iterator adapter returned by `StreamThinner::thin`
+pub struct Thinned<I, D> {
+    inner: I,
+    detector: D,
+    thinner: StreamThinner,
+}
+
+impl<I, D> Thinned<I, D> {
+    /// the thinner, eg for its counts
+    pub fn thinner(&self) -> &StreamThinner {
+        &self.thinner
+    }
+}
+
+impl<I, D> Iterator for Thinned<I, D>
+    where I: Iterator<Item = SaeEvent>, D: CornerDetector
+{
+    type Item = SaeEvent;
+
+    fn next(&mut self) -> Option<SaeEvent> {
+        loop {
+            let evt = self.inner.next()?;
+            let is_corner = self.detector.process(&evt).is_some();
+            if self.thinner.keep(is_corner) {
+                return Some(evt);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thinning() {
+        let config = ThinningConfig { keep_fraction: 0.1, seed: 7 };
+        let mut thinner = StreamThinner::new(config.clone());
+
+        let mut kept = Vec::new();
+        for i in 0..10_000 {
+            let is_corner = i % 100 == 0;
+            kept.push(thinner.keep(is_corner));
+        }
+        let stats = thinner.stats().clone();
+        assert_eq!(stats.events_in, 10_000);
+        assert_eq!(stats.corners_kept, 100);
+        // roughly 10% of the 9900 other events
+        assert!(stats.others_kept > 800 && stats.others_kept < 1200);
+        assert!(stats.output_ratio() < 0.15);
+
+        // the same seed reproduces the same decisions
+        let mut replay = StreamThinner::new(config);
+        for (i, &was_kept) in kept.iter().enumerate() {
+            assert_eq!(replay.keep(i % 100 == 0), was_kept);
+        }
+    }
+
+    #[test]
+    fn test_corners_only() {
+        let mut thinner = StreamThinner::new(ThinningConfig { keep_fraction: 0.0, seed: 1 });
+        assert!(!thinner.keep(false));
+        assert!(thinner.keep(true));
+        assert_eq!(thinner.stats().events_out(), 1);
+    }
+
+    /// reports every tenth event as a corner
+    struct EveryTenth;
+
+    impl CornerDetector for EveryTenth {
+        fn process(&mut self, evt: &SaeEvent) -> Option<SaeEvent> {
+            if evt.timestamp.is_multiple_of(10) { Some(evt.clone()) } else { None }
+        }
+    }
+
+    #[test]
+    fn test_thin_adapter() {
+        let events = (0..1_000).map(|timestamp| SaeEvent { timestamp, ..SaeEvent::default() });
+        let mut thinned = StreamThinner::new(ThinningConfig { keep_fraction: 0.0, seed: 3 }).thin(events, EveryTenth);
+        let kept: Vec<SaeTime> = thinned.by_ref().map(|evt| evt.timestamp).collect();
+        assert_eq!(kept, (0..100).map(|i| i * 10).collect::<Vec<SaeTime>>());
+        let stats = thinned.thinner().stats();
+        assert_eq!((stats.events_in, stats.corners_kept, stats.others_kept), (1_000, 100, 0));
+    }
+}
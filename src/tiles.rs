@@ -0,0 +1,175 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Tracking of which square tiles of an SAE changed, so that visualization,
+//! heatmaps and GPU uploads only need to touch the changed regions.
+
+use crate::sae_types::*;
+
+
+/// A rectangular region of the SAE, in pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileRect {
+    pub row: usize,
+    pub col: usize,
+    pub nrows: usize,
+    pub ncols: usize,
+}
+
+/// Per-tile change flags over an SAE of a given shape
+#[derive(Clone, Debug)]
+pub struct DirtyTiles {
+    shape: (usize, usize),
+    tile_size: usize,
+    tile_rows: usize,
+    tile_cols: usize,
+    dirty: Vec<bool>,
+    dirty_count: usize,
+}
+
+impl DirtyTiles {
+    /// Tiles of `tile_size` pixels square over an SAE of `nrows` x `ncols`, all clean.
+    /// Tiles along the bottom and right edges may be smaller.
+    pub fn new(nrows: usize, ncols: usize, tile_size: usize) -> Self {
+        let tile_size = tile_size.max(1);
+        let tile_rows = nrows.div_ceil(tile_size);
+        let tile_cols = ncols.div_ceil(tile_size);
+        DirtyTiles {
+            shape: (nrows, ncols),
+            tile_size,
+            tile_rows,
+            tile_cols,
+            dirty: vec![false; tile_rows * tile_cols],
+            dirty_count: 0,
+        }
+    }
+
+    pub fn tile_size(&self) -> usize {
+        self.tile_size
+    }
+
+    /// number of tiles (rows, cols)
+    pub fn grid_shape(&self) -> (usize, usize) {
+        (self.tile_rows, self.tile_cols)
+    }
+
+    /// Mark the tile containing the pixel as changed; pixels outside the SAE are ignored
+    pub fn mark(&mut self, row: usize, col: usize) {
+        if row >= self.shape.0 || col >= self.shape.1 {
+            return;
+        }
+        let flag = &mut self.dirty[(row / self.tile_size) * self.tile_cols + (col / self.tile_size)];
+        if !*flag {
+            *flag = true;
+            self.dirty_count += 1;
+        }
+    }
+
+    /// Mark the tile containing the event as changed
+    pub fn mark_event(&mut self, evt: &SaeEvent) {
+        self.mark(evt.row as usize, evt.col as usize);
+    }
+
+    pub fn mark_all(&mut self) {
+        for flag in self.dirty.iter_mut() {
+            *flag = true;
+        }
+        self.dirty_count = self.dirty.len();
+    }
+
+    pub fn clear(&mut self) {
+        for flag in self.dirty.iter_mut() {
+            *flag = false;
+        }
+        self.dirty_count = 0;
+    }
+
+    pub fn dirty_count(&self) -> usize {
+        self.dirty_count
+    }
+
+    /// fraction (0..1) of tiles changed
+    pub fn dirty_fraction(&self) -> f32 {
+        if self.dirty.is_empty() {
+            return 0.0;
+        }
+        (self.dirty_count as f32) / (self.dirty.len() as f32)
+    }
+
+    /// whether the tile at (tile row, tile col) is changed
+    pub fn is_dirty(&self, tile_row: usize, tile_col: usize) -> bool {
+        tile_row < self.tile_rows && tile_col < self.tile_cols &&
+            self.dirty[tile_row * self.tile_cols + tile_col]
+    }
+
+    /// the pixel region covered by the tile at (tile row, tile col)
+    pub fn tile_rect(&self, tile_row: usize, tile_col: usize) -> TileRect {
+        let row = tile_row * self.tile_size;
+        let col = tile_col * self.tile_size;
+        TileRect {
+            row,
+            col,
+            nrows: (self.shape.0 - row).min(self.tile_size),
+            ncols: (self.shape.1 - col).min(self.tile_size),
+        }
+    }
+
+    /// the regions of all changed tiles, in row-major order
+    pub fn dirty_tiles(&self) -> impl Iterator<Item = TileRect> + '_ {
+        let tile_cols = self.tile_cols;
+        self.dirty.iter().enumerate()
+            .filter(|(_, &dirty)| dirty)
+            .map(move |(idx, _)| self.tile_rect(idx / tile_cols, idx % tile_cols))
+    }
+
+    /// Copy the changed tiles from `src` into `dst` (both of the tracked shape),
+    /// clearing the flags. Returns the number of tiles copied.
+    pub fn copy_dirty(&mut self, src: &SaeMatrix, dst: &mut SaeMatrix) -> usize {
+        let rects: Vec<TileRect> = self.dirty_tiles().collect();
+        for rect in rects.iter() {
+            let start = (rect.row, rect.col);
+            let shape = (rect.nrows, rect.ncols);
+            dst.slice_mut(start, shape).copy_from(&src.slice(start, shape));
+        }
+        self.clear();
+        rects.len()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dirty_tiles() {
+        let mut tiles = DirtyTiles::new(10, 10, 4);
+        assert_eq!(tiles.grid_shape(), (3, 3));
+        tiles.mark(0, 0);
+        tiles.mark(1, 2);
+        tiles.mark(9, 9);
+        tiles.mark(10, 0);
+        assert_eq!(tiles.dirty_count(), 2);
+        assert!(tiles.is_dirty(2, 2));
+        assert!(!tiles.is_dirty(1, 1));
+
+        let rects: Vec<TileRect> = tiles.dirty_tiles().collect();
+        assert_eq!(rects, vec![
+            TileRect { row: 0, col: 0, nrows: 4, ncols: 4 },
+            TileRect { row: 8, col: 8, nrows: 2, ncols: 2 },
+        ]);
+    }
+
+    #[test]
+    fn test_copy_dirty() {
+        let src = SaeMatrix::from_element(10, 10, 7);
+        let mut dst = SaeMatrix::zeros(10, 10);
+        let mut tiles = DirtyTiles::new(10, 10, 4);
+        tiles.mark(9, 0);
+        assert_eq!(tiles.copy_dirty(&src, &mut dst), 1);
+        assert_eq!(tiles.dirty_count(), 0);
+        assert_eq!(dst[(8, 3)], 7);
+        assert_eq!(dst[(7, 3)], 0);
+        assert_eq!(dst.iter().filter(|&&t| t == 7).count(), 8);
+    }
+}
@@ -0,0 +1,348 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Timestamps with explicit units.
+//! Event datasets disagree on timestamp scale (nanoseconds, microseconds, or
+//! floating-point seconds), while the SAE always stores integer microsecond ticks.
+//! `EventTime` makes the unit explicit at API boundaries. Its conversions and
+//! arithmetic saturate at the ends of its range, rather than overflowing on
+//! corrupt or far-off timestamps.
+
+use core::ops::{Add, Sub};
+use core::time::Duration;
+
+use crate::sae_types::*;
+
+
+/// The unit of raw timestamp values read from a dataset or driver
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeUnit {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl TimeUnit {
+    /// number of nanoseconds in one tick of this unit
+    fn nanos_per_tick(self) -> u64 {
+        match self {
+            TimeUnit::Nanoseconds => 1,
+            TimeUnit::Microseconds => 1_000,
+            TimeUnit::Milliseconds => 1_000_000,
+            TimeUnit::Seconds => 1_000_000_000,
+        }
+    }
+}
+
+/// A point in time, stored as integer microsecond ticks (the SAE resolution)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EventTime {
+    micros: u64,
+}
+
+impl EventTime {
+    pub const ZERO: EventTime = EventTime { micros: 0 };
+
+    pub fn from_micros(micros: u64) -> Self {
+        EventTime { micros }
+    }
+
+    /// sub-microsecond precision is truncated
+    pub fn from_nanos(nanos: u64) -> Self {
+        EventTime { micros: nanos / 1_000 }
+    }
+
+    /// saturates at the end of the range
+    pub fn from_millis(millis: u64) -> Self {
+        EventTime { micros: millis.saturating_mul(1_000) }
+    }
+
+    /// negative or non-finite values map to zero, and values past the range saturate
+    pub fn from_secs_f64(secs: f64) -> Self {
+        if !secs.is_finite() || secs <= 0.0 {
+            return Self::ZERO;
+        }
+        EventTime { micros: libm::round(secs * 1e6) as u64 }
+    }
+
+    /// convert a raw integer timestamp in the given unit, saturating at the end of the range
+    pub fn from_ticks(ticks: u64, unit: TimeUnit) -> Self {
+        let nanos_per_tick = unit.nanos_per_tick();
+        if nanos_per_tick >= 1_000 {
+            Self::from_micros(ticks.saturating_mul(nanos_per_tick / 1_000))
+        } else {
+            Self::from_nanos(ticks.saturating_mul(nanos_per_tick))
+        }
+    }
+
+    /// the SAE timestamp value, which is always in microseconds
+    pub fn from_sae_time(time: SaeTime) -> Self {
+        Self::from_micros(time as u64)
+    }
+
+    pub fn as_micros(&self) -> u64 {
+        self.micros
+    }
+
+    /// saturates past about 584 years
+    pub fn as_nanos(&self) -> u64 {
+        self.micros.saturating_mul(1_000)
+    }
+
+    pub fn as_secs_f64(&self) -> f64 {
+        (self.micros as f64) / 1e6
+    }
+
+    /// convert to an SAE timestamp, or `None` if this time is beyond the range of `SaeTime`
+    pub fn to_sae_time(&self) -> Option<SaeTime> {
+        if self.micros > (SaeTime::MAX as u64) {
+            None
+        } else {
+            Some(self.micros as SaeTime)
+        }
+    }
+
+    /// elapsed time since an earlier time, or zero if `earlier` is actually later
+    pub fn saturating_duration_since(&self, earlier: EventTime) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(earlier.micros))
+    }
+}
+
+fn duration_micros(duration: Duration) -> u64 {
+    duration.as_micros().min(u64::MAX as u128) as u64
+}
+
+impl Add<Duration> for EventTime {
+    type Output = EventTime;
+
+    /// saturates at the end of the range
+    fn add(self, rhs: Duration) -> EventTime {
+        EventTime { micros: self.micros.saturating_add(duration_micros(rhs)) }
+    }
+}
+
+impl Sub<Duration> for EventTime {
+    type Output = EventTime;
+
+    fn sub(self, rhs: Duration) -> EventTime {
+        EventTime { micros: self.micros.saturating_sub(duration_micros(rhs)) }
+    }
+}
+
+impl From<Duration> for EventTime {
+    /// interpret a duration as time elapsed since the start of the recording
+    fn from(elapsed: Duration) -> Self {
+        EventTime { micros: duration_micros(elapsed) }
+    }
+}
+
+impl SaeEvent {
+    /// the event timestamp with explicit units
+    pub fn event_time(&self) -> EventTime {
+        EventTime::from_sae_time(self.timestamp)
+    }
+
+    /// set the event timestamp; returns false (leaving the timestamp unchanged)
+    /// if the time is beyond the range of the SAE
+    pub fn set_event_time(&mut self, time: EventTime) -> bool {
+        match time.to_sae_time() {
+            Some(timestamp) => {
+                self.timestamp = timestamp;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Overflow-safe arithmetic on raw SAE timestamps, for custom stages.
+/// Plain `-` and `+` on `SaeTime` panic in debug builds, and wrap silently in
+/// release builds, when an event arrives out of order or a window reaches past the
+/// start or end of the timestamp range; these helpers say what happens instead.
+pub trait SaeTimeExt: Copy {
+    /// time from `earlier` to this, or zero if `earlier` is in fact later
+    fn elapsed_since(self, earlier: SaeTime) -> SaeTime;
+    /// time from `earlier` to this, or None if `earlier` is later
+    fn checked_elapsed_since(self, earlier: SaeTime) -> Option<SaeTime>;
+    /// the time `dt` after this, saturating at the end of the timestamp range
+    fn after(self, dt: SaeTime) -> SaeTime;
+    /// the time `dt` after this, or None past the end of the timestamp range
+    fn checked_after(self, dt: SaeTime) -> Option<SaeTime>;
+    /// the time `dt` before this, saturating at zero
+    fn before(self, dt: SaeTime) -> SaeTime;
+    /// whether this is at most `horizon` before `now`; times after `now` count as within
+    fn is_within(self, now: SaeTime, horizon: SaeTime) -> bool;
+    /// time from `earlier` to this on a wrapping timestamp counter, as from a sensor
+    /// whose clock rolls over: correct across one rollover
+    fn wrapping_elapsed_since(self, earlier: SaeTime) -> SaeTime;
+    /// whether this is later than `other`, by at most `horizon`, on a wrapping counter.
+    /// Meaningful for horizons under half the timestamp range.
+    fn is_fresher_within(self, other: SaeTime, horizon: SaeTime) -> bool;
+}
+
+impl SaeTimeExt for SaeTime {
+    fn elapsed_since(self, earlier: SaeTime) -> SaeTime {
+        self.saturating_sub(earlier)
+    }
+
+    fn checked_elapsed_since(self, earlier: SaeTime) -> Option<SaeTime> {
+        self.checked_sub(earlier)
+    }
+
+    fn after(self, dt: SaeTime) -> SaeTime {
+        self.saturating_add(dt)
+    }
+
+    fn checked_after(self, dt: SaeTime) -> Option<SaeTime> {
+        self.checked_add(dt)
+    }
+
+    fn before(self, dt: SaeTime) -> SaeTime {
+        self.saturating_sub(dt)
+    }
+
+    fn is_within(self, now: SaeTime, horizon: SaeTime) -> bool {
+        now.elapsed_since(self) <= horizon
+    }
+
+    fn wrapping_elapsed_since(self, earlier: SaeTime) -> SaeTime {
+        self.wrapping_sub(earlier)
+    }
+
+    fn is_fresher_within(self, other: SaeTime, horizon: SaeTime) -> bool {
+        let elapsed = self.wrapping_elapsed_since(other);
+        elapsed > 0 && elapsed <= horizon && elapsed <= SaeTime::MAX / 2
+    }
+}
+
+/// Shift every timestamp of an SAE back by `offset`, saturating at zero,
+/// as when the time origin moves forward by `offset`
+#[cfg(feature = "std")]
+pub fn rebase_sae(sae: &mut SaeMatrix, offset: SaeTime) {
+    sae.apply(|t| t.before(offset));
+}
+
+/// Maps 64-bit event times onto SAE timestamps for streams longer than the
+/// `SaeTime` range (about 71 minutes of microseconds).
+/// SAE timestamps count from a moving origin. Once an event comes `rebase_after`
+/// past the origin, the origin moves forward to leave `keep` of history, and
+/// `to_sae_time` returns the offset by which every surface of the stream must be
+/// rebased, eg with `SaeTracker::rebase`, before the event is processed.
+/// Timestamps older than the kept history saturate at zero.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeRebaser {
+    origin: u64,
+    rebase_after: SaeTime,
+    keep: SaeTime,
+}
+
+impl Default for TimeRebaser {
+    /// rebase after half the timestamp range, keeping one minute of history
+    fn default() -> Self {
+        Self::new(SaeTime::MAX / 2, 60_000_000)
+    }
+}
+
+impl TimeRebaser {
+    /// `keep` is clamped to less than `rebase_after`
+    pub fn new(rebase_after: SaeTime, keep: SaeTime) -> Self {
+        TimeRebaser { origin: 0, rebase_after, keep: keep.min(rebase_after.saturating_sub(1)) }
+    }
+
+    /// the event time that SAE timestamp zero stands for
+    pub fn origin(&self) -> EventTime {
+        EventTime::from_micros(self.origin)
+    }
+
+    /// The SAE timestamp of `time`, and the offset to rebase surfaces by first, if the
+    /// origin moved. Times before the origin map to zero.
+    pub fn to_sae_time(&mut self, time: EventTime) -> (SaeTime, Option<SaeTime>) {
+        let mut since = time.as_micros().saturating_sub(self.origin);
+        let mut shift = None;
+        if since > self.rebase_after as u64 {
+            let offset = since - self.keep as u64;
+            self.origin += offset;
+            since = self.keep as u64;
+            shift = Some(offset.min(SaeTime::MAX as u64) as SaeTime);
+        }
+        (since as SaeTime, shift)
+    }
+
+    /// the event time of an SAE timestamp, eg of a detected corner
+    pub fn to_event_time(&self, time: SaeTime) -> EventTime {
+        EventTime::from_micros(self.origin.saturating_add(time as u64))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_unit_conversions() {
+        let t = EventTime::from_ticks(1_500_000_000, TimeUnit::Nanoseconds);
+        assert_eq!(t, EventTime::from_micros(1_500_000));
+        assert_eq!(t, EventTime::from_millis(1_500));
+        assert_eq!(t, EventTime::from_secs_f64(1.5));
+        assert_eq!(t, EventTime::from_ticks(1_500, TimeUnit::Milliseconds));
+        assert_eq!(EventTime::from_ticks(2, TimeUnit::Seconds).as_micros(), 2_000_000);
+        assert_approx_eq!(t.as_secs_f64(), 1.5);
+        assert_eq!(t.as_nanos(), 1_500_000_000);
+        assert_eq!(EventTime::from_secs_f64(-1.0), EventTime::ZERO);
+    }
+
+    #[test]
+    fn test_sae_time_range() {
+        let t = EventTime::from_micros(SaeTime::MAX as u64);
+        assert_eq!(t.to_sae_time(), Some(SaeTime::MAX));
+        let t = t + Duration::from_micros(1);
+        assert_eq!(t.to_sae_time(), None);
+
+        let mut evt = SaeEvent::new();
+        assert!(evt.set_event_time(EventTime::from_millis(3)));
+        assert_eq!(evt.timestamp, 3_000);
+        assert!(!evt.set_event_time(t));
+        assert_eq!(evt.event_time(), EventTime::from_micros(3_000));
+    }
+
+    #[test]
+    fn test_duration_arithmetic() {
+        let t0 = EventTime::from_millis(10);
+        let t1 = t0 + Duration::from_millis(5);
+        assert_eq!(t1.saturating_duration_since(t0), Duration::from_millis(5));
+        assert_eq!(t0.saturating_duration_since(t1), Duration::from_millis(0));
+        assert_eq!(t0 - Duration::from_secs(1), EventTime::ZERO);
+
+        // saturating at the end of the range, rather than overflowing
+        let end = EventTime::from_micros(u64::MAX);
+        assert_eq!(t0 + Duration::MAX, end);
+        assert_eq!(EventTime::from_millis(u64::MAX), end);
+        assert_eq!(EventTime::from_ticks(u64::MAX / 2, TimeUnit::Seconds), end);
+        assert_eq!(end.as_nanos(), u64::MAX);
+        assert_eq!(EventTime::from(Duration::MAX), end);
+    }
+
+    #[test]
+    fn test_sae_time_ext() {
+        assert_eq!(5u32.elapsed_since(7), 0);
+        assert_eq!(7u32.elapsed_since(5), 2);
+        assert_eq!(5u32.checked_elapsed_since(7), None);
+        assert_eq!((SaeTime::MAX - 1).after(5), SaeTime::MAX);
+        assert_eq!((SaeTime::MAX - 1).checked_after(5), None);
+        assert_eq!(3u32.before(5), 0);
+        assert!(90u32.is_within(100, 10));
+        assert!(!89u32.is_within(100, 10));
+        assert!(110u32.is_within(100, 10));
+
+        // a counter that rolled over between the two timestamps
+        let before_rollover = SaeTime::MAX - 9;
+        assert_eq!(5u32.wrapping_elapsed_since(before_rollover), 15);
+        assert!(5u32.is_fresher_within(before_rollover, 20));
+        assert!(!5u32.is_fresher_within(before_rollover, 10));
+        assert!(!before_rollover.is_fresher_within(5, 20));
+        assert!(!5u32.is_fresher_within(5, 20));
+    }
+}
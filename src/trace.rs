@@ -0,0 +1,169 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Step-by-step traces of the Arc* decision for a single event, for teaching,
+//! visual explainers, and debugging changes to the algorithm.
+//!
+//! `trace_detection` reruns the configured detector on one event, recording for
+//! each ring checked the sampled timestamps, the newest element the arc grows
+//! from, and every expansion decision: which side the arc grew to, the element
+//! taken and the one it beat, and how the freshest segment changed. The trace has
+//! no cost when not requested: the detector reports its decisions to a no-op
+//! observer. `DetectionTrace::to_json` gives the trace as JSON for a UI.
+
+use crate::circle::CircleSpec;
+use crate::detector::{arcstar_expand_observed, find_freshest_in_circle, is_arc_valid, DetectorConfig, DetectorWork};
+use crate::sae_types::*;
+
+pub use crate::detector::{ArcDirection, ExpansionStep};
+
+
+/// How one ring was checked
+#[derive(Clone, Debug, PartialEq)]
+pub struct RingTrace {
+    /// offsets (row, col) of the ring pixels sampled, in ring order, without dead pixels
+    pub offsets: Vec<[i32; 2]>,
+    /// timestamps at the offsets
+    pub values: Vec<SaeTime>,
+    /// index of the newest element, where the arc starts
+    pub newest: usize,
+    pub min_arc_len: usize,
+    pub max_arc_len: usize,
+    pub steps: Vec<ExpansionStep>,
+    /// the freshest segment found: its length, and its elements clockwise and
+    /// counter-clockwise of the newest
+    pub segment_size: usize,
+    pub segment_cw: usize,
+    pub segment_ccw: usize,
+    /// whether the ring holds a valid arc
+    pub valid: bool,
+}
+
+/// The full decision for one event
+#[derive(Clone, Debug, PartialEq)]
+pub struct DetectionTrace {
+    pub event: SaeEvent,
+    /// rings checked in order: the outer ring only if the inner one held a valid arc.
+    /// Empty if the event is too near the border, or at a dead pixel, to be checked.
+    pub rings: Vec<RingTrace>,
+    pub is_corner: bool,
+}
+
+fn trace_ring(vals: Vec<SaeTime>, offsets: Vec<[i32; 2]>, ring: &CircleSpec) -> RingTrace {
+    let mut trace = RingTrace {
+        offsets,
+        values: Vec::new(),
+        newest: 0,
+        min_arc_len: ring.min_arc_len(),
+        max_arc_len: ring.max_arc_len(),
+        steps: Vec::new(),
+        segment_size: 0,
+        segment_cw: 0,
+        segment_ccw: 0,
+        valid: is_arc_valid(&vals, ring),
+    };
+    // too few pixels left to hold an arc, eg after masking: nothing to expand
+    if vals.len() > ring.max_arc_len() {
+        let (newest, _) = find_freshest_in_circle(&vals);
+        let steps = &mut trace.steps;
+        let (size, cw, ccw) = arcstar_expand_observed(&vals, vals.len(), ring.min_arc_len(), newest, |step| steps.push(step));
+        trace.newest = newest;
+        trace.segment_size = size;
+        trace.segment_cw = cw;
+        trace.segment_ccw = ccw;
+    }
+    trace.values = vals;
+    trace
+}
+
+/// Trace the decision of `config`'s detector on `evt`, over the surface `sae`
+/// already updated with it. Occupancy is not considered: the result matches
+/// `detector::detect_and_compute_configured` without an occupancy map.
+pub fn trace_detection(config: &DetectorConfig, sae: &SaeMatrix, evt: &SaeEvent) -> DetectionTrace {
+    let mut trace = DetectionTrace { event: evt.clone(), rings: Vec::new(), is_corner: false };
+    let (row, col) = (evt.row as usize, evt.col as usize);
+    let inset = config.border_inset();
+    let (nrows, ncols) = sae.shape();
+    if row < inset || col < inset || row + inset >= nrows || col + inset >= ncols ||
+        config.dead_pixels.as_ref().is_some_and(|dead| dead[(row, col)]) {
+        return trace;
+    }
+    let mut work = DetectorWork::default();
+    for ring in [&config.inner, &config.outer].iter() {
+        let (vals, offsets, _) = config.ring_vals(ring, sae, None, evt, &mut work);
+        let ring_trace = trace_ring(vals, offsets, ring);
+        let valid = ring_trace.valid;
+        trace.rings.push(ring_trace);
+        if !valid {
+            return trace;
+        }
+    }
+    trace.is_corner = true;
+    trace
+}
+
+impl DetectionTrace {
+    /// The trace as a JSON object, for a visual explainer
+    pub fn to_json(&self) -> String {
+        let rings: Vec<String> = self.rings.iter().map(|ring| {
+            let offsets: Vec<String> = ring.offsets.iter().map(|off| format!("[{},{}]", off[0], off[1])).collect();
+            let values: Vec<String> = ring.values.iter().map(|value| value.to_string()).collect();
+            let steps: Vec<String> = ring.steps.iter().map(|step| format!(
+                "{{\"direction\":\"{}\",\"index\":{},\"value\":{},\"other_value\":{},\"minimal\":{},\"in_segment\":{},\
+                 \"segment_size\":{},\"segment_oldest\":{}}}",
+                match step.direction { ArcDirection::Clockwise => "cw", ArcDirection::CounterClockwise => "ccw" },
+                step.index, step.value, step.other_value, step.minimal, step.in_segment, step.segment_size, step.segment_oldest,
+            )).collect();
+            format!("{{\"offsets\":[{}],\"values\":[{}],\"newest\":{},\"min_arc_len\":{},\"max_arc_len\":{},\"steps\":[{}],\
+                     \"segment_size\":{},\"segment_cw\":{},\"segment_ccw\":{},\"valid\":{}}}",
+                    offsets.join(","), values.join(","), ring.newest, ring.min_arc_len, ring.max_arc_len, steps.join(","),
+                    ring.segment_size, ring.segment_cw, ring.segment_ccw, ring.valid)
+        }).collect();
+        format!("{{\"event\":{{\"row\":{},\"col\":{},\"timestamp\":{},\"polarity\":{}}},\"is_corner\":{},\"rings\":[{}]}}",
+                self.event.row, self.event.col, self.event.timestamp, self.event.polarity, self.is_corner, rings.join(","))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detector::detect_and_compute_configured;
+
+    #[test]
+    fn test_trace_matches_detector() {
+        let config = DetectorConfig::default();
+        let mut sae = SaeMatrix::zeros(20, 20);
+        for row in 8..13 {
+            for col in 8..13 {
+                sae[(row, col)] = 7;
+            }
+        }
+        sae[(12, 12)] = 9;
+
+        for (row, col) in [(12u16, 12u16), (10, 10), (8, 12), (1, 1)].iter() {
+            let evt = SaeEvent { row: *row, col: *col, timestamp: sae[(*row as usize, *col as usize)], ..SaeEvent::default() };
+            let trace = trace_detection(&config, &sae, &evt);
+            assert_eq!(trace.is_corner, detect_and_compute_configured(&config, &sae, None, &evt).is_some(), "{:?}", (row, col));
+            for ring in trace.rings.iter() {
+                // every element but the newest is taken, once
+                assert_eq!(ring.steps.len(), ring.values.len() - 1);
+                let mut taken: Vec<usize> = ring.steps.iter().map(|step| step.index).collect();
+                taken.push(ring.newest);
+                taken.sort_unstable();
+                assert_eq!(taken, (0..ring.values.len()).collect::<Vec<usize>>());
+                assert_eq!(ring.steps.last().unwrap().segment_size, ring.segment_size);
+                assert_eq!(ring.segment_cw + ring.segment_ccw + 1, ring.segment_size);
+                assert!(ring.steps.iter().take(ring.min_arc_len - 1).all(|step| step.minimal && step.in_segment));
+            }
+        }
+
+        let corner = trace_detection(&config, &sae, &SaeEvent { row: 12, col: 12, timestamp: 9, ..SaeEvent::default() });
+        assert!(corner.is_corner);
+        assert_eq!(corner.rings.len(), 2);
+        let json = corner.to_json();
+        assert!(json.starts_with("{\"event\":{\"row\":12,\"col\":12,\"timestamp\":9,\"polarity\":0},\"is_corner\":true,\"rings\":[{\"offsets\":[[0,3],"));
+        assert_eq!(json.matches("\"direction\"").count(), 15 + 19);
+        assert!(trace_detection(&config, &sae, &SaeEvent { row: 1, col: 1, ..SaeEvent::default() }).rings.is_empty());
+    }
+}
@@ -0,0 +1,563 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Feature tracks: sequences of corner observations believed to belong to the same
+//! physical feature, and a store holding the history of all tracks.
+
+use std::collections::BTreeMap;
+
+use crate::predict::{CornerPredictor, PredictionConfig};
+use crate::sae_types::*;
+use crate::sink::CornerSink;
+
+
+/// Identifies a track within a `TrackStore`
+pub type TrackId = u32;
+
+/// The time-ordered corner observations of a single feature
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub id: TrackId,
+    pub observations: Vec<SaeEvent>,
+}
+
+impl Track {
+    pub fn new(id: TrackId, first: SaeEvent) -> Self {
+        Track { id, observations: vec![first] }
+    }
+
+    /// the most recent observation
+    pub fn last(&self) -> &SaeEvent {
+        // tracks are never empty
+        &self.observations[self.observations.len() - 1]
+    }
+
+    pub fn first(&self) -> &SaeEvent {
+        &self.observations[0]
+    }
+
+    pub fn len(&self) -> usize {
+        self.observations.len()
+    }
+
+    /// tracks always hold at least one observation
+    pub fn is_empty(&self) -> bool {
+        self.observations.is_empty()
+    }
+
+    /// Position (x = column, y = row) at `timestamp`, linearly interpolated between
+    /// observations. None outside the time spanned by the track.
+    pub fn position_at(&self, timestamp: SaeTime) -> Option<[f32; 2]> {
+        if timestamp < self.first().timestamp || timestamp > self.last().timestamp {
+            return None;
+        }
+        // index of the first observation at or after the timestamp
+        let next = self.observations.partition_point(|obs| obs.timestamp < timestamp);
+        let (row1, col1) = self.observations[next].subpixel_position();
+        if next == 0 || self.observations[next].timestamp == timestamp {
+            return Some([col1, row1]);
+        }
+        let prev = &self.observations[next - 1];
+        let (row0, col0) = prev.subpixel_position();
+        let span = (self.observations[next].timestamp - prev.timestamp) as f32;
+        let frac = (timestamp - prev.timestamp) as f32 / span;
+        Some([col0 + (col1 - col0) * frac, row0 + (row1 - row0) * frac])
+    }
+
+    /// Velocity (pixels per second, x = column, y = row) at `timestamp`: that of the
+    /// segment between the observations around it. None outside the track, or for a single observation.
+    pub fn velocity_at(&self, timestamp: SaeTime) -> Option<[f32; 2]> {
+        if self.observations.len() < 2 || timestamp < self.first().timestamp || timestamp > self.last().timestamp {
+            return None;
+        }
+        let next = self.observations.partition_point(|obs| obs.timestamp <= timestamp)
+            .clamp(1, self.observations.len() - 1);
+        let prev = &self.observations[next - 1];
+        let span = self.observations[next].timestamp.checked_sub(prev.timestamp).filter(|&span| span > 0)?;
+        let (row0, col0) = prev.subpixel_position();
+        let (row1, col1) = self.observations[next].subpixel_position();
+        let scale = 1e6 / span as f32;
+        Some([(col1 - col0) * scale, (row1 - row0) * scale])
+    }
+
+    /// Interpolated positions at every multiple of `period` within the track's lifetime,
+    /// so that tracks resampled with the same period share a time grid
+    pub fn resample(&self, period: SaeTime) -> Vec<TrackSample> {
+        if period == 0 {
+            return Vec::new();
+        }
+        let first = self.first().timestamp;
+        let start = first.div_ceil(period) * period;
+        (start..=self.last().timestamp)
+            .step_by(period as usize)
+            .filter_map(|timestamp| self.position_at(timestamp).map(|position| TrackSample { timestamp, position }))
+            .collect()
+    }
+
+    /// time between the first and the most recent observation
+    pub fn lifetime(&self) -> SaeTime {
+        self.last().timestamp.saturating_sub(self.first().timestamp)
+    }
+
+    /// time from the first observation to `now`
+    pub fn age_at(&self, now: SaeTime) -> SaeTime {
+        now.saturating_sub(self.first().timestamp)
+    }
+}
+
+/// A track position on a resampling grid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackSample {
+    pub timestamp: SaeTime,
+    /// position (x = column, y = row)
+    pub position: [f32; 2],
+}
+
+/// Positions of all tracks alive at one time
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackSnapshot {
+    pub timestamp: SaeTime,
+    /// (track, position) in track id order
+    pub positions: Vec<(TrackId, [f32; 2])>,
+}
+
+/// Notified as tracks grow and end, eg to classify trajectories as they form
+pub trait TrackObserver {
+    /// called after an observation is appended to `track`
+    fn track_extended(&mut self, _track: &Track) {}
+
+    /// called when `track` ends and is removed from its store
+    fn track_ended(&mut self, _track: &Track) {}
+}
+
+/// Holds the full history of all tracks, keyed by id
+#[derive(Clone, Debug, Default)]
+pub struct TrackStore {
+    tracks: BTreeMap<TrackId, Track>,
+    next_id: TrackId,
+}
+
+impl TrackStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a new track from its first observation, returning the new track id
+    pub fn start_track(&mut self, first: SaeEvent) -> TrackId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tracks.insert(id, Track::new(id, first));
+        id
+    }
+
+    /// Append an observation to an existing track; returns false if there is no such track
+    pub fn extend_track(&mut self, id: TrackId, observation: SaeEvent) -> bool {
+        match self.tracks.get_mut(&id) {
+            Some(track) => {
+                track.observations.push(observation);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Like `extend_track`, then notify `observer` of the extended track
+    pub fn extend_track_observed(&mut self, id: TrackId, observation: SaeEvent, observer: &mut dyn TrackObserver) -> bool {
+        match self.tracks.get_mut(&id) {
+            Some(track) => {
+                track.observations.push(observation);
+                observer.track_extended(track);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove a track that has ended, notifying `observer`
+    pub fn end_track(&mut self, id: TrackId, observer: &mut dyn TrackObserver) -> Option<Track> {
+        let track = self.tracks.remove(&id)?;
+        observer.track_ended(&track);
+        Some(track)
+    }
+
+    pub fn get(&self, id: TrackId) -> Option<&Track> {
+        self.tracks.get(&id)
+    }
+
+    pub fn remove(&mut self, id: TrackId) -> Option<Track> {
+        self.tracks.remove(&id)
+    }
+
+    /// all tracks in id order
+    pub fn iter(&self) -> impl Iterator<Item = &Track> {
+        self.tracks.values()
+    }
+
+    /// Interpolated positions of all tracks spanning `timestamp`
+    pub fn snapshot_at(&self, timestamp: SaeTime) -> TrackSnapshot {
+        let positions = self.tracks.values()
+            .filter_map(|track| track.position_at(timestamp).map(|pos| (track.id, pos)))
+            .collect();
+        TrackSnapshot { timestamp, positions }
+    }
+
+    /// Snapshots at fixed `period` from `start` through `end`, eg to feed a fixed-rate
+    /// controller or a batch optimizer from asynchronous corners
+    pub fn resample(&self, start: SaeTime, end: SaeTime, period: SaeTime) -> Vec<TrackSnapshot> {
+        if period == 0 {
+            return Vec::new();
+        }
+        (start..=end).step_by(period as usize).map(|timestamp| self.snapshot_at(timestamp)).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+}
+
+
+/// Parameters for associating corners into tracks
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackerConfig {
+    /// maximum distance (pixels) from a track's latest observation for a corner to extend it
+    pub match_radius: f32,
+    /// tracks without an observation for longer than this are no longer extended
+    pub max_gap: SaeTime,
+    /// minimum descriptor likeness for a corner to extend a track; zero ignores descriptors
+    pub min_likeness: f32,
+    /// how corners are associated with tracks
+    pub mode: MatchMode,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        TrackerConfig {
+            match_radius: 3.0,
+            max_gap: 20_000,
+            min_likeness: 0.0,
+            mode: MatchMode::Proximity,
+        }
+    }
+}
+
+/// How a corner is associated with a track
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum MatchMode {
+    /// the nearest track within the match radius of its latest observation,
+    /// with a descriptor likeness of at least `min_likeness`
+    #[default]
+    Proximity,
+    /// the track whose predicted position is nearest, within its prediction gate.
+    /// Descriptors are not used, so they can be disabled in the detector for speed.
+    Geometric(GeometricConfig),
+}
+
+/// Parameters of descriptor-free matching by motion consistency
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeometricConfig {
+    /// how tracks' next positions are predicted, and how wide their gates are
+    pub prediction: PredictionConfig,
+    /// Tracks too short to have a velocity of their own are predicted to move with
+    /// the mean velocity of the tracks whose latest observation is within this
+    /// distance (pixels), gated by the match radius
+    pub flow_radius: f32,
+}
+
+impl Default for GeometricConfig {
+    fn default() -> Self {
+        GeometricConfig {
+            prediction: PredictionConfig::default(),
+            flow_radius: 20.0,
+        }
+    }
+}
+
+/// Associates corners into tracks, by proximity to the latest observation of
+/// active tracks or by consistency with their motion (see `MatchMode`)
+pub struct CornerTracker {
+    config: TrackerConfig,
+    store: TrackStore,
+    active: Vec<TrackId>,
+}
+
+impl CornerTracker {
+    pub fn new(config: TrackerConfig) -> Self {
+        CornerTracker {
+            config,
+            store: TrackStore::new(),
+            active: Vec::new(),
+        }
+    }
+
+    pub fn store(&self) -> &TrackStore {
+        &self.store
+    }
+
+    /// ids of the tracks that can still be extended
+    pub fn active(&self) -> &[TrackId] {
+        &self.active
+    }
+
+    /// Extend the nearest matching active track with the corner, or start a new track.
+    /// Returns the id of the track the corner was added to.
+    pub fn add_corner(&mut self, corner: &SaeEvent) -> TrackId {
+        self.add_corner_scaled(corner, 1.0)
+    }
+
+    /// Like `add_corner`, with the match radius and prediction gates scaled for this
+    /// corner, eg widened where the scene moves fast (see `speed::SpeedSurface`)
+    pub fn add_corner_scaled(&mut self, corner: &SaeEvent, gate_scale: f32) -> TrackId {
+        let best = match &self.config.mode {
+            MatchMode::Proximity => self.nearest_track(corner, gate_scale),
+            MatchMode::Geometric(geometric) => self.predicted_track(corner, geometric, gate_scale),
+        };
+        match best {
+            Some(id) => {
+                self.store.extend_track(id, corner.clone());
+                id
+            }
+            None => {
+                let id = self.store.start_track(corner.clone());
+                self.active.push(id);
+                id
+            }
+        }
+    }
+
+    /// Extend an active track with a corner found for it, eg by `redetect::Redetector`;
+    /// returns false if the track is not active
+    pub fn extend_track(&mut self, id: TrackId, corner: &SaeEvent) -> bool {
+        self.active.contains(&id) && self.store.extend_track(id, corner.clone())
+    }
+
+    /// the active tracks that a corner at `timestamp` may still extend
+    fn open_tracks(&self, timestamp: SaeTime) -> impl Iterator<Item = &Track> {
+        let max_gap = self.config.max_gap;
+        self.active.iter()
+            .filter_map(move |&id| self.store.get(id))
+            .filter(move |track| timestamp.saturating_sub(track.last().timestamp) <= max_gap)
+    }
+
+    fn nearest_track(&self, corner: &SaeEvent, gate_scale: f32) -> Option<TrackId> {
+        let (row, col) = corner.subpixel_position();
+        let radius = self.config.match_radius * gate_scale;
+        let radius2 = radius * radius;
+        let mut best: Option<(f32, TrackId)> = None;
+        for track in self.open_tracks(corner.timestamp) {
+            let last = track.last();
+            let (lrow, lcol) = last.subpixel_position();
+            let d2 = (lrow - row).powi(2) + (lcol - col).powi(2);
+            if d2 > radius2 || (self.config.min_likeness > 0.0 && corner.likeness(last) < self.config.min_likeness) {
+                continue;
+            }
+            if best.is_none_or(|(best_d2, _)| d2 < best_d2) {
+                best = Some((d2, track.id));
+            }
+        }
+        best.map(|(_, id)| id)
+    }
+
+    /// mean velocity (pixels per microsecond) of the open tracks moving near `position`
+    fn local_flow(&self, predictor: &CornerPredictor, position: [f32; 2], radius: f32, timestamp: SaeTime) -> Option<[f32; 2]> {
+        let mut sum = [0.0f32; 2];
+        let mut count = 0;
+        for track in self.open_tracks(timestamp) {
+            let (row, col) = track.last().subpixel_position();
+            if (col - position[0]).hypot(row - position[1]) > radius {
+                continue;
+            }
+            if let Some(velocity) = predictor.velocity(track) {
+                sum[0] += velocity[0];
+                sum[1] += velocity[1];
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return None;
+        }
+        Some([sum[0] / count as f32, sum[1] / count as f32])
+    }
+
+    fn predicted_track(&self, corner: &SaeEvent, geometric: &GeometricConfig, gate_scale: f32) -> Option<TrackId> {
+        let predictor = CornerPredictor::new(geometric.prediction.clone());
+        let (row, col) = corner.subpixel_position();
+        let mut best: Option<(f32, TrackId)> = None;
+        for track in self.open_tracks(corner.timestamp) {
+            let (position, radius) = match predictor.predict_at(track, corner.timestamp, None) {
+                Some(prediction) => (prediction.position, prediction.radius),
+                None => {
+                    let (lrow, lcol) = track.last().subpixel_position();
+                    let dt = corner.timestamp.saturating_sub(track.last().timestamp) as f32;
+                    let flow = self.local_flow(&predictor, [lcol, lrow], geometric.flow_radius, corner.timestamp)
+                        .unwrap_or([0.0, 0.0]);
+                    ([lcol + flow[0] * dt, lrow + flow[1] * dt], self.config.match_radius)
+                }
+            };
+            let distance = (col - position[0]).hypot(row - position[1]);
+            if distance <= radius * gate_scale && best.is_none_or(|(best_distance, _)| distance < best_distance) {
+                best = Some((distance, track.id));
+            }
+        }
+        best.map(|(_, id)| id)
+    }
+
+    /// End the tracks not extended within the maximum gap before `now`,
+    /// notifying `observer` and removing them from the store
+    pub fn retire(&mut self, now: SaeTime, observer: &mut dyn TrackObserver) -> Vec<TrackId> {
+        let max_gap = self.config.max_gap;
+        let store = &self.store;
+        let (expired, active): (Vec<TrackId>, Vec<TrackId>) = self.active.iter()
+            .partition(|&&id| store.get(id).is_none_or(|track| now.saturating_sub(track.last().timestamp) > max_gap));
+        self.active = active;
+        for &id in expired.iter() {
+            self.store.end_track(id, observer);
+        }
+        expired
+    }
+
+    /// Remove a track, eg one rejected as an outlier
+    pub fn drop_track(&mut self, id: TrackId) -> Option<Track> {
+        self.active.retain(|&active| active != id);
+        self.store.remove(id)
+    }
+}
+
+/// Consuming the corners of a detector directly: each corner is added, then the
+/// tracks it leaves stale, beyond the maximum gap, are retired and removed.
+/// To keep the history of ended tracks, call `add_corner` and `retire` instead.
+impl CornerSink for CornerTracker {
+    fn accept(&mut self, corner: &SaeEvent) {
+        self.add_corner(corner);
+        self.retire(corner.timestamp, &mut ());
+    }
+}
+
+impl TrackObserver for () {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corner_at(row: u16, col: u16, timestamp: SaeTime) -> SaeEvent {
+        SaeEvent { row, col, timestamp, ..SaeEvent::default() }
+    }
+
+    #[test]
+    fn test_resample() {
+        let mut store = TrackStore::new();
+        let a = store.start_track(corner_at(10, 0, 1_500));
+        store.extend_track(a, corner_at(10, 10, 3_500));
+        store.extend_track(a, corner_at(20, 10, 5_500));
+        let b = store.start_track(corner_at(0, 0, 4_000));
+        store.extend_track(b, corner_at(0, 4, 6_000));
+
+        let track = store.get(a).unwrap();
+        let samples = track.resample(1_000);
+        let times: Vec<SaeTime> = samples.iter().map(|s| s.timestamp).collect();
+        assert_eq!(times, vec![2_000, 3_000, 4_000, 5_000]);
+        assert_eq!(samples[0].position, [2.5, 10.0]);
+        assert_eq!(samples[3].position, [10.0, 17.5]);
+        assert_eq!(track.velocity_at(2_000), Some([5_000.0, 0.0]));
+        assert_eq!(track.velocity_at(3_500), Some([0.0, 5_000.0]));
+        assert_eq!(track.velocity_at(6_000), None);
+
+        let snapshots = store.resample(3_000, 6_000, 1_000);
+        assert_eq!(snapshots.len(), 4);
+        assert_eq!(snapshots[0].positions, vec![(a, [7.5, 10.0])]);
+        assert_eq!(snapshots[1].positions, vec![(a, [10.0, 12.5]), (b, [0.0, 0.0])]);
+        assert_eq!(snapshots[3].positions, vec![(b, [4.0, 0.0])]);
+    }
+
+    #[test]
+    fn test_track_store() {
+        let mut store = TrackStore::new();
+        let a = store.start_track(corner_at(1, 1, 10));
+        let b = store.start_track(corner_at(5, 5, 12));
+        assert_ne!(a, b);
+        assert!(store.extend_track(a, corner_at(1, 2, 30)));
+        assert!(!store.extend_track(99, corner_at(1, 2, 30)));
+
+        let track = store.get(a).unwrap();
+        assert_eq!(track.len(), 2);
+        assert_eq!(track.lifetime(), 20);
+        assert_eq!(track.last().col, 2);
+
+        assert_eq!(track.position_at(20), Some([1.5, 1.0]));
+        assert_eq!(track.position_at(31), None);
+
+        let ids: Vec<TrackId> = store.iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![a, b]);
+        assert!(store.remove(b).is_some());
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_corner_tracker() {
+        let mut tracker = CornerTracker::new(TrackerConfig::default());
+        let a = tracker.add_corner(&corner_at(10, 10, 0));
+        let b = tracker.add_corner(&corner_at(30, 30, 5));
+        assert_eq!(tracker.add_corner(&corner_at(11, 12, 1_000)), a);
+        assert_eq!(tracker.add_corner(&corner_at(31, 29, 1_500)), b);
+        // too far from any track
+        let c = tracker.add_corner(&corner_at(50, 10, 2_000));
+        assert_eq!(tracker.active(), &[a, b, c]);
+        assert_eq!(tracker.store().get(a).unwrap().len(), 2);
+
+        // a corner near track a, but after the maximum gap
+        let d = tracker.add_corner(&corner_at(12, 12, 25_000));
+        assert_ne!(d, a);
+        let retired = tracker.retire(25_000, &mut ());
+        assert_eq!(retired, vec![a, b, c]);
+        assert_eq!(tracker.active(), &[d]);
+        assert_eq!(tracker.store().len(), 1);
+        assert!(tracker.drop_track(d).is_some());
+        assert!(tracker.active().is_empty());
+    }
+
+    #[test]
+    fn test_tracker_as_sink() {
+        let mut tracker = CornerTracker::new(TrackerConfig::default());
+        let corners = [corner_at(10, 10, 0), corner_at(30, 30, 5), corner_at(11, 11, 15_000), corner_at(12, 12, 30_000)];
+        for corner in corners.iter() {
+            tracker.accept(corner);
+        }
+        // the track at (30, 30) went stale and was pruned
+        assert_eq!(tracker.active().len(), 1);
+        let track = tracker.store().get(tracker.active()[0]).unwrap();
+        assert_eq!(track.len(), 3);
+        assert_eq!(track.age_at(40_000), 40_000);
+    }
+
+    #[test]
+    fn test_geometric_matching() {
+        let geometric = TrackerConfig { match_radius: 5.0, mode: MatchMode::Geometric(GeometricConfig::default()), ..TrackerConfig::default() };
+        let proximity = TrackerConfig { match_radius: 5.0, ..TrackerConfig::default() };
+        let mut trackers = [CornerTracker::new(geometric), CornerTracker::new(proximity)];
+        let mut ids = Vec::new();
+        for tracker in trackers.iter_mut() {
+            // a feature moving 4 pixels per millisecond
+            let a = tracker.add_corner(&corner_at(10, 10, 0));
+            for step in 1..4u16 {
+                assert_eq!(tracker.add_corner(&corner_at(10, 10 + 4 * step, step as SaeTime * 1_000)), a);
+            }
+            // a new feature nearby, moving a little faster
+            let b = tracker.add_corner(&corner_at(20, 10, 2_500));
+            let b_next = tracker.add_corner(&corner_at(20, 16, 3_500));
+            // a corner where the first feature was, rather than where it went
+            let stale = tracker.add_corner(&corner_at(10, 22, 4_000));
+            ids.push((a, b, b_next, stale));
+        }
+        let (a, b, b_next, stale) = ids[0];
+        // the local flow carries the new track along, and the stale corner misses the prediction gate
+        assert_eq!(b_next, b);
+        assert_ne!(stale, a);
+        let (a, b, b_next, stale) = ids[1];
+        assert_ne!(b_next, b);
+        assert_eq!(stale, a);
+    }
+}
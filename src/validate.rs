@@ -0,0 +1,157 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Invariant checks for the Arc* detector, written for property-based tests.
+//!
+//! Each check takes an input a property test can generate (ring timestamps, an SAE
+//! and an event, a descriptor) and returns the first violation it finds. The arc
+//! decision depends only on the order of the ring timestamps, so it must not change
+//! when the ring is rotated or when all timestamps are shifted by the same offset.
+//! Ties between timestamps are broken by position in the ring, so rotation
+//! invariance only holds for rings of distinct timestamps.
+
+use crate::circle::CircleSpec;
+use crate::detector::{detect_and_compute_one, is_arc_valid};
+use crate::sae_types::*;
+
+
+/// An invariant that does not hold for some input
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub invariant: &'static str,
+    pub detail: String,
+}
+
+fn violation(invariant: &'static str, detail: String) -> Result<(), Violation> {
+    Err(Violation { invariant, detail })
+}
+
+/// Whether no two ring timestamps are equal, the precondition of `check_rotation_invariance`
+pub fn distinct_timestamps(vals: &[SaeTime]) -> bool {
+    let mut sorted = vals.to_vec();
+    sorted.sort_unstable();
+    sorted.windows(2).all(|w| w[0] != w[1])
+}
+
+/// The arc decision on `vals` is the same for every circular shift of `vals`
+pub fn check_rotation_invariance(vals: &[SaeTime], ring: &CircleSpec) -> Result<(), Violation> {
+    if vals.len() != ring.len() {
+        return violation("ring length", format!("{} values for a ring of {}", vals.len(), ring.len()));
+    }
+    let expected = is_arc_valid(vals, ring);
+    let mut rotated = vals.to_vec();
+    for shift in 1..vals.len() {
+        rotated.rotate_left(1);
+        if is_arc_valid(&rotated, ring) != expected {
+            return violation("rotation invariance", format!("decision {} changed by shift {} of {:?}", expected, shift, vals));
+        }
+    }
+    Ok(())
+}
+
+/// The arc decision on `vals` is unchanged when every observed (non-zero) timestamp
+/// is moved later by `offset`
+pub fn check_ring_offset_invariance(vals: &[SaeTime], ring: &CircleSpec, offset: SaeTime) -> Result<(), Violation> {
+    let shifted: Vec<SaeTime> = vals.iter().map(|&t| if t > 0 { t.saturating_add(offset) } else { 0 }).collect();
+    let expected = is_arc_valid(vals, ring);
+    if is_arc_valid(&shifted, ring) != expected {
+        return violation("timestamp offset invariance", format!("decision {} changed by offset {} of {:?}", expected, offset, vals));
+    }
+    Ok(())
+}
+
+/// Detection of `evt` on `sae_pol` is unchanged when the event and every observed
+/// pixel of the surface are moved later by `offset`
+pub fn check_detection_offset_invariance(sae_pol: &SaeMatrix, evt: &SaeEvent, offset: SaeTime) -> Result<(), Violation> {
+    let shifted_sae = sae_pol.map(|t| if t > 0 { t.saturating_add(offset) } else { 0 });
+    let shifted_evt = SaeEvent { timestamp: evt.timestamp.saturating_add(offset), ..evt.clone() };
+    let expected = detect_and_compute_one(sae_pol, evt).is_some();
+    if detect_and_compute_one(&shifted_sae, &shifted_evt).is_some() != expected {
+        return violation("timestamp offset invariance",
+            format!("detection {} at ({}, {}) changed by offset {}", expected, evt.row, evt.col, offset));
+    }
+    Ok(())
+}
+
+/// Descriptor elements lie in 0..=1, and the freshest element of one of the rings
+/// (the first element of the C3 or C4 part) is 1
+pub fn check_descriptor_range(desc: &NormDescriptor) -> Result<(), Violation> {
+    if let Some(idx) = desc.iter().position(|val| !(0.0..=1.0).contains(val)) {
+        return violation("descriptor range", format!("element {} is {}", idx, desc[idx]));
+    }
+    if desc[0] != 1.0 && desc[DESCRIPTOR_C3_LEN] != 1.0 {
+        return violation("descriptor normalization",
+            format!("ring heads are {} and {}", desc[0], desc[DESCRIPTOR_C3_LEN]));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    /// random distinct timestamps, some of them forming a fresh contiguous arc
+    fn random_ring(rng: &mut StdRng, len: usize) -> Vec<SaeTime> {
+        let mut vals: Vec<SaeTime> = (1..=len as SaeTime).map(|t| t * 100).collect();
+        vals.shuffle(rng);
+        let start = rng.gen_range(0, len);
+        let arc = rng.gen_range(1, len);
+        for k in 0..arc {
+            vals[(start + k) % len] += 10_000 + k as SaeTime;
+        }
+        vals
+    }
+
+    #[test]
+    fn test_ring_invariants() {
+        let mut rng = StdRng::seed_from_u64(5);
+        for ring in [CircleSpec::c3(), CircleSpec::c4()].iter() {
+            let mut corners = 0;
+            for _ in 0..500 {
+                let vals = random_ring(&mut rng, ring.len());
+                assert!(distinct_timestamps(&vals));
+                check_rotation_invariance(&vals, ring).unwrap();
+                check_ring_offset_invariance(&vals, ring, rng.gen_range(1, 1_000_000)).unwrap();
+                if is_arc_valid(&vals, ring) {
+                    corners += 1;
+                }
+            }
+            // the generator exercises both decisions
+            assert!(corners > 0 && corners < 500);
+        }
+    }
+
+    #[test]
+    fn test_detection_invariants() {
+        let mut rng = StdRng::seed_from_u64(9);
+        for _ in 0..200 {
+            let mut sae = SaeMatrix::zeros(12, 12);
+            for val in sae.iter_mut() {
+                if rng.gen::<f32>() < 0.7 {
+                    *val = rng.gen_range(1, 1_000);
+                }
+            }
+            let evt = SaeEvent { row: 6, col: 6, timestamp: 1_000, ..SaeEvent::default() };
+            sae[(6, 6)] = evt.timestamp;
+            check_detection_offset_invariance(&sae, &evt, rng.gen_range(1, 1_000_000)).unwrap();
+            if let Some(corner) = detect_and_compute_one(&sae, &evt) {
+                check_descriptor_range(corner.norm_descriptor.as_ref().unwrap()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_reports_violations() {
+        assert!(!distinct_timestamps(&[1, 2, 1]));
+        let err = check_rotation_invariance(&[1, 2, 3], &CircleSpec::c3()).unwrap_err();
+        assert_eq!(err.invariant, "ring length");
+        let mut desc = [0.5; NORM_DESCRIPTOR_LEN];
+        assert_eq!(check_descriptor_range(&desc).unwrap_err().invariant, "descriptor normalization");
+        desc[3] = 1.5;
+        assert_eq!(check_descriptor_range(&desc).unwrap_err().invariant, "descriptor range");
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Read-only SAE views over timestamp buffers owned elsewhere, so the detector can
+//! run directly over another framework's surface without copying it into a
+//! `SaeMatrix`: a GPU-mapped buffer, a C-owned array, a memory-mapped file.
+//!
+//! `ExternalSaeView` reads timestamps of any supported element type and byte order,
+//! laid out with arbitrary row and column strides (row-major, column-major, padded
+//! rows). The layout is validated once, when the view is made: every element must be
+//! aligned to its size and lie inside the buffer, so reads need no further checks.
+//! The detector functions taking `SaeView` (eg `detector::detect_and_compute_one`)
+//! accept either a view or a `SaeMatrix`.
+
+use core::error::Error;
+use core::fmt;
+
+use crate::sae_types::*;
+
+
+/// Read access to a surface of event timestamps
+pub trait SaeView {
+    /// (rows, columns)
+    fn shape(&self) -> (usize, usize);
+    /// the timestamp at a pixel inside the shape
+    fn timestamp(&self, row: usize, col: usize) -> SaeTime;
+}
+
+#[cfg(feature = "std")]
+impl SaeView for SaeMatrix {
+    fn shape(&self) -> (usize, usize) {
+        SaeMatrix::shape(self)
+    }
+
+    fn timestamp(&self, row: usize, col: usize) -> SaeTime {
+        self[(row, col)]
+    }
+}
+
+/// How timestamps are stored in an external buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampFormat {
+    U32,
+    /// wider timestamps saturate at the largest `SaeTime`
+    U64,
+    /// floating point microseconds, eg from a float texture; negative values read as zero
+    F32,
+}
+
+impl TimestampFormat {
+    /// size (and required alignment) of one element, in bytes
+    pub fn size(self) -> usize {
+        match self {
+            TimestampFormat::U32 | TimestampFormat::F32 => 4,
+            TimestampFormat::U64 => 8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    /// the byte order of this machine
+    pub fn native() -> Self {
+        if cfg!(target_endian = "big") { ByteOrder::Big } else { ByteOrder::Little }
+    }
+}
+
+/// Where each element of an external buffer is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ViewLayout {
+    pub format: TimestampFormat,
+    pub order: ByteOrder,
+    /// bytes from one row to the next
+    pub row_stride: usize,
+    /// bytes from one column to the next
+    pub col_stride: usize,
+}
+
+impl ViewLayout {
+    /// densely packed rows of `ncols` elements
+    pub fn row_major(ncols: usize, format: TimestampFormat, order: ByteOrder) -> Self {
+        ViewLayout { format, order, row_stride: ncols * format.size(), col_stride: format.size() }
+    }
+
+    /// densely packed columns of `nrows` elements, as in a `SaeMatrix`
+    pub fn column_major(nrows: usize, format: TimestampFormat, order: ByteOrder) -> Self {
+        ViewLayout { format, order, row_stride: format.size(), col_stride: nrows * format.size() }
+    }
+}
+
+/// Why a buffer can't be viewed with a layout
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViewError {
+    /// the buffer start or a stride is not a multiple of the element size
+    Misaligned,
+    /// a stride is smaller than an element
+    BadStride,
+    /// the last element would end past the end of the buffer
+    OutOfBounds,
+}
+
+impl fmt::Display for ViewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ViewError::Misaligned => write!(f, "SAE view elements are misaligned"),
+            ViewError::BadStride => write!(f, "SAE view stride is smaller than an element"),
+            ViewError::OutOfBounds => write!(f, "SAE view extends past its buffer"),
+        }
+    }
+}
+
+impl Error for ViewError {}
+
+/// A read-only SAE over a borrowed timestamp buffer
+#[derive(Clone, Copy, Debug)]
+pub struct ExternalSaeView<'a> {
+    bytes: &'a [u8],
+    nrows: usize,
+    ncols: usize,
+    layout: ViewLayout,
+}
+
+impl<'a> ExternalSaeView<'a> {
+    /// View `bytes` as `nrows` by `ncols` timestamps placed as `layout` says
+    pub fn new(bytes: &'a [u8], nrows: usize, ncols: usize, layout: ViewLayout) -> Result<Self, ViewError> {
+        let size = layout.format.size();
+        if !(bytes.as_ptr() as usize).is_multiple_of(size) ||
+            !layout.row_stride.is_multiple_of(size) || !layout.col_stride.is_multiple_of(size) {
+            return Err(ViewError::Misaligned);
+        }
+        if (nrows > 1 && layout.row_stride < size) || (ncols > 1 && layout.col_stride < size) {
+            return Err(ViewError::BadStride);
+        }
+        if nrows > 0 && ncols > 0 {
+            let end = (nrows - 1).checked_mul(layout.row_stride)
+                .and_then(|offset| offset.checked_add((ncols - 1).checked_mul(layout.col_stride)?))
+                .and_then(|offset| offset.checked_add(size));
+            if end.is_none_or(|end| end > bytes.len()) {
+                return Err(ViewError::OutOfBounds);
+            }
+        }
+        Ok(ExternalSaeView { bytes, nrows, ncols, layout })
+    }
+
+    /// View a buffer owned by foreign code, eg a C array or mapped GPU memory.
+    ///
+    /// # Safety
+    /// `ptr` must be valid for reads of `len` bytes for the lifetime `'a`,
+    /// and the memory must not be written while the view exists.
+    pub unsafe fn from_raw_parts(ptr: *const u8, len: usize, nrows: usize, ncols: usize, layout: ViewLayout)
+        -> Result<Self, ViewError>
+    {
+        Self::new(core::slice::from_raw_parts(ptr, len), nrows, ncols, layout)
+    }
+
+    pub fn layout(&self) -> &ViewLayout {
+        &self.layout
+    }
+
+    /// Copy the view into a `SaeMatrix`, eg for the functions that need one
+    #[cfg(feature = "std")]
+    pub fn to_matrix(&self) -> SaeMatrix {
+        SaeMatrix::from_fn(self.nrows, self.ncols, |row, col| self.timestamp(row, col))
+    }
+}
+
+impl<'a> SaeView for ExternalSaeView<'a> {
+    fn shape(&self) -> (usize, usize) {
+        (self.nrows, self.ncols)
+    }
+
+    fn timestamp(&self, row: usize, col: usize) -> SaeTime {
+        debug_assert!(row < self.nrows && col < self.ncols);
+        let offset = row * self.layout.row_stride + col * self.layout.col_stride;
+        let big = self.layout.order == ByteOrder::Big;
+        match self.layout.format {
+            TimestampFormat::U32 => {
+                let bytes = [self.bytes[offset], self.bytes[offset + 1], self.bytes[offset + 2], self.bytes[offset + 3]];
+                if big { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+            }
+            TimestampFormat::F32 => {
+                let bytes = [self.bytes[offset], self.bytes[offset + 1], self.bytes[offset + 2], self.bytes[offset + 3]];
+                let value = if big { f32::from_be_bytes(bytes) } else { f32::from_le_bytes(bytes) };
+                // saturating, with NaN as zero
+                value as SaeTime
+            }
+            TimestampFormat::U64 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&self.bytes[offset..offset + 8]);
+                let value = if big { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) };
+                value.min(SaeTime::MAX as u64) as SaeTime
+            }
+        }
+    }
+}
+
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::detector::{detect_and_compute_one, ring_descriptor};
+
+    /// a zeroed buffer of `len` bytes starting at an 8-byte boundary
+    fn aligned(storage: &mut Vec<u8>, len: usize) -> &mut [u8] {
+        *storage = vec![0; len + 8];
+        let skip = (8 - storage.as_ptr() as usize % 8) % 8;
+        &mut storage[skip..skip + len]
+    }
+
+    fn corner_surface() -> SaeMatrix {
+        let mut sae = SaeMatrix::zeros(16, 20);
+        for row in 6..11 {
+            for col in 6..11 {
+                sae[(row, col)] = 7;
+            }
+        }
+        sae[(10, 10)] = 9;
+        sae
+    }
+
+    #[test]
+    fn test_strided_views_match_matrix() {
+        let sae = corner_surface();
+        let (nrows, ncols) = sae.shape();
+        let evt = SaeEvent { row: 10, col: 10, timestamp: 9, ..SaeEvent::default() };
+        let expected = detect_and_compute_one(&sae, &evt).unwrap();
+
+        // big-endian u32 rows padded to 24 columns
+        let mut storage = Vec::new();
+        let layout = ViewLayout { format: TimestampFormat::U32, order: ByteOrder::Big, row_stride: 24 * 4, col_stride: 4 };
+        let buf = aligned(&mut storage, nrows * 24 * 4);
+        for row in 0..nrows {
+            for col in 0..ncols {
+                let offset = row * layout.row_stride + col * 4;
+                buf[offset..offset + 4].copy_from_slice(&sae[(row, col)].to_be_bytes());
+            }
+        }
+        let view = ExternalSaeView::new(buf, nrows, ncols, layout).unwrap();
+        assert_eq!(view.to_matrix(), sae);
+        let corner = detect_and_compute_one(&view, &evt).unwrap();
+        assert_eq!(corner, expected);
+        assert_eq!(corner.norm_descriptor, expected.norm_descriptor);
+        assert_eq!(ring_descriptor(&view, 10, 10), ring_descriptor(&sae, 10, 10));
+
+        // little-endian u64, column-major
+        let mut storage = Vec::new();
+        let layout = ViewLayout::column_major(nrows, TimestampFormat::U64, ByteOrder::Little);
+        let buf = aligned(&mut storage, nrows * ncols * 8);
+        for (idx, value) in sae.iter().enumerate() {
+            buf[idx * 8..idx * 8 + 8].copy_from_slice(&(*value as u64).to_le_bytes());
+        }
+        let view = ExternalSaeView::new(buf, nrows, ncols, layout).unwrap();
+        assert_eq!(detect_and_compute_one(&view, &evt), Some(expected));
+    }
+
+    #[test]
+    fn test_layout_validation() {
+        let mut storage = Vec::new();
+        let buf = aligned(&mut storage, 64);
+        let layout = ViewLayout::row_major(4, TimestampFormat::U32, ByteOrder::native());
+        assert!(ExternalSaeView::new(buf, 4, 4, layout).is_ok());
+        assert_eq!(ExternalSaeView::new(buf, 5, 4, layout).err(), Some(ViewError::OutOfBounds));
+        assert_eq!(ExternalSaeView::new(&buf[1..], 2, 2, layout).err(), Some(ViewError::Misaligned));
+        let odd = ViewLayout { row_stride: 6, ..layout };
+        assert_eq!(ExternalSaeView::new(buf, 2, 2, odd).err(), Some(ViewError::Misaligned));
+        let overlapping = ViewLayout { col_stride: 0, ..layout };
+        assert_eq!(ExternalSaeView::new(buf, 2, 2, overlapping).err(), Some(ViewError::BadStride));
+        let wide = ViewLayout::row_major(4, TimestampFormat::U64, ByteOrder::Big);
+        assert_eq!(ExternalSaeView::new(&buf[4..], 1, 1, wide).err(), Some(ViewError::Misaligned));
+
+        let mut storage = Vec::new();
+        let buf = aligned(&mut storage, 16);
+        buf[..8].copy_from_slice(&(1u64 << 40).to_be_bytes());
+        buf[8..12].copy_from_slice(&1234.5f32.to_le_bytes());
+        let view = ExternalSaeView::new(&buf[..8], 1, 1, wide).unwrap();
+        assert_eq!(view.timestamp(0, 0), SaeTime::MAX);
+        let float = ViewLayout::row_major(1, TimestampFormat::F32, ByteOrder::Little);
+        let view = ExternalSaeView::new(&buf[8..], 1, 1, float).unwrap();
+        assert_eq!(view.timestamp(0, 0), 1234);
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! IMU preintegration between key slices (Forster et al., "On-Manifold
+//! Preintegration for Real-Time Visual-Inertial Odometry", 2017).
+//!
+//! Gyro and accelerometer samples between two key slices are integrated once into
+//! relative rotation, velocity and position increments that don't depend on the
+//! absolute state, along with their covariance and first-order Jacobians with respect
+//! to the sensor biases. An optimizer can then use the increments as a factor between
+//! the two key-slice states, and apply small bias updates without reintegrating.
+
+use nalgebra::{Matrix3, MatrixN, UnitQuaternion, Vector3, U3, U9};
+
+use crate::sae_types::*;
+
+
+/// One IMU measurement, in the IMU body frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImuSample {
+    /// in SAE time units (microseconds)
+    pub timestamp: SaeTime,
+    /// angular rate, rad/s
+    pub gyro: [f64; 3],
+    /// specific force, m/s^2
+    pub accel: [f64; 3],
+}
+
+/// Sensor biases, subtracted from the raw measurements
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImuBias {
+    pub gyro: [f64; 3],
+    pub accel: [f64; 3],
+}
+
+/// Continuous-time white noise densities of the sensors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImuNoise {
+    /// rad/s/sqrt(Hz)
+    pub gyro_density: f64,
+    /// m/s^2/sqrt(Hz)
+    pub accel_density: f64,
+}
+
+impl Default for ImuNoise {
+    fn default() -> Self {
+        // typical of the MEMS IMUs built into event cameras
+        ImuNoise {
+            gyro_density: 1.7e-4,
+            accel_density: 2.0e-3,
+        }
+    }
+}
+
+/// Rotation, velocity and position of the body in the world frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NavState {
+    pub rotation: UnitQuaternion<f64>,
+    pub velocity: Vector3<f64>,
+    pub position: Vector3<f64>,
+}
+
+fn skew(v: &Vector3<f64>) -> Matrix3<f64> {
+    Matrix3::new(
+        0.0, -v.z, v.y,
+        v.z, 0.0, -v.x,
+        -v.y, v.x, 0.0)
+}
+
+/// Right Jacobian of SO(3)
+fn right_jacobian(phi: &Vector3<f64>) -> Matrix3<f64> {
+    let theta = phi.norm();
+    let k = skew(phi);
+    if theta < 1e-8 {
+        return Matrix3::identity() - 0.5 * k;
+    }
+    let theta2 = theta * theta;
+    Matrix3::identity() - (1.0 - theta.cos()) / theta2 * k + (theta - theta.sin()) / (theta2 * theta) * k * k
+}
+
+/// Preintegrated increments between two times
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preintegrated {
+    pub start: SaeTime,
+    pub end: SaeTime,
+    /// the biases the increments were integrated with
+    pub bias: ImuBias,
+    pub delta_rotation: UnitQuaternion<f64>,
+    pub delta_velocity: Vector3<f64>,
+    pub delta_position: Vector3<f64>,
+    /// covariance of the rotation, velocity and position increment errors, in that order
+    pub covariance: MatrixN<f64, U9>,
+    pub d_rotation_d_gyro_bias: Matrix3<f64>,
+    pub d_velocity_d_gyro_bias: Matrix3<f64>,
+    pub d_velocity_d_accel_bias: Matrix3<f64>,
+    pub d_position_d_gyro_bias: Matrix3<f64>,
+    pub d_position_d_accel_bias: Matrix3<f64>,
+}
+
+impl Preintegrated {
+    fn new(start: SaeTime, bias: ImuBias) -> Self {
+        Preintegrated {
+            start,
+            end: start,
+            bias,
+            delta_rotation: UnitQuaternion::identity(),
+            delta_velocity: Vector3::zeros(),
+            delta_position: Vector3::zeros(),
+            covariance: MatrixN::<f64, U9>::zeros(),
+            d_rotation_d_gyro_bias: Matrix3::zeros(),
+            d_velocity_d_gyro_bias: Matrix3::zeros(),
+            d_velocity_d_accel_bias: Matrix3::zeros(),
+            d_position_d_gyro_bias: Matrix3::zeros(),
+            d_position_d_accel_bias: Matrix3::zeros(),
+        }
+    }
+
+    /// integrated time, in seconds
+    pub fn duration(&self) -> f64 {
+        (self.end - self.start) as f64 * 1e-6
+    }
+
+    /// Integrate one constant measurement over `dt` seconds
+    fn integrate(&mut self, gyro: &Vector3<f64>, accel: &Vector3<f64>, dt: f64, noise: &ImuNoise) {
+        let omega = gyro - Vector3::from(self.bias.gyro);
+        let acc = accel - Vector3::from(self.bias.accel);
+        let rot = self.delta_rotation.to_rotation_matrix().into_inner();
+        let acc_skew = skew(&acc);
+        let phi = omega * dt;
+        let increment = UnitQuaternion::from_scaled_axis(phi);
+        let increment_t = increment.to_rotation_matrix().into_inner().transpose();
+        let jr = right_jacobian(&phi);
+
+        // propagate the error covariance
+        let mut a = MatrixN::<f64, U9>::identity();
+        a.fixed_slice_mut::<U3, U3>(0, 0).copy_from(&increment_t);
+        a.fixed_slice_mut::<U3, U3>(3, 0).copy_from(&(-rot * acc_skew * dt));
+        a.fixed_slice_mut::<U3, U3>(6, 0).copy_from(&(-0.5 * rot * acc_skew * dt * dt));
+        a.fixed_slice_mut::<U3, U3>(6, 3).copy_from(&(Matrix3::identity() * dt));
+        let gyro_var = noise.gyro_density * noise.gyro_density / dt;
+        let accel_var = noise.accel_density * noise.accel_density / dt;
+        let bg = jr * dt;
+        let bv = rot * dt;
+        let bp = 0.5 * rot * dt * dt;
+        let mut q = MatrixN::<f64, U9>::zeros();
+        q.fixed_slice_mut::<U3, U3>(0, 0).copy_from(&(bg * bg.transpose() * gyro_var));
+        q.fixed_slice_mut::<U3, U3>(3, 3).copy_from(&(bv * bv.transpose() * accel_var));
+        q.fixed_slice_mut::<U3, U3>(3, 6).copy_from(&(bv * bp.transpose() * accel_var));
+        q.fixed_slice_mut::<U3, U3>(6, 3).copy_from(&(bp * bv.transpose() * accel_var));
+        q.fixed_slice_mut::<U3, U3>(6, 6).copy_from(&(bp * bp.transpose() * accel_var));
+        self.covariance = a * self.covariance * a.transpose() + q;
+
+        // bias Jacobians, using the values before this step
+        self.d_position_d_accel_bias += self.d_velocity_d_accel_bias * dt - 0.5 * rot * dt * dt;
+        self.d_position_d_gyro_bias += self.d_velocity_d_gyro_bias * dt - 0.5 * rot * acc_skew * self.d_rotation_d_gyro_bias * dt * dt;
+        self.d_velocity_d_accel_bias -= rot * dt;
+        self.d_velocity_d_gyro_bias -= rot * acc_skew * self.d_rotation_d_gyro_bias * dt;
+        self.d_rotation_d_gyro_bias = increment_t * self.d_rotation_d_gyro_bias - jr * dt;
+
+        // the increments themselves
+        self.delta_position += self.delta_velocity * dt + 0.5 * rot * acc * dt * dt;
+        self.delta_velocity += rot * acc * dt;
+        self.delta_rotation *= increment;
+    }
+
+    /// The increments for slightly different biases, to first order
+    pub fn corrected(&self, bias: &ImuBias) -> (UnitQuaternion<f64>, Vector3<f64>, Vector3<f64>) {
+        let dbg = Vector3::from(bias.gyro) - Vector3::from(self.bias.gyro);
+        let dba = Vector3::from(bias.accel) - Vector3::from(self.bias.accel);
+        let rotation = self.delta_rotation * UnitQuaternion::from_scaled_axis(self.d_rotation_d_gyro_bias * dbg);
+        let velocity = self.delta_velocity + self.d_velocity_d_gyro_bias * dbg + self.d_velocity_d_accel_bias * dba;
+        let position = self.delta_position + self.d_position_d_gyro_bias * dbg + self.d_position_d_accel_bias * dba;
+        (rotation, velocity, position)
+    }
+
+    /// Predict the state at the end time from the state at the start time
+    pub fn predict(&self, start: &NavState, gravity: &Vector3<f64>) -> NavState {
+        let dt = self.duration();
+        NavState {
+            rotation: start.rotation * self.delta_rotation,
+            velocity: start.velocity + gravity * dt + start.rotation * self.delta_velocity,
+            position: start.position + start.velocity * dt + 0.5 * gravity * dt * dt + start.rotation * self.delta_position,
+        }
+    }
+}
+
+/// Integrates IMU samples as they arrive
+pub struct Preintegrator {
+    noise: ImuNoise,
+    current: Preintegrated,
+    last: Option<ImuSample>,
+}
+
+impl Preintegrator {
+    /// Start integrating at time `start` with the given bias estimate
+    pub fn new(start: SaeTime, bias: ImuBias, noise: ImuNoise) -> Self {
+        Preintegrator {
+            noise,
+            current: Preintegrated::new(start, bias),
+            last: None,
+        }
+    }
+
+    /// Add a sample; each sample's measurement holds until the next sample.
+    /// Samples before the current end time are ignored.
+    pub fn add_sample(&mut self, sample: &ImuSample) {
+        if sample.timestamp < self.current.end {
+            return;
+        }
+        if let Some(last) = self.last {
+            let dt = (sample.timestamp - self.current.end) as f64 * 1e-6;
+            if dt > 0.0 {
+                self.current.integrate(&Vector3::from(last.gyro), &Vector3::from(last.accel), dt, &self.noise);
+            }
+        }
+        self.current.end = sample.timestamp;
+        self.last = Some(*sample);
+    }
+
+    /// Integrate up to `end` (eg the next key slice), returning the increments since the
+    /// start, and start a new interval there. The last sample is held until `end`.
+    pub fn split(&mut self, end: SaeTime) -> Preintegrated {
+        if let Some(last) = self.last {
+            if end > self.current.end {
+                let dt = (end - self.current.end) as f64 * 1e-6;
+                self.current.integrate(&Vector3::from(last.gyro), &Vector3::from(last.accel), dt, &self.noise);
+                self.current.end = end;
+            }
+        }
+        let next = Preintegrated::new(end.max(self.current.end), self.current.bias);
+        std::mem::replace(&mut self.current, next)
+    }
+
+    /// Use a new bias estimate from the next interval on
+    pub fn set_bias(&mut self, bias: ImuBias) {
+        self.current.bias = bias;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAVITY: [f64; 3] = [0.0, 0.0, -9.81];
+
+    /// samples at 1 kHz for a body turning about z while accelerating along its own x axis
+    fn samples(bias: &ImuBias, count: u32) -> Vec<ImuSample> {
+        (0..count)
+            .map(|k| ImuSample {
+                timestamp: k * 1_000,
+                gyro: [bias.gyro[0], bias.gyro[1], 0.5 + bias.gyro[2]],
+                accel: [1.0 + bias.accel[0], bias.accel[1], 9.81 + bias.accel[2]],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_preintegrate_and_predict() {
+        let bias = ImuBias { gyro: [0.01, -0.02, 0.005], accel: [0.1, 0.0, -0.05] };
+        let mut integrator = Preintegrator::new(0, bias, ImuNoise::default());
+        for sample in samples(&bias, 500) {
+            integrator.add_sample(&sample);
+        }
+        let pre = integrator.split(500_000);
+        assert!((pre.duration() - 0.5).abs() < 1e-12);
+        assert!((pre.delta_rotation.angle() - 0.25).abs() < 1e-9);
+
+        // start at rest, level: gravity cancels the vertical specific force
+        let start = NavState { rotation: UnitQuaternion::identity(), velocity: Vector3::zeros(), position: Vector3::zeros() };
+        let end = pre.predict(&start, &Vector3::from(GRAVITY));
+        // accelerating along a turning heading: |v| = 2 sin(w T / 2) a / w
+        assert!((end.velocity.norm() - 4.0 * 0.125f64.sin()).abs() < 1e-3);
+        assert!(end.velocity.z.abs() < 1e-9 && end.position.z.abs() < 1e-9);
+        assert!(pre.covariance[(0, 0)] > 0.0 && pre.covariance[(8, 8)] > 0.0);
+
+        // the next interval starts where the last one ended
+        let next = integrator.split(600_000);
+        assert_eq!((next.start, next.end), (500_000, 600_000));
+    }
+
+    #[test]
+    fn test_bias_correction() {
+        let truth = ImuBias { gyro: [0.002, 0.001, -0.003], accel: [0.02, -0.01, 0.03] };
+        let mut exact = Preintegrator::new(0, truth, ImuNoise::default());
+        let mut approx = Preintegrator::new(0, ImuBias::default(), ImuNoise::default());
+        for sample in samples(&truth, 200) {
+            exact.add_sample(&sample);
+            approx.add_sample(&sample);
+        }
+        let exact = exact.split(200_000);
+        let approx = approx.split(200_000);
+        let (rotation, velocity, position) = approx.corrected(&truth);
+        assert!(rotation.angle_to(&exact.delta_rotation) < 1e-6);
+        assert!((velocity - exact.delta_velocity).norm() < 1e-5);
+        assert!((position - exact.delta_position).norm() < 1e-6);
+        // without correction the velocity is noticeably off
+        assert!((approx.delta_velocity - exact.delta_velocity).norm() > 1e-3);
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Key-slice selection: the event-camera analogue of keyframes.
+//! A new key slice is taken when the tracked corners have moved far enough since
+//! the previous one to give useful parallax, when too many of its tracks have been
+//! lost, or when too much time has passed. Each key slice freezes the latest corner
+//! (with its descriptor) of every active track, so that a back end can optimize over
+//! a bounded set of slices instead of the full stream.
+
+use crate::sae_types::*;
+use crate::track::{TrackId, TrackStore};
+
+
+/// Parameters of key-slice selection
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeySliceConfig {
+    /// no key slice is taken sooner than this after the previous one
+    pub min_interval: SaeTime,
+    /// a key slice is always taken this long after the previous one
+    pub max_interval: SaeTime,
+    /// median displacement (pixels) of shared tracks that triggers a key slice
+    pub min_parallax: f32,
+    /// fraction of the previous key slice's tracks lost that triggers a key slice
+    pub max_churn: f32,
+    /// minimum number of active tracks for a key slice to be taken
+    pub min_tracks: usize,
+}
+
+impl Default for KeySliceConfig {
+    fn default() -> Self {
+        KeySliceConfig {
+            min_interval: 5_000,
+            max_interval: 200_000,
+            min_parallax: 10.0,
+            max_churn: 0.5,
+            min_tracks: 10,
+        }
+    }
+}
+
+/// Why a key slice was taken
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeySliceReason {
+    First,
+    Parallax,
+    Churn,
+    Elapsed,
+}
+
+pub type KeySliceId = u32;
+
+/// A frozen snapshot of the tracked corners
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeySlice {
+    pub id: KeySliceId,
+    pub timestamp: SaeTime,
+    pub reason: KeySliceReason,
+    /// the latest corner of each active track, in track id order
+    pub corners: Vec<(TrackId, SaeEvent)>,
+}
+
+impl KeySlice {
+    pub fn corner(&self, track: TrackId) -> Option<&SaeEvent> {
+        self.corners.binary_search_by_key(&track, |(id, _)| *id)
+            .ok()
+            .map(|idx| &self.corners[idx].1)
+    }
+}
+
+/// Decides when to take key slices
+pub struct KeySliceSelector {
+    config: KeySliceConfig,
+    last: Option<KeySlice>,
+    next_id: KeySliceId,
+}
+
+impl KeySliceSelector {
+    pub fn new(config: KeySliceConfig) -> Self {
+        KeySliceSelector {
+            config,
+            last: None,
+            next_id: 0,
+        }
+    }
+
+    /// the most recent key slice
+    pub fn last(&self) -> Option<&KeySlice> {
+        self.last.as_ref()
+    }
+
+    /// median displacement of the tracks shared with the previous key slice,
+    /// and the fraction of its tracks no longer active
+    fn parallax_and_churn(&self, previous: &KeySlice, store: &TrackStore, active: &[TrackId]) -> (f32, f32) {
+        let mut displacements: Vec<f32> = previous.corners.iter()
+            .filter(|(id, _)| active.contains(id))
+            .filter_map(|(id, corner)| {
+                let (row0, col0) = corner.subpixel_position();
+                let (row1, col1) = store.get(*id)?.last().subpixel_position();
+                Some(((row1 - row0).powi(2) + (col1 - col0).powi(2)).sqrt())
+            })
+            .collect();
+        let churn = if previous.corners.is_empty() {
+            0.0
+        } else {
+            1.0 - displacements.len() as f32 / previous.corners.len() as f32
+        };
+        if displacements.is_empty() {
+            return (0.0, churn);
+        }
+        displacements.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        (displacements[displacements.len() / 2], churn)
+    }
+
+    /// Take a key slice of the active tracks at time `now`, if one is due
+    pub fn update(&mut self, store: &TrackStore, active: &[TrackId], now: SaeTime) -> Option<KeySlice> {
+        if active.len() < self.config.min_tracks {
+            return None;
+        }
+        let reason = match self.last.as_ref() {
+            None => KeySliceReason::First,
+            Some(previous) => {
+                let elapsed = now.saturating_sub(previous.timestamp);
+                if elapsed < self.config.min_interval {
+                    return None;
+                }
+                let (parallax, churn) = self.parallax_and_churn(previous, store, active);
+                if parallax >= self.config.min_parallax {
+                    KeySliceReason::Parallax
+                } else if churn >= self.config.max_churn {
+                    KeySliceReason::Churn
+                } else if elapsed >= self.config.max_interval {
+                    KeySliceReason::Elapsed
+                } else {
+                    return None;
+                }
+            }
+        };
+        let mut corners: Vec<(TrackId, SaeEvent)> = active.iter()
+            .filter_map(|&id| store.get(id).map(|track| (id, track.last().clone())))
+            .collect();
+        corners.sort_by_key(|(id, _)| *id);
+        let slice = KeySlice { id: self.next_id, timestamp: now, reason, corners };
+        self.next_id += 1;
+        self.last = Some(slice.clone());
+        Some(slice)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_slice_triggers() {
+        let config = KeySliceConfig { min_tracks: 3, ..KeySliceConfig::default() };
+        let mut selector = KeySliceSelector::new(config);
+        let mut store = TrackStore::new();
+        let ids: Vec<TrackId> = (0..8).map(|k| store.start_track(corner_at(10.0 * k as f32, 5.0, 0))).collect();
+
+        let first = selector.update(&store, &ids, 0).unwrap();
+        assert_eq!(first.reason, KeySliceReason::First);
+        assert_eq!(first.corners.len(), 8);
+
+        // small motion: no new key slice
+        for &id in ids.iter() {
+            let x = store.get(id).unwrap().last().subpixel_position().1;
+            store.extend_track(id, corner_at(x + 3.0, 5.0, 10_000));
+        }
+        assert!(selector.update(&store, &ids, 10_000).is_none());
+
+        // enough parallax
+        for &id in ids.iter() {
+            let x = store.get(id).unwrap().last().subpixel_position().1;
+            store.extend_track(id, corner_at(x + 9.0, 5.0, 20_000));
+        }
+        let second = selector.update(&store, &ids, 20_000).unwrap();
+        assert_eq!((second.id, second.reason), (1, KeySliceReason::Parallax));
+        assert_eq!(second.corner(ids[2]).unwrap().subpixel_position(), (5.0, 32.0));
+        // the first slice keeps its frozen corners
+        assert_eq!(first.corner(ids[2]).unwrap().subpixel_position(), (5.0, 20.0));
+
+        // half the tracks lost
+        assert_eq!(selector.update(&store, &ids[4..], 30_000).unwrap().reason, KeySliceReason::Churn);
+        // nothing happening for a long time
+        assert!(selector.update(&store, &ids[4..], 100_000).is_none());
+        assert_eq!(selector.update(&store, &ids[4..], 230_000).unwrap().reason, KeySliceReason::Elapsed);
+        assert!(selector.update(&store, &ids[6..], 500_000).is_none());
+    }
+}
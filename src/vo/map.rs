@@ -0,0 +1,277 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! A landmark map for VO/SLAM back ends: landmarks (triangulated 3-D points, or
+//! bearings from a key slice where depth is not yet known), their descriptors, and
+//! the observation graph linking each landmark to the key slices and tracks that saw it.
+//!
+//! Maps are saved in a little-endian binary format:
+//! ```text
+//! header:      magic "ARCSMAP\0" | version u16 | landmark count u32 | observation count u32
+//! landmark:    id u32 | kind u8 (0 point, 1 bearing) | key slice u32 | xyz 3 x f64 |
+//!              has descriptor u8 | descriptor 36 x f32 (if present)
+//! observation: landmark u32 | key slice u32 | track u32 | x f32 | y f32
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use crate::io::decode::{DecodeError, DecodeErrorKind, OffsetReader};
+use crate::sae_types::*;
+use crate::track::TrackId;
+use crate::vo::keyslice::KeySliceId;
+
+
+const MAGIC: &[u8; 8] = b"ARCSMAP\0";
+const FORMAT_VERSION: u16 = 1;
+
+pub type LandmarkId = u32;
+
+/// Where a landmark is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LandmarkPosition {
+    /// triangulated position in world coordinates
+    Point([f64; 3]),
+    /// unit direction from the camera at a key slice, in that camera's frame
+    Bearing { key_slice: KeySliceId, direction: [f64; 3] },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Landmark {
+    pub id: LandmarkId,
+    pub position: LandmarkPosition,
+    pub descriptor: Option<Box<NormDescriptor>>,
+}
+
+/// A landmark seen as a track's corner in a key slice
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Observation {
+    pub landmark: LandmarkId,
+    pub key_slice: KeySliceId,
+    pub track: TrackId,
+    /// image position (x = column, y = row)
+    pub image: [f32; 2],
+}
+
+/// Landmarks and their observations
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Map {
+    landmarks: BTreeMap<LandmarkId, Landmark>,
+    observations: Vec<Observation>,
+    next_id: LandmarkId,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a landmark, returning its id
+    pub fn add_landmark(&mut self, position: LandmarkPosition, descriptor: Option<Box<NormDescriptor>>) -> LandmarkId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.landmarks.insert(id, Landmark { id, position, descriptor });
+        id
+    }
+
+    pub fn landmark(&self, id: LandmarkId) -> Option<&Landmark> {
+        self.landmarks.get(&id)
+    }
+
+    pub fn landmark_mut(&mut self, id: LandmarkId) -> Option<&mut Landmark> {
+        self.landmarks.get_mut(&id)
+    }
+
+    /// Remove a landmark and all its observations
+    pub fn remove_landmark(&mut self, id: LandmarkId) -> Option<Landmark> {
+        let landmark = self.landmarks.remove(&id)?;
+        self.observations.retain(|obs| obs.landmark != id);
+        Some(landmark)
+    }
+
+    /// all landmarks in id order
+    pub fn landmarks(&self) -> impl Iterator<Item = &Landmark> {
+        self.landmarks.values()
+    }
+
+    /// Record an observation; returns false if the landmark is unknown
+    pub fn add_observation(&mut self, observation: Observation) -> bool {
+        if !self.landmarks.contains_key(&observation.landmark) {
+            return false;
+        }
+        self.observations.push(observation);
+        true
+    }
+
+    pub fn observations(&self) -> &[Observation] {
+        &self.observations
+    }
+
+    pub fn observations_of(&self, landmark: LandmarkId) -> impl Iterator<Item = &Observation> {
+        self.observations.iter().filter(move |obs| obs.landmark == landmark)
+    }
+
+    pub fn observations_in(&self, key_slice: KeySliceId) -> impl Iterator<Item = &Observation> {
+        self.observations.iter().filter(move |obs| obs.key_slice == key_slice)
+    }
+
+    /// the landmark most recently observed through a track
+    pub fn landmark_for_track(&self, track: TrackId) -> Option<LandmarkId> {
+        self.observations.iter().rev().find(|obs| obs.track == track).map(|obs| obs.landmark)
+    }
+
+    pub fn len(&self) -> usize {
+        self.landmarks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.landmarks.is_empty()
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.landmarks.len() as u32).to_le_bytes())?;
+        writer.write_all(&(self.observations.len() as u32).to_le_bytes())?;
+        for landmark in self.landmarks.values() {
+            writer.write_all(&landmark.id.to_le_bytes())?;
+            let (kind, key_slice, xyz) = match landmark.position {
+                LandmarkPosition::Point(xyz) => (0u8, 0, xyz),
+                LandmarkPosition::Bearing { key_slice, direction } => (1u8, key_slice, direction),
+            };
+            writer.write_all(&[kind])?;
+            writer.write_all(&key_slice.to_le_bytes())?;
+            for val in xyz.iter() {
+                writer.write_all(&val.to_bits().to_le_bytes())?;
+            }
+            match landmark.descriptor {
+                Some(ref desc) => {
+                    writer.write_all(&[1])?;
+                    for val in desc.iter() {
+                        writer.write_all(&val.to_bits().to_le_bytes())?;
+                    }
+                }
+                None => writer.write_all(&[0])?,
+            }
+        }
+        for obs in self.observations.iter() {
+            writer.write_all(&obs.landmark.to_le_bytes())?;
+            writer.write_all(&obs.key_slice.to_le_bytes())?;
+            writer.write_all(&obs.track.to_le_bytes())?;
+            writer.write_all(&obs.image[0].to_bits().to_le_bytes())?;
+            writer.write_all(&obs.image[1].to_bits().to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut reader = OffsetReader::new(reader);
+        reader.expect_header(MAGIC, FORMAT_VERSION)?;
+        let landmark_count = reader.read_u32()?;
+        let observation_count = reader.read_u32()?;
+
+        let mut map = Map::new();
+        for _ in 0..landmark_count {
+            let start = reader.offset();
+            let id = reader.read_u32()?;
+            let kind = reader.read_u8()?;
+            let key_slice = reader.read_u32()?;
+            let mut xyz = [0.0; 3];
+            for val in xyz.iter_mut() {
+                *val = reader.read_f64()?;
+            }
+            let position = match kind {
+                0 => LandmarkPosition::Point(xyz),
+                1 => LandmarkPosition::Bearing { key_slice, direction: xyz },
+                _ => return Err(DecodeError::new(start + 4, DecodeErrorKind::Invalid("landmark kind")).into()),
+            };
+            let descriptor = match reader.read_u8()? {
+                0 => None,
+                1 => {
+                    let mut desc = [0.0f32; NORM_DESCRIPTOR_LEN];
+                    for val in desc.iter_mut() {
+                        *val = reader.read_f32()?;
+                    }
+                    Some(Box::new(desc))
+                }
+                _ => return Err(reader.error(DecodeErrorKind::Invalid("descriptor flag"))),
+            };
+            if id == LandmarkId::MAX || map.landmarks.insert(id, Landmark { id, position, descriptor }).is_some() {
+                return Err(DecodeError::new(start, DecodeErrorKind::Invalid("landmark id")).into());
+            }
+            map.next_id = map.next_id.max(id + 1);
+        }
+        for _ in 0..observation_count {
+            let start = reader.offset();
+            let observation = Observation {
+                landmark: reader.read_u32()?,
+                key_slice: reader.read_u32()?,
+                track: reader.read_u32()?,
+                image: [reader.read_f32()?, reader.read_f32()?],
+            };
+            if !map.add_observation(observation) {
+                return Err(DecodeError::new(start, DecodeErrorKind::Invalid("observed landmark")).into());
+            }
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_map() -> Map {
+        let mut map = Map::new();
+        let mut desc = [0.0f32; NORM_DESCRIPTOR_LEN];
+        desc[3] = 0.5;
+        let a = map.add_landmark(LandmarkPosition::Point([1.0, -2.0, 5.5]), Some(Box::new(desc)));
+        let b = map.add_landmark(LandmarkPosition::Bearing { key_slice: 2, direction: [0.0, 0.6, 0.8] }, None);
+        assert!(map.add_observation(Observation { landmark: a, key_slice: 0, track: 7, image: [10.5, 20.0] }));
+        assert!(map.add_observation(Observation { landmark: a, key_slice: 1, track: 7, image: [12.5, 20.0] }));
+        assert!(map.add_observation(Observation { landmark: b, key_slice: 1, track: 9, image: [40.0, 3.25] }));
+        map
+    }
+
+    #[test]
+    fn test_observation_graph() {
+        let mut map = sample_map();
+        assert!(!map.add_observation(Observation { landmark: 42, key_slice: 0, track: 1, image: [0.0, 0.0] }));
+        assert_eq!(map.observations_of(0).count(), 2);
+        assert_eq!(map.observations_in(1).count(), 2);
+        assert_eq!(map.landmark_for_track(9), Some(1));
+        assert!(map.remove_landmark(0).is_some());
+        assert_eq!(map.observations().len(), 1);
+        assert_eq!(map.landmark_for_track(7), None);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let map = sample_map();
+        let mut buf = Vec::new();
+        map.write_to(&mut buf).unwrap();
+        let loaded = Map::read_from(&mut buf.as_slice()).unwrap();
+        assert_eq!(loaded, map);
+
+        let mut loaded = loaded;
+        assert_eq!(loaded.add_landmark(LandmarkPosition::Point([0.0; 3]), None), 2);
+
+        buf[0] = b'X';
+        let err = Map::read_from(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        buf[0] = b'A';
+
+        // every truncation is reported, with the offset of the incomplete field
+        for len in 0..buf.len() {
+            let err = Map::read_from(&mut &buf[..len]).unwrap_err();
+            let decode = DecodeError::of(&err).unwrap();
+            assert_eq!(decode.kind, DecodeErrorKind::Truncated);
+            assert!(decode.offset <= len as u64);
+        }
+        // an unknown landmark kind, in the first landmark record
+        buf[22] = 9;
+        let err = Map::read_from(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(DecodeError::of(&err), Some(&DecodeError::new(22, DecodeErrorKind::Invalid("landmark kind"))));
+    }
+}
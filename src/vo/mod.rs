@@ -0,0 +1,230 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Visual odometry front end: a single type that turns an event stream into
+//! time-stamped feature tracks and relative-pose hypotheses for a back end.
+//!
+//! Events are routed to per-polarity surfaces for corner detection, corners are
+//! associated into tracks, tracks are periodically checked for epipolar consistency
+//! with outliers rejected, and on each check the relative camera pose over the
+//! check baseline is estimated from the surviving tracks. Key slices of the
+//! tracked corners are taken as the tracks move and change, and `map::Map` holds
+//! the landmarks a back end builds from them.
+
+pub mod imu;
+pub mod keyslice;
+pub mod map;
+
+use nalgebra::{Unit, UnitQuaternion, Vector3};
+
+use crate::calib::camera::CameraIntrinsics;
+use crate::detector::ReplayDetector;
+use crate::epipolar::{relative_pose, EpipolarConfig, EpipolarFilter};
+use crate::sae_types::*;
+use crate::surface::RecordingHeader;
+use crate::track::{CornerTracker, TrackId, TrackStore, TrackerConfig};
+use crate::vo::keyslice::{KeySlice, KeySliceConfig, KeySliceSelector};
+
+
+/// Parameters of the front end
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrontEndConfig {
+    pub tracker: TrackerConfig,
+    /// outlier rejection; relative poses are estimated over the same baseline, at the same interval
+    pub epipolar: EpipolarConfig,
+    /// checks look this far behind the latest corner, so that most tracks
+    /// have been observed since the checked time
+    pub latency: SaeTime,
+    pub key_slices: KeySliceConfig,
+}
+
+impl Default for FrontEndConfig {
+    fn default() -> Self {
+        FrontEndConfig {
+            tracker: TrackerConfig::default(),
+            epipolar: EpipolarConfig::default(),
+            latency: 2_000,
+            key_slices: KeySliceConfig::default(),
+        }
+    }
+}
+
+/// Relative camera motion between two times, estimated from tracks
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoseHypothesis {
+    pub from_time: SaeTime,
+    pub to_time: SaeTime,
+    /// a point X at `from_time`, in camera coordinates, is at `rotation * X + translation` at `to_time`
+    pub rotation: UnitQuaternion<f64>,
+    /// direction of the translation; its scale is not observable from two views
+    pub translation: Unit<Vector3<f64>>,
+    /// the tracks supporting the hypothesis
+    pub tracks: Vec<TrackId>,
+}
+
+/// Output of the front end
+#[derive(Clone, Debug, PartialEq)]
+pub enum FrontEndEvent {
+    /// a corner was added to a feature track
+    Feature { track: TrackId, corner: SaeEvent },
+    /// a track was rejected as an outlier and removed
+    TrackRejected(TrackId),
+    /// a track ended without further observations and was removed
+    TrackEnded(TrackId),
+    /// relative pose over the most recent baseline
+    Pose(PoseHypothesis),
+    /// a new key slice was taken
+    KeySlice(KeySlice),
+}
+
+/// Detection, tracking, outlier rejection and two-view geometry in one type
+pub struct FrontEnd {
+    camera: CameraIntrinsics,
+    config: FrontEndConfig,
+    detector: ReplayDetector,
+    tracker: CornerTracker,
+    epipolar: EpipolarFilter,
+    key_slices: KeySliceSelector,
+}
+
+impl FrontEnd {
+    pub fn new(header: &RecordingHeader, camera: CameraIntrinsics, config: FrontEndConfig) -> Self {
+        FrontEnd {
+            camera,
+            detector: ReplayDetector::new(header),
+            tracker: CornerTracker::new(config.tracker.clone()),
+            epipolar: EpipolarFilter::new(config.epipolar.clone()),
+            key_slices: KeySliceSelector::new(config.key_slices.clone()),
+            config,
+        }
+    }
+
+    pub fn camera(&self) -> &CameraIntrinsics {
+        &self.camera
+    }
+
+    /// the active and recently ended tracks
+    pub fn tracks(&self) -> &TrackStore {
+        self.tracker.store()
+    }
+
+    /// Process one event, returning what it produced
+    pub fn process(&mut self, evt: &SaeEvent) -> Vec<FrontEndEvent> {
+        match self.detector.process(evt) {
+            Some(corner) => self.process_corner(&corner),
+            None => Vec::new(),
+        }
+    }
+
+    /// Process a corner detected elsewhere, eg by a custom detector
+    pub fn process_corner(&mut self, corner: &SaeEvent) -> Vec<FrontEndEvent> {
+        let now = corner.timestamp;
+        let track = self.tracker.add_corner(corner);
+        let mut out = vec![FrontEndEvent::Feature { track, corner: corner.clone() }];
+
+        let checked = match now.checked_sub(self.config.latency) {
+            Some(checked) => checked,
+            None => return out,
+        };
+        if let Some(rejected) = self.epipolar.check(self.tracker.store(), checked) {
+            for id in rejected {
+                self.tracker.drop_track(id);
+                out.push(FrontEndEvent::TrackRejected(id));
+            }
+            if let Some(pose) = self.estimate_pose(checked) {
+                out.push(FrontEndEvent::Pose(pose));
+            }
+            for id in self.tracker.retire(now, &mut ()) {
+                out.push(FrontEndEvent::TrackEnded(id));
+            }
+            if let Some(slice) = self.key_slices.update(self.tracker.store(), self.tracker.active(), now) {
+                out.push(FrontEndEvent::KeySlice(slice));
+            }
+        }
+        out
+    }
+
+    /// relative pose over the baseline ending at `end`
+    fn estimate_pose(&self, end: SaeTime) -> Option<PoseHypothesis> {
+        let start = end.checked_sub(self.config.epipolar.baseline)?;
+        let mut ids = Vec::new();
+        let mut from = Vec::new();
+        let mut to = Vec::new();
+        for track in self.tracker.store().iter() {
+            if let (Some(p0), Some(p1)) = (track.position_at(start), track.position_at(end)) {
+                ids.push(track.id);
+                from.push([p0[0] as f64, p0[1] as f64]);
+                to.push([p1[0] as f64, p1[1] as f64]);
+            }
+        }
+        let epipolar = &self.config.epipolar;
+        let (pose, inliers) = relative_pose(&self.camera, &from, &to, epipolar.threshold, epipolar.iterations, epipolar.seed)?;
+        Some(PoseHypothesis {
+            from_time: start,
+            to_time: end,
+            rotation: pose.rotation,
+            translation: pose.translation,
+            tracks: inliers.iter().map(|&i| ids[i]).collect(),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surface::WarmupConfig;
+    use nalgebra::{Isometry3, Point3};
+
+    #[test]
+    fn test_front_end_tracks_and_pose() {
+        let camera = CameraIntrinsics::pinhole(200.0, 200.0, 120.0, 90.0);
+        let header = RecordingHeader::new(180, 240, WarmupConfig::disabled());
+        let mut front_end = FrontEnd::new(&header, camera, FrontEndConfig::default());
+
+        // a static scene, seen while the camera moves sideways at 1 m/s, turning at 2 rad/s
+        let points: Vec<Point3<f64>> = (0..24)
+            .map(|k| {
+                let f = k as f64;
+                Point3::new((f * 0.9).sin() * 2.0, (f * 1.7).cos() * 1.5, 6.0 + (f * 0.5).sin() * 2.0)
+            })
+            .collect();
+        let mut outputs = Vec::new();
+        for step in 0..40u32 {
+            let t = step * 1_000;
+            let pose = Isometry3::new(Vector3::new(-(t as f64) * 1e-6, 0.0, 0.0), Vector3::new(0.0, 2e-6 * t as f64, 0.0));
+            for (k, point) in points.iter().enumerate() {
+                let pixel = camera.project_world(&pose, point).unwrap();
+                let corner = SaeEvent {
+                    row_f: Some(pixel[1] as f32),
+                    col_f: Some(pixel[0] as f32),
+                    timestamp: t + k as SaeTime,
+                    ..SaeEvent::default()
+                };
+                outputs.extend(front_end.process_corner(&corner));
+            }
+        }
+
+        let features = outputs.iter().filter(|out| matches!(out, FrontEndEvent::Feature { .. })).count();
+        assert_eq!(features, 24 * 40);
+        assert_eq!(front_end.tracks().len(), 24);
+        let poses: Vec<&PoseHypothesis> = outputs.iter()
+            .filter_map(|out| match out {
+                FrontEndEvent::Pose(pose) => Some(pose),
+                _ => None,
+            })
+            .collect();
+        assert!(!poses.is_empty());
+        let last = poses[poses.len() - 1];
+        assert_eq!((last.from_time, last.to_time), (10_000, 30_000));
+        assert_eq!(last.tracks.len(), 24);
+        // the camera moves towards +x, so scene points move towards -x in camera coordinates
+        assert!(last.translation.x < -0.99, "{:?}", last.translation);
+
+        let slices = outputs.iter().filter(|out| matches!(out, FrontEndEvent::KeySlice(_))).count();
+        assert_eq!(slices, 1);
+
+        // ordinary events produce no output until they form corners
+        assert!(front_end.process(&SaeEvent { row: 5, col: 5, timestamp: 50_000, ..SaeEvent::default() }).is_empty());
+    }
+}
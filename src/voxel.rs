@@ -0,0 +1,112 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Event voxel grids: events accumulated into a fixed number of time bins,
+//! the usual dense input representation for learned components.
+//!
+//! Each event adds its polarity (+1 for ON, -1 for OFF) to its pixel, split
+//! between the two nearest time bins in proportion to its distance from each,
+//! so the grid varies smoothly as events move between bins.
+
+use crate::sae_types::*;
+
+
+/// Events of a time window, binned as `bins` x `nrows` x `ncols` values
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxelGrid {
+    bins: usize,
+    nrows: usize,
+    ncols: usize,
+    start: SaeTime,
+    end: SaeTime,
+    /// values in (bin, row, col) order
+    data: Vec<f32>,
+}
+
+impl VoxelGrid {
+    pub fn new(bins: usize, nrows: usize, ncols: usize, start: SaeTime, end: SaeTime) -> Self {
+        VoxelGrid {
+            bins: bins.max(1),
+            nrows,
+            ncols,
+            start,
+            end: end.max(start),
+            data: vec![0.0; bins.max(1) * nrows * ncols],
+        }
+    }
+
+    /// A grid spanning the timestamps of `events`, which should be in time order
+    pub fn from_events(events: &[SaeEvent], bins: usize, nrows: usize, ncols: usize) -> Self {
+        let start = events.first().map_or(0, |evt| evt.timestamp);
+        let end = events.last().map_or(0, |evt| evt.timestamp);
+        let mut grid = Self::new(bins, nrows, ncols, start, end);
+        for evt in events.iter() {
+            grid.add(evt);
+        }
+        grid
+    }
+
+    /// Accumulate one event; events outside the sensor or the time window are ignored
+    pub fn add(&mut self, evt: &SaeEvent) {
+        let (row, col) = (evt.row as usize, evt.col as usize);
+        if row >= self.nrows || col >= self.ncols || evt.timestamp < self.start || evt.timestamp > self.end {
+            return;
+        }
+        let span = (self.end - self.start).max(1) as f32;
+        let pos = (evt.timestamp - self.start) as f32 / span * (self.bins - 1) as f32;
+        let lower = pos.floor() as usize;
+        let frac = pos - lower as f32;
+        let value = if evt.polarity > 0 { 1.0 } else { -1.0 };
+        let pixel = row * self.ncols + col;
+        let plane = self.nrows * self.ncols;
+        self.data[lower * plane + pixel] += value * (1.0 - frac);
+        if frac > 0.0 && lower + 1 < self.bins {
+            self.data[(lower + 1) * plane + pixel] += value * frac;
+        }
+    }
+
+    /// (bins, nrows, ncols)
+    pub fn shape(&self) -> (usize, usize, usize) {
+        (self.bins, self.nrows, self.ncols)
+    }
+
+    /// (start, end) of the time window
+    pub fn window(&self) -> (SaeTime, SaeTime) {
+        (self.start, self.end)
+    }
+
+    pub fn get(&self, bin: usize, row: usize, col: usize) -> f32 {
+        self.data[(bin * self.nrows + row) * self.ncols + col]
+    }
+
+    /// all values, in (bin, row, col) order
+    pub fn as_slice(&self) -> &[f32] {
+        &self.data
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temporal_interpolation() {
+        let events = vec![
+            SaeEvent { row: 0, col: 0, polarity: 1, timestamp: 100, ..SaeEvent::default() },
+            SaeEvent { row: 1, col: 2, polarity: 0, timestamp: 150, ..SaeEvent::default() },
+            SaeEvent { row: 0, col: 0, polarity: 1, timestamp: 300, ..SaeEvent::default() },
+            SaeEvent { row: 9, col: 9, polarity: 1, timestamp: 300, ..SaeEvent::default() },
+        ];
+        let grid = VoxelGrid::from_events(&events, 3, 2, 3);
+        assert_eq!(grid.shape(), (3, 2, 3));
+        assert_eq!(grid.window(), (100, 300));
+        assert_eq!(grid.get(0, 0, 0), 1.0);
+        assert_eq!(grid.get(2, 0, 0), 1.0);
+        // halfway between bins 0 and 1
+        assert_eq!(grid.get(0, 1, 2), -0.5);
+        assert_eq!(grid.get(1, 1, 2), -0.5);
+        // polarity is conserved and out of bounds events are dropped
+        assert_eq!(grid.as_slice().iter().sum::<f32>(), 1.0);
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Stall and latency supervision for live pipelines.
+//! The watchdog is fed wall-clock observations from the pipeline and reports
+//! structured status events only when the pipeline health changes, so that
+//! supervisory logic (eg on a robot) can react.
+//! All methods take the current time explicitly, which keeps them deterministic under test.
+
+use std::time::{Duration, Instant};
+
+use crate::surface::SaeSurface;
+
+
+/// Thresholds used by the watchdog
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchdogConfig {
+    /// the source is considered stalled if no event arrives for this long
+    pub stall_timeout: Duration,
+    /// the detection stage is considered lagging if an event waits longer than this to be processed
+    pub max_latency: Duration,
+    /// whether a stall should request that the pipeline state (eg surfaces) be reset
+    pub reset_on_stall: bool,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            stall_timeout: Duration::from_millis(500),
+            max_latency: Duration::from_millis(50),
+            reset_on_stall: false,
+        }
+    }
+}
+
+/// Health of the supervised pipeline
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PipelineHealth {
+    Healthy,
+    /// no events have arrived from the source for the given duration
+    SourceStalled(Duration),
+    /// the detection stage processed an event with the given latency, above the bound
+    Lagging(Duration),
+}
+
+/// A change in pipeline health reported by the watchdog
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatusEvent {
+    pub health: PipelineHealth,
+    pub at: Instant,
+    /// whether the pipeline state should be flushed/reset in response
+    pub reset_requested: bool,
+}
+
+/// Detects a stalled event source or a detection stage falling behind
+pub struct Watchdog {
+    config: WatchdogConfig,
+    health: PipelineHealth,
+    last_arrival: Option<Instant>,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Watchdog {
+            config,
+            health: PipelineHealth::Healthy,
+            last_arrival: None,
+        }
+    }
+
+    pub fn health(&self) -> PipelineHealth {
+        self.health
+    }
+
+    fn transition(&mut self, health: PipelineHealth, now: Instant) -> Option<StatusEvent> {
+        let changed = std::mem::discriminant(&health) != std::mem::discriminant(&self.health);
+        self.health = health;
+        if !changed {
+            return None;
+        }
+        let reset_requested = self.config.reset_on_stall &&
+            matches!(health, PipelineHealth::SourceStalled(_));
+        Some(StatusEvent { health, at: now, reset_requested })
+    }
+
+    /// Record that the source delivered an event at `now`
+    pub fn on_event(&mut self, now: Instant) -> Option<StatusEvent> {
+        self.last_arrival = Some(now);
+        if let PipelineHealth::SourceStalled(_) = self.health {
+            return self.transition(PipelineHealth::Healthy, now);
+        }
+        None
+    }
+
+    /// Record that the detection stage finished processing an event which arrived at `arrival`
+    pub fn on_processed(&mut self, arrival: Instant, now: Instant) -> Option<StatusEvent> {
+        let latency = now.saturating_duration_since(arrival);
+        if latency > self.config.max_latency {
+            self.transition(PipelineHealth::Lagging(latency), now)
+        } else if let PipelineHealth::Lagging(_) = self.health {
+            self.transition(PipelineHealth::Healthy, now)
+        } else {
+            None
+        }
+    }
+
+    /// Periodic check for a stalled source; call regularly, even when no events arrive
+    pub fn poll(&mut self, now: Instant) -> Option<StatusEvent> {
+        let last_arrival = self.last_arrival?;
+        let idle = now.saturating_duration_since(last_arrival);
+        if idle > self.config.stall_timeout {
+            self.transition(PipelineHealth::SourceStalled(idle), now)
+        } else {
+            None
+        }
+    }
+
+    /// Apply any reset requested by the status event to the given surface.
+    /// Returns whether the surface was reset.
+    pub fn apply_reset(status: &StatusEvent, surface: &mut SaeSurface) -> bool {
+        if status.reset_requested {
+            surface.reset();
+        }
+        status.reset_requested
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stall_detection() {
+        let config = WatchdogConfig {
+            stall_timeout: Duration::from_millis(100),
+            reset_on_stall: true,
+            ..WatchdogConfig::default()
+        };
+        let mut watchdog = Watchdog::new(config);
+        let t0 = Instant::now();
+
+        // nothing to supervise before the first event
+        assert!(watchdog.poll(t0 + Duration::from_secs(10)).is_none());
+
+        assert!(watchdog.on_event(t0).is_none());
+        assert!(watchdog.poll(t0 + Duration::from_millis(50)).is_none());
+
+        let status = watchdog.poll(t0 + Duration::from_millis(150)).unwrap();
+        assert_eq!(status.health, PipelineHealth::SourceStalled(Duration::from_millis(150)));
+        assert!(status.reset_requested);
+        // only transitions are reported
+        assert!(watchdog.poll(t0 + Duration::from_millis(200)).is_none());
+
+        let mut surface = SaeSurface::new(8, 8);
+        assert!(Watchdog::apply_reset(&status, &mut surface));
+
+        let status = watchdog.on_event(t0 + Duration::from_millis(300)).unwrap();
+        assert_eq!(status.health, PipelineHealth::Healthy);
+        assert!(!status.reset_requested);
+    }
+
+    #[test]
+    fn test_latency_detection() {
+        let mut watchdog = Watchdog::new(WatchdogConfig::default());
+        let t0 = Instant::now();
+
+        assert!(watchdog.on_processed(t0, t0 + Duration::from_millis(10)).is_none());
+        let status = watchdog.on_processed(t0, t0 + Duration::from_millis(80)).unwrap();
+        assert_eq!(status.health, PipelineHealth::Lagging(Duration::from_millis(80)));
+        assert!(!status.reset_requested);
+        assert!(watchdog.on_processed(t0, t0 + Duration::from_millis(90)).is_none());
+
+        let status = watchdog.on_processed(t0, t0 + Duration::from_millis(5)).unwrap();
+        assert_eq!(status.health, PipelineHealth::Healthy);
+    }
+}
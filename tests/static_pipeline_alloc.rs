@@ -0,0 +1,90 @@
+// Copyright 2019, Todd Stellanova
+// License: see LICENSE file
+
+//! Checks that a fixed-size `StaticPipeline` allocates nothing per event.
+//! Kept apart from the unit tests since it replaces the global allocator of its test binary.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use arcstar::detector::StaticRing;
+use arcstar::filter::EventFilter;
+use arcstar::sae_types::*;
+use arcstar::sink::CornerSink;
+use arcstar::static_pipeline::StaticPipeline;
+use arcstar::storage::FixedOnlineDetector;
+
+
+/// Counts the allocations made by each thread, so a test can check that it made none
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+struct MaxRow(u16);
+
+impl EventFilter for MaxRow {
+    fn accept(&mut self, evt: &SaeEvent) -> bool {
+        evt.row < self.0
+    }
+}
+
+/// Counts corners without keeping them
+#[derive(Default)]
+struct CornerCount(usize);
+
+impl CornerSink for CornerCount {
+    fn accept(&mut self, _corner: &SaeEvent) {
+        self.0 += 1;
+    }
+}
+
+/// a 5x5 block of events with its top left pixel at (10, 10), then its bottom
+/// right pixel again 2us later, forming a corner there
+fn corner_block() -> Vec<RawEvent> {
+    let mut events = Vec::new();
+    for row in 10..15 {
+        for col in 10..15 {
+            events.push(RawEvent { row, col, polarity: 1, timestamp: 7 });
+        }
+    }
+    events.push(RawEvent { row: 14, col: 14, polarity: 1, timestamp: 9 });
+    events
+}
+
+#[test]
+fn test_no_allocation_per_event() {
+    let events = corner_block();
+    let detector: FixedOnlineDetector<StaticRing<3, 3, 6>, StaticRing<4, 4, 8>, false, 32, 32> = FixedOnlineDetector::new();
+    let mut pipeline = StaticPipeline::new(detector, CornerCount::default())
+        .with_filter(MaxRow(30));
+
+    let before = allocations();
+    for &evt in events.iter() {
+        pipeline.process(evt);
+    }
+    pipeline.process(RawEvent { row: 31, col: 14, polarity: 1, timestamp: 9 });
+    assert_eq!(allocations(), before);
+    assert_eq!(pipeline.events_processed(), 27);
+    assert!(pipeline.sink().0 > 0);
+    assert_eq!(pipeline.corners_emitted() as usize, pipeline.sink().0);
+}